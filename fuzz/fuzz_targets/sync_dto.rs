@@ -0,0 +1,14 @@
+#![no_main]
+
+use kingcoin::blockchain::{invariants, Transaction};
+use kingcoin::blockchain::core::Blockchain;
+use kingcoin::network::communication::BlockchainDto;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(dto) = serde_json::from_slice::<BlockchainDto<Transaction>>(data) {
+        if let Ok(blockchain) = Blockchain::try_from(dto) {
+            invariants::audit(&blockchain);
+        }
+    }
+});
@@ -1,33 +1,72 @@
-use chrono::{DateTime, Utc};
-use lazy_static::lazy_static;
-use rsa::{pss::VerifyingKey, RsaPublicKey, signature::{Signature, Verifier}};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use rsa::RsaPublicKey;
 use rsa::pss::BlindedSigningKey;
-use rsa::rand_core::{CryptoRng, RngCore};
+use rsa::rand_core::{CryptoRng, CryptoRngCore, RngCore};
 use rsa::signature::RandomizedSigner;
 use serde::{Deserialize, Serialize};
-use sha2::Sha512;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256, Sha512};
 
 use crate::blockchain::core::{
     BlockCandidate, Blockchain, BlockchainError, BlockValidationError,
-    Criteria, Summary, Validate,
+    ChainEvent, Criteria, MAX_FUTURE_DRIFT_SECONDS, Summary, Validate,
+};
+use crate::blockchain::contract::{Approval, Contract};
+use crate::blockchain::key_history::KeyHistory;
+use crate::blockchain::pipeline::VerifiedSignatures;
+// the constants lived here before the protocol module, paths that name them here keep working
+pub use crate::blockchain::protocol::{
+    BLOCK_INTERVAL_SECONDS, BLOCK_SIZE, BURN_WALLET_ADDRESS, CHAIN_ID, CONTRACT_WALLET_ADDRESS, MINTING_WALLET_ADDRESS,
+    REWARD_WALLET_ADDRESS, STAKE_WALLET_ADDRESS, TOTAL_SUPPLY, TRANSACTION_FEE, TRANSACTION_SIGNING_DOMAIN,
+    TRANSFER_FEE, WALLET_GRANT,
 };
+use crate::blockchain::signer::Signer;
+use crate::blockchain::stake::StakeRegistry;
+use crate::blockchain::upgrade::{ConsensusRules, SignatureScheme, UPGRADE_SCHEDULE, UpgradeSchedule};
+use crate::display::DisplayConfig;
 
+pub mod access;
+pub mod builder;
+pub mod compact_key;
+pub mod contract;
 pub mod core;
+pub mod governance;
+pub mod history;
+pub mod keyfile;
+pub mod key_history;
+pub mod invariants;
+pub mod memo;
+pub mod pipeline;
+pub mod proof;
+pub mod protocol;
+pub mod rules;
+pub mod signer;
+pub mod snapshot;
+pub mod stake;
+pub mod store;
+pub mod upgrade;
 
 pub type Address = [u8; 32];
 
-pub static TRANSACTION_FEE: i64 = 50;
-pub static MINTING_WALLET_ADDRESS: Address = [0; 32];
-lazy_static! {
-    pub static ref STAKE_WALLET_ADDRESS: Address = {
-        let mut address = [0;32];
-        address[0] = 1;
-        address
-    };
-}
 
+pub trait BlockchainData: Summary + Clone + Serialize + DeserializeOwned {
+    fn balance_changes(&self) -> Vec<(Address, i64)> {
+        vec![]
+    }
+
+    // the address that signed the data off, counted for nonces
+    fn sender(&self) -> Option<Address> {
+        None
+    }
 
-pub trait BlockchainData: Summary + Clone + Serialize {}
+    // movements of tokens issued on top of the chain, as token id, address and change
+    fn token_changes(&self) -> Vec<(String, Address, i64)> {
+        vec![]
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Eq, Hash, PartialEq)]
 pub struct Transaction {
@@ -37,7 +76,14 @@ pub struct Transaction {
     // in Kingcoin's smallest unit
     amount: i64,
     time: DateTime<Utc>,
+    // per source address sequence number, minting and stake transfers are exempt
+    #[serde(default)]
+    nonce: u64,
     sender_signature: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    contract: Option<Contract>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    approvals: Vec<Approval>,
 }
 
 impl Transaction {
@@ -54,10 +100,18 @@ impl Transaction {
             title: message,
             amount,
             time,
+            nonce: 0,
             sender_signature: None,
+            contract: None,
+            approvals: vec![],
         }
     }
 
+    pub fn with_contract(mut self, contract: Contract) -> Self {
+        self.contract = Some(contract);
+        self
+    }
+
     pub fn source_address(&self) -> Address {
         self.source_address
     }
@@ -73,9 +127,21 @@ impl Transaction {
     pub fn time(&self) -> DateTime<Utc> {
         self.time
     }
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+    pub fn set_nonce(&mut self, nonce: u64) {
+        self.nonce = nonce;
+    }
     pub fn sender_signature(&self) -> &Option<String> {
         &self.sender_signature
     }
+    pub fn contract(&self) -> &Option<Contract> {
+        &self.contract
+    }
+    pub fn approvals(&self) -> &[Approval] {
+        &self.approvals
+    }
 
     pub fn sign(&mut self, key: BlindedSigningKey<Sha512>, rng: impl CryptoRng + RngCore) {
         let signature = key.sign_with_rng(
@@ -85,13 +151,103 @@ impl Transaction {
         self.sender_signature = Some(signature.to_string());
     }
 
+    // for signatures produced outside the node, see signer::Signer
+    pub fn attach_signature(&mut self, signature: String) {
+        self.sender_signature = Some(signature);
+    }
+
+    // keeps the first approval of each signer, ordered by signer so that nodes merging the same
+    // approvals end up with the same transaction id
+    pub fn add_approval(&mut self, approval: Approval) {
+        if let Err(position) = self.approvals.binary_search_by_key(&approval.signer(), Approval::signer) {
+            self.approvals.insert(position, approval);
+        }
+    }
+
+    pub fn take_approvals(&mut self) -> Vec<Approval> {
+        std::mem::take(&mut self.approvals)
+    }
+
+    pub fn id(&self) -> String {
+        array_bytes::bytes2hex("", Sha256::digest(self.summary().as_bytes()))
+    }
+
+    // blocks list transactions by sender, nonce and hash, whatever order the mempool saw them in
+    pub fn canonical_cmp(&self, other: &Transaction) -> Ordering {
+        self.source_address.cmp(&other.source_address)
+            .then(self.nonce.cmp(&other.nonce))
+            .then_with(|| self.id().cmp(&other.id()))
+    }
+
     pub fn signed_content(&self) -> String {
-        format! {
-            "{}{}{}{}",
+        self.signed_content_on(protocol::chain_id())
+    }
+
+    // every field is prefixed with its length, so no two transactions sign the same content by
+    // moving characters from one field into the next
+    fn signed_content_on(&self, chain_id: &str) -> String {
+        let mut fields = vec![
+            TRANSACTION_SIGNING_DOMAIN.to_string(), chain_id.to_string(),
             array_bytes::bytes2hex("", self.source_address),
             array_bytes::bytes2hex("", self.target_address),
-            self.amount, self.title
+            self.amount.to_string(), self.title.clone(), self.nonce.to_string(), self.time.to_rfc3339(),
+        ];
+        if let Some(contract) = &self.contract {
+            fields.push(serde_json::to_string(contract).unwrap());
         }
+        fields.iter()
+            .map(|field| format!("{}:{}", field.len(), field))
+            .collect()
+    }
+
+    pub fn nonce_exempt(&self) -> bool {
+        self.source_address == MINTING_WALLET_ADDRESS
+            || self.source_address == *STAKE_WALLET_ADDRESS
+            || self.source_address == *REWARD_WALLET_ADDRESS
+            || self.source_address == *CONTRACT_WALLET_ADDRESS
+            || self.is_penalty()
+    }
+
+    pub fn fee(source_address: Address, fee: i64) -> Transaction {
+        Transaction::new(
+            source_address, *REWARD_WALLET_ADDRESS, "Fee".to_string(),
+            fee, Utc::now(),
+        )
+    }
+
+    // signed by the sponsor, which pays the fee of another wallet's transfer
+    pub fn sponsorship(sponsor: Address, transfer: &Transaction, fee: i64) -> Transaction {
+        Transaction::new(
+            sponsor, *REWARD_WALLET_ADDRESS, "Sponsored fee".to_string(), fee, Utc::now(),
+        ).with_contract(Contract::Sponsorship { transfer_id: transfer.id() })
+    }
+
+    pub fn grant(target_address: Address, amount: i64, work: u64) -> Transaction {
+        Transaction::new(
+            MINTING_WALLET_ADDRESS, target_address, "Grant".to_string(), amount, Utc::now(),
+        ).with_contract(Contract::Grant { work })
+    }
+
+    pub fn is_grant(&self) -> bool {
+        self.source_address == MINTING_WALLET_ADDRESS && matches!(self.contract, Some(Contract::Grant { .. }))
+    }
+
+    pub fn fee_payout(target_address: Address, payout: i64) -> Transaction {
+        Transaction::new(
+            *REWARD_WALLET_ADDRESS, target_address, "Fee payout".to_string(),
+            payout, Utc::now(),
+        )
+    }
+
+    // every node that saw the offence makes the same penalty, the time is taken from the chain
+    // so they all share one id
+    pub fn penalty(offender: Address, amount: i64, epoch: u64, reason: &str, time: DateTime<Utc>) -> Transaction {
+        Transaction::new(offender, *BURN_WALLET_ADDRESS, reason.to_string(), amount, time)
+            .with_contract(Contract::Penalty { epoch })
+    }
+
+    pub fn is_penalty(&self) -> bool {
+        matches!(self.contract, Some(Contract::Penalty { .. }))
     }
 
     pub fn stake_bid(bid: i64, source_address: Address) -> Transaction {
@@ -101,6 +257,77 @@ impl Transaction {
         )
     }
 
+    pub fn htlc_lock(
+        source_address: Address, recipient: Address, amount: i64, hash_lock: String, expires_at: DateTime<Utc>,
+    ) -> Transaction {
+        Transaction::new(
+            source_address, *CONTRACT_WALLET_ADDRESS, "HTLC lock".to_string(), amount, Utc::now(),
+        ).with_contract(Contract::HtlcLock { recipient, hash_lock, expires_at })
+    }
+
+    // signed by the lock's recipient, none if the transaction is no lock
+    pub fn htlc_claim(lock: &Transaction, preimage: String) -> Option<Transaction> {
+        match &lock.contract {
+            Some(Contract::HtlcLock { recipient, .. }) => Some(Transaction::new(
+                *CONTRACT_WALLET_ADDRESS, *recipient, "HTLC claim".to_string(), lock.amount, Utc::now(),
+            ).with_contract(Contract::HtlcClaim { lock_id: lock.id(), preimage })),
+            _ => None
+        }
+    }
+
+    // signed by the lock's sender
+    pub fn htlc_refund(lock: &Transaction) -> Option<Transaction> {
+        match &lock.contract {
+            Some(Contract::HtlcLock { .. }) => Some(Transaction::new(
+                *CONTRACT_WALLET_ADDRESS, lock.source_address, "HTLC refund".to_string(), lock.amount, Utc::now(),
+            ).with_contract(Contract::HtlcRefund { lock_id: lock.id() })),
+            _ => None
+        }
+    }
+
+    pub fn burn(source_address: Address, amount: i64) -> Transaction {
+        Transaction::new(source_address, *BURN_WALLET_ADDRESS, "Burn".to_string(), amount, Utc::now())
+    }
+
+    pub fn is_burn(&self) -> bool {
+        self.target_address == *BURN_WALLET_ADDRESS
+    }
+
+    pub fn token_mint(issuer: Address, symbol: String, amount: i64) -> Transaction {
+        Transaction::new(issuer, issuer, format!("Mint {}", symbol), 0, Utc::now())
+            .with_contract(Contract::TokenMint { symbol, amount })
+    }
+
+    pub fn token_transfer(source_address: Address, target_address: Address, token_id: String, amount: i64) -> Transaction {
+        Transaction::new(source_address, target_address, "Token transfer".to_string(), 0, Utc::now())
+            .with_contract(Contract::TokenTransfer { token_id, amount })
+    }
+
+    pub fn escrow_open(source_address: Address, recipient: Address, arbiter: Address, amount: i64) -> Transaction {
+        Transaction::new(
+            source_address, *CONTRACT_WALLET_ADDRESS, "Escrow".to_string(), amount, Utc::now(),
+        ).with_contract(Contract::EscrowOpen { recipient, arbiter })
+    }
+
+    // settlements take the escrow's time so every party approves the same signed content
+    pub fn escrow_release(escrow: &Transaction) -> Option<Transaction> {
+        match &escrow.contract {
+            Some(Contract::EscrowOpen { recipient, .. }) => Some(Transaction::new(
+                *CONTRACT_WALLET_ADDRESS, *recipient, "Escrow release".to_string(), escrow.amount, escrow.time,
+            ).with_contract(Contract::EscrowRelease { escrow_id: escrow.id() })),
+            _ => None
+        }
+    }
+
+    pub fn escrow_refund(escrow: &Transaction) -> Option<Transaction> {
+        match &escrow.contract {
+            Some(Contract::EscrowOpen { .. }) => Some(Transaction::new(
+                *CONTRACT_WALLET_ADDRESS, escrow.source_address, "Escrow refund".to_string(), escrow.amount, escrow.time,
+            ).with_contract(Contract::EscrowRefund { escrow_id: escrow.id() })),
+            _ => None
+        }
+    }
+
     pub fn stake_return(bid: i64, target_address: Address) -> Transaction {
         Transaction::new(
             *STAKE_WALLET_ADDRESS, target_address, "".to_string(),
@@ -109,13 +336,14 @@ impl Transaction {
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct StakeBid {
     stake: i64,
     transaction: Transaction,
 }
 
 impl StakeBid {
+    // unsigned, peers ignore it, only stands for the node's stake until it publishes a bid
     pub fn bid(bid: i64, wallet_address: Address) -> StakeBid {
         StakeBid {
             stake: bid,
@@ -123,6 +351,40 @@ impl StakeBid {
         }
     }
 
+    // signed with the key of the wallet it bids from and titled with the peer bidding for it, so
+    // no other peer can pass it on as its own
+    pub fn signed(
+        bid: i64, bidding_peer: &str, signer: &dyn Signer, rng: &mut dyn CryptoRngCore,
+    ) -> Result<StakeBid, Box<dyn BlockchainError>> {
+        let mut transaction = Transaction::new(
+            signer.address(), *STAKE_WALLET_ADDRESS, bidding_peer.to_string(), bid, Utc::now(),
+        );
+        signer.sign(&mut transaction, rng)?;
+        Ok(StakeBid {
+            stake: bid,
+            transaction,
+        })
+    }
+
+    // a bid only counts for the registered wallet that signed it, and for the peer it names
+    pub fn verify(&self, sending_peer: &str, wallets: &Blockchain<Wallet>) -> Result<(), TransactionValidationError> {
+        let transaction = &self.transaction;
+        if transaction.target_address != *STAKE_WALLET_ADDRESS || transaction.contract.is_some() || transaction.amount != self.stake {
+            return Err(TransactionValidationError::BadContract);
+        }
+        if transaction.amount <= 0 {
+            return Err(TransactionValidationError::NonPositiveAmount { amount: transaction.amount });
+        }
+        let key = find_wallet_by_address(transaction.source_address, wallets)
+            .and_then(|wallet| wallet.key().clone())
+            .ok_or(TransactionValidationError::UnknownSourceWallet)?;
+        stake::bid_signed(transaction, &[key])?;
+        if transaction.title != sending_peer {
+            return Err(TransactionValidationError::BadSignature);
+        }
+        Ok(())
+    }
+
     pub fn stake(&self) -> i64 {
         self.stake
     }
@@ -132,6 +394,110 @@ impl StakeBid {
     }
 }
 
+// committed balance split from mempool activity, incoming payments only become spendable once
+// committed while outgoing ones are reserved immediately so they cannot be spent twice
+pub struct BalanceBreakdown {
+    confirmed: i64,
+    pending_incoming: i64,
+    pending_outgoing: i64,
+    // bonded on the stakes chain and not yet through the unbonding period
+    locked: i64,
+}
+
+impl BalanceBreakdown {
+    pub fn confirmed(&self) -> i64 {
+        self.confirmed
+    }
+    pub fn pending_incoming(&self) -> i64 {
+        self.pending_incoming
+    }
+    pub fn pending_outgoing(&self) -> i64 {
+        self.pending_outgoing
+    }
+    pub fn locked(&self) -> i64 {
+        self.locked
+    }
+
+    pub fn with_locked(mut self, locked: i64) -> Self {
+        self.locked = locked;
+        self
+    }
+
+    pub fn spendable(&self) -> i64 {
+        self.confirmed - self.pending_outgoing - self.locked
+    }
+
+    pub fn describe(&self, display: &DisplayConfig) -> String {
+        let locked = match self.locked {
+            0 => String::new(),
+            locked => format!(", locked {}", display.amount(locked))
+        };
+        format!(
+            "confirmed {}, pending +{}/-{}{}, spendable {}",
+            display.amount(self.confirmed), display.amount(self.pending_incoming),
+            display.amount(self.pending_outgoing), locked, display.amount(self.spendable())
+        )
+    }
+}
+
+// committed history only, the mempool is reported by the node status
+pub struct ChainStats {
+    block_count: u64,
+    total_transactions: usize,
+    total_volume: i64,
+    // fraction of the block size limit actually used
+    average_block_fill: f64,
+    average_block_interval: Option<Duration>,
+    active_addresses: usize,
+    circulating_supply: i64,
+    burned: i64,
+}
+
+impl ChainStats {
+    pub fn block_count(&self) -> u64 {
+        self.block_count
+    }
+    pub fn total_transactions(&self) -> usize {
+        self.total_transactions
+    }
+    pub fn total_volume(&self) -> i64 {
+        self.total_volume
+    }
+    pub fn average_block_fill(&self) -> f64 {
+        self.average_block_fill
+    }
+    pub fn average_block_interval(&self) -> Option<Duration> {
+        self.average_block_interval
+    }
+    pub fn active_addresses(&self) -> usize {
+        self.active_addresses
+    }
+    pub fn circulating_supply(&self) -> i64 {
+        self.circulating_supply
+    }
+    pub fn burned(&self) -> i64 {
+        self.burned
+    }
+
+    pub fn describe(&self) -> String {
+        let interval = match self.average_block_interval {
+            None => String::from("n/a"),
+            Some(interval) => format!("{}s", interval.num_seconds())
+        };
+        format!(
+            "Blocks: {}\n\
+             Transactions: {}, volume {}\n\
+             Average block fill: {:.1}%\n\
+             Average block interval: {}\n\
+             Active addresses: {}\n\
+             Circulating supply: {} of {}, {} burned",
+            self.block_count, self.total_transactions, self.total_volume,
+            self.average_block_fill * 100.0, interval, self.active_addresses,
+            self.circulating_supply, TOTAL_SUPPLY, self.burned
+        )
+    }
+}
+
 impl Clone for Transaction {
     fn clone(&self) -> Self {
         Self {
@@ -140,271 +506,1273 @@ impl Clone for Transaction {
             title: self.title.clone(),
             amount: self.amount,
             time: self.time.clone(),
+            nonce: self.nonce,
             sender_signature: self.sender_signature.clone(),
+            contract: self.contract.clone(),
+            approvals: self.approvals.clone(),
         }
     }
 }
 
-impl BlockchainData for Transaction {}
-
-pub struct TransactionValidator<'a> {
-    wallets: &'a Blockchain<Wallet>,
-    transactions: &'a Blockchain<Transaction>,
-}
-
-impl<'a> Validate<Transaction> for TransactionValidator<'a> {
-    fn block_valid(&self, block: &BlockCandidate<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
-        let mut total_reward = 0;
-
-        self.validate_hash(block)?;
+impl BlockchainData for Transaction {
+    fn balance_changes(&self) -> Vec<(Address, i64)> {
+        vec![
+            (self.source_address, -self.amount),
+            (self.target_address, self.amount),
+        ]
+    }
 
-        for transaction in block.data() {
-            if transaction.source_address() != MINTING_WALLET_ADDRESS {
-                let signature = match transaction.sender_signature() {
-                    None => {
-                        return Err(
-                            Box::new(TransactionValidationError)
-                        );
-                    }
-                    Some(signature) => signature
-                };
-                if transaction.source_address() == transaction.target_address() {
-                    return Err(
-                        Box::new(TransactionValidationError)
-                    );
-                }
-                self.validate_transfer(transaction, &signature)?;
-            } else {
-                total_reward += transaction.amount;
-            }
-        }
+    // penalties and system payouts take no nonce, they are not signed by their source
+    fn sender(&self) -> Option<Address> {
+        (!self.nonce_exempt()).then_some(self.source_address)
+    }
 
-        if total_reward == TRANSACTION_FEE {
-            Ok(())
-        } else {
-            Err(Box::new(
-                BlockValidationError::new(
-                    serde_json::to_string_pretty(block).unwrap(),
-                    "Invalid reward",
-                )
-            ))
+    fn token_changes(&self) -> Vec<(String, Address, i64)> {
+        match &self.contract {
+            Some(Contract::TokenMint { symbol, amount }) => vec![
+                (contract::token_id(self.source_address, symbol), self.target_address, *amount),
+            ],
+            Some(Contract::TokenTransfer { token_id, amount }) => vec![
+                (token_id.clone(), self.source_address, -amount),
+                (token_id.clone(), self.target_address, *amount),
+            ],
+            _ => vec![]
         }
     }
 }
 
-impl<'a> TransactionValidator<'a> {
-    pub fn new(wallets: &'a Blockchain<Wallet>, transactions: &'a Blockchain<Transaction>) -> TransactionValidator<'a> {
-        Self {
-            wallets,
-            transactions,
+impl Blockchain<Transaction> {
+    pub fn balance_of(&self, address: Address) -> i64 {
+        if address == MINTING_WALLET_ADDRESS {
+            return self.remaining_pool();
         }
-    }
-    pub fn wallets(&self) -> &Blockchain<Wallet> {
-        &self.wallets
+        let pending: i64 = self.uncommitted_data()
+            .iter()
+            .flat_map(Transaction::balance_changes)
+            .filter(|(changed, _)| *changed == address)
+            .map(|(_, change)| change)
+            .sum();
+        self.committed_balance(address) + pending
     }
 
-    fn validate_hash(
-        &self, block_candidate: &BlockCandidate<Transaction>,
-    ) -> Result<(), Box<dyn BlockchainError>> {
-        let given_key = block_candidate.key();
-
-        let computed = BlockCandidate::<Transaction>::hash(
-            given_key, BlockCandidate::summarize(block_candidate.data()),
-        );
+    // token amounts are whatever issuers mint, none if the pending changes would overflow
+    pub fn token_balance_of(&self, token_id: &str, address: Address) -> Option<i64> {
+        self.uncommitted_data()
+            .iter()
+            .flat_map(Transaction::token_changes)
+            .filter(|(changed_token, changed, _)| changed_token == token_id && *changed == address)
+            .try_fold(self.committed_token_balance(token_id, address), |balance, (_, _, change)| balance.checked_add(change))
+    }
 
-        if computed.previous_hash() == given_key.previous_hash()
-            && computed.hash() == given_key.hash() {
-            Ok(())
-        } else {
-            Err(Box::new(
-                BlockValidationError::new(
-                    serde_json::to_string_pretty(block_candidate).unwrap(),
-                    "Invalid hash",
-                )
-            ))
+    pub fn balance_breakdown(&self, address: Address) -> BalanceBreakdown {
+        let pending = self.uncommitted_data();
+        let pending_sum = |side: fn(&Transaction) -> Address| pending.iter()
+            .filter(|transaction| side(transaction) == address)
+            .map(|transaction| transaction.amount)
+            .sum::<i64>();
+        BalanceBreakdown {
+            confirmed: self.committed_balance(address),
+            pending_incoming: pending_sum(|transaction| transaction.target_address),
+            pending_outgoing: pending_sum(|transaction| transaction.source_address),
+            locked: 0,
         }
     }
 
-    fn validate_transfer(
-        &self, transaction: &Transaction, signature: &str,
-    ) -> Result<(), Box<dyn BlockchainError>> {
-        let source_wallet = find_wallet_by_address(
-            transaction.source_address(), &self.wallets,
-        );
-
-        match find_wallet_by_address(transaction.target_address(), &self.wallets) {
-            None => return Err(
-                Box::new(TransactionValidationError)
-            ),
-            Some(wallet) => wallet
-        };
+    // the breakdown with the stake the stakes chain holds bonded
+    pub fn staked_breakdown(&self, stakes: &Blockchain<Transaction>, address: Address) -> BalanceBreakdown {
+        self.balance_breakdown(address).with_locked(StakeRegistry::derive(stakes, self).bonded(address))
+    }
 
-        match source_wallet {
-            None => return Err(
-                Box::new(TransactionValidationError)
-            ),
-            Some(wallet) => {
-                let available_balance = wallet.balance(self.transactions);
-                let public_key = wallet.key()
-                    .clone()
-                    .unwrap();
-                let key: VerifyingKey<Sha512> = VerifyingKey::from(public_key);
-                let verified = key.verify(
-                    transaction.signed_content().as_bytes(),
-                    &Signature::from_bytes(signature.as_bytes()).unwrap())
-                    .is_err();
-                if !verified || available_balance < transaction.amount {
-                    return Err(
-                        Box::new(TransactionValidationError)
-                    );
-                } else {
-                    Ok(())
+    // a single pass over the chain, bounded chains stream their stored blocks
+    pub fn stats(&self) -> Result<ChainStats, Box<dyn BlockchainError>> {
+        let system_addresses = [
+            MINTING_WALLET_ADDRESS, *REWARD_WALLET_ADDRESS, *STAKE_WALLET_ADDRESS,
+            *CONTRACT_WALLET_ADDRESS, *BURN_WALLET_ADDRESS,
+        ];
+        let mut active_addresses: HashSet<Address> = HashSet::new();
+        let (mut block_count, mut total_transactions, mut total_volume, mut minted) = (0, 0, 0, 0);
+        let mut times: (Option<DateTime<Utc>>, Option<DateTime<Utc>>, i32) = (None, None, 0);
+        for block in self.blocks() {
+            let block = block?;
+            block_count += 1;
+            if let Some(time) = block.time() {
+                times = (times.0.or(Some(time)), Some(time), times.2 + 1);
+            }
+            for transaction in block.data() {
+                total_transactions += 1;
+                match transaction.source_address == MINTING_WALLET_ADDRESS {
+                    true => minted += transaction.amount,
+                    false => total_volume += transaction.amount,
                 }
+                active_addresses.extend(
+                    [transaction.source_address, transaction.target_address]
+                        .into_iter()
+                        .filter(|address| !system_addresses.contains(address))
+                );
             }
         }
+        let average_block_interval = match times {
+            (Some(first), Some(last), count) if count > 1 => Some((last - first) / (count - 1)),
+            _ => None
+        };
+        let capacity = (block_count * self.data_units_per_block()).max(1);
+        let burned = self.committed_balance(*BURN_WALLET_ADDRESS);
+        Ok(ChainStats {
+            block_count,
+            total_transactions,
+            total_volume,
+            average_block_fill: total_transactions as f64 / capacity as f64,
+            average_block_interval,
+            active_addresses: active_addresses.len(),
+            circulating_supply: minted - burned,
+            burned,
+        })
     }
-}
-
-pub struct TransactionCriteria;
 
-impl Criteria for TransactionCriteria {
-    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
-        true
+    // committed transfers from or to the address with their block numbers, oldest first
+    pub fn wallet_history(&self, address: Address) -> Result<Vec<(u64, Transaction)>, Box<dyn BlockchainError>> {
+        let mut history = vec![];
+        for block in self.blocks() {
+            let block = block?;
+            history.extend(block.data().iter()
+                .filter(|transaction| transaction.source_address == address || transaction.target_address == address)
+                .map(|transaction| (block.block_number(), transaction.clone())));
+        }
+        Ok(history)
     }
-}
-
-pub struct BlockCriteria;
 
-impl Criteria for BlockCriteria {
-    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
-        let hash = array_bytes::bytes2hex("", hash);
-        hash.starts_with("000000")
+    // fees already held by the reward wallet plus those paid within the block
+    pub fn accumulated_fees(&self, block_data: &[Transaction]) -> i64 {
+        let paid_in_block: i64 = block_data.iter()
+            .filter(|transaction| transaction.target_address == *REWARD_WALLET_ADDRESS)
+            .map(|transaction| transaction.amount)
+            .sum();
+        self.committed_balance(*REWARD_WALLET_ADDRESS) + paid_in_block
     }
-}
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Wallet {
-    address: [u8; 32],
-    public_key: Option<RsaPublicKey>,
+    pub fn next_nonce(&self, address: Address) -> u64 {
+        let pending = self.uncommitted_data()
+            .iter()
+            .filter(|transaction| transaction.source_address == address && !transaction.nonce_exempt())
+            .count() as u64;
+        self.committed_sent(address) + pending
+    }
 }
 
-pub struct WalletCriteria;
-
-impl Criteria for WalletCriteria {
-    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
-        true
+impl ChainEvent<Transaction> {
+    pub fn wallet_transactions(&self, address: Address) -> Vec<&Transaction> {
+        let transactions = match self {
+            ChainEvent::BlockAppended { data, .. } => data.iter().collect(),
+            ChainEvent::DataSubmitted(transaction) => vec![transaction],
+            ChainEvent::IncomingPayment { data, .. } => vec![data],
+            ChainEvent::Reorg { affected_txs, .. } => affected_txs.iter().collect(),
+            ChainEvent::Slashed { .. } => vec![],
+        };
+        transactions.into_iter()
+            .filter(|transaction| {
+                transaction.source_address == address || transaction.target_address == address
+            })
+            .collect()
     }
 }
 
-pub struct WalletValidator;
-
-impl Validate<Wallet> for WalletValidator {
-    fn block_valid(&self, block: &BlockCandidate<Wallet>) -> Result<(), Box<dyn BlockchainError>> {
-        todo!()
-    }
+pub struct TransactionValidator<'a> {
+    wallets: &'a Blockchain<Wallet>,
+    transactions: &'a Blockchain<Transaction>,
+    upgrades: &'a UpgradeSchedule,
+    // stake bonded on the stakes chain is not spendable and bounds penalties, the stake chain's
+    // balances are part of the state root
+    stakes: Option<(StakeRegistry, &'a Blockchain<Transaction>)>,
+    // replaying history checks signatures against the key active at the height of their block,
+    // new transfers always need the current key
+    key_history: Option<&'a KeyHistory>,
+    // added to the local clock when checking block times
+    clock_offset: Duration,
+    // checked ahead of a replay, see pipeline
+    verified_signatures: Option<&'a VerifiedSignatures>,
 }
 
-impl Wallet {
-    pub fn new(address: Address, public_key: Option<RsaPublicKey>) -> Wallet {
-        Wallet {
-            address,
-            public_key,
+impl<'a> Validate<Transaction> for TransactionValidator<'a> {
+    fn block_valid(&self, block: &BlockCandidate<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
+        match self.diagnose(block) {
+            Ok(_) => Ok(()),
+            Err(reason) => Err(Box::new(reason))
         }
     }
-    pub fn address(&self) -> [u8; 32] {
-        self.address
-    }
-    pub fn key(&self) -> &Option<RsaPublicKey> {
-        &self.public_key
-    }
+}
 
-    pub fn balance(
-        &self, transaction_chain: &Blockchain<Transaction>,
-    ) -> i64 {
-        if self.address == MINTING_WALLET_ADDRESS {
-            return transaction_chain.remaining_pool();
-        }
-        let mut current_block = transaction_chain.last_block();
-        let mut balance: i64 = 0;
-        loop {
-            match current_block {
-                None => break,
-                Some(block) => {
-                    balance += self.balance_pool(block.data());
-                    current_block = block.previous_block();
-                }
-            }
-        }
-        balance += self.balance_pool(transaction_chain.uncommitted_data());
-        balance
+impl<'a> TransactionValidator<'a> {
+    pub fn new(wallets: &'a Blockchain<Wallet>, transactions: &'a Blockchain<Transaction>) -> TransactionValidator<'a> {
+        TransactionValidator::with_upgrades(wallets, transactions, &UPGRADE_SCHEDULE)
     }
 
-    fn balance_pool(&self, transaction_pool: &[Transaction]) -> i64 {
-        let mut spent = 0;
-        let mut gained = 0;
-        for transaction in transaction_pool {
-            if transaction.source_address == self.address {
-                spent += transaction.amount;
-            } else if transaction.target_address == self.address {
-                gained += transaction.amount;
-            }
+    pub fn with_upgrades(
+        wallets: &'a Blockchain<Wallet>, transactions: &'a Blockchain<Transaction>,
+        upgrades: &'a UpgradeSchedule,
+    ) -> TransactionValidator<'a> {
+        Self {
+            wallets,
+            transactions,
+            upgrades,
+            stakes: None,
+            key_history: None,
+            clock_offset: Duration::zero(),
+            verified_signatures: None,
         }
-        gained - spent
     }
-}
 
-impl Summary for Wallet {
-    fn summary(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    // only voters have the stake state of the round, replays of history go without
+    pub fn with_stakes(mut self, stakes: &'a Blockchain<Transaction>) -> TransactionValidator<'a> {
+        self.stakes = Some((StakeRegistry::derive(stakes, self.transactions), stakes));
+        self
     }
-}
 
-impl BlockchainData for Wallet {}
+    pub fn with_key_history(mut self, key_history: &'a KeyHistory) -> TransactionValidator<'a> {
+        self.key_history = Some(key_history);
+        self
+    }
 
-struct BalanceError;
+    pub fn with_clock_offset(mut self, clock_offset: Duration) -> TransactionValidator<'a> {
+        self.clock_offset = clock_offset;
+        self
+    }
 
-impl BlockchainError for BalanceError {
-    fn message(&self) -> String {
-        String::from("Illegal balance")
+    pub fn with_verified_signatures(mut self, verified_signatures: &'a VerifiedSignatures) -> TransactionValidator<'a> {
+        self.verified_signatures = Some(verified_signatures);
+        self
+    }
+    pub fn wallets(&self) -> &Blockchain<Wallet> {
+        &self.wallets
     }
-}
 
-struct TransactionValidationError;
+    // checks a single transfer against the current chain state, used before broadcasting and on votes
+    pub fn diagnose(&self, block: &BlockCandidate<Transaction>) -> Result<(), RejectionReason> {
+        let mut total_reward = 0;
+        let mut total_payout = 0;
+        let rules = self.upgrades.rules_at(block.block_number());
 
-impl BlockchainError for TransactionValidationError {
-    fn message(&self) -> String {
-        String::from("Transaction invalid")
-    }
-}
+        if let Err(error) = validate_hash(block) {
+            return Err(RejectionReason::Malformed(error.message()));
+        }
+        validate_time(self.transactions, block, Utc::now() + self.clock_offset)?;
+        // the forger's reward and fee payout come on top of what a block may carry
+        let carried = block.data().iter()
+            .filter(|transaction| {
+                transaction.is_grant()
+                    || ![MINTING_WALLET_ADDRESS, *REWARD_WALLET_ADDRESS].contains(&transaction.source_address())
+            })
+            .count() as u64;
+        if carried > rules.block_size() {
+            return Err(RejectionReason::Malformed(
+                format!("{} transactions, at most {} fit in a block", carried, rules.block_size())
+            ));
+        }
 
-pub fn find_wallet_by_address(address: Address, wallet_chain: &Blockchain<Wallet>) -> Option<Wallet> {
-    let mut current_block = wallet_chain.last_block();
-    loop {
-        match current_block {
-            None => break None,
-            Some(block) => {
-                match extract_wallet(block.data(), address) {
-                    None => current_block = block.previous_block(),
-                    Some(wallet) => break Some(wallet)
+        match (block.key().raw_state_root(), &self.stakes) {
+            (None, Some(_)) if rules.state_roots() => {
+                return Err(RejectionReason::Malformed(String::from("Block carries no state root")));
+            }
+            (Some(state_root), Some((_, stakes))) => {
+                let expected = snapshot::account_state_root(self.transactions, block.data(), stakes);
+                if state_root != expected {
+                    return Err(RejectionReason::StateRootMismatch {
+                        expected: array_bytes::bytes2hex("", expected),
+                    });
                 }
             }
+            _ => {}
         }
-    }
-}
-
-fn extract_wallet(data: &Vec<Wallet>, address: Address) -> Option<Wallet> {
-    for entry in data {
-        if entry.address() == address {
-            return Some(entry.clone());
+        // the same data always makes the same block, whoever forged it
+        let ordered = block.data().windows(2).all(|pair| pair[0].canonical_cmp(&pair[1]) != Ordering::Greater);
+        if rules.canonical_order() && !ordered {
+            return Err(RejectionReason::Malformed(String::from("Transactions out of canonical order")));
         }
-    };
-    None
-}
-
-mod test {
-    use std::cell::RefCell;
 
-    use chrono::Utc;
+        let mut settled_locks = HashSet::new();
+        let mut granted_wallets = HashSet::new();
+        let mut total_granted = 0;
+        let mut penalized = HashMap::new();
+        let mut next_nonces = HashMap::new();
+        let mut spent = HashMap::new();
+        for transaction in block.data() {
+            if transaction.is_penalty() {
+                let result = self.validate_penalty(transaction, &mut penalized);
+                if let Err(error) = result {
+                    return Err(RejectionReason::InvalidTransaction { id: transaction.id(), error });
+                }
+            } else if transaction.source_address() == *REWARD_WALLET_ADDRESS {
+                total_payout += transaction.amount;
+            } else if transaction.source_address() == *CONTRACT_WALLET_ADDRESS {
+                // one block settling a lock twice would pass the checks against the chain
+                let lock_id = transaction.contract.as_ref().and_then(Contract::lock_id).unwrap_or_default();
+                let result = match settled_locks.insert(lock_id.to_string()) {
+                    false => Err(TransactionValidationError::ContractSettled),
+                    true => self.validate_settlement(transaction, rules.signature_scheme()),
+                };
+                if let Err(error) = result {
+                    return Err(RejectionReason::InvalidTransaction { id: transaction.id(), error });
+                }
+            } else if transaction.is_grant() {
+                let result = match granted_wallets.insert(transaction.target_address()) {
+                    false => Err(TransactionValidationError::AlreadyGranted),
+                    true => self.validate_grant(transaction, rules),
+                };
+                if let Err(error) = result {
+                    return Err(RejectionReason::InvalidTransaction { id: transaction.id(), error });
+                }
+                // grants are cheap to claim for wallets registered in bulk, their rate is capped
+                if granted_wallets.len() > rules.grants_per_block() {
+                    return Err(RejectionReason::Malformed(String::from("Too many grants in block")));
+                }
+                total_granted += transaction.amount;
+            } else if transaction.source_address() != MINTING_WALLET_ADDRESS {
+                // a block pays from committed coins, less what the sender's earlier transfers in it
+                // moved, whatever the mempool holds besides
+                let source = transaction.source_address();
+                let spent_by_source: &mut i64 = spent.entry(source).or_default();
+                let balance = self.transactions.committed_balance(source) - *spent_by_source;
+                let result = self.validate_nonce(transaction, &mut next_nonces)
+                    .and_then(|_| self.validate_transfer(transaction, rules, balance))
+                    .and_then(|_| self.validate_sponsorship(transaction, block.data()));
+                if let Err(error) = result {
+                    return Err(RejectionReason::InvalidTransaction { id: transaction.id(), error });
+                }
+                *spent_by_source += transaction.amount;
+            } else {
+                total_reward += transaction.amount;
+            }
+        }
+
+        if total_reward + total_granted > self.transactions.remaining_pool() {
+            return Err(RejectionReason::InvalidPayout(TransactionValidationError::SupplyExceeded {
+                remaining: self.transactions.remaining_pool(),
+                minted: total_reward + total_granted,
+            }));
+        }
+        // the last rewards shrink to what is left of the supply, after that forgers live on fees
+        let expected_reward = self.transactions.mintable(rules.block_reward());
+        if total_reward != expected_reward {
+            return Err(RejectionReason::InvalidPayout(TransactionValidationError::BadReward {
+                expected: expected_reward,
+                actual: total_reward,
+            }));
+        }
+        let accumulated_fees = self.transactions.accumulated_fees(block.data());
+        if total_payout != accumulated_fees {
+            return Err(RejectionReason::InvalidPayout(TransactionValidationError::BadFeePayout {
+                expected: accumulated_fees,
+                actual: total_payout,
+            }));
+        }
+        Ok(())
+    }
+
+    pub fn transaction_valid(&self, transaction: &Transaction) -> Result<(), TransactionValidationError> {
+        let rules = self.upgrades.rules_at(self.transactions.chain_length());
+        if transaction.source_address() == *CONTRACT_WALLET_ADDRESS {
+            return self.validate_settlement(transaction, rules.signature_scheme());
+        }
+        if transaction.is_grant() {
+            return self.validate_grant(transaction, rules);
+        }
+        if transaction.is_penalty() {
+            return self.validate_penalty(transaction, &mut HashMap::new());
+        }
+        self.validate_transfer(transaction, rules, self.transactions.balance_of(transaction.source_address()))?;
+        self.validate_sponsorship(transaction, self.transactions.uncommitted_data())
+    }
+
+    // What a node checks before broadcasting a transaction: valid against the chain, next in
+    // line for its sender, paid from spendable coins and not dust. Sends run it too, so a dry
+    // run reports what a real send would refuse.
+    pub fn check_transaction(&self, transaction: &Transaction) -> Result<(), TransactionValidationError> {
+        self.check_after(transaction, &[])
+    }
+
+    // a transfer and its fee, each checked as if the ones before it were already pending
+    pub fn check_transactions(&self, transactions: &[Transaction]) -> Result<(), TransactionValidationError> {
+        for (position, transaction) in transactions.iter().enumerate() {
+            self.check_after(transaction, &transactions[..position])?;
+        }
+        Ok(())
+    }
+
+    fn check_after(&self, transaction: &Transaction, earlier: &[Transaction]) -> Result<(), TransactionValidationError> {
+        self.transaction_valid(transaction)?;
+        // a plain transfer moving less than the fee paying for it
+        let minimum = self.upgrades.rules_at(self.transactions.chain_length()).transfer_fee().max(1);
+        let plain = transaction.contract.is_none() && transaction.target_address() != *REWARD_WALLET_ADDRESS;
+        if plain && transaction.amount < minimum {
+            return Err(TransactionValidationError::BelowDust { minimum });
+        }
+        if transaction.nonce_exempt() {
+            return Ok(());
+        }
+        let source = transaction.source_address();
+        let earlier: Vec<&Transaction> = earlier.iter().filter(|other| other.source_address() == source).collect();
+        let expected = self.transactions.next_nonce(source) + earlier.len() as u64;
+        if transaction.nonce() != expected {
+            return Err(TransactionValidationError::BadNonce {
+                expected,
+                actual: transaction.nonce(),
+            });
+        }
+        // unlike transaction_valid, incoming payments still pending are not spendable yet
+        let locked = match &self.stakes {
+            None => 0,
+            Some((registry, _)) => registry.bonded(source)
+        };
+        let spendable = self.transactions.balance_breakdown(source).spendable() - locked
+            - earlier.iter().map(|other| other.amount).sum::<i64>();
+        if transaction.amount > spendable {
+            return Err(TransactionValidationError::InsufficientBalance {
+                have: spendable,
+                need: transaction.amount,
+            });
+        }
+        Ok(())
+    }
+
+    // Each sender's transfers in a block go on from the nonce its committed ones reached, one
+    // after another, so a transfer committed before cannot be carried by a later block again.
+    fn validate_nonce(
+        &self, transaction: &Transaction, next_nonces: &mut HashMap<Address, u64>,
+    ) -> Result<(), TransactionValidationError> {
+        if transaction.nonce_exempt() {
+            return Ok(());
+        }
+        let source = transaction.source_address();
+        let expected = next_nonces.entry(source).or_insert_with(|| self.transactions.committed_sent(source));
+        if transaction.nonce() != *expected {
+            return Err(TransactionValidationError::BadNonce {
+                expected: *expected,
+                actual: transaction.nonce(),
+            });
+        }
+        *expected += 1;
+        Ok(())
+    }
+
+    // a sponsored transfer comes first, committed or earlier among the other transactions of the
+    // block or the mempool passed alongside, and its fee is sponsored once
+    fn validate_sponsorship(
+        &self, transaction: &Transaction, alongside: &[Transaction],
+    ) -> Result<(), TransactionValidationError> {
+        let transfer_id = match transaction.contract.as_ref().and_then(Contract::sponsored_transfer) {
+            None => return Ok(()),
+            Some(transfer_id) => transfer_id
+        };
+        let sponsors = |other: &&Transaction| other.contract.as_ref().and_then(Contract::sponsored_transfer) == Some(transfer_id);
+        let sponsored_alongside = alongside.iter().filter(sponsors).any(|other| other.id() != transaction.id());
+        if sponsored_alongside || contract::sponsored(self.transactions, transfer_id) {
+            return Err(TransactionValidationError::AlreadySponsored);
+        }
+        if !alongside.iter().any(|other| other.id() == transfer_id) && !contract::transfer_committed(self.transactions, transfer_id) {
+            return Err(TransactionValidationError::UnknownSponsoredTransfer);
+        }
+        Ok(())
+    }
+
+    // Burned from the offender without its signature, so voters bound it by the bond it is
+    // charged against; replays without the stake state only check its form. Penalties earlier
+    // in the same block count against the bond too.
+    fn validate_penalty(
+        &self, transaction: &Transaction, penalized: &mut HashMap<(Address, u64), i64>,
+    ) -> Result<(), TransactionValidationError> {
+        let epoch = match transaction.contract {
+            Some(Contract::Penalty { epoch }) => epoch,
+            _ => return Err(TransactionValidationError::BadContract),
+        };
+        if !transaction.is_burn() || transaction.amount <= 0 || protocol::is_reserved(&transaction.source_address()) {
+            return Err(TransactionValidationError::BadContract);
+        }
+        let registry = match &self.stakes {
+            None => return Ok(()),
+            Some((registry, _)) => registry
+        };
+        let charged = penalized.entry((transaction.source_address(), epoch)).or_insert(0);
+        let bonded = registry.bond(transaction.source_address(), epoch).unwrap_or(0) - *charged;
+        if transaction.amount > bonded {
+            return Err(TransactionValidationError::PenaltyExceedsStake {
+                bonded,
+                penalty: transaction.amount,
+            });
+        }
+        *charged += transaction.amount;
+        Ok(())
+    }
+
+    // minted once per registered wallet, for whoever solved the puzzle over its address
+    fn validate_grant(&self, transaction: &Transaction, rules: &ConsensusRules) -> Result<(), TransactionValidationError> {
+        let wallet_grant = rules.wallet_grant();
+        let work = match transaction.contract {
+            Some(Contract::Grant { work }) => work,
+            _ => return Err(TransactionValidationError::BadContract),
+        };
+        if wallet_grant <= 0 || transaction.amount != wallet_grant {
+            return Err(TransactionValidationError::BadGrant {
+                expected: wallet_grant.max(0),
+                actual: transaction.amount,
+            });
+        }
+        if find_wallet_by_address(transaction.target_address(), self.wallets).is_none() {
+            return Err(TransactionValidationError::UnknownTargetWallet);
+        }
+        if !contract::grant_work_valid(transaction.target_address(), work, rules.grant_work_bits()) {
+            return Err(TransactionValidationError::BadGrantWork);
+        }
+        if contract::granted(self.transactions, transaction.target_address()) {
+            return Err(TransactionValidationError::AlreadyGranted);
+        }
+        Ok(())
+    }
+
+    // releases locked funds, signed by whoever the lock lets take them
+    fn validate_settlement(
+        &self, transaction: &Transaction, signature_scheme: SignatureScheme,
+    ) -> Result<(), TransactionValidationError> {
+        let lock_id = match transaction.contract.as_ref().and_then(Contract::lock_id) {
+            None => return Err(TransactionValidationError::BadContract),
+            Some(lock_id) => lock_id
+        };
+        let lock = match contract::find_lock(self.transactions, lock_id) {
+            None => return Err(TransactionValidationError::UnknownContract),
+            Some(lock) => lock
+        };
+        if contract::settled(self.transactions, lock_id) {
+            return Err(TransactionValidationError::ContractSettled);
+        }
+        if let Some(parties) = contract::escrow_parties(&lock) {
+            return self.validate_escrow_settlement(transaction, &lock, parties, signature_scheme);
+        }
+        let (recipient, hash_lock, expires_at) = match &lock.contract {
+            Some(Contract::HtlcLock { recipient, hash_lock, expires_at }) => (*recipient, hash_lock, *expires_at),
+            _ => return Err(TransactionValidationError::UnknownContract)
+        };
+        // the previous block's time, not the transaction's, so nobody can backdate a claim
+        let chain_time = self.transactions.last_block()
+            .as_ref()
+            .and_then(|block| block.time())
+            .unwrap_or_default();
+        let signer = match &transaction.contract {
+            Some(Contract::HtlcClaim { preimage, .. }) => {
+                if contract::hash_lock(preimage) != *hash_lock {
+                    return Err(TransactionValidationError::BadPreimage);
+                }
+                if chain_time >= expires_at {
+                    return Err(TransactionValidationError::ContractExpired);
+                }
+                recipient
+            }
+            Some(Contract::HtlcRefund { .. }) => {
+                if chain_time < expires_at {
+                    return Err(TransactionValidationError::ContractNotExpired);
+                }
+                lock.source_address
+            }
+            _ => return Err(TransactionValidationError::BadContract)
+        };
+        if transaction.target_address != signer || transaction.amount != lock.amount {
+            return Err(TransactionValidationError::BadContract);
+        }
+        self.verify_signature(transaction, signer, signature_scheme)
+    }
+
+    // token transfers move no coins and only tokens the sender holds, system wallets hold none
+    fn validate_tokens(&self, transaction: &Transaction) -> Result<(), TransactionValidationError> {
+        let amount = match &transaction.contract {
+            Some(Contract::TokenMint { symbol, amount }) if contract::symbol_valid(symbol) => *amount,
+            Some(Contract::TokenTransfer { amount, .. }) => *amount,
+            Some(Contract::TokenMint { .. }) => return Err(TransactionValidationError::BadContract),
+            _ => return Ok(())
+        };
+        let system_target = transaction.target_address() == *REWARD_WALLET_ADDRESS
+            || transaction.target_address() == *STAKE_WALLET_ADDRESS
+            || transaction.is_burn();
+        if amount <= 0 || transaction.amount != 0 || system_target {
+            return Err(TransactionValidationError::BadContract);
+        }
+        if let Some(Contract::TokenTransfer { token_id, .. }) = &transaction.contract {
+            let held = self.transactions.token_balance_of(token_id, transaction.source_address()).unwrap_or(0);
+            if held < amount {
+                return Err(TransactionValidationError::InsufficientTokens {
+                    have: held,
+                    need: amount,
+                });
+            }
+        }
+        // no holder may end up with more than a balance can count
+        for (token_id, holder, change) in transaction.token_changes() {
+            let updated = self.transactions.token_balance_of(&token_id, holder)
+                .and_then(|balance| balance.checked_add(change));
+            if updated.is_none() {
+                return Err(TransactionValidationError::TokenOverflow);
+            }
+        }
+        Ok(())
+    }
+
+    // releases go to the recipient and refunds back to the sender, each once two of the three
+    // parties approved it
+    fn validate_escrow_settlement(
+        &self, transaction: &Transaction, escrow: &Transaction, parties: [Address; 3],
+        signature_scheme: SignatureScheme,
+    ) -> Result<(), TransactionValidationError> {
+        let [sender, recipient, _] = parties;
+        let target = match &transaction.contract {
+            Some(Contract::EscrowRelease { .. }) => recipient,
+            Some(Contract::EscrowRefund { .. }) => sender,
+            _ => return Err(TransactionValidationError::BadContract)
+        };
+        if transaction.target_address != target || transaction.amount != escrow.amount {
+            return Err(TransactionValidationError::BadContract);
+        }
+        self.verify_approvals(transaction, &parties, contract::ESCROW_APPROVALS, signature_scheme)
+    }
+
+    // approvals of an escrow settlement signed by one of the escrow's parties, whatever else it
+    // carries is dropped before it is kept around waiting for the rest
+    pub fn party_approvals(&self, settlement: &Transaction) -> Vec<Approval> {
+        let parties = settlement.contract.as_ref()
+            .and_then(Contract::lock_id)
+            .and_then(|lock_id| contract::find_lock(self.transactions, lock_id))
+            .and_then(|escrow| contract::escrow_parties(&escrow));
+        let parties = match parties {
+            None => return vec![],
+            Some(parties) => parties
+        };
+        let signature_scheme = self.upgrades.rules_at(self.transactions.chain_length()).signature_scheme();
+        settlement.approvals().iter()
+            .filter(|approval| parties.contains(&approval.signer()))
+            .filter(|approval| self.signature_valid(settlement, approval.signer(), approval.signature(), signature_scheme))
+            .cloned()
+            .collect()
+    }
+
+    // the multisig path: distinct parties with a valid approval must reach the threshold, approvals
+    // of anyone else are ignored
+    fn verify_approvals(
+        &self, transaction: &Transaction, parties: &[Address], threshold: usize,
+        signature_scheme: SignatureScheme,
+    ) -> Result<(), TransactionValidationError> {
+        let mut approved = HashSet::new();
+        for approval in transaction.approvals() {
+            if !parties.contains(&approval.signer()) {
+                continue;
+            }
+            if !self.signature_valid(transaction, approval.signer(), approval.signature(), signature_scheme) {
+                return Err(TransactionValidationError::BadSignature);
+            }
+            approved.insert(approval.signer());
+        }
+        match approved.len() >= threshold {
+            true => Ok(()),
+            false => Err(TransactionValidationError::MissingApprovals {
+                have: approved.len(),
+                need: threshold,
+            })
+        }
+    }
+
+    fn verify_signature(
+        &self, transaction: &Transaction, signer: Address, signature_scheme: SignatureScheme,
+    ) -> Result<(), TransactionValidationError> {
+        let signature = match transaction.sender_signature() {
+            None => return Err(TransactionValidationError::MissingSignature),
+            Some(signature) => signature
+        };
+        if find_wallet_by_address(signer, self.wallets).is_none() {
+            return Err(TransactionValidationError::UnknownSourceWallet);
+        }
+        match self.signature_valid(transaction, signer, signature, signature_scheme) {
+            true => Ok(()),
+            false => Err(TransactionValidationError::BadSignature)
+        }
+    }
+
+    fn signature_valid(
+        &self, transaction: &Transaction, signer: Address, signature: &str, signature_scheme: SignatureScheme,
+    ) -> bool {
+        let public_key = match find_wallet_by_address(signer, self.wallets) {
+            None => None,
+            Some(wallet) => match self.key_history {
+                None => wallet.key().clone(),
+                Some(history) => history.key_at(signer, self.transactions.chain_length()).or_else(|| wallet.key().clone())
+            }
+        };
+        let content = transaction.signed_content();
+        let verified = |public_key| self.verified_signatures
+            .is_some_and(|verified| verified.contains(public_key, &content, signature));
+        match (&public_key, signature_scheme) {
+            (None, _) => false,
+            (Some(public_key), _) if verified(public_key) => true,
+            (Some(public_key), SignatureScheme::RsaPssSha512) => access::verify_message(public_key, &content, signature)
+        }
+    }
+
+    // balance is what the sender holds before this transfer, bonded stake is taken off it here
+    fn validate_transfer(
+        &self, transaction: &Transaction, rules: &ConsensusRules, balance: i64,
+    ) -> Result<(), TransactionValidationError> {
+        // a negative burn would take coins back out of the burn address
+        if transaction.source_address() == *BURN_WALLET_ADDRESS || (transaction.is_burn() && transaction.amount <= 0) {
+            return Err(TransactionValidationError::BurnedCoinsSpent);
+        }
+        // a negative transfer would take coins from its target, tokens ride on transfers of none
+        let token = matches!(transaction.contract, Some(Contract::TokenMint { .. } | Contract::TokenTransfer { .. }));
+        if !token && transaction.amount <= 0 {
+            return Err(TransactionValidationError::NonPositiveAmount { amount: transaction.amount });
+        }
+        // the minting and stake wallets only pay out, coins sent there would count as unminted
+        // supply or go missing from stake accounting. Whatever reaches the reward wallet is a fee
+        // the next block pays out to its forger, so transfers there stay valid.
+        if rules.reserved_targets() && [MINTING_WALLET_ADDRESS, *STAKE_WALLET_ADDRESS].contains(&transaction.target_address()) {
+            return Err(TransactionValidationError::ReservedTarget);
+        }
+        if transaction.sender_signature().is_none() {
+            return Err(TransactionValidationError::MissingSignature);
+        }
+        // grants are only ever paid from the minting wallet
+        if matches!(transaction.contract, Some(Contract::Grant { .. })) {
+            return Err(TransactionValidationError::BadContract);
+        }
+        let sponsorship = matches!(transaction.contract, Some(Contract::Sponsorship { .. }));
+        if sponsorship && (transaction.target_address() != *REWARD_WALLET_ADDRESS || transaction.amount <= 0) {
+            return Err(TransactionValidationError::BadContract);
+        }
+        // issuers mint tokens to themselves
+        let minting = matches!(transaction.contract, Some(Contract::TokenMint { .. }));
+        if transaction.source_address() == transaction.target_address() && !minting {
+            return Err(TransactionValidationError::SelfTransfer);
+        }
+        // only locks carry a contract into the contract wallet, settlements leave it
+        let locking = transaction.contract.as_ref().is_some_and(Contract::locks_funds);
+        let into_contract = transaction.target_address() == *CONTRACT_WALLET_ADDRESS;
+        let settling = transaction.contract.as_ref().and_then(Contract::lock_id).is_some();
+        if locking != into_contract || settling {
+            return Err(TransactionValidationError::BadContract);
+        }
+        let counterparties = match &transaction.contract {
+            Some(Contract::HtlcLock { recipient, .. }) => vec![*recipient],
+            Some(Contract::EscrowOpen { recipient, arbiter }) => {
+                // a sender arbitrating its own escrow could take the funds back alone
+                let source = transaction.source_address();
+                if *arbiter == source || *arbiter == *recipient || *recipient == source {
+                    return Err(TransactionValidationError::BadContract);
+                }
+                vec![*recipient, *arbiter]
+            }
+            _ => vec![]
+        };
+        if rules.reserved_targets() && counterparties.iter().any(protocol::is_reserved) {
+            return Err(TransactionValidationError::ReservedTarget);
+        }
+        if counterparties.iter().any(|address| find_wallet_by_address(*address, self.wallets).is_none()) {
+            return Err(TransactionValidationError::UnknownTargetWallet);
+        }
+        // fees, locks and burns go to system wallets that are never registered, and so did
+        // transfers to the stake wallet until it was reserved
+        let system_target = transaction.target_address() == *REWARD_WALLET_ADDRESS
+            || transaction.is_burn()
+            || locking
+            || (!rules.reserved_targets() && transaction.target_address() == *STAKE_WALLET_ADDRESS);
+        if !system_target && find_wallet_by_address(transaction.target_address(), self.wallets).is_none() {
+            return Err(TransactionValidationError::UnknownTargetWallet);
+        }
+
+        let wallet = match find_wallet_by_address(transaction.source_address(), self.wallets) {
+            None => return Err(TransactionValidationError::UnknownSourceWallet),
+            Some(wallet) => wallet
+        };
+        self.verify_signature(transaction, wallet.address(), rules.signature_scheme())?;
+        self.validate_tokens(transaction)?;
+        let locked = match &self.stakes {
+            None => 0,
+            Some((registry, _)) => registry.bonded(wallet.address())
+        };
+        let available_balance = balance - locked;
+        if available_balance < transaction.amount {
+            return Err(TransactionValidationError::InsufficientBalance {
+                have: available_balance,
+                need: transaction.amount,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct TransactionCriteria;
+
+impl Criteria for TransactionCriteria {
+    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
+        true
+    }
+}
+
+pub struct BlockCriteria;
+
+impl Criteria for BlockCriteria {
+    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
+        let hash = array_bytes::bytes2hex("", hash);
+        hash.starts_with("000000")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Wallet {
+    address: [u8; 32],
+    #[serde(with = "compact_key")]
+    public_key: Option<RsaPublicKey>,
+    // set on key rotations, made with the key being replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rotation_signature: Option<String>,
+}
+
+// the serde form wallets had before their keys were compacted, block hashes cover this one
+#[derive(Serialize)]
+struct ExpandedWallet<'a> {
+    address: &'a Address,
+    public_key: Option<&'a RsaPublicKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotation_signature: Option<&'a String>,
+}
+
+pub struct WalletCriteria;
+
+impl Criteria for WalletCriteria {
+    fn criteria_fulfilled(&self, hash: &[u8]) -> bool {
+        true
+    }
+}
+
+pub struct WalletValidator<'a> {
+    wallets: &'a Blockchain<Wallet>,
+    clock_offset: Duration,
+}
+
+impl<'a> Validate<Wallet> for WalletValidator<'a> {
+    fn block_valid(&self, block: &BlockCandidate<Wallet>) -> Result<(), Box<dyn BlockchainError>> {
+        match self.diagnose(block) {
+            Ok(_) => Ok(()),
+            Err(reason) => Err(Box::new(reason))
+        }
+    }
+}
+
+impl<'a> WalletValidator<'a> {
+    pub fn new(wallets: &'a Blockchain<Wallet>) -> WalletValidator<'a> {
+        WalletValidator {
+            wallets,
+            clock_offset: Duration::zero(),
+        }
+    }
+
+    pub fn with_clock_offset(mut self, clock_offset: Duration) -> WalletValidator<'a> {
+        self.clock_offset = clock_offset;
+        self
+    }
+
+    pub fn diagnose(&self, block: &BlockCandidate<Wallet>) -> Result<(), RejectionReason> {
+        if let Err(error) = validate_hash(block) {
+            return Err(RejectionReason::Malformed(error.message()));
+        }
+        validate_time(self.wallets, block, Utc::now() + self.clock_offset)?;
+        for (index, wallet) in block.data().iter().enumerate() {
+            let valid = match find_wallet_by_address(wallet.address, self.wallets) {
+                None => self.registration_valid(wallet),
+                Some(current) => rotation_valid(&current, wallet)
+            };
+            if let Err(error) = valid {
+                return Err(RejectionReason::InvalidWallet(error.message()));
+            }
+            if block.data()[..index].iter().any(|other| other.address == wallet.address) {
+                let error = WalletRegistrationError::new("Wallet updated twice in one block");
+                return Err(RejectionReason::InvalidWallet(error.message()));
+            }
+        }
+        Ok(())
+    }
+
+    // a wallet may only be registered once, under the address derived from its own key
+    pub fn registration_valid(&self, wallet: &Wallet) -> Result<(), Box<dyn BlockchainError>> {
+        let public_key = match &wallet.public_key {
+            None => return Err(Box::new(WalletRegistrationError::new("Wallet has no public key"))),
+            Some(public_key) => public_key
+        };
+        if access::derive_address(public_key) != wallet.address {
+            return Err(Box::new(WalletRegistrationError::new("Address does not match public key")));
+        }
+        if find_wallet_by_address(wallet.address, self.wallets).is_some() {
+            return Err(Box::new(WalletRegistrationError::new("Wallet already registered")));
+        }
+        Ok(())
+    }
+
+    pub fn key_update_valid(&self, wallet: &Wallet) -> Result<(), Box<dyn BlockchainError>> {
+        match find_wallet_by_address(wallet.address, self.wallets) {
+            None => Err(Box::new(WalletRegistrationError::new("Cannot rotate the key of an unknown wallet"))),
+            Some(current) => rotation_valid(&current, wallet)
+        }
+    }
+}
+
+// the address stays, only the key changes and the current key has to sign off on its successor
+fn rotation_valid(current: &Wallet, rotated: &Wallet) -> Result<(), Box<dyn BlockchainError>> {
+    let (current_key, rotated_key, signature) = match (
+        &current.public_key, &rotated.public_key, &rotated.rotation_signature,
+    ) {
+        (Some(current_key), Some(rotated_key), Some(signature)) => (current_key, rotated_key, signature),
+        (None, _, _) => return Err(Box::new(WalletRegistrationError::new("Wallet has no key to rotate"))),
+        (_, None, _) => return Err(Box::new(WalletRegistrationError::new("Wallet has no public key"))),
+        (_, _, None) => return Err(Box::new(WalletRegistrationError::new("Wallet already registered")))
+    };
+    if current_key == rotated_key {
+        return Err(Box::new(WalletRegistrationError::new("Rotated key is unchanged")));
+    }
+    if !access::verify_message(current_key, &rotated.rotation_content(), signature) {
+        return Err(Box::new(WalletRegistrationError::new("Key rotation not signed by the current key")));
+    }
+    Ok(())
+}
+
+impl Wallet {
+    pub fn new(address: Address, public_key: Option<RsaPublicKey>) -> Wallet {
+        Wallet {
+            address,
+            public_key,
+            rotation_signature: None,
+        }
+    }
+
+    pub fn rotated(address: Address, public_key: RsaPublicKey, rotation_signature: String) -> Wallet {
+        Wallet {
+            address,
+            public_key: Some(public_key),
+            rotation_signature: Some(rotation_signature),
+        }
+    }
+
+    // what the outgoing key signs, commits to the address and the fingerprint of the new key
+    pub fn rotation_content(&self) -> String {
+        let fingerprint = match &self.public_key {
+            None => String::new(),
+            Some(public_key) => array_bytes::bytes2hex("", access::derive_address(public_key))
+        };
+        format!(
+            "wallet-key:{}:{}:{}",
+            protocol::chain_id(), array_bytes::bytes2hex("", self.address), fingerprint
+        )
+    }
+
+    pub fn rotation_signature(&self) -> &Option<String> {
+        &self.rotation_signature
+    }
+
+    // identifies a registration or rotation while it is pending, like Transaction::id
+    pub fn id(&self) -> String {
+        array_bytes::bytes2hex("", Sha256::digest(self.summary().as_bytes()))
+    }
+
+    pub fn address(&self) -> [u8; 32] {
+        self.address
+    }
+    pub fn key(&self) -> &Option<RsaPublicKey> {
+        &self.public_key
+    }
+
+    pub fn balance(
+        &self, transaction_chain: &Blockchain<Transaction>,
+    ) -> i64 {
+        transaction_chain.balance_of(self.address)
+    }
+
+    pub fn balance_at(
+        &self, transaction_chain: &Blockchain<Transaction>, height: u64,
+    ) -> Result<i64, Box<dyn BlockchainError>> {
+        transaction_chain.committed_balance_at(self.address, height)
+    }
+}
+
+impl Summary for Wallet {
+    fn summary(&self) -> String {
+        serde_json::to_string(&ExpandedWallet {
+            address: &self.address,
+            public_key: self.public_key.as_ref(),
+            rotation_signature: self.rotation_signature.as_ref(),
+        }).unwrap()
+    }
+}
+
+impl BlockchainData for Wallet {}
+
+struct BalanceError;
+
+impl BlockchainError for BalanceError {
+    fn message(&self) -> String {
+        String::from("Illegal balance")
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TransactionValidationError {
+    MissingSignature,
+    BadSignature,
+    UnknownSourceWallet,
+    UnknownTargetWallet,
+    InsufficientBalance {
+        have: i64,
+        need: i64,
+    },
+    SelfTransfer,
+    BadReward {
+        expected: i64,
+        actual: i64,
+    },
+    SupplyExceeded {
+        remaining: i64,
+        minted: i64,
+    },
+    BadFeePayout {
+        expected: i64,
+        actual: i64,
+    },
+    BadContract,
+    UnknownContract,
+    ContractSettled,
+    BadPreimage,
+    ContractExpired,
+    ContractNotExpired,
+    MissingApprovals {
+        have: usize,
+        need: usize,
+    },
+    InsufficientTokens {
+        have: i64,
+        need: i64,
+    },
+    TokenOverflow,
+    BurnedCoinsSpent,
+    NonPositiveAmount {
+        amount: i64,
+    },
+    BadGrant {
+        expected: i64,
+        actual: i64,
+    },
+    BadGrantWork,
+    AlreadyGranted,
+    UnknownSponsoredTransfer,
+    AlreadySponsored,
+    ReservedTarget,
+    // checked before broadcasting, see TransactionValidator::check_transaction, and on blocks
+    BadNonce {
+        expected: u64,
+        actual: u64,
+    },
+    BelowDust {
+        minimum: i64,
+    },
+    PenaltyExceedsStake {
+        bonded: i64,
+        penalty: i64,
+    },
+}
+
+// why a voter rejected a block, sent along with its vote so the forger can tell what went wrong
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RejectionReason {
+    // identified by Transaction::id
+    InvalidTransaction {
+        id: String,
+        error: TransactionValidationError,
+    },
+    InvalidPayout(TransactionValidationError),
+    InvalidWallet(String),
+    Malformed(String),
+    // times going back behind the median of recent blocks or too far ahead of the voter's clock
+    TimeTooEarly {
+        median_time_past: DateTime<Utc>,
+    },
+    TimeTooLate {
+        drift_seconds: i64,
+    },
+    // the voter's account state after the block differs from the forger's
+    StateRootMismatch {
+        expected: String,
+    },
+    // one of the rules the network's config adds, see blockchain::rules
+    RuleBroken(String),
+}
+
+impl BlockchainError for RejectionReason {
+    fn message(&self) -> String {
+        match self {
+            RejectionReason::InvalidTransaction { id, error } => {
+                format!("{} (transaction {})", error.message(), &id[..id.len().min(16)])
+            }
+            RejectionReason::InvalidPayout(error) => error.message(),
+            RejectionReason::InvalidWallet(message) => message.clone(),
+            RejectionReason::Malformed(message) => format!("Malformed block: {}", message),
+            RejectionReason::TimeTooEarly { median_time_past } => {
+                format!("Block time precedes the median of recent blocks, {}", median_time_past.to_rfc3339())
+            }
+            RejectionReason::TimeTooLate { drift_seconds } => {
+                format!("Block time is {}s ahead, at most {}s allowed", drift_seconds, MAX_FUTURE_DRIFT_SECONDS)
+            }
+            RejectionReason::StateRootMismatch { expected } => {
+                format!("State root differs, expected {}", &expected[..16])
+            }
+            RejectionReason::RuleBroken(message) => message.clone(),
+        }
+    }
+}
+
+pub struct WalletRegistrationError {
+    message: String,
+}
+
+impl WalletRegistrationError {
+    pub fn new(message: &str) -> WalletRegistrationError {
+        WalletRegistrationError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for WalletRegistrationError {
+    fn message(&self) -> String {
+        format!("Wallet: {}", self.message)
+    }
+}
+
+impl BlockchainError for TransactionValidationError {
+    fn message(&self) -> String {
+        let reason = match self {
+            TransactionValidationError::MissingSignature => String::from("missing signature"),
+            TransactionValidationError::BadSignature => String::from("signature does not match the source wallet"),
+            TransactionValidationError::UnknownSourceWallet => String::from("source wallet is not registered"),
+            TransactionValidationError::UnknownTargetWallet => String::from("target wallet is not registered"),
+            TransactionValidationError::InsufficientBalance { have, need } => {
+                format!("insufficient balance, have {} need {}", have, need)
+            }
+            TransactionValidationError::SelfTransfer => String::from("source and target are the same wallet"),
+            TransactionValidationError::BadReward { expected, actual } => {
+                format!("block reward is {}, expected {}", actual, expected)
+            }
+            TransactionValidationError::SupplyExceeded { remaining, minted } => {
+                format!("mints {} with only {} left of the total supply", minted, remaining)
+            }
+            TransactionValidationError::BadFeePayout { expected, actual } => {
+                format!("fee payout is {}, expected {}", actual, expected)
+            }
+            TransactionValidationError::BadContract => String::from("contract does not fit the transfer"),
+            TransactionValidationError::UnknownContract => String::from("settles a lock that is not on the chain"),
+            TransactionValidationError::ContractSettled => String::from("lock is already settled"),
+            TransactionValidationError::BadPreimage => String::from("preimage does not match the hash lock"),
+            TransactionValidationError::ContractExpired => String::from("lock expired, only a refund is possible"),
+            TransactionValidationError::ContractNotExpired => String::from("lock has not expired yet"),
+            TransactionValidationError::MissingApprovals { have, need } => {
+                format!("approved by {} of the {} required parties", have, need)
+            }
+            TransactionValidationError::InsufficientTokens { have, need } => {
+                format!("holds {} tokens, needs {}", have, need)
+            }
+            TransactionValidationError::TokenOverflow => String::from("token balance would overflow"),
+            TransactionValidationError::BurnedCoinsSpent => String::from("burned coins can never be spent"),
+            TransactionValidationError::NonPositiveAmount { amount } => format!("cannot transfer {} coins", amount),
+            TransactionValidationError::BadGrant { expected, actual } => {
+                format!("grant is {}, expected {}", actual, expected)
+            }
+            TransactionValidationError::BadGrantWork => String::from("grant puzzle not solved for the wallet"),
+            TransactionValidationError::AlreadyGranted => String::from("wallet already received its grant"),
+            TransactionValidationError::UnknownSponsoredTransfer => String::from("sponsored transfer is not on the chain or alongside it"),
+            TransactionValidationError::AlreadySponsored => String::from("transfer already has a sponsored fee"),
+            TransactionValidationError::ReservedTarget => String::from("system address cannot receive transfers"),
+            TransactionValidationError::BadNonce { expected, actual } => {
+                format!("nonce is {}, expected {}", actual, expected)
+            }
+            TransactionValidationError::BelowDust { minimum } => {
+                format!("transfers below {} are dust", minimum)
+            }
+            TransactionValidationError::PenaltyExceedsStake { bonded, penalty } => {
+                format!("penalty of {} exceeds the {} stake bonded", penalty, bonded)
+            }
+        };
+        format!("Transaction invalid: {}", reason)
+    }
+}
+
+fn validate_hash<T>(block_candidate: &BlockCandidate<T>) -> Result<(), Box<dyn BlockchainError>>
+    where T: BlockchainData {
+    let given_key = block_candidate.key();
+
+    let hash_valid = match given_key.raw_previous_hash() {
+        None => false,
+        Some(previous_hash) => {
+            let computed = BlockCandidate::<T>::hash(
+                previous_hash, BlockCandidate::summarize(block_candidate.data()), given_key.raw_state_root(),
+            );
+            computed.hash() == given_key.hash()
+        }
+    };
+
+    if hash_valid {
+        Ok(())
+    } else {
+        Err(Box::new(
+            BlockValidationError::new(
+                serde_json::to_string_pretty(block_candidate).unwrap(),
+                "Invalid hash",
+            )
+        ))
+    }
+}
+
+fn validate_time<T>(chain: &Blockchain<T>, block: &BlockCandidate<T>, now: DateTime<Utc>) -> Result<(), RejectionReason>
+    where T: BlockchainData {
+    if let Some(median_time_past) = chain.median_time_past() {
+        if block.time() < median_time_past {
+            return Err(RejectionReason::TimeTooEarly { median_time_past });
+        }
+    }
+    let drift_seconds = (block.time() - now).num_seconds();
+    if drift_seconds > MAX_FUTURE_DRIFT_SECONDS {
+        return Err(RejectionReason::TimeTooLate { drift_seconds });
+    }
+    Ok(())
+}
+
+pub fn find_wallet_by_address(address: Address, wallet_chain: &Blockchain<Wallet>) -> Option<Wallet> {
+    wallet_chain.iter().find_map(|block| extract_wallet(block.data(), address))
+}
+
+// keys a wallet went through, oldest first, with the time each one took effect
+pub fn wallet_key_history(address: Address, wallet_chain: &Blockchain<Wallet>) -> Vec<(DateTime<Utc>, RsaPublicKey)> {
+    wallet_chain.iter()
+        .rev()
+        .flat_map(|block| {
+            let time = block.time().unwrap_or_default();
+            block.data().iter()
+                .filter(|wallet| wallet.address == address)
+                .filter_map(move |wallet| wallet.public_key.clone().map(|key| (time, key)))
+        })
+        .collect()
+}
+
+fn extract_wallet(data: &Vec<Wallet>, address: Address) -> Option<Wallet> {
+    for entry in data {
+        if entry.address() == address {
+            return Some(entry.clone());
+        }
+    };
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+
+    use chrono::{Duration, Utc};
     use rsa::{RsaPrivateKey, RsaPublicKey};
     use rsa::pss::BlindedSigningKey;
     use rsa::rand_core::{CryptoRng, RngCore};
@@ -412,13 +1780,20 @@ mod test {
     use serde::Serialize;
     use sha2::Sha512;
 
-    use crate::blockchain::{BlockchainData, MINTING_WALLET_ADDRESS, Transaction, TRANSACTION_FEE, TransactionCriteria, TransactionValidator, Wallet, WalletCriteria, WalletValidator};
+    use crate::blockchain::{BlockchainData, find_wallet_by_address, RejectionReason, Transaction, TransactionCriteria, TransactionValidationError, TransactionValidator, Wallet, wallet_key_history, WalletCriteria, WalletValidator};
+    use crate::blockchain::protocol::{BLOCK_SIZE, BURN_WALLET_ADDRESS, MINTING_WALLET_ADDRESS, STAKE_WALLET_ADDRESS, TRANSACTION_FEE};
+    use crate::blockchain::access::HotWallet;
     use crate::blockchain::core::{Block, BlockCandidate, Blockchain, BlockchainError, BlockKey, BlockPointer, Summary, Validate};
+    use crate::blockchain::key_history::KeyHistory;
+    use crate::blockchain::snapshot;
+    use crate::blockchain::upgrade::{ConsensusRules, SignatureScheme, UPGRADE_SCHEDULE, UpgradeSchedule};
     use crate::BlockHash;
+    use crate::network::communication::BlockchainDto;
+    use crate::random;
 
     #[test]
     fn ok_on_valid_transaction() {
-        let mut rng = rand::thread_rng();
+        let mut rng = random::seeded(1);
 
         let mut wallets = Blockchain::<Wallet>::wallet_chain();
         let first_key = RsaPrivateKey::new(&mut rng, 2048).unwrap();
@@ -454,7 +1829,8 @@ mod test {
         );
         transaction.sign(BlindedSigningKey::<Sha512>::new(first_key), rng);
 
-        let to_validate = vec![transaction, reward];
+        let mut to_validate = vec![transaction, reward];
+        to_validate.sort_by(Transaction::canonical_cmp);
         let block_candidate = prepare_block_candidate(
             transactions.last_block(), to_validate,
         );
@@ -462,6 +1838,11 @@ mod test {
         let validator = TransactionValidator {
             wallets: &wallets,
             transactions: &transactions,
+            upgrades: &UPGRADE_SCHEDULE,
+            stakes: None,
+            key_history: None,
+            clock_offset: Duration::zero(),
+            verified_signatures: None,
         };
         match validator.block_valid(&block_candidate) {
             Ok(_) => {
@@ -473,21 +1854,590 @@ mod test {
         }
     }
 
+    #[test]
+    fn rejects_signature_made_for_another_network() {
+        let mut rng = random::seeded(2);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = prepare_block_candidate(
+            wallets.last_block(), vec![sender.wallet().clone(), recipient.wallet().clone()],
+        );
+        wallets.submit_new_block(registered);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let reward = || Transaction::new(
+            MINTING_WALLET_ADDRESS,
+            recipient.address(),
+            "Reward".to_string(), TRANSACTION_FEE, Utc::now(),
+        );
+        let mut transaction = Transaction::new(
+            sender.address(), recipient.address(), "".to_string(), 5, Utc::now(),
+        );
+
+        let foreign_content = transaction.signed_content_on("kingcoin-test");
+        transaction.sender_signature = Some(sender.sign_message(&foreign_content, &mut rng));
+        let canonical = |mut data: Vec<Transaction>| {
+            data.sort_by(Transaction::canonical_cmp);
+            data
+        };
+        let replayed = prepare_block_candidate(
+            transactions.last_block(), canonical(vec![transaction.clone(), reward()]),
+        );
+        assert!(validator.block_valid(&replayed).is_err());
+
+        sender.sign(&mut transaction, &mut rng);
+        let signed = prepare_block_candidate(
+            transactions.last_block(), canonical(vec![transaction, reward()]),
+        );
+        assert!(validator.block_valid(&signed).is_ok());
+    }
+
+    #[test]
+    fn signatures_do_not_carry_over_when_digits_move_between_fields() {
+        let mut rng = random::seeded(43);
+        let sender = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(wallets.last_block(), vec![sender.wallet().clone()]));
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let time = Utc::now();
+        let transfer = |amount: i64, title: &str, nonce: u64| {
+            let mut transfer = Transaction::new(sender.address(), [2; 32], title.to_string(), amount, time);
+            transfer.set_nonce(nonce);
+            transfer
+        };
+
+        // title "x1" at nonce 2 against "x" at nonce 12, amount 5 titled "0 thanks" against 50 titled " thanks"
+        for (mut signed, mut moved) in [(transfer(5, "x1", 2), transfer(5, "x", 12)), (transfer(5, "0 thanks", 0), transfer(50, " thanks", 0))] {
+            sender.sign(&mut signed, &mut rng);
+            moved.sender_signature = signed.sender_signature.clone();
+            let signature = signed.sender_signature.clone().unwrap();
+            assert_ne!(moved.signed_content(), signed.signed_content());
+            assert!(validator.signature_valid(&signed, sender.address(), &signature, SignatureScheme::RsaPssSha512));
+            assert!(!validator.signature_valid(&moved, sender.address(), &signature, SignatureScheme::RsaPssSha512));
+        }
+    }
+
+    #[test]
+    fn blocks_cannot_carry_a_committed_transfer_again() {
+        let mut rng = random::seeded(42);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(
+            wallets.last_block(), vec![sender.wallet().clone(), recipient.wallet().clone()],
+        ));
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let mut transfer = Transaction::new(sender.address(), recipient.address(), "".to_string(), 5, Utc::now());
+        sender.sign(&mut transfer, &mut rng);
+        let block = |transactions: &Blockchain<Transaction>, transfer: Transaction| {
+            let reward = Transaction::new(MINTING_WALLET_ADDRESS, recipient.address(), "Reward".to_string(), TRANSACTION_FEE, Utc::now());
+            let mut data = vec![transfer, reward];
+            data.sort_by(Transaction::canonical_cmp);
+            prepare_block_candidate(transactions.last_block(), data)
+        };
+
+        let first = block(&transactions, transfer.clone());
+        assert!(TransactionValidator::new(&wallets, &transactions).diagnose(&first).is_ok());
+        transactions.submit_new_block(first);
+        let replayed = block(&transactions, transfer.clone());
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).diagnose(&replayed),
+            Err(RejectionReason::InvalidTransaction {
+                id: transfer.id(),
+                error: TransactionValidationError::BadNonce { expected: 1, actual: 0 },
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_spend_committed_coins_once_whatever_the_mempool_holds() {
+        let mut rng = random::seeded(44);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(
+            wallets.last_block(), vec![sender.wallet().clone(), recipient.wallet().clone()],
+        ));
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let mut transfers = |amount: i64| -> Vec<Transaction> {
+            (0..2).map(|nonce| {
+                let mut transfer = Transaction::new(sender.address(), recipient.address(), "".to_string(), amount, Utc::now());
+                transfer.set_nonce(nonce);
+                sender.sign(&mut transfer, &mut rng);
+                transfer
+            }).collect()
+        };
+        let block = |transactions: &Blockchain<Transaction>, transfers: &[Transaction]| {
+            let reward = Transaction::new(MINTING_WALLET_ADDRESS, recipient.address(), "Reward".to_string(), TRANSACTION_FEE, Utc::now());
+            let mut data = transfers.to_vec();
+            data.push(reward);
+            data.sort_by(Transaction::canonical_cmp);
+            prepare_block_candidate(transactions.last_block(), data)
+        };
+
+        // the mempool holding the block's own transfers takes nothing more off the balance
+        let covered = transfers(30);
+        covered.iter().cloned().for_each(|transfer| transactions.add_uncommitted(transfer));
+        assert!(TransactionValidator::new(&wallets, &transactions).diagnose(&block(&transactions, &covered)).is_ok());
+
+        let overdrawn = transfers(40);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).diagnose(&block(&transactions, &overdrawn)),
+            Err(RejectionReason::InvalidTransaction {
+                id: overdrawn[1].id(),
+                error: TransactionValidationError::InsufficientBalance { have: 30, need: 40 },
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_list_transactions_by_sender_nonce_and_hash() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut later = Transaction::new([2; 32], [1; 32], "".to_string(), 5, Utc::now());
+        later.set_nonce(1);
+        let earlier = Transaction::new([2; 32], [1; 32], "".to_string(), 5, Utc::now());
+        let other_sender = Transaction::new([1; 32], [2; 32], "".to_string(), 5, Utc::now());
+
+        let mut data = [later.clone(), earlier.clone(), other_sender.clone()];
+        data.sort_by(Transaction::canonical_cmp);
+        let ids: Vec<String> = data.iter().map(Transaction::id).collect();
+        assert_eq!(ids, [other_sender.id(), earlier.id(), later.id()]);
+
+        // blocks before the upgrade activates keep whatever order they were forged in
+        let genesis_rules = ConsensusRules::new(TRANSACTION_FEE, 1, 30, SignatureScheme::RsaPssSha512);
+        let mut upgrades = UpgradeSchedule::new(genesis_rules);
+        upgrades.schedule(2, genesis_rules.with_canonical_order()).ok().unwrap();
+        let unordered = vec![later, earlier, other_sender];
+        let early = prepare_block_candidate(transactions.last_block(), unordered.clone());
+        let out_of_order = RejectionReason::Malformed(String::from("Transactions out of canonical order"));
+        assert_ne!(TransactionValidator::with_upgrades(&wallets, &transactions, &upgrades).diagnose(&early), Err(out_of_order.clone()));
+
+        transactions.submit_new_block(prepare_block_candidate(transactions.last_block(), vec![]));
+        let late = prepare_block_candidate(transactions.last_block(), unordered);
+        assert_eq!(TransactionValidator::with_upgrades(&wallets, &transactions, &upgrades).diagnose(&late), Err(out_of_order));
+    }
+
+    #[test]
+    fn rejects_tampered_chain_on_sync() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(
+                MINTING_WALLET_ADDRESS,
+                [1; 32],
+                "Transaction".to_string(), 70, Utc::now(),
+            )
+        ]);
+        let block_candidate = prepare_block_candidate(
+            transactions.last_block(), vec![Transaction::new(
+                [1; 32],
+                [2; 32],
+                "Transaction".to_string(), 5, Utc::now(),
+            )],
+        );
+        transactions.submit_new_block(block_candidate);
+
+        let dto = serde_json::to_value(BlockchainDto::from(transactions)).unwrap();
+        let synced = serde_json::from_value::<BlockchainDto<Transaction>>(dto.clone()).unwrap();
+        assert!(Blockchain::try_from(synced).is_ok());
+
+        let mut tampered = dto.clone();
+        tampered["blocks"][0]["data"][0]["amount"] = 500.into();
+        let tampered = serde_json::from_value::<BlockchainDto<Transaction>>(tampered).unwrap();
+        assert!(Blockchain::try_from(tampered).is_err());
+
+        let mut malformed = dto;
+        malformed["blocks"][0]["block_hash"] = "not a hash".into();
+        let malformed = serde_json::from_value::<BlockchainDto<Transaction>>(malformed).unwrap();
+        assert!(Blockchain::try_from(malformed).is_err());
+    }
+
+    #[test]
+    fn synced_chains_keep_the_local_block_size() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut remote = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let block_candidate = prepare_block_candidate(remote.last_block(), vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "Reward".to_string(), 5, Utc::now()),
+        ]);
+        remote.submit_new_block(block_candidate);
+
+        let mut dto = serde_json::to_value(BlockchainDto::from(&remote)).unwrap();
+        dto["max_data_units_per_block"] = 1000.into();
+        let synced = Blockchain::try_from(serde_json::from_value::<BlockchainDto<Transaction>>(dto).unwrap()).ok().unwrap();
+        assert_eq!(synced.data_units_per_block(), BLOCK_SIZE);
+        local.replace(synced);
+        assert_eq!(local.chain_length(), 2);
+        assert_eq!(local.data_units_per_block(), UPGRADE_SCHEDULE.rules_at(0).block_size());
+    }
+
+    #[test]
+    fn rejection_names_the_precise_reason() {
+        let mut rng = random::seeded(3);
+        let sender = HotWallet::generate(&mut rng);
+        let stranger = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = prepare_block_candidate(wallets.last_block(), vec![sender.wallet().clone()]);
+        wallets.submit_new_block(registered);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+
+        let mut unsigned = Transaction::fee(sender.address(), 5);
+        assert!(matches!(validator.transaction_valid(&unsigned), Err(TransactionValidationError::MissingSignature)));
+        sender.sign(&mut unsigned, &mut rng);
+        assert!(validator.transaction_valid(&unsigned).is_ok());
+
+        let mut overdrawn = Transaction::fee(sender.address(), 80);
+        sender.sign(&mut overdrawn, &mut rng);
+        assert!(matches!(
+            validator.transaction_valid(&overdrawn),
+            Err(TransactionValidationError::InsufficientBalance { have: 70, need: 80 })
+        ));
+
+        let mut to_stranger = Transaction::new(
+            sender.address(), stranger.address(), "".to_string(), 5, Utc::now(),
+        );
+        sender.sign(&mut to_stranger, &mut rng);
+        let error = validator.transaction_valid(&to_stranger).err().unwrap();
+        assert_eq!(error.message(), "Transaction invalid: target wallet is not registered");
+    }
+
+    #[test]
+    fn wallet_registration_requires_matching_key_and_unique_address() {
+        let mut rng = random::seeded(4);
+        let first = HotWallet::generate(&mut rng);
+        let second = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+
+        let forged = Wallet::new([9; 32], first.wallet().key().clone());
+        assert!(WalletValidator::new(&wallets).registration_valid(&forged).is_err());
+        let duplicated = prepare_block_candidate(
+            wallets.last_block(), vec![first.wallet().clone(), first.wallet().clone()],
+        );
+        assert!(WalletValidator::new(&wallets).block_valid(&duplicated).is_err());
+
+        let registered = prepare_block_candidate(wallets.last_block(), vec![first.wallet().clone()]);
+        assert!(WalletValidator::new(&wallets).block_valid(&registered).is_ok());
+        wallets.submit_new_block(registered);
+        assert!(WalletValidator::new(&wallets).registration_valid(first.wallet()).is_err());
+        assert!(WalletValidator::new(&wallets).registration_valid(second.wallet()).is_ok());
+    }
+
+    #[test]
+    fn key_rotation_is_signed_by_the_old_key_and_keeps_history() {
+        let mut rng = random::seeded(5);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = prepare_block_candidate(
+            wallets.last_block(), vec![sender.wallet().clone(), recipient.wallet().clone()],
+        );
+        wallets.submit_new_block(registered);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let mut before_rotation = Transaction::new(
+            sender.address(), recipient.address(), "".to_string(), 5, Utc::now(),
+        );
+        sender.sign(&mut before_rotation, &mut rng);
+
+        let rotated = HotWallet::rotated_from(&sender, &mut rng).ok().unwrap();
+        assert_eq!(rotated.address(), sender.address());
+        assert!(WalletValidator::new(&wallets).key_update_valid(rotated.wallet()).is_ok());
+        let hijacked = HotWallet::rotated_from(&recipient, &mut rng).ok().unwrap();
+        let hijacked = Wallet::rotated(
+            sender.address(),
+            hijacked.wallet().key().clone().unwrap(),
+            hijacked.wallet().rotation_signature().clone().unwrap(),
+        );
+        assert!(WalletValidator::new(&wallets).key_update_valid(&hijacked).is_err());
+
+        let rotation = prepare_block_candidate(wallets.last_block(), vec![rotated.wallet().clone()]);
+        assert!(WalletValidator::new(&wallets).block_valid(&rotation).is_ok());
+        wallets.submit_new_block(rotation);
+        assert_eq!(find_wallet_by_address(sender.address(), &wallets).unwrap().key(), rotated.wallet().key());
+        assert_eq!(wallet_key_history(sender.address(), &wallets).len(), 2);
+
+        let mut after_rotation = Transaction::new(
+            sender.address(), recipient.address(), "".to_string(), 5, Utc::now() + Duration::seconds(1),
+        );
+        rotated.sign(&mut after_rotation, &mut rng);
+        let current_keys = TransactionValidator::new(&wallets, &transactions);
+        assert!(current_keys.transaction_valid(&before_rotation).is_err());
+        assert!(current_keys.transaction_valid(&after_rotation).is_ok());
+        // the old key signed for blocks before the rotation only, no block was forged since
+        let history = KeyHistory::derive(&wallets, &transactions).ok().unwrap();
+        let key_history = TransactionValidator::new(&wallets, &transactions).with_key_history(&history);
+        assert!(key_history.transaction_valid(&before_rotation).is_err());
+        assert!(key_history.transaction_valid(&after_rotation).is_ok());
+    }
+
+    #[test]
+    fn balance_of_includes_pending_transactions() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(
+                MINTING_WALLET_ADDRESS,
+                [1; 32],
+                "Transaction".to_string(), 70, Utc::now(),
+            )
+        ]);
+        let block_candidate = prepare_block_candidate(
+            transactions.last_block(), vec![Transaction::new(
+                [1; 32],
+                [2; 32],
+                "Transaction".to_string(), 20, Utc::now(),
+            )],
+        );
+        transactions.submit_new_block(block_candidate);
+        transactions.add_uncommitted(Transaction::new(
+            [2; 32],
+            [3; 32],
+            "Transaction".to_string(), 5, Utc::now(),
+        ));
+
+        assert_eq!(transactions.committed_balance([2; 32]), 20);
+        assert_eq!(transactions.balance_of([1; 32]), 50);
+        assert_eq!(transactions.balance_of([2; 32]), 15);
+        assert_eq!(transactions.balance_of([3; 32]), 5);
+        assert_eq!(transactions.committed_balance_at([1; 32], 0).ok(), Some(70));
+        assert_eq!(transactions.committed_balance_at([1; 32], 1).ok(), Some(50));
+
+        let breakdown = transactions.balance_breakdown([2; 32]);
+        assert_eq!(breakdown.pending_outgoing(), 5);
+        assert_eq!(breakdown.spendable(), 15);
+        assert_eq!(breakdown.with_locked(10).spendable(), 5);
+        assert_eq!(transactions.balance_breakdown([3; 32]).spendable(), 0);
+    }
+
+    #[test]
+    fn stats_summarise_committed_history() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 70, Utc::now())
+        ]);
+        let transfers = prepare_block_candidate(transactions.last_block(), vec![
+            Transaction::new([1; 32], [2; 32], "".to_string(), 5, Utc::now()),
+            Transaction::new([1; 32], [3; 32], "".to_string(), 7, Utc::now()),
+        ]);
+        transactions.submit_new_block(transfers);
+        transactions.add_uncommitted(Transaction::new([2; 32], [3; 32], "".to_string(), 1, Utc::now()));
+
+        let stats = transactions.stats().ok().unwrap();
+        assert_eq!(stats.block_count(), 2);
+        assert_eq!(stats.total_transactions(), 3);
+        assert_eq!(stats.total_volume(), 12);
+        assert_eq!(stats.active_addresses(), 3);
+        assert_eq!(stats.circulating_supply(), 70);
+        let expected_fill = 3.0 / (2 * transactions.data_units_per_block()) as f64;
+        assert!((stats.average_block_fill() - expected_fill).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn competing_chains_cannot_roll_back_finalized_blocks() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 70, Utc::now())
+        ]);
+        let mut remote = Blockchain::try_from(BlockchainDto::from(&local)).ok().unwrap();
+        for height in 1..5 {
+            let payment = Transaction::new([1; 32], [2; 32], "".to_string(), height, Utc::now());
+            local.submit_new_block(prepare_block_candidate(local.last_block(), vec![payment]));
+        }
+        for _ in 0..8 {
+            remote.submit_new_block(prepare_block_candidate(remote.last_block(), vec![]));
+        }
+        assert_eq!(local.common_height(&remote), 1);
+        assert_eq!(local.finalized_height(3), 2);
+        assert!(local.reorg_allowed(&remote, 3).is_err());
+        assert!(local.reorg_allowed(&remote, 4).is_ok());
+    }
+
+    #[test]
+    fn burned_coins_leave_the_supply_for_good() {
+        let mut rng = random::seeded(13);
+        let holder = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(wallets.last_block(), vec![holder.wallet().clone()]));
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, holder.address(), "".to_string(), 70, Utc::now())
+        ]);
+
+        let mut burn = Transaction::burn(holder.address(), 20);
+        holder.sign(&mut burn, &mut rng);
+        let mut negative_burn = Transaction::burn(holder.address(), -20);
+        holder.sign(&mut negative_burn, &mut rng);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        assert!(validator.transaction_valid(&burn).is_ok());
+        assert_eq!(validator.transaction_valid(&negative_burn), Err(TransactionValidationError::BurnedCoinsSpent));
+        transactions.submit_new_block(prepare_block_candidate(transactions.last_block(), vec![burn]));
+
+        let stats = transactions.stats().ok().unwrap();
+        assert_eq!(stats.burned(), 20);
+        assert_eq!(stats.circulating_supply(), 50);
+        let recovery = Transaction::new(*BURN_WALLET_ADDRESS, holder.address(), "".to_string(), 20, Utc::now());
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&recovery),
+            Err(TransactionValidationError::BurnedCoinsSpent)
+        );
+    }
+
+    #[test]
+    fn transfers_move_a_positive_amount() {
+        let mut rng = random::seeded(15);
+        let holder = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(
+            wallets.last_block(), vec![holder.wallet().clone(), recipient.wallet().clone()],
+        ));
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, recipient.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+
+        for amount in [-20, 0] {
+            let mut transfer = Transaction::new(holder.address(), recipient.address(), "".to_string(), amount, Utc::now());
+            holder.sign(&mut transfer, &mut rng);
+            assert_eq!(validator.transaction_valid(&transfer), Err(TransactionValidationError::NonPositiveAmount { amount }));
+        }
+    }
+
+    #[test]
+    fn minting_and_stake_wallets_receive_no_transfers() {
+        let mut rng = random::seeded(14);
+        let holder = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        wallets.submit_new_block(prepare_block_candidate(
+            wallets.last_block(), vec![holder.wallet().clone(), recipient.wallet().clone()],
+        ));
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, holder.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let upgrades = UpgradeSchedule::new(UPGRADE_SCHEDULE.rules_at(0).with_reserved_targets());
+        let validator = TransactionValidator::with_upgrades(&wallets, &transactions, &upgrades);
+        let signed = |mut transaction: Transaction, rng: &mut _| {
+            holder.sign(&mut transaction, rng);
+            transaction
+        };
+
+        // blocks forged before the rule activates may still pay the stake wallet
+        let staked = signed(Transaction::new(holder.address(), *STAKE_WALLET_ADDRESS, "".to_string(), 20, Utc::now()), &mut rng);
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&staked).is_ok());
+        for target in [MINTING_WALLET_ADDRESS, *STAKE_WALLET_ADDRESS] {
+            let transfer = signed(Transaction::new(holder.address(), target, "".to_string(), 20, Utc::now()), &mut rng);
+            assert_eq!(validator.transaction_valid(&transfer), Err(TransactionValidationError::ReservedTarget));
+        }
+        let arbitrated_by_minting = signed(Transaction::escrow_open(holder.address(), recipient.address(), MINTING_WALLET_ADDRESS, 20), &mut rng);
+        assert_eq!(validator.transaction_valid(&arbitrated_by_minting), Err(TransactionValidationError::ReservedTarget));
+        // fees keep reaching the reward wallet
+        assert!(validator.transaction_valid(&signed(Transaction::fee(holder.address(), 1), &mut rng)).is_ok());
+    }
+
+    #[test]
+    fn fee_payout_must_match_accumulated_fees() {
+        let wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(
+                MINTING_WALLET_ADDRESS,
+                [1; 32],
+                "Transaction".to_string(), 70, Utc::now(),
+            )
+        ]);
+        let fees = prepare_block_candidate(
+            transactions.last_block(), vec![Transaction::fee([1; 32], 5)],
+        );
+        transactions.submit_new_block(fees);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let reward = || Transaction::new(
+            MINTING_WALLET_ADDRESS,
+            [3; 32],
+            "Reward".to_string(), TRANSACTION_FEE, Utc::now(),
+        );
+
+        let paid_out = prepare_block_candidate(
+            transactions.last_block(), vec![reward(), Transaction::fee_payout([3; 32], 5)],
+        );
+        assert!(validator.block_valid(&paid_out).is_ok());
+
+        let underpaid = prepare_block_candidate(
+            transactions.last_block(), vec![reward(), Transaction::fee_payout([3; 32], 4)],
+        );
+        assert!(validator.block_valid(&underpaid).is_err());
+    }
+
+    #[test]
+    fn penalties_burn_the_balance_up_to_the_bond() {
+        let wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 70, Utc::now())
+        ]);
+        let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let bid = prepare_block_candidate(stakes.last_block(), vec![Transaction::stake_bid(30, [1; 32])]);
+        let epoch = stakes.submit_new_block(bid).block_number();
+        let validator = TransactionValidator::new(&wallets, &transactions).with_stakes(&stakes);
+
+        let leak = Transaction::penalty([1; 32], 10, epoch, "Leak", Utc::now());
+        let slash = Transaction::penalty([1; 32], 25, epoch, "Slash", Utc::now());
+        assert!(validator.transaction_valid(&leak).is_ok());
+        let penalties = vec![leak.clone(), slash];
+        let state_root = snapshot::account_state_root(&transactions, &penalties, &stakes);
+        let both = prepare_block_candidate(transactions.last_block(), penalties).with_state_root(state_root);
+        assert!(matches!(
+            validator.diagnose(&both),
+            Err(RejectionReason::InvalidTransaction { error: TransactionValidationError::PenaltyExceedsStake { bonded: 20, penalty: 25 }, .. })
+        ));
+        let unbonded = Transaction::penalty([1; 32], 10, epoch + 1, "Leak", Utc::now());
+        assert!(validator.transaction_valid(&unbonded).is_err());
+
+        // the leak comes out of the balance, what stays bonded is still locked
+        transactions.submit_new_block(prepare_block_candidate(transactions.last_block(), vec![leak]));
+        let breakdown = transactions.staked_breakdown(&stakes, [1; 32]);
+        assert_eq!(breakdown.confirmed(), 60);
+        assert_eq!(breakdown.locked(), 20);
+        assert_eq!(breakdown.spendable(), 40);
+    }
+
+    #[test]
+    fn verify_full_reports_first_invalid_block() {
+        let wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        for reward in [TRANSACTION_FEE, TRANSACTION_FEE + 1, TRANSACTION_FEE] {
+            let block_candidate = prepare_block_candidate(
+                transactions.last_block(), vec![Transaction::new(
+                    MINTING_WALLET_ADDRESS,
+                    [3; 32],
+                    "Reward".to_string(), reward, Utc::now(),
+                )],
+            );
+            transactions.submit_new_block(block_candidate);
+        }
+
+        let result = transactions.verify_full(|replayed, block| {
+            TransactionValidator::new(&wallets, replayed).block_valid(block)
+        });
+        match result {
+            Ok(_) => panic!("Overpaid reward was accepted"),
+            Err(error) => assert!(error.message().starts_with("Invalid chain at block 2:"))
+        }
+    }
+
     fn prepare_wallets_block(
         previous_block: &BlockPointer<Wallet>, first_key: &RsaPrivateKey,
         second_key: &RsaPrivateKey, third_key: &RsaPrivateKey,
     ) -> BlockCandidate<Wallet> {
         let wallets = vec![
-            Wallet {
-                address: [1; 32],
-                public_key: Some(RsaPublicKey::from(first_key)),
-            }, Wallet {
-                address: [2; 32],
-                public_key: Some(RsaPublicKey::from(second_key)),
-            }, Wallet {
-                address: [3; 32],
-                public_key: Some(RsaPublicKey::from(third_key)),
-            },
+            Wallet::new([1; 32], Some(RsaPublicKey::from(first_key))),
+            Wallet::new([2; 32], Some(RsaPublicKey::from(second_key))),
+            Wallet::new([3; 32], Some(RsaPublicKey::from(third_key))),
         ];
         prepare_block_candidate(previous_block, wallets)
     }
@@ -0,0 +1,447 @@
+use std::fs;
+use std::path::Path;
+
+use aes_gcm::{Aes256Gcm, aead::{Aead, KeyInit}, Nonce};
+use rsa::{PublicKeyParts, RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs8::{DecodePrivateKey, EncodePrivateKey};
+use rsa::pss::{BlindedSigningKey, Signature, VerifyingKey};
+use rsa::rand_core::{CryptoRng, CryptoRngCore, RngCore};
+use rsa::signature::{RandomizedSigner, Signature as _, Verifier};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::blockchain::{Address, BlockchainData, Transaction, Wallet, wallet_key_history};
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, Summary};
+use crate::blockchain::signer::Signer;
+use crate::network::chains::ChainPayload;
+use crate::platform;
+
+pub static KEY_SIZE: usize = 2048;
+pub(crate) static KEYSTORE_ROUNDS: u32 = 100_000;
+static CHECKSUM_LENGTH: usize = 4;
+pub(crate) static SALT_LENGTH: usize = 16;
+static NONCE_LENGTH: usize = 12;
+// salts user names, the hash of a name must be the same on every node to be found
+static LOGIN_SALT: &[u8] = b"kingcoin-login";
+
+pub struct AccessError {
+    message: String,
+}
+
+impl AccessError {
+    pub fn new(message: &str) -> AccessError {
+        AccessError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for AccessError {
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+pub struct HotWallet {
+    private_key: RsaPrivateKey,
+    wallet: Wallet,
+}
+
+impl HotWallet {
+    pub fn generate<R>(rng: &mut R) -> HotWallet where R: CryptoRng + RngCore {
+        let private_key = RsaPrivateKey::new(rng, KEY_SIZE)
+            .expect("Failed to generate a key");
+        HotWallet::from_private_key(private_key)
+    }
+
+    pub fn from_private_key(private_key: RsaPrivateKey) -> HotWallet {
+        let public_key = RsaPublicKey::from(&private_key);
+        let wallet = Wallet::new(derive_address(&public_key), Some(public_key));
+        HotWallet {
+            private_key,
+            wallet,
+        }
+    }
+
+    // an imported key for an existing wallet has to be the key the wallet chain holds for it
+    pub fn for_wallet(
+        private_key: RsaPrivateKey, address: Address, wallets: &Blockchain<Wallet>,
+    ) -> Result<HotWallet, Box<dyn BlockchainError>> {
+        match wallet_key_history(address, wallets).last() {
+            None => Err(Box::new(AccessError::new("Unknown wallet"))),
+            Some((_, key)) if *key != RsaPublicKey::from(&private_key) => {
+                Err(Box::new(AccessError::new("The key is not the current key of the wallet")))
+            }
+            Some(_) => Ok(HotWallet::with_address(private_key, address))
+        }
+    }
+
+    // a rotated key keeps signing for the address derived from the wallet's first key
+    pub(crate) fn with_address(private_key: RsaPrivateKey, address: Address) -> HotWallet {
+        let public_key = RsaPublicKey::from(&private_key);
+        HotWallet {
+            private_key,
+            wallet: Wallet::new(address, Some(public_key)),
+        }
+    }
+
+    // fresh key for the signer's address, its wallet entry is signed by the current key
+    pub fn rotated_from(
+        signer: &dyn Signer, mut rng: &mut dyn CryptoRngCore,
+    ) -> Result<HotWallet, Box<dyn BlockchainError>> {
+        let private_key = RsaPrivateKey::new(&mut rng, KEY_SIZE)
+            .expect("Failed to generate a key");
+        let unsigned = Wallet::new(signer.address(), Some(RsaPublicKey::from(&private_key)));
+        let signature = signer.sign_message(&unsigned.rotation_content(), rng)?;
+        Ok(HotWallet {
+            wallet: Wallet::rotated(signer.address(), RsaPublicKey::from(&private_key), signature),
+            private_key,
+        })
+    }
+
+    pub fn address(&self) -> Address {
+        self.wallet.address()
+    }
+
+    pub fn wallet(&self) -> &Wallet {
+        &self.wallet
+    }
+
+    pub fn private_key(&self) -> &RsaPrivateKey {
+        &self.private_key
+    }
+
+    pub fn sign(&self, transaction: &mut Transaction, rng: impl CryptoRng + RngCore) {
+        let key = BlindedSigningKey::<Sha512>::new(self.private_key.clone());
+        transaction.sign(key, rng);
+    }
+
+    pub fn sign_message(&self, content: &str, rng: impl CryptoRng + RngCore) -> String {
+        let key = BlindedSigningKey::<Sha512>::new(self.private_key.clone());
+        let signature = key.sign_with_rng(rng, content.as_bytes());
+        array_bytes::bytes2hex("", signature.as_ref())
+    }
+}
+
+pub fn verify_message(public_key: &RsaPublicKey, content: &str, signature: &str) -> bool {
+    let signature = match array_bytes::hex2bytes(signature) {
+        Ok(bytes) => match Signature::from_bytes(&bytes) {
+            Ok(signature) => signature,
+            Err(_) => return false
+        },
+        Err(_) => return false
+    };
+    let key: VerifyingKey<Sha512> = VerifyingKey::from(public_key.clone());
+    key.verify(content.as_bytes(), &signature).is_ok()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Keystore {
+    address: String,
+    salt: String,
+    nonce: String,
+    cipher_text: String,
+}
+
+impl Keystore {
+    pub fn seal<R>(
+        hot_wallet: &HotWallet, password: &str, rng: &mut R,
+    ) -> Result<Keystore, Box<dyn BlockchainError>> where R: CryptoRng + RngCore {
+        let mut salt = [0u8; SALT_LENGTH];
+        let mut nonce = [0u8; NONCE_LENGTH];
+        rng.fill_bytes(&mut salt);
+        rng.fill_bytes(&mut nonce);
+
+        let encoded_key = match hot_wallet.private_key().to_pkcs8_der() {
+            Ok(document) => document,
+            Err(_) => return Err(Box::new(AccessError::new("Could not encode private key")))
+        };
+        let cipher = Keystore::cipher(password, &salt);
+        let cipher_text = match cipher.encrypt(Nonce::from_slice(&nonce), encoded_key.as_bytes()) {
+            Ok(cipher_text) => cipher_text,
+            Err(_) => return Err(Box::new(AccessError::new("Could not encrypt private key")))
+        };
+
+        Ok(Keystore {
+            address: encode_address(hot_wallet.address()),
+            salt: array_bytes::bytes2hex("", salt),
+            nonce: array_bytes::bytes2hex("", nonce),
+            cipher_text: array_bytes::bytes2hex("", cipher_text),
+        })
+    }
+
+    pub fn open(&self, password: &str) -> Result<HotWallet, Box<dyn BlockchainError>> {
+        let (salt, nonce, cipher_text) = match (
+            array_bytes::hex2bytes(&self.salt),
+            array_bytes::hex2bytes(&self.nonce),
+            array_bytes::hex2bytes(&self.cipher_text),
+        ) {
+            (Ok(salt), Ok(nonce), Ok(cipher_text)) if nonce.len() == NONCE_LENGTH => {
+                (salt, nonce, cipher_text)
+            }
+            _ => return Err(Box::new(AccessError::new("Corrupted keystore")))
+        };
+        let cipher = Keystore::cipher(password, &salt);
+        let encoded_key = match cipher.decrypt(Nonce::from_slice(&nonce), cipher_text.as_slice()) {
+            Ok(encoded_key) => encoded_key,
+            Err(_) => return Err(Box::new(AccessError::new("Invalid password")))
+        };
+        let private_key = match RsaPrivateKey::from_pkcs8_der(&encoded_key) {
+            Ok(private_key) => private_key,
+            Err(_) => return Err(Box::new(AccessError::new("Corrupted keystore")))
+        };
+        Ok(HotWallet::with_address(private_key, decode_address(&self.address)?))
+    }
+
+    pub fn read(path: &Path) -> Result<Keystore, Box<dyn BlockchainError>> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(error) => return Err(Box::new(AccessError::new(&error.to_string())))
+        };
+        match serde_json::from_str(&content) {
+            Ok(keystore) => Ok(keystore),
+            Err(_) => Err(Box::new(AccessError::new("Corrupted keystore")))
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn BlockchainError>> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        match platform::write_private(path, content.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(AccessError::new(&error.to_string())))
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    fn cipher(password: &str, salt: &[u8]) -> Aes256Gcm {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, KEYSTORE_ROUNDS, &mut key);
+        Aes256Gcm::new_from_slice(&key).expect("Valid key length")
+    }
+}
+
+// Binds a user name to a wallet so its owner finds the wallet by name on any node that keeps
+// its keystore. Only a salted hash of the name goes on the chain, signed with the wallet's key;
+// the key itself never leaves the keyring it is sealed in.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Credential {
+    login: String,
+    address: String,
+    public_key: RsaPublicKey,
+    signature: String,
+}
+
+impl Credential {
+    pub fn register(
+        user_name: &str, signer: &dyn Signer, rng: &mut dyn CryptoRngCore,
+    ) -> Result<Credential, Box<dyn BlockchainError>> {
+        let public_key = match signer.wallet().key() {
+            None => return Err(Box::new(AccessError::new("Signer has no public key"))),
+            Some(public_key) => public_key.clone()
+        };
+        let login = login_hash(user_name);
+        let address = encode_address(signer.address());
+        let signature = signer.sign_message(&Credential::signed_content(&login, &address), rng)?;
+        Ok(Credential {
+            login,
+            address,
+            public_key,
+            signature,
+        })
+    }
+
+    pub fn login(&self) -> &str {
+        &self.login
+    }
+
+    pub fn address(&self) -> Result<Address, Box<dyn BlockchainError>> {
+        decode_address(&self.address)
+    }
+
+    // apart from transaction content, so a credential signature never passes for a transfer
+    fn signed_content(login: &str, address: &str) -> String {
+        format!("kingcoin-credential:{}:{}", login, address)
+    }
+}
+
+impl Summary for Credential {
+    fn summary(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl BlockchainData for Credential {}
+
+impl ChainPayload for Credential {
+    fn chain_name() -> &'static str {
+        "credentials"
+    }
+
+    // a user name can only be taken once
+    fn block_valid(
+        chain: &Blockchain<Credential>, block: &BlockCandidate<Credential>,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        for (index, credential) in block.data().iter().enumerate() {
+            Credential::data_valid(chain, credential)?;
+            if block.data()[..index].iter().any(|other| other.login == credential.login) {
+                return Err(Box::new(AccessError::new("User name registered twice in one block")));
+            }
+        }
+        Ok(())
+    }
+
+    // whether the key belongs to the address is up to the wallet chain, see login
+    fn data_valid(chain: &Blockchain<Credential>, data: &Credential) -> Result<(), Box<dyn BlockchainError>> {
+        data.address()?;
+        if !verify_message(&data.public_key, &Credential::signed_content(&data.login, &data.address), &data.signature) {
+            return Err(Box::new(AccessError::new("Credential is not signed by its key")));
+        }
+        if find_credential(chain, &data.login).is_some() {
+            return Err(Box::new(AccessError::new("User name is taken")));
+        }
+        Ok(())
+    }
+}
+
+pub fn login_hash(user_name: &str) -> String {
+    let mut hash = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(user_name.as_bytes(), LOGIN_SALT, KEYSTORE_ROUNDS, &mut hash);
+    array_bytes::bytes2hex("", hash)
+}
+
+fn find_credential<'a>(credentials: &'a Blockchain<Credential>, login: &str) -> Option<&'a Credential> {
+    credentials.iter_transactions()
+        .rev()
+        .find(|credential| credential.login == login)
+}
+
+pub fn fetch_wallet_address(credentials: &Blockchain<Credential>, user_name: &str) -> Option<Address> {
+    find_credential(credentials, &login_hash(user_name))
+        .and_then(|credential| credential.address().ok())
+}
+
+// the wallet a user name was registered for, as long as the key that signed the registration
+// is one the wallet held; the keystore of that wallet is then opened from the local keyring
+pub fn login(
+    credentials: &Blockchain<Credential>, wallets: &Blockchain<Wallet>, user_name: &str,
+) -> Result<Address, Box<dyn BlockchainError>> {
+    let credential = match find_credential(credentials, &login_hash(user_name)) {
+        None => return Err(Box::new(AccessError::new("Unknown user name"))),
+        Some(credential) => credential
+    };
+    let address = credential.address()?;
+    let history = wallet_key_history(address, wallets);
+    if history.is_empty() {
+        return Err(Box::new(AccessError::new("Wallet of this credential is not registered")));
+    }
+    match history.iter().any(|(_, key)| *key == credential.public_key) {
+        true => Ok(address),
+        false => Err(Box::new(AccessError::new("Credential was not signed by the wallet's key")))
+    }
+}
+
+pub fn derive_address(public_key: &RsaPublicKey) -> Address {
+    let mut hasher = Sha256::new();
+    hasher.update(public_key.n().to_bytes_be());
+    hasher.update(public_key.e().to_bytes_be());
+    hasher.finalize().into()
+}
+
+pub fn encode_address(address: Address) -> String {
+    format!(
+        "{}{}",
+        array_bytes::bytes2hex("", address),
+        array_bytes::bytes2hex("", address_checksum(address))
+    )
+}
+
+pub fn decode_address(encoded: &str) -> Result<Address, Box<dyn BlockchainError>> {
+    let bytes = match array_bytes::hex2bytes(encoded) {
+        Ok(bytes) if bytes.len() == 32 + CHECKSUM_LENGTH => bytes,
+        _ => return Err(Box::new(AccessError::new("Malformed address")))
+    };
+    let address: Address = bytes[..32].try_into().unwrap();
+    if address_checksum(address) == bytes[32..] {
+        Ok(address)
+    } else {
+        Err(Box::new(AccessError::new("Address checksum mismatch")))
+    }
+}
+
+fn address_checksum(address: Address) -> Vec<u8> {
+    let first_pass = Sha256::digest(address);
+    Sha256::digest(first_pass)[..CHECKSUM_LENGTH].to_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::Wallet;
+    use crate::blockchain::access::{self, Credential, HotWallet};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::network::chains::ChainPayload;
+    use crate::random;
+
+    #[test]
+    fn user_names_lead_to_the_wallet_that_signed_their_registration() {
+        let mut rng = random::seeded(8);
+        let owner = HotWallet::generate(&mut rng);
+        let squatter = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = BlockCandidate::create_new(
+            vec![owner.wallet().clone(), squatter.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registered);
+
+        let mut credentials = Blockchain::<Credential>::empty_chain();
+        let credential = Credential::register("alice", &owner, &mut rng).ok().unwrap();
+        assert!(Credential::data_valid(&credentials, &credential).is_ok());
+        let mut tampered = credential.clone();
+        tampered.login = access::login_hash("bob");
+        assert!(Credential::data_valid(&credentials, &tampered).is_err());
+        // signed consistently, but with a key the named wallet never held
+        let login = access::login_hash("mallory");
+        let address = access::encode_address(owner.address());
+        let squatting = Credential {
+            signature: squatter.sign_message(&Credential::signed_content(&login, &address), &mut rng),
+            login,
+            address,
+            public_key: squatter.wallet().key().clone().unwrap(),
+        };
+        assert!(Credential::data_valid(&credentials, &squatting).is_ok());
+        let block = BlockCandidate::create_new(vec![credential.clone(), squatting], credentials.last_block()).ok().unwrap();
+        assert!(Credential::block_valid(&credentials, &block).is_ok());
+        credentials.submit_new_block(block);
+        assert!(Credential::data_valid(&credentials, &credential).is_err());
+
+        assert_eq!(access::fetch_wallet_address(&credentials, "alice"), Some(owner.address()));
+        assert_eq!(access::fetch_wallet_address(&credentials, "bob"), None);
+        assert_eq!(access::login(&credentials, &wallets, "alice").ok(), Some(owner.address()));
+        assert!(access::login(&credentials, &wallets, "mallory").is_err());
+        assert!(access::login(&credentials, &wallets, "bob").is_err());
+        assert!(access::login(&credentials, &Blockchain::<Wallet>::wallet_chain(), "alice").is_err());
+    }
+
+    #[test]
+    fn imported_keys_must_be_the_current_key_of_their_wallet() {
+        let mut rng = random::seeded(9);
+        let owner = HotWallet::generate(&mut rng);
+        let stranger = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = BlockCandidate::create_new(vec![owner.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registered);
+
+        let imported = HotWallet::for_wallet(owner.private_key().clone(), owner.address(), &wallets).ok().unwrap();
+        assert_eq!(imported.address(), owner.address());
+        assert!(HotWallet::for_wallet(stranger.private_key().clone(), owner.address(), &wallets).is_err());
+        assert!(HotWallet::for_wallet(stranger.private_key().clone(), stranger.address(), &wallets).is_err());
+
+        let rotated = HotWallet::rotated_from(&owner, &mut rng).ok().unwrap();
+        let rotation = BlockCandidate::create_new(vec![rotated.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(rotation);
+        assert!(HotWallet::for_wallet(owner.private_key().clone(), owner.address(), &wallets).is_err());
+        assert!(HotWallet::for_wallet(rotated.private_key().clone(), owner.address(), &wallets).is_ok());
+    }
+}
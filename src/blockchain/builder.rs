@@ -0,0 +1,224 @@
+use chrono::{DateTime, Utc};
+use rsa::rand_core::CryptoRngCore;
+
+use crate::blockchain::{Address, Transaction};
+use crate::blockchain::protocol::TRANSFER_FEE;
+use crate::blockchain::core::{Blockchain, BlockchainError};
+use crate::blockchain::signer::Signer;
+
+pub struct BuilderError {
+    message: String,
+}
+
+impl BuilderError {
+    pub fn new(message: &str) -> BuilderError {
+        BuilderError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for BuilderError {
+    fn message(&self) -> String {
+        format!("Transaction builder: {}", self.message)
+    }
+}
+
+// A transfer, or a contract call, together with the fee paying for it. Both are numbered in
+// a row starting at the source's next nonce and signed by the source, which is what validators
+// expect of every user transaction. Sponsored transfers go without, a sponsor pays their fee.
+pub struct TransactionBuilder {
+    transfer: Transaction,
+    // none for sponsored transfers and for sponsorships, which are fees themselves
+    fee: Option<i64>,
+    nonce: Option<u64>,
+}
+
+impl TransactionBuilder {
+    pub fn transfer(source_address: Address, target_address: Address, amount: i64) -> TransactionBuilder {
+        TransactionBuilder::from_transaction(
+            Transaction::new(source_address, target_address, String::new(), amount, Utc::now())
+        )
+    }
+
+    pub fn from_transaction(transfer: Transaction) -> TransactionBuilder {
+        TransactionBuilder {
+            transfer,
+            fee: Some(TRANSFER_FEE),
+            nonce: None,
+        }
+    }
+
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.transfer.title = title.to_string();
+        self
+    }
+
+    pub fn with_time(mut self, time: DateTime<Utc>) -> Self {
+        self.transfer.time = time;
+        self
+    }
+
+    pub fn with_fee(mut self, fee: i64) -> Self {
+        self.fee = Some(fee);
+        self
+    }
+
+    pub fn without_fee(mut self) -> Self {
+        self.fee = None;
+        self
+    }
+
+    // for clients without a chain, which ask a node for the next nonce
+    pub fn with_nonce(mut self, nonce: u64) -> Self {
+        self.nonce = Some(nonce);
+        self
+    }
+
+    // counts the source's transactions already waiting in the mempool
+    pub fn with_next_nonce(self, transactions: &Blockchain<Transaction>) -> Self {
+        let nonce = transactions.next_nonce(self.transfer.source_address);
+        self.with_nonce(nonce)
+    }
+
+    // the transfer and its fee if any, numbered but not yet signed
+    pub fn build(self) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+        let nonce = match self.nonce {
+            None => return Err(Box::new(BuilderError::new("Nonce is not set"))),
+            Some(nonce) => nonce
+        };
+        if self.fee.is_some_and(|fee| fee < 0) {
+            return Err(Box::new(BuilderError::new("Fee must not be negative")));
+        }
+        // a fee of nothing moves no coins, validators would refuse it
+        let fee = self.fee
+            .filter(|fee| *fee > 0)
+            .map(|fee| Transaction::fee(self.transfer.source_address, fee));
+        Ok(std::iter::once(self.transfer).chain(fee)
+            .enumerate()
+            .map(|(offset, mut transaction)| {
+                transaction.set_nonce(nonce + offset as u64);
+                transaction
+            })
+            .collect())
+    }
+
+    pub fn sign(
+        self, signer: &dyn Signer, rng: &mut dyn CryptoRngCore,
+    ) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+        if signer.address() != self.transfer.source_address {
+            return Err(Box::new(BuilderError::new("Only the source of a transfer can sign it")));
+        }
+        let mut transactions = self.build()?;
+        for transaction in &mut transactions {
+            signer.sign(transaction, rng)?;
+        }
+        Ok(transactions)
+    }
+}
+
+// the form transactions are submitted and gossiped in, the signature covers its content
+pub fn encode(transaction: &Transaction) -> String {
+    serde_json::to_string(transaction).unwrap()
+}
+
+pub fn decode(encoded: &str) -> Result<Transaction, Box<dyn BlockchainError>> {
+    serde_json::from_str(encoded).map_err(|_| {
+        Box::new(BuilderError::new("Malformed transaction")) as Box<dyn BlockchainError>
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{Transaction, TransactionValidationError, TransactionValidator, Wallet};
+    use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, TRANSFER_FEE};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::builder::{self, TransactionBuilder};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::random;
+
+    #[test]
+    fn built_transfers_pass_validation_after_what_is_already_pending() {
+        let mut rng = random::seeded(21);
+        let sender = HotWallet::generate(&mut rng);
+        let stranger = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), stranger.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+
+        assert!(TransactionBuilder::transfer(sender.address(), stranger.address(), 10).build().is_err());
+        let forged = TransactionBuilder::transfer(sender.address(), stranger.address(), 10)
+            .with_next_nonce(&transactions)
+            .sign(&stranger, &mut rng);
+        assert!(forged.is_err());
+
+        for amount in [10, 20] {
+            let signed = TransactionBuilder::transfer(sender.address(), stranger.address(), amount)
+                .with_title("rent")
+                .with_fee(2)
+                .with_next_nonce(&transactions)
+                .sign(&sender, &mut rng)
+                .ok().unwrap();
+            assert_eq!(signed.len(), 2);
+            assert_eq!(signed[1].amount(), 2);
+            assert_eq!(signed[1].nonce(), signed[0].nonce() + 1);
+            for transaction in signed {
+                let decoded = builder::decode(&builder::encode(&transaction)).ok().unwrap();
+                assert!(decoded == transaction);
+                assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&decoded).is_ok());
+                transactions.add_uncommitted(decoded);
+            }
+        }
+        assert!(builder::decode("{}").is_err());
+    }
+
+    #[test]
+    fn checks_before_broadcast_tell_why_a_send_would_be_refused() {
+        let mut rng = random::seeded(22);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 70, Utc::now())
+        ]);
+        let transfer = |target_address, amount| TransactionBuilder::transfer(sender.address(), target_address, amount);
+
+        let affordable = transfer(recipient.address(), 60).with_next_nonce(&transactions).sign(&sender, &mut rng).ok().unwrap();
+        let everything = transfer(recipient.address(), 70).with_next_nonce(&transactions).sign(&sender, &mut rng).ok().unwrap();
+        let skipping = transfer(recipient.address(), 10).with_nonce(5).sign(&sender, &mut rng).ok().unwrap();
+        let dust = transfer(recipient.address(), 0).with_next_nonce(&transactions).sign(&sender, &mut rng).ok().unwrap();
+        let unknown = transfer([9; 32], 10).with_next_nonce(&transactions).sign(&sender, &mut rng).ok().unwrap();
+        assert_eq!(affordable[1].amount(), TRANSFER_FEE);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        assert!(validator.check_transactions(&affordable).is_ok());
+        // the transfer alone fits, its fee no longer does
+        assert!(validator.check_transaction(&everything[0]).is_ok());
+        assert_eq!(validator.check_transactions(&everything), Err(TransactionValidationError::InsufficientBalance { have: 0, need: 1 }));
+        assert_eq!(validator.check_transaction(&skipping[0]), Err(TransactionValidationError::BadNonce { expected: 0, actual: 5 }));
+        assert_eq!(validator.check_transaction(&dust[0]), Err(TransactionValidationError::NonPositiveAmount { amount: 0 }));
+        assert_eq!(validator.check_transaction(&unknown[0]), Err(TransactionValidationError::UnknownTargetWallet));
+
+        for transaction in affordable {
+            transactions.add_uncommitted(transaction);
+        }
+        // pending incoming payments pass transaction_valid but cannot be spent yet
+        let early = TransactionBuilder::transfer(recipient.address(), sender.address(), 10)
+            .with_next_nonce(&transactions)
+            .sign(&recipient, &mut rng)
+            .ok().unwrap();
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        assert!(validator.transaction_valid(&early[0]).is_ok());
+        assert_eq!(validator.check_transaction(&early[0]), Err(TransactionValidationError::InsufficientBalance { have: 0, need: 10 }));
+    }
+}
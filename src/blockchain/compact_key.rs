@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rsa::RsaPublicKey;
+use rsa::pkcs1::{DecodeRsaPublicKey, EncodeRsaPublicKey};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+// Wallet keys travel and are stored as base64 of their PKCS#1 DER encoding. The rsa crate's own
+// serde form spells the modulus out as a list of 32 bit digits, close to twice as long. Entries
+// written that way by older nodes are still read, and wallet hashes keep covering that form.
+// Peers of protocol versions before capability::COMPACT_KEYS_VERSION cannot read compact keys,
+// while one of them is connected keys are written the old way.
+
+static COMPACT: AtomicBool = AtomicBool::new(true);
+
+// set from the version negotiated with the connected peers
+pub fn use_compact(compact: bool) {
+    COMPACT.store(compact, Ordering::Relaxed);
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum EncodedKey {
+    Compact(String),
+    Expanded(RsaPublicKey),
+}
+
+pub fn serialize<S>(key: &Option<RsaPublicKey>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    serialize_as(key, COMPACT.load(Ordering::Relaxed), serializer)
+}
+
+fn serialize_as<S>(key: &Option<RsaPublicKey>, compact: bool, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    match compact {
+        true => key.as_ref().map(encode).serialize(serializer),
+        false => key.serialize(serializer),
+    }
+}
+
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<RsaPublicKey>, D::Error> where D: Deserializer<'de> {
+    match Option::<EncodedKey>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(EncodedKey::Compact(encoded)) => decode(&encoded).map(Some).map_err(D::Error::custom),
+        Some(EncodedKey::Expanded(key)) => Ok(Some(key)),
+    }
+}
+
+pub fn encode(key: &RsaPublicKey) -> String {
+    base64::encode(key.to_pkcs1_der().expect("RSA public keys encode").as_bytes())
+}
+
+pub fn decode(encoded: &str) -> Result<RsaPublicKey, String> {
+    let der = base64::decode(encoded).map_err(|_| String::from("public key is not base64"))?;
+    RsaPublicKey::from_pkcs1_der(&der).map_err(|_| String::from("public key is not a PKCS#1 RSA key"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::Wallet;
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::Summary;
+    use crate::random;
+
+    #[test]
+    fn wallets_gossip_compact_keys_and_keep_their_hashes() {
+        let hot_wallet = HotWallet::generate(&mut random::seeded(5));
+        let wallet = hot_wallet.wallet();
+        let compact = serde_json::to_string(wallet).unwrap();
+        // what nodes wrote before keys were compacted, still what the hash covers
+        let expanded = wallet.summary();
+        assert!(compact.len() * 3 < expanded.len() * 2, "{} vs {} bytes", compact.len(), expanded.len());
+
+        let read: Wallet = serde_json::from_str(&compact).unwrap();
+        assert_eq!(read.key(), wallet.key());
+        assert_eq!(read.id(), wallet.id());
+        let legacy: Wallet = serde_json::from_str(&expanded).unwrap();
+        assert_eq!(legacy.key(), wallet.key());
+        assert_eq!(serde_json::to_string(&legacy).unwrap(), compact);
+
+        let keyless: Wallet = serde_json::from_str(r#"{"address":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"public_key":null}"#).unwrap();
+        assert!(keyless.key().is_none());
+        assert!(serde_json::from_str::<Wallet>(&compact.replace(&super::encode(wallet.key().as_ref().unwrap()), "AAAA")).is_err());
+    }
+
+    #[test]
+    fn keys_stay_expanded_for_peers_that_predate_compact_ones() {
+        let hot_wallet = HotWallet::generate(&mut random::seeded(6));
+        let key = hot_wallet.wallet().key().clone();
+        let mut expanded = vec![];
+        super::serialize_as(&key, false, &mut serde_json::Serializer::new(&mut expanded)).unwrap();
+        assert_eq!(String::from_utf8(expanded).unwrap(), serde_json::to_string(&key).unwrap());
+        let mut compact = vec![];
+        super::serialize_as(&key, true, &mut serde_json::Serializer::new(&mut compact)).unwrap();
+        assert_eq!(String::from_utf8(compact).unwrap(), format!("\"{}\"", super::encode(key.as_ref().unwrap())));
+    }
+}
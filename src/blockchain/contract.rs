@@ -0,0 +1,551 @@
+use chrono::{DateTime, Utc};
+use rsa::rand_core::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::{Address, Transaction};
+use crate::blockchain::protocol;
+use crate::blockchain::core::{Blockchain, BlockchainError};
+
+static SECRET_LENGTH: usize = 32;
+// any two of sender, recipient and arbiter settle an escrow
+pub static ESCROW_APPROVALS: usize = 2;
+pub static MAX_TOKEN_SYMBOL_LENGTH: usize = 12;
+
+// Conditions attached to a transfer. Locked funds are held by a system wallet and leave it only
+// through a settlement naming the lock, which TransactionValidator checks against the chain.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub enum Contract {
+    // claimable by the recipient with the preimage of hash_lock until expiry, refundable after
+    HtlcLock {
+        recipient: Address,
+        hash_lock: String,
+        expires_at: DateTime<Utc>,
+    },
+    // lock ids are Transaction::id of the locking transfer
+    HtlcClaim {
+        lock_id: String,
+        preimage: String,
+    },
+    HtlcRefund {
+        lock_id: String,
+    },
+    // the arbiter decides disputes, it never receives the funds itself
+    EscrowOpen {
+        recipient: Address,
+        arbiter: Address,
+    },
+    EscrowRelease {
+        escrow_id: String,
+    },
+    EscrowRefund {
+        escrow_id: String,
+    },
+    // tokens ride on transfers of zero coins, the token id is derived from issuer and symbol so
+    // only the issuer can mint more of it
+    TokenMint {
+        symbol: String,
+        amount: i64,
+    },
+    TokenTransfer {
+        token_id: String,
+        amount: i64,
+    },
+    // coins minted once for a newly registered wallet, registering costs nothing so the claim
+    // carries a proof of work over the wallet's address that every validator checks
+    Grant {
+        work: u64,
+    },
+    // a fee paid by someone other than the sender of the transfer, e.g. for a new wallet that
+    // holds no coins yet, transfer ids are Transaction::id
+    Sponsorship {
+        transfer_id: String,
+    },
+    // stake forfeited by a forger whose block was voted down or a validator that went inactive,
+    // burned from the owner's balance against the bond made at the stakes chain's epoch
+    Penalty {
+        epoch: u64,
+    },
+}
+
+// signature of one of several parties a contract asks for, over Transaction::signed_content
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct Approval {
+    signer: Address,
+    signature: String,
+}
+
+impl Approval {
+    pub fn new(signer: Address, signature: String) -> Approval {
+        Approval {
+            signer,
+            signature,
+        }
+    }
+
+    pub fn signer(&self) -> Address {
+        self.signer
+    }
+    pub fn signature(&self) -> &str {
+        &self.signature
+    }
+}
+
+impl Contract {
+    // id of the lock a settlement spends, none for anything else
+    pub fn lock_id(&self) -> Option<&str> {
+        match self {
+            Contract::HtlcLock { .. } | Contract::EscrowOpen { .. } => None,
+            Contract::TokenMint { .. } | Contract::TokenTransfer { .. } | Contract::Grant { .. } => None,
+            Contract::Sponsorship { .. } | Contract::Penalty { .. } => None,
+            Contract::HtlcClaim { lock_id, .. } | Contract::HtlcRefund { lock_id } => Some(lock_id),
+            Contract::EscrowRelease { escrow_id } | Contract::EscrowRefund { escrow_id } => Some(escrow_id),
+        }
+    }
+
+    pub fn locks_funds(&self) -> bool {
+        matches!(self, Contract::HtlcLock { .. } | Contract::EscrowOpen { .. })
+    }
+
+    pub fn sponsored_transfer(&self) -> Option<&str> {
+        match self {
+            Contract::Sponsorship { transfer_id } => Some(transfer_id),
+            _ => None
+        }
+    }
+
+    // settlements signed by several parties instead of a single sender
+    pub fn needs_approvals(&self) -> bool {
+        matches!(self, Contract::EscrowRelease { .. } | Contract::EscrowRefund { .. })
+    }
+}
+
+pub fn hash_lock(preimage: &str) -> String {
+    array_bytes::bytes2hex("", Sha256::digest(preimage.as_bytes()))
+}
+
+// the preimage an initiator keeps until the counterparty has locked its side of a swap
+pub fn generate_secret<R>(rng: &mut R) -> String where R: CryptoRng + RngCore {
+    let mut secret = [0u8; SECRET_LENGTH];
+    rng.fill_bytes(&mut secret);
+    array_bytes::bytes2hex("", secret)
+}
+
+// a lock in an unreadable stored block counts as unknown
+pub fn find_lock(transactions: &Blockchain<Transaction>, lock_id: &str) -> Option<Transaction> {
+    find_committed(transactions, |transaction| {
+        let lock = transaction.contract().as_ref().is_some_and(Contract::locks_funds) && transaction.id() == lock_id;
+        lock.then(|| transaction.clone())
+    }).unwrap_or(None)
+}
+
+// and a settlement in one as settled, so nothing is released twice
+pub fn settled(transactions: &Blockchain<Transaction>, lock_id: &str) -> bool {
+    find_committed(transactions, |transaction| {
+        (transaction.contract().as_ref().and_then(Contract::lock_id) == Some(lock_id)).then_some(())
+    }).map_or(true, |settlement| settlement.is_some())
+}
+
+pub fn token_id(issuer: Address, symbol: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(issuer);
+    hasher.update(symbol.as_bytes());
+    array_bytes::bytes2hex("", &hasher.finalize()[..16])
+}
+
+pub fn symbol_valid(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && symbol.len() <= MAX_TOKEN_SYMBOL_LENGTH
+        && symbol.chars().all(|character| character.is_ascii_alphanumeric())
+}
+
+// symbol the token was minted under, none for tokens never minted
+pub fn token_symbol(transactions: &Blockchain<Transaction>, token_id: &str) -> Option<String> {
+    find_committed(transactions, |transaction| match transaction.contract() {
+        Some(Contract::TokenMint { symbol, .. }) if self::token_id(transaction.source_address(), symbol) == token_id => {
+            Some(symbol.clone())
+        }
+        _ => None
+    }).unwrap_or(None)
+}
+
+// token id, symbol and balance of one token, or of every token the address holds, the balance
+// is none when it overflows
+pub fn token_balances(
+    transactions: &Blockchain<Transaction>, address: Address, token_id: Option<String>,
+) -> Vec<(String, String, Option<i64>)> {
+    let token_ids = match token_id {
+        Some(token_id) => vec![token_id],
+        None => transactions.committed_tokens(address).into_iter().map(|(token_id, _)| token_id).collect()
+    };
+    token_ids.into_iter()
+        .map(|token_id| {
+            let symbol = token_symbol(transactions, &token_id).unwrap_or(String::from("?"));
+            let balance = transactions.token_balance_of(&token_id, address);
+            (token_id, symbol, balance)
+        })
+        .collect()
+}
+
+// takes about 2^work_bits attempts, the work only fits the one address
+pub fn solve_grant_work(address: Address, work_bits: u32) -> u64 {
+    (0u64..).find(|work| grant_work_valid(address, *work, work_bits)).unwrap_or_default()
+}
+
+pub fn grant_work_valid(address: Address, work: u64, work_bits: u32) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(protocol::chain_id().as_bytes());
+    hasher.update(address);
+    hasher.update(work.to_be_bytes());
+    let digest = hasher.finalize();
+    let zero_bytes = digest.iter().take_while(|byte| **byte == 0).count();
+    let zero_bits = zero_bytes as u32 * 8 + digest.get(zero_bytes).map_or(0, |byte| byte.leading_zeros());
+    zero_bits >= work_bits
+}
+
+// a grant in an unreadable stored block counts as paid, so none is paid twice
+pub fn granted(transactions: &Blockchain<Transaction>, address: Address) -> bool {
+    find_committed(transactions, |transaction| {
+        (transaction.is_grant() && transaction.target_address() == address).then_some(())
+    }).map_or(true, |grant| grant.is_some())
+}
+
+// a transfer in an unreadable stored block counts as unknown
+pub fn transfer_committed(transactions: &Blockchain<Transaction>, transfer_id: &str) -> bool {
+    find_committed(transactions, |transaction| (transaction.id() == transfer_id).then_some(()))
+        .is_ok_and(|transfer| transfer.is_some())
+}
+
+// and a sponsorship in one as paid, so no fee is paid twice
+pub fn sponsored(transactions: &Blockchain<Transaction>, transfer_id: &str) -> bool {
+    find_committed(transactions, |transaction| {
+        (transaction.contract().as_ref().and_then(Contract::sponsored_transfer) == Some(transfer_id)).then_some(())
+    }).map_or(true, |sponsorship| sponsorship.is_some())
+}
+
+// drops sponsorships whose transfer is neither committed nor among the data before them, they
+// wait in the mempool for a block after the transfer's
+pub fn keep_linked_sponsorships(transactions: &Blockchain<Transaction>, data: &[Transaction]) -> Vec<Transaction> {
+    let mut kept: Vec<Transaction> = Vec::with_capacity(data.len());
+    for transaction in data {
+        let linked = match transaction.contract().as_ref().and_then(Contract::sponsored_transfer) {
+            None => true,
+            Some(transfer_id) => kept.iter().any(|earlier| earlier.id() == transfer_id)
+                || transfer_committed(transactions, transfer_id),
+        };
+        if linked {
+            kept.push(transaction.clone());
+        }
+    }
+    kept
+}
+
+// drops the grants past the first limit ones, they wait in the mempool for a later block
+pub fn keep_grants_within(data: Vec<Transaction>, limit: usize) -> Vec<Transaction> {
+    let mut grants = 0;
+    data.into_iter()
+        .filter(|transaction| {
+            grants += transaction.is_grant() as usize;
+            !transaction.is_grant() || grants <= limit
+        })
+        .collect()
+}
+
+// sender, recipient and arbiter of an escrow
+pub fn escrow_parties(escrow: &Transaction) -> Option<[Address; 3]> {
+    match escrow.contract() {
+        Some(Contract::EscrowOpen { recipient, arbiter }) => Some([escrow.source_address(), *recipient, *arbiter]),
+        _ => None
+    }
+}
+
+// first committed transaction the closure picks something from, oldest first
+fn find_committed<F, R>(transactions: &Blockchain<Transaction>, mut pick: F) -> Result<Option<R>, Box<dyn BlockchainError>>
+    where F: FnMut(&Transaction) -> Option<R> {
+    for block in transactions.blocks() {
+        if let Some(picked) = block?.data().iter().find_map(&mut pick) {
+            return Ok(Some(picked));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::blockchain::{RejectionReason, Transaction, TransactionValidationError, TransactionValidator, Wallet};
+    use crate::blockchain::protocol::{CONTRACT_WALLET_ADDRESS, MINTING_WALLET_ADDRESS, WALLET_GRANT};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::contract::{self, Contract};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::upgrade::{UPGRADE_SCHEDULE, UpgradeSchedule};
+    use crate::random;
+
+    // solved in a blink, the genesis difficulty would slow the tests down
+    static CHEAP_GRANT_WORK_BITS: u32 = 8;
+
+    fn cheap_grants(grants_per_block: usize) -> UpgradeSchedule {
+        UpgradeSchedule::new(
+            UPGRADE_SCHEDULE.rules_at(0).with_grant_work(CHEAP_GRANT_WORK_BITS).with_grants_per_block(grants_per_block)
+        )
+    }
+
+    #[test]
+    fn locked_funds_go_to_the_preimage_holder_or_back_after_expiry() {
+        let mut rng = random::seeded(10);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+
+        let secret = contract::generate_secret(&mut rng);
+        let mut lock = Transaction::htlc_lock(
+            sender.address(), recipient.address(), 40, contract::hash_lock(&secret), Utc::now() + Duration::hours(1),
+        );
+        sender.sign(&mut lock, &mut rng);
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&lock).is_ok());
+        let block = BlockCandidate::create_new(vec![lock.clone()], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert_eq!(transactions.balance_of(*CONTRACT_WALLET_ADDRESS), 40);
+
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let mut wrong_secret = Transaction::htlc_claim(&lock, "guess".to_string()).unwrap();
+        recipient.sign(&mut wrong_secret, &mut rng);
+        assert_eq!(validator.transaction_valid(&wrong_secret), Err(TransactionValidationError::BadPreimage));
+        let mut early_refund = Transaction::htlc_refund(&lock).unwrap();
+        sender.sign(&mut early_refund, &mut rng);
+        assert_eq!(validator.transaction_valid(&early_refund), Err(TransactionValidationError::ContractNotExpired));
+        let mut stolen = Transaction::htlc_claim(&lock, secret.clone()).unwrap();
+        sender.sign(&mut stolen, &mut rng);
+        assert_eq!(validator.transaction_valid(&stolen), Err(TransactionValidationError::BadSignature));
+
+        let mut claim = Transaction::htlc_claim(&lock, secret).unwrap();
+        recipient.sign(&mut claim, &mut rng);
+        assert!(validator.transaction_valid(&claim).is_ok());
+        let block = BlockCandidate::create_new(vec![claim.clone()], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert_eq!(transactions.balance_of(recipient.address()), 40);
+        assert!(contract::settled(&transactions, &lock.id()));
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&claim),
+            Err(TransactionValidationError::ContractSettled)
+        );
+        assert!(matches!(claim.contract(), Some(Contract::HtlcClaim { .. })));
+    }
+
+    #[test]
+    fn locks_of_no_coins_are_rejected() {
+        let mut rng = random::seeded(18);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let arbiter = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone(), arbiter.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, *CONTRACT_WALLET_ADDRESS, "".to_string(), 100, Utc::now())
+        ]);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let hash_lock = contract::hash_lock(&contract::generate_secret(&mut rng));
+
+        for amount in [-40, 0] {
+            let mut lock = Transaction::htlc_lock(
+                sender.address(), recipient.address(), amount, hash_lock.clone(), Utc::now() + Duration::hours(1),
+            );
+            sender.sign(&mut lock, &mut rng);
+            assert_eq!(validator.transaction_valid(&lock), Err(TransactionValidationError::NonPositiveAmount { amount }));
+            let mut escrow = Transaction::escrow_open(sender.address(), recipient.address(), arbiter.address(), amount);
+            sender.sign(&mut escrow, &mut rng);
+            assert_eq!(validator.transaction_valid(&escrow), Err(TransactionValidationError::NonPositiveAmount { amount }));
+        }
+    }
+
+    #[test]
+    fn tokens_are_minted_by_their_issuer_and_tracked_per_holder() {
+        let mut rng = random::seeded(12);
+        let issuer = HotWallet::generate(&mut rng);
+        let holder = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![issuer.wallet().clone(), holder.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+
+        let mut mint = Transaction::token_mint(issuer.address(), "GOLD".to_string(), 50);
+        issuer.sign(&mut mint, &mut rng);
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&mint).is_ok());
+        let mut bad_symbol = Transaction::token_mint(issuer.address(), "GOLD COIN".to_string(), 50);
+        issuer.sign(&mut bad_symbol, &mut rng);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&bad_symbol),
+            Err(TransactionValidationError::BadContract)
+        );
+        let block = BlockCandidate::create_new(vec![mint], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+
+        let gold = contract::token_id(issuer.address(), "GOLD");
+        assert_eq!(transactions.committed_token_balance(&gold, issuer.address()), 50);
+        assert_eq!(contract::token_symbol(&transactions, &gold), Some("GOLD".to_string()));
+        let mut transfer = Transaction::token_transfer(issuer.address(), holder.address(), gold.clone(), 20);
+        issuer.sign(&mut transfer, &mut rng);
+        let mut overdraft = Transaction::token_transfer(holder.address(), issuer.address(), gold.clone(), 1);
+        holder.sign(&mut overdraft, &mut rng);
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        assert!(validator.transaction_valid(&transfer).is_ok());
+        assert_eq!(
+            validator.transaction_valid(&overdraft),
+            Err(TransactionValidationError::InsufficientTokens { have: 0, need: 1 })
+        );
+        let block = BlockCandidate::create_new(vec![transfer], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert_eq!(transactions.committed_tokens(holder.address()), vec![(gold.clone(), 20)]);
+        assert_eq!(transactions.token_balance_of(&gold, issuer.address()), Some(30));
+    }
+
+    #[test]
+    fn token_mints_that_would_overflow_a_balance_are_refused() {
+        let mut rng = random::seeded(27);
+        let issuer = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(vec![issuer.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+
+        let mut mint = Transaction::token_mint(issuer.address(), "GOLD".to_string(), i64::MAX);
+        issuer.sign(&mut mint, &mut rng);
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&mint).is_ok());
+        transactions.add_uncommitted(mint);
+        let mut more = Transaction::token_mint(issuer.address(), "GOLD".to_string(), 1);
+        more.set_nonce(1);
+        issuer.sign(&mut more, &mut rng);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&more),
+            Err(TransactionValidationError::TokenOverflow)
+        );
+        let gold = contract::token_id(issuer.address(), "GOLD");
+        transactions.add_uncommitted(more);
+        assert_eq!(transactions.token_balance_of(&gold, issuer.address()), None);
+    }
+
+    #[test]
+    fn grants_need_the_wallets_puzzle_and_are_paid_once() {
+        let mut rng = random::seeded(17);
+        let newcomer = HotWallet::generate(&mut rng);
+        let address = newcomer.address();
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let upgrades = cheap_grants(1);
+        let grant = Transaction::grant(address, WALLET_GRANT, contract::solve_grant_work(address, CHEAP_GRANT_WORK_BITS));
+        let valid = |transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, grant: &Transaction| {
+            TransactionValidator::with_upgrades(wallets, transactions, &upgrades).transaction_valid(grant)
+        };
+        assert_eq!(valid(&transactions, &wallets, &grant), Err(TransactionValidationError::UnknownTargetWallet));
+
+        let registration = BlockCandidate::create_new(vec![newcomer.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let unsolved = (0u64..).find(|work| !contract::grant_work_valid(address, *work, CHEAP_GRANT_WORK_BITS)).unwrap();
+        assert_eq!(
+            valid(&transactions, &wallets, &Transaction::grant(address, WALLET_GRANT, unsolved)),
+            Err(TransactionValidationError::BadGrantWork)
+        );
+        let Some(Contract::Grant { work }) = grant.contract().clone() else { panic!("not a grant") };
+        assert!(matches!(
+            valid(&transactions, &wallets, &Transaction::grant(address, WALLET_GRANT * 2, work)),
+            Err(TransactionValidationError::BadGrant { .. })
+        ));
+        assert!(valid(&transactions, &wallets, &grant).is_ok());
+
+        let twice = BlockCandidate::create_new(vec![grant.clone(), grant.clone()], transactions.last_block()).ok().unwrap();
+        assert!(matches!(
+            TransactionValidator::with_upgrades(&wallets, &transactions, &upgrades).diagnose(&twice),
+            Err(RejectionReason::InvalidTransaction { error: TransactionValidationError::AlreadyGranted, .. })
+        ));
+        let block = BlockCandidate::create_new(vec![grant.clone()], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert_eq!(transactions.balance_of(address), WALLET_GRANT);
+        assert_eq!(valid(&transactions, &wallets, &grant), Err(TransactionValidationError::AlreadyGranted));
+    }
+
+    #[test]
+    fn blocks_pay_no_more_grants_than_the_rules_allow() {
+        let mut rng = random::seeded(18);
+        let newcomers = [HotWallet::generate(&mut rng), HotWallet::generate(&mut rng)];
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            newcomers.iter().map(|newcomer| newcomer.wallet().clone()).collect(), wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let grants: Vec<Transaction> = newcomers.iter()
+            .map(|newcomer| Transaction::grant(
+                newcomer.address(), WALLET_GRANT, contract::solve_grant_work(newcomer.address(), CHEAP_GRANT_WORK_BITS),
+            ))
+            .collect();
+
+        let both = BlockCandidate::create_new(grants.clone(), transactions.last_block()).ok().unwrap();
+        let too_many = RejectionReason::Malformed(String::from("Too many grants in block"));
+        assert_ne!(TransactionValidator::with_upgrades(&wallets, &transactions, &cheap_grants(2)).diagnose(&both), Err(too_many.clone()));
+        assert_eq!(TransactionValidator::with_upgrades(&wallets, &transactions, &cheap_grants(1)).diagnose(&both), Err(too_many));
+        // forgers leave the second one for the next block
+        assert_eq!(contract::keep_grants_within(grants.clone(), 1), grants[..1]);
+    }
+
+    #[test]
+    fn sponsors_pay_the_fee_of_a_transfer_once_and_only_after_it() {
+        let mut rng = random::seeded(14);
+        let newcomer = HotWallet::generate(&mut rng);
+        let sponsor = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![newcomer.wallet().clone(), sponsor.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, newcomer.address(), "".to_string(), 10, Utc::now()),
+            Transaction::new(MINTING_WALLET_ADDRESS, sponsor.address(), "".to_string(), 100, Utc::now()),
+        ]);
+
+        // the whole balance goes out, nothing is left for a fee
+        let mut transfer = Transaction::new(newcomer.address(), sponsor.address(), "".to_string(), 10, Utc::now());
+        newcomer.sign(&mut transfer, &mut rng);
+        let mut sponsorship = Transaction::sponsorship(sponsor.address(), &transfer, 1);
+        sponsor.sign(&mut sponsorship, &mut rng);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&sponsorship),
+            Err(TransactionValidationError::UnknownSponsoredTransfer)
+        );
+        assert_eq!(contract::keep_linked_sponsorships(&transactions, &[sponsorship.clone(), transfer.clone()]).len(), 1);
+
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&transfer).is_ok());
+        transactions.add_uncommitted(transfer.clone());
+        assert!(TransactionValidator::new(&wallets, &transactions).transaction_valid(&sponsorship).is_ok());
+        transactions.add_uncommitted(sponsorship.clone());
+        let mut second = Transaction::sponsorship(sponsor.address(), &transfer, 1);
+        sponsor.sign(&mut second, &mut rng);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&second),
+            Err(TransactionValidationError::AlreadySponsored)
+        );
+
+        let block_data = contract::keep_linked_sponsorships(&transactions, transactions.uncommitted_data());
+        assert_eq!(block_data.len(), 2);
+        let block = BlockCandidate::create_new(block_data, transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert!(contract::sponsored(&transactions, &transfer.id()));
+        assert_eq!(transactions.balance_of(newcomer.address()), 0);
+        assert_eq!(
+            TransactionValidator::new(&wallets, &transactions).transaction_valid(&second),
+            Err(TransactionValidationError::AlreadySponsored)
+        );
+    }
+}
@@ -1,16 +1,34 @@
 use std::{cmp, mem};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::Deref;
 
 use chrono::{DateTime, Utc};
 use serde::{ser::SerializeStruct, Serialize, Serializer};
 use sha2::{Digest, Sha512};
-
-use crate::blockchain::{self, BlockchainData, Transaction, TransactionCriteria, Wallet, WalletCriteria};
+use tokio::sync::broadcast;
+
+use crate::blockchain::{Address, BlockchainData, Transaction, TransactionCriteria, Wallet, WalletCriteria};
+use crate::blockchain::protocol;
+use crate::blockchain::governance::GovernanceRecord;
+use crate::blockchain::snapshot::{BalanceSnapshot, SnapshotIndex};
+use crate::blockchain::store::BlockStore;
+use crate::blockchain::upgrade::UPGRADE_SCHEDULE;
 use crate::BlockHash;
 use crate::network::communication::{BlockchainDto, BlockDto};
+use crate::report;
 
 //todo consider introducing designated types
 type CommitTime = Option<DateTime<Utc>>;
 pub type BlockPointer<T> = Option<Box<Block<T>>>;
+// hash of the account state a block leaves behind, see snapshot::account_state_root
+pub type StateRoot = [u8; 32];
+
+static EVENT_CHANNEL_CAPACITY: usize = 256;
+// blocks this far below the tip are final, no competing chain may replace them
+pub static DEFAULT_MAX_REORG_DEPTH: u64 = 6;
+// block times are proposer clocks, so new blocks are checked against the median of recent ones
+pub static MEDIAN_TIME_SPAN: usize = 11;
+pub static MAX_FUTURE_DRIFT_SECONDS: i64 = 120;
 
 
 pub trait Summary {
@@ -41,6 +59,15 @@ pub struct BlockValidationError {
 
 pub struct BlockCreationError;
 
+pub struct StorageError {
+    message: String,
+}
+
+pub struct ChainValidationError {
+    block_number: u64,
+    message: String,
+}
+
 pub struct BlockAdditionResult {
     block_number: u64,
     block_hash: BlockHash,
@@ -51,6 +78,8 @@ pub struct BlockAdditionResult {
 pub struct BlockKey {
     hash: BlockHash,
     previous_hash: Option<BlockHash>,
+    // blocks forged before state roots, and those of chains without balances, carry none
+    state_root: Option<StateRoot>,
 }
 
 #[derive(Serialize)]
@@ -69,12 +98,118 @@ pub struct Block<T> where T: BlockchainData {
     block_number: u64,
 }
 
+// blocks a replaced chain lost, affected data was committed before but is not on the new chain
+pub struct Rollback<T> where T: BlockchainData {
+    depth: u64,
+    fork_height: u64,
+    affected: Vec<T>,
+}
+
+impl<T> Rollback<T> where T: BlockchainData {
+    pub fn depth(&self) -> u64 {
+        self.depth
+    }
+    // height of the first block that was rolled back
+    pub fn fork_height(&self) -> u64 {
+        self.fork_height
+    }
+    pub fn affected(&self) -> &[T] {
+        &self.affected
+    }
+}
+
 pub struct Blockchain<T> where T: BlockchainData {
     last_block: BlockPointer<T>,
     chain_length: u64,
     uncommitted_data: Vec<T>,
     data_units_per_block: u64,
     remaining_pool: i64,
+    accounts: AccountIndex,
+    snapshots: SnapshotIndex,
+    events: broadcast::Sender<ChainEvent<T>>,
+    retention: Option<Retention>,
+}
+
+// how a bounded chain keeps blocks, the first stored_height blocks are on disk
+struct Retention {
+    store: BlockStore,
+    resident_blocks: u64,
+    stored_height: u64,
+}
+
+// a block either still in memory or read back from the store
+pub enum ChainBlock<'a, T> where T: BlockchainData {
+    Resident(&'a Block<T>),
+    Loaded(Box<Block<T>>),
+}
+
+impl<'a, T> Deref for ChainBlock<'a, T> where T: BlockchainData {
+    type Target = Block<T>;
+
+    fn deref(&self) -> &Block<T> {
+        match self {
+            ChainBlock::Resident(block) => block,
+            ChainBlock::Loaded(block) => block,
+        }
+    }
+}
+
+// Blocks held in memory, newest first as they link to each other. Iterating from the back walks
+// the links once and hands the blocks out oldest first.
+pub struct ChainIter<'a, T> where T: BlockchainData {
+    next: Option<&'a Block<T>>,
+    // blocks not handed out yet, newest first, once the back was asked for
+    collected: Option<VecDeque<&'a Block<T>>>,
+}
+
+impl<'a, T> Iterator for ChainIter<'a, T> where T: BlockchainData {
+    type Item = &'a Block<T>;
+
+    fn next(&mut self) -> Option<&'a Block<T>> {
+        if let Some(collected) = &mut self.collected {
+            return collected.pop_front();
+        }
+        let block = self.next?;
+        self.next = block.previous_block.as_deref();
+        Some(block)
+    }
+}
+
+impl<'a, T> DoubleEndedIterator for ChainIter<'a, T> where T: BlockchainData {
+    fn next_back(&mut self) -> Option<&'a Block<T>> {
+        if self.collected.is_none() {
+            self.collected = Some(self.by_ref().collect());
+        }
+        self.collected.as_mut()?.pop_back()
+    }
+}
+
+#[derive(Clone)]
+pub enum ChainEvent<T> where T: BlockchainData {
+    BlockAppended {
+        block_number: u64,
+        block_hash: String,
+        data: Vec<T>,
+    },
+    Reorg {
+        depth: u64,
+        new_tip_hash: String,
+        // data of the rolled back blocks the new chain does not carry
+        affected_txs: Vec<T>,
+    },
+    DataSubmitted(T),
+    // a peer announced that a block at this height carries a payment to a local wallet
+    IncomingPayment {
+        block_number: u64,
+        data: T,
+        // false while the block has not reached the local chain, the payment only sits in the mempool
+        committed: bool,
+    },
+    // stake taken from a forger that misbehaved, reported on the chain the stake was bid on
+    Slashed {
+        address: Address,
+        amount: i64,
+    },
 }
 
 impl BlockchainError for BlockValidationError {
@@ -105,6 +240,45 @@ impl TransactionCountError {
     }
 }
 
+impl StorageError {
+    pub fn new(message: &str) -> StorageError {
+        StorageError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for StorageError {
+    fn message(&self) -> String {
+        format!("Storage error: {}", self.message)
+    }
+}
+
+impl ChainValidationError {
+    pub fn new(block_number: u64, message: &str) -> ChainValidationError {
+        ChainValidationError {
+            block_number,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for ChainValidationError {
+    fn message(&self) -> String {
+        format!("Invalid chain at block {}: {}", self.block_number, self.message)
+    }
+}
+
+impl BlockAdditionResult {
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub fn block_hash(&self) -> BlockHash {
+        self.block_hash
+    }
+}
+
 impl BlockchainError for BlockCreationError {
     fn message(&self) -> String {
         "Only genesis block can have no ancestor".to_string()
@@ -138,6 +312,7 @@ impl Default for BlockKey {
         BlockKey {
             hash: [0; 64],
             previous_hash: None,
+            state_root: None,
         }
     }
 }
@@ -162,7 +337,7 @@ impl ToString for BlockKey {
 
 impl Serialize for BlockKey {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
-        let mut state = serializer.serialize_struct("BlockKey", 2)?;
+        let mut state = serializer.serialize_struct("BlockKey", 3)?;
         let hash = array_bytes::bytes2hex("", self.hash);
         let previous_hash = match &self.previous_hash {
             None => None,
@@ -172,18 +347,39 @@ impl Serialize for BlockKey {
         };
         state.serialize_field("hash", &hash)?;
         state.serialize_field("previous_hash", &previous_hash)?;
+        state.serialize_field("state_root", &self.state_root())?;
         state.end()
     }
 }
 
 impl BlockKey {
-    fn parse_from_dto<T>(block_dto: &mut BlockDto<T>) -> BlockKey where T: BlockchainData {
-        BlockKey {
-            hash: array_bytes::hex2array(block_dto.take_block_hash()).unwrap(),
-            previous_hash: match block_dto.take_previous_block_hash() {
-                None => None,
-                Some(previous_hash) => Some(array_bytes::hex2array(previous_hash).unwrap())
-            },
+    fn parse_from_dto<T>(
+        block_dto: &mut BlockDto<T>
+    ) -> Result<BlockKey, Box<dyn BlockchainError>> where T: BlockchainData {
+        let block_number = block_dto.block_number();
+        let hash = BlockKey::parse_hash(&block_dto.take_block_hash(), block_number)?;
+        let previous_hash = match block_dto.take_previous_block_hash() {
+            None => None,
+            Some(previous_hash) => Some(BlockKey::parse_hash(&previous_hash, block_number)?)
+        };
+        let state_root = match block_dto.take_state_root() {
+            None => None,
+            Some(state_root) => match array_bytes::hex2array(&state_root) {
+                Ok(state_root) => Some(state_root),
+                Err(_) => return Err(Box::new(ChainValidationError::new(block_number, "Malformed state root")))
+            }
+        };
+        Ok(BlockKey {
+            hash,
+            previous_hash,
+            state_root,
+        })
+    }
+
+    fn parse_hash(value: &str, block_number: u64) -> Result<BlockHash, Box<dyn BlockchainError>> {
+        match array_bytes::hex2array(value) {
+            Ok(hash) => Ok(hash),
+            Err(_) => Err(Box::new(ChainValidationError::new(block_number, "Malformed block hash")))
         }
     }
 
@@ -199,12 +395,24 @@ impl BlockKey {
         BlockKey::hash_to_string(self.hash)
     }
 
+    pub fn raw_previous_hash(&self) -> Option<BlockHash> {
+        self.previous_hash
+    }
+
     pub fn previous_hash(&self) -> Option<String> {
         match self.previous_hash {
             None => None,
             Some(hash) => Some(array_bytes::bytes2hex("", hash))
         }
     }
+
+    pub fn raw_state_root(&self) -> Option<StateRoot> {
+        self.state_root
+    }
+
+    pub fn state_root(&self) -> Option<String> {
+        self.state_root.map(|state_root| array_bytes::bytes2hex("", state_root))
+    }
 }
 
 impl<T> BlockCandidate<T> where T: BlockchainData {
@@ -216,7 +424,7 @@ impl<T> BlockCandidate<T> where T: BlockchainData {
         mem::take(&mut self.time)
     }
 
-    pub fn block_number(&mut self) -> u64 {
+    pub fn block_number(&self) -> u64 {
         self.block_number
     }
 
@@ -240,7 +448,7 @@ impl<T> BlockCandidate<T> where T: BlockchainData {
                 )),
             Some(previous_block) => {
                 let key = BlockCandidate::<T>::hash(
-                    previous_block.key, BlockCandidate::summarize(&data),
+                    previous_block.key.hash, BlockCandidate::summarize(&data), None,
                 );
                 Ok(BlockCandidate {
                     key,
@@ -252,28 +460,35 @@ impl<T> BlockCandidate<T> where T: BlockchainData {
         }
     }
 
+    // the root is part of what the block hash covers, so it cannot be swapped afterwards
+    pub fn with_state_root(mut self, state_root: StateRoot) -> Self {
+        if let Some(previous_hash) = self.key.previous_hash {
+            self.key = BlockCandidate::<T>::hash(previous_hash, BlockCandidate::summarize(&self.data), Some(state_root));
+        }
+        self
+    }
+
     pub fn summarize(data: &[T]) -> String where T: BlockchainData {
         data.iter()
             .map(|data| data.summary())
             .collect::<String>()
     }
 
-    pub fn hash(previous_key: BlockKey, data_summary: String) -> BlockKey {
-        match previous_key.previous_hash {
-            None => BlockKey::default(),
-            Some(matched) => {
-                let mut hasher = Sha512::new();
-                hasher.update(matched);
-                hasher.update(data_summary.as_bytes());
-                let hash: BlockHash = hasher.finalize()
-                    .as_slice()
-                    .try_into()
-                    .expect("Wrong output length");
-                BlockKey {
-                    hash,
-                    previous_hash: Some(matched),
-                }
-            }
+    pub fn hash(previous_hash: BlockHash, data_summary: String, state_root: Option<StateRoot>) -> BlockKey {
+        let mut hasher = Sha512::new();
+        hasher.update(previous_hash);
+        hasher.update(data_summary.as_bytes());
+        if let Some(state_root) = state_root {
+            hasher.update(state_root);
+        }
+        let hash: BlockHash = hasher.finalize()
+            .as_slice()
+            .try_into()
+            .expect("Wrong output length");
+        BlockKey {
+            hash,
+            previous_hash: Some(previous_hash),
+            state_root,
         }
     }
 }
@@ -313,16 +528,27 @@ impl<T> Block<T> where T: BlockchainData + Summary {
     pub fn block_number(&self) -> u64 {
         self.block_number
     }
+
+    // as read back from a store, the genesis block was never committed at a time
+    pub(crate) fn stored(block_candidate: BlockCandidate<T>) -> Block<T> {
+        let mut block = Block::from(block_candidate);
+        if block.block_number == 0 {
+            block.time = None;
+        }
+        block
+    }
 }
 
-impl<T> From<BlockDto<T>> for BlockCandidate<T> where T: BlockchainData {
-    fn from(mut dto: BlockDto<T>) -> Self {
-        Self {
+impl<T> TryFrom<BlockDto<T>> for BlockCandidate<T> where T: BlockchainData {
+    type Error = Box<dyn BlockchainError>;
+
+    fn try_from(mut dto: BlockDto<T>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            key: BlockKey::parse_from_dto(&mut dto)?,
             data: dto.take_data(),
-            key: BlockKey::parse_from_dto(&mut dto),
             time: dto.take_time(),
             block_number: dto.block_number(),
-        }
+        })
     }
 }
 
@@ -338,30 +564,49 @@ impl<T> From<BlockCandidate<T>> for Block<T> where T: BlockchainData {
     }
 }
 
-impl<T> From<BlockchainDto<T>> for Blockchain<T> where T: BlockchainData {
-    fn from(mut dto: BlockchainDto<T>) -> Self {
-        let last_block = {
-            let mut last_block = None;
-            let block_dtos = dto.take_blocks();
-            for mut block_dto in block_dtos {
-                let block = Block {
-                    previous_block: last_block,
-                    data: block_dto.take_data(),
-                    key: BlockKey::parse_from_dto(&mut block_dto),
-                    time: Some(block_dto.take_time()),
-                    block_number: block_dto.block_number(),
-                };
-                last_block = Some(Box::new(block));
-            }
-            last_block
-        };
-        Self {
+impl<T> TryFrom<BlockchainDto<T>> for Blockchain<T> where T: BlockchainData {
+    type Error = Box<dyn BlockchainError>;
+
+    fn try_from(mut dto: BlockchainDto<T>) -> Result<Self, Self::Error> {
+        let mut block_dtos = dto.take_blocks();
+        // blocks are sent newest first
+        block_dtos.reverse();
+        if block_dtos.is_empty() {
+            return Err(Box::new(ChainValidationError::new(0, "Chain has no genesis block")));
+        }
+        if dto.chain_length() != block_dtos.len() as u64 {
+            return Err(Box::new(ChainValidationError::new(
+                dto.chain_length(), "Chain length does not match block count",
+            )));
+        }
+        let mut last_block: BlockPointer<T> = None;
+        for mut block_dto in block_dtos {
+            let block_number = block_dto.block_number();
+            let key = BlockKey::parse_from_dto(&mut block_dto)?;
+            let data = block_dto.take_data();
+            Blockchain::verify_link(&last_block, block_number, key, &data)?;
+            last_block = Some(Box::new(Block {
+                previous_block: last_block,
+                data,
+                key,
+                time: Some(block_dto.take_time()),
+                block_number,
+            }));
+        }
+        let mut blockchain = Self {
             last_block,
             chain_length: dto.chain_length(),
             uncommitted_data: dto.take_uncommitted_data(),
-            data_units_per_block: dto.max_data_units_per_block(),
+            // the peer's block size is only what it claims, the local genesis decides it
+            data_units_per_block: protocol::BLOCK_SIZE,
             remaining_pool: dto.remaining_pool(),
-        }
+            accounts: AccountIndex::default(),
+            snapshots: SnapshotIndex::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            retention: None,
+        };
+        blockchain.rebuild_accounts();
+        Ok(blockchain)
     }
 }
 
@@ -384,42 +629,70 @@ impl<T> Summary for BlockCandidate<T> where T: BlockchainData {
 
 impl<T> Blockchain<T> where T: BlockchainData {
     fn new(genesis_block: Block<T>, remaining_pool: i64) -> Blockchain<T> {
-        Blockchain {
+        let mut blockchain = Blockchain {
             last_block: Some(Box::new(genesis_block)),
-            chain_length: 0,
+            chain_length: 1,
             uncommitted_data: vec![],
-            data_units_per_block: 30,
+            data_units_per_block: protocol::BLOCK_SIZE,
             remaining_pool,
-        }
+            accounts: AccountIndex::default(),
+            snapshots: SnapshotIndex::default(),
+            events: broadcast::channel(EVENT_CHANNEL_CAPACITY).0,
+            retention: None,
+        };
+        blockchain.rebuild_accounts();
+        blockchain
     }
 
     pub fn transaction_chain(genesis_transactions: Vec<Transaction>) -> Blockchain<Transaction> {
-        let to_mint: i64 = genesis_transactions.iter()
-            .filter(|transaction| transaction.source_address == blockchain::MINTING_WALLET_ADDRESS)
-            .map(|transaction| transaction.amount)
-            .sum();
+        let to_mint = minted(&genesis_transactions);
 
         let genesis_block = Block::new(
             None, genesis_transactions, 0, BlockKey::default(),
         );
 
-        let mut blockchain = Blockchain::new(genesis_block, 21000000);
+        let mut blockchain = Blockchain::new(genesis_block, protocol::TOTAL_SUPPLY);
         blockchain.mint(to_mint);
+        // the size the network's genesis sets, amendments to it are in the upgrade schedule
+        blockchain.data_units_per_block = UPGRADE_SCHEDULE.rules_at(0).block_size();
         blockchain
     }
 
+    pub fn governance_chain() -> Blockchain<GovernanceRecord> {
+        let genesis_block = Block::new(
+            None, vec![], 0, BlockKey::default(),
+        );
+        Blockchain::new(genesis_block, 0)
+    }
+
+    // genesis without data, e.g. for chains kept in a network::chains::ChainRegistry
+    pub fn empty_chain() -> Blockchain<T> {
+        let genesis_block = Block::new(
+            None, vec![], 0, BlockKey::default(),
+        );
+        Blockchain::new(genesis_block, 0)
+    }
+
     pub fn wallet_chain() -> Blockchain<Wallet> {
         let genesis_block = Block::new(
             None, vec![
-                Wallet {
-                    address: blockchain::MINTING_WALLET_ADDRESS,
-                    public_key: None,
-                },
+                Wallet::new(protocol::MINTING_WALLET_ADDRESS, None),
             ], 0, BlockKey::default(),
         );
         Blockchain::new(genesis_block, 0)
     }
 
+    // keeps only the newest resident_blocks blocks in memory, older ones are read back from the store
+    pub fn with_store(mut self, store: BlockStore, resident_blocks: u64) -> Result<Self, Box<dyn BlockchainError>> {
+        self.retention = Some(Retention {
+            store,
+            resident_blocks: resident_blocks.max(1),
+            stored_height: 0,
+        });
+        self.retain()?;
+        Ok(self)
+    }
+
     pub fn last_block(&self) -> &BlockPointer<T> {
         &self.last_block
     }
@@ -436,31 +709,152 @@ impl<T> Blockchain<T> where T: BlockchainData {
         &self.uncommitted_data[..]
     }
 
-    fn remove_uncommitted_data(&mut self) {
-        self.uncommitted_data.drain(..self.data_units_per_block as usize).count();
+    fn remove_uncommitted_data(&mut self, committed: &[T]) {
+        let committed: HashSet<String> = committed.iter()
+            .map(|data| data.summary())
+            .collect();
+        self.uncommitted_data.retain(|data| !committed.contains(&data.summary()));
     }
 
     pub fn add_uncommitted(&mut self, data: T) {
+        self.publish(ChainEvent::DataSubmitted(data.clone()));
         self.uncommitted_data.push(data);
     }
 
+    pub fn discard_uncommitted(&mut self, discarded: &[T]) {
+        self.remove_uncommitted_data(discarded);
+    }
+
+    pub fn notify_incoming(&self, block_number: u64, data: T, committed: bool) {
+        self.publish(ChainEvent::IncomingPayment {
+            block_number,
+            data,
+            committed,
+        });
+    }
+
+    pub fn notify_slashed(&self, address: Address, amount: i64) {
+        self.publish(ChainEvent::Slashed {
+            address,
+            amount,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ChainEvent<T>> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: ChainEvent<T>) {
+        // sending only fails when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    // takes at most what is left in the pool, returns the amount actually minted
     pub fn mint(&mut self, amount: i64) -> i64 {
-        if amount <= self.remaining_pool {
-            self.remaining_pool -= amount;
-            self.remaining_pool
-        } else {
-            0
-        }
+        let minted = self.mintable(amount);
+        self.remaining_pool -= minted;
+        minted
+    }
+
+    pub fn mintable(&self, amount: i64) -> i64 {
+        amount.clamp(0, self.remaining_pool.max(0))
     }
 
     pub fn remaining_pool(&self) -> i64 {
         self.remaining_pool
     }
 
+    pub fn committed_balance(&self, address: Address) -> i64 {
+        self.accounts.balance(address)
+    }
+
+    pub fn committed_balances(&self) -> &HashMap<Address, i64> {
+        &self.accounts.balances
+    }
+
+    // how much data the address sent in committed blocks
+    pub fn committed_sent(&self, address: Address) -> u64 {
+        self.accounts.sent.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn committed_token_balance(&self, token_id: &str, address: Address) -> i64 {
+        self.accounts.token_balance(token_id, address)
+    }
+
+    // every token the address ever held, with its committed balance
+    pub fn committed_tokens(&self, address: Address) -> Vec<(String, i64)> {
+        let mut tokens: Vec<(String, i64)> = self.accounts.tokens.iter()
+            .filter(|((_, holder), _)| *holder == address)
+            .map(|((token_id, _), balance)| (token_id.clone(), *balance))
+            .collect();
+        tokens.sort();
+        tokens
+    }
+
+    // adds the deltas of the blocks since the nearest snapshot, or undoes those above the height
+    // when the tip is closer
+    pub fn committed_balance_at(&self, address: Address, height: u64) -> Result<i64, Box<dyn BlockchainError>> {
+        let change_of = |data: &[T]| data.iter()
+            .flat_map(T::balance_changes)
+            .filter(|(changed, _)| *changed == address)
+            .map(|(_, change)| change)
+            .sum::<i64>();
+        let tip = self.chain_length.saturating_sub(1);
+        if let Some(snapshot) = self.snapshots.at_or_below(height) {
+            if height - snapshot.height() < tip.saturating_sub(height) {
+                let mut balance = snapshot.balance(address);
+                for block in self.blocks_from(snapshot.height() + 1).take((height - snapshot.height()) as usize) {
+                    balance += change_of(&block?.data);
+                }
+                return Ok(balance);
+            }
+        }
+        let mut balance = self.committed_balance(address);
+        for block in self.blocks_from(height + 1) {
+            balance -= change_of(&block?.data);
+        }
+        Ok(balance)
+    }
+
+    // the newest balance snapshot taken at or below the height
+    pub fn snapshot_at(&self, height: u64) -> Option<&BalanceSnapshot> {
+        self.snapshots.at_or_below(height)
+    }
+
+    pub fn latest_snapshot(&self) -> Option<&BalanceSnapshot> {
+        self.snapshots.latest()
+    }
+
+    fn rebuild_accounts(&mut self) {
+        let mut accounts = AccountIndex::default();
+        let mut snapshots = SnapshotIndex::default();
+        for block in self.iter().rev() {
+            accounts.index(&block.data);
+            snapshots.record(block.block_number, &accounts.balances);
+        }
+        self.accounts = accounts;
+        self.snapshots = snapshots;
+    }
+
     fn append_block(&mut self, mut block: Block<T>) -> BlockAdditionResult {
         let block_number = self.chain_length;
         let block_hash = block.key.hash;
         block.block_number = block_number;
+        self.accounts.index(&block.data);
+        if let Some(snapshot) = self.snapshots.record(block_number, &self.accounts.balances) {
+            if let Some(retention) = &self.retention {
+                if let Err(error) = retention.store.write_snapshot(snapshot) {
+                    report!("Snapshot at block {} not stored: {}", block_number, error.message());
+                }
+            }
+        }
+        // validators cap rewards at the pool, so committed blocks never overdraw it
+        self.remaining_pool -= minted(&block.data);
+        self.publish(ChainEvent::BlockAppended {
+            block_number,
+            block_hash: block.key.hash(),
+            data: block.data.clone(),
+        });
         match &mut self.last_block {
             None => {
                 self.last_block = Some(Box::new(block));
@@ -471,6 +865,9 @@ impl<T> Blockchain<T> where T: BlockchainData {
             }
         }
         self.chain_length += 1;
+        if let Err(error) = self.retain() {
+            report!("Keeping block {} in memory: {}", block_number, error.message());
+        }
         BlockAdditionResult {
             block_number,
             block_hash,
@@ -481,7 +878,340 @@ impl<T> Blockchain<T> where T: BlockchainData {
         &mut self, block_candidate: BlockCandidate<T>,
     ) -> BlockAdditionResult {
         let block = Block::from(block_candidate);
-        self.remove_uncommitted_data();
+        self.remove_uncommitted_data(&block.data);
         self.append_block(block)
     }
-}
\ No newline at end of file
+
+    // adopts another chain, reporting the blocks of this one it rolled back
+    pub fn replace(&mut self, other: Blockchain<T>) -> Rollback<T> {
+        let known_hashes = other.block_hashes();
+        let mut depth = 0;
+        let mut rolled_back = vec![];
+        for block in self.iter().take_while(|block| !known_hashes.contains(&block.key.hash)) {
+            depth += 1;
+            rolled_back.extend(block.data.iter().cloned());
+        }
+        let fork_height = self.chain_length - depth;
+        let local_hashes = self.block_hashes();
+
+        self.last_block = other.last_block;
+        self.chain_length = other.chain_length;
+        self.uncommitted_data = other.uncommitted_data;
+        self.remaining_pool = other.remaining_pool;
+        self.accounts = other.accounts;
+        self.snapshots = other.snapshots;
+        // the adopted chain is fully in memory, its blocks above the fork replace the stored ones
+        if let Some(retention) = &mut self.retention {
+            retention.stored_height = retention.stored_height.min(fork_height);
+            for snapshot in self.snapshots.above(fork_height) {
+                if let Err(error) = retention.store.write_snapshot(snapshot) {
+                    report!("Snapshot at block {} not stored: {}", snapshot.height(), error.message());
+                }
+            }
+        }
+        if let Err(error) = self.retain() {
+            report!("Keeping adopted blocks in memory: {}", error.message());
+        }
+
+        let appended: Vec<ChainEvent<T>> = self.iter()
+            .take_while(|block| !local_hashes.contains(&block.key.hash))
+            .map(|block| ChainEvent::BlockAppended {
+                block_number: block.block_number,
+                block_hash: block.key.hash(),
+                data: block.data.clone(),
+            })
+            .collect();
+        // data the new chain carries as well stays confirmed, just in another block
+        let recommitted: HashSet<String> = appended.iter()
+            .flat_map(|event| match event {
+                ChainEvent::BlockAppended { data, .. } => data.iter().map(T::summary).collect(),
+                _ => vec![]
+            })
+            .collect();
+        rolled_back.retain(|data| !recommitted.contains(&data.summary()));
+        if depth > 0 {
+            let new_tip_hash = match &self.last_block {
+                None => String::new(),
+                Some(block) => block.key.hash()
+            };
+            self.publish(ChainEvent::Reorg {
+                depth,
+                new_tip_hash,
+                affected_txs: rolled_back.clone(),
+            });
+        }
+        for event in appended.into_iter().rev() {
+            self.publish(event);
+        }
+        Rollback {
+            depth,
+            fork_height,
+            affected: rolled_back,
+        }
+    }
+
+    fn verify_link(
+        previous_block: &BlockPointer<T>, block_number: u64, key: BlockKey, data: &[T],
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let error = match previous_block {
+            None => {
+                if block_number != 0 || key.previous_hash.is_some() {
+                    Some("Genesis block must be block 0 without ancestor")
+                } else {
+                    None
+                }
+            }
+            Some(previous_block) => {
+                let expected = BlockCandidate::<T>::hash(
+                    previous_block.key.hash, BlockCandidate::summarize(data), key.state_root,
+                );
+                if block_number != previous_block.block_number + 1 {
+                    Some("Block number does not follow its predecessor")
+                } else if key.previous_hash != Some(previous_block.key.hash) {
+                    Some("Broken link to previous block")
+                } else if key.hash != expected.hash {
+                    Some("Hash does not match block content")
+                } else {
+                    None
+                }
+            }
+        };
+        match error {
+            None => Ok(()),
+            Some(message) => Err(Box::new(ChainValidationError::new(block_number, message)))
+        }
+    }
+
+    pub fn block_at(&self, block_number: u64) -> Result<Option<ChainBlock<'_, T>>, Box<dyn BlockchainError>> {
+        if block_number >= self.chain_length {
+            return Ok(None);
+        }
+        self.blocks_from(block_number).next().transpose()
+    }
+
+    // blocks held in memory, newest first, the whole chain unless it is bounded; blocks() also
+    // reads those on disk
+    pub fn iter(&self) -> ChainIter<'_, T> {
+        ChainIter {
+            next: self.last_block.as_deref(),
+            collected: None,
+        }
+    }
+
+    // data of the blocks held in memory, newest first, reversed it is in chain order
+    pub fn iter_transactions(&self) -> impl DoubleEndedIterator<Item=&T> {
+        self.iter().flat_map(|block| block.data.iter().rev())
+    }
+
+    // blocks held in memory, oldest first
+    pub fn resident_blocks(&self) -> Vec<&Block<T>> {
+        self.iter().rev().collect()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item=Result<ChainBlock<'_, T>, Box<dyn BlockchainError>>> {
+        self.blocks_from(0)
+    }
+
+    // blocks from the given number up to the tip, those below the resident ones are read lazily
+    pub fn blocks_from(&self, first: u64) -> impl Iterator<Item=Result<ChainBlock<'_, T>, Box<dyn BlockchainError>>> {
+        let resident = self.resident_blocks();
+        let oldest_resident = resident.first().map_or(self.chain_length, |block| block.block_number);
+        let store = self.retention.as_ref().map(|retention| &retention.store);
+        let stored = (first..oldest_resident).map(move |block_number| match store {
+            Some(store) => store.read(block_number).map(|block| ChainBlock::Loaded(Box::new(block))),
+            None => {
+                let error = StorageError::new(&format!("Block {} is not in memory", block_number));
+                Err(Box::new(error) as Box<dyn BlockchainError>)
+            }
+        });
+        let resident = resident.into_iter()
+            .filter(move |block| block.block_number >= first)
+            .map(|block| Ok(ChainBlock::Resident(block)));
+        stored.chain(resident)
+    }
+
+    // writes blocks not stored yet, then drops those below the resident window from memory
+    fn retain(&mut self) -> Result<(), Box<dyn BlockchainError>> {
+        let retention = match &mut self.retention {
+            None => return Ok(()),
+            Some(retention) => retention
+        };
+        let mut unstored = vec![];
+        let mut current_block = &self.last_block;
+        while let Some(block) = current_block {
+            if block.block_number < retention.stored_height {
+                break;
+            }
+            unstored.push(block.as_ref());
+            current_block = &block.previous_block;
+        }
+        for block in unstored.into_iter().rev() {
+            retention.store.write(block)?;
+            retention.stored_height = block.block_number + 1;
+        }
+
+        let mut kept = 1;
+        let mut current_block = &mut self.last_block;
+        while let Some(block) = current_block {
+            if kept == retention.resident_blocks {
+                if block.block_number <= retention.stored_height {
+                    block.previous_block = None;
+                }
+                break;
+            }
+            kept += 1;
+            current_block = &mut block.previous_block;
+        }
+        Ok(())
+    }
+
+    // replays the chain from genesis, validating every block against the state preceding it
+    pub fn verify_full<F>(&self, validate: F) -> Result<(), Box<dyn BlockchainError>>
+        where F: Fn(&Blockchain<T>, &BlockCandidate<T>) -> Result<(), Box<dyn BlockchainError>> {
+        let mut blocks = self.blocks();
+        let genesis = match blocks.next() {
+            None => return Err(Box::new(ChainValidationError::new(0, "Chain has no genesis block"))),
+            Some(genesis) => genesis?
+        };
+        Blockchain::verify_link(&None, genesis.block_number, genesis.key, &genesis.data)?;
+        // the pool as it was right after genesis, before any block reward
+        let minted_after_genesis = -self.committed_balance(protocol::MINTING_WALLET_ADDRESS) - minted(&genesis.data);
+        let mut replayed = Blockchain::new(
+            Block::new(None, genesis.data.clone(), 0, genesis.key), self.remaining_pool + minted_after_genesis,
+        );
+        for block in blocks {
+            let block = block?;
+            Blockchain::verify_link(&replayed.last_block, block.block_number, block.key, &block.data)?;
+            let block_candidate = BlockCandidate {
+                key: block.key,
+                block_number: block.block_number,
+                data: block.data.clone(),
+                time: block.time.unwrap_or_default(),
+            };
+            if let Err(error) = validate(&replayed, &block_candidate) {
+                return Err(Box::new(ChainValidationError::new(block.block_number, &error.message())));
+            }
+            replayed.submit_new_block(block_candidate);
+        }
+        Ok(())
+    }
+
+    // median commit time of the last MEDIAN_TIME_SPAN blocks, none while no block has a time
+    pub fn median_time_past(&self) -> Option<DateTime<Utc>> {
+        let mut times: Vec<DateTime<Utc>> = self.iter()
+            .filter_map(|block| block.time)
+            .take(MEDIAN_TIME_SPAN)
+            .collect();
+        times.sort();
+        times.get(times.len() / 2).copied()
+    }
+
+    pub fn finalized_height(&self, max_reorg_depth: u64) -> u64 {
+        self.chain_length.saturating_sub(max_reorg_depth)
+    }
+
+    // number of leading blocks both chains share
+    pub fn common_height(&self, other: &Blockchain<T>) -> u64 {
+        let known_hashes = other.block_hashes();
+        self.iter()
+            .find(|block| known_hashes.contains(&block.key.hash))
+            .map_or(0, |block| block.block_number + 1)
+    }
+
+    // a competing chain may only roll back blocks above the finalized height, however long it is
+    pub fn reorg_allowed(&self, other: &Blockchain<T>, max_reorg_depth: u64) -> Result<(), Box<dyn BlockchainError>> {
+        let common_height = self.common_height(other);
+        let finalized_height = self.finalized_height(max_reorg_depth);
+        if common_height < finalized_height {
+            return Err(Box::new(ChainValidationError::new(common_height, &format!(
+                "diverges below finalized height {}", finalized_height
+            ))));
+        }
+        Ok(())
+    }
+
+    fn block_hashes(&self) -> HashSet<BlockHash> {
+        self.iter().map(|block| block.key.hash).collect()
+    }
+}
+
+// coins taken from the minting pool by the data of one block
+fn minted<T>(data: &[T]) -> i64 where T: BlockchainData {
+    data.iter()
+        .flat_map(T::balance_changes)
+        .filter(|(address, _)| *address == protocol::MINTING_WALLET_ADDRESS)
+        .map(|(_, change)| -change)
+        .sum()
+}
+
+// committed coin and token balances, updated with every appended block
+#[derive(Default)]
+struct AccountIndex {
+    balances: HashMap<Address, i64>,
+    tokens: HashMap<(String, Address), i64>,
+    sent: HashMap<Address, u64>,
+}
+
+impl AccountIndex {
+    fn index<T>(&mut self, data: &[T]) where T: BlockchainData {
+        for (address, change) in data.iter().flat_map(T::balance_changes) {
+            *self.balances.entry(address).or_insert(0) += change;
+        }
+        // validators refuse overflowing token changes, a block that got past them anyway
+        // leaves the balance as it was
+        for (token_id, address, change) in data.iter().flat_map(T::token_changes) {
+            let balance = self.tokens.entry((token_id, address)).or_insert(0);
+            match balance.checked_add(change) {
+                Some(updated) => *balance = updated,
+                None => report!("Token balance overflow, change of {} ignored", change),
+            }
+        }
+        for sender in data.iter().filter_map(T::sender) {
+            *self.sent.entry(sender).or_insert(0) += 1;
+        }
+    }
+
+    fn balance(&self, address: Address) -> i64 {
+        self.balances.get(&address).copied().unwrap_or(0)
+    }
+
+    fn token_balance(&self, token_id: &str, address: Address) -> i64 {
+        self.tokens.get(&(token_id.to_string(), address)).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+
+    #[test]
+    fn chains_iterate_from_either_end() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        for amount in 1..=3 {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], "".to_string(), amount, Utc::now()),
+                Transaction::new([1; 32], [3; 32], "".to_string(), amount * 10, Utc::now()),
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+
+        let newest_first: Vec<u64> = transactions.iter().map(|block| block.block_number()).collect();
+        assert_eq!(newest_first, vec![3, 2, 1, 0]);
+        let oldest_first: Vec<u64> = transactions.iter().rev().map(|block| block.block_number()).collect();
+        assert_eq!(oldest_first, vec![0, 1, 2, 3]);
+        let mut both_ends = transactions.iter();
+        assert_eq!(both_ends.next().map(|block| block.block_number()), Some(3));
+        assert_eq!(both_ends.next_back().map(|block| block.block_number()), Some(0));
+        assert_eq!(both_ends.map(|block| block.block_number()).collect::<Vec<u64>>(), vec![2, 1]);
+
+        let amounts: Vec<i64> = transactions.iter_transactions().rev().map(|transaction| transaction.amount()).collect();
+        assert_eq!(amounts, vec![100, 1, 10, 2, 20, 3, 30]);
+        assert_eq!(transactions.iter_transactions().next().map(|transaction| transaction.amount()), Some(30));
+    }
+}
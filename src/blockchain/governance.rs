@@ -0,0 +1,592 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rsa::rand_core::CryptoRngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::{Address, BlockchainData, find_wallet_by_address, Transaction, Wallet};
+use crate::blockchain::protocol;
+use crate::blockchain::access;
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, StorageError, Summary};
+use crate::blockchain::signer::Signer;
+use crate::blockchain::upgrade::UpgradeSchedule;
+
+// proposals must leave the network at least this many blocks to vote
+pub static MIN_VOTING_PERIOD: u64 = 10;
+pub static GOVERNANCE_FILE: &str = "governance.json";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    BlockReward(i64),
+    TransferFee(i64),
+    BlockSize(u64),
+    WalletGrant(i64),
+}
+
+impl Parameter {
+    pub fn parse(name: &str, value: &str) -> Result<Parameter, Box<dyn BlockchainError>> {
+        let invalid_value = || -> Box<dyn BlockchainError> {
+            Box::new(GovernanceError::new("Parameter value must be a positive integer"))
+        };
+        let change = match name {
+            "reward" => value.parse().map(Parameter::BlockReward).map_err(|_| invalid_value()),
+            "fee" => value.parse().map(Parameter::TransferFee).map_err(|_| invalid_value()),
+            "block-size" => value.parse().map(Parameter::BlockSize).map_err(|_| invalid_value()),
+            "grant" => value.parse().map(Parameter::WalletGrant).map_err(|_| invalid_value()),
+            _ => Err(Box::new(GovernanceError::new(
+                "Parameter must be one of reward, fee, block-size, grant"
+            )) as Box<dyn BlockchainError>)
+        }?;
+        match change.valid() {
+            true => Ok(change),
+            false => Err(invalid_value())
+        }
+    }
+
+    // checked again for proposals from peers, which never went through parse
+    pub fn valid(&self) -> bool {
+        match *self {
+            Parameter::BlockReward(reward) => reward > 0,
+            Parameter::TransferFee(fee) => fee > 0,
+            Parameter::BlockSize(size) => size > 0,
+            // zero stops grants from the activation height on
+            Parameter::WalletGrant(grant) => grant >= 0,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Parameter::BlockReward(reward) => format!("block reward = {}", reward),
+            Parameter::TransferFee(fee) => format!("transfer fee = {}", fee),
+            Parameter::BlockSize(size) => format!("block size = {}", size),
+            Parameter::WalletGrant(grant) => format!("wallet grant = {}", grant),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Proposal {
+    proposer: Address,
+    change: Parameter,
+    activation_height: u64,
+    time: DateTime<Utc>,
+    signature: Option<String>,
+}
+
+impl Proposal {
+    pub fn new(proposer: Address, change: Parameter, activation_height: u64) -> Proposal {
+        Proposal {
+            proposer,
+            change,
+            activation_height,
+            time: Utc::now(),
+            signature: None,
+        }
+    }
+
+    // the signature is left out, so the id stays the same once the proposal is signed
+    pub fn id(&self) -> String {
+        let digest = Sha256::digest(self.signed_content().as_bytes());
+        array_bytes::bytes2hex("", &digest[..8])
+    }
+
+    pub fn proposer(&self) -> Address {
+        self.proposer
+    }
+    pub fn change(&self) -> Parameter {
+        self.change
+    }
+    pub fn activation_height(&self) -> u64 {
+        self.activation_height
+    }
+
+    // votes weigh the stake committed at this height, fixed and final before voting closes
+    pub fn snapshot_height(&self) -> u64 {
+        self.activation_height.saturating_sub(MIN_VOTING_PERIOD)
+    }
+
+    pub fn sign(
+        &mut self, signer: &dyn Signer, rng: &mut dyn CryptoRngCore,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        self.signature = Some(signer.sign_message(&self.signed_content(), rng)?);
+        Ok(())
+    }
+
+    fn signed_content(&self) -> String {
+        format!(
+            "proposal:{}:{}{}{}{}",
+            protocol::chain_id(), array_bytes::bytes2hex("", self.proposer), self.change.describe(),
+            self.activation_height, self.time.to_rfc3339()
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct GovernanceVote {
+    proposal_id: String,
+    voter: Address,
+    approve: bool,
+    signature: Option<String>,
+}
+
+impl GovernanceVote {
+    pub fn new(proposal_id: String, voter: Address, approve: bool) -> GovernanceVote {
+        GovernanceVote {
+            proposal_id,
+            voter,
+            approve,
+            signature: None,
+        }
+    }
+
+    pub fn proposal_id(&self) -> &str {
+        &self.proposal_id
+    }
+    pub fn voter(&self) -> Address {
+        self.voter
+    }
+    pub fn approve(&self) -> bool {
+        self.approve
+    }
+
+    pub fn sign(
+        &mut self, signer: &dyn Signer, rng: &mut dyn CryptoRngCore,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        self.signature = Some(signer.sign_message(&self.signed_content(), rng)?);
+        Ok(())
+    }
+
+    fn signed_content(&self) -> String {
+        format!(
+            "vote:{}:{}{}{}",
+            protocol::chain_id(), self.proposal_id, array_bytes::bytes2hex("", self.voter), self.approve
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub enum GovernanceRecord {
+    Proposal(Proposal),
+    Vote(GovernanceVote),
+}
+
+impl Summary for GovernanceRecord {
+    fn summary(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl BlockchainData for GovernanceRecord {}
+
+pub struct GovernanceError {
+    message: String,
+}
+
+impl GovernanceError {
+    pub fn new(message: &str) -> GovernanceError {
+        GovernanceError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for GovernanceError {
+    fn message(&self) -> String {
+        format!("Governance: {}", self.message)
+    }
+}
+
+pub struct Tally {
+    approving_stake: i64,
+    rejecting_stake: i64,
+}
+
+impl Tally {
+    pub fn approving_stake(&self) -> i64 {
+        self.approving_stake
+    }
+
+    pub fn rejecting_stake(&self) -> i64 {
+        self.rejecting_stake
+    }
+
+    pub fn approved(&self) -> bool {
+        self.approving_stake > self.rejecting_stake
+    }
+}
+
+// Proposals and votes as this node learned them, merged with peers on sync. A proposal is
+// settled once the transaction chain reaches its activation height, from then on its outcome
+// is frozen and late votes are refused, so blocks validated under it are never judged again
+// under different rules. Governance loaded from a file writes every change back to it.
+pub struct Governance {
+    records: Blockchain<GovernanceRecord>,
+    settled: BTreeMap<String, bool>,
+    settled_height: u64,
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct GovernanceFile {
+    records: Vec<GovernanceRecord>,
+    settled: BTreeMap<String, bool>,
+    settled_height: u64,
+}
+
+impl Default for Governance {
+    fn default() -> Self {
+        Governance::new()
+    }
+}
+
+impl Governance {
+    pub fn new() -> Governance {
+        Governance {
+            records: Blockchain::<GovernanceRecord>::governance_chain(),
+            settled: BTreeMap::new(),
+            settled_height: 0,
+            path: None,
+        }
+    }
+
+    // records in the file were checked when they were first submitted
+    pub fn load(path: &Path) -> Governance {
+        let file: GovernanceFile = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => GovernanceFile::default()
+        };
+        let mut governance = Governance {
+            settled: file.settled,
+            settled_height: file.settled_height,
+            path: Some(path.to_path_buf()),
+            ..Governance::new()
+        };
+        for record in file.records {
+            governance.append(record).ok();
+        }
+        governance
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let file = GovernanceFile {
+            records: self.records.iter_transactions().rev().cloned().collect(),
+            settled: self.settled.clone(),
+            settled_height: self.settled_height,
+        };
+        match fs::write(path, serde_json::to_string_pretty(&file).unwrap()) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn records(&self) -> &Blockchain<GovernanceRecord> {
+        &self.records
+    }
+
+    pub fn proposals(&self) -> Vec<&Proposal> {
+        self.all_records().into_iter()
+            .filter_map(|record| match record {
+                GovernanceRecord::Proposal(proposal) => Some(proposal),
+                GovernanceRecord::Vote(_) => None
+            })
+            .collect()
+    }
+
+    pub fn proposal(&self, proposal_id: &str) -> Option<&Proposal> {
+        self.proposals().into_iter()
+            .find(|proposal| proposal.id() == proposal_id)
+    }
+
+    pub fn votes(&self, proposal_id: &str) -> Vec<&GovernanceVote> {
+        self.all_records().into_iter()
+            .filter_map(|record| match record {
+                GovernanceRecord::Vote(vote) if vote.proposal_id == proposal_id => Some(vote),
+                _ => None
+            })
+            .collect()
+    }
+
+    pub fn submit_proposal(
+        &mut self, proposal: Proposal, wallets: &Blockchain<Wallet>, chain_height: u64,
+    ) -> Result<String, Box<dyn BlockchainError>> {
+        if proposal.activation_height < chain_height + MIN_VOTING_PERIOD {
+            return Err(Box::new(GovernanceError::new(&format!(
+                "Activation height must be at least {}", chain_height + MIN_VOTING_PERIOD
+            ))));
+        }
+        self.accept_proposal(proposal, wallets)
+    }
+
+    pub fn submit_vote(
+        &mut self, vote: GovernanceVote, wallets: &Blockchain<Wallet>,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let activation_height = match self.proposal(&vote.proposal_id) {
+            None => return Err(Box::new(GovernanceError::new("Unknown proposal"))),
+            Some(proposal) => proposal.activation_height
+        };
+        if activation_height <= self.settled_height {
+            return Err(Box::new(GovernanceError::new("Voting on this proposal has closed")));
+        }
+        if self.votes(&vote.proposal_id).iter().any(|cast| cast.voter == vote.voter) {
+            return Err(Box::new(GovernanceError::new("Voter has already voted")));
+        }
+        if !signed_by(vote.voter, &vote.signed_content(), &vote.signature, wallets) {
+            return Err(Box::new(GovernanceError::new("Invalid vote signature")));
+        }
+        self.append(GovernanceRecord::Vote(vote))
+    }
+
+    // records a peer knows and this node missed, checked like submissions but without the
+    // voting period, which only applies when a proposal is first made; returns how many were new
+    pub fn merge(&mut self, records: Vec<GovernanceRecord>, wallets: &Blockchain<Wallet>) -> usize {
+        let mut merged = 0;
+        let (proposals, votes): (Vec<GovernanceRecord>, Vec<GovernanceRecord>) = records.into_iter()
+            .partition(|record| matches!(record, GovernanceRecord::Proposal(_)));
+        for record in proposals.into_iter().chain(votes) {
+            let accepted = match record {
+                GovernanceRecord::Proposal(proposal) => match self.proposal(&proposal.id()) {
+                    Some(_) => false,
+                    None => self.accept_proposal(proposal, wallets).is_ok(),
+                },
+                GovernanceRecord::Vote(vote) => self.submit_vote(vote, wallets).is_ok(),
+            };
+            if accepted {
+                merged += 1;
+            }
+        }
+        merged
+    }
+
+    // votes weigh the voter's committed balance at the proposal's snapshot height, the
+    // mempool and anything moved after the snapshot are left out
+    pub fn tally(&self, proposal_id: &str, transactions: &Blockchain<Transaction>) -> Tally {
+        let mut tally = Tally {
+            approving_stake: 0,
+            rejecting_stake: 0,
+        };
+        let snapshot_height = match self.proposal(proposal_id) {
+            None => return tally,
+            Some(proposal) => proposal.snapshot_height()
+        };
+        for vote in self.votes(proposal_id) {
+            let stake = transactions.committed_balance_at(vote.voter, snapshot_height).unwrap_or(0).max(0);
+            if vote.approve {
+                tally.approving_stake += stake;
+            } else {
+                tally.rejecting_stake += stake;
+            }
+        }
+        tally
+    }
+
+    // freezes the outcome of every proposal the chain has reached the activation height of,
+    // returns whether any was frozen
+    pub fn settle(&mut self, transactions: &Blockchain<Transaction>) -> bool {
+        let chain_height = transactions.chain_length();
+        if chain_height <= self.settled_height {
+            return false;
+        }
+        let outcomes: Vec<(String, bool)> = self.proposals().into_iter()
+            .filter(|proposal| proposal.activation_height <= chain_height)
+            .filter(|proposal| !self.settled.contains_key(&proposal.id()))
+            .map(|proposal| (proposal.id(), self.tally(&proposal.id(), transactions).approved()))
+            .collect();
+        let frozen = !outcomes.is_empty();
+        self.settled.extend(outcomes);
+        self.settled_height = chain_height;
+        frozen
+    }
+
+    // settled outcomes as frozen, proposals the chain reached since the last settle as
+    // tallied now from the final snapshot, and nothing of proposals still open
+    pub fn schedule(
+        &self, upgrades: &UpgradeSchedule, transactions: &Blockchain<Transaction>,
+    ) -> UpgradeSchedule {
+        let chain_height = transactions.chain_length();
+        let mut amendments: Vec<(u64, Parameter)> = self.proposals().into_iter()
+            .filter(|proposal| match self.settled.get(&proposal.id()) {
+                Some(approved) => *approved,
+                None => proposal.activation_height <= chain_height
+                    && proposal.activation_height > self.settled_height
+                    && self.tally(&proposal.id(), transactions).approved(),
+            })
+            .map(|proposal| (proposal.activation_height, proposal.change))
+            .collect();
+        amendments.sort_by_key(|(activation_height, _)| *activation_height);
+        upgrades.amended(&amendments)
+    }
+
+    fn accept_proposal(
+        &mut self, proposal: Proposal, wallets: &Blockchain<Wallet>,
+    ) -> Result<String, Box<dyn BlockchainError>> {
+        let proposal_id = proposal.id();
+        if self.proposal(&proposal_id).is_some() {
+            return Err(Box::new(GovernanceError::new("Proposal already submitted")));
+        }
+        if proposal.activation_height <= self.settled_height {
+            return Err(Box::new(GovernanceError::new("Proposal activates below a settled height")));
+        }
+        if !proposal.change.valid() {
+            return Err(Box::new(GovernanceError::new("Parameter value out of range")));
+        }
+        if !signed_by(proposal.proposer, &proposal.signed_content(), &proposal.signature, wallets) {
+            return Err(Box::new(GovernanceError::new("Invalid proposal signature")));
+        }
+        self.append(GovernanceRecord::Proposal(proposal))?;
+        Ok(proposal_id)
+    }
+
+    fn append(&mut self, record: GovernanceRecord) -> Result<(), Box<dyn BlockchainError>> {
+        let block_candidate = BlockCandidate::create_new(vec![record], self.records.last_block())?;
+        self.records.submit_new_block(block_candidate);
+        Ok(())
+    }
+
+    fn all_records(&self) -> Vec<&GovernanceRecord> {
+        self.records.iter_transactions().rev().collect()
+    }
+}
+
+fn signed_by(signer: Address, content: &str, signature: &Option<String>, wallets: &Blockchain<Wallet>) -> bool {
+    let public_key = match find_wallet_by_address(signer, wallets) {
+        Some(wallet) => wallet.key().clone(),
+        None => None
+    };
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => access::verify_message(&public_key, content, signature),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use chrono::Utc;
+
+    use crate::blockchain::{Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::governance::{Governance, GovernanceVote, Parameter, Proposal};
+    use crate::blockchain::upgrade::UPGRADE_SCHEDULE;
+    use crate::random;
+
+    fn extend_to(transactions: &mut Blockchain<Transaction>, height: u64) {
+        while transactions.chain_length() < height {
+            let block = BlockCandidate::create_new(vec![], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+    }
+
+    #[test]
+    fn stake_weighted_approval_amends_schedule() {
+        let mut rng = random::seeded(1);
+        let whale = HotWallet::generate(&mut rng);
+        let minnow = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = BlockCandidate::create_new(
+            vec![whale.wallet().clone(), minnow.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registered);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, whale.address(), "".to_string(), 100, Utc::now()),
+            Transaction::new(MINTING_WALLET_ADDRESS, minnow.address(), "".to_string(), 10, Utc::now()),
+        ]);
+
+        let mut governance = Governance::new();
+        let mut proposal = Proposal::new(minnow.address(), Parameter::TransferFee(7), 20);
+        assert!(governance.submit_proposal(proposal.clone(), &wallets, 1).is_err());
+        proposal.sign(&minnow, &mut rng).ok();
+        assert!(governance.submit_proposal(proposal.clone(), &wallets, 15).is_err());
+        let proposal_id = governance.submit_proposal(proposal, &wallets, 1).ok().unwrap();
+
+        let mut forged = GovernanceVote::new(proposal_id.clone(), whale.address(), false);
+        forged.sign(&minnow, &mut rng).ok();
+        assert!(governance.submit_vote(forged, &wallets).is_err());
+
+        for (voter, approve) in [(&whale, true), (&minnow, false)] {
+            let mut vote = GovernanceVote::new(proposal_id.clone(), voter.address(), approve);
+            vote.sign(voter, &mut rng).ok();
+            assert!(governance.submit_vote(vote, &wallets).is_ok());
+        }
+
+        let tally = governance.tally(&proposal_id, &transactions);
+        assert_eq!((tally.approving_stake(), tally.rejecting_stake()), (100, 10));
+        // nothing applies before the chain reaches the activation height
+        assert_eq!(governance.schedule(&UPGRADE_SCHEDULE, &transactions).rules_at(20).transfer_fee(), 1);
+        extend_to(&mut transactions, 20);
+        let schedule = governance.schedule(&UPGRADE_SCHEDULE, &transactions);
+        assert_eq!(schedule.rules_at(19).transfer_fee(), 1);
+        assert_eq!(schedule.rules_at(20).transfer_fee(), 7);
+    }
+
+    #[test]
+    fn negative_rewards_and_fees_are_rejected() {
+        assert!(Parameter::parse("reward", "-5").is_err());
+        assert!(Parameter::parse("fee", "0").is_err());
+        assert!(Parameter::parse("grant", "0").is_ok());
+        assert!(!Parameter::TransferFee(-1).valid());
+
+        let mut rng = random::seeded(2);
+        let proposer = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = BlockCandidate::create_new(vec![proposer.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registered);
+        let mut proposal = Proposal::new(proposer.address(), Parameter::BlockReward(-100), 20);
+        proposal.sign(&proposer, &mut rng).ok();
+        assert!(Governance::new().submit_proposal(proposal, &wallets, 1).is_err());
+    }
+
+    #[test]
+    fn settled_outcomes_survive_late_votes_sync_and_restart() {
+        let mut rng = random::seeded(3);
+        let whale = HotWallet::generate(&mut rng);
+        let minnow = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registered = BlockCandidate::create_new(
+            vec![whale.wallet().clone(), minnow.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registered);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, whale.address(), "".to_string(), 100, Utc::now()),
+            Transaction::new(MINTING_WALLET_ADDRESS, minnow.address(), "".to_string(), 10, Utc::now()),
+        ]);
+        let path = env::temp_dir().join(format!("kingcoin-governance-{}.json", std::process::id()));
+        fs::remove_file(&path).ok();
+
+        let mut governance = Governance::load(&path);
+        let mut proposal = Proposal::new(minnow.address(), Parameter::TransferFee(7), 20);
+        proposal.sign(&minnow, &mut rng).ok();
+        let proposal_id = governance.submit_proposal(proposal, &wallets, 1).ok().unwrap();
+        let mut approval = GovernanceVote::new(proposal_id.clone(), minnow.address(), true);
+        approval.sign(&minnow, &mut rng).ok();
+        assert!(governance.submit_vote(approval, &wallets).is_ok());
+
+        // a peer that missed both records picks them up on sync
+        let mut peer = Governance::new();
+        let records = governance.records().iter_transactions().rev().cloned().collect::<Vec<_>>();
+        assert_eq!(peer.merge(records.clone(), &wallets), 2);
+        assert_eq!(peer.merge(records, &wallets), 0);
+
+        extend_to(&mut transactions, 20);
+        assert!(governance.settle(&transactions));
+        assert!(governance.save().is_ok());
+        let mut rejection = GovernanceVote::new(proposal_id, whale.address(), false);
+        rejection.sign(&whale, &mut rng).ok();
+        assert!(governance.submit_vote(rejection, &wallets).is_err());
+
+        let reloaded = Governance::load(&path);
+        assert_eq!(reloaded.proposals().len(), 1);
+        assert_eq!(reloaded.schedule(&UPGRADE_SCHEDULE, &transactions).rules_at(20).transfer_fee(), 7);
+        fs::remove_file(&path).ok();
+    }
+}
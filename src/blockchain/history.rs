@@ -0,0 +1,446 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use rsa::RsaPrivateKey;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{Address, Transaction};
+use crate::blockchain::protocol::REWARD_WALLET_ADDRESS;
+use crate::blockchain::access;
+use crate::blockchain::core::{Blockchain, BlockchainError};
+use crate::blockchain::memo::MemoKeys;
+use crate::display::DisplayConfig;
+
+// entries list shows per page unless told otherwise
+pub static DEFAULT_PAGE_SIZE: usize = 20;
+
+pub struct HistoryEntry {
+    block_number: u64,
+    // commit time of the block, the genesis block has none
+    time: Option<DateTime<Utc>>,
+    transaction: Transaction,
+}
+
+impl HistoryEntry {
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        self.time
+    }
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+}
+
+// Committed transactions of one wallet, oldest first, narrowed down by block time.
+pub struct TransactionHistory {
+    address: Address,
+    entries: Vec<HistoryEntry>,
+}
+
+impl TransactionHistory {
+    pub fn of(transactions: &Blockchain<Transaction>, address: Address) -> Result<TransactionHistory, Box<dyn BlockchainError>> {
+        let mut entries = vec![];
+        for block in transactions.blocks() {
+            let block = block?;
+            entries.extend(block.data().iter()
+                .filter(|transaction| transaction.source_address() == address || transaction.target_address() == address)
+                .map(|transaction| HistoryEntry {
+                    block_number: block.block_number(),
+                    time: block.time(),
+                    transaction: transaction.clone(),
+                }));
+        }
+        Ok(TransactionHistory {
+            address,
+            entries,
+        })
+    }
+
+    // entries committed at or after from and before to
+    pub fn between(mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> TransactionHistory {
+        self.entries.retain(|entry| entry.time.is_some_and(|time| from <= time && time < to));
+        self
+    }
+
+    pub fn filter(mut self, filter: &HistoryFilter) -> TransactionHistory {
+        let address = self.address;
+        self.entries.retain(|entry| filter.matches(address, &entry.transaction, entry.time));
+        self
+    }
+
+    // pages are counted from the newest entries, each one in chain order
+    pub fn page(&self, page: Page) -> &[HistoryEntry] {
+        let skipped = page.size.saturating_mul(page.number - 1);
+        let end = self.entries.len().saturating_sub(skipped);
+        &self.entries[end.saturating_sub(page.size)..end]
+    }
+
+    pub fn page_count(&self, page_size: usize) -> usize {
+        self.entries.len().div_ceil(page_size.max(1))
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    // learned from every entry, a filtered page may miss the memo that carried a secret
+    pub fn memo_keys<'a>(&self, private_key: Option<&'a RsaPrivateKey>) -> MemoKeys<'a> {
+        let mut keys = MemoKeys::new(self.address, private_key);
+        for entry in &self.entries {
+            keys.learn(entry.transaction());
+        }
+        keys
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+}
+
+// transactions of the wallet still waiting in the mempool, matched by the time they were signed
+pub fn pending(transactions: &Blockchain<Transaction>, address: Address, filter: &HistoryFilter) -> Vec<Transaction> {
+    transactions.uncommitted_data()
+        .iter()
+        .filter(|transaction| transaction.source_address() == address || transaction.target_address() == address)
+        .filter(|transaction| filter.matches(address, transaction, Some(transaction.time())))
+        .cloned()
+        .collect()
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Flow {
+    In,
+    // transfers and fees paid
+    Out,
+}
+
+// What a listing narrows the history down to, unset criteria let every entry through. Thin
+// clients send it along with their history request.
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct HistoryFilter {
+    since: Option<DateTime<Utc>>,
+    flow: Option<Flow>,
+    min_amount: Option<i64>,
+    counterparty: Option<Address>,
+}
+
+impl HistoryFilter {
+    pub fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn with_flow(mut self, flow: Flow) -> Self {
+        self.flow = Some(flow);
+        self
+    }
+
+    pub fn with_min_amount(mut self, min_amount: i64) -> Self {
+        self.min_amount = Some(min_amount);
+        self
+    }
+
+    pub fn with_counterparty(mut self, counterparty: Address) -> Self {
+        self.counterparty = Some(counterparty);
+        self
+    }
+
+    fn matches(&self, address: Address, transaction: &Transaction, time: Option<DateTime<Utc>>) -> bool {
+        let (flow, counterparty) = match transaction.source_address() == address {
+            true => (Flow::Out, transaction.target_address()),
+            false => (Flow::In, transaction.source_address()),
+        };
+        self.since.is_none_or(|since| time.is_some_and(|time| time >= since))
+            && self.flow.is_none_or(|wanted| wanted == flow)
+            && self.min_amount.is_none_or(|min_amount| transaction.amount() >= min_amount)
+            && self.counterparty.is_none_or(|wanted| wanted == counterparty)
+    }
+}
+
+// requests naming page 0 or pages of 0 entries are refused rather than read as empty pages
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(try_from = "PageFields")]
+pub struct Page {
+    size: usize,
+    // the first page holds the newest entries
+    number: usize,
+}
+
+#[derive(Deserialize)]
+struct PageFields {
+    size: usize,
+    number: usize,
+}
+
+impl TryFrom<PageFields> for Page {
+    type Error = String;
+
+    fn try_from(fields: PageFields) -> Result<Self, Self::Error> {
+        match fields.size == 0 || fields.number == 0 {
+            true => Err("page size and number start at 1".to_string()),
+            false => Ok(Page::new(fields.size, fields.number))
+        }
+    }
+}
+
+impl Default for Page {
+    fn default() -> Self {
+        Page::new(DEFAULT_PAGE_SIZE, 1)
+    }
+}
+
+impl Page {
+    pub fn new(size: usize, number: usize) -> Page {
+        Page {
+            size: size.max(1),
+            number: number.max(1),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Received,
+    Sent,
+    Fee,
+}
+
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::Received => "received",
+            Direction::Sent => "sent",
+            Direction::Fee => "fee",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatementLine {
+    block_number: u64,
+    time: DateTime<Utc>,
+    direction: Direction,
+    counterparty: String,
+    amount: i64,
+    title: String,
+}
+
+// Totals and transactions of a wallet over one calendar month, serialized flat so it can be
+// turned into a printable document.
+#[derive(Serialize, Deserialize)]
+pub struct Statement {
+    address: String,
+    // as given on the command line, e.g. 2024-06
+    period: String,
+    received: i64,
+    sent: i64,
+    fees: i64,
+    net: i64,
+    lines: Vec<StatementLine>,
+}
+
+impl Statement {
+    pub fn for_month(
+        transactions: &Blockchain<Transaction>, address: Address, month: NaiveDate,
+        private_key: Option<&RsaPrivateKey>,
+    ) -> Result<Statement, Box<dyn BlockchainError>> {
+        let (from, to) = month_bounds(month);
+        let history = TransactionHistory::of(transactions, address)?;
+        let memos = history.memo_keys(private_key);
+        let history = history.between(from, to);
+        let lines: Vec<StatementLine> = history.entries()
+            .iter()
+            .map(|entry| {
+                let transaction = entry.transaction();
+                let (direction, counterparty) = match transaction.source_address() == address {
+                    true if transaction.target_address() == *REWARD_WALLET_ADDRESS => {
+                        (Direction::Fee, transaction.target_address())
+                    }
+                    true => (Direction::Sent, transaction.target_address()),
+                    false => (Direction::Received, transaction.source_address()),
+                };
+                StatementLine {
+                    block_number: entry.block_number(),
+                    time: entry.time().unwrap_or_default(),
+                    direction,
+                    counterparty: access::encode_address(counterparty),
+                    amount: transaction.amount(),
+                    title: memos.readable(transaction.title()),
+                }
+            })
+            .collect();
+        let total = |direction| lines.iter()
+            .filter(|line| line.direction == direction)
+            .map(|line| line.amount)
+            .sum::<i64>();
+        let (received, sent, fees) = (total(Direction::Received), total(Direction::Sent), total(Direction::Fee));
+        Ok(Statement {
+            address: access::encode_address(address),
+            period: month.format("%Y-%m").to_string(),
+            received,
+            sent,
+            fees,
+            net: received - sent - fees,
+            lines,
+        })
+    }
+
+    pub fn received(&self) -> i64 {
+        self.received
+    }
+    pub fn sent(&self) -> i64 {
+        self.sent
+    }
+    pub fn fees(&self) -> i64 {
+        self.fees
+    }
+    pub fn net(&self) -> i64 {
+        self.net
+    }
+    pub fn lines(&self) -> &[StatementLine] {
+        &self.lines
+    }
+
+    pub fn describe(&self, display: &DisplayConfig) -> String {
+        let mut description = format!(
+            "Statement of {} for {}\nReceived: {}, sent: {}, fees: {}, net: {}",
+            self.address, self.period, display.amount(self.received), display.amount(self.sent),
+            display.amount(self.fees), display.signed_amount(self.net)
+        );
+        for line in &self.lines {
+            description.push_str(&format!(
+                "\n#{} {} {} {} {} \"{}\"",
+                line.block_number, display.time(line.time), line.direction.name(),
+                display.amount(line.amount), line.counterparty, line.title
+            ));
+        }
+        description
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // one row per transaction, the totals follow the transactions
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("block,time,direction,counterparty,amount,title\n");
+        for line in &self.lines {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                line.block_number, line.time.to_rfc3339(), line.direction.name(),
+                line.counterparty, line.amount, csv_field(&line.title)
+            ));
+        }
+        for (name, total) in [("received", self.received), ("sent", self.sent), ("fees", self.fees), ("net", self.net)] {
+            csv.push_str(&format!(",,{},,{},\n", name, total));
+        }
+        csv
+    }
+}
+
+// first instant of the month and of the month after it
+fn month_bounds(month: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let first = month.with_day(1).unwrap();
+    let next = match first.month() {
+        12 => NaiveDate::from_ymd_opt(first.year() + 1, 1, 1),
+        month => NaiveDate::from_ymd_opt(first.year(), month + 1, 1),
+    }.unwrap();
+    let start_of = |date: NaiveDate| DateTime::<Utc>::from_utc(date.and_hms_opt(0, 0, 0).unwrap(), Utc);
+    (start_of(first), start_of(next))
+}
+
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Datelike, NaiveDate, Utc};
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::history::{self, Direction, Flow, HistoryFilter, Page, Statement, TransactionHistory};
+
+    #[test]
+    fn statement_totals_a_month_of_wallet_activity() {
+        let (wallet, friend) = ([5; 32], [6; 32]);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let block = BlockCandidate::create_new(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, wallet, "Salary, june".to_string(), 100, Utc::now()),
+            Transaction::new(wallet, friend, "Rent".to_string(), 30, Utc::now()),
+            Transaction::fee(wallet, 1),
+            Transaction::new(friend, [7; 32], "Unrelated".to_string(), 5, Utc::now()),
+        ], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+
+        let today = Utc::now().date_naive();
+        let statement = Statement::for_month(&transactions, wallet, today, None).ok().unwrap();
+        assert_eq!((statement.received(), statement.sent(), statement.fees(), statement.net()), (100, 30, 1, 69));
+        assert_eq!(statement.lines().len(), 3);
+        assert_eq!(statement.lines()[2].direction, Direction::Fee);
+        let csv = statement.to_csv();
+        assert!(csv.contains(",received,"));
+        assert!(csv.contains(",100,\"Salary, june\"\n"));
+        assert!(csv.ends_with(",,net,,69,\n"));
+        assert!(statement.to_json().contains("\"direction\": \"sent\""));
+
+        let last_year = NaiveDate::from_ymd_opt(today.year() - 1, today.month(), 1).unwrap();
+        assert!(Statement::for_month(&transactions, wallet, last_year, None).ok().unwrap().lines().is_empty());
+    }
+
+    #[test]
+    fn listings_filter_and_page_from_the_newest_entries() {
+        let (wallet, friend, shop) = ([5; 32], [6; 32], [7; 32]);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, wallet, "".to_string(), 1000, Utc::now())
+        ]);
+        for amount in 1..=5 {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new(wallet, shop, "".to_string(), amount * 100, Utc::now()),
+                Transaction::new(friend, wallet, "".to_string(), amount, Utc::now()),
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+        let history = || TransactionHistory::of(&transactions, wallet).ok().unwrap();
+        let amounts = |history: &TransactionHistory, page| history.page(page)
+            .iter()
+            .map(|entry| entry.transaction().amount())
+            .collect::<Vec<i64>>();
+
+        let spending = history().filter(&HistoryFilter::default().with_flow(Flow::Out).with_min_amount(200));
+        assert_eq!(amounts(&spending, Page::new(2, 1)), vec![400, 500]);
+        assert_eq!(amounts(&spending, Page::new(2, 2)), vec![200, 300]);
+        assert!(amounts(&spending, Page::new(2, 3)).is_empty());
+        assert_eq!(spending.page_count(2), 2);
+        assert!(serde_json::from_str::<Page>(r#"{"size": 0, "number": 1}"#).is_err());
+        assert!(serde_json::from_str::<Page>(r#"{"size": 2, "number": 0}"#).is_err());
+        let requested = serde_json::from_str::<Page>(r#"{"size": 2, "number": 2}"#).ok().unwrap();
+        assert_eq!(amounts(&spending, requested), vec![200, 300]);
+
+        let from_friend = history().filter(&HistoryFilter::default().with_counterparty(friend).with_flow(Flow::In));
+        assert_eq!(amounts(&from_friend, Page::default()), vec![1, 2, 3, 4, 5]);
+        let tomorrow = Utc::now() + chrono::Duration::days(1);
+        assert!(history().filter(&HistoryFilter::default().with_since(tomorrow)).entries().is_empty());
+        assert_eq!(history().page_count(3), 4);
+
+        transactions.add_uncommitted(Transaction::new(wallet, friend, "".to_string(), 7, Utc::now()));
+        transactions.add_uncommitted(Transaction::new(friend, shop, "".to_string(), 8, Utc::now()));
+        let outgoing = HistoryFilter::default().with_flow(Flow::Out);
+        assert_eq!(history::pending(&transactions, wallet, &outgoing).len(), 1);
+        assert!(history::pending(&transactions, wallet, &outgoing.with_min_amount(10)).is_empty());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+
+use crate::blockchain::{Address, Transaction, TransactionValidator, Wallet};
+use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, TOTAL_SUPPLY};
+use crate::blockchain::key_history::KeyHistory;
+use crate::blockchain::pipeline;
+use crate::blockchain::core::{Block, BlockCandidate, Blockchain, BlockchainError, ChainBlock, Validate};
+use crate::blockchain::upgrade::UpgradeSchedule;
+
+pub struct InvariantViolation {
+    block_number: u64,
+    message: String,
+}
+
+impl InvariantViolation {
+    fn new(block_number: u64, message: String) -> InvariantViolation {
+        InvariantViolation {
+            block_number,
+            message,
+        }
+    }
+
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+}
+
+impl BlockchainError for InvariantViolation {
+    fn message(&self) -> String {
+        format!("Block {}: {}", self.block_number, self.message)
+    }
+}
+
+pub fn audit(blockchain: &Blockchain<Transaction>) -> Vec<InvariantViolation> {
+    let mut violations = vec![];
+    let mut balances: HashMap<Address, i64> = HashMap::new();
+    let mut minted: i64 = 0;
+    let mut previous: Option<ChainBlock<Transaction>> = None;
+    let mut tip_number = 0;
+
+    for (index, block) in blockchain.blocks().enumerate() {
+        let block = match block {
+            Ok(block) => block,
+            Err(error) => {
+                // the next block has nothing to be linked against
+                violations.push(InvariantViolation::new(index as u64, error.message()));
+                previous = None;
+                continue;
+            }
+        };
+        let block_number = block.block_number();
+        tip_number = block_number;
+        match &previous {
+            None if index == 0 && block.key().raw_previous_hash().is_some() => {
+                violations.push(InvariantViolation::new(
+                    block_number, String::from("Genesis block has an ancestor hash"),
+                ));
+            }
+            None => {}
+            Some(previous) => {
+                check_link(previous, &block, &mut violations);
+            }
+        }
+
+        // a block's transactions are in canonical order, not the order they were spent in, so
+        // balances are checked once the whole block is applied
+        let mut sources = vec![];
+        for transaction in block.data() {
+            if transaction.amount() < 0 {
+                violations.push(InvariantViolation::new(
+                    block_number, format!("Negative amount {}", transaction.amount()),
+                ));
+            }
+            if transaction.source_address() == MINTING_WALLET_ADDRESS {
+                minted = minted.saturating_add(transaction.amount());
+            } else {
+                *balances.entry(transaction.source_address()).or_insert(0) -= transaction.amount();
+                if !sources.contains(&transaction.source_address()) {
+                    sources.push(transaction.source_address());
+                }
+            }
+            *balances.entry(transaction.target_address()).or_insert(0) += transaction.amount();
+        }
+        for source in sources {
+            let source_balance = balances[&source];
+            if source_balance < 0 {
+                violations.push(InvariantViolation::new(
+                    block_number, format!(
+                        "Negative balance {} of {}", source_balance, array_bytes::bytes2hex("", source)
+                    ),
+                ));
+            }
+        }
+        previous = Some(block);
+    }
+
+    if minted > TOTAL_SUPPLY {
+        violations.push(InvariantViolation::new(
+            tip_number, format!("Minted {} exceeds total supply {}", minted, TOTAL_SUPPLY),
+        ));
+    }
+    if blockchain.remaining_pool() < 0 {
+        violations.push(InvariantViolation::new(
+            tip_number, format!("Negative remaining pool {}", blockchain.remaining_pool()),
+        ));
+    }
+    // every coin is either still in the pool or was minted, whatever moved it afterwards
+    if minted.saturating_add(blockchain.remaining_pool()) != TOTAL_SUPPLY {
+        violations.push(InvariantViolation::new(
+            tip_number, format!(
+                "Minted {} and remaining pool {} do not add up to total supply {}",
+                minted, blockchain.remaining_pool(), TOTAL_SUPPLY
+            ),
+        ));
+    }
+    violations
+}
+
+pub fn verify(blockchain: &Blockchain<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
+    match audit(blockchain).into_iter().next() {
+        None => Ok(()),
+        Some(violation) => Err(Box::new(violation))
+    }
+}
+
+// full replay from genesis: links, signatures and rewards per block, then the balance invariants
+pub fn verify_transactions(
+    transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, key_history: &KeyHistory,
+    upgrades: &UpgradeSchedule,
+) -> Result<(), Box<dyn BlockchainError>> {
+    let verified_signatures = pipeline::verify_signatures(transactions, key_history)?;
+    transactions.verify_full(|replayed, block| {
+        TransactionValidator::with_upgrades(wallets, replayed, upgrades)
+            .with_key_history(key_history)
+            .with_verified_signatures(&verified_signatures)
+            .block_valid(block)
+    })?;
+    verify(transactions)
+}
+
+fn check_link(
+    previous: &Block<Transaction>, block: &Block<Transaction>,
+    violations: &mut Vec<InvariantViolation>,
+) {
+    let block_number = block.block_number();
+    if block_number <= previous.block_number() {
+        violations.push(InvariantViolation::new(
+            block_number, format!("Block number does not follow {}", previous.block_number()),
+        ));
+    }
+    if block.key().raw_previous_hash() != Some(previous.key().raw_hash()) {
+        violations.push(InvariantViolation::new(
+            block_number, String::from("Broken link to previous block"),
+        ));
+    }
+    let computed = BlockCandidate::<Transaction>::hash(
+        previous.key().raw_hash(), BlockCandidate::summarize(block.data()), block.key().raw_state_root(),
+    );
+    if computed.raw_hash() != block.key().raw_hash() {
+        violations.push(InvariantViolation::new(
+            block_number, String::from("Hash does not match block content"),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{invariants, RejectionReason, Transaction, TransactionValidationError, TransactionValidator, Wallet};
+    use crate::blockchain::protocol::{BURN_WALLET_ADDRESS, MINTING_WALLET_ADDRESS, REWARD_WALLET_ADDRESS, TOTAL_SUPPLY, TRANSACTION_FEE};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::{BlockCandidate, Blockchain, Validate};
+    use crate::random;
+
+    fn reward(amount: i64) -> Transaction {
+        Transaction::new(MINTING_WALLET_ADDRESS, [9; 32], "Reward".to_string(), amount, Utc::now())
+    }
+
+    // in canonical order, as forgers fill blocks
+    fn commit(
+        transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, mut data: Vec<Transaction>,
+    ) -> Result<(), RejectionReason> {
+        data.sort_by(Transaction::canonical_cmp);
+        let block = BlockCandidate::create_new(data, transactions.last_block()).ok().unwrap();
+        TransactionValidator::new(wallets, transactions).diagnose(&block)?;
+        transactions.submit_new_block(block);
+        Ok(())
+    }
+
+    #[test]
+    fn supply_is_conserved_by_rewards_transfers_fees_and_burns() {
+        let mut rng = random::seeded(15);
+        let (sender, recipient) = (HotWallet::generate(&mut rng), HotWallet::generate(&mut rng));
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 1000, Utc::now())
+        ]);
+        assert_eq!(transactions.remaining_pool(), TOTAL_SUPPLY - 1000);
+
+        let mut flows = vec![
+            Transaction::new(sender.address(), recipient.address(), "".to_string(), 300, Utc::now()),
+            Transaction::fee(sender.address(), TRANSACTION_FEE),
+            Transaction::burn(sender.address(), 100),
+        ];
+        for (nonce, transaction) in flows.iter_mut().enumerate() {
+            transaction.set_nonce(transactions.next_nonce(sender.address()) + nonce as u64);
+            sender.sign(transaction, &mut rng);
+        }
+        flows.extend([reward(TRANSACTION_FEE), Transaction::fee_payout([9; 32], TRANSACTION_FEE)]);
+        assert!(commit(&mut transactions, &wallets, flows).is_ok());
+
+        let holders = [sender.address(), recipient.address(), [9; 32], *REWARD_WALLET_ADDRESS, *BURN_WALLET_ADDRESS];
+        let held: i64 = holders.iter().map(|holder| transactions.committed_balance(*holder)).sum();
+        assert_eq!(held + transactions.remaining_pool(), TOTAL_SUPPLY);
+        assert_eq!(transactions.committed_balance(*BURN_WALLET_ADDRESS), 100);
+        assert!(invariants::audit(&transactions).is_empty());
+        assert!(transactions.verify_full(|replayed, block| {
+            TransactionValidator::new(&wallets, replayed).block_valid(block)
+        }).is_ok());
+    }
+
+    #[test]
+    fn rewards_stop_at_the_supply_cap() {
+        let wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), TOTAL_SUPPLY - 20, Utc::now())
+        ]);
+        assert_eq!(transactions.mintable(TRANSACTION_FEE), 20);
+
+        let exceeded = commit(&mut transactions, &wallets, vec![reward(TRANSACTION_FEE)]);
+        assert_eq!(exceeded, Err(RejectionReason::InvalidPayout(TransactionValidationError::SupplyExceeded {
+            remaining: 20,
+            minted: TRANSACTION_FEE,
+        })));
+        assert!(commit(&mut transactions, &wallets, vec![reward(20)]).is_ok());
+        assert_eq!(transactions.remaining_pool(), 0);
+        assert!(commit(&mut transactions, &wallets, vec![reward(1)]).is_err());
+        assert!(commit(&mut transactions, &wallets, vec![]).is_ok());
+        assert!(invariants::audit(&transactions).is_empty());
+
+        let mut pool = Blockchain::<Transaction>::transaction_chain(vec![]);
+        assert_eq!(pool.mint(TOTAL_SUPPLY + 5), TOTAL_SUPPLY);
+        assert_eq!(pool.mint(1), 0);
+    }
+}
@@ -0,0 +1,171 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{Address, Transaction, Wallet};
+use crate::blockchain::core::{Blockchain, BlockchainError, StorageError};
+
+pub static KEY_HISTORY_FILE: &str = "key_history.json";
+
+// Keys each wallet went through, each with the height of the first transaction block it signs
+// for. A key takes over at the first transaction block committed no earlier than the wallet
+// block carrying it, so replays check old signatures by where they landed in the chain, never
+// by the time their signer claims. Kept on disk so recorded heights don't move once settled.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct KeyHistory {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    changes: Vec<KeyChange>,
+    // the last wallet block taken into account, a wallet chain not holding it any more
+    // was replaced and the history is rebuilt
+    wallet_tip: Option<(u64, String)>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct KeyChange {
+    address: Address,
+    height: u64,
+    key: RsaPublicKey,
+}
+
+impl KeyHistory {
+    pub fn load(path: &Path) -> KeyHistory {
+        let history: KeyHistory = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => KeyHistory::default()
+        };
+        KeyHistory {
+            path: Some(path.to_path_buf()),
+            ..history
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let content = serde_json::to_string(self).unwrap();
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn derive(
+        wallets: &Blockchain<Wallet>, transactions: &Blockchain<Transaction>,
+    ) -> Result<KeyHistory, Box<dyn BlockchainError>> {
+        let mut history = KeyHistory::default();
+        history.update(wallets, transactions)?;
+        Ok(history)
+    }
+
+    // takes in the wallet blocks committed since the last update
+    pub fn update(
+        &mut self, wallets: &Blockchain<Wallet>, transactions: &Blockchain<Transaction>,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let first = match &self.wallet_tip {
+            Some((number, hash)) if wallets.block_at(*number)?.is_some_and(|block| block.key().hash() == *hash) => number + 1,
+            _ => {
+                self.changes.clear();
+                0
+            }
+        };
+        let block_times = transactions.blocks()
+            .map(|block| block.map(|block| (block.block_number(), block.time())))
+            .collect::<Result<Vec<_>, _>>()?;
+        for block in wallets.blocks_from(first) {
+            let block = block?;
+            let height = match block.time() {
+                None => 0,
+                Some(time) => block_times.iter()
+                    .find(|(number, committed)| *number > 0 && committed.is_some_and(|committed| committed >= time))
+                    .map_or(transactions.chain_length(), |(number, _)| *number)
+            };
+            for wallet in block.data() {
+                if let Some(key) = wallet.key() {
+                    self.changes.push(KeyChange {
+                        address: wallet.address(),
+                        height,
+                        key: key.clone(),
+                    });
+                }
+            }
+            self.wallet_tip = Some((block.block_number(), block.key().hash()));
+        }
+        Ok(())
+    }
+
+    // the key the address signed with at the given transaction block height, its first one
+    // for blocks older than any of its keys
+    pub fn key_at(&self, address: Address, height: u64) -> Option<RsaPublicKey> {
+        let mut keys = self.changes.iter().filter(|change| change.address == address);
+        let first = keys.clone().next().map(|change| change.key.clone());
+        keys.rfind(|change| change.height <= height)
+            .map(|change| change.key.clone())
+            .or(first)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use chrono::{Duration, Utc};
+
+    use crate::blockchain::{Transaction, TransactionValidator, Wallet};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::key_history::KeyHistory;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::random;
+
+    #[test]
+    fn signatures_are_checked_against_the_key_of_the_block_they_landed_in() {
+        let mut rng = random::seeded(26);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+        let transfer = BlockCandidate::create_new(vec![
+            Transaction::new(sender.address(), recipient.address(), "".to_string(), 1, Utc::now())
+        ], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(transfer);
+
+        let rotated = HotWallet::rotated_from(&sender, &mut rng).ok().unwrap();
+        let rotation = BlockCandidate::create_new(vec![rotated.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(rotation);
+        let history = KeyHistory::derive(&wallets, &transactions).ok().unwrap();
+        assert!(history.key_at(sender.address(), 1) == sender.wallet().key().clone());
+        assert!(history.key_at(sender.address(), 2) == rotated.wallet().key().clone());
+
+        // a signature with the old key claiming to predate the rotation lands after it
+        let mut backdated = Transaction::new(
+            sender.address(), recipient.address(), "".to_string(), 5, Utc::now() - Duration::days(1),
+        );
+        sender.sign(&mut backdated, &mut rng);
+        let replay = TransactionValidator::new(&wallets, &transactions).with_key_history(&history);
+        assert!(replay.transaction_valid(&backdated).is_err());
+        let mut current = backdated.clone();
+        rotated.sign(&mut current, &mut rng);
+        assert!(replay.transaction_valid(&current).is_ok());
+
+        let path = env::temp_dir().join(format!("kingcoin-key-history-{}.json", std::process::id()));
+        let mut persisted = KeyHistory::load(&path);
+        persisted.update(&wallets, &transactions).ok().unwrap();
+        persisted.save().ok().unwrap();
+        let reloaded = KeyHistory::load(&path);
+        assert!(reloaded.key_at(sender.address(), 1) == sender.wallet().key().clone());
+        assert!(reloaded.key_at(sender.address(), 2) == rotated.wallet().key().clone());
+        fs::remove_file(&path).ok();
+    }
+}
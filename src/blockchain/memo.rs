@@ -0,0 +1,291 @@
+use std::collections::{HashMap, HashSet};
+
+use aes_gcm::{Aes256Gcm, aead::{Aead, KeyInit}, Nonce};
+use rsa::{PaddingScheme, PublicKey, RsaPrivateKey, RsaPublicKey};
+use rsa::rand_core::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::{Address, Transaction};
+use crate::blockchain::core::BlockchainError;
+
+// sealed memos replace the plain transaction title, so they need no new transaction fields and
+// are signed and hashed like any other title
+pub static SEALED_MEMO_PREFIX: &str = "sealed1:";
+pub static PRIVATE_MEMO_PREFIX: &str = "private1:";
+// bytes of the secret's hash naming it in a private memo
+static SECRET_ID_LENGTH: usize = 8;
+static KEY_LENGTH: usize = 32;
+static NONCE_LENGTH: usize = 12;
+
+pub struct MemoError {
+    message: String,
+}
+
+impl MemoError {
+    pub fn new(message: &str) -> MemoError {
+        MemoError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for MemoError {
+    fn message(&self) -> String {
+        format!("Memo: {}", self.message)
+    }
+}
+
+// The memo is encrypted with a fresh AES key, which is wrapped with RSA-OAEP for the recipient
+// and for the sender, so both sides can read it back from the chain.
+pub fn seal<R>(
+    memo: &str, recipient_key: &RsaPublicKey, sender_key: &RsaPublicKey, rng: &mut R,
+) -> Result<String, Box<dyn BlockchainError>> where R: CryptoRng + RngCore {
+    let mut key = [0u8; KEY_LENGTH];
+    rng.fill_bytes(&mut key);
+    let encrypted = encrypt(memo, &key, rng)?;
+    let recipient = wrap(&key, recipient_key, rng)?;
+    let sender = wrap(&key, sender_key, rng)?;
+    Ok(format!("{}{}:{}:{}", SEALED_MEMO_PREFIX, recipient, sender, encrypted))
+}
+
+pub fn is_sealed(title: &str) -> bool {
+    title.starts_with(SEALED_MEMO_PREFIX)
+}
+
+pub fn open(title: &str, private_key: &RsaPrivateKey) -> Result<String, Box<dyn BlockchainError>> {
+    let parts = match title.strip_prefix(SEALED_MEMO_PREFIX) {
+        None => return Err(Box::new(MemoError::new("Not a sealed memo"))),
+        Some(sealed) => hex_parts(sealed, "Malformed sealed memo")?
+    };
+    match parts.as_slice() {
+        [recipient, sender, nonce, cipher_text] => {
+            let key = unwrap_key(&[recipient, sender], private_key)
+                .ok_or_else(|| Box::new(MemoError::new("Sealed for another wallet")) as Box<dyn BlockchainError>)?;
+            decrypt(&key, nonce, cipher_text)
+        }
+        _ => Err(Box::new(MemoError::new("Malformed sealed memo")))
+    }
+}
+
+// title as shown to a wallet holder, sealed memos stay hidden from anyone else and private ones
+// need the keys learned from the wallet's history
+pub fn readable(title: &str, private_key: Option<&RsaPrivateKey>) -> String {
+    if is_private(title) {
+        return String::from("[private memo]");
+    }
+    if !is_sealed(title) {
+        return title.to_string();
+    }
+    match private_key.map(|private_key| open(title, private_key)) {
+        Some(Ok(memo)) => format!("{} (sealed)", memo),
+        _ => String::from("[sealed memo]")
+    }
+}
+
+pub fn is_private(title: &str) -> bool {
+    title.starts_with(PRIVATE_MEMO_PREFIX)
+}
+
+// Secrets a wallet shares with the wallets it has transacted with, learned from its history.
+// RSA keys cannot agree on a secret without sending one, so the first private memo between two
+// wallets carries it wrapped for both like a sealed memo and later ones only name it. Once wallet
+// keys move off RSA the secret can come from ECDH instead.
+pub struct MemoKeys<'a> {
+    address: Address,
+    private_key: Option<&'a RsaPrivateKey>,
+    counterparties: HashSet<Address>,
+    // by secret id
+    secrets: HashMap<String, [u8; KEY_LENGTH]>,
+    // newest secret shared with each counterparty
+    shared: HashMap<Address, [u8; KEY_LENGTH]>,
+}
+
+impl<'a> MemoKeys<'a> {
+    pub fn new(address: Address, private_key: Option<&'a RsaPrivateKey>) -> MemoKeys<'a> {
+        MemoKeys {
+            address,
+            private_key,
+            counterparties: HashSet::new(),
+            secrets: HashMap::new(),
+            shared: HashMap::new(),
+        }
+    }
+
+    // the wallet's transactions are learned oldest first, a memo naming a secret follows the one
+    // carrying it
+    pub fn learn(&mut self, transaction: &Transaction) {
+        let counterparty = match transaction.source_address() == self.address {
+            true => transaction.target_address(),
+            false => transaction.source_address(),
+        };
+        self.counterparties.insert(counterparty);
+        let parts = match transaction.title().strip_prefix(PRIVATE_MEMO_PREFIX) {
+            None => return,
+            Some(private) => hex_parts(private, "Malformed private memo").unwrap_or_default()
+        };
+        let key = match (parts.as_slice(), self.private_key) {
+            ([recipient, sender, _, _], Some(private_key)) => unwrap_key(&[recipient, sender], private_key),
+            _ => None
+        };
+        if let Some(key) = key.and_then(|key| <[u8; KEY_LENGTH]>::try_from(key).ok()) {
+            self.secrets.insert(secret_id(&key), key);
+            self.shared.insert(counterparty, key);
+        }
+    }
+
+    // only wallets that transacted before get private memos, a first transfer can use a sealed one
+    pub fn private_memo<R>(
+        &self, memo: &str, counterparty: Address, recipient_key: &RsaPublicKey, sender_key: &RsaPublicKey, rng: &mut R,
+    ) -> Result<String, Box<dyn BlockchainError>> where R: CryptoRng + RngCore {
+        if !self.counterparties.contains(&counterparty) {
+            return Err(Box::new(MemoError::new("Private memos need an earlier transfer between both wallets, use --seal")));
+        }
+        if let Some(key) = self.shared.get(&counterparty) {
+            return Ok(format!("{}{}:{}", PRIVATE_MEMO_PREFIX, secret_id(key), encrypt(memo, key, rng)?));
+        }
+        let mut key = [0u8; KEY_LENGTH];
+        rng.fill_bytes(&mut key);
+        let encrypted = encrypt(memo, &key, rng)?;
+        let recipient = wrap(&key, recipient_key, rng)?;
+        let sender = wrap(&key, sender_key, rng)?;
+        Ok(format!("{}{}:{}:{}", PRIVATE_MEMO_PREFIX, recipient, sender, encrypted))
+    }
+
+    pub fn open(&self, title: &str) -> Result<String, Box<dyn BlockchainError>> {
+        let parts = match title.strip_prefix(PRIVATE_MEMO_PREFIX) {
+            None => return Err(Box::new(MemoError::new("Not a private memo"))),
+            Some(private) => hex_parts(private, "Malformed private memo")?
+        };
+        let (key, nonce, cipher_text) = match parts.as_slice() {
+            [recipient, sender, nonce, cipher_text] => {
+                let key = self.private_key.and_then(|private_key| unwrap_key(&[recipient, sender], private_key));
+                (key, nonce, cipher_text)
+            }
+            [id, nonce, cipher_text] => {
+                let key = self.secrets.get(&array_bytes::bytes2hex("", id)).map(|key| key.to_vec());
+                (key, nonce, cipher_text)
+            }
+            _ => return Err(Box::new(MemoError::new("Malformed private memo")))
+        };
+        match key {
+            None => Err(Box::new(MemoError::new("No secret shared for this memo"))),
+            Some(key) => decrypt(&key, nonce, cipher_text)
+        }
+    }
+
+    pub fn readable(&self, title: &str) -> String {
+        match self.open(title) {
+            Ok(memo) => format!("{} (private)", memo),
+            Err(_) => readable(title, self.private_key)
+        }
+    }
+}
+
+// names a shared secret without giving it away
+fn secret_id(key: &[u8]) -> String {
+    array_bytes::bytes2hex("", &Sha256::digest(key)[..SECRET_ID_LENGTH])
+}
+
+// nonce and cipher text in hex, separated by a colon
+fn encrypt<R>(memo: &str, key: &[u8], rng: &mut R) -> Result<String, Box<dyn BlockchainError>> where R: CryptoRng + RngCore {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    rng.fill_bytes(&mut nonce);
+    let cipher = Aes256Gcm::new_from_slice(key).expect("Valid key length");
+    match cipher.encrypt(Nonce::from_slice(&nonce), memo.as_bytes()) {
+        Ok(cipher_text) => Ok(format!("{}:{}", array_bytes::bytes2hex("", nonce), array_bytes::bytes2hex("", cipher_text))),
+        Err(_) => Err(Box::new(MemoError::new("Could not encrypt")))
+    }
+}
+
+fn decrypt(key: &[u8], nonce: &[u8], cipher_text: &[u8]) -> Result<String, Box<dyn BlockchainError>> {
+    if key.len() != KEY_LENGTH || nonce.len() != NONCE_LENGTH {
+        return Err(Box::new(MemoError::new("Malformed memo")));
+    }
+    let cipher = Aes256Gcm::new_from_slice(key).expect("Valid key length");
+    match cipher.decrypt(Nonce::from_slice(nonce), cipher_text) {
+        Ok(memo) => String::from_utf8(memo).map_err(|_| Box::new(MemoError::new("Memo is not text")) as Box<dyn BlockchainError>),
+        Err(_) => Err(Box::new(MemoError::new("Memo was tampered with")))
+    }
+}
+
+fn wrap<R>(key: &[u8], public_key: &RsaPublicKey, rng: &mut R) -> Result<String, Box<dyn BlockchainError>> where R: CryptoRng + RngCore {
+    match public_key.encrypt(rng, PaddingScheme::new_oaep::<Sha256>(), key) {
+        Ok(wrapped) => Ok(array_bytes::bytes2hex("", wrapped)),
+        Err(_) => Err(Box::new(MemoError::new("Could not wrap the memo key")))
+    }
+}
+
+// the key wrapped for whichever side the private key belongs to
+fn unwrap_key(wrapped_keys: &[&Vec<u8>], private_key: &RsaPrivateKey) -> Option<Vec<u8>> {
+    wrapped_keys.iter()
+        .find_map(|wrapped| private_key.decrypt(PaddingScheme::new_oaep::<Sha256>(), wrapped).ok())
+        .filter(|key| key.len() == KEY_LENGTH)
+}
+
+fn hex_parts(memo: &str, malformed: &str) -> Result<Vec<Vec<u8>>, Box<dyn BlockchainError>> {
+    match memo.split(':').map(array_bytes::hex2bytes).collect::<Result<_, _>>() {
+        Ok(parts) => Ok(parts),
+        Err(_) => Err(Box::new(MemoError::new(malformed)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::memo::{self, MemoKeys};
+    use crate::blockchain::Transaction;
+    use crate::random;
+
+    #[test]
+    fn sealed_memos_are_readable_by_both_parties_only() {
+        let mut rng = random::seeded(9);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let outsider = HotWallet::generate(&mut rng);
+        let sealed = memo::seal(
+            "rent for june", recipient.wallet().key().as_ref().unwrap(),
+            sender.wallet().key().as_ref().unwrap(), &mut rng,
+        ).ok().unwrap();
+        assert!(memo::is_sealed(&sealed));
+        assert!(!sealed.contains("rent"));
+
+        assert_eq!(memo::open(&sealed, recipient.private_key()).ok(), Some(String::from("rent for june")));
+        assert_eq!(memo::open(&sealed, sender.private_key()).ok(), Some(String::from("rent for june")));
+        assert!(memo::open(&sealed, outsider.private_key()).is_err());
+        assert_eq!(memo::readable(&sealed, Some(outsider.private_key())), "[sealed memo]");
+        assert_eq!(memo::readable("plain", None), "plain");
+    }
+
+    #[test]
+    fn private_memos_reuse_the_secret_of_the_first_one() {
+        let mut rng = random::seeded(10);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let outsider = HotWallet::generate(&mut rng);
+        let (sender_key, recipient_key) = (sender.wallet().key().clone().unwrap(), recipient.wallet().key().clone().unwrap());
+        let transfer = |title: String| Transaction::new(sender.address(), recipient.address(), title, 10, Utc::now());
+
+        let mut keys = MemoKeys::new(sender.address(), Some(sender.private_key()));
+        assert!(keys.private_memo("hi", recipient.address(), &recipient_key, &sender_key, &mut rng).is_err());
+        keys.learn(&transfer(String::from("first")));
+        let first = keys.private_memo("rent for june", recipient.address(), &recipient_key, &sender_key, &mut rng).ok().unwrap();
+        keys.learn(&transfer(first.clone()));
+        let second = keys.private_memo("rent for july", recipient.address(), &recipient_key, &sender_key, &mut rng).ok().unwrap();
+        assert!(memo::is_private(&second));
+        assert!(second.len() < first.len());
+
+        let mut received = MemoKeys::new(recipient.address(), Some(recipient.private_key()));
+        let mut overheard = MemoKeys::new(outsider.address(), Some(outsider.private_key()));
+        for title in [&first, &second] {
+            received.learn(&transfer(title.clone()));
+            overheard.learn(&transfer(title.clone()));
+        }
+        assert_eq!(received.readable(&first), "rent for june (private)");
+        assert_eq!(received.readable(&second), "rent for july (private)");
+        assert_eq!(keys.readable(&second), "rent for july (private)");
+        assert_eq!(overheard.readable(&second), "[private memo]");
+        assert_eq!(memo::readable(&second, Some(recipient.private_key())), "[private memo]");
+    }
+}
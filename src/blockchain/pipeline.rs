@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+use std::thread;
+
+use rsa::{PublicKeyParts, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::{access, Address, Transaction};
+use crate::blockchain::core::{Blockchain, BlockchainError};
+use crate::blockchain::key_history::KeyHistory;
+
+// Signatures checked ahead of a replay. They only depend on the transaction and the key its
+// signer held when signing, so the blocks of a downloaded chain are checked on several threads
+// at once while the replay, which needs the state left by the previous block, stays sequential.
+#[derive(Default)]
+pub struct VerifiedSignatures {
+    verified: HashSet<[u8; 32]>,
+}
+
+impl VerifiedSignatures {
+    pub fn contains(&self, public_key: &RsaPublicKey, content: &str, signature: &str) -> bool {
+        self.verified.contains(&digest(public_key, content, signature))
+    }
+
+    pub fn len(&self) -> usize {
+        self.verified.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verified.is_empty()
+    }
+}
+
+// the sender's signature and every approval, checked against the key active at the height of
+// their block; failed checks are left out and reported by the replay with the proper rejection reason
+pub fn verify_signatures(
+    transactions: &Blockchain<Transaction>, key_history: &KeyHistory,
+) -> Result<VerifiedSignatures, Box<dyn BlockchainError>> {
+    let blocks = transactions.blocks().collect::<Result<Vec<_>, _>>()?;
+    let mut checks: Vec<(u64, &Transaction, Address, &str)> = vec![];
+    for block in &blocks {
+        for transaction in block.data() {
+            if let Some(signature) = transaction.sender_signature() {
+                checks.push((block.block_number(), transaction, transaction.source_address(), signature));
+            }
+            for approval in transaction.approvals() {
+                checks.push((block.block_number(), transaction, approval.signer(), approval.signature()));
+            }
+        }
+    }
+    let workers = thread::available_parallelism().map_or(1, |workers| workers.get());
+    let chunk_size = checks.len().div_ceil(workers).max(1);
+    let verified = thread::scope(|scope| {
+        let handles: Vec<_> = checks.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || verify_chunk(chunk, key_history)))
+            .collect();
+        handles.into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default())
+            .collect()
+    });
+    Ok(VerifiedSignatures {
+        verified,
+    })
+}
+
+fn verify_chunk(checks: &[(u64, &Transaction, Address, &str)], key_history: &KeyHistory) -> Vec<[u8; 32]> {
+    checks.iter()
+        .filter_map(|(height, transaction, signer, signature)| {
+            let public_key = key_history.key_at(*signer, *height)?;
+            let content = transaction.signed_content();
+            access::verify_message(&public_key, &content, signature)
+                .then(|| digest(&public_key, &content, signature))
+        })
+        .collect()
+}
+
+fn digest(public_key: &RsaPublicKey, content: &str, signature: &str) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(public_key.n().to_bytes_be());
+    hasher.update(public_key.e().to_bytes_be());
+    hasher.update(content.as_bytes());
+    hasher.update(signature.as_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::key_history::KeyHistory;
+    use crate::blockchain::pipeline;
+    use crate::random;
+
+    #[test]
+    fn signatures_of_all_blocks_are_checked_ahead_of_the_replay() {
+        let mut rng = random::seeded(16);
+        let sender = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(vec![sender.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+        let mut forged = Transaction::burn(sender.address(), 5);
+        sender.sign(&mut forged, &mut rng);
+        forged.set_nonce(7);
+        for nonce in 0..4 {
+            let mut transfer = Transaction::burn(sender.address(), 1);
+            transfer.set_nonce(nonce);
+            sender.sign(&mut transfer, &mut rng);
+            let data = match nonce {
+                3 => vec![transfer, forged.clone()],
+                _ => vec![transfer],
+            };
+            let block = BlockCandidate::create_new(data, transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+
+        let key_history = KeyHistory::derive(&wallets, &transactions).ok().unwrap();
+        let verified = pipeline::verify_signatures(&transactions, &key_history).ok().unwrap();
+        assert_eq!(verified.len(), 4);
+        let key = sender.wallet().key().clone().unwrap();
+        assert!(!verified.contains(&key, &forged.signed_content(), forged.sender_signature().as_ref().unwrap()));
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{access, Address, BlockchainData, Transaction};
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError};
+
+pub struct ProofError {
+    message: String,
+}
+
+impl ProofError {
+    pub fn new(message: &str) -> ProofError {
+        ProofError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for ProofError {
+    fn message(&self) -> String {
+        format!("Balance proof: {}", self.message)
+    }
+}
+
+// one committed transaction moving the address's balance
+#[derive(Serialize, Deserialize)]
+pub struct ProofEntry {
+    block_number: u64,
+    block_hash: String,
+    transaction_id: String,
+    counterparty: Address,
+    change: i64,
+    // after this transaction
+    balance: i64,
+}
+
+impl ProofEntry {
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+    pub fn block_hash(&self) -> &str {
+        &self.block_hash
+    }
+    pub fn transaction_id(&self) -> &str {
+        &self.transaction_id
+    }
+    pub fn counterparty(&self) -> Address {
+        self.counterparty
+    }
+    pub fn change(&self) -> i64 {
+        self.change
+    }
+    pub fn balance(&self) -> i64 {
+        self.balance
+    }
+}
+
+// Balance of an address derived by replaying every credit and debit from genesis up to a
+// height. Each step names the block it comes from by hash, so an auditor holding the chain can
+// check it with verify rather than trust the node that derived it.
+#[derive(Serialize, Deserialize)]
+pub struct BalanceProof {
+    address: Address,
+    height: u64,
+    entries: Vec<ProofEntry>,
+}
+
+impl BalanceProof {
+    pub fn derive(transactions: &Blockchain<Transaction>, address: Address) -> Result<BalanceProof, Box<dyn BlockchainError>> {
+        let mut entries = vec![];
+        let mut balance = 0;
+        for block in transactions.blocks() {
+            let block = block?;
+            for transaction in block.data() {
+                let change = match change_of(transaction, address) {
+                    None => continue,
+                    Some(change) => change
+                };
+                balance += change;
+                entries.push(ProofEntry {
+                    block_number: block.block_number(),
+                    block_hash: block.key().hash(),
+                    transaction_id: transaction.id(),
+                    counterparty: match transaction.source_address() == address {
+                        true => transaction.target_address(),
+                        false => transaction.source_address(),
+                    },
+                    change,
+                    balance,
+                });
+            }
+        }
+        Ok(BalanceProof {
+            address,
+            height: transactions.chain_length() - 1,
+            entries,
+        })
+    }
+
+    // checks every step against the given chain and the result against its balance index
+    pub fn verify(&self, transactions: &Blockchain<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
+        let failed = |entry: &ProofEntry, reason: &str| -> Box<dyn BlockchainError> {
+            Box::new(ProofError::new(&format!(
+                "transaction {} in block {}: {}", &entry.transaction_id[..16], entry.block_number, reason
+            )))
+        };
+        let mut balance = 0;
+        for entry in &self.entries {
+            let block = match transactions.block_at(entry.block_number)? {
+                None => return Err(failed(entry, "block is not on the chain")),
+                Some(block) => block
+            };
+            if block.key().hash() != entry.block_hash {
+                return Err(failed(entry, "block hash differs"));
+            }
+            // the genesis block has no previous hash to recompute it from
+            let content_matches = block.key().raw_previous_hash().is_none_or(|previous_hash| {
+                BlockCandidate::<Transaction>::hash(
+                    previous_hash, BlockCandidate::summarize(block.data()), block.key().raw_state_root(),
+                ).hash()
+                    == entry.block_hash
+            });
+            if !content_matches {
+                return Err(failed(entry, "block content does not match its hash"));
+            }
+            let change = block.data().iter()
+                .find(|transaction| transaction.id() == entry.transaction_id)
+                .and_then(|transaction| change_of(transaction, self.address));
+            if change != Some(entry.change) {
+                return Err(failed(entry, "block does not hold this change"));
+            }
+            balance += entry.change;
+            if balance != entry.balance {
+                return Err(failed(entry, "running balance does not add up"));
+            }
+        }
+        let committed = transactions.committed_balance_at(self.address, self.height)?;
+        if balance != committed {
+            return Err(Box::new(ProofError::new(&format!(
+                "derived {} but the chain holds {} at height {}", balance, committed, self.height
+            ))));
+        }
+        Ok(())
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+    pub fn entries(&self) -> &[ProofEntry] {
+        &self.entries
+    }
+
+    pub fn balance(&self) -> i64 {
+        self.entries.last().map_or(0, ProofEntry::balance)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!(
+            "Balance of {} up to block {}", access::encode_address(self.address), self.height
+        )];
+        lines.extend(self.entries.iter().map(|entry| format!(
+            "#{} {} {} {:+} {} = {}",
+            entry.block_number, &entry.block_hash[..16], &entry.transaction_id[..16], entry.change,
+            access::encode_address(entry.counterparty), entry.balance
+        )));
+        lines.push(format!("Balance: {} over {} transactions", self.balance(), self.entries.len()));
+        lines.join("\n")
+    }
+}
+
+// none if the transaction does not touch the address at all
+fn change_of(transaction: &Transaction, address: Address) -> Option<i64> {
+    let changes: Vec<i64> = transaction.balance_changes().into_iter()
+        .filter(|(changed, _)| *changed == address)
+        .map(|(_, change)| change)
+        .collect();
+    match changes.is_empty() {
+        true => None,
+        false => Some(changes.iter().sum())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::proof::BalanceProof;
+
+    #[test]
+    fn derived_balance_verifies_against_the_chain_it_came_from() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        for (source, target, amount) in [([1; 32], [2; 32], 30), ([2; 32], [1; 32], 5), ([2; 32], [3; 32], 1)] {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new(source, target, "".to_string(), amount, Utc::now())
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+
+        let proof = BalanceProof::derive(&transactions, [1; 32]).ok().unwrap();
+        assert_eq!(proof.entries().len(), 3);
+        assert_eq!(proof.balance(), 75);
+        assert_eq!(proof.balance(), transactions.committed_balance([1; 32]));
+        assert!(proof.verify(&transactions).is_ok());
+
+        // a chain that diverged from the audited one does not confirm the proof
+        let other = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        assert!(proof.verify(&other).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::Address;
+use crate::blockchain::core::BlockchainError;
+use crate::blockchain::governance::Parameter;
+use crate::blockchain::rules::RulesConfig;
+use crate::network::quorum::QuorumConfig;
+
+// Constants every node of a network must agree on. The economic ones are only the defaults the
+// genesis consensus rules start from, a genesis file and later governance amendments change
+// them through the upgrade schedule, which is what validators consult.
+
+// minted for the forger of every block
+pub static TRANSACTION_FEE: i64 = 50;
+// paid by the sender of every transfer
+pub static TRANSFER_FEE: i64 = 1;
+// transactions per block, positive
+pub static BLOCK_SIZE: u64 = 30;
+// a block that is not full is only forged this long after the previous one
+pub static BLOCK_INTERVAL_SECONDS: u64 = 30;
+// transaction blocks from this height on list their transactions in canonical order, blocks
+// forged before it by nodes that did not sort them stay valid
+pub static CANONICAL_ORDER_HEIGHT: u64 = 2000;
+// from this height on the minting and stake wallets and escrow parties that are system wallets
+// are refused as transfer targets, older blocks may still pay them
+pub static RESERVED_TARGETS_HEIGHT: u64 = 3000;
+// everything ever minted, rewards and grants included, stays below it
+pub static TOTAL_SUPPLY: i64 = 21000000;
+// minted once for every newly registered wallet that solves the grant puzzle, 0 turns grants off
+pub static WALLET_GRANT: i64 = 1000;
+// leading zero bits of the grant puzzle, about sixteen million hashes per claiming wallet
+pub static GRANT_WORK_BITS: u32 = 24;
+// grants one block pays at most, the rest wait in the mempool for the blocks after it
+pub static GRANTS_PER_BLOCK: usize = 4;
+// signatures commit to the network they were made for and cannot be replayed on another, a
+// network with genesis overrides has its own id, see chain_id
+pub static CHAIN_ID: &str = "kingcoin-main";
+pub static TRANSACTION_SIGNING_DOMAIN: &str = "kingcoin-transaction";
+pub static GENESIS_FILE: &str = "genesis.json";
+
+// System addresses have no key and are all zero but their first byte, no public key hashes to
+// them. They must stay pairwise distinct, each one's balance means something else.
+pub static MINTING_WALLET_ADDRESS: Address = [0; 32];
+lazy_static! {
+    pub static ref STAKE_WALLET_ADDRESS: Address = system_address(1);
+    pub static ref REWARD_WALLET_ADDRESS: Address = system_address(2);
+    // holds funds locked by contracts until they are settled
+    pub static ref CONTRACT_WALLET_ADDRESS: Address = system_address(3);
+    // has no key, coins sent here leave the circulating supply for good
+    pub static ref BURN_WALLET_ADDRESS: Address = system_address(4);
+}
+
+// genesis overrides, fixed the first time the upgrade schedule is built
+static GENESIS: OnceLock<GenesisOverrides> = OnceLock::new();
+static NETWORK_ID: OnceLock<String> = OnceLock::new();
+
+pub struct ProtocolError {
+    message: String,
+}
+
+impl ProtocolError {
+    pub fn new(message: &str) -> ProtocolError {
+        ProtocolError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for ProtocolError {
+    fn message(&self) -> String {
+        format!("Protocol: {}", self.message)
+    }
+}
+
+fn system_address(tag: u8) -> Address {
+    let mut address = [0; 32];
+    address[0] = tag;
+    address
+}
+
+pub fn system_addresses() -> [Address; 5] {
+    [
+        MINTING_WALLET_ADDRESS, *STAKE_WALLET_ADDRESS, *REWARD_WALLET_ADDRESS,
+        *CONTRACT_WALLET_ADDRESS, *BURN_WALLET_ADDRESS,
+    ]
+}
+
+pub fn is_reserved(address: &Address) -> bool {
+    system_addresses().contains(address)
+}
+
+// Parameters a network sets at genesis instead of the defaults, e.g. a test network with small
+// blocks. Every node of the network needs the same file, unset parameters keep their default.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+#[serde(default)]
+pub struct GenesisOverrides {
+    block_reward: Option<i64>,
+    transfer_fee: Option<i64>,
+    block_size: Option<u64>,
+    wallet_grant: Option<i64>,
+    // bid, vote, approval and checkpoint thresholds, stake weighting included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quorum: Option<QuorumConfig>,
+    // criteria and validators by chain, see blockchain::rules
+    #[serde(skip_serializing_if = "RulesConfig::is_empty")]
+    rules: RulesConfig,
+}
+
+impl GenesisOverrides {
+    // a missing file means defaults, a malformed one is an error rather than silently ignored
+    pub fn load(path: &Path) -> Result<GenesisOverrides, Box<dyn BlockchainError>> {
+        let overrides: GenesisOverrides = match fs::read_to_string(path) {
+            Err(_) => return Ok(GenesisOverrides::default()),
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(overrides) => overrides,
+                Err(error) => return Err(Box::new(ProtocolError::new(&format!("Malformed genesis file: {}", error))))
+            }
+        };
+        overrides.validate()?;
+        Ok(overrides)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let amounts = [self.block_reward, self.transfer_fee, self.wallet_grant];
+        if amounts.iter().flatten().any(|amount| !(0..=TOTAL_SUPPLY).contains(amount)) {
+            return Err(Box::new(ProtocolError::new(&format!(
+                "Reward, fee and grant must be between 0 and the supply of {}", TOTAL_SUPPLY
+            ))));
+        }
+        if self.block_size == Some(0) {
+            return Err(Box::new(ProtocolError::new("Block size must be positive")));
+        }
+        if let Some(quorum) = &self.quorum {
+            quorum.validate()?;
+        }
+        self.rules.validate()
+    }
+
+    pub fn quorum(&self) -> QuorumConfig {
+        self.quorum.unwrap_or_default()
+    }
+
+    pub fn rules(&self) -> &RulesConfig {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == GenesisOverrides::default()
+    }
+
+    pub fn describe(&self) -> String {
+        self.parameters().iter().map(Parameter::describe).collect::<Vec<String>>().join(", ")
+    }
+
+    // applied to the default genesis rules like governance amendments
+    pub fn parameters(&self) -> Vec<Parameter> {
+        [
+            self.block_reward.map(Parameter::BlockReward),
+            self.transfer_fee.map(Parameter::TransferFee),
+            self.block_size.map(Parameter::BlockSize),
+            self.wallet_grant.map(Parameter::WalletGrant),
+        ].into_iter().flatten().collect()
+    }
+}
+
+// must run before the upgrade schedule is first used, a node validating with the defaults first
+// would disagree with itself later
+pub fn apply_genesis(overrides: GenesisOverrides) -> Result<(), Box<dyn BlockchainError>> {
+    overrides.validate()?;
+    match GENESIS.set(overrides) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(Box::new(ProtocolError::new("Genesis parameters are already in use")))
+    }
+}
+
+pub fn genesis_overrides() -> &'static GenesisOverrides {
+    GENESIS.get_or_init(GenesisOverrides::default)
+}
+
+// what signatures commit to, networks started from different genesis parameters never accept
+// each other's transactions
+pub fn chain_id() -> &'static str {
+    NETWORK_ID.get_or_init(|| network_id(genesis_overrides()))
+}
+
+fn network_id(overrides: &GenesisOverrides) -> String {
+    if overrides.is_empty() {
+        return CHAIN_ID.to_string();
+    }
+    let digest = Sha256::digest(serde_json::to_vec(overrides).unwrap());
+    format!("{}-{}", CHAIN_ID, array_bytes::bytes2hex("", &digest[..8]))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::governance::Parameter;
+    use crate::blockchain::protocol::{self, GenesisOverrides};
+    use crate::network::quorum::QuorumConfig;
+    use crate::random;
+
+    #[test]
+    fn system_addresses_are_disjoint_and_reserved() {
+        let addresses = protocol::system_addresses();
+        assert_eq!(addresses.iter().collect::<HashSet<_>>().len(), addresses.len());
+        assert!(addresses.iter().all(protocol::is_reserved));
+        let mut rng = random::seeded(11);
+        assert!(!protocol::is_reserved(&HotWallet::generate(&mut rng).address()));
+    }
+
+    #[test]
+    fn genesis_overrides_replace_only_the_parameters_they_set() {
+        let small: GenesisOverrides = serde_json::from_str(r#"{"block_size": 5, "block_reward": 10}"#).unwrap();
+        assert!(small.validate().is_ok());
+        assert_eq!(small.parameters(), vec![Parameter::BlockReward(10), Parameter::BlockSize(5)]);
+        assert!(GenesisOverrides::default().is_empty());
+        for invalid in [r#"{"block_size": 0}"#, r#"{"transfer_fee": -1}"#, r#"{"wallet_grant": 100000000}"#] {
+            assert!(serde_json::from_str::<GenesisOverrides>(invalid).unwrap().validate().is_err());
+        }
+    }
+
+    #[test]
+    fn networks_with_other_genesis_parameters_have_other_ids() {
+        let small: GenesisOverrides = serde_json::from_str(r#"{"block_size": 5}"#).unwrap();
+        let smaller: GenesisOverrides = serde_json::from_str(r#"{"block_size": 4}"#).unwrap();
+        assert_eq!(protocol::network_id(&GenesisOverrides::default()), protocol::CHAIN_ID);
+        assert_ne!(protocol::network_id(&small), protocol::CHAIN_ID);
+        assert_ne!(protocol::network_id(&small), protocol::network_id(&smaller));
+    }
+
+    #[test]
+    fn genesis_sets_the_quorums_every_node_counts() {
+        assert_eq!(GenesisOverrides::default().quorum(), QuorumConfig::default());
+        let weighted: GenesisOverrides = serde_json::from_str(
+            r#"{"quorum": {"checkpoint_percent": 80, "stake_weighted": true}}"#
+        ).unwrap();
+        assert!(weighted.validate().is_ok());
+        assert_ne!(weighted.quorum(), QuorumConfig::default());
+        assert_ne!(protocol::network_id(&weighted), protocol::CHAIN_ID);
+        let invalid: GenesisOverrides = serde_json::from_str(r#"{"quorum": {"checkpoint_percent": 0}}"#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn genesis_sets_the_rules_blocks_are_checked_against() {
+        let strict: GenesisOverrides = serde_json::from_str(
+            r#"{"rules": {"validators": {"transactions": ["no-self-transfers"]}}}"#
+        ).unwrap();
+        assert!(strict.validate().is_ok());
+        assert!(!strict.rules().is_empty());
+        assert_ne!(protocol::network_id(&strict), protocol::CHAIN_ID);
+        let unknown: GenesisOverrides = serde_json::from_str(r#"{"rules": {"criteria": {"votes": "any"}}}"#).unwrap();
+        assert!(unknown.validate().is_err());
+    }
+}
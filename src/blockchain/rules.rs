@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{BlockchainData, Transaction, TransactionCriteria, Wallet};
+use crate::blockchain::core::{BlockCandidate, BlockchainError, BlockValidationError, Criteria, Summary, Validate};
+use crate::blockchain::protocol::ProtocolError;
+
+pub static TRANSACTIONS_CHAIN: &str = "transactions";
+pub static WALLETS_CHAIN: &str = "wallets";
+pub static ANY_HASH: &str = "any";
+
+// Rules a network adds on top of the built-in validators, picked by name per chain. Set in the
+// genesis file like the other consensus parameters, so a test network can try stricter rules
+// without recompiling and nodes with other rules end up on another network id. A program
+// embedding the node can register implementations of its own before the rules are built.
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+#[serde(default)]
+pub struct RulesConfig {
+    // chain name to the criteria its block hashes meet, chains not listed accept any hash
+    criteria: BTreeMap<String, String>,
+    // chain name to validators checked after the built-in ones
+    validators: BTreeMap<String, Vec<String>>,
+}
+
+impl RulesConfig {
+    pub fn with_criteria(mut self, chain: &str, criteria: &str) -> Self {
+        self.criteria.insert(chain.to_string(), criteria.to_string());
+        self
+    }
+
+    pub fn with_validator(mut self, chain: &str, validator: &str) -> Self {
+        self.validators.entry(chain.to_string()).or_default().push(validator.to_string());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == RulesConfig::default()
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let chains = self.criteria.keys().chain(self.validators.keys());
+        for chain in chains {
+            if chain != TRANSACTIONS_CHAIN && chain != WALLETS_CHAIN {
+                return Err(Box::new(ProtocolError::new(&format!("Rules for unknown chain {}", chain))));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ChainRules<T> where T: BlockchainData {
+    criteria: Box<dyn Criteria + Send + Sync>,
+    validators: Vec<Box<dyn Validate<T> + Send + Sync>>,
+}
+
+impl<T> Default for ChainRules<T> where T: BlockchainData {
+    fn default() -> Self {
+        ChainRules {
+            criteria: Box::new(TransactionCriteria),
+            validators: vec![],
+        }
+    }
+}
+
+impl<T> Validate<T> for ChainRules<T> where T: BlockchainData {
+    fn block_valid(&self, block: &BlockCandidate<T>) -> Result<(), Box<dyn BlockchainError>> {
+        if !self.criteria.criteria_fulfilled(&block.key().raw_hash()) {
+            return Err(Box::new(BlockValidationError::new(block.key().hash(), "Block hash does not meet the chain's criteria")));
+        }
+        self.validators.iter().try_for_each(|validator| validator.block_valid(block))
+    }
+}
+
+// what the node checks pending blocks against besides the built-in validators
+#[derive(Default)]
+pub struct Rules {
+    transactions: ChainRules<Transaction>,
+    wallets: ChainRules<Wallet>,
+}
+
+impl Rules {
+    pub fn transactions(&self) -> &ChainRules<Transaction> {
+        &self.transactions
+    }
+
+    pub fn wallets(&self) -> &ChainRules<Wallet> {
+        &self.wallets
+    }
+}
+
+// rejects blocks carrying no data, the forger has nothing to propose then
+pub struct NoEmptyBlocks;
+
+impl<T> Validate<T> for NoEmptyBlocks where T: BlockchainData {
+    fn block_valid(&self, block: &BlockCandidate<T>) -> Result<(), Box<dyn BlockchainError>> {
+        match block.data().is_empty() {
+            true => Err(Box::new(BlockValidationError::new(block.key().hash(), "Block is empty"))),
+            false => Ok(())
+        }
+    }
+}
+
+pub struct NoSelfTransfers;
+
+impl Validate<Transaction> for NoSelfTransfers {
+    fn block_valid(&self, block: &BlockCandidate<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
+        match block.data().iter().find(|transaction| transaction.source_address() == transaction.target_address()) {
+            Some(transaction) => Err(Box::new(BlockValidationError::new(
+                block.key().hash(), &format!("Transaction {} pays its own sender", transaction.summary()),
+            ))),
+            None => Ok(())
+        }
+    }
+}
+
+type CriteriaConstructor = fn() -> Box<dyn Criteria + Send + Sync>;
+type ValidatorConstructor<T> = fn() -> Box<dyn Validate<T> + Send + Sync>;
+
+// Named criteria and validators rules are built from, like chains they are registered once at
+// startup.
+pub struct RuleRegistry {
+    criteria: HashMap<String, CriteriaConstructor>,
+    transaction_validators: HashMap<String, ValidatorConstructor<Transaction>>,
+    wallet_validators: HashMap<String, ValidatorConstructor<Wallet>>,
+}
+
+impl Default for RuleRegistry {
+    fn default() -> Self {
+        let mut registry = RuleRegistry {
+            criteria: HashMap::new(),
+            transaction_validators: HashMap::new(),
+            wallet_validators: HashMap::new(),
+        };
+        registry.criteria.insert(ANY_HASH.to_string(), || Box::new(TransactionCriteria));
+        registry.transaction_validators.insert(String::from("no-empty-blocks"), || Box::new(NoEmptyBlocks));
+        registry.transaction_validators.insert(String::from("no-self-transfers"), || Box::new(NoSelfTransfers));
+        registry.wallet_validators.insert(String::from("no-empty-blocks"), || Box::new(NoEmptyBlocks));
+        registry
+    }
+}
+
+impl RuleRegistry {
+    pub fn new() -> RuleRegistry {
+        RuleRegistry::default()
+    }
+
+    pub fn register_criteria(&mut self, name: &str, criteria: CriteriaConstructor) -> Result<(), Box<dyn BlockchainError>> {
+        register(&mut self.criteria, name, criteria)
+    }
+
+    pub fn register_transaction_validator(
+        &mut self, name: &str, validator: ValidatorConstructor<Transaction>,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        register(&mut self.transaction_validators, name, validator)
+    }
+
+    pub fn register_wallet_validator(
+        &mut self, name: &str, validator: ValidatorConstructor<Wallet>,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        register(&mut self.wallet_validators, name, validator)
+    }
+
+    // every name in the config must be registered, a node silently skipping a rule would
+    // vote differently from its peers
+    pub fn rules(&self, config: &RulesConfig) -> Result<Rules, Box<dyn BlockchainError>> {
+        Ok(Rules {
+            transactions: self.chain_rules(config, TRANSACTIONS_CHAIN, &self.transaction_validators)?,
+            wallets: self.chain_rules(config, WALLETS_CHAIN, &self.wallet_validators)?,
+        })
+    }
+
+    fn chain_rules<T>(
+        &self, config: &RulesConfig, chain: &str, validators: &HashMap<String, ValidatorConstructor<T>>,
+    ) -> Result<ChainRules<T>, Box<dyn BlockchainError>> where T: BlockchainData {
+        let criteria = config.criteria.get(chain).map(String::as_str).unwrap_or(ANY_HASH);
+        let criteria = match self.criteria.get(criteria) {
+            None => return Err(Box::new(ProtocolError::new(&format!("Unknown criteria {} for {}", criteria, chain)))),
+            Some(criteria) => criteria()
+        };
+        let validators = config.validators.get(chain)
+            .into_iter()
+            .flatten()
+            .map(|name| match validators.get(name) {
+                None => Err(Box::new(ProtocolError::new(&format!("Unknown validator {} for {}", name, chain))) as Box<dyn BlockchainError>),
+                Some(validator) => Ok(validator())
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ChainRules { criteria, validators })
+    }
+}
+
+fn register<C>(registered: &mut HashMap<String, C>, name: &str, constructor: C) -> Result<(), Box<dyn BlockchainError>> {
+    if registered.contains_key(name) {
+        return Err(Box::new(ProtocolError::new(&format!("{} is already registered", name))));
+    }
+    registered.insert(name.to_string(), constructor);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{BlockCriteria, Transaction};
+    use crate::blockchain::core::{BlockCandidate, Blockchain, Validate};
+    use crate::blockchain::rules::{RuleRegistry, RulesConfig, TRANSACTIONS_CHAIN, WALLETS_CHAIN};
+
+    #[test]
+    fn configured_rules_are_checked_after_the_built_in_ones() {
+        let transactions: Blockchain<Transaction> = Blockchain::empty_chain();
+        let block = |data: Vec<Transaction>| BlockCandidate::create_new(data, transactions.last_block()).ok().unwrap();
+        let to_self = Transaction::new([1; 32], [1; 32], String::from("savings"), 10, Utc::now());
+        let transfer = Transaction::new([1; 32], [2; 32], String::from("rent"), 10, Utc::now());
+
+        let mut registry = RuleRegistry::new();
+        let defaults = registry.rules(&RulesConfig::default()).ok().unwrap();
+        assert!(defaults.transactions().block_valid(&block(vec![to_self.clone()])).is_ok());
+
+        let config = RulesConfig::default()
+            .with_validator(TRANSACTIONS_CHAIN, "no-self-transfers")
+            .with_validator(WALLETS_CHAIN, "no-empty-blocks");
+        assert!(config.validate().is_ok());
+        let strict = registry.rules(&config).ok().unwrap();
+        assert!(strict.transactions().block_valid(&block(vec![transfer.clone()])).is_ok());
+        assert!(strict.transactions().block_valid(&block(vec![transfer, to_self])).is_err());
+
+        let mined = RulesConfig::default().with_criteria(TRANSACTIONS_CHAIN, "leading-zeros");
+        assert!(registry.rules(&mined).is_err());
+        assert!(registry.register_criteria("leading-zeros", || Box::new(BlockCriteria)).is_ok());
+        assert!(registry.register_criteria("leading-zeros", || Box::new(BlockCriteria)).is_err());
+        let mined = registry.rules(&mined).ok().unwrap();
+        assert!(mined.transactions().block_valid(&block(vec![])).is_err());
+
+        assert!(RulesConfig::default().with_validator("votes", "no-empty-blocks").validate().is_err());
+    }
+}
@@ -0,0 +1,405 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::time::Duration;
+
+use rsa::rand_core::CryptoRngCore;
+use rsa::RsaPublicKey;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::runtime::{self, Handle, RuntimeFlavor};
+use tokio::{task, time};
+
+use crate::blockchain::{Address, Transaction, Wallet};
+use crate::blockchain::access::{self, HotWallet};
+use crate::blockchain::contract::Approval;
+use crate::blockchain::core::BlockchainError;
+
+// hardware signers may wait for a button press before answering
+static SIGNER_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct SignerError {
+    message: String,
+}
+
+impl SignerError {
+    pub fn new(message: &str) -> SignerError {
+        SignerError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for SignerError {
+    fn message(&self) -> String {
+        format!("Signer: {}", self.message)
+    }
+}
+
+pub trait Signer: Send + Sync {
+    // registered wallet entry, the node only ever sees its public key
+    fn wallet(&self) -> Wallet;
+
+    fn sign_message(
+        &self, content: &str, rng: &mut dyn CryptoRngCore,
+    ) -> Result<String, Box<dyn BlockchainError>>;
+
+    fn address(&self) -> Address {
+        self.wallet().address()
+    }
+
+    // signers holding the key in this process, only they can seal it into a keystore
+    fn hot_wallet(&self) -> Option<&HotWallet> {
+        None
+    }
+
+    // a fresh key for the same address, kept where this signer keeps its own, with a wallet
+    // entry the current key signs
+    fn rotate(&self, rng: &mut dyn CryptoRngCore) -> Result<Box<dyn Signer>, Box<dyn BlockchainError>>;
+
+    fn sign(
+        &self, transaction: &mut Transaction, rng: &mut dyn CryptoRngCore,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let signature = self.sign_message(&transaction.signed_content(), rng)?;
+        transaction.attach_signature(signature);
+        Ok(())
+    }
+
+    // adds this signer's approval to a settlement several parties have to sign
+    fn approve(
+        &self, transaction: &mut Transaction, rng: &mut dyn CryptoRngCore,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let signature = self.sign_message(&transaction.signed_content(), rng)?;
+        transaction.add_approval(Approval::new(self.address(), signature));
+        Ok(())
+    }
+}
+
+impl Signer for HotWallet {
+    fn wallet(&self) -> Wallet {
+        HotWallet::wallet(self).clone()
+    }
+
+    fn sign_message(
+        &self, content: &str, rng: &mut dyn CryptoRngCore,
+    ) -> Result<String, Box<dyn BlockchainError>> {
+        Ok(HotWallet::sign_message(self, content, rng))
+    }
+
+    fn hot_wallet(&self) -> Option<&HotWallet> {
+        Some(self)
+    }
+
+    fn rotate(&self, rng: &mut dyn CryptoRngCore) -> Result<Box<dyn Signer>, Box<dyn BlockchainError>> {
+        Ok(Box::new(HotWallet::rotated_from(self, rng)?))
+    }
+}
+
+// one json request per line, answered by one json response per line. Signing and rotating
+// name the key the node knows, a device that rotated holds the old key until the new one is
+// committed.
+#[derive(Serialize, Deserialize)]
+pub enum SignerRequest {
+    Wallet,
+    Sign { content: String, public_key: Option<RsaPublicKey> },
+    Rotate { public_key: Option<RsaPublicKey> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum SignerResponse {
+    Wallet { address: String, public_key: RsaPublicKey },
+    Signature(String),
+    Rotated(Wallet),
+    Refused(String),
+}
+
+// keys stay with an external process, e.g. a bridge to a hardware wallet or an hsm
+pub struct RemoteSigner {
+    endpoint: SocketAddr,
+    wallet: Wallet,
+    timeout: Duration,
+}
+
+impl RemoteSigner {
+    pub fn connect(endpoint: SocketAddr) -> Result<RemoteSigner, Box<dyn BlockchainError>> {
+        match request(endpoint, &SignerRequest::Wallet, SIGNER_TIMEOUT)? {
+            SignerResponse::Wallet { address, public_key } => Ok(RemoteSigner {
+                endpoint,
+                wallet: Wallet::new(access::decode_address(&address)?, Some(public_key)),
+                timeout: SIGNER_TIMEOUT,
+            }),
+            SignerResponse::Refused(reason) => Err(Box::new(SignerError::new(&reason))),
+            _ => Err(Box::new(SignerError::new("Unexpected response"))),
+        }
+    }
+
+    // how long signing and rotating wait on the device, e.g. for a slow key generation
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn endpoint(&self) -> SocketAddr {
+        self.endpoint
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn wallet(&self) -> Wallet {
+        self.wallet.clone()
+    }
+
+    fn sign_message(
+        &self, content: &str, _rng: &mut dyn CryptoRngCore,
+    ) -> Result<String, Box<dyn BlockchainError>> {
+        let sign = SignerRequest::Sign {
+            content: content.to_string(),
+            public_key: self.wallet.key().clone(),
+        };
+        let signature = match request(self.endpoint, &sign, self.timeout)? {
+            SignerResponse::Signature(signature) => signature,
+            SignerResponse::Refused(reason) => return Err(Box::new(SignerError::new(&reason))),
+            _ => return Err(Box::new(SignerError::new("Unexpected response"))),
+        };
+        // a signer answering with another key would only get our transactions rejected later
+        let valid = self.wallet.key().as_ref()
+            .is_some_and(|key| access::verify_message(key, content, &signature));
+        if !valid {
+            return Err(Box::new(SignerError::new("Signature does not match the wallet key")));
+        }
+        Ok(signature)
+    }
+
+    // the device generates the key, the node only learns its public half
+    fn rotate(&self, _rng: &mut dyn CryptoRngCore) -> Result<Box<dyn Signer>, Box<dyn BlockchainError>> {
+        let rotate = SignerRequest::Rotate { public_key: self.wallet.key().clone() };
+        let rotated = match request(self.endpoint, &rotate, self.timeout)? {
+            SignerResponse::Rotated(rotated) => rotated,
+            SignerResponse::Refused(reason) => return Err(Box::new(SignerError::new(&reason))),
+            _ => return Err(Box::new(SignerError::new("Unexpected response"))),
+        };
+        let signed = match (self.wallet.key(), rotated.rotation_signature()) {
+            (Some(key), Some(signature)) => access::verify_message(key, &rotated.rotation_content(), signature),
+            _ => false
+        };
+        if rotated.address() != self.wallet.address() || !signed {
+            return Err(Box::new(SignerError::new("Rotated key is not signed by the wallet key")));
+        }
+        Ok(Box::new(RemoteSigner {
+            endpoint: self.endpoint,
+            wallet: rotated,
+            timeout: self.timeout,
+        }))
+    }
+}
+
+// signing waits on the device for as long as the timeout, the exchange itself runs on the
+// node's runtime so its other tasks carry on meanwhile
+fn request(
+    endpoint: SocketAddr, request: &SignerRequest, timeout: Duration,
+) -> Result<SignerResponse, Box<dyn BlockchainError>> {
+    let exchanged = match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(|| handle.block_on(exchange(endpoint, request, timeout)))
+        }
+        _ => match runtime::Builder::new_current_thread().enable_all().build() {
+            Ok(runtime) => runtime.block_on(exchange(endpoint, request, timeout)),
+            Err(error) => Err(error),
+        }
+    };
+    let response = match exchanged {
+        Ok(response) => response,
+        Err(error) => return Err(Box::new(SignerError::new(&format!("{} unreachable: {}", endpoint, error))))
+    };
+    match serde_json::from_str(&response) {
+        Ok(response) => Ok(response),
+        Err(_) => Err(Box::new(SignerError::new("Malformed response")))
+    }
+}
+
+async fn exchange(endpoint: SocketAddr, request: &SignerRequest, timeout: Duration) -> std::io::Result<String> {
+    let mut line = serde_json::to_string(request).unwrap();
+    line.push('\n');
+    let exchanged = time::timeout(timeout, async {
+        let mut stream = TcpStream::connect(endpoint).await?;
+        stream.write_all(line.as_bytes()).await?;
+        let mut response = String::new();
+        tokio::io::BufReader::new(stream).read_line(&mut response).await?;
+        Ok(response)
+    }).await;
+    match exchanged {
+        Ok(response) => response,
+        Err(_) => Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "no answer in time")),
+    }
+}
+
+// whoever sits at the signing device, every request waits for their consent
+pub trait SigningOperator {
+    fn confirm(&mut self, request: &str) -> bool;
+
+    // the device keeps the rotated key next to the old one, the operator stores it
+    fn key_rotated(&mut self, hot_wallet: &HotWallet) -> Result<(), Box<dyn BlockchainError>>;
+}
+
+// reference signer side, answers requests with keys the node never loads. Keys are oldest first,
+// requests naming no key are served with the newest.
+pub fn serve(
+    listener: &TcpListener, mut keys: Vec<HotWallet>, operator: &mut dyn SigningOperator,
+    rng: &mut dyn CryptoRngCore, requests: Option<usize>,
+) -> Result<(), Box<dyn BlockchainError>> {
+    if keys.is_empty() {
+        return Err(Box::new(SignerError::new("No key loaded")));
+    }
+    for stream in listener.incoming().take(requests.unwrap_or(usize::MAX)) {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => return Err(Box::new(SignerError::new(&error.to_string())))
+        };
+        let mut line = String::new();
+        let mut reader = BufReader::new(&stream);
+        if reader.read_line(&mut line).is_err() {
+            continue;
+        }
+        let response = match serde_json::from_str::<SignerRequest>(&line) {
+            Ok(SignerRequest::Wallet) => {
+                let wallet = keys.last().unwrap().wallet();
+                SignerResponse::Wallet {
+                    address: access::encode_address(wallet.address()),
+                    public_key: wallet.key().clone().unwrap(),
+                }
+            }
+            Ok(SignerRequest::Sign { content, public_key }) => match held_key(&keys, &public_key) {
+                None => SignerResponse::Refused(String::from("Unknown key")),
+                Some(_) if !operator.confirm(&format!("Sign {}", content)) => {
+                    SignerResponse::Refused(String::from("Refused by the operator"))
+                }
+                Some(hot_wallet) => SignerResponse::Signature(hot_wallet.sign_message(&content, &mut *rng)),
+            },
+            Ok(SignerRequest::Rotate { public_key }) => match held_key(&keys, &public_key) {
+                None => SignerResponse::Refused(String::from("Unknown key")),
+                Some(hot_wallet) => {
+                    let request = format!("Rotate the key of {}", access::encode_address(hot_wallet.address()));
+                    match operator.confirm(&request) {
+                        false => SignerResponse::Refused(String::from("Refused by the operator")),
+                        true => match rotate_held_key(hot_wallet, operator, rng) {
+                            Ok(rotated) => {
+                                let response = SignerResponse::Rotated(rotated.wallet().clone());
+                                keys.push(rotated);
+                                response
+                            }
+                            Err(error) => SignerResponse::Refused(error.message()),
+                        }
+                    }
+                }
+            },
+            Err(_) => SignerResponse::Refused(String::from("Malformed request")),
+        };
+        let mut answer = serde_json::to_string(&response).unwrap();
+        answer.push('\n');
+        let _ = (&stream).write_all(answer.as_bytes());
+    }
+    Ok(())
+}
+
+fn held_key<'a>(keys: &'a [HotWallet], public_key: &Option<RsaPublicKey>) -> Option<&'a HotWallet> {
+    match public_key {
+        None => keys.last(),
+        Some(public_key) => keys.iter().rfind(|hot_wallet| hot_wallet.wallet().key().as_ref() == Some(public_key)),
+    }
+}
+
+fn rotate_held_key(
+    hot_wallet: &HotWallet, operator: &mut dyn SigningOperator, rng: &mut dyn CryptoRngCore,
+) -> Result<HotWallet, Box<dyn BlockchainError>> {
+    let rotated = HotWallet::rotated_from(hot_wallet, rng)?;
+    operator.key_rotated(&rotated)?;
+    Ok(rotated)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use crate::blockchain::{Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::{self, HotWallet};
+    use crate::blockchain::core::BlockchainError;
+    use crate::blockchain::signer::{self, RemoteSigner, Signer, SigningOperator};
+    use crate::random;
+
+    struct Operator {
+        consents: bool,
+        rotated: Vec<Wallet>,
+    }
+
+    impl SigningOperator for Operator {
+        fn confirm(&mut self, _request: &str) -> bool {
+            self.consents
+        }
+
+        fn key_rotated(&mut self, hot_wallet: &HotWallet) -> Result<(), Box<dyn BlockchainError>> {
+            self.rotated.push(hot_wallet.wallet().clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn remote_signer_signs_with_a_key_outside_the_node() {
+        let mut rng = random::seeded(6);
+        let device = HotWallet::generate(&mut rng);
+        let address = device.address();
+        let public_key = device.wallet().key().clone().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap();
+        let device = thread::spawn(move || {
+            let mut rng = random::seeded(7);
+            let mut operator = Operator { consents: true, rotated: Vec::new() };
+            signer::serve(&listener, vec![device], &mut operator, &mut rng, Some(5)).ok().map(|_| operator.rotated)
+        });
+
+        // the device generates the rotated key while the test runner keeps the other cores busy
+        let remote = RemoteSigner::connect(endpoint).ok().unwrap().with_timeout(Duration::from_secs(600));
+        assert_eq!(remote.address(), address);
+        let mut transaction = Transaction::new(
+            address, MINTING_WALLET_ADDRESS, "".to_string(), 5, Utc::now(),
+        );
+        assert!(remote.sign(&mut transaction, &mut rng).is_ok());
+        let signature = transaction.sender_signature().clone().unwrap();
+        assert!(access::verify_message(&public_key, &transaction.signed_content(), &signature));
+
+        // the rotated key stays on the device, the old one keeps signing until it is committed
+        let rotated = remote.rotate(&mut rng).ok().unwrap();
+        assert!(rotated.hot_wallet().is_none());
+        assert_eq!(rotated.address(), address);
+        let rotated_key = rotated.wallet().key().clone().unwrap();
+        assert!(rotated_key != public_key);
+        let content = transaction.signed_content();
+        let signature = rotated.sign_message(&content, &mut rng).ok().unwrap();
+        assert!(access::verify_message(&rotated_key, &content, &signature));
+        assert!(remote.sign_message(&content, &mut rng).is_ok());
+        let stored = device.join().unwrap().unwrap();
+        assert!(stored.len() == 1 && stored[0].key() == &Some(rotated_key));
+    }
+
+    #[test]
+    fn remote_signing_waits_for_the_operator() {
+        let mut rng = random::seeded(8);
+        let device = HotWallet::generate(&mut rng);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = listener.local_addr().unwrap();
+        let device = thread::spawn(move || {
+            let mut rng = random::seeded(9);
+            let mut operator = Operator { consents: false, rotated: Vec::new() };
+            signer::serve(&listener, vec![device], &mut operator, &mut rng, Some(3)).ok().map(|_| operator.rotated)
+        });
+
+        let remote = RemoteSigner::connect(endpoint).ok().unwrap();
+        assert!(remote.sign_message("anything", &mut rng).is_err());
+        assert!(remote.rotate(&mut rng).is_err());
+        assert!(device.join().unwrap().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::{Address, BlockchainData, Transaction};
+use crate::blockchain::core::{Blockchain, StateRoot};
+
+// committed balances are snapshotted every this many blocks
+pub static SNAPSHOT_INTERVAL: u64 = 100;
+
+// Committed balances after a block, so balances at a past height start from the nearest
+// snapshot instead of replaying every block since. The state root hashes the balances, nodes
+// holding the same chain hold the same roots.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    height: u64,
+    // non-zero balances sorted by address
+    balances: Vec<(Address, i64)>,
+    state_root: String,
+}
+
+impl BalanceSnapshot {
+    pub fn take(height: u64, balances: &HashMap<Address, i64>) -> BalanceSnapshot {
+        let mut balances: Vec<(Address, i64)> = balances.iter()
+            .filter(|(_, balance)| **balance != 0)
+            .map(|(address, balance)| (*address, *balance))
+            .collect();
+        balances.sort();
+        BalanceSnapshot {
+            height,
+            state_root: state_root(&balances),
+            balances,
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn state_root(&self) -> &str {
+        &self.state_root
+    }
+
+    pub fn account_count(&self) -> usize {
+        self.balances.len()
+    }
+
+    pub fn balance(&self, address: Address) -> i64 {
+        match self.balances.binary_search_by(|(held_by, _)| held_by.cmp(&address)) {
+            Ok(position) => self.balances[position].1,
+            Err(_) => 0
+        }
+    }
+}
+
+pub fn state_root(balances: &[(Address, i64)]) -> String {
+    let mut hasher = Sha256::new();
+    for (address, balance) in balances {
+        hasher.update(address);
+        hasher.update(balance.to_be_bytes());
+    }
+    array_bytes::bytes2hex("", hasher.finalize())
+}
+
+// Account state after a block: committed balances with the block's changes applied, and the
+// stake every wallet has bid into won rounds. Forgers put it in the block header and voters
+// recompute it, so nodes whose state drifted apart notice at the next block.
+pub fn account_state_root(
+    transactions: &Blockchain<Transaction>, data: &[Transaction], stakes: &Blockchain<Transaction>,
+) -> StateRoot {
+    let mut balances = transactions.committed_balances().clone();
+    for (address, change) in data.iter().flat_map(Transaction::balance_changes) {
+        *balances.entry(address).or_insert(0) += change;
+    }
+    let balances = BalanceSnapshot::take(0, &balances).balances;
+    let staked = BalanceSnapshot::take(0, stakes.committed_balances()).balances;
+    let mut hasher = Sha256::new();
+    for (address, balance) in balances.iter().chain(&staked) {
+        hasher.update(address);
+        hasher.update(balance.to_be_bytes());
+    }
+    // two sections, so a balance cannot pass for a stake
+    hasher.update((balances.len() as u64).to_be_bytes());
+    hasher.finalize().into()
+}
+
+// snapshots of one chain, oldest first
+#[derive(Default)]
+pub struct SnapshotIndex {
+    snapshots: Vec<BalanceSnapshot>,
+}
+
+impl SnapshotIndex {
+    // takes a snapshot if one is due at the height, blocks re-appended after a rollback replace theirs
+    pub fn record(&mut self, height: u64, balances: &HashMap<Address, i64>) -> Option<&BalanceSnapshot> {
+        if !height.is_multiple_of(SNAPSHOT_INTERVAL) {
+            return None;
+        }
+        self.snapshots.retain(|snapshot| snapshot.height < height);
+        self.snapshots.push(BalanceSnapshot::take(height, balances));
+        self.snapshots.last()
+    }
+
+    // the newest snapshot not above the height
+    pub fn at_or_below(&self, height: u64) -> Option<&BalanceSnapshot> {
+        self.snapshots.iter().rev().find(|snapshot| snapshot.height <= height)
+    }
+
+    pub fn latest(&self) -> Option<&BalanceSnapshot> {
+        self.snapshots.last()
+    }
+
+    pub fn above(&self, height: u64) -> impl Iterator<Item=&BalanceSnapshot> {
+        self.snapshots.iter().filter(move |snapshot| snapshot.height > height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::snapshot::SNAPSHOT_INTERVAL;
+
+    #[test]
+    fn past_balances_start_from_the_nearest_snapshot() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 1000, Utc::now())
+        ]);
+        let tip = 2 * SNAPSHOT_INTERVAL + 30;
+        for _ in 1..=tip {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], "".to_string(), 2, Utc::now())
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+
+        let snapshot = transactions.snapshot_at(SNAPSHOT_INTERVAL + 5).unwrap();
+        assert_eq!(snapshot.height(), SNAPSHOT_INTERVAL);
+        assert_eq!(snapshot.balance([2; 32]), 2 * SNAPSHOT_INTERVAL as i64);
+        assert_eq!(snapshot.balance([3; 32]), 0);
+        assert_eq!(transactions.latest_snapshot().unwrap().height(), 2 * SNAPSHOT_INTERVAL);
+        // one height read through a snapshot, the other undone from the tip
+        assert_eq!(transactions.committed_balance_at([2; 32], SNAPSHOT_INTERVAL + 5).ok(), Some(210));
+        assert_eq!(transactions.committed_balance_at([1; 32], tip - 5).ok(), Some(1000 - 2 * (tip as i64 - 5)));
+        assert_eq!(transactions.committed_balance_at([2; 32], 0).ok(), Some(0));
+
+        let copy = transactions.snapshot_at(tip).unwrap().clone();
+        let mut other = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 1000, Utc::now())
+        ]);
+        other.replace(transactions);
+        assert_eq!(other.latest_snapshot().map(|snapshot| snapshot.state_root().to_string()), Some(copy.state_root().to_string()));
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use rsa::RsaPublicKey;
+
+use crate::blockchain::{Address, RejectionReason, Transaction, TransactionValidationError, Wallet, wallet_key_history};
+use crate::blockchain::access;
+use crate::blockchain::protocol::{self, STAKE_WALLET_ADDRESS, TOTAL_SUPPLY};
+use crate::blockchain::contract::Contract;
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, ChainValidationError, Validate};
+
+// number of staking epochs a winning bid stays locked, long enough to slash a misbehaving forger
+pub static UNBONDING_PERIOD: u64 = 10;
+
+// Stake as the chains record it. The winning bid of every stakes block is bonded from that
+// epoch on and stays locked for UNBONDING_PERIOD epochs, penalties on the transaction chain
+// burn part of a bond out of its owner's balance. Every node holding the same chains derives
+// the same registry, so it can weigh votes and lock balances; bids of a round in progress lock
+// nothing until the winning one is on the stakes chain.
+pub struct StakeRegistry {
+    // by wallet and the stakes block the bid was recorded in, net of penalties
+    bonds: HashMap<(Address, u64), i64>,
+}
+
+impl StakeRegistry {
+    pub fn derive(stakes: &Blockchain<Transaction>, transactions: &Blockchain<Transaction>) -> StakeRegistry {
+        let first_locked = stakes.chain_length().saturating_sub(UNBONDING_PERIOD);
+        let mut bonds = HashMap::new();
+        for block in stakes.iter().take_while(|block| block.block_number() >= first_locked) {
+            if let Some(bid) = block.data().first() {
+                bonds.insert((bid.source_address(), block.block_number()), bid.amount().max(0));
+            }
+        }
+        for penalty in transactions.iter_transactions() {
+            if let Some(Contract::Penalty { epoch }) = penalty.contract() {
+                if let Some(bonded) = bonds.get_mut(&(penalty.source_address(), *epoch)) {
+                    *bonded = (*bonded - penalty.amount()).max(0);
+                }
+            }
+        }
+        StakeRegistry {
+            bonds,
+        }
+    }
+
+    // stake still locked, what the wallet weighs in stake weighted quorums
+    pub fn bonded(&self, address: Address) -> i64 {
+        self.bonds.iter()
+            .filter(|((owner, _), _)| *owner == address)
+            .map(|(_, bonded)| *bonded)
+            .sum()
+    }
+
+    // what is left to slash of the bond made at the epoch, none once it is unlocked
+    pub fn bond(&self, address: Address, epoch: u64) -> Option<i64> {
+        self.bonds.get(&(address, epoch)).copied()
+    }
+
+    // the bond less the penalties still waiting in the mempool, what a new penalty may burn
+    pub fn unpenalized(&self, pending: &[Transaction], address: Address, epoch: u64) -> i64 {
+        let charged: i64 = pending.iter()
+            .filter(|penalty| penalty.source_address() == address)
+            .filter(|penalty| matches!(penalty.contract(), Some(Contract::Penalty { epoch: charged }) if *charged == epoch))
+            .map(Transaction::amount)
+            .sum();
+        (self.bond(address, epoch).unwrap_or(0) - charged).max(0)
+    }
+}
+
+// A stakes block records the winning bid of one round and nothing else, signed by the wallet
+// it bonds. Bids from wallets the wallet chain does not know would bond stake nobody can be
+// held to.
+pub struct StakeValidator<'a> {
+    wallets: &'a Blockchain<Wallet>,
+}
+
+impl<'a> Validate<Transaction> for StakeValidator<'a> {
+    fn block_valid(&self, block: &BlockCandidate<Transaction>) -> Result<(), Box<dyn BlockchainError>> {
+        match self.diagnose(block) {
+            Ok(_) => Ok(()),
+            Err(reason) => Err(Box::new(reason))
+        }
+    }
+}
+
+impl<'a> StakeValidator<'a> {
+    pub fn new(wallets: &'a Blockchain<Wallet>) -> StakeValidator<'a> {
+        StakeValidator {
+            wallets,
+        }
+    }
+
+    pub fn diagnose(&self, block: &BlockCandidate<Transaction>) -> Result<(), RejectionReason> {
+        let bid = match block.data().as_slice() {
+            [bid] => bid,
+            _ => return Err(RejectionReason::Malformed(String::from("A stakes block records exactly one bid")))
+        };
+        let invalid = |error| Err(RejectionReason::InvalidTransaction { id: bid.id(), error });
+        if bid.target_address() != *STAKE_WALLET_ADDRESS || bid.contract().is_some() || protocol::is_reserved(&bid.source_address()) {
+            return invalid(TransactionValidationError::BadContract);
+        }
+        if bid.amount() <= 0 {
+            return invalid(TransactionValidationError::NonPositiveAmount { amount: bid.amount() });
+        }
+        // bids recorded before a key rotation were signed with a key the wallet had back then
+        let keys: Vec<RsaPublicKey> = wallet_key_history(bid.source_address(), self.wallets).into_iter()
+            .map(|(_, key)| key)
+            .collect();
+        if keys.is_empty() {
+            return invalid(TransactionValidationError::UnknownSourceWallet);
+        }
+        match bid_signed(bid, &keys) {
+            Ok(_) => Ok(()),
+            Err(error) => invalid(error)
+        }
+    }
+}
+
+// the bid's signature matches one of the keys of the wallet it bids from
+pub fn bid_signed(bid: &Transaction, keys: &[RsaPublicKey]) -> Result<(), TransactionValidationError> {
+    let signature = match bid.sender_signature() {
+        None => return Err(TransactionValidationError::MissingSignature),
+        Some(signature) => signature
+    };
+    let content = bid.signed_content();
+    match keys.iter().any(|key| access::verify_message(key, &content, signature)) {
+        true => Ok(()),
+        false => Err(TransactionValidationError::BadSignature)
+    }
+}
+
+// Checks a stakes chain received from a peer against the transaction and wallet chains it came
+// with. The chain mints nothing, so its pool stays whole, and the stake still bonded at its tip
+// is covered by what each bidder holds, bonded stake cannot be spent.
+pub fn verify(
+    stakes: &Blockchain<Transaction>, transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>,
+) -> Result<(), Box<dyn BlockchainError>> {
+    if let Some(genesis) = stakes.blocks().next() {
+        if !genesis?.data().is_empty() {
+            return Err(Box::new(ChainValidationError::new(0, "Stakes genesis block records bids")));
+        }
+    }
+    if stakes.remaining_pool() != TOTAL_SUPPLY {
+        return Err(Box::new(ChainValidationError::new(
+            stakes.chain_length(), &format!("Stakes chain claims a pool of {}", stakes.remaining_pool()),
+        )));
+    }
+    stakes.verify_full(|_, block| StakeValidator::new(wallets).block_valid(block))?;
+    let registry = StakeRegistry::derive(stakes, transactions);
+    for (address, _) in registry.bonds.keys() {
+        let (bonded, balance) = (registry.bonded(*address), transactions.committed_balance(*address));
+        if bonded > balance {
+            return Err(Box::new(ChainValidationError::new(
+                stakes.chain_length(), &format!("{} bonded from a balance of {}", bonded, balance),
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use rand::rngs::StdRng;
+
+    use crate::blockchain::{RejectionReason, StakeBid, Transaction, TransactionValidationError, Wallet};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, STAKE_WALLET_ADDRESS};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::stake::{self, StakeRegistry, StakeValidator, UNBONDING_PERIOD};
+    use crate::random;
+
+    fn record_bid(stakes: &mut Blockchain<Transaction>, address: [u8; 32], amount: i64) -> u64 {
+        let block = BlockCandidate::create_new(vec![Transaction::stake_bid(amount, address)], stakes.last_block()).ok().unwrap();
+        stakes.submit_new_block(block).block_number()
+    }
+
+    fn record_signed_bid(stakes: &mut Blockchain<Transaction>, bidder: &HotWallet, amount: i64, rng: &mut StdRng) {
+        let bid = StakeBid::signed(amount, "bidder", bidder, rng).ok().unwrap();
+        let block = BlockCandidate::create_new(vec![bid.transaction().clone()], stakes.last_block()).ok().unwrap();
+        stakes.submit_new_block(block);
+    }
+
+    #[test]
+    fn stake_is_derived_from_the_chains() {
+        let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now()),
+        ]);
+        let first = record_bid(&mut stakes, [1; 32], 40);
+        let second = record_bid(&mut stakes, [1; 32], 30);
+        record_bid(&mut stakes, [2; 32], 25);
+
+        let registry = StakeRegistry::derive(&stakes, &transactions);
+        assert_eq!(registry.bonded([1; 32]), 70);
+        assert_eq!(registry.bond([1; 32], first), Some(40));
+        assert_eq!(registry.bond([2; 32], first), None);
+
+        // the penalty comes out of the owner's balance and the bond it was charged against
+        let penalty = Transaction::penalty([1; 32], 10, second, "Leak", Utc::now());
+        let block = BlockCandidate::create_new(vec![penalty], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        let registry = StakeRegistry::derive(&stakes, &transactions);
+        assert_eq!(registry.bond([1; 32], second), Some(20));
+        assert_eq!(registry.bonded([1; 32]), 60);
+        assert_eq!(transactions.balance_of([1; 32]), 90);
+        let pending = [Transaction::penalty([1; 32], 15, second, "Slash", Utc::now())];
+        assert_eq!(registry.unpenalized(&pending, [1; 32], second), 5);
+        assert_eq!(registry.unpenalized(&pending, [1; 32], first), 40);
+
+        for _ in 0..UNBONDING_PERIOD {
+            record_bid(&mut stakes, [3; 32], 5);
+        }
+        let registry = StakeRegistry::derive(&stakes, &transactions);
+        assert_eq!(registry.bonded([1; 32]), 0);
+        assert_eq!(registry.bonded([3; 32]), 5 * UNBONDING_PERIOD as i64);
+    }
+
+    #[test]
+    fn bids_lock_nothing_until_one_is_recorded() {
+        let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now()),
+            Transaction::new(MINTING_WALLET_ADDRESS, [2; 32], "".to_string(), 100, Utc::now()),
+        ]);
+        // bids published in a round are only gossiped, none of them is escrowed
+        stakes.add_uncommitted(Transaction::stake_bid(60, [2; 32]));
+        assert_eq!(transactions.staked_breakdown(&stakes, [2; 32]).spendable(), 100);
+
+        record_bid(&mut stakes, [1; 32], 60);
+        assert_eq!(transactions.staked_breakdown(&stakes, [1; 32]).spendable(), 40);
+        assert_eq!(transactions.staked_breakdown(&stakes, [2; 32]).spendable(), 100);
+    }
+
+    #[test]
+    fn synced_stakes_hold_registered_bids_their_bidders_cover() {
+        let mut rng = random::seeded(41);
+        let bidder = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(vec![bidder.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, bidder.address(), "".to_string(), 100, Utc::now()),
+        ]);
+
+        let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        record_signed_bid(&mut stakes, &bidder, 60, &mut rng);
+        assert!(stake::verify(&stakes, &transactions, &wallets).is_ok());
+        // bonded twice over what the bidder holds
+        record_signed_bid(&mut stakes, &bidder, 60, &mut rng);
+        assert!(stake::verify(&stakes, &transactions, &wallets).is_err());
+
+        let mut unsigned = Blockchain::<Transaction>::transaction_chain(vec![]);
+        record_bid(&mut unsigned, bidder.address(), 10);
+        assert!(stake::verify(&unsigned, &transactions, &wallets).is_err());
+
+        let mut unknown = Blockchain::<Transaction>::transaction_chain(vec![]);
+        record_bid(&mut unknown, [7; 32], 10);
+        assert!(stake::verify(&unknown, &transactions, &wallets).is_err());
+        let mut minting = Blockchain::<Transaction>::transaction_chain(vec![]);
+        record_bid(&mut minting, MINTING_WALLET_ADDRESS, 10);
+        assert!(stake::verify(&minting, &transactions, &wallets).is_err());
+    }
+
+    #[test]
+    fn bids_count_for_the_registered_wallet_that_signed_them_for_the_sending_peer() {
+        let mut rng = random::seeded(45);
+        let bidder = HotWallet::generate(&mut rng);
+        let impostor = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![bidder.wallet().clone(), impostor.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+
+        let bid = StakeBid::signed(60, "bidder", &bidder, &mut rng).ok().unwrap();
+        assert!(bid.verify("bidder", &wallets).is_ok());
+        // passed on by another peer as its own
+        assert_eq!(bid.verify("impostor", &wallets), Err(TransactionValidationError::BadSignature));
+        assert_eq!(StakeBid::bid(60, bidder.address()).verify("impostor", &wallets), Err(TransactionValidationError::MissingSignature));
+
+        // the impostor signs for the bidder's wallet
+        let mut claimed = Transaction::new(bidder.address(), *STAKE_WALLET_ADDRESS, "impostor".to_string(), 60, Utc::now());
+        impostor.sign(&mut claimed, &mut rng);
+        let claimed = StakeBid { stake: 60, transaction: claimed };
+        assert_eq!(claimed.verify("impostor", &wallets), Err(TransactionValidationError::BadSignature));
+    }
+
+    #[test]
+    fn stakes_blocks_record_exactly_one_signed_bid() {
+        let mut rng = random::seeded(47);
+        let bidder = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(vec![bidder.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let bid = || StakeBid::signed(10, "bidder", &bidder, &mut random::seeded(48)).ok().unwrap().transaction().clone();
+        let block = |bids: Vec<Transaction>| BlockCandidate::create_new(bids, stakes.last_block()).ok().unwrap();
+        let validator = StakeValidator::new(&wallets);
+
+        assert!(matches!(validator.diagnose(&block(vec![])), Err(RejectionReason::Malformed(_))));
+        assert!(matches!(validator.diagnose(&block(vec![bid(), bid()])), Err(RejectionReason::Malformed(_))));
+        assert!(validator.diagnose(&block(vec![bid()])).is_ok());
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::blockchain::BlockchainData;
+use crate::blockchain::core::{Block, BlockCandidate, BlockchainError, StorageError};
+use crate::blockchain::snapshot::BalanceSnapshot;
+use crate::network::communication::BlockDto;
+
+// Committed blocks of one chain, a json file per block named after its number. Bounded chains
+// keep only their newest blocks in memory and read older ones back from here. Balance
+// snapshots are kept next to the block they were taken after.
+#[derive(Clone)]
+pub struct BlockStore {
+    dir: PathBuf,
+}
+
+impl BlockStore {
+    pub fn open(dir: PathBuf) -> Result<BlockStore, Box<dyn BlockchainError>> {
+        if let Err(error) = fs::create_dir_all(&dir) {
+            return Err(Box::new(StorageError::new(&format!("{}: {}", dir.display(), error))));
+        }
+        Ok(BlockStore {
+            dir,
+        })
+    }
+
+    pub fn write<T>(&self, block: &Block<T>) -> Result<(), Box<dyn BlockchainError>> where T: BlockchainData {
+        let content = serde_json::to_string(&BlockDto::from(block)).unwrap();
+        match fs::write(self.path(block.block_number()), content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn read<T>(&self, block_number: u64) -> Result<Block<T>, Box<dyn BlockchainError>> where T: BlockchainData {
+        let missing = |error: String| -> Box<dyn BlockchainError> {
+            Box::new(StorageError::new(&format!("Block {} unreadable: {}", block_number, error)))
+        };
+        let content = fs::read_to_string(self.path(block_number)).map_err(|error| missing(error.to_string()))?;
+        let block_dto: BlockDto<T> = serde_json::from_str(&content).map_err(|error| missing(error.to_string()))?;
+        if block_dto.block_number() != block_number {
+            return Err(missing(String::from("numbered differently")));
+        }
+        Ok(Block::stored(BlockCandidate::try_from(block_dto)?))
+    }
+
+    pub fn write_snapshot(&self, snapshot: &BalanceSnapshot) -> Result<(), Box<dyn BlockchainError>> {
+        let content = serde_json::to_string(snapshot).unwrap();
+        match fs::write(self.dir.join(format!("snapshot-{}.json", snapshot.height())), content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    fn path(&self, block_number: u64) -> PathBuf {
+        self.dir.join(format!("{}.json", block_number))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::invariants;
+    use crate::blockchain::store::BlockStore;
+
+    #[test]
+    fn bounded_chain_streams_old_blocks_from_disk() {
+        let dir = env::temp_dir().join(format!("kingcoin-store-{}", std::process::id()));
+        let store = BlockStore::open(dir.clone()).ok().unwrap();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]).with_store(store, 3).ok().unwrap();
+        for amount in 1..=6 {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], "".to_string(), amount, Utc::now())
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+
+        assert_eq!(transactions.resident_blocks().len(), 3);
+        let numbers: Vec<u64> = transactions.blocks()
+            .map(|block| block.ok().unwrap().block_number())
+            .collect();
+        assert_eq!(numbers, (0..7).collect::<Vec<u64>>());
+        assert_eq!(transactions.block_at(1).ok().flatten().map(|block| block.data()[0].amount()), Some(1));
+        assert_eq!(transactions.committed_balance_at([2; 32], 2).ok(), Some(3));
+        assert!(transactions.block_at(0).ok().flatten().is_some_and(|genesis| genesis.time().is_none()));
+        assert!(invariants::audit(&transactions).is_empty());
+
+        fs::remove_file(dir.join("2.json")).unwrap();
+        assert!(transactions.blocks().any(|block| block.is_err()));
+        assert!(!invariants::audit(&transactions).is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,266 @@
+use lazy_static::lazy_static;
+
+use crate::blockchain::protocol::{self, BLOCK_SIZE, CANONICAL_ORDER_HEIGHT, GRANT_WORK_BITS, GRANTS_PER_BLOCK, RESERVED_TARGETS_HEIGHT, TRANSACTION_FEE, TRANSFER_FEE, WALLET_GRANT};
+use crate::blockchain::core::BlockchainError;
+use crate::blockchain::governance::Parameter;
+
+lazy_static! {
+    // the network's genesis file is applied before anything reads the schedule
+    pub static ref UPGRADE_SCHEDULE: UpgradeSchedule = {
+        let mut genesis_rules = ConsensusRules::new(
+            TRANSACTION_FEE, TRANSFER_FEE, BLOCK_SIZE, SignatureScheme::RsaPssSha512,
+        ).with_wallet_grant(WALLET_GRANT)
+            .with_state_roots();
+        for parameter in protocol::genesis_overrides().parameters() {
+            genesis_rules.amend(parameter);
+        }
+        UpgradeSchedule {
+            activations: vec![
+                (0, genesis_rules),
+                (CANONICAL_ORDER_HEIGHT, genesis_rules.with_canonical_order()),
+                (RESERVED_TARGETS_HEIGHT, genesis_rules.with_canonical_order().with_reserved_targets()),
+            ],
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    RsaPssSha512,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsensusRules {
+    block_reward: i64,
+    transfer_fee: i64,
+    block_size: u64,
+    signature_scheme: SignatureScheme,
+    wallet_grant: i64,
+    // difficulty of the grant puzzle in leading zero bits
+    grant_work_bits: u32,
+    grants_per_block: usize,
+    // transactions of a block sorted by sender, nonce and hash
+    canonical_order: bool,
+    // transaction blocks carry the root of the account state they leave behind
+    state_roots: bool,
+    // transfers to the minting and stake wallets are refused
+    reserved_targets: bool,
+}
+
+impl ConsensusRules {
+    pub fn new(
+        block_reward: i64, transfer_fee: i64, block_size: u64, signature_scheme: SignatureScheme,
+    ) -> ConsensusRules {
+        ConsensusRules {
+            block_reward,
+            transfer_fee,
+            block_size,
+            signature_scheme,
+            wallet_grant: 0,
+            grant_work_bits: GRANT_WORK_BITS,
+            grants_per_block: GRANTS_PER_BLOCK,
+            canonical_order: false,
+            state_roots: false,
+            reserved_targets: false,
+        }
+    }
+
+    pub fn with_wallet_grant(mut self, wallet_grant: i64) -> Self {
+        self.wallet_grant = wallet_grant;
+        self
+    }
+
+    pub fn with_grant_work(mut self, grant_work_bits: u32) -> Self {
+        self.grant_work_bits = grant_work_bits;
+        self
+    }
+
+    pub fn with_grants_per_block(mut self, grants_per_block: usize) -> Self {
+        self.grants_per_block = grants_per_block;
+        self
+    }
+
+    pub fn with_canonical_order(mut self) -> Self {
+        self.canonical_order = true;
+        self
+    }
+
+    pub fn with_state_roots(mut self) -> Self {
+        self.state_roots = true;
+        self
+    }
+
+    pub fn with_reserved_targets(mut self) -> Self {
+        self.reserved_targets = true;
+        self
+    }
+
+    pub fn block_reward(&self) -> i64 {
+        self.block_reward
+    }
+
+    pub fn transfer_fee(&self) -> i64 {
+        self.transfer_fee
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    pub fn signature_scheme(&self) -> SignatureScheme {
+        self.signature_scheme
+    }
+
+    pub fn wallet_grant(&self) -> i64 {
+        self.wallet_grant
+    }
+
+    pub fn grant_work_bits(&self) -> u32 {
+        self.grant_work_bits
+    }
+
+    pub fn grants_per_block(&self) -> usize {
+        self.grants_per_block
+    }
+
+    pub fn canonical_order(&self) -> bool {
+        self.canonical_order
+    }
+
+    pub fn state_roots(&self) -> bool {
+        self.state_roots
+    }
+
+    pub fn reserved_targets(&self) -> bool {
+        self.reserved_targets
+    }
+
+    pub fn amend(&mut self, parameter: Parameter) {
+        match parameter {
+            Parameter::BlockReward(block_reward) => self.block_reward = block_reward,
+            Parameter::TransferFee(transfer_fee) => self.transfer_fee = transfer_fee,
+            Parameter::BlockSize(block_size) => self.block_size = block_size,
+            Parameter::WalletGrant(wallet_grant) => self.wallet_grant = wallet_grant,
+        }
+    }
+}
+
+pub struct UpgradeError {
+    activation_height: u64,
+}
+
+impl BlockchainError for UpgradeError {
+    fn message(&self) -> String {
+        format!(
+            "Upgrade at height {} must activate after all previously scheduled upgrades",
+            self.activation_height
+        )
+    }
+}
+
+pub struct UpgradeSchedule {
+    // (activation height, rules) sorted by height, the first entry activates at genesis
+    activations: Vec<(u64, ConsensusRules)>,
+}
+
+impl UpgradeSchedule {
+    pub fn new(genesis_rules: ConsensusRules) -> UpgradeSchedule {
+        UpgradeSchedule {
+            activations: vec![(0, genesis_rules)],
+        }
+    }
+
+    pub fn schedule(
+        &mut self, activation_height: u64, rules: ConsensusRules,
+    ) -> Result<(), Box<dyn BlockchainError>> {
+        let (last_height, _) = self.activations[self.activations.len() - 1];
+        if activation_height <= last_height {
+            return Err(Box::new(UpgradeError { activation_height }));
+        }
+        self.activations.push((activation_height, rules));
+        Ok(())
+    }
+
+    pub fn rules_at(&self, block_number: u64) -> &ConsensusRules {
+        let (_, rules) = self.activations.iter()
+            .rev()
+            .find(|(activation_height, _)| *activation_height <= block_number)
+            .expect("Genesis rules are always scheduled");
+        rules
+    }
+
+    pub fn amended(&self, amendments: &[(u64, Parameter)]) -> UpgradeSchedule {
+        let mut heights: Vec<u64> = self.activations.iter()
+            .map(|(activation_height, _)| *activation_height)
+            .chain(amendments.iter().map(|(activation_height, _)| *activation_height))
+            .collect();
+        heights.sort_unstable();
+        heights.dedup();
+        let activations = heights.into_iter()
+            .map(|height| {
+                let mut rules = *self.rules_at(height);
+                amendments.iter()
+                    .filter(|(activation_height, _)| *activation_height <= height)
+                    .for_each(|(_, parameter)| rules.amend(*parameter));
+                (height, rules)
+            })
+            .collect();
+        UpgradeSchedule {
+            activations,
+        }
+    }
+
+    pub fn next_activation(&self, block_number: u64) -> Option<u64> {
+        self.activations.iter()
+            .map(|(activation_height, _)| *activation_height)
+            .find(|activation_height| *activation_height > block_number)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::governance::Parameter;
+    use crate::blockchain::protocol::{CANONICAL_ORDER_HEIGHT, RESERVED_TARGETS_HEIGHT};
+    use crate::blockchain::upgrade::{ConsensusRules, SignatureScheme, UPGRADE_SCHEDULE, UpgradeSchedule};
+
+    #[test]
+    fn rules_switch_at_activation_height() {
+        let genesis_rules = ConsensusRules::new(50, 1, 30, SignatureScheme::RsaPssSha512);
+        let upgraded_rules = ConsensusRules::new(25, 1, 30, SignatureScheme::RsaPssSha512);
+        let mut schedule = UpgradeSchedule::new(genesis_rules);
+        assert!(schedule.schedule(100, upgraded_rules).is_ok());
+        assert!(schedule.schedule(100, genesis_rules).is_err());
+
+        assert_eq!(schedule.rules_at(99).block_reward(), 50);
+        assert_eq!(schedule.rules_at(100).block_reward(), 25);
+        assert_eq!(schedule.next_activation(0), Some(100));
+        assert_eq!(schedule.next_activation(100), None);
+    }
+
+    #[test]
+    fn amendments_survive_later_upgrades() {
+        let genesis_rules = ConsensusRules::new(50, 1, 30, SignatureScheme::RsaPssSha512);
+        let mut schedule = UpgradeSchedule::new(genesis_rules);
+        schedule.schedule(100, ConsensusRules::new(25, 1, 30, SignatureScheme::RsaPssSha512)).ok();
+
+        let amended = schedule.amended(&[(40, Parameter::TransferFee(3))]);
+        assert_eq!(amended.rules_at(39).transfer_fee(), 1);
+        assert_eq!(amended.rules_at(40).transfer_fee(), 3);
+        assert_eq!(amended.rules_at(100).transfer_fee(), 3);
+        assert_eq!(amended.rules_at(100).block_reward(), 25);
+    }
+
+    #[test]
+    fn canonical_order_activates_at_its_height() {
+        assert!(!UPGRADE_SCHEDULE.rules_at(CANONICAL_ORDER_HEIGHT - 1).canonical_order());
+        assert!(UPGRADE_SCHEDULE.rules_at(CANONICAL_ORDER_HEIGHT).canonical_order());
+        assert!(UPGRADE_SCHEDULE.rules_at(0).state_roots());
+    }
+
+    #[test]
+    fn reserved_targets_activate_at_their_height_and_keep_canonical_order() {
+        assert!(!UPGRADE_SCHEDULE.rules_at(RESERVED_TARGETS_HEIGHT - 1).reserved_targets());
+        let rules = UPGRADE_SCHEDULE.rules_at(RESERVED_TARGETS_HEIGHT);
+        assert!(rules.reserved_targets() && rules.canonical_order());
+    }
+}
@@ -0,0 +1,670 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use libp2p::PeerId;
+
+use crate::blockchain::Address;
+use crate::blockchain::access;
+use crate::blockchain::core::BlockchainError;
+use crate::blockchain::governance::Parameter;
+use crate::blockchain::protocol;
+use crate::blockchain::history::{DEFAULT_PAGE_SIZE, Flow, HistoryFilter, Page};
+use crate::command::payment_request::PaymentRequest;
+use crate::network::bid_policy::BidPolicy;
+use crate::network::rounds::DEFAULT_SHOWN_ROUNDS;
+
+pub mod batch;
+pub mod input;
+pub mod payment_request;
+
+pub enum Command {
+    Send {
+        amount: i64,
+        target_address: Address,
+        title: String,
+        // skips the confirmation prompt
+        confirmed: bool,
+        // encrypts the title so only sender and recipient can read it
+        sealed: bool,
+        // like sealed, with a secret the two wallets share once they have transacted
+        private_memo: bool,
+        // reports whether the transfer would be accepted without publishing it
+        dry_run: bool,
+    },
+    SendBatch(PathBuf),
+    Burn {
+        amount: i64,
+        confirmed: bool,
+    },
+    // sends the whole spendable balance less the fee, e.g. to retire a wallet
+    Sweep {
+        target_address: Address,
+        confirmed: bool,
+    },
+    Request {
+        amount: i64,
+        memo: Option<String>,
+        qr_code: bool,
+    },
+    Schedule(ScheduleCommand),
+    Watch(bool),
+    Balance {
+        address: Option<Address>,
+        height: Option<u64>,
+    },
+    Register,
+    // claims the one time grant of a newly registered wallet, solving its puzzle first
+    Grant,
+    // stores the wallet key sealed by a password on the credentials chain
+    RegisterLogin(String),
+    Login(String),
+    RotateKey,
+    // raw shows plain addresses instead of names
+    List {
+        raw: bool,
+        plain: bool,
+        filter: HistoryFilter,
+        // counterparty given by its name in the address book, resolved by the node
+        contact: Option<String>,
+        page: Page,
+    },
+    Contacts(ContactsCommand),
+    Wallet(WalletCommand),
+    // activity of the node's wallet over the month of the given day
+    Statement {
+        month: NaiveDate,
+        export: Option<StatementExport>,
+    },
+    Status,
+    // plain prints tab separated columns, also what piped output gets
+    Peers {
+        plain: bool,
+    },
+    // compares the chain with a peer's recent blocks
+    Diff(PeerId),
+    // outcomes of the last consensus rounds, newest first
+    Rounds(usize),
+    Validators {
+        plain: bool,
+    },
+    // pending transactions in the order forgers take them
+    Mempool {
+        plain: bool,
+    },
+    Stats,
+    // every credit and debit of the address with the blocks they were committed in
+    Audit(Address),
+    // balance snapshot at or below the height, the latest one without
+    Snapshot(Option<u64>),
+    Verify,
+    ShowBidPolicy,
+    SetBidPolicy(BidPolicy),
+    Propose {
+        change: Parameter,
+        activation_height: u64,
+    },
+    Vote {
+        proposal_id: String,
+        approve: bool,
+    },
+    Proposals,
+    Bans(BanCommand),
+    Htlc(HtlcCommand),
+    Escrow(EscrowCommand),
+    Token(TokenCommand),
+    Sponsor(SponsorCommand),
+    Exit,
+}
+
+pub enum WalletCommand {
+    // each asks for the wallet's password next
+    Create(String),
+    Use(String),
+    List,
+    // asks for the wallet's password, then for one protecting the exported file
+    ExportKey {
+        name: String,
+        path: PathBuf,
+    },
+    // a rotated key keeps the address of the wallet it was rotated into
+    ImportKey {
+        name: String,
+        path: PathBuf,
+        address: Option<Address>,
+    },
+}
+
+pub enum ContactsCommand {
+    List,
+    Add {
+        name: String,
+        address: Address,
+    },
+    Remove(String),
+}
+
+pub enum StatementExport {
+    Csv(PathBuf),
+    Json(PathBuf),
+}
+
+pub enum ScheduleCommand {
+    Send {
+        amount: i64,
+        target_address: Address,
+        first_run: DateTime<Utc>,
+        interval: Option<Duration>,
+    },
+    List,
+    Cancel(u64),
+}
+
+pub enum BanCommand {
+    List,
+    // none lifts every active ban
+    Clear(Option<PeerId>),
+}
+
+pub enum HtlcCommand {
+    Create {
+        amount: i64,
+        target_address: Address,
+        timeout: Duration,
+        // a fresh secret is generated when the counterparty did not provide a hash
+        hash_lock: Option<String>,
+    },
+    Claim {
+        lock_id: String,
+        preimage: String,
+    },
+    Refund(String),
+}
+
+pub enum EscrowCommand {
+    Open {
+        amount: i64,
+        recipient: Address,
+        arbiter: Address,
+    },
+    // approves paying the recipient
+    Release(String),
+    // approves refunding the sender
+    Dispute(String),
+}
+
+pub enum TokenCommand {
+    Create {
+        symbol: String,
+        amount: i64,
+    },
+    Send {
+        token_id: String,
+        amount: i64,
+        target_address: Address,
+    },
+    // none lists every token the wallet holds
+    Balance(Option<String>),
+}
+
+pub enum SponsorCommand {
+    // signs a transfer without a fee and writes it to the file for a sponsor
+    Request {
+        amount: i64,
+        target_address: Address,
+        title: String,
+        path: PathBuf,
+    },
+    // pays the fee of the transfer in the file and submits both, none pays the usual fee
+    Pay {
+        path: PathBuf,
+        fee: Option<i64>,
+    },
+}
+
+pub struct CommandError {
+    message: String,
+}
+
+impl CommandError {
+    pub fn new(message: &str) -> CommandError {
+        CommandError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for CommandError {
+    fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+pub fn parse(line: &str) -> Result<Command, Box<dyn BlockchainError>> {
+    let arguments = split_arguments(line)?;
+    let arguments: Vec<&str> = arguments.iter().map(String::as_str).collect();
+    match arguments.as_slice() {
+        ["send", "--batch", file] => Ok(Command::SendBatch(PathBuf::from(file))),
+        ["send", amount, target, title @ ..] => Ok(Command::Send {
+            amount: parse_amount(amount)?,
+            target_address: transfer_target(target)?,
+            title: title.iter()
+                .filter(|word| !["--yes", "--seal", "--private-memo", "--dry-run"].contains(word))
+                .copied()
+                .collect::<Vec<_>>()
+                .join(" "),
+            confirmed: title.contains(&"--yes"),
+            sealed: title.contains(&"--seal"),
+            private_memo: title.contains(&"--private-memo"),
+            dry_run: title.contains(&"--dry-run"),
+        }),
+        ["burn", amount] | ["burn", amount, "--yes"] => Ok(Command::Burn {
+            amount: parse_amount(amount)?,
+            confirmed: arguments.len() == 3,
+        }),
+        ["sweep", target] | ["sweep", target, "--yes"] => Ok(Command::Sweep {
+            target_address: transfer_target(target)?,
+            confirmed: arguments.len() == 3,
+        }),
+        ["sweep", ..] => Err(Box::new(CommandError::new("Usage: sweep <address> [--yes]"))),
+        ["request", amount, options @ ..] => parse_request(amount, options),
+        ["pay", uri] | ["pay", uri, "--yes"] => {
+            let request = PaymentRequest::parse(uri)?;
+            reject_reserved(request.target_address())?;
+            match request.amount() {
+                None => Err(Box::new(CommandError::new("Payment request has no amount"))),
+                Some(amount) => Ok(Command::Send {
+                    amount,
+                    target_address: request.target_address(),
+                    title: request.memo().unwrap_or_default().to_string(),
+                    confirmed: arguments.len() == 3,
+                    sealed: false,
+                    private_memo: false,
+                    dry_run: false,
+                })
+            }
+        }
+        ["schedule", rest @ ..] => parse_schedule(rest),
+        ["watch"] | ["watch", "on"] => Ok(Command::Watch(true)),
+        ["watch", "off"] => Ok(Command::Watch(false)),
+        ["balance", options @ ..] => parse_balance(options),
+        ["register"] => Ok(Command::Register),
+        ["grant"] => Ok(Command::Grant),
+        ["register", user_name] => Ok(Command::RegisterLogin(user_name.to_string())),
+        ["login", user_name] => Ok(Command::Login(user_name.to_string())),
+        ["rotate-key"] => Ok(Command::RotateKey),
+        ["wallet", "create", name] => Ok(Command::Wallet(WalletCommand::Create(name.to_string()))),
+        ["wallet", "use", name] => Ok(Command::Wallet(WalletCommand::Use(name.to_string()))),
+        ["wallet", "list"] => Ok(Command::Wallet(WalletCommand::List)),
+        ["wallet", "export-key", name, path] => Ok(Command::Wallet(WalletCommand::ExportKey {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+        })),
+        ["wallet", "import-key", name, path] => Ok(Command::Wallet(WalletCommand::ImportKey {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            address: None,
+        })),
+        ["wallet", "import-key", name, path, "--address", address] => Ok(Command::Wallet(WalletCommand::ImportKey {
+            name: name.to_string(),
+            path: PathBuf::from(path),
+            address: Some(access::decode_address(address)?),
+        })),
+        ["wallet", ..] => Err(Box::new(CommandError::new(
+            "Usage: wallet create <name>|use <name>|list|export-key <name> <file>|import-key <name> <file> [--address <address>]"
+        ))),
+        ["list", options @ ..] => parse_list(options),
+        ["contacts"] => Ok(Command::Contacts(ContactsCommand::List)),
+        ["contacts", "add", name, address] => Ok(Command::Contacts(ContactsCommand::Add {
+            name: name.to_string(),
+            address: access::decode_address(address)?,
+        })),
+        ["contacts", "remove", name] => Ok(Command::Contacts(ContactsCommand::Remove(name.to_string()))),
+        ["contacts", ..] => Err(Box::new(CommandError::new(
+            "Usage: contacts [add <name> <address>|remove <name>]"
+        ))),
+        ["statement", options @ ..] => parse_statement(options),
+        ["status"] => Ok(Command::Status),
+        ["peers"] => Ok(Command::Peers { plain: false }),
+        ["peers", "--plain"] => Ok(Command::Peers { plain: true }),
+        ["validators"] => Ok(Command::Validators { plain: false }),
+        ["validators", "--plain"] => Ok(Command::Validators { plain: true }),
+        ["rounds"] => Ok(Command::Rounds(DEFAULT_SHOWN_ROUNDS)),
+        ["rounds", count] => match count.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(Command::Rounds(count)),
+            _ => Err(Box::new(CommandError::new("Usage: rounds [count]")))
+        },
+        ["snapshot"] => Ok(Command::Snapshot(None)),
+        ["snapshot", height] => match height.parse::<u64>() {
+            Ok(height) => Ok(Command::Snapshot(Some(height))),
+            Err(_) => Err(Box::new(CommandError::new("Usage: snapshot [height]")))
+        },
+        ["diff", peer_id] => match PeerId::from_str(peer_id) {
+            Ok(peer_id) => Ok(Command::Diff(peer_id)),
+            Err(_) => Err(Box::new(CommandError::new("Invalid peer id")))
+        },
+        ["diff", ..] => Err(Box::new(CommandError::new("Usage: diff <peer id>"))),
+        ["mempool"] => Ok(Command::Mempool { plain: false }),
+        ["mempool", "--plain"] => Ok(Command::Mempool { plain: true }),
+        ["stats"] => Ok(Command::Stats),
+        ["audit", address] => Ok(Command::Audit(access::decode_address(address)?)),
+        ["verify"] => Ok(Command::Verify),
+        ["bid"] => Ok(Command::ShowBidPolicy),
+        ["bid", "set", policy] => Ok(Command::SetBidPolicy(BidPolicy::parse(policy)?)),
+        ["propose", name, value, "--at", height] => match height.parse() {
+            Ok(activation_height) => Ok(Command::Propose {
+                change: Parameter::parse(name, value)?,
+                activation_height,
+            }),
+            Err(_) => Err(Box::new(CommandError::new("Invalid activation height")))
+        },
+        ["propose", ..] => Err(Box::new(CommandError::new(
+            "Usage: propose reward|fee|block-size|grant <value> --at <height>"
+        ))),
+        ["vote", proposal_id, decision] => match *decision {
+            "yes" | "no" => Ok(Command::Vote {
+                proposal_id: proposal_id.to_string(),
+                approve: *decision == "yes",
+            }),
+            _ => Err(Box::new(CommandError::new("Usage: vote <proposal id> yes|no")))
+        },
+        ["proposals"] => Ok(Command::Proposals),
+        ["bans"] => Ok(Command::Bans(BanCommand::List)),
+        ["bans", "clear", "--all"] => Ok(Command::Bans(BanCommand::Clear(None))),
+        ["bans", "clear", peer_id] => match PeerId::from_str(peer_id) {
+            Ok(peer_id) => Ok(Command::Bans(BanCommand::Clear(Some(peer_id)))),
+            Err(_) => Err(Box::new(CommandError::new("Invalid peer id")))
+        },
+        ["bans", ..] => Err(Box::new(CommandError::new("Usage: bans [clear <peer id>|--all]"))),
+        ["htlc", rest @ ..] => parse_htlc(rest),
+        ["escrow", "open", amount, recipient, arbiter] => Ok(Command::Escrow(EscrowCommand::Open {
+            amount: parse_amount(amount)?,
+            recipient: transfer_target(recipient)?,
+            arbiter: access::decode_address(arbiter)?,
+        })),
+        ["escrow", "release", escrow_id] => Ok(Command::Escrow(EscrowCommand::Release(escrow_id.to_string()))),
+        ["escrow", "dispute", escrow_id] => Ok(Command::Escrow(EscrowCommand::Dispute(escrow_id.to_string()))),
+        ["token", "create", symbol, amount] => Ok(Command::Token(TokenCommand::Create {
+            symbol: symbol.to_string(),
+            amount: parse_amount(amount)?,
+        })),
+        ["token", "send", token_id, amount, target] => Ok(Command::Token(TokenCommand::Send {
+            token_id: token_id.to_string(),
+            amount: parse_amount(amount)?,
+            target_address: transfer_target(target)?,
+        })),
+        ["token", "balance"] => Ok(Command::Token(TokenCommand::Balance(None))),
+        ["token", "balance", token_id] => Ok(Command::Token(TokenCommand::Balance(Some(token_id.to_string())))),
+        ["sponsor", "request", amount, target, path, title @ ..] => Ok(Command::Sponsor(SponsorCommand::Request {
+            amount: parse_amount(amount)?,
+            target_address: transfer_target(target)?,
+            title: title.join(" "),
+            path: PathBuf::from(path),
+        })),
+        ["sponsor", "pay", path] => Ok(Command::Sponsor(SponsorCommand::Pay {
+            path: PathBuf::from(path),
+            fee: None,
+        })),
+        ["sponsor", "pay", path, fee] => Ok(Command::Sponsor(SponsorCommand::Pay {
+            path: PathBuf::from(path),
+            fee: Some(parse_amount(fee)?),
+        })),
+        ["sponsor", ..] => Err(Box::new(CommandError::new(
+            "Usage: sponsor request <amount> <address> <file> [title]|pay <file> [fee]"
+        ))),
+        ["token", ..] => Err(Box::new(CommandError::new(
+            "Usage: token create <symbol> <amount>|send <token id> <amount> <address>|balance [token id]"
+        ))),
+        ["escrow", ..] => Err(Box::new(CommandError::new(
+            "Usage: escrow open <amount> <recipient> <arbiter>|release <escrow id>|dispute <escrow id>"
+        ))),
+        ["exit"] => Ok(Command::Exit),
+        [] => Err(Box::new(CommandError::new("No command given"))),
+        [unknown, ..] => Err(Box::new(CommandError::new(&format!("Unknown command: {}", unknown))))
+    }
+}
+
+fn parse_balance(options: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let mut address = None;
+    let mut height = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match (*option, address) {
+            ("--at", _) => match options.next().map(|value| value.parse::<u64>()) {
+                Some(Ok(value)) => height = Some(value),
+                _ => return Err(Box::new(CommandError::new("Invalid height")))
+            },
+            (value, None) => address = Some(access::decode_address(value)?),
+            _ => return Err(Box::new(CommandError::new(
+                "Usage: balance [address] [--at <height>]"
+            )))
+        }
+    }
+    Ok(Command::Balance {
+        address,
+        height,
+    })
+}
+
+fn parse_statement(options: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let mut month = Utc::now().date_naive();
+    let mut export = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match (*option, options.next()) {
+            ("--month", Some(value)) => match NaiveDate::parse_from_str(&format!("{}-01", value), "%Y-%m-%d") {
+                Ok(value) => month = value,
+                Err(_) => return Err(Box::new(CommandError::new("Month must look like 2024-06")))
+            },
+            ("--csv", Some(file)) if export.is_none() => export = Some(StatementExport::Csv(PathBuf::from(file))),
+            ("--json", Some(file)) if export.is_none() => export = Some(StatementExport::Json(PathBuf::from(file))),
+            _ => return Err(Box::new(CommandError::new(
+                "Usage: statement [--month <yyyy-mm>] [--csv <file>|--json <file>]"
+            )))
+        }
+    }
+    Ok(Command::Statement {
+        month,
+        export,
+    })
+}
+
+fn parse_list(options: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let usage = || -> Box<dyn BlockchainError> {
+        Box::new(CommandError::new(
+            "Usage: list [--limit <n>] [--page <n>] [--since <date>] [--direction in|out] [--min <amount>] [--counterparty <address>|@<contact>] [--raw] [--plain]"
+        ))
+    };
+    let count = |value: &str| match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(usage())
+    };
+    let (mut raw, mut plain, mut filter, mut contact) = (false, false, HistoryFilter::default(), None);
+    let (mut limit, mut page) = (DEFAULT_PAGE_SIZE, 1);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "--raw" => raw = true,
+            "--plain" => plain = true,
+            option => match (option, *options.next().ok_or_else(usage)?) {
+                ("--limit", value) => limit = count(value)?,
+                ("--page", value) => page = count(value)?,
+                ("--since", value) => filter = filter.with_since(parse_date(value)?),
+                ("--direction", "in") => filter = filter.with_flow(Flow::In),
+                ("--direction", "out") => filter = filter.with_flow(Flow::Out),
+                ("--min", value) => filter = filter.with_min_amount(parse_amount(value)?),
+                ("--counterparty", value) => match value.strip_prefix('@') {
+                    Some(name) => contact = Some(name.to_string()),
+                    None => filter = filter.with_counterparty(access::decode_address(value)?),
+                },
+                _ => return Err(usage())
+            }
+        }
+    }
+    Ok(Command::List {
+        raw,
+        plain,
+        filter,
+        contact,
+        page: Page::new(limit, page),
+    })
+}
+
+fn parse_request(amount: &str, options: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let amount = parse_amount(amount)?;
+    let mut memo = None;
+    let mut qr_code = false;
+    for option in options {
+        match *option {
+            "--qr" => qr_code = true,
+            memo_text if memo.is_none() => memo = Some(memo_text.to_string()),
+            _ => return Err(Box::new(CommandError::new(
+                "Usage: request <amount> [\"memo\"] [--qr]"
+            )))
+        }
+    }
+    Ok(Command::Request {
+        amount,
+        memo,
+        qr_code,
+    })
+}
+
+// system wallets are reached through fees, bids, contracts and burn, coins sent there directly
+// would be locked for good
+fn reject_reserved(address: Address) -> Result<Address, Box<dyn BlockchainError>> {
+    match protocol::is_reserved(&address) {
+        true => Err(Box::new(CommandError::new("Cannot send to a system address, burn destroys coins"))),
+        false => Ok(address)
+    }
+}
+
+fn transfer_target(target: &str) -> Result<Address, Box<dyn BlockchainError>> {
+    reject_reserved(access::decode_address(target)?)
+}
+
+fn parse_schedule(arguments: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let command = match arguments {
+        ["send", amount, target, options @ ..] => {
+            let amount = parse_amount(amount)?;
+            let target_address = transfer_target(target)?;
+            let mut first_run = Utc::now();
+            let mut interval = None;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match (*option, options.next()) {
+                    ("--every", Some(value)) => interval = Some(parse_interval(value)?),
+                    ("--at", Some(value)) => first_run = parse_time(value)?,
+                    _ => return Err(Box::new(CommandError::new(
+                        "Usage: schedule send <amount> <address> [--at <time>] [--every <interval>]"
+                    )))
+                }
+            }
+            ScheduleCommand::Send {
+                amount,
+                target_address,
+                first_run,
+                interval,
+            }
+        }
+        ["list"] => ScheduleCommand::List,
+        ["cancel", id] => match id.parse() {
+            Ok(id) => ScheduleCommand::Cancel(id),
+            Err(_) => return Err(Box::new(CommandError::new("Invalid schedule id")))
+        },
+        _ => return Err(Box::new(CommandError::new(
+            "Usage: schedule send|list|cancel"
+        )))
+    };
+    Ok(Command::Schedule(command))
+}
+
+fn parse_htlc(arguments: &[&str]) -> Result<Command, Box<dyn BlockchainError>> {
+    let command = match arguments {
+        ["create", amount, target, timeout, hash_lock @ ..] if hash_lock.len() <= 1 => HtlcCommand::Create {
+            amount: parse_amount(amount)?,
+            target_address: transfer_target(target)?,
+            timeout: parse_interval(timeout)?,
+            hash_lock: hash_lock.first().map(|hash_lock| hash_lock.to_string()),
+        },
+        ["claim", lock_id, preimage] => HtlcCommand::Claim {
+            lock_id: lock_id.to_string(),
+            preimage: preimage.to_string(),
+        },
+        ["refund", lock_id] => HtlcCommand::Refund(lock_id.to_string()),
+        _ => return Err(Box::new(CommandError::new(
+            "Usage: htlc create <amount> <address> <timeout> [hash]|claim <lock id> <preimage>|refund <lock id>"
+        )))
+    };
+    Ok(Command::Htlc(command))
+}
+
+pub fn parse_amount(value: &str) -> Result<i64, Box<dyn BlockchainError>> {
+    match value.parse::<i64>() {
+        Ok(amount) if amount > 0 => Ok(amount),
+        _ => Err(Box::new(CommandError::new("Amount must be a positive integer")))
+    }
+}
+
+pub fn parse_interval(value: &str) -> Result<Duration, Box<dyn BlockchainError>> {
+    let split = value.len().saturating_sub(1);
+    let (count, unit) = value.split_at(split);
+    let count = match count.parse::<i64>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err(Box::new(CommandError::new("Invalid interval")))
+    };
+    match unit {
+        "s" => Ok(Duration::seconds(count)),
+        "m" => Ok(Duration::minutes(count)),
+        "h" => Ok(Duration::hours(count)),
+        "d" => Ok(Duration::days(count)),
+        _ => Err(Box::new(CommandError::new("Interval unit must be one of s, m, h, d")))
+    }
+}
+
+pub fn parse_time(value: &str) -> Result<DateTime<Utc>, Box<dyn BlockchainError>> {
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => Ok(time.with_timezone(&Utc)),
+        Err(_) => Err(Box::new(CommandError::new("Time must be given in RFC 3339 format")))
+    }
+}
+
+// a day like 2024-06-01 starts at midnight UTC, anything else is read as an RFC 3339 time
+pub fn parse_date(value: &str) -> Result<DateTime<Utc>, Box<dyn BlockchainError>> {
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(day) => Ok(DateTime::<Utc>::from_utc(day.and_hms_opt(0, 0, 0).unwrap(), Utc)),
+        Err(_) => parse_time(value)
+    }
+}
+
+fn split_arguments(line: &str) -> Result<Vec<String>, Box<dyn BlockchainError>> {
+    let mut arguments = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut pending = false;
+    for character in line.chars() {
+        match character {
+            '"' => {
+                quoted = !quoted;
+                pending = true;
+            }
+            whitespace if whitespace.is_whitespace() && !quoted => {
+                if pending {
+                    arguments.push(std::mem::take(&mut current));
+                    pending = false;
+                }
+            }
+            other => {
+                current.push(other);
+                pending = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(Box::new(CommandError::new("Unterminated quote")));
+    }
+    if pending {
+        arguments.push(current);
+    }
+    Ok(arguments)
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::Path;
+
+use crate::blockchain::Address;
+use crate::blockchain::core::BlockchainError;
+use crate::command::{self, CommandError, parse_amount};
+
+pub struct BatchRow {
+    line: usize,
+    amount: i64,
+    target_address: Address,
+    memo: String,
+}
+
+impl BatchRow {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+    pub fn target_address(&self) -> Address {
+        self.target_address
+    }
+    pub fn memo(&self) -> &str {
+        &self.memo
+    }
+}
+
+pub struct RowError {
+    line: usize,
+    message: String,
+}
+
+impl RowError {
+    pub fn new(line: usize, message: &str) -> RowError {
+        RowError {
+            line,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+}
+
+impl BlockchainError for RowError {
+    fn message(&self) -> String {
+        format!("Row {}: {}", self.line, self.message)
+    }
+}
+
+pub fn read_batch(path: &Path) -> Result<Vec<Result<BatchRow, RowError>>, Box<dyn BlockchainError>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(parse_batch(&content)),
+        Err(error) => Err(Box::new(CommandError::new(&error.to_string())))
+    }
+}
+
+// rows are `amount,address,memo`, an optional header row starting with "amount" is skipped
+pub fn parse_batch(content: &str) -> Vec<Result<BatchRow, RowError>> {
+    content.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(index, line)| !(line.is_empty() || *index == 1 && line.starts_with("amount")))
+        .map(|(index, line)| parse_row(index, line))
+        .collect()
+}
+
+fn parse_row(line: usize, content: &str) -> Result<BatchRow, RowError> {
+    let fields = split_fields(content);
+    let (amount, address, memo) = match fields.as_slice() {
+        [amount, address] => (amount, address, String::new()),
+        [amount, address, memo] => (amount, address, memo.clone()),
+        _ => return Err(RowError::new(line, "Expected amount,address[,memo]"))
+    };
+    let amount = match parse_amount(amount.trim()) {
+        Ok(amount) => amount,
+        Err(error) => return Err(RowError::new(line, &error.message()))
+    };
+    let target_address = match command::transfer_target(address.trim()) {
+        Ok(address) => address,
+        Err(error) => return Err(RowError::new(line, &error.message()))
+    };
+    Ok(BatchRow {
+        line,
+        amount,
+        target_address,
+        memo,
+    })
+}
+
+fn split_fields(content: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut characters = content.chars().peekable();
+    while let Some(character) = characters.next() {
+        match character {
+            '"' if quoted && characters.peek() == Some(&'"') => {
+                current.push('"');
+                characters.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut current)),
+            other => current.push(other),
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::access;
+    use crate::command::batch;
+
+    #[test]
+    fn reports_rows_individually() {
+        let address = access::encode_address([4; 32]);
+        let content = format!(
+            "amount,address,memo\n10,{0},\"salary, march\"\n-5,{0}\n\n7,{0}\n", address
+        );
+        let rows = batch::parse_batch(&content);
+        assert_eq!(rows.len(), 3);
+
+        let first = rows[0].as_ref().ok().unwrap();
+        assert_eq!((first.line(), first.amount(), first.memo()), (2, 10, "salary, march"));
+        assert_eq!(rows[1].as_ref().err().unwrap().line(), 3);
+        assert_eq!(rows[2].as_ref().ok().unwrap().line(), 5);
+    }
+}
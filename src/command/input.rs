@@ -0,0 +1,53 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::mpsc;
+
+use crate::report;
+
+pub static COMMAND_QUEUE_CAPACITY: usize = 64;
+
+// Command lines are read on a task of their own and queued, so a main loop busy with network
+// events never holds back reading them. The queue closes at the end of input, or once it can no
+// longer be read.
+pub fn spawn_reader<R>(input: R) -> mpsc::Receiver<String> where R: AsyncBufRead + Unpin + Send + 'static {
+    let (sender, receiver) = mpsc::channel(COMMAND_QUEUE_CAPACITY);
+    tokio::spawn(forward_lines(input, sender));
+    receiver
+}
+
+async fn forward_lines<R>(input: R, sender: mpsc::Sender<String>) where R: AsyncBufRead + Unpin {
+    let mut lines = input.lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => {
+                if sender.send(line).await.is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(error) => {
+                report!("{}", error);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::command::input;
+
+    #[tokio::test]
+    async fn lines_are_queued_in_order_until_input_ends() {
+        let mut commands = input::spawn_reader(&b"status\nsend 5 abc\n"[..]);
+        assert_eq!(commands.recv().await.as_deref(), Some("status"));
+        assert_eq!(commands.recv().await.as_deref(), Some("send 5 abc"));
+        assert_eq!(commands.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn queue_closes_when_input_cannot_be_read() {
+        let mut commands = input::spawn_reader(&b"status\n\xff\nsend 5 abc\n"[..]);
+        assert_eq!(commands.recv().await.as_deref(), Some("status"));
+        assert_eq!(commands.recv().await, None);
+    }
+}
@@ -0,0 +1,140 @@
+use qrcode::QrCode;
+use qrcode::render::unicode;
+
+use crate::blockchain::Address;
+use crate::blockchain::access;
+use crate::blockchain::core::BlockchainError;
+use crate::command::CommandError;
+
+pub static URI_SCHEME: &str = "kingcoin:";
+
+pub struct PaymentRequest {
+    target_address: Address,
+    amount: Option<i64>,
+    memo: Option<String>,
+}
+
+impl PaymentRequest {
+    pub fn new(target_address: Address, amount: Option<i64>, memo: Option<String>) -> PaymentRequest {
+        PaymentRequest {
+            target_address,
+            amount,
+            memo,
+        }
+    }
+
+    pub fn target_address(&self) -> Address {
+        self.target_address
+    }
+    pub fn amount(&self) -> Option<i64> {
+        self.amount
+    }
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    pub fn to_uri(&self) -> String {
+        let mut parameters = vec![];
+        if let Some(amount) = self.amount {
+            parameters.push(format!("amount={}", amount));
+        }
+        if let Some(memo) = &self.memo {
+            parameters.push(format!("memo={}", percent_encode(memo)));
+        }
+        let mut uri = format!("{}{}", URI_SCHEME, access::encode_address(self.target_address));
+        if !parameters.is_empty() {
+            uri.push('?');
+            uri.push_str(&parameters.join("&"));
+        }
+        uri
+    }
+
+    pub fn parse(uri: &str) -> Result<PaymentRequest, Box<dyn BlockchainError>> {
+        let body = match uri.strip_prefix(URI_SCHEME) {
+            None => return Err(Box::new(CommandError::new("Not a kingcoin payment request"))),
+            Some(body) => body
+        };
+        let (address, query) = match body.split_once('?') {
+            None => (body, ""),
+            Some(split) => split
+        };
+        let mut request = PaymentRequest::new(access::decode_address(address)?, None, None);
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            match parameter.split_once('=') {
+                Some(("amount", value)) => request.amount = Some(super::parse_amount(value)?),
+                Some(("memo", value)) => request.memo = Some(percent_decode(value)?),
+                // unknown parameters are ignored for forward compatibility
+                Some(_) => {}
+                None => return Err(Box::new(CommandError::new("Malformed payment request")))
+            }
+        }
+        Ok(request)
+    }
+
+    pub fn to_qr_code(&self) -> Result<String, Box<dyn BlockchainError>> {
+        match QrCode::new(self.to_uri().as_bytes()) {
+            Ok(code) => Ok(code.render::<unicode::Dense1x2>().build()),
+            Err(_) => Err(Box::new(CommandError::new("Payment request too long for a QR code")))
+        }
+    }
+}
+
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    encoded
+}
+
+fn percent_decode(value: &str) -> Result<String, Box<dyn BlockchainError>> {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' if index + 2 < bytes.len() => {
+                let hex = String::from_utf8_lossy(&bytes[index + 1..index + 3]);
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) => decoded.push(byte),
+                    Err(_) => return Err(Box::new(CommandError::new("Malformed payment request")))
+                }
+                index += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Ok(decoded),
+        Err(_) => Err(Box::new(CommandError::new("Malformed payment request")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::command::payment_request::PaymentRequest;
+
+    #[test]
+    fn uri_round_trip() {
+        let request = PaymentRequest::new([7; 32], Some(100), Some("invoice 42 & co".to_string()));
+        let uri = request.to_uri();
+        assert!(uri.ends_with("?amount=100&memo=invoice%2042%20%26%20co"));
+
+        let parsed = PaymentRequest::parse(&uri).ok().unwrap();
+        assert_eq!(parsed.target_address(), [7; 32]);
+        assert_eq!(parsed.amount(), Some(100));
+        assert_eq!(parsed.memo(), Some("invoice 42 & co"));
+    }
+}
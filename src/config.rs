@@ -0,0 +1,375 @@
+use std::fs;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::protocol::BLOCK_INTERVAL_SECONDS;
+use crate::blockchain::core::{BlockchainError, DEFAULT_MAX_REORG_DEPTH, MEDIAN_TIME_SPAN};
+use crate::display::DisplayConfig;
+use crate::limits::SpendLimits;
+use crate::network::communication::outbox::GOSSIP_FRAMING;
+use crate::network::inactivity::InactivityConfig;
+use crate::webhook::WebhookConfig;
+
+pub static CONFIG_FILE: &str = "config.json";
+
+pub struct ConfigError {
+    message: String,
+}
+
+impl ConfigError {
+    pub fn new(message: &str) -> ConfigError {
+        ConfigError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for ConfigError {
+    fn message(&self) -> String {
+        format!("Config: {}", self.message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum GossipValidation {
+    Strict,
+    Permissive,
+    Anonymous,
+    None,
+}
+
+impl GossipValidation {
+    // strict and permissive validation expect messages signed by their author
+    pub fn signed(&self) -> bool {
+        matches!(self, GossipValidation::Strict | GossipValidation::Permissive)
+    }
+
+    pub fn describe(&self) -> &str {
+        match self {
+            GossipValidation::Strict => "strict",
+            GossipValidation::Permissive => "permissive",
+            GossipValidation::Anonymous => "anonymous",
+            GossipValidation::None => "none",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct GossipConfig {
+    heartbeat_interval_seconds: u64,
+    mesh_n_low: usize,
+    mesh_n: usize,
+    mesh_n_high: usize,
+    validation_mode: GossipValidation,
+    max_transmit_size: usize,
+    flood_publish: bool,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        GossipConfig {
+            heartbeat_interval_seconds: 10,
+            mesh_n_low: 5,
+            mesh_n: 6,
+            mesh_n_high: 12,
+            validation_mode: GossipValidation::Strict,
+            max_transmit_size: 65536,
+            flood_publish: true,
+        }
+    }
+}
+
+impl GossipConfig {
+    pub fn heartbeat_interval_seconds(&self) -> u64 {
+        self.heartbeat_interval_seconds
+    }
+    pub fn mesh_n_low(&self) -> usize {
+        self.mesh_n_low
+    }
+    pub fn mesh_n(&self) -> usize {
+        self.mesh_n
+    }
+    pub fn mesh_n_high(&self) -> usize {
+        self.mesh_n_high
+    }
+    pub fn validation_mode(&self) -> GossipValidation {
+        self.validation_mode
+    }
+    pub fn max_transmit_size(&self) -> usize {
+        self.max_transmit_size
+    }
+    pub fn flood_publish(&self) -> bool {
+        self.flood_publish
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        if self.heartbeat_interval_seconds == 0 {
+            return Err(Box::new(ConfigError::new("Gossip heartbeat interval must be positive")));
+        }
+        if !(0 < self.mesh_n_low && self.mesh_n_low <= self.mesh_n && self.mesh_n <= self.mesh_n_high) {
+            return Err(Box::new(ConfigError::new(
+                "Gossip mesh sizes must satisfy 0 < mesh_n_low <= mesh_n <= mesh_n_high"
+            )));
+        }
+        if self.max_transmit_size <= GOSSIP_FRAMING {
+            return Err(Box::new(ConfigError::new(&format!(
+                "Gossip max transmit size must exceed the {} bytes of message framing", GOSSIP_FRAMING
+            ))));
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "heartbeat {}s, mesh {}/{}/{}, validation {} ({}), max message {} B, flood publish {}",
+            self.heartbeat_interval_seconds, self.mesh_n_low, self.mesh_n, self.mesh_n_high,
+            self.validation_mode.describe(),
+            if self.validation_mode.signed() { "signed" } else { "unsigned" },
+            self.max_transmit_size, if self.flood_publish { "on" } else { "off" }
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct NodeConfig {
+    gossip: GossipConfig,
+    // multiaddrs, e.g. "/ip6/::/tcp/4001" for IPv6 on a fixed port
+    listen_addresses: Vec<String>,
+    // publicly reachable addresses announced to peers
+    external_addresses: Vec<String>,
+    mdns_ipv6: bool,
+    // relays to reserve a circuit on when built with the nat feature, must end in /p2p/<peer id>
+    relay_addresses: Vec<String>,
+    spend_limits: SpendLimits,
+    // signs with a key held by an external process, see `kingcoin signer`
+    remote_signer: Option<SocketAddr>,
+    // serves thin clients started with `kingcoin client` on this port
+    rpc_port: Option<u16>,
+    // serves the same queries over grpc along with block and wallet event streams
+    grpc_port: Option<u16>,
+    // both ports only listen on loopback unless exposed, then only expose them to trusted networks
+    expose_api: bool,
+    // synced chains may roll back at most this many blocks
+    max_reorg_depth: u64,
+    // keeps only this many transaction blocks in memory, older ones are read back from the
+    // chains directory when needed, unset keeps the whole chain in memory
+    resident_blocks: Option<u64>,
+    // seconds after a block before a partially filled one may follow, also how often this node
+    // calls for a forging round while transactions are waiting
+    block_interval_seconds: u64,
+    // receivers of json posts on new blocks, payments to watched addresses and slashing
+    webhooks: Vec<WebhookConfig>,
+    // validators that stop bidding and voting without disconnecting
+    inactivity: InactivityConfig,
+    // thousands separators, date format and timezone of amounts and times on the console
+    display: DisplayConfig,
+    // connected peers needed before the node bids and votes, short of them transactions stay queued
+    min_peers: usize,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            gossip: GossipConfig::default(),
+            listen_addresses: vec![String::from("/ip4/0.0.0.0/tcp/0")],
+            external_addresses: vec![],
+            mdns_ipv6: false,
+            relay_addresses: vec![],
+            spend_limits: SpendLimits::default(),
+            remote_signer: None,
+            rpc_port: None,
+            grpc_port: None,
+            expose_api: false,
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            resident_blocks: None,
+            block_interval_seconds: BLOCK_INTERVAL_SECONDS,
+            webhooks: vec![],
+            inactivity: InactivityConfig::default(),
+            display: DisplayConfig::default(),
+            min_peers: 1,
+        }
+    }
+}
+
+impl NodeConfig {
+    // a missing file means defaults, a malformed one is an error rather than silently ignored
+    pub fn load(path: &Path) -> Result<NodeConfig, Box<dyn BlockchainError>> {
+        let config: NodeConfig = match fs::read_to_string(path) {
+            Err(_) => NodeConfig::default(),
+            Ok(content) => match serde_json::from_str(&content) {
+                Ok(config) => config,
+                Err(error) => return Err(Box::new(ConfigError::new(&error.to_string())))
+            }
+        };
+        config.gossip.validate()?;
+        if config.listen_addresses.is_empty() {
+            return Err(Box::new(ConfigError::new("At least one listen address is required")));
+        }
+        parse_addresses(&config.listen_addresses)?;
+        parse_addresses(&config.external_addresses)?;
+        parse_addresses(&config.relay_addresses)?;
+        config.spend_limits.validate()?;
+        if config.remote_signer.is_some_and(|endpoint| !endpoint.ip().is_loopback()) {
+            return Err(Box::new(ConfigError::new("Remote signer must listen on a loopback address")));
+        }
+        if config.max_reorg_depth == 0 {
+            return Err(Box::new(ConfigError::new("Max reorg depth must be positive")));
+        }
+        // reorgs and block time checks only look at blocks held in memory
+        let least_resident = config.max_reorg_depth + MEDIAN_TIME_SPAN as u64;
+        if config.resident_blocks.is_some_and(|resident_blocks| resident_blocks < least_resident) {
+            return Err(Box::new(ConfigError::new(
+                &format!("At least {} resident blocks are required", least_resident)
+            )));
+        }
+        if config.block_interval_seconds == 0 {
+            return Err(Box::new(ConfigError::new("Block interval must be positive")));
+        }
+        for webhook in &config.webhooks {
+            webhook.validate()?;
+        }
+        config.inactivity.validate()?;
+        config.display.validate()?;
+        if config.min_peers == 0 {
+            return Err(Box::new(ConfigError::new("Min peers must be positive")));
+        }
+        Ok(config)
+    }
+
+    pub fn gossip(&self) -> &GossipConfig {
+        &self.gossip
+    }
+
+    pub fn listen_addresses(&self) -> Vec<Multiaddr> {
+        parse_addresses(&self.listen_addresses).unwrap_or_default()
+    }
+
+    pub fn external_addresses(&self) -> Vec<Multiaddr> {
+        parse_addresses(&self.external_addresses).unwrap_or_default()
+    }
+
+    pub fn relay_addresses(&self) -> Vec<Multiaddr> {
+        parse_addresses(&self.relay_addresses).unwrap_or_default()
+    }
+
+    pub fn mdns_ipv6(&self) -> bool {
+        self.mdns_ipv6
+    }
+
+    pub fn spend_limits(&self) -> SpendLimits {
+        self.spend_limits
+    }
+
+    pub fn remote_signer(&self) -> Option<SocketAddr> {
+        self.remote_signer
+    }
+
+    pub fn rpc_address(&self) -> Option<SocketAddr> {
+        self.rpc_port.map(|port| self.api_address(port))
+    }
+
+    pub fn grpc_address(&self) -> Option<SocketAddr> {
+        self.grpc_port.map(|port| self.api_address(port))
+    }
+
+    fn api_address(&self, port: u16) -> SocketAddr {
+        let ip = if self.expose_api { Ipv4Addr::UNSPECIFIED } else { Ipv4Addr::LOCALHOST };
+        SocketAddr::from((ip, port))
+    }
+
+    pub fn max_reorg_depth(&self) -> u64 {
+        self.max_reorg_depth
+    }
+
+    pub fn min_peers(&self) -> usize {
+        self.min_peers
+    }
+
+    pub fn resident_blocks(&self) -> Option<u64> {
+        self.resident_blocks
+    }
+
+    pub fn block_interval(&self) -> Duration {
+        Duration::from_secs(self.block_interval_seconds)
+    }
+
+    pub fn webhooks(&self) -> &[WebhookConfig] {
+        &self.webhooks
+    }
+
+    pub fn inactivity(&self) -> &InactivityConfig {
+        &self.inactivity
+    }
+
+    pub fn display(&self) -> &DisplayConfig {
+        &self.display
+    }
+}
+
+fn parse_addresses(addresses: &[String]) -> Result<Vec<Multiaddr>, Box<dyn BlockchainError>> {
+    addresses.iter()
+        .map(|address| match address.parse::<Multiaddr>() {
+            Ok(address) => Ok(address),
+            Err(_) => Err(Box::new(ConfigError::new(&format!("Invalid multiaddr {}", address)))
+                as Box<dyn BlockchainError>)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{GossipValidation, NodeConfig};
+    use crate::network;
+
+    #[test]
+    fn partial_config_keeps_defaults_and_rejects_bad_mesh() {
+        let config: NodeConfig = serde_json::from_str(
+            r#"{"gossip": {"mesh_n": 8, "validation_mode": "permissive"}}"#
+        ).unwrap();
+        assert_eq!(config.gossip().mesh_n(), 8);
+        assert_eq!(config.gossip().heartbeat_interval_seconds(), 10);
+        assert!(config.gossip().validation_mode() == GossipValidation::Permissive);
+        assert!(config.gossip().validate().is_ok());
+
+        let config: NodeConfig = serde_json::from_str(r#"{"gossip": {"mesh_n": 20}}"#).unwrap();
+        assert!(config.gossip().validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn every_validated_gossip_config_builds_a_swarm() {
+        let config: NodeConfig = serde_json::from_str(
+            r#"{"gossip": {"mesh_n_low": 1, "mesh_n": 4, "mesh_n_high": 5}}"#
+        ).unwrap();
+        assert!(config.gossip().validate().is_ok());
+        assert!(network::configure_swarm(&config).is_ok());
+    }
+
+    #[test]
+    fn parses_ipv4_and_ipv6_listen_addresses() {
+        let config: NodeConfig = serde_json::from_str(
+            r#"{"listen_addresses": ["/ip4/0.0.0.0/tcp/4001", "/ip6/::/tcp/4001"], "mdns_ipv6": true}"#
+        ).unwrap();
+        assert_eq!(config.listen_addresses().len(), 2);
+        assert!(config.mdns_ipv6());
+        assert!(NodeConfig::default().external_addresses().is_empty());
+        assert!(super::parse_addresses(&[String::from("/ip6/not-an-ip/tcp/1")]).is_err());
+    }
+
+    #[test]
+    fn api_ports_listen_on_loopback_unless_exposed() {
+        let config: NodeConfig = serde_json::from_str(r#"{"rpc_port": 4100}"#).unwrap();
+        assert_eq!(config.rpc_address(), Some("127.0.0.1:4100".parse().unwrap()));
+        assert_eq!(config.grpc_address(), None);
+
+        let config: NodeConfig = serde_json::from_str(r#"{"rpc_port": 4100, "grpc_port": 4101, "expose_api": true}"#).unwrap();
+        assert_eq!(config.rpc_address(), Some("0.0.0.0:4100".parse().unwrap()));
+        assert_eq!(config.grpc_address(), Some("0.0.0.0:4101".parse().unwrap()));
+    }
+}
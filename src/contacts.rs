@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Address;
+use crate::blockchain::access;
+use crate::blockchain::core::{BlockchainError, StorageError};
+use crate::command::CommandError;
+
+pub static CONTACTS_FILE: &str = "contacts.json";
+// validators are told apart by the tail of their peer id, the head is the same for every key type
+static PEER_ID_SUFFIX_LENGTH: usize = 6;
+
+// Names the operator gave to addresses. Books loaded from a file write every change back to it.
+#[derive(Serialize, Deserialize, Default)]
+pub struct AddressBook {
+    contacts: BTreeMap<String, Address>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl AddressBook {
+    pub fn load(path: &Path) -> AddressBook {
+        let book: AddressBook = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => AddressBook::default()
+        };
+        AddressBook {
+            path: Some(path.to_path_buf()),
+            ..book
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let content = serde_json::to_string_pretty(self).unwrap();
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn contacts(&self) -> &BTreeMap<String, Address> {
+        &self.contacts
+    }
+
+    // renaming an address replaces its previous name
+    pub fn add(&mut self, name: &str, address: Address) -> Result<(), Box<dyn BlockchainError>> {
+        let valid = !name.is_empty()
+            && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_');
+        if !valid {
+            return Err(Box::new(CommandError::new("Names may only hold letters, digits, - and _")));
+        }
+        self.contacts.retain(|_, known| *known != address);
+        self.contacts.insert(name.to_string(), address);
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<bool, Box<dyn BlockchainError>> {
+        let removed = self.contacts.remove(name).is_some();
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn name_of(&self, address: Address) -> Option<&str> {
+        self.contacts.iter()
+            .find(|(_, known)| **known == address)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+// Resolves addresses for listings, names from the address book win over validator wallets.
+pub struct AddressLabels<'a> {
+    book: &'a AddressBook,
+    validators: HashMap<Address, PeerId>,
+    raw: bool,
+}
+
+impl<'a> AddressLabels<'a> {
+    pub fn new(book: &'a AddressBook, validators: HashMap<Address, PeerId>) -> AddressLabels<'a> {
+        AddressLabels {
+            book,
+            validators,
+            raw: false,
+        }
+    }
+
+    pub fn with_raw(mut self, raw: bool) -> AddressLabels<'a> {
+        self.raw = raw;
+        self
+    }
+
+    pub fn label(&self, address: Address) -> String {
+        if self.raw {
+            return access::encode_address(address);
+        }
+        if let Some(name) = self.book.name_of(address) {
+            return format!("@{}", name);
+        }
+        match self.validators.get(&address) {
+            Some(peer_id) => {
+                let peer_id = peer_id.to_base58();
+                format!("validator:{}", &peer_id[peer_id.len().saturating_sub(PEER_ID_SUFFIX_LENGTH)..])
+            }
+            None => access::encode_address(address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use libp2p::PeerId;
+
+    use crate::blockchain::access;
+    use crate::contacts::{AddressBook, AddressLabels};
+
+    #[test]
+    fn listings_name_known_addresses_unless_raw() {
+        let mut book = AddressBook::default();
+        assert!(book.add("alice", [1; 32]).is_ok());
+        assert!(book.add("alice smith", [2; 32]).is_err());
+        assert!(book.add("ally", [1; 32]).is_ok());
+        assert_eq!(book.contacts().len(), 1);
+        let validator = PeerId::random();
+        let labels = AddressLabels::new(&book, HashMap::from([([1; 32], validator), ([3; 32], validator)]));
+
+        assert_eq!(labels.label([1; 32]), "@ally");
+        assert!(labels.label([3; 32]).starts_with("validator:"));
+        assert!(validator.to_base58().ends_with(labels.label([3; 32]).trim_start_matches("validator:")));
+        assert_eq!(labels.label([4; 32]), access::encode_address([4; 32]));
+        assert_eq!(labels.with_raw(true).label([1; 32]), access::encode_address([1; 32]));
+    }
+}
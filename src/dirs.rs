@@ -0,0 +1,202 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::{BlockchainError, StorageError};
+use crate::blockchain::governance::GOVERNANCE_FILE;
+use crate::blockchain::key_history::KEY_HISTORY_FILE;
+use crate::blockchain::protocol::GENESIS_FILE;
+use crate::config::CONFIG_FILE;
+use crate::contacts::CONTACTS_FILE;
+use crate::network::bans::BANS_FILE;
+use crate::network::known_peers::KNOWN_PEERS_FILE;
+use crate::network::rounds::ROUNDS_FILE;
+use crate::platform;
+use crate::schedule::SCHEDULE_FILE;
+use crate::report;
+
+// overrides the platform's default, e.g. to run several nodes on one machine
+pub static HOME_VARIABLE: &str = "KINGCOIN_HOME";
+// bumped whenever files move or change format, with a migration from the previous version
+pub static LAYOUT_VERSION: u32 = 1;
+static LAYOUT_FILE: &str = "layout.json";
+static LEGACY_CONFIG_FILE: &str = "node.json";
+
+// version n migrates a directory at version n - 1, the legacy directory is where
+// nodes before the structured layout kept their files
+static MIGRATIONS: [Migration; 1] = [
+    import_working_directory,
+];
+
+type Migration = fn(&AppDirs, &Path) -> Result<(), Box<dyn BlockchainError>>;
+
+#[derive(Serialize, Deserialize)]
+struct Layout {
+    version: u32,
+}
+
+pub struct AppDirs {
+    root: PathBuf,
+}
+
+impl AppDirs {
+    pub fn locate() -> Result<AppDirs, Box<dyn BlockchainError>> {
+        if let Some(root) = env::var_os(HOME_VARIABLE) {
+            return Ok(AppDirs::at(PathBuf::from(root)));
+        }
+        match platform::default_data_dir() {
+            Some(root) => Ok(AppDirs::at(root)),
+            None => Err(Box::new(StorageError::new(&format!(
+                "No home directory, set {} to choose a data directory", HOME_VARIABLE
+            ))))
+        }
+    }
+
+    pub fn at(root: PathBuf) -> AppDirs {
+        AppDirs {
+            root,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    pub fn keystore_dir(&self) -> PathBuf {
+        self.root.join("keystore")
+    }
+    pub fn chains_dir(&self) -> PathBuf {
+        self.root.join("chains")
+    }
+    pub fn peers_dir(&self) -> PathBuf {
+        self.root.join("peers")
+    }
+    pub fn config_file(&self) -> PathBuf {
+        self.root.join(CONFIG_FILE)
+    }
+    pub fn genesis_file(&self) -> PathBuf {
+        self.root.join(GENESIS_FILE)
+    }
+    pub fn schedule_file(&self) -> PathBuf {
+        self.root.join(SCHEDULE_FILE)
+    }
+    pub fn contacts_file(&self) -> PathBuf {
+        self.root.join(CONTACTS_FILE)
+    }
+    pub fn governance_file(&self) -> PathBuf {
+        self.chains_dir().join(GOVERNANCE_FILE)
+    }
+    pub fn key_history_file(&self) -> PathBuf {
+        self.chains_dir().join(KEY_HISTORY_FILE)
+    }
+    pub fn bans_file(&self) -> PathBuf {
+        self.peers_dir().join(BANS_FILE)
+    }
+    pub fn rounds_file(&self) -> PathBuf {
+        self.peers_dir().join(ROUNDS_FILE)
+    }
+    pub fn known_peers_file(&self) -> PathBuf {
+        self.peers_dir().join(KNOWN_PEERS_FILE)
+    }
+
+    // version of the files on disk, 0 for a directory that was never prepared
+    pub fn layout_version(&self) -> Result<u32, Box<dyn BlockchainError>> {
+        match fs::read_to_string(self.root.join(LAYOUT_FILE)) {
+            Err(_) => Ok(0),
+            Ok(content) => match serde_json::from_str::<Layout>(&content) {
+                Ok(layout) => Ok(layout.version),
+                Err(_) => Err(Box::new(StorageError::new("Corrupted layout file")))
+            }
+        }
+    }
+
+    // creates missing directories and migrates older layouts one version at a time
+    pub fn prepare(&self, legacy_dir: &Path) -> Result<(), Box<dyn BlockchainError>> {
+        let version = self.layout_version()?;
+        if version > LAYOUT_VERSION {
+            return Err(Box::new(StorageError::new(&format!(
+                "{} uses layout version {}, this node only understands up to {}",
+                self.root.display(), version, LAYOUT_VERSION
+            ))));
+        }
+        for directory in [self.root.clone(), self.keystore_dir(), self.chains_dir(), self.peers_dir()] {
+            create_dir(&directory)?;
+        }
+        if let Err(error) = platform::make_dir_private(&self.keystore_dir()) {
+            return Err(Box::new(StorageError::new(&format!("{}: {}", self.keystore_dir().display(), error))));
+        }
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(self, legacy_dir)?;
+            self.write_layout(index as u32 + 1)?;
+            report!("Migrated {} to layout version {}", self.root.display(), index + 1);
+        }
+        Ok(())
+    }
+
+    fn write_layout(&self, version: u32) -> Result<(), Box<dyn BlockchainError>> {
+        let content = serde_json::to_string_pretty(&Layout { version }).unwrap();
+        match fs::write(self.root.join(LAYOUT_FILE), content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+}
+
+fn create_dir(directory: &Path) -> Result<(), Box<dyn BlockchainError>> {
+    match fs::create_dir_all(directory) {
+        Ok(_) => Ok(()),
+        Err(error) => Err(Box::new(StorageError::new(&format!("{}: {}", directory.display(), error))))
+    }
+}
+
+// moves node.json and schedule.json out of the working directory, never overwriting
+fn import_working_directory(dirs: &AppDirs, legacy_dir: &Path) -> Result<(), Box<dyn BlockchainError>> {
+    let moves = [
+        (legacy_dir.join(LEGACY_CONFIG_FILE), dirs.config_file()),
+        (legacy_dir.join(SCHEDULE_FILE), dirs.schedule_file()),
+    ];
+    for (from, to) in moves {
+        if !from.is_file() || to.exists() {
+            continue;
+        }
+        // rename fails across file systems, copying and removing works everywhere
+        let moved = fs::copy(&from, &to).and_then(|_| fs::remove_file(&from));
+        if let Err(error) = moved {
+            return Err(Box::new(StorageError::new(&format!("Could not move {}: {}", from.display(), error))));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use crate::dirs::{AppDirs, LAYOUT_VERSION};
+
+    #[test]
+    fn migrates_legacy_files_once_and_refuses_newer_layouts() {
+        let scratch = env::temp_dir().join(format!("kingcoin-dirs-{}", std::process::id()));
+        let legacy = scratch.join("legacy");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("node.json"), "{}").unwrap();
+        let dirs = AppDirs::at(scratch.join("home"));
+
+        assert_eq!(dirs.layout_version().ok(), Some(0));
+        assert!(dirs.prepare(&legacy).is_ok());
+        assert_eq!(dirs.layout_version().ok(), Some(LAYOUT_VERSION));
+        assert!(dirs.config_file().is_file() && dirs.keystore_dir().is_dir());
+        assert!(!legacy.join("node.json").exists());
+
+        fs::write(legacy.join("node.json"), "{\"min_peers\": 2}").unwrap();
+        assert!(dirs.prepare(&legacy).is_ok());
+        assert!(legacy.join("node.json").exists());
+        assert_eq!(fs::read_to_string(dirs.config_file()).unwrap(), "{}");
+
+        fs::write(dirs.root().join("layout.json"), "{\"version\": 99}").unwrap();
+        assert!(dirs.prepare(&legacy).is_err());
+        fs::remove_dir_all(&scratch).ok();
+    }
+}
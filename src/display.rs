@@ -0,0 +1,142 @@
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono::format::{Item, StrftimeItems};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::BlockchainError;
+use crate::config::ConfigError;
+
+pub mod table;
+
+// How amounts and times are shown on the console. Exported statements and rpc answers keep plain
+// integers and RFC 3339 UTC times, other programs read those.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct DisplayConfig {
+    // groups the digits of amounts by thousands, none prints them as plain integers
+    thousands_separator: Option<char>,
+    // strftime pattern, e.g. %d.%m.%Y %H:%M
+    date_format: String,
+    // utc, local for the system's zone, or a fixed offset like +02:00
+    timezone: String,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        DisplayConfig {
+            thousands_separator: Some(','),
+            date_format: String::from("%Y-%m-%d %H:%M"),
+            timezone: String::from("utc"),
+        }
+    }
+}
+
+enum Zone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl DisplayConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        if self.thousands_separator.is_some_and(|separator| separator.is_ascii_digit() || separator == '-') {
+            return Err(Box::new(ConfigError::new("Thousands separator cannot be a digit or a minus sign")));
+        }
+        if StrftimeItems::new(&self.date_format).any(|item| item == Item::Error) {
+            return Err(Box::new(ConfigError::new(&format!("Invalid date format {}", self.date_format))));
+        }
+        if self.zone().is_none() {
+            return Err(Box::new(ConfigError::new("Timezone must be utc, local or an offset like +02:00")));
+        }
+        Ok(())
+    }
+
+    pub fn amount(&self, amount: i64) -> String {
+        let separator = match self.thousands_separator {
+            None => return amount.to_string(),
+            Some(separator) => separator
+        };
+        let digits = amount.unsigned_abs().to_string();
+        let mut grouped = String::new();
+        if amount < 0 {
+            grouped.push('-');
+        }
+        for (index, digit) in digits.chars().enumerate() {
+            if index > 0 && (digits.len() - index).is_multiple_of(3) {
+                grouped.push(separator);
+            }
+            grouped.push(digit);
+        }
+        grouped
+    }
+
+    // the sign always shown, for amounts coming in and going out
+    pub fn signed_amount(&self, amount: i64) -> String {
+        match amount < 0 {
+            true => self.amount(amount),
+            false => format!("+{}", self.amount(amount)),
+        }
+    }
+
+    pub fn time(&self, time: DateTime<Utc>) -> String {
+        match self.zone() {
+            Some(Zone::Local) => time.with_timezone(&Local).format(&self.date_format).to_string(),
+            Some(Zone::Fixed(offset)) => time.with_timezone(&offset).format(&self.date_format).to_string(),
+            Some(Zone::Utc) | None => time.format(&self.date_format).to_string(),
+        }
+    }
+
+    fn zone(&self) -> Option<Zone> {
+        match self.timezone.to_lowercase().as_str() {
+            "utc" => Some(Zone::Utc),
+            "local" => Some(Zone::Local),
+            offset => parse_offset(offset).map(Zone::Fixed),
+        }
+    }
+}
+
+// +hh:mm or -hh:mm
+fn parse_offset(offset: &str) -> Option<FixedOffset> {
+    let (sign, rest) = match offset.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<i32>().ok()?, minutes.parse::<i32>().ok()?);
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use crate::display::DisplayConfig;
+
+    #[test]
+    fn amounts_and_times_follow_the_configured_locale() {
+        let defaults = DisplayConfig::default();
+        assert!(defaults.validate().is_ok());
+        assert_eq!(defaults.amount(999), "999");
+        assert_eq!(defaults.amount(1234567), "1,234,567");
+        assert_eq!(defaults.amount(-100000), "-100,000");
+        assert_eq!(defaults.signed_amount(2500), "+2,500");
+        let evening = Utc.with_ymd_and_hms(2024, 6, 30, 22, 15, 0).unwrap();
+        assert_eq!(defaults.time(evening), "2024-06-30 22:15");
+
+        let german: DisplayConfig = serde_json::from_str(
+            r#"{"thousands_separator": ".", "date_format": "%d.%m.%Y %H:%M", "timezone": "+02:00"}"#
+        ).unwrap();
+        assert!(german.validate().is_ok());
+        assert_eq!(german.amount(1234567), "1.234.567");
+        assert_eq!(german.time(evening), "01.07.2024 00:15");
+
+        let plain: DisplayConfig = serde_json::from_str(r#"{"thousands_separator": null}"#).unwrap();
+        assert_eq!(plain.amount(1234567), "1234567");
+        for invalid in [r#"{"timezone": "Europe/Warsaw"}"#, r#"{"date_format": "%Y-%"}"#, r#"{"thousands_separator": "5"}"#] {
+            assert!(serde_json::from_str::<DisplayConfig>(invalid).unwrap().validate().is_err());
+        }
+    }
+}
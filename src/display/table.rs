@@ -0,0 +1,138 @@
+use std::io::IsTerminal;
+
+use crate::output;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Color {
+    // money coming in
+    Green,
+    // money going out
+    Red,
+    // waiting in the mempool
+    Yellow,
+}
+
+impl Color {
+    fn code(&self) -> &'static str {
+        match self {
+            Color::Green => "32",
+            Color::Red => "31",
+            Color::Yellow => "33",
+        }
+    }
+}
+
+// How a table is printed. Output piped to another program, json mode and --plain get tab
+// separated columns without padding or colors, what cut and awk read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Style {
+    Plain,
+    Aligned,
+    Colored,
+}
+
+impl Style {
+    // NO_COLOR or a dumb terminal keep the columns aligned but uncolored
+    pub fn detect(plain: bool) -> Style {
+        if plain || output::json() || !std::io::stdout().is_terminal() {
+            return Style::Plain;
+        }
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let dumb = std::env::var("TERM").is_ok_and(|term| term == "dumb");
+        match no_color || dumb {
+            true => Style::Aligned,
+            false => Style::Colored,
+        }
+    }
+}
+
+pub struct Table {
+    header: Vec<String>,
+    rows: Vec<(Vec<String>, Option<Color>)>,
+    // aligned to the right, amounts and counts
+    numeric: Vec<usize>,
+}
+
+impl Table {
+    pub fn new(header: &[&str]) -> Table {
+        Table {
+            header: header.iter().map(|title| title.to_string()).collect(),
+            rows: vec![],
+            numeric: vec![],
+        }
+    }
+
+    pub fn with_numeric(mut self, columns: &[usize]) -> Self {
+        self.numeric = columns.to_vec();
+        self
+    }
+
+    // memos may hold tabs and line breaks, they would break the columns
+    pub fn push(&mut self, cells: Vec<String>, color: Option<Color>) {
+        let cells = cells.into_iter().map(|cell| cell.replace(['\t', '\n', '\r'], " ")).collect();
+        self.rows.push((cells, color));
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn render(&self, style: Style) -> String {
+        let lines = std::iter::once((&self.header, None)).chain(self.rows.iter().map(|(cells, color)| (cells, *color)));
+        if style == Style::Plain {
+            return lines.map(|(cells, _)| cells.join("\t")).collect::<Vec<String>>().join("\n");
+        }
+        let mut widths = vec![0; self.header.len()];
+        for (cells, _) in lines.clone() {
+            for (column, cell) in cells.iter().enumerate().take(widths.len()) {
+                widths[column] = widths[column].max(cell.chars().count());
+            }
+        }
+        lines.map(|(cells, color)| {
+            let padded: Vec<String> = cells.iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(column, (cell, width))| match self.numeric.contains(&column) {
+                    true => format!("{:>width$}", cell, width = width),
+                    false => format!("{:<width$}", cell, width = width),
+                })
+                .collect();
+            let line = padded.join("  ").trim_end().to_string();
+            match (style, color) {
+                (Style::Colored, Some(color)) => format!("\x1b[{}m{}\x1b[0m", color.code(), line),
+                _ => line,
+            }
+        }).collect::<Vec<String>>().join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::display::table::{Color, Style, Table};
+
+    #[test]
+    fn tables_align_columns_and_color_only_terminals() {
+        let mut table = Table::new(&["Block", "Amount", "Memo"]).with_numeric(&[1]);
+        table.push(vec!["#12".to_string(), "+1,500".to_string(), "Salary".to_string()], Some(Color::Green));
+        table.push(vec!["pending".to_string(), "-20".to_string(), "Rent\tand\nwater".to_string()], Some(Color::Yellow));
+        assert_eq!(table.len(), 2);
+
+        assert_eq!(
+            table.render(Style::Plain),
+            "Block\tAmount\tMemo\n#12\t+1,500\tSalary\npending\t-20\tRent and water"
+        );
+        assert_eq!(
+            table.render(Style::Aligned),
+            "Block    Amount  Memo\n\
+             #12      +1,500  Salary\n\
+             pending     -20  Rent and water"
+        );
+        let colored = table.render(Style::Colored);
+        assert!(colored.starts_with("Block    Amount  Memo\n\x1b[32m#12      +1,500  Salary\x1b[0m\n"));
+        assert!(colored.ends_with("\x1b[33mpending     -20  Rent and water\x1b[0m"));
+    }
+}
@@ -0,0 +1,328 @@
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast::error::RecvError, mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status};
+use tonic::transport::Server;
+
+use crate::blockchain::{access, builder, Transaction};
+use crate::blockchain::core::ChainEvent;
+use crate::blockchain::history::HistoryFilter;
+use crate::rpc::{RpcCall, RpcRequest, RpcResponse};
+use crate::state::SharedState;
+use crate::watch::{WalletActivity, WalletWatcher};
+
+use proto::node_server::{Node, NodeServer};
+
+pub mod proto {
+    tonic::include_proto!("kingcoin");
+}
+
+// events a subscriber has not read yet, one that falls further behind is cut off
+pub static GRPC_STREAM_CAPACITY: usize = 64;
+
+// Queries and submissions take the same way as json rpc requests, through the main loop that
+// owns the chains. Subscriptions only listen to chain events and never hold a chain lock.
+pub struct GrpcNode {
+    calls: mpsc::Sender<RpcCall>,
+    state: SharedState,
+}
+
+impl GrpcNode {
+    pub fn new(calls: mpsc::Sender<RpcCall>, state: SharedState) -> GrpcNode {
+        GrpcNode {
+            calls,
+            state,
+        }
+    }
+
+    async fn call(&self, request: RpcRequest) -> Result<RpcResponse, Status> {
+        let (sender, receiver) = oneshot::channel();
+        if self.calls.send((request, sender)).await.is_err() {
+            return Err(Status::unavailable("Node is shutting down"));
+        }
+        match receiver.await {
+            Ok(RpcResponse::Failed(reason)) => Err(Status::invalid_argument(reason)),
+            Ok(response) => Ok(response),
+            Err(_) => Err(Status::unavailable("Node is shutting down"))
+        }
+    }
+}
+
+pub async fn serve(listener: TcpListener, node: GrpcNode) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(NodeServer::new(node))
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+}
+
+fn unexpected() -> Status {
+    Status::internal("Unexpected answer from the node")
+}
+
+fn summary(transaction: &Transaction) -> proto::TransactionSummary {
+    proto::TransactionSummary {
+        id: transaction.id(),
+        source: access::encode_address(transaction.source_address()),
+        target: access::encode_address(transaction.target_address()),
+        amount: transaction.amount(),
+        nonce: transaction.nonce(),
+        json: builder::encode(transaction),
+    }
+}
+
+fn chain_update(event: ChainEvent<Transaction>) -> Option<proto::ChainUpdate> {
+    match event {
+        ChainEvent::BlockAppended { block_number, block_hash, data } => Some(proto::ChainUpdate {
+            block_number,
+            block_hash,
+            transactions: data.iter().map(summary).collect(),
+            reorg_depth: 0,
+        }),
+        ChainEvent::Reorg { depth, new_tip_hash, .. } => Some(proto::ChainUpdate {
+            block_number: 0,
+            block_hash: new_tip_hash,
+            transactions: vec![],
+            reorg_depth: depth,
+        }),
+        _ => None
+    }
+}
+
+fn wallet_event(activity: &WalletActivity) -> proto::WalletEvent {
+    let mut event = proto::WalletEvent {
+        description: activity.describe(),
+        ..Default::default()
+    };
+    match activity {
+        WalletActivity::Pending { transaction, incoming } => {
+            event.kind = String::from("pending");
+            event.transaction = Some(summary(transaction));
+            event.incoming = *incoming;
+        }
+        WalletActivity::Confirmed { transaction, incoming, block_number, confirmations } => {
+            event.kind = String::from("confirmed");
+            event.transaction = Some(summary(transaction));
+            event.incoming = *incoming;
+            event.block_number = *block_number;
+            event.confirmations = *confirmations;
+        }
+        WalletActivity::Reorg { depth } => {
+            event.kind = String::from("reorg");
+            event.reorg_depth = *depth;
+        }
+        WalletActivity::Unconfirmed { transaction, incoming } => {
+            event.kind = String::from("unconfirmed");
+            event.transaction = Some(summary(transaction));
+            event.incoming = *incoming;
+        }
+    }
+    event
+}
+
+#[tonic::async_trait]
+impl Node for GrpcNode {
+    async fn get_account(
+        &self, request: Request<proto::AccountRequest>,
+    ) -> Result<Response<proto::Account>, Status> {
+        let address = request.into_inner().address;
+        match self.call(RpcRequest::Account { address }).await? {
+            RpcResponse::Account { confirmed, spendable, next_nonce, transfer_fee, registered } => {
+                Ok(Response::new(proto::Account {
+                    confirmed,
+                    spendable,
+                    next_nonce,
+                    transfer_fee,
+                    registered,
+                }))
+            }
+            _ => Err(unexpected())
+        }
+    }
+
+    async fn get_history(
+        &self, request: Request<proto::AccountRequest>,
+    ) -> Result<Response<proto::History>, Status> {
+        let address = request.into_inner().address;
+        let request = RpcRequest::History { address, filter: HistoryFilter::default(), page: None };
+        match self.call(request).await? {
+            RpcResponse::History(history) => Ok(Response::new(proto::History {
+                entries: history.iter()
+                    .map(|(block_number, transaction)| proto::HistoryEntry {
+                        block_number: *block_number,
+                        transaction: Some(summary(transaction)),
+                    })
+                    .collect(),
+            })),
+            _ => Err(unexpected())
+        }
+    }
+
+    async fn get_mempool(
+        &self, _: Request<proto::MempoolRequest>,
+    ) -> Result<Response<proto::Mempool>, Status> {
+        match self.call(RpcRequest::Mempool).await? {
+            RpcResponse::Mempool(pending) => Ok(Response::new(proto::Mempool {
+                transactions: pending.iter()
+                    .map(|pending| proto::PendingTransaction {
+                        transaction: Some(summary(pending.transaction())),
+                        fee: pending.fee(),
+                        age_seconds: pending.age_seconds(),
+                        fits_next_block: pending.fits_next_block(),
+                    })
+                    .collect(),
+            })),
+            _ => Err(unexpected())
+        }
+    }
+
+    async fn get_block(
+        &self, request: Request<proto::BlockRequest>,
+    ) -> Result<Response<proto::Block>, Status> {
+        let block_number = request.into_inner().block_number;
+        match self.call(RpcRequest::Block { block_number }).await? {
+            RpcResponse::Block { block_number, block_hash, time, transactions } => Ok(Response::new(proto::Block {
+                block_number,
+                block_hash,
+                time: time.map(|time| time.to_rfc3339()).unwrap_or_default(),
+                transactions: transactions.iter().map(summary).collect(),
+            })),
+            _ => Err(unexpected())
+        }
+    }
+
+    async fn submit_transactions(
+        &self, request: Request<proto::Submission>,
+    ) -> Result<Response<proto::SubmissionResult>, Status> {
+        let submitted = request.into_inner().transactions.iter()
+            .map(|encoded| builder::decode(encoded))
+            .collect::<Result<Vec<Transaction>, _>>()
+            .map_err(|error| Status::invalid_argument(error.message()))?;
+        match self.call(RpcRequest::Submit(submitted)).await? {
+            RpcResponse::Accepted => Ok(Response::new(proto::SubmissionResult {})),
+            _ => Err(unexpected())
+        }
+    }
+
+    type SubscribeBlocksStream = ReceiverStream<Result<proto::ChainUpdate, Status>>;
+
+    async fn subscribe_blocks(
+        &self, _: Request<proto::SubscribeBlocksRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let mut events = self.state.transactions().subscribe();
+        let (sender, receiver) = mpsc::channel(GRPC_STREAM_CAPACITY);
+        tokio::spawn(async move {
+            loop {
+                let update = tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => match chain_update(event) {
+                            None => continue,
+                            Some(update) => Ok(update)
+                        },
+                        // the subscriber fills the gap with GetBlock after resubscribing
+                        Err(RecvError::Lagged(skipped)) => Err(Status::data_loss(
+                            format!("Skipped {} chain events", skipped)
+                        )),
+                        Err(RecvError::Closed) => return,
+                    },
+                    _ = sender.closed() => return,
+                };
+                let lagged = update.is_err();
+                if sender.send(update).await.is_err() || lagged {
+                    return;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    type SubscribeWalletEventsStream = ReceiverStream<Result<proto::WalletEvent, Status>>;
+
+    async fn subscribe_wallet_events(
+        &self, request: Request<proto::AccountRequest>,
+    ) -> Result<Response<Self::SubscribeWalletEventsStream>, Status> {
+        let address = access::decode_address(&request.into_inner().address)
+            .map_err(|error| Status::invalid_argument(error.message()))?;
+        let mut watcher = WalletWatcher::new(address, self.state.transactions().subscribe());
+        let (sender, receiver) = mpsc::channel(GRPC_STREAM_CAPACITY);
+        tokio::spawn(async move {
+            loop {
+                let activity = tokio::select! {
+                    activity = watcher.next_activity() => activity,
+                    _ = sender.closed() => return,
+                };
+                for activity in &activity {
+                    if sender.send(Ok(wallet_event(activity))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use libp2p::PeerId;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use crate::blockchain::{access, builder, StakeBid, Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::upgrade::UPGRADE_SCHEDULE;
+    use crate::grpc::{self, GrpcNode, proto};
+    use crate::grpc::proto::node_client::NodeClient;
+    use crate::network::NodeState;
+    use crate::rpc::{self, RpcCall};
+    use crate::state::SharedState;
+
+    #[tokio::test]
+    async fn integrator_queries_the_chain_and_follows_new_blocks() {
+        let state = SharedState::new(
+            Blockchain::<Transaction>::transaction_chain(vec![
+                Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+            ]),
+            Blockchain::<Wallet>::wallet_chain(),
+            Blockchain::<Transaction>::transaction_chain(vec![]),
+            NodeState::init(PeerId::random(), StakeBid::bid(0, [1; 32])),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = listener.local_addr().unwrap();
+        let (calls, mut received) = mpsc::channel::<RpcCall>(rpc::RPC_QUEUE_CAPACITY);
+        tokio::spawn(grpc::serve(listener, GrpcNode::new(calls, state.clone())));
+        let node_state = state.clone();
+        tokio::spawn(async move {
+            while let Some((request, responder)) = received.recv().await {
+                let (response, _) = rpc::answer(
+                    request, &mut node_state.transactions_mut(), &mut node_state.wallets_mut(),
+                    &node_state.node_state(), UPGRADE_SCHEDULE.rules_at(0),
+                );
+                responder.send(response).ok();
+            }
+        });
+
+        let mut client = NodeClient::connect(format!("http://{}", endpoint)).await.unwrap();
+        let address = access::encode_address([1; 32]);
+        let account = client.get_account(proto::AccountRequest { address: address.clone() }).await.unwrap().into_inner();
+        assert_eq!((account.confirmed, account.registered), (100, false));
+        let genesis = client.get_block(proto::BlockRequest { block_number: 0 }).await.unwrap().into_inner();
+        assert_eq!(genesis.transactions.len(), 1);
+        assert!(client.get_block(proto::BlockRequest { block_number: 1 }).await.is_err());
+        let unsigned = builder::encode(&Transaction::burn([1; 32], 30));
+        assert!(client.submit_transactions(proto::Submission { transactions: vec![unsigned] }).await.is_err());
+
+        let mut updates = client.subscribe_blocks(proto::SubscribeBlocksRequest {}).await.unwrap().into_inner();
+        {
+            let mut transactions = state.transactions_mut();
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], "".to_string(), 30, Utc::now())
+            ], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+        let update = updates.message().await.unwrap().unwrap();
+        assert_eq!((update.block_number, update.transactions.len()), (1, 1));
+        assert_eq!(update.transactions[0].target, access::encode_address([2; 32]));
+    }
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rsa::rand_core::{CryptoRng, RngCore};
+
+use crate::blockchain::access::{AccessError, HotWallet, Keystore};
+use crate::blockchain::core::BlockchainError;
+use crate::platform;
+
+// The wallets a node holds, one keystore file per wallet named after it in the keystore
+// directory. Keys stay sealed on disk, a wallet is opened with its password when it is used.
+pub struct Keyring {
+    dir: PathBuf,
+}
+
+impl Keyring {
+    pub fn new(dir: PathBuf) -> Keyring {
+        Keyring {
+            dir,
+        }
+    }
+
+    pub fn create<R>(&self, name: &str, password: &str, rng: &mut R) -> Result<HotWallet, Box<dyn BlockchainError>>
+        where R: CryptoRng + RngCore {
+        let path = self.vacant_path(name)?;
+        let hot_wallet = HotWallet::generate(rng);
+        Keystore::seal(&hot_wallet, password, rng)?.write(&path)?;
+        Ok(hot_wallet)
+    }
+
+    // seals a key that was made elsewhere, e.g. read from a key file
+    pub fn import<R>(&self, name: &str, hot_wallet: &HotWallet, password: &str, rng: &mut R) -> Result<(), Box<dyn BlockchainError>>
+        where R: CryptoRng + RngCore {
+        let path = self.vacant_path(name)?;
+        Keystore::seal(hot_wallet, password, rng)?.write(&path)
+    }
+
+    pub fn open(&self, name: &str, password: &str) -> Result<HotWallet, Box<dyn BlockchainError>> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(Box::new(AccessError::new(&format!("No wallet named {}", name))));
+        }
+        Keystore::read(&path)?.open(password)
+    }
+
+    // names with the address of each wallet, sorted by name
+    pub fn list(&self) -> Vec<(String, String)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![]
+        };
+        let mut wallets: Vec<(String, String)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name = path.file_stem()?.to_str()?.to_string();
+                let keystore = Keystore::read(&path).ok()?;
+                Some((name, keystore.address().to_string()))
+            })
+            .collect();
+        wallets.sort();
+        wallets
+    }
+
+    fn vacant_path(&self, name: &str) -> Result<PathBuf, Box<dyn BlockchainError>> {
+        let path = self.path(name)?;
+        match path.exists() {
+            true => Err(Box::new(AccessError::new(&format!("Wallet {} already exists", name)))),
+            false => Ok(path)
+        }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, Box<dyn BlockchainError>> {
+        let valid = !name.is_empty()
+            && name.chars().all(|character| character.is_ascii_alphanumeric() || character == '-' || character == '_');
+        if !valid {
+            return Err(Box::new(AccessError::new("Wallet names may only hold letters, digits, - and _")));
+        }
+        match platform::portable_file_name(name) {
+            true => Ok(self.dir.join(format!("{}.json", name))),
+            false => Err(Box::new(AccessError::new(&format!("{} is reserved on Windows", name))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use crate::blockchain::access;
+    use crate::keyring::Keyring;
+    use crate::random;
+
+    #[test]
+    fn wallets_are_kept_apart_and_opened_by_name() {
+        let dir = env::temp_dir().join(format!("kingcoin-keyring-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let keyring = Keyring::new(dir.clone());
+        let mut rng = random::seeded(13);
+        let savings = keyring.create("savings", "secret", &mut rng).ok().unwrap();
+        let spending = keyring.create("spending", "other", &mut rng).ok().unwrap();
+        assert!(keyring.create("savings", "secret", &mut rng).is_err());
+        assert!(keyring.create("../escape", "secret", &mut rng).is_err());
+
+        assert_eq!(keyring.list(), vec![
+            (String::from("savings"), access::encode_address(savings.address())),
+            (String::from("spending"), access::encode_address(spending.address())),
+        ]);
+        assert_eq!(keyring.open("spending", "other").ok().map(|wallet| wallet.address()), Some(spending.address()));
+        assert!(keyring.open("savings", "other").is_err());
+        assert!(keyring.open("checking", "secret").is_err());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
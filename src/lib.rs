@@ -1,6 +1,22 @@
 extern crate core;
 
 pub mod blockchain;
+pub mod command;
+pub mod config;
+pub mod contacts;
+pub mod dirs;
+pub mod display;
+pub mod grpc;
+pub mod keyring;
+pub mod limits;
 pub mod network;
+pub mod output;
+pub mod platform;
+pub mod random;
+pub mod rpc;
+pub mod schedule;
+pub mod state;
+pub mod watch;
+pub mod webhook;
 
 type BlockHash = [u8; 64];
\ No newline at end of file
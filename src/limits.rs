@@ -0,0 +1,124 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::BlockchainError;
+
+pub struct LimitError {
+    message: String,
+}
+
+impl LimitError {
+    pub fn new(message: &str) -> LimitError {
+        LimitError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for LimitError {
+    fn message(&self) -> String {
+        format!("Limit: {}", self.message)
+    }
+}
+
+// both limits are opt-in and cover the amount plus the transfer fee
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(default)]
+pub struct SpendLimits {
+    per_transaction: Option<i64>,
+    per_day: Option<i64>,
+}
+
+impl SpendLimits {
+    pub fn new(per_transaction: Option<i64>, per_day: Option<i64>) -> SpendLimits {
+        SpendLimits {
+            per_transaction,
+            per_day,
+        }
+    }
+
+    pub fn per_transaction(&self) -> Option<i64> {
+        self.per_transaction
+    }
+    pub fn per_day(&self) -> Option<i64> {
+        self.per_day
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        for limit in [self.per_transaction, self.per_day].into_iter().flatten() {
+            if limit <= 0 {
+                return Err(Box::new(LimitError::new("Spend limits must be positive")));
+            }
+        }
+        Ok(())
+    }
+}
+
+// outgoing spends of this node over a rolling 24 hour window
+pub struct SpendTracker {
+    limits: SpendLimits,
+    spent: Vec<(DateTime<Utc>, i64)>,
+}
+
+impl SpendTracker {
+    pub fn new(limits: SpendLimits) -> SpendTracker {
+        SpendTracker {
+            limits,
+            spent: vec![],
+        }
+    }
+
+    pub fn spent_today(&self, now: DateTime<Utc>) -> i64 {
+        self.spent.iter()
+            .filter(|(time, _)| *time > now - Duration::days(1))
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    pub fn check(&self, amount: i64, now: DateTime<Utc>) -> Result<(), Box<dyn BlockchainError>> {
+        if let Some(limit) = self.limits.per_transaction {
+            if amount > limit {
+                return Err(Box::new(LimitError::new(&format!(
+                    "{} exceeds the per-transaction limit of {}", amount, limit
+                ))));
+            }
+        }
+        if let Some(limit) = self.limits.per_day {
+            let spent = self.spent_today(now);
+            if spent + amount > limit {
+                return Err(Box::new(LimitError::new(&format!(
+                    "{} would exceed the daily limit of {}, {} already spent", amount, limit, spent
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn record(&mut self, amount: i64, now: DateTime<Utc>) {
+        self.spent.retain(|(time, _)| *time > now - Duration::days(1));
+        self.spent.push((now, amount));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::limits::{SpendLimits, SpendTracker};
+
+    #[test]
+    fn daily_limit_rolls_over_after_a_day() {
+        let mut tracker = SpendTracker::new(SpendLimits::new(Some(50), Some(80)));
+        let now = Utc::now();
+        assert!(tracker.check(60, now).is_err());
+        assert!(tracker.check(50, now).is_ok());
+        tracker.record(50, now);
+        assert!(tracker.check(40, now).is_err());
+        assert!(tracker.check(30, now).is_ok());
+
+        let tomorrow = now + Duration::days(1) + Duration::seconds(1);
+        assert_eq!(tracker.spent_today(tomorrow), 0);
+        assert!(tracker.check(50, tomorrow).is_ok());
+        assert!(SpendTracker::new(SpendLimits::default()).check(i64::MAX, now).is_ok());
+    }
+}
@@ -1,51 +1,611 @@
+use std::env;
 use std::error::Error;
-use io::{BufReader};
+use std::future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use libp2p::{futures::StreamExt, Swarm};
-use tokio::io::{self, AsyncBufReadExt};
+use chrono::Utc;
+use libp2p::{futures::StreamExt, PeerId, Swarm, swarm::AddressScore};
+use rand::rngs::OsRng;
+use rsa::RsaPrivateKey;
+#[cfg(feature = "nat")]
+use libp2p::multiaddr::Protocol;
+use tokio::io::{self, BufReader};
+use tokio::sync::mpsc;
+use tokio::time::{self, Duration};
 
 use kingcoin::{
-    blockchain::{core::Blockchain, Transaction, Wallet},
-    network::{self, NodeState, communication::dispatch}
+    blockchain::{Address, core::{Blockchain, BlockchainError}, find_wallet_by_address, invariants, StakeBid, Transaction, TransactionValidator, Wallet},
+    command::{self, BanCommand, batch, Command, ContactsCommand, input, WalletCommand, StatementExport, CommandError, EscrowCommand, HtlcCommand, SponsorCommand, TokenCommand, payment_request::PaymentRequest, ScheduleCommand},
+    config::NodeConfig,
+    contacts::{AddressBook, AddressLabels},
+    dirs::AppDirs,
+    display::table::{Color, Style, Table},
+    grpc::{self, GrpcNode},
+    keyring::Keyring,
+    limits::SpendTracker,
+    network::{self, bans::BanList, known_peers::{self, KnownPeers}, NodeState, communication::{self, BlockchainDto, BlockchainMessage, dispatch, mempool}, divergence, rounds::{RoundLog, RoundRecord}, status::NodeStatus},
+    output,
+    platform,
+    random,
+    report,
+    rpc::{self, RpcRequest, RpcResponse},
+    schedule::PaymentSchedule,
+    state::SharedState,
+    watch::{WalletActivity, WalletWatcher},
+    webhook::Webhooks,
 };
+use kingcoin::blockchain::access::{self, Credential, HotWallet, Keystore};
+use kingcoin::blockchain::builder::{self, TransactionBuilder};
+use kingcoin::blockchain::contract;
+use kingcoin::blockchain::history::{self, Statement, TransactionHistory};
+use kingcoin::blockchain::keyfile;
+use kingcoin::blockchain::key_history::KeyHistory;
+use kingcoin::blockchain::memo::{self, MemoError, MemoKeys};
+use kingcoin::blockchain::signer::{self, RemoteSigner, Signer, SigningOperator};
+use kingcoin::blockchain::governance::{Governance, GovernanceVote, Proposal};
+use kingcoin::blockchain::store::BlockStore;
+use kingcoin::blockchain::proof::BalanceProof;
+use kingcoin::blockchain::protocol::{self, BURN_WALLET_ADDRESS, GenesisOverrides};
+use kingcoin::blockchain::rules::RuleRegistry;
+use kingcoin::blockchain::upgrade::{ConsensusRules, UPGRADE_SCHEDULE};
 use kingcoin::network::BlockchainBehaviour;
+use kingcoin::network::chains::ChainPayload;
 
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut swarm = network::configure_swarm();
-    let (
-        mut transactions,
-        mut wallets,
-        mut stakes
-    ) = initialize_node(&mut swarm);
-
-    let mut node_state = NodeState::init(swarm.local_peer_id().clone(),);
-    let mut stdin = BufReader::new(io::stdin()).lines();
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
+    let args: Vec<String> = env::args().collect();
+    if args.iter().any(|arg| arg == "--json") {
+        output::enable_json();
+    }
+    // files left in the working directory by older versions are moved on first start
+    let dirs = match AppDirs::locate().and_then(|dirs| dirs.prepare(Path::new(".")).map(|_| dirs)) {
+        Ok(dirs) => dirs,
+        Err(error) => {
+            report!("{}", error.message());
+            return Ok(());
+        }
+    };
+    // consensus rules differ on test networks, every mode validates with the same ones
+    let genesis = GenesisOverrides::load(&dirs.genesis_file()).and_then(|overrides| {
+        protocol::apply_genesis(overrides.clone()).map(|_| overrides)
+    });
+    match genesis {
+        Ok(overrides) if !overrides.is_empty() => report!("Genesis overrides {}", overrides.describe()),
+        Ok(_) => {}
+        Err(error) => {
+            report!("{}", error.message());
+            return Ok(());
+        }
+    }
+    if let Some(subcommand) = args.get(1) {
+        if subcommand == "keygen" {
+            generate_cold_wallet(&dirs, args.get(2));
+            return Ok(());
+        }
+        if subcommand == "signer" {
+            serve_signer(&dirs, args.get(2), args.get(3));
+            return Ok(());
+        }
+        if subcommand == "client" {
+            run_thin_client(&dirs, args.get(2), args.get(3));
+            return Ok(());
+        }
+    }
+
+    let config = match NodeConfig::load(&dirs.config_file()) {
+        Ok(config) => config,
+        Err(error) => {
+            report!("{}", error.message());
+            return Ok(());
+        }
+    };
+    let rules = match RuleRegistry::new().rules(protocol::genesis_overrides().rules()) {
+        Ok(rules) => rules,
+        Err(error) => {
+            report!("{}", error.message());
+            return Ok(());
+        }
+    };
+    let mut swarm = match network::configure_swarm(&config) {
+        Ok(swarm) => swarm,
+        Err(error) => {
+            report!("{}", error.message());
+            return Ok(());
+        }
+    };
+    let (transactions, wallets, stakes) = initialize_node(&mut swarm);
+    let transactions = match config.resident_blocks() {
+        None => transactions,
+        Some(resident_blocks) => {
+            let bounded = BlockStore::open(dirs.chains_dir().join("transactions"))
+                .and_then(|store| transactions.with_store(store, resident_blocks));
+            match bounded {
+                Ok(transactions) => transactions,
+                Err(error) => {
+                    report!("{}", error.message());
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let mut rng = random::node_rng();
+    let signer: Arc<dyn Signer> = match config.remote_signer() {
+        None => Arc::new(HotWallet::generate(&mut rng)),
+        Some(endpoint) => match RemoteSigner::connect(endpoint) {
+            Ok(remote_signer) => {
+                report!("Signing with the remote signer at {}", endpoint);
+                Arc::new(remote_signer)
+            }
+            Err(error) => {
+                report!("{}", error.message());
+                return Ok(());
+            }
+        }
+    };
+    let mut node_state = NodeState::init(
+        *swarm.local_peer_id(), StakeBid::bid(0, signer.address()),
+    ).with_bid_signer(signer.clone())
+        .with_bans(BanList::load(&dirs.bans_file()))
+        .with_key_history(KeyHistory::load(&dirs.key_history_file()))
+        .with_rounds(RoundLog::load(&dirs.rounds_file()))
+        .with_known_peers(KnownPeers::load(&dirs.known_peers_file(), Utc::now()))
+        .with_governance(Governance::load(&dirs.governance_file()))
+        .with_max_reorg_depth(config.max_reorg_depth())
+        .with_inactivity(*config.inactivity())
+        .with_min_peers(config.min_peers())
+        .with_rules(rules)
+        .with_block_interval(config.block_interval());
+    let registered = node_state.chains_mut().register(Blockchain::<Credential>::empty_chain())
+        .and_then(|_| node_state.chains_mut().persist_in(dirs.chains_dir()));
+    if let Err(error) = registered {
+        report!("{}", error.message());
+    }
+    let state = SharedState::new(transactions, wallets, stakes, node_state);
+    let (import_sender, mut opened_imports) = mpsc::unbounded_channel();
+    let mut payer = Payer {
+        signer,
+        rng,
+        pending_key: None,
+        keyring: Keyring::new(dirs.keystore_dir()),
+        opened_imports: import_sender,
+    };
+    let mut schedule = PaymentSchedule::load(&dirs.schedule_file());
+    let mut contacts = AddressBook::load(&dirs.contacts_file());
+    let mut schedule_timer = time::interval(Duration::from_secs(1));
+    let mut sync_timer = time::interval(Duration::from_secs(1));
+    let mut outbox_timer = time::interval(Duration::from_secs(1));
+    let mut block_timer = time::interval(config.block_interval());
+    let mut known_peers_timer = time::interval(Duration::from_secs(known_peers::SAVE_INTERVAL_SECONDS));
+    let mut watcher: Option<WalletWatcher> = None;
+    let mut spending = SpendTracker::new(config.spend_limits());
+    let mut prompt: Option<Prompt> = None;
+    let mut commands = input::spawn_reader(BufReader::new(io::stdin()));
+    for address in config.listen_addresses() {
+        swarm.listen_on(address)?;
+    }
+    if !state.node_state().known_peers().is_empty() {
+        report!("Reconnecting to {} known peer address(es)", state.node_state().known_peers().len());
+        dispatch::dial_known_peers(&mut swarm, &state.node_state());
+    }
+    for address in config.external_addresses() {
+        report!("Advertising {}", address);
+        swarm.add_external_address(address, AddressScore::Infinite);
+    }
+    listen_on_relays(&mut swarm, &config)?;
+    // the sender stays alive without an rpc address so the branch below just never fires
+    let (rpc_sender, mut rpc_calls) = mpsc::channel(rpc::RPC_QUEUE_CAPACITY);
+    if let Some(rpc_address) = config.rpc_address() {
+        let listener = tokio::net::TcpListener::bind(rpc_address).await?;
+        report!("Serving thin clients on {}", rpc_address);
+        tokio::spawn(rpc::serve(listener, rpc_sender.clone()));
+    }
+    if let Some(grpc_address) = config.grpc_address() {
+        let listener = tokio::net::TcpListener::bind(grpc_address).await?;
+        report!("Serving grpc on {}", grpc_address);
+        let node = GrpcNode::new(rpc_sender.clone(), state.clone());
+        tokio::spawn(async move {
+            if let Err(error) = grpc::serve(listener, node).await {
+                report!("Grpc server stopped: {}", error);
+            }
+        });
+    }
+    if !config.webhooks().is_empty() {
+        match Webhooks::start(config.webhooks()) {
+            Ok(webhooks) => {
+                report!("Posting chain events to {} webhook(s)", config.webhooks().len());
+                webhooks.follow(state.transactions().subscribe());
+                webhooks.follow_slashing(state.stakes().subscribe());
+            }
+            Err(error) => report!("{}", error.message())
+        }
+    }
+    let (grant_sender, mut solved_grants) = mpsc::unbounded_channel();
     loop {
+        // typed commands go first, a flood of network events must not make the prompt lag
         tokio::select! {
-            io_result = stdin.next_line() => {
-                match io_result {
-                    Ok(command) => {
-                        let stop = !dispatch_command(command);
-                        if stop {
-                            break Ok(());
-                        }
-                    },
-                    Err(error) => println!("{}", error.to_string())
+            biased;
+            command = commands.recv() => {
+                let stop = match command {
+                    None => true,
+                    Some(command) => !output::command(&RpcResponse::Accepted, || dispatch_command(
+                        command, &mut swarm, &mut state.transactions_mut(), &mut state.wallets_mut(),
+                        &state.stakes(), &mut state.node_state_mut(), &mut payer,
+                        &mut schedule, &mut watcher, &config, &mut spending,
+                        &mut prompt, &mut contacts, &grant_sender,
+                    )),
+                };
+                hide_password_input(&prompt);
+                if stop {
+                    dispatch::save_known_peers(&mut state.node_state_mut());
+                    break Ok(());
+                }
+            },
+            Some((request, responder)) = rpc_calls.recv() => {
+                let rules = current_rules(&state.node_state(), &state.transactions());
+                let (response, messages) = rpc::answer(
+                    request, &mut state.transactions_mut(), &mut state.wallets_mut(), &state.node_state(), &rules,
+                );
+                for message in messages {
+                    communication::publish_message(&mut swarm, message);
+                }
+                let _ = responder.send(response);
+            },
+            Some(grant) = solved_grants.recv() => {
+                submit_grant(&mut swarm, &mut state.transactions_mut(), &state.wallets(), &state.node_state(), grant);
+            },
+            Some(import) = opened_imports.recv() => {
+                match import.private_key {
+                    Ok(private_key) => prompt_import_seal(import.name, private_key, import.address, &state.wallets(), &mut prompt),
+                    Err(message) => report!("{}", message)
+                }
+                hide_password_input(&prompt);
+            },
+            activity = next_wallet_activity(&mut watcher) => {
+                for entry in activity {
+                    report!("{}", entry.describe());
                 }
             },
+            _ = schedule_timer.tick() => {
+                execute_due_payments(
+                    &mut swarm, &mut state.transactions_mut(), &state.wallets(), &mut state.node_state_mut(),
+                    &mut payer, &mut schedule, &mut spending,
+                );
+            },
+            _ = sync_timer.tick() => {
+                dispatch::drive_sync(&mut swarm, &state.transactions(), &mut state.node_state_mut());
+                dispatch::drive_rounds(
+                    &mut swarm, &mut state.transactions_mut(), &mut state.wallets_mut(),
+                    &mut state.node_state_mut(), &mut state.stakes_mut(),
+                );
+            },
+            _ = outbox_timer.tick() => {
+                communication::flush_outbox(&mut swarm);
+            },
+            _ = known_peers_timer.tick() => {
+                dispatch::save_known_peers(&mut state.node_state_mut());
+            },
+            _ = block_timer.tick() => {
+                dispatch::on_block_interval(
+                    &mut swarm, &state.transactions(), &state.wallets(), &mut state.node_state_mut(),
+                    &state.stakes(),
+                );
+            },
             event = swarm.select_next_some() => {
+                let mut transactions = state.transactions_mut();
+                let mut wallets = state.wallets_mut();
+                let mut stakes = state.stakes_mut();
                 dispatch::dispatch_network_event(
                     event, &mut swarm, &mut transactions,
-                    &mut wallets, &mut node_state, &mut stakes
+                    &mut wallets, &mut state.node_state_mut(), &mut stakes
                 );
             }
         }
     }
 }
 
+// whatever answers a password prompt is not shown
+fn hide_password_input(prompt: &Option<Prompt>) {
+    if let Err(error) = platform::set_echo(!matches!(prompt, Some(Prompt::Password(_)))) {
+        report!("{}", error);
+    }
+}
+
+#[cfg(feature = "nat")]
+fn listen_on_relays(
+    swarm: &mut Swarm<BlockchainBehaviour>, config: &NodeConfig,
+) -> Result<(), Box<dyn Error>> {
+    for relay in config.relay_addresses() {
+        swarm.listen_on(relay.with(Protocol::P2pCircuit))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "nat"))]
+fn listen_on_relays(
+    _swarm: &mut Swarm<BlockchainBehaviour>, config: &NodeConfig,
+) -> Result<(), Box<dyn Error>> {
+    if !config.relay_addresses().is_empty() {
+        report!("Relay addresses are ignored, rebuild with --features nat to use them");
+    }
+    Ok(())
+}
+
+// without a path the keystore is named after its address in the keystore directory
+fn generate_cold_wallet(dirs: &AppDirs, keystore_path: Option<&String>) {
+    let mut rng = random::node_rng();
+    let hot_wallet = HotWallet::generate(&mut rng);
+    let keystore_path = match keystore_path {
+        None => dirs.keystore_dir().join(format!("{}.json", access::encode_address(hot_wallet.address()))),
+        Some(path) => PathBuf::from(path)
+    };
+    if keystore_path.exists() {
+        report!("{} already exists", keystore_path.display());
+        return;
+    }
+
+    report!("Keystore password:");
+    let password = match platform::read_password() {
+        Ok(password) => password,
+        Err(error) => {
+            report!("{}", error);
+            return;
+        }
+    };
+
+    let sealed = Keystore::seal(&hot_wallet, &password, &mut rng)
+        .and_then(|keystore| keystore.write(&keystore_path));
+    match sealed {
+        Ok(_) => report!(
+            "Address: {}, keystore {}", access::encode_address(hot_wallet.address()), keystore_path.display()
+        ),
+        Err(error) => report!("{}", error.message())
+    }
+}
+
+// answers signing requests from a node on the same machine, the keys never leave this process
+fn serve_signer(dirs: &AppDirs, keystore_path: Option<&String>, endpoint: Option<&String>) {
+    let (keystore_path, endpoint) = match (keystore_path, endpoint.map(|endpoint| endpoint.parse::<SocketAddr>())) {
+        (Some(path), Some(Ok(endpoint))) if endpoint.ip().is_loopback() => (PathBuf::from(path), endpoint),
+        _ => {
+            report!("Usage: kingcoin signer <keystore file> <loopback address:port>");
+            return;
+        }
+    };
+    let password = match read_keystore_password() {
+        None => return,
+        Some(password) => password
+    };
+    // keys rotated on the device are kept beside the keystore, numbered in the order they came
+    let keystore_path = keystore_location(dirs, keystore_path);
+    let mut keys = Vec::new();
+    let mut path = keystore_path.clone();
+    while keys.is_empty() || path.exists() {
+        match unlock_keystore(&path, &password) {
+            None => return,
+            Some(hot_wallet) => keys.push(hot_wallet)
+        }
+        path = rotated_keystore(&keystore_path, keys.len());
+    }
+    let listener = match TcpListener::bind(endpoint) {
+        Ok(listener) => listener,
+        Err(error) => {
+            report!("{}", error);
+            return;
+        }
+    };
+    report!("Signing for {} on {}", access::encode_address(keys[0].address()), endpoint);
+    let mut operator = TerminalOperator {
+        keystore_path,
+        rotations: keys.len() - 1,
+        password,
+    };
+    if let Err(error) = signer::serve(&listener, keys, &mut operator, &mut random::node_rng(), None) {
+        report!("{}", error.message());
+    }
+}
+
+// whoever runs the signer answers every request at its terminal
+struct TerminalOperator {
+    keystore_path: PathBuf,
+    rotations: usize,
+    password: String,
+}
+
+impl SigningOperator for TerminalOperator {
+    fn confirm(&mut self, request: &str) -> bool {
+        report!("{}? [y/N]", request);
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+    }
+
+    fn key_rotated(&mut self, hot_wallet: &HotWallet) -> Result<(), Box<dyn BlockchainError>> {
+        let path = rotated_keystore(&self.keystore_path, self.rotations + 1);
+        Keystore::seal(hot_wallet, &self.password, &mut random::node_rng())
+            .and_then(|keystore| keystore.write(&path))?;
+        self.rotations += 1;
+        report!("Rotated key kept in {}", path.display());
+        Ok(())
+    }
+}
+
+fn rotated_keystore(keystore_path: &Path, rotation: usize) -> PathBuf {
+    let mut path = keystore_path.as_os_str().to_owned();
+    path.push(format!(".{}", rotation));
+    PathBuf::from(path)
+}
+
+fn open_keystore(dirs: &AppDirs, keystore_path: PathBuf) -> Option<HotWallet> {
+    let password = read_keystore_password()?;
+    unlock_keystore(&keystore_location(dirs, keystore_path), &password)
+}
+
+fn read_keystore_password() -> Option<String> {
+    report!("Keystore password:");
+    match platform::read_password() {
+        Ok(password) => Some(password),
+        Err(error) => {
+            report!("{}", error);
+            None
+        }
+    }
+}
+
+// bare file names are looked up in the keystore directory
+fn keystore_location(dirs: &AppDirs, keystore_path: PathBuf) -> PathBuf {
+    if keystore_path.exists() {
+        keystore_path
+    } else {
+        dirs.keystore_dir().join(keystore_path)
+    }
+}
+
+fn unlock_keystore(keystore_path: &Path, password: &str) -> Option<HotWallet> {
+    match Keystore::read(keystore_path).and_then(|keystore| keystore.open(password)) {
+        Ok(hot_wallet) => Some(hot_wallet),
+        Err(error) => {
+            report!("{}: {}", keystore_path.display(), error.message());
+            None
+        }
+    }
+}
+
+// holds only the key and asks a trusted node for everything else, never joins gossip itself
+fn run_thin_client(dirs: &AppDirs, endpoint: Option<&String>, keystore_path: Option<&String>) {
+    let (endpoint, keystore_path) = match (endpoint.map(|endpoint| endpoint.parse::<SocketAddr>()), keystore_path) {
+        (Some(Ok(endpoint)), Some(path)) => (endpoint, PathBuf::from(path)),
+        _ => {
+            report!("Usage: kingcoin client <node rpc address:port> <keystore file> [--json]");
+            return;
+        }
+    };
+    // the client shares the node's config file for how it shows amounts and times
+    let display = match NodeConfig::load(&dirs.config_file()) {
+        Ok(config) => config.display().clone(),
+        Err(error) => {
+            report!("{}", error.message());
+            return;
+        }
+    };
+    let hot_wallet = match open_keystore(dirs, keystore_path) {
+        None => return,
+        Some(hot_wallet) => hot_wallet
+    };
+    let mut rng = random::node_rng();
+    let address = access::encode_address(hot_wallet.address());
+    report!("Client for {} through {}", address, endpoint);
+    for line in std::io::stdin().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        let exit = output::command(&RpcResponse::Accepted, || {
+            let parsed = command::parse(&line);
+            let rpc_request = parsed.as_ref().ok()
+                .filter(|_| output::json())
+                .and_then(|parsed| rpc_equivalent(parsed, hot_wallet.address(), None));
+            if let Some(request) = rpc_request {
+                match rpc::request(endpoint, &request) {
+                    Ok(response) => output::document(&response),
+                    Err(error) => report_failure(error.message()),
+                }
+                return false;
+            }
+            let result = match parsed {
+                Ok(Command::Exit) => return true,
+                Ok(Command::Balance { address: None, height: None }) => {
+                    rpc::request(endpoint, &RpcRequest::Account { address: address.clone() }).map(|response| {
+                        if let RpcResponse::Account { confirmed, spendable, registered, .. } = response {
+                            let registered = if registered { "" } else { ", not registered" };
+                            report!(
+                                "{}: confirmed {}, spendable {}{}",
+                                address, display.amount(confirmed), display.amount(spendable), registered
+                            );
+                        }
+                    })
+                }
+                Ok(Command::List { contact: Some(name), .. }) => {
+                    report!("The client has no address book, give the address of @{} instead", name);
+                    Ok(())
+                }
+                Ok(Command::List { plain, filter, page, .. }) => {
+                    let request = RpcRequest::History { address: address.clone(), filter, page: Some(page) };
+                    rpc::request(endpoint, &request).map(|response| {
+                        if let RpcResponse::History(history) = response {
+                            // secrets carried on earlier pages stay unknown to the client
+                            let mut memos = MemoKeys::new(hot_wallet.address(), Some(hot_wallet.private_key()));
+                            for (_, transaction) in &history {
+                                memos.learn(transaction);
+                            }
+                            let mut table = Table::new(&["Block", "Amount", "Counterparty", "Memo"]).with_numeric(&[1]);
+                            for (block_number, transaction) in history {
+                                let (amount, counterparty) = signed_flow(&transaction, hot_wallet.address());
+                                table.push(vec![
+                                    format!("#{}", block_number), display.signed_amount(amount),
+                                    access::encode_address(counterparty),
+                                    memos.readable(transaction.title()),
+                                ], Some(flow_color(amount)));
+                            }
+                            report!("{}", table.render(Style::detect(plain)));
+                        }
+                    })
+                }
+                Ok(Command::Mempool { plain }) => {
+                    rpc::request(endpoint, &RpcRequest::Mempool).map(|response| {
+                        if let RpcResponse::Mempool(pending) = response {
+                            report!("{}", mempool::table(&pending).render(Style::detect(plain)));
+                        }
+                    })
+                }
+                Ok(Command::Rounds(count)) => {
+                    rpc::request(endpoint, &RpcRequest::Rounds { count }).map(|response| {
+                        if let RpcResponse::Rounds(rounds) = response {
+                            print_rounds(&rounds);
+                        }
+                    })
+                }
+                Ok(Command::Register) => {
+                    rpc::request(endpoint, &RpcRequest::Register(hot_wallet.wallet().clone()))
+                        .map(|_| report!("Registration of {} submitted", address))
+                }
+                Ok(Command::Send { amount, target_address, title, sealed: false, private_memo: false, dry_run: false, .. }) => {
+                    remote_send(endpoint, &hot_wallet, &mut rng, amount, target_address, title)
+                        .map(|_| report!("Sent {} to {}", amount, access::encode_address(target_address)))
+                }
+                Ok(_) => Err(Box::new(CommandError::new("Not available in client mode")) as Box<dyn BlockchainError>),
+                Err(error) => Err(error)
+            };
+            if let Err(error) = result {
+                report_failure(error.message());
+            }
+            false
+        });
+        if exit {
+            break;
+        }
+    }
+}
+
+fn remote_send(
+    endpoint: SocketAddr, hot_wallet: &HotWallet, rng: &mut OsRng,
+    amount: i64, target_address: Address, title: String,
+) -> Result<(), Box<dyn BlockchainError>> {
+    let address = access::encode_address(hot_wallet.address());
+    let (next_nonce, transfer_fee) = match rpc::request(endpoint, &RpcRequest::Account { address })? {
+        RpcResponse::Account { next_nonce, transfer_fee, .. } => (next_nonce, transfer_fee),
+        _ => return Err(Box::new(CommandError::new("Unexpected answer from the node")))
+    };
+    let signed = TransactionBuilder::transfer(hot_wallet.address(), target_address, amount)
+        .with_title(&title)
+        .with_fee(transfer_fee)
+        .with_nonce(next_nonce)
+        .sign(hot_wallet, rng)?;
+    rpc::request(endpoint, &RpcRequest::Submit(signed)).map(|_| ())
+}
+
 fn initialize_node(
     swarm: &mut Swarm<BlockchainBehaviour>
 ) -> (Blockchain<Transaction>, Blockchain<Wallet>, Blockchain<Transaction>) {
@@ -60,6 +620,1352 @@ fn initialize_node(
     (transactions, wallets, stakes)
 }
 
-fn dispatch_command(command: Option<String>) -> bool {
-todo!()
-}
\ No newline at end of file
+// replaying the whole chain takes long, it runs on a snapshot so consensus keeps going meanwhile
+fn verify_in_background(
+    transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, node_state: &mut NodeState,
+) {
+    let schedule = node_state.governance().schedule(&UPGRADE_SCHEDULE, transactions);
+    let key_history = node_state.key_history_mut();
+    if let Err(error) = key_history.update(wallets, transactions).and_then(|_| key_history.save()) {
+        report!("Could not record the key history: {}", error.message());
+    }
+    let key_history = key_history.clone();
+    let transactions = BlockchainDto::from(transactions);
+    let wallets = BlockchainDto::from(wallets);
+    report!("Verifying the chain in the background");
+    tokio::task::spawn_blocking(move || {
+        let verified = Blockchain::try_from(transactions).and_then(|transactions| {
+            let wallets = Blockchain::try_from(wallets)?;
+            invariants::verify_transactions(&transactions, &wallets, &key_history, &schedule)?;
+            Ok(transactions.chain_length())
+        });
+        match verified {
+            Ok(chain_length) => report!("Chain valid up to block {}", chain_length - 1),
+            Err(error) => report!("{}", error.message())
+        }
+    });
+}
+
+fn dispatch_command(
+    command: String, swarm: &mut Swarm<BlockchainBehaviour>,
+    transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>,
+    stakes: &Blockchain<Transaction>, node_state: &mut NodeState, payer: &mut Payer,
+    schedule: &mut PaymentSchedule, watcher: &mut Option<WalletWatcher>, config: &NodeConfig,
+    spending: &mut SpendTracker, prompt: &mut Option<Prompt>, contacts: &mut AddressBook,
+    solved_grants: &mpsc::UnboundedSender<Transaction>,
+) -> bool {
+    payer.promote_rotated_key(wallets, node_state);
+    // the line after a prompt is its answer, anything but yes cancels a send
+    match prompt.take() {
+        None => {}
+        Some(Prompt::ConfirmSend(pending)) => {
+            match command.trim() {
+                "y" | "yes" => confirm_send(swarm, transactions, wallets, payer, spending, pending),
+                _ => report_failure(String::from("Cancelled"))
+            }
+            return true;
+        }
+        Some(Prompt::Password(action)) => {
+            on_credential_password(action, command.trim_end(), node_state, payer, prompt);
+            return true;
+        }
+    }
+    let parsed = command::parse(&command);
+    let rpc_request = parsed.as_ref().ok()
+        .filter(|_| output::json())
+        .and_then(|parsed| rpc_equivalent(parsed, payer.signer.address(), Some(contacts)));
+    if let Some(request) = rpc_request {
+        let rules = current_rules(node_state, transactions);
+        output::document(&rpc::answer(request, transactions, wallets, node_state, &rules).0);
+        return true;
+    }
+    match parsed {
+        Ok(Command::Exit) => return false,
+        Ok(Command::Send { amount, target_address, title, dry_run: true, .. }) => {
+            let fee = transfer_fee(node_state, transactions);
+            let payment = OutgoingPayment {
+                amount,
+                target_address,
+                title,
+                fee,
+            };
+            dry_run_send(transactions, wallets, stakes, payer, payment);
+        }
+        Ok(Command::Send { amount, target_address, title, confirmed, sealed, private_memo, .. }) => {
+            let fee = transfer_fee(node_state, transactions);
+            let spendable = transactions.staked_breakdown(stakes, payer.signer.address()).spendable();
+            if !affordable(spendable, spending, amount + fee) {
+                return true;
+            }
+            let title = match sealed || private_memo {
+                false => title,
+                true => match seal_memo(&title, target_address, private_memo, transactions, wallets, payer) {
+                    Ok(sealed) => sealed,
+                    Err(error) => {
+                        report_failure(error.message());
+                        return true;
+                    }
+                }
+            };
+            let pending = OutgoingPayment {
+                amount,
+                target_address,
+                title,
+                fee,
+            };
+            if confirmed {
+                confirm_send(swarm, transactions, wallets, payer, spending, pending);
+            } else {
+                ask(format!(
+                    "Send {} to {} with fee {} (total {})? [y/N]",
+                    amount, access::encode_address(target_address), fee, amount + fee
+                ));
+                *prompt = Some(Prompt::ConfirmSend(pending));
+            }
+        }
+        Ok(Command::Burn { amount, confirmed }) => {
+            let fee = transfer_fee(node_state, transactions);
+            let spendable = transactions.staked_breakdown(stakes, payer.signer.address()).spendable();
+            if !affordable(spendable, spending, amount + fee) {
+                return true;
+            }
+            let pending = OutgoingPayment {
+                amount,
+                target_address: *BURN_WALLET_ADDRESS,
+                title: String::from("Burn"),
+                fee,
+            };
+            if confirmed {
+                confirm_send(swarm, transactions, wallets, payer, spending, pending);
+            } else {
+                ask(format!("Burn {} for good with fee {} (total {})? [y/N]", amount, fee, amount + fee));
+                *prompt = Some(Prompt::ConfirmSend(pending));
+            }
+        }
+        Ok(Command::Sweep { target_address, confirmed }) => {
+            let fee = transfer_fee(node_state, transactions);
+            // pending payments in either direction are left out, so is stake the registry holds
+            let spendable = transactions.staked_breakdown(stakes, payer.signer.address()).spendable();
+            let amount = spendable - fee;
+            if amount <= 0 {
+                report_failure(format!("Nothing to sweep: {} spendable, the fee is {}", spendable, fee));
+                return true;
+            }
+            if !affordable(spendable, spending, spendable) {
+                return true;
+            }
+            let pending = OutgoingPayment {
+                amount,
+                target_address,
+                title: String::from("Sweep"),
+                fee,
+            };
+            if confirmed {
+                confirm_send(swarm, transactions, wallets, payer, spending, pending);
+            } else {
+                ask(format!(
+                    "Sweep {} to {} with fee {}, leaving nothing spendable? [y/N]",
+                    amount, access::encode_address(target_address), fee
+                ));
+                *prompt = Some(Prompt::ConfirmSend(pending));
+            }
+        }
+        Ok(Command::SendBatch(file)) => {
+            let fee = transfer_fee(node_state, transactions);
+            send_batch(swarm, transactions, wallets, payer, &file, fee, spending);
+        }
+        Ok(Command::Request { amount, memo, qr_code }) => {
+            let request = PaymentRequest::new(payer.signer.address(), Some(amount), memo);
+            let qr_code = match qr_code {
+                false => Ok(None),
+                true => request.to_qr_code().map(Some),
+            };
+            match qr_code {
+                Ok(qr_code) => {
+                    let uri = request.to_uri();
+                    respond(RpcResponse::PaymentRequest { uri: uri.clone(), qr_code: qr_code.clone() }, || {
+                        report!("{}", uri);
+                        if let Some(code) = qr_code {
+                            report!("{}", code);
+                        }
+                    });
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        Ok(Command::Schedule(schedule_command)) => {
+            on_schedule_command(schedule_command, schedule)
+        }
+        Ok(Command::Watch(enabled)) => {
+            if enabled {
+                *watcher = Some(WalletWatcher::new(payer.signer.address(), transactions.subscribe()));
+                report!("Watching {}", access::encode_address(payer.signer.address()));
+            } else {
+                *watcher = None;
+                report!("Stopped watching");
+            }
+        }
+        Ok(Command::Balance { address, height }) => {
+            let address = address.unwrap_or(payer.signer.address());
+            match height {
+                None => report!(
+                    "{}: {}", access::encode_address(address),
+                    transactions.staked_breakdown(stakes, address).describe(config.display())
+                ),
+                Some(height) => match transactions.committed_balance_at(address, height) {
+                    Ok(balance) => report!(
+                        "{} at block {}: {}", access::encode_address(address), height, config.display().amount(balance)
+                    ),
+                    Err(error) => report_failure(error.message())
+                }
+            }
+        }
+        Ok(Command::Snapshot(height)) => {
+            let snapshot = match height {
+                None => transactions.latest_snapshot(),
+                Some(height) => transactions.snapshot_at(height),
+            };
+            match snapshot {
+                None => report_failure(String::from("No balance snapshot yet")),
+                Some(snapshot) => report!(
+                    "Snapshot at block {}: {} accounts, state root {}",
+                    snapshot.height(), snapshot.account_count(), snapshot.state_root()
+                ),
+            }
+        }
+        Ok(Command::Register) => {
+            let wallet = payer.signer.wallet();
+            match dispatch::register_wallet(wallets, wallet.clone()) {
+                Ok(_) => {
+                    communication::publish_message(swarm, BlockchainMessage::RegisterWallet(wallet));
+                    report!("Registration of {} submitted", access::encode_address(payer.signer.address()));
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        Ok(Command::Grant) => request_grant(transactions, node_state, payer, solved_grants),
+        Ok(Command::RegisterLogin(user_name)) => register_login(swarm, node_state, payer, &user_name),
+        Ok(Command::Login(user_name)) => login(wallets, node_state, payer, &user_name, prompt),
+        Ok(Command::RotateKey) => {
+            let rotated = match payer.signer.rotate(&mut payer.rng) {
+                Ok(rotated) => rotated,
+                Err(error) => {
+                    report_failure(error.message());
+                    return true;
+                }
+            };
+            match dispatch::update_wallet_key(wallets, rotated.wallet().clone()) {
+                Ok(_) => {
+                    communication::publish_message(
+                        swarm, BlockchainMessage::UpdateWalletKey(rotated.wallet().clone()),
+                    );
+                    report!("Key rotation of {} submitted", access::encode_address(rotated.address()));
+                    payer.pending_key = Some(rotated);
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        Ok(Command::List { raw, plain, mut filter, contact, page }) => {
+            if let Some(name) = contact {
+                match contacts.contacts().get(&name) {
+                    Some(counterparty) => filter = filter.with_counterparty(*counterparty),
+                    None => {
+                        report_failure(format!("No contact named {}", name));
+                        return true;
+                    }
+                }
+            }
+            let address = payer.signer.address();
+            let private_key = payer.signer.hot_wallet().map(HotWallet::private_key);
+            let labels = AddressLabels::new(contacts, node_state.validator_wallets()).with_raw(raw);
+            let history = match TransactionHistory::of(transactions, address) {
+                Ok(history) => history,
+                Err(error) => {
+                    report_failure(error.message());
+                    return true;
+                }
+            };
+            let memos = history.memo_keys(private_key);
+            let history = history.filter(&filter);
+            let display = config.display();
+            let mut table = Table::new(&["Block", "Time", "Amount", "Counterparty", "Memo"]).with_numeric(&[2]);
+            let mut row = |block: String, time: String, transaction: &Transaction, color: Option<Color>| {
+                let (amount, counterparty) = signed_flow(transaction, address);
+                table.push(vec![
+                    block, time, display.signed_amount(amount), labels.label(counterparty),
+                    memos.readable(transaction.title()),
+                ], Some(color.unwrap_or(flow_color(amount))));
+            };
+            for entry in history.page(page) {
+                // the genesis block has no time
+                let time = entry.time().map(|time| display.time(time)).unwrap_or_default();
+                row(format!("#{}", entry.block_number()), time, entry.transaction(), None);
+            }
+            // the mempool is newer than any page but the first
+            if page.number() == 1 {
+                for transaction in history::pending(transactions, address, &filter) {
+                    row(String::from("pending"), display.time(transaction.time()), &transaction, Some(Color::Yellow));
+                }
+            }
+            report!("{}", table.render(Style::detect(plain)));
+            let page_count = history.page_count(page.size());
+            if page.number() < page_count {
+                report!("Page {} of {}, --page {} shows older entries", page.number(), page_count, page.number() + 1);
+            }
+        }
+        Ok(Command::Statement { month, export }) => {
+            let private_key = payer.signer.hot_wallet().map(HotWallet::private_key);
+            let statement = match Statement::for_month(transactions, payer.signer.address(), month, private_key) {
+                Ok(statement) => statement,
+                Err(error) => {
+                    report_failure(error.message());
+                    return true;
+                }
+            };
+            let (file, content) = match export {
+                None => {
+                    let text = statement.describe(config.display());
+                    respond(RpcResponse::Statement(statement), || report!("{}", text));
+                    return true;
+                }
+                Some(StatementExport::Csv(file)) => (file, statement.to_csv()),
+                Some(StatementExport::Json(file)) => (file, statement.to_json()),
+            };
+            match std::fs::write(&file, content) {
+                Ok(_) => report!("Statement with {} transactions written to {}", statement.lines().len(), file.display()),
+                Err(error) => report_failure(error.to_string())
+            }
+        }
+        Ok(Command::Status) => {
+            let status = NodeStatus::collect(
+                node_state, transactions, stakes, swarm.connected_peers().count(), config.gossip(),
+            );
+            let text = status.describe();
+            respond(RpcResponse::Status(status), || report!("{}", text));
+        }
+        Ok(Command::Peers { plain }) => {
+            let mut peers: Vec<PeerId> = swarm.connected_peers().copied().collect();
+            peers.sort_by(|peer, other| node_state.latency().cost(peer).total_cmp(&node_state.latency().cost(other)));
+            respond(RpcResponse::Peers(node_state.latency().probes(&peers)), || match peers.is_empty() {
+                true => report!("No connected peers"),
+                false => report!("{}", node_state.latency().table(&peers).render(Style::detect(plain))),
+            });
+        }
+        Ok(Command::Rounds(count)) => print_rounds(&node_state.rounds().recent(count)),
+        Ok(Command::Validators { plain }) => {
+            let validators = node_state.validator_stats().table();
+            match validators.is_empty() {
+                true => report!("No validator activity seen yet"),
+                false => report!("{}", validators.render(Style::detect(plain))),
+            }
+        }
+        Ok(Command::Diff(peer)) => {
+            if !swarm.is_connected(&peer) {
+                report_failure(format!("Not connected to {}", peer));
+                return true;
+            }
+            node_state.request_diff(peer);
+            report!("Asking {} for its last {} blocks", peer, divergence::DIFF_HEADERS);
+            communication::publish_message(swarm, BlockchainMessage::HeadersRequest {
+                peer: peer.to_base58(),
+                count: divergence::DIFF_HEADERS,
+            });
+        }
+        Ok(Command::Mempool { plain }) => {
+            let block_size = current_rules(node_state, transactions).block_size();
+            let pending = mempool::pending(transactions, block_size, Utc::now());
+            match pending.is_empty() {
+                true => report!("No pending transactions"),
+                false => report!("{}", mempool::table(&pending).render(Style::detect(plain))),
+            }
+        }
+        Ok(Command::Stats) => match transactions.stats() {
+            Ok(stats) => report!("{}", stats.describe()),
+            Err(error) => report_failure(error.message())
+        },
+        Ok(Command::Audit(address)) => match BalanceProof::derive(transactions, address) {
+            Ok(proof) => report!("{}", proof.describe()),
+            Err(error) => report_failure(error.message())
+        },
+        Ok(Command::Verify) => verify_in_background(transactions, wallets, node_state),
+        Ok(Command::ShowBidPolicy) => {
+            let bid_policy = node_state.bid_policy();
+            respond(RpcResponse::BidPolicy(bid_policy), || report!("Bid policy: {}", bid_policy.describe()));
+        }
+        Ok(Command::SetBidPolicy(bid_policy)) => {
+            node_state.set_bid_policy(bid_policy);
+            report!("Bid policy set to {}", bid_policy.describe());
+        }
+        Ok(Command::Propose { change, activation_height }) => {
+            let mut proposal = Proposal::new(payer.signer.address(), change, activation_height);
+            if let Err(error) = proposal.sign(payer.signer.as_ref(), &mut payer.rng) {
+                report_failure(error.message());
+                return true;
+            }
+            let chain_height = transactions.chain_length();
+            match node_state.governance_mut().submit_proposal(proposal.clone(), wallets, chain_height) {
+                Ok(proposal_id) => {
+                    dispatch::save_governance(node_state);
+                    communication::publish_message(swarm, BlockchainMessage::Proposal(proposal));
+                    let text = format!("Proposal {}: {}", proposal_id, change.describe());
+                    respond(RpcResponse::Created(proposal_id), || report!("{}", text));
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        Ok(Command::Vote { proposal_id, approve }) => {
+            let mut vote = GovernanceVote::new(proposal_id, payer.signer.address(), approve);
+            if let Err(error) = vote.sign(payer.signer.as_ref(), &mut payer.rng) {
+                report_failure(error.message());
+                return true;
+            }
+            match node_state.governance_mut().submit_vote(vote.clone(), wallets) {
+                Ok(_) => {
+                    dispatch::save_governance(node_state);
+                    communication::publish_message(swarm, BlockchainMessage::GovernanceVote(vote));
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        Ok(Command::Proposals) => {
+            let governance = node_state.governance();
+            for proposal in governance.proposals() {
+                let tally = governance.tally(&proposal.id(), transactions);
+                report!(
+                    "{} {} at height {}: {} for, {} against",
+                    proposal.id(), proposal.change().describe(), proposal.activation_height(),
+                    tally.approving_stake(), tally.rejecting_stake()
+                );
+            }
+        }
+        Ok(Command::Wallet(wallet_command)) => on_wallet_command(wallet_command, wallets, node_state, payer, prompt),
+        Ok(Command::Contacts(contacts_command)) => on_contacts_command(contacts_command, contacts),
+        Ok(Command::Bans(ban_command)) => on_ban_command(ban_command, node_state),
+        Ok(Command::Htlc(htlc_command)) => {
+            let fee = transfer_fee(node_state, transactions);
+            on_htlc_command(htlc_command, swarm, transactions, wallets, payer, fee);
+        }
+        Ok(Command::Token(token_command)) => {
+            let fee = transfer_fee(node_state, transactions);
+            on_token_command(token_command, swarm, transactions, wallets, payer, fee);
+        }
+        Ok(Command::Escrow(escrow_command)) => {
+            let fee = transfer_fee(node_state, transactions);
+            on_escrow_command(escrow_command, swarm, transactions, wallets, node_state, payer, fee);
+        }
+        Ok(Command::Sponsor(sponsor_command)) => {
+            let fee = transfer_fee(node_state, transactions);
+            on_sponsor_command(sponsor_command, swarm, transactions, wallets, payer, fee);
+        }
+        Err(error) => report_failure(error.message())
+    }
+    true
+}
+
+// in json mode commands the rpc api has an answer for are printed with its schemas
+fn rpc_equivalent(command: &Command, address: Address, contacts: Option<&AddressBook>) -> Option<RpcRequest> {
+    let address = access::encode_address(address);
+    match command {
+        Command::Balance { address: queried, height: None } => Some(RpcRequest::Account {
+            address: queried.map_or(address, access::encode_address),
+        }),
+        Command::Balance { address: queried, height: Some(height) } => Some(RpcRequest::BalanceAt {
+            address: queried.map_or(address, access::encode_address),
+            height: *height,
+        }),
+        Command::List { filter, contact, page, .. } => {
+            let filter = match contact {
+                None => filter.clone(),
+                Some(name) => filter.clone().with_counterparty(*contacts?.contacts().get(name)?),
+            };
+            Some(RpcRequest::History { address, filter, page: Some(*page) })
+        }
+        Command::Mempool { .. } => Some(RpcRequest::Mempool),
+        Command::Rounds(count) => Some(RpcRequest::Rounds { count: *count }),
+        Command::Snapshot(height) => Some(RpcRequest::Snapshot { height: *height }),
+        Command::Stats => Some(RpcRequest::Stats),
+        Command::Audit(audited) => Some(RpcRequest::Audit { address: access::encode_address(*audited) }),
+        Command::Validators { .. } => Some(RpcRequest::Validators),
+        Command::Proposals => Some(RpcRequest::Proposals),
+        Command::Token(TokenCommand::Balance(token_id)) => Some(RpcRequest::Tokens { address, token_id: token_id.clone() }),
+        _ => None,
+    }
+}
+
+fn report_failure(message: String) {
+    respond(RpcResponse::Failed(message.clone()), || report!("{}", message));
+}
+
+// the next line answers the question
+fn ask(question: String) {
+    respond(RpcResponse::Prompt(question.clone()), || report!("{}", question));
+}
+
+// scripts get the structured answer, people the text
+fn respond(response: RpcResponse, text: impl FnOnce()) {
+    match output::json() {
+        true => output::document(&response),
+        false => text(),
+    }
+}
+
+// the node's own signing key together with the randomness its signatures draw from
+struct Payer {
+    signer: Arc<dyn Signer>,
+    rng: OsRng,
+    // the rotated signer, keeping its key where the current one does
+    pending_key: Option<Box<dyn Signer>>,
+    keyring: Keyring,
+    opened_imports: mpsc::UnboundedSender<OpenedImport>,
+}
+
+// a key file decrypted off the event loop, sealed into the keyring once the user picks a password
+struct OpenedImport {
+    name: String,
+    address: Option<Address>,
+    private_key: Result<RsaPrivateKey, String>,
+}
+
+impl Payer {
+    // the old key keeps signing until the rotated one is committed to the wallet chain, bids too
+    // once the node's own wallet rotated
+    fn promote_rotated_key(&mut self, wallets: &Blockchain<Wallet>, node_state: &mut NodeState) {
+        let committed = match &self.pending_key {
+            Some(pending_key) => find_wallet_by_address(pending_key.address(), wallets)
+                .is_some_and(|wallet| wallet.key() == pending_key.wallet().key()),
+            None => false
+        };
+        if committed {
+            self.signer = Arc::from(self.pending_key.take().unwrap());
+            report!("Key rotation of {} committed", access::encode_address(self.signer.address()));
+            if self.signer.address() == node_state.wallet_address() {
+                node_state.set_bid_signer(self.signer.clone());
+            }
+        }
+    }
+}
+
+// spendable net of stake the node's registry holds, bids in escrow included
+fn affordable(spendable: i64, spending: &SpendTracker, total: i64) -> bool {
+    if total > spendable {
+        report_failure(format!("Insufficient spendable balance: {} needed, {} available", total, spendable));
+        return false;
+    }
+    if let Err(error) = spending.check(total, Utc::now()) {
+        report_failure(error.message());
+        return false;
+    }
+    true
+}
+
+fn seal_memo(
+    title: &str, target_address: Address, private_memo: bool, transactions: &Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer,
+) -> Result<String, Box<dyn BlockchainError>> {
+    let recipient = find_wallet_by_address(target_address, wallets)
+        .and_then(|wallet| wallet.key().clone());
+    let (recipient_key, sender_key) = match (recipient, payer.signer.wallet().key().clone()) {
+        (Some(recipient_key), Some(sender_key)) => (recipient_key, sender_key),
+        _ => return Err(Box::new(MemoError::new("Memos can only be sealed between registered wallets")))
+    };
+    if !private_memo {
+        return memo::seal(title, &recipient_key, &sender_key, &mut payer.rng);
+    }
+    let private_key = payer.signer.hot_wallet().map(HotWallet::private_key);
+    let memos = TransactionHistory::of(transactions, payer.signer.address())?.memo_keys(private_key);
+    memos.private_memo(title, target_address, &recipient_key, &sender_key, &mut payer.rng)
+}
+
+enum Prompt {
+    ConfirmSend(OutgoingPayment),
+    Password(CredentialAction),
+}
+
+enum CredentialAction {
+    CreateWallet(String),
+    UseWallet(String),
+    ExportKey {
+        name: String,
+        path: PathBuf,
+    },
+    // the wallet's key, waiting for the password of the exported file
+    SealExport {
+        hot_wallet: Box<HotWallet>,
+        path: PathBuf,
+    },
+    OpenImport {
+        name: String,
+        content: String,
+        address: Option<Address>,
+    },
+    SealImport {
+        name: String,
+        hot_wallet: Box<HotWallet>,
+    },
+}
+
+fn on_credential_password(
+    action: CredentialAction, password: &str, node_state: &mut NodeState, payer: &mut Payer,
+    prompt: &mut Option<Prompt>,
+) {
+    match action {
+        CredentialAction::CreateWallet(name) => {
+            match payer.keyring.create(&name, password, &mut payer.rng) {
+                Ok(hot_wallet) => use_wallet(node_state, payer, name, hot_wallet),
+                Err(error) => report_failure(error.message())
+            }
+        }
+        CredentialAction::UseWallet(name) => {
+            match payer.keyring.open(&name, password) {
+                Ok(hot_wallet) => use_wallet(node_state, payer, name, hot_wallet),
+                Err(error) => report_failure(error.message())
+            }
+        }
+        CredentialAction::ExportKey { name, path } => {
+            match payer.keyring.open(&name, password) {
+                Ok(hot_wallet) => {
+                    ask(String::from("Password for the exported key:"));
+                    let hot_wallet = Box::new(hot_wallet);
+                    *prompt = Some(Prompt::Password(CredentialAction::SealExport { hot_wallet, path }));
+                }
+                Err(error) => report_failure(error.message())
+            }
+        }
+        CredentialAction::SealExport { hot_wallet, path } => {
+            let exported = keyfile::export_key(&hot_wallet, password, &mut payer.rng)
+                .and_then(|exported| platform::write_private(&path, exported.as_bytes()).map_err(|error| {
+                    Box::new(CommandError::new(&error.to_string())) as Box<dyn BlockchainError>
+                }));
+            match exported {
+                Ok(_) => report!("Exported the key of {} to {}", access::encode_address(hot_wallet.address()), path.display()),
+                Err(error) => report_failure(error.message())
+            }
+        }
+        CredentialAction::OpenImport { name, content, address } => {
+            report!("Opening the key file");
+            let password = password.to_string();
+            let opened_imports = payer.opened_imports.clone();
+            tokio::task::spawn_blocking(move || {
+                let private_key = keyfile::import_key(&content, &password).map_err(|error| error.message());
+                let _ = opened_imports.send(OpenedImport { name, address, private_key });
+            });
+        }
+        CredentialAction::SealImport { name, hot_wallet } => {
+            match payer.keyring.import(&name, &hot_wallet, password, &mut payer.rng) {
+                Ok(_) => report!("Imported wallet {} ({})", name, access::encode_address(hot_wallet.address())),
+                Err(error) => report_failure(error.message())
+            }
+        }
+    }
+}
+
+// binds the user name to the active wallet with its signature, the keystore stays in the keyring
+fn register_login(swarm: &mut Swarm<BlockchainBehaviour>, node_state: &mut NodeState, payer: &mut Payer, user_name: &str) {
+    let credential = match Credential::register(user_name, payer.signer.as_ref(), &mut payer.rng) {
+        Ok(credential) => credential,
+        Err(error) => {
+            report_failure(error.message());
+            return;
+        }
+    };
+    let data = serde_json::to_value(&credential).unwrap();
+    let credentials = node_state.chains_mut()
+        .get_mut(Credential::chain_name())
+        .expect("Credentials chain is registered");
+    match credentials.submit_data(data.clone()) {
+        Ok(_) => {
+            communication::publish_message(swarm, BlockchainMessage::ChainData {
+                chain: Credential::chain_name().to_string(),
+                data,
+            });
+            report!("User name {} submitted for {}", user_name, access::encode_address(payer.signer.address()));
+        }
+        Err(error) => report_failure(error.message())
+    }
+}
+
+// finds the wallet the user name stands for in the local keyring and opens it like wallet use
+fn login(
+    wallets: &Blockchain<Wallet>, node_state: &NodeState, payer: &Payer, user_name: &str,
+    prompt: &mut Option<Prompt>,
+) {
+    let credentials = node_state.chains()
+        .chain::<Credential>()
+        .expect("Credentials chain is registered");
+    let address = match access::login(credentials, wallets, user_name) {
+        Ok(address) => access::encode_address(address),
+        Err(error) => {
+            report_failure(error.message());
+            return;
+        }
+    };
+    let name = payer.keyring.list().into_iter()
+        .find(|(_, keystore_address)| *keystore_address == address)
+        .map(|(name, _)| name);
+    match name {
+        None => report_failure(format!("No keystore of {} on this node, import its key first", address)),
+        Some(name) => {
+            ask(format!("Password for {}:", user_name));
+            *prompt = Some(Prompt::Password(CredentialAction::UseWallet(name)));
+        }
+    }
+}
+
+fn prompt_import_seal(
+    name: String, private_key: RsaPrivateKey, address: Option<Address>, wallets: &Blockchain<Wallet>,
+    prompt: &mut Option<Prompt>,
+) {
+    let hot_wallet = Box::new(match address {
+        None => HotWallet::from_private_key(private_key),
+        Some(address) => match HotWallet::for_wallet(private_key, address, wallets) {
+            Ok(hot_wallet) => hot_wallet,
+            Err(error) => {
+                report_failure(error.message());
+                return;
+            }
+        },
+    });
+    ask(format!("Password for the new wallet {}:", name));
+    *prompt = Some(Prompt::Password(CredentialAction::SealImport { name, hot_wallet }));
+}
+
+// balance, send and the other payment commands act on the active wallet, the stake bid stays
+// with the wallet the node started with
+fn use_wallet(node_state: &mut NodeState, payer: &mut Payer, name: String, hot_wallet: HotWallet) {
+    report!("Using wallet {} ({})", name, access::encode_address(hot_wallet.address()));
+    payer.signer = Arc::new(hot_wallet);
+    payer.pending_key = None;
+    node_state.set_active_wallet(Some(name));
+}
+
+fn on_wallet_command(
+    command: WalletCommand, wallets: &Blockchain<Wallet>, node_state: &NodeState, payer: &Payer,
+    prompt: &mut Option<Prompt>,
+) {
+    match command {
+        WalletCommand::Create(name) => {
+            ask(format!("Password for the new wallet {}:", name));
+            *prompt = Some(Prompt::Password(CredentialAction::CreateWallet(name)));
+        }
+        WalletCommand::Use(name) => {
+            ask(format!("Password for {}:", name));
+            *prompt = Some(Prompt::Password(CredentialAction::UseWallet(name)));
+        }
+        WalletCommand::ExportKey { name, path } => {
+            ask(format!("Password for {}:", name));
+            *prompt = Some(Prompt::Password(CredentialAction::ExportKey { name, path }));
+        }
+        WalletCommand::ImportKey { name, path, address } => {
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(error) => {
+                    report_failure(format!("{}: {}", path.display(), error));
+                    return;
+                }
+            };
+            if keyfile::encrypted(&content) {
+                ask(format!("Password of {}:", path.display()));
+                *prompt = Some(Prompt::Password(CredentialAction::OpenImport { name, content, address }));
+                return;
+            }
+            match keyfile::import_key(&content, "") {
+                Ok(private_key) => prompt_import_seal(name, private_key, address, wallets, prompt),
+                Err(error) => report_failure(error.message())
+            }
+        }
+        WalletCommand::List => {
+            let wallets = payer.keyring.list().into_iter()
+                .map(|(name, address)| {
+                    let active = node_state.active_wallet() == Some(name.as_str());
+                    (name, address, active)
+                })
+                .collect::<Vec<_>>();
+            respond(RpcResponse::Wallets(wallets.clone()), || {
+                if wallets.is_empty() {
+                    report!("No wallets, create one with wallet create <name>");
+                }
+                for (name, address, active) in wallets {
+                    report!("{}{} {}", if active { "* " } else { "  " }, name, address);
+                }
+            });
+        }
+    }
+}
+
+struct OutgoingPayment {
+    amount: i64,
+    target_address: Address,
+    title: String,
+    fee: i64,
+}
+
+// the amount as the wallet sees it, negative when paid out, and the other side of the transfer
+fn signed_flow(transaction: &Transaction, address: Address) -> (i64, Address) {
+    match transaction.source_address() == address {
+        true => (-transaction.amount(), transaction.target_address()),
+        false => (transaction.amount(), transaction.source_address()),
+    }
+}
+
+fn flow_color(amount: i64) -> Color {
+    match amount < 0 {
+        true => Color::Red,
+        false => Color::Green,
+    }
+}
+
+fn print_rounds(rounds: &[RoundRecord]) {
+    if rounds.is_empty() {
+        report!("No rounds recorded");
+    }
+    for round in rounds {
+        report!("{}", round.describe());
+    }
+}
+
+fn confirm_send(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, spending: &mut SpendTracker,
+    pending: OutgoingPayment,
+) {
+    report!("Sending {} to {}", pending.amount, access::encode_address(pending.target_address));
+    let total = pending.amount + pending.fee;
+    match send_payment(swarm, transactions, wallets, payer, pending) {
+        Ok(_) => spending.record(total, Utc::now()),
+        Err(error) => report_failure(error.message())
+    }
+}
+
+fn on_contacts_command(command: ContactsCommand, contacts: &mut AddressBook) {
+    let result = match command {
+        ContactsCommand::List => {
+            let listed = contacts.contacts().iter()
+                .map(|(name, address)| (name.clone(), access::encode_address(*address)))
+                .collect::<Vec<_>>();
+            respond(RpcResponse::Contacts(listed.clone()), || {
+                if listed.is_empty() {
+                    report!("No contacts");
+                }
+                for (name, address) in listed {
+                    report!("@{} {}", name, address);
+                }
+            });
+            Ok(())
+        }
+        ContactsCommand::Add { name, address } => contacts.add(&name, address)
+            .map(|_| report!("Saved @{}", name)),
+        ContactsCommand::Remove(name) => contacts.remove(&name).map(|removed| match removed {
+            true => report!("Removed @{}", name),
+            false => report_failure(format!("No contact named {}", name)),
+        }),
+    };
+    if let Err(error) = result {
+        report_failure(error.message());
+    }
+}
+
+fn on_ban_command(command: BanCommand, node_state: &mut NodeState) {
+    let now = Utc::now();
+    match command {
+        BanCommand::List => {
+            let records = node_state.bans().records();
+            respond(RpcResponse::Bans(records.to_vec()), || {
+                if records.is_empty() {
+                    report!("No bans issued");
+                }
+                for record in records {
+                    report!("{}", record.describe(now));
+                }
+            });
+        }
+        BanCommand::Clear(peer_id) => {
+            let cleared = node_state.bans_mut().clear(peer_id, now);
+            report!("Lifted {} bans", cleared);
+            if let Err(error) = node_state.bans().save() {
+                report_failure(format!("Could not save bans: {}", error.message()));
+            }
+        }
+    }
+}
+
+fn on_htlc_command(
+    command: HtlcCommand, swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, fee: i64,
+) {
+    let mut locked = None;
+    let prepared = match command {
+        HtlcCommand::Create { amount, target_address, timeout, hash_lock } => {
+            let (hash_lock, secret) = match hash_lock {
+                Some(hash_lock) => (hash_lock, None),
+                None => {
+                    let secret = contract::generate_secret(&mut payer.rng);
+                    report!("Secret: {} (reveal it only once the counterparty has locked its side)", secret);
+                    (contract::hash_lock(&secret), Some(secret))
+                }
+            };
+            let lock = Transaction::htlc_lock(
+                payer.signer.address(), target_address, amount, hash_lock, Utc::now() + timeout,
+            );
+            report!("Locking {} for {} under lock {}", amount, access::encode_address(target_address), lock.id());
+            locked = Some(RpcResponse::Locked { lock_id: lock.id(), secret });
+            prepare_transfer(transactions, wallets, payer, lock, fee)
+        }
+        HtlcCommand::Claim { lock_id, preimage } => match contract::find_lock(transactions, &lock_id) {
+            None => Err(Box::new(CommandError::new("No such lock on the chain")) as Box<dyn BlockchainError>),
+            Some(lock) => prepare_settlement(transactions, wallets, payer, Transaction::htlc_claim(&lock, preimage)),
+        },
+        HtlcCommand::Refund(lock_id) => match contract::find_lock(transactions, &lock_id) {
+            None => Err(Box::new(CommandError::new("No such lock on the chain")) as Box<dyn BlockchainError>),
+            Some(lock) => prepare_settlement(transactions, wallets, payer, Transaction::htlc_refund(&lock)),
+        },
+    };
+    match prepared {
+        Ok(prepared) => {
+            for transaction in prepared {
+                communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(transaction));
+            }
+            if let Some(locked) = locked {
+                respond(locked, || ());
+            }
+        }
+        Err(error) => report_failure(error.message())
+    }
+}
+
+fn on_token_command(
+    command: TokenCommand, swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, fee: i64,
+) {
+    let address = payer.signer.address();
+    let mut created = None;
+    let transfer = match command {
+        TokenCommand::Create { symbol, amount } => {
+            let token_id = contract::token_id(address, &symbol);
+            report!("Minting {} {} as token {}", amount, symbol, token_id);
+            created = Some(token_id);
+            Transaction::token_mint(address, symbol, amount)
+        }
+        TokenCommand::Send { token_id, amount, target_address } => {
+            Transaction::token_transfer(address, target_address, token_id, amount)
+        }
+        TokenCommand::Balance(token_id) => {
+            for (token_id, symbol, balance) in contract::token_balances(transactions, address, token_id) {
+                match balance {
+                    Some(balance) => report!("{} ({}): {}", token_id, symbol, balance),
+                    None => report!("{} ({}): balance overflows", token_id, symbol),
+                }
+            }
+            return;
+        }
+    };
+    match prepare_transfer(transactions, wallets, payer, transfer, fee) {
+        Ok(prepared) => {
+            for transaction in prepared {
+                communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(transaction));
+            }
+            if let Some(token_id) = created {
+                respond(RpcResponse::Created(token_id), || ());
+            }
+        }
+        Err(error) => report_failure(error.message())
+    }
+}
+
+fn on_sponsor_command(
+    command: SponsorCommand, swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, transfer_fee: i64,
+) {
+    let result = match command {
+        SponsorCommand::Request { amount, target_address, title, path } => {
+            request_sponsorship(transactions, wallets, payer, Transaction::new(
+                payer.signer.address(), target_address, title, amount, Utc::now(),
+            ), &path)
+        }
+        SponsorCommand::Pay { path, fee } => {
+            sponsor_transfer(transactions, wallets, payer, &path, fee.unwrap_or(transfer_fee)).map(|submitted| {
+                for transaction in submitted {
+                    communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(transaction));
+                }
+            })
+        }
+    };
+    if let Err(error) = result {
+        report_failure(error.message());
+    }
+}
+
+// the transfer is only written out, the sponsor submits it along with the fee it pays
+fn request_sponsorship(
+    transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, payer: &mut Payer,
+    transfer: Transaction, path: &Path,
+) -> Result<(), Box<dyn BlockchainError>> {
+    let signed = TransactionBuilder::from_transaction(transfer)
+        .without_fee()
+        .with_next_nonce(transactions)
+        .sign(payer.signer.as_ref(), &mut payer.rng)?
+        .remove(0);
+    if let Err(error) = TransactionValidator::new(wallets, transactions).transaction_valid(&signed) {
+        return Err(Box::new(error));
+    }
+    if let Err(error) = std::fs::write(path, builder::encode(&signed)) {
+        return Err(Box::new(CommandError::new(&error.to_string())));
+    }
+    report!("Transfer {} written to {}, a sponsor pays its fee with sponsor pay", signed.id(), path.display());
+    Ok(())
+}
+
+// both enter the mempool or neither, a transfer already pending is only paid for
+fn sponsor_transfer(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, payer: &mut Payer,
+    path: &Path, fee: i64,
+) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+    let transfer = match std::fs::read_to_string(path) {
+        Ok(content) => builder::decode(content.trim())?,
+        Err(error) => return Err(Box::new(CommandError::new(&error.to_string())))
+    };
+    let pending = transactions.uncommitted_data().iter().any(|transaction| transaction.id() == transfer.id());
+    let mut submitted = vec![];
+    if !pending {
+        if let Err(error) = TransactionValidator::new(wallets, transactions).transaction_valid(&transfer) {
+            return Err(Box::new(error));
+        }
+        transactions.add_uncommitted(transfer.clone());
+        submitted.push(transfer.clone());
+    }
+    let sponsorship = TransactionBuilder::from_transaction(Transaction::sponsorship(payer.signer.address(), &transfer, fee))
+        .without_fee()
+        .with_next_nonce(transactions)
+        .sign(payer.signer.as_ref(), &mut payer.rng)?
+        .remove(0);
+    if let Err(error) = TransactionValidator::new(wallets, transactions).transaction_valid(&sponsorship) {
+        transactions.discard_uncommitted(&submitted);
+        return Err(Box::new(error));
+    }
+    report!("Paying the fee of {} for {}", fee, transfer.id());
+    transactions.add_uncommitted(sponsorship.clone());
+    submitted.push(sponsorship);
+    Ok(submitted)
+}
+
+fn on_escrow_command(
+    command: EscrowCommand, swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &mut NodeState, payer: &mut Payer, fee: i64,
+) {
+    let (escrow_id, release) = match command {
+        EscrowCommand::Open { amount, recipient, arbiter } => {
+            let escrow = Transaction::escrow_open(payer.signer.address(), recipient, arbiter, amount);
+            let escrow_id = escrow.id();
+            report!("Opening escrow {} of {}", escrow_id, amount);
+            match prepare_transfer(transactions, wallets, payer, escrow, fee) {
+                Ok(prepared) => {
+                    for transaction in prepared {
+                        communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(transaction));
+                    }
+                    respond(RpcResponse::Created(escrow_id), || ());
+                }
+                Err(error) => report_failure(error.message())
+            }
+            return;
+        }
+        EscrowCommand::Release(escrow_id) => (escrow_id, true),
+        EscrowCommand::Dispute(escrow_id) => (escrow_id, false),
+    };
+    let settlement = match contract::find_lock(transactions, &escrow_id) {
+        Some(escrow) if release => Transaction::escrow_release(&escrow),
+        Some(escrow) => Transaction::escrow_refund(&escrow),
+        None => None
+    };
+    let mut settlement = match settlement {
+        None => {
+            report_failure(String::from("No such escrow on the chain"));
+            return;
+        }
+        Some(settlement) => settlement
+    };
+    if let Err(error) = payer.signer.approve(&mut settlement, &mut payer.rng) {
+        report_failure(error.message());
+        return;
+    }
+    match dispatch::collect_approval(transactions, wallets, node_state, settlement.clone()) {
+        Ok(complete) => {
+            communication::publish_message(swarm, BlockchainMessage::SettlementApproval(settlement));
+            match complete {
+                true => report!("Escrow {} settled", escrow_id),
+                false => report!("Escrow {} approved, waiting for another party", escrow_id)
+            }
+        }
+        Err(error) => report_failure(error.message())
+    }
+}
+
+// settlements leave the contract wallet, so they carry no nonce and pay no fee
+fn prepare_settlement(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, payer: &mut Payer,
+    settlement: Option<Transaction>,
+) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+    let mut settlement = match settlement {
+        None => return Err(Box::new(CommandError::new("Not a lock"))),
+        Some(settlement) => settlement
+    };
+    payer.signer.sign(&mut settlement, &mut payer.rng)?;
+    if let Err(error) = TransactionValidator::new(wallets, transactions).transaction_valid(&settlement) {
+        return Err(Box::new(error));
+    }
+    transactions.add_uncommitted(settlement.clone());
+    Ok(vec![settlement])
+}
+
+fn on_schedule_command(command: ScheduleCommand, schedule: &mut PaymentSchedule) {
+    match command {
+        ScheduleCommand::Send { amount, target_address, first_run, interval } => {
+            let id = schedule.register(target_address, amount, first_run, interval);
+            respond(RpcResponse::Created(id.to_string()), || report!("Scheduled payment #{}", id));
+        }
+        ScheduleCommand::List => respond(RpcResponse::Schedule(schedule.payments().to_vec()), || {
+            for payment in schedule.payments() {
+                let repeat = match payment.interval() {
+                    None => String::from("once"),
+                    Some(interval) => format!("every {}s", interval.num_seconds())
+                };
+                report!(
+                    "#{} {} -> {} next: {} ({})",
+                    payment.id(), payment.amount(),
+                    access::encode_address(payment.target_address()),
+                    payment.next_run().to_rfc3339(), repeat
+                );
+            }
+        }),
+        ScheduleCommand::Cancel(id) => {
+            if schedule.cancel(id) {
+                report!("Cancelled payment #{}", id);
+            } else {
+                report_failure(format!("No scheduled payment #{}", id));
+            }
+        }
+    }
+    save_schedule(schedule);
+}
+
+fn execute_due_payments(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &mut NodeState, payer: &mut Payer,
+    schedule: &mut PaymentSchedule, spending: &mut SpendTracker,
+) {
+    payer.promote_rotated_key(wallets, node_state);
+    let balance = transactions.balance_breakdown(payer.signer.address()).spendable();
+    let due = schedule.take_due(Utc::now(), balance);
+    if due.is_empty() {
+        return;
+    }
+    for payment in due {
+        let fee = transfer_fee(node_state, transactions);
+        if let Err(error) = spending.check(payment.amount() + fee, Utc::now()) {
+            report!("Skipped {}: {}", payment.title(), error.message());
+            continue;
+        }
+        let outgoing = OutgoingPayment {
+            amount: payment.amount(),
+            target_address: payment.target_address(),
+            title: payment.title(),
+            fee,
+        };
+        match send_payment(swarm, transactions, wallets, payer, outgoing) {
+            Ok(_) => {
+                spending.record(payment.amount() + fee, Utc::now());
+                report!("Executed {}", payment.title());
+            }
+            Err(error) => report!("Skipped {}: {}", payment.title(), error.message())
+        }
+    }
+    save_schedule(schedule);
+}
+
+fn send_payment(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, payment: OutgoingPayment,
+) -> Result<(), Box<dyn BlockchainError>> {
+    for transaction in prepare_payment(transactions, wallets, payer, payment)? {
+        communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(transaction));
+    }
+    Ok(())
+}
+
+fn send_batch(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, payer: &mut Payer, file: &Path, fee: i64,
+    spending: &mut SpendTracker,
+) {
+    let rows = match batch::read_batch(file) {
+        Ok(rows) => rows,
+        Err(error) => {
+            report_failure(error.message());
+            return;
+        }
+    };
+    let mut available = transactions.balance_breakdown(payer.signer.address()).spendable();
+    let mut prepared = vec![];
+    let (mut sent, mut failed) = (0, vec![]);
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(error) => {
+                report!("{}", error.message());
+                failed.push(error.message());
+                continue;
+            }
+        };
+        if row.amount() + fee > available {
+            let message = format!("Row {}: insufficient balance", row.line());
+            report!("{}", message);
+            failed.push(message);
+            continue;
+        }
+        if let Err(error) = spending.check(row.amount() + fee, Utc::now()) {
+            let message = format!("Row {}: {}", row.line(), error.message());
+            report!("{}", message);
+            failed.push(message);
+            continue;
+        }
+        let payment = prepare_payment(transactions, wallets, payer, OutgoingPayment {
+            amount: row.amount(),
+            target_address: row.target_address(),
+            title: row.memo().to_string(),
+            fee,
+        });
+        match payment {
+            Ok(payment) => prepared.extend(payment),
+            Err(error) => {
+                let message = format!("Row {}: {}", row.line(), error.message());
+                report!("{}", message);
+                failed.push(message);
+                continue;
+            }
+        }
+        spending.record(row.amount() + fee, Utc::now());
+        available -= row.amount() + fee;
+        report!(
+            "Row {}: {} to {}",
+            row.line(), row.amount(), access::encode_address(row.target_address())
+        );
+        sent += 1;
+    }
+    for chunk in prepared.chunks(transactions.data_units_per_block() as usize) {
+        communication::publish_message(swarm, BlockchainMessage::MempoolTransactions(chunk.to_vec()));
+    }
+    let summary = format!("Batch: {} sent, {} failed", sent, failed.len());
+    respond(RpcResponse::Batch { sent, failed }, || report!("{}", summary));
+}
+
+// nothing reaches the mempool unless both the transfer and its fee pass validation locally
+fn prepare_payment(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, payer: &mut Payer,
+    payment: OutgoingPayment,
+) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+    let transfer = Transaction::new(
+        payer.signer.address(), payment.target_address, payment.title, payment.amount, Utc::now(),
+    );
+    prepare_transfer(transactions, wallets, payer, transfer, payment.fee)
+}
+
+fn prepare_transfer(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, payer: &mut Payer,
+    transfer: Transaction, fee: i64,
+) -> Result<Vec<Transaction>, Box<dyn BlockchainError>> {
+    let prepared = TransactionBuilder::from_transaction(transfer)
+        .with_fee(fee)
+        .with_next_nonce(transactions)
+        .sign(payer.signer.as_ref(), &mut payer.rng)?;
+    if let Err(error) = TransactionValidator::new(wallets, transactions).check_transactions(&prepared) {
+        return Err(Box::new(error));
+    }
+    for transaction in &prepared {
+        transactions.add_uncommitted(transaction.clone());
+    }
+    Ok(prepared)
+}
+
+// signs the transfer and its fee like a send would, but only reports what validators would say
+fn dry_run_send(
+    transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, stakes: &Blockchain<Transaction>,
+    payer: &mut Payer, payment: OutgoingPayment,
+) {
+    let transfer = Transaction::new(
+        payer.signer.address(), payment.target_address, payment.title, payment.amount, Utc::now(),
+    );
+    let checked = TransactionBuilder::from_transaction(transfer)
+        .with_fee(payment.fee)
+        .with_next_nonce(transactions)
+        .sign(payer.signer.as_ref(), &mut payer.rng)
+        .and_then(|prepared| {
+            TransactionValidator::new(wallets, transactions)
+                .with_stakes(stakes)
+                .check_transactions(&prepared)
+                .map_err(|error| Box::new(error) as Box<dyn BlockchainError>)
+        });
+    match checked {
+        Ok(_) => report!(
+            "Dry run: sending {} to {} with fee {} would be accepted, nothing was published",
+            payment.amount, access::encode_address(payment.target_address), payment.fee
+        ),
+        Err(error) => report_failure(format!("Dry run: {}", error.message())),
+    }
+}
+
+// the puzzle takes a while to solve, it runs off the event loop and the grant comes back through
+// solved_grants, validators pay it only to the wallet it was solved for
+fn request_grant(
+    transactions: &Blockchain<Transaction>, node_state: &NodeState, payer: &Payer,
+    solved_grants: &mpsc::UnboundedSender<Transaction>,
+) {
+    let rules = current_rules(node_state, transactions);
+    if rules.wallet_grant() <= 0 {
+        report_failure(String::from("Grants are turned off"));
+        return;
+    }
+    let address = payer.signer.address();
+    report!("Solving the grant puzzle for {}", access::encode_address(address));
+    let solved_grants = solved_grants.clone();
+    tokio::task::spawn_blocking(move || {
+        let work = contract::solve_grant_work(address, rules.grant_work_bits());
+        let _ = solved_grants.send(Transaction::grant(address, rules.wallet_grant(), work));
+    });
+}
+
+fn submit_grant(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &NodeState, grant: Transaction,
+) {
+    let wallet_grant = grant.amount();
+    let schedule = node_state.governance().schedule(&UPGRADE_SCHEDULE, transactions);
+    if let Err(error) = TransactionValidator::with_upgrades(wallets, transactions, &schedule).transaction_valid(&grant) {
+        report!("{}", error.message());
+        return;
+    }
+    let message = dispatch::submit_transaction(transactions, grant);
+    communication::publish_message(swarm, message);
+    report!("Grant of {} requested", wallet_grant);
+}
+
+fn transfer_fee(node_state: &NodeState, transactions: &Blockchain<Transaction>) -> i64 {
+    current_rules(node_state, transactions).transfer_fee()
+}
+
+// rules the next block is forged under, governance amendments included
+fn current_rules(node_state: &NodeState, transactions: &Blockchain<Transaction>) -> ConsensusRules {
+    *node_state.governance()
+        .schedule(&UPGRADE_SCHEDULE, transactions)
+        .rules_at(transactions.chain_length())
+}
+
+async fn next_wallet_activity(watcher: &mut Option<WalletWatcher>) -> Vec<WalletActivity> {
+    match watcher {
+        None => future::pending().await,
+        Some(watcher) => watcher.next_activity().await
+    }
+}
+
+fn save_schedule(schedule: &PaymentSchedule) {
+    if let Err(error) = schedule.save() {
+        report!("{}", error.message());
+    }
+}
@@ -1,29 +1,149 @@
-use std::collections::{HashMap, HashSet};
-use std::mem;
+use std::{cmp, mem};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::num::NonZeroU32;
+use std::sync::Arc;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use libp2p::{core::upgrade, gossipsub, identity::Keypair, mdns::{Event, tokio::Behaviour as TokioBehaviour}, mdns, mplex, noise, PeerId, Swarm, swarm::NetworkBehaviour, tcp::{Config, tokio::Transport as TokioTransport}, Transport};
 use libp2p::gossipsub::{Gossipsub, GossipsubEvent, IdentTopic, MessageAuthenticity, ValidationMode};
-
-use crate::blockchain::{StakeBid, Transaction};
-use crate::blockchain::core::{BlockCandidate, BlockchainError};
+use libp2p::ping;
+
+use crate::blockchain::{access, Address, compact_key, StakeBid, Transaction, TransactionValidationError, Wallet};
+use crate::blockchain::protocol::{self, BLOCK_INTERVAL_SECONDS};
+use crate::blockchain::governance::Governance;
+use crate::blockchain::key_history::KeyHistory;
+use crate::blockchain::rules::Rules;
+use crate::blockchain::signer::Signer;
+use crate::blockchain::stake::StakeRegistry;
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, BlockKey, DEFAULT_MAX_REORG_DEPTH};
+use crate::config::{ConfigError, GossipValidation, NodeConfig};
+use crate::network::anti_entropy::AntiEntropy;
+use crate::network::bans::BanList;
+use crate::network::bid_policy::BidPolicy;
+use crate::network::capability::{COMPACT_KEYS_VERSION, Feature, PeerCapabilities};
+use crate::network::chains::ChainRegistry;
+use crate::network::clock::ClockSamples;
 use crate::network::communication::{Vote, VotingResult};
-
+use crate::network::communication::approval::ApprovalPool;
+use crate::network::communication::orphan::OrphanPool;
+use crate::network::inactivity::{InactivityConfig, InactivityTracker, RoundPhase};
+use crate::network::known_peers::KnownPeers;
+use crate::network::quorum::QuorumConfig;
+use crate::network::latency::{PeerLatency, PING_INTERVAL_SECONDS};
+use crate::network::presence::PeerPresence;
+use crate::network::rounds::RoundLog;
+use crate::network::sync::SyncManager;
+use crate::network::validators::{ChainMisses, ValidatorStats};
+use crate::{random, report};
+#[cfg(feature = "nat")]
+use crate::network::nat::{NatBehaviour, NatEvent};
+#[cfg(feature = "nat")]
+use libp2p::{core::transport::OrTransport, relay};
+
+pub mod anti_entropy;
+pub mod bans;
+pub mod bid_policy;
+pub mod capability;
+pub mod chains;
+pub mod clock;
 pub mod communication;
+pub mod divergence;
+pub mod election;
+pub mod inactivity;
+pub mod quorum;
+pub mod known_peers;
+pub mod latency;
+#[cfg(feature = "nat")]
+pub mod nat;
+pub mod presence;
+pub mod rounds;
+#[cfg(test)]
+mod simulation;
+pub mod status;
+pub mod sync;
+pub mod validators;
+
+pub enum ProposalRejection {
+    NotForger,
+    Equivocation,
+}
+
+impl BlockchainError for ProposalRejection {
+    fn message(&self) -> String {
+        match self {
+            ProposalRejection::NotForger => String::from("Proposer is not this round's forger"),
+            ProposalRejection::Equivocation => String::from("Forger proposed two different blocks"),
+        }
+    }
+}
+
+// how many fallback forgers a round tries after its block was voted down
+pub static MAX_REPROPOSALS: u32 = 3;
 
 lazy_static! {
     pub static ref NETWORK_TOPIC: IdentTopic = IdentTopic::new("KINGCOIN");
 }
 
+// Who a quorum counts. Several nodes may serve one wallet, e.g. a backup node, and all of them
+// count once; a peer that never bid has no known wallet and counts on its own.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Voter {
+    Wallet(Address),
+    Peer(PeerId),
+}
+
 pub struct NodeState {
     node_id: PeerId,
     node_bid: StakeBid,
+    // key of the wallet the node bids from, peers ignore bids it did not sign
+    bid_signer: Option<Arc<dyn Signer>>,
+    bid_policy: BidPolicy,
+    bid_published: bool,
     peers_bids: HashMap<PeerId, StakeBid>,
     block_creator: Option<PeerId>,
-    bad_peers: HashSet<PeerId>,
+    bans: BanList,
+    known_peers: KnownPeers,
+    key_history: KeyHistory,
+    rounds: RoundLog,
+    // wallets peers bid from, kept past the round so bans can name them
+    peer_wallets: HashMap<PeerId, Address>,
     votes: HashSet<Vote>,
     pending_block: Option<BlockCandidate<Transaction>>,
+    // a round settles either a transaction block or a wallet registration block, never both
+    pending_wallet_block: Option<BlockCandidate<Wallet>>,
+    // runners-up of the last election with their bids, best first
+    fallback_forgers: Vec<(PeerId, Transaction)>,
+    reproposals: u32,
+    peer_capabilities: HashMap<PeerId, PeerCapabilities>,
+    clock: ClockSamples,
+    latency: PeerLatency,
+    presence: PeerPresence,
+    orphans: OrphanPool,
+    approvals: ApprovalPool,
+    synced_at: Option<DateTime<Utc>>,
+    sync: SyncManager,
+    // peers asked for their recent headers by the diff command
+    pending_diffs: HashSet<PeerId>,
+    anti_entropy: AntiEntropy,
+    governance: Governance,
+    chains: ChainRegistry,
+    validator_stats: ValidatorStats,
+    inactivity: InactivityTracker,
+    quorum: QuorumConfig,
+    min_peers: usize,
+    // reported once when the node falls short of its peers and once it has enough again
+    short_of_peers: bool,
+    rules: Rules,
+    // heights of the blocks whose receipts were processed, within reorg reach of the tip
+    receipts: BTreeSet<u64>,
+    max_reorg_depth: u64,
+    // last transaction block approved by the checkpoint quorum
+    checkpoint: Option<u64>,
+    block_interval: Duration,
+    // keyring wallet payments are made from, none for the key generated at start
+    active_wallet: Option<String>,
 }
 
 
@@ -32,11 +152,44 @@ impl NodeState {
         NodeState {
             node_id,
             node_bid: initial_bid,
+            bid_signer: None,
+            bid_policy: BidPolicy::default(),
+            bid_published: false,
             peers_bids: HashMap::new(),
             block_creator: None,
-            bad_peers: HashSet::new(),
+            bans: BanList::default(),
+            known_peers: KnownPeers::default(),
+            key_history: KeyHistory::default(),
+            rounds: RoundLog::default(),
+            peer_wallets: HashMap::new(),
             votes: HashSet::new(),
             pending_block: None,
+            pending_wallet_block: None,
+            fallback_forgers: vec![],
+            reproposals: 0,
+            peer_capabilities: HashMap::new(),
+            clock: ClockSamples::new(),
+            latency: PeerLatency::default(),
+            presence: PeerPresence::default(),
+            orphans: OrphanPool::new(),
+            approvals: ApprovalPool::new(),
+            synced_at: None,
+            sync: SyncManager::new(),
+            pending_diffs: HashSet::new(),
+            anti_entropy: AntiEntropy::default(),
+            governance: Governance::new(),
+            chains: ChainRegistry::new(),
+            validator_stats: ValidatorStats::new(),
+            inactivity: InactivityTracker::default(),
+            quorum: protocol::genesis_overrides().quorum(),
+            min_peers: 1,
+            short_of_peers: false,
+            rules: Rules::default(),
+            receipts: BTreeSet::new(),
+            max_reorg_depth: DEFAULT_MAX_REORG_DEPTH,
+            checkpoint: None,
+            block_interval: Duration::from_secs(BLOCK_INTERVAL_SECONDS),
+            active_wallet: None,
         }
     }
 
@@ -48,122 +201,748 @@ impl NodeState {
         &self.node_bid
     }
 
+    pub fn wallet_address(&self) -> Address {
+        self.node_bid.transaction().source_address()
+    }
+
+    pub fn bid_policy(&self) -> BidPolicy {
+        self.bid_policy
+    }
+
+    pub fn set_bid_policy(&mut self, bid_policy: BidPolicy) {
+        self.bid_policy = bid_policy;
+    }
+
+    // the bid is drawn from what is spendable besides stake already bonded, it is locked once
+    // it wins and lands on the stakes chain
+    pub fn prepare_bid(&mut self, spendable: i64) -> Option<StakeBid> {
+        if self.bid_published {
+            return None;
+        }
+        let amount = self.bid_policy.bid_amount(spendable)?;
+        let signer = self.bid_signer.as_ref()?;
+        let bid = match StakeBid::signed(amount, &self.node_id.to_base58(), signer.as_ref(), &mut random::node_rng()) {
+            Ok(bid) => bid,
+            Err(error) => {
+                report!("Could not sign bid: {}", error.message());
+                return None;
+            }
+        };
+        self.node_bid = bid.clone();
+        self.bid_published = true;
+        self.inactivity.open_bidding(Utc::now());
+        Some(bid)
+    }
+
+    // bids are signed with the key of the wallet the node started with, also after it rotated
+    pub fn with_bid_signer(mut self, signer: Arc<dyn Signer>) -> Self {
+        self.bid_signer = Some(signer);
+        self
+    }
+
+    pub fn set_bid_signer(&mut self, signer: Arc<dyn Signer>) {
+        self.bid_signer = Some(signer);
+    }
+
     pub fn peers_bids(&self) -> &HashMap<PeerId, StakeBid> {
         &self.peers_bids
     }
 
-    pub fn bad_peers(&self) -> &HashSet<PeerId> {
-        &self.bad_peers
+    // bids the next election draws from, the node's own included
+    pub fn bid_count(&self) -> usize {
+        self.peers_bids.len() + usize::from(self.bid_policy.participates())
+    }
+
+    // bans restored from disk keep applying after a restart
+    pub fn with_bans(mut self, bans: BanList) -> Self {
+        self.bans = bans;
+        self
+    }
+
+    // heights keys took over at stay as recorded across restarts
+    pub fn with_key_history(mut self, key_history: KeyHistory) -> Self {
+        self.key_history = key_history;
+        self
+    }
+
+    pub fn with_governance(mut self, governance: Governance) -> Self {
+        self.governance = governance;
+        self
+    }
+
+    pub fn with_known_peers(mut self, known_peers: KnownPeers) -> Self {
+        self.known_peers = known_peers;
+        self
+    }
+
+    pub fn with_rounds(mut self, rounds: RoundLog) -> Self {
+        self.rounds = rounds;
+        self
+    }
+
+    pub fn with_inactivity(mut self, config: InactivityConfig) -> Self {
+        self.inactivity = InactivityTracker::new(config);
+        self
+    }
+
+    pub fn quorum(&self) -> &QuorumConfig {
+        &self.quorum
+    }
+
+    pub fn with_min_peers(mut self, min_peers: usize) -> Self {
+        self.min_peers = min_peers;
+        self
+    }
+
+    pub fn min_peers(&self) -> usize {
+        self.min_peers
+    }
+
+    pub fn with_rules(mut self, rules: Rules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    // short of its peers the node keeps transactions queued and stays out of bidding and voting
+    pub fn enough_peers(&mut self, connected: usize) -> bool {
+        let enough = connected >= self.min_peers;
+        if enough == self.short_of_peers {
+            match enough {
+                true => report!("Enough peers, taking part in consensus again"),
+                false => report!(
+                    "Not enough peers, {} of {} connected, transactions stay queued", connected, self.min_peers
+                ),
+            }
+            self.short_of_peers = !enough;
+        }
+        enough
+    }
+
+    pub fn with_max_reorg_depth(mut self, max_reorg_depth: u64) -> Self {
+        self.max_reorg_depth = max_reorg_depth;
+        self
+    }
+
+    pub fn max_reorg_depth(&self) -> u64 {
+        self.max_reorg_depth
+    }
+
+    // how far the transaction chain may be rolled back, never below the last checkpoint
+    pub fn reorg_depth(&self, transactions: &Blockchain<Transaction>) -> u64 {
+        match self.checkpoint {
+            None => self.max_reorg_depth,
+            Some(height) => self.max_reorg_depth.min(transactions.chain_length().saturating_sub(height + 1)),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Option<u64> {
+        self.checkpoint
+    }
+
+    pub fn set_checkpoint(&mut self, height: u64) {
+        self.checkpoint = Some(height);
+    }
+
+    pub fn with_block_interval(mut self, block_interval: Duration) -> Self {
+        self.block_interval = block_interval;
+        self
+    }
+
+    pub fn block_interval(&self) -> Duration {
+        self.block_interval
+    }
+
+    pub fn active_wallet(&self) -> Option<&str> {
+        self.active_wallet.as_deref()
+    }
+
+    pub fn set_active_wallet(&mut self, name: Option<String>) {
+        self.active_wallet = name;
+    }
+
+    pub fn bans(&self) -> &BanList {
+        &self.bans
+    }
+
+    pub fn bans_mut(&mut self) -> &mut BanList {
+        &mut self.bans
+    }
+
+    pub fn key_history_mut(&mut self) -> &mut KeyHistory {
+        &mut self.key_history
+    }
+
+    pub fn known_peers(&self) -> &KnownPeers {
+        &self.known_peers
+    }
+
+    pub fn known_peers_mut(&mut self) -> &mut KnownPeers {
+        &mut self.known_peers
+    }
+
+    pub fn rounds(&self) -> &RoundLog {
+        &self.rounds
+    }
+
+    pub fn rounds_mut(&mut self) -> &mut RoundLog {
+        &mut self.rounds
+    }
+
+    pub fn bad_peers(&self) -> HashSet<PeerId> {
+        self.bans.active(Utc::now())
+            .iter()
+            .filter_map(|record| record.peer_id())
+            .collect()
+    }
+
+    pub fn voter(&self, peer_id: &PeerId) -> Voter {
+        match self.peer_wallets.get(peer_id) {
+            Some(wallet) => Voter::Wallet(*wallet),
+            None => Voter::Peer(*peer_id),
+        }
+    }
+
+    // distinct wallets behind the connected peers, this node speaks for its own wallet and
+    // inactive validators are not waited for until they bid again
+    fn expected_voters(&self, connected: &[PeerId]) -> HashSet<Voter> {
+        connected.iter()
+            .filter(|peer_id| !self.presence.is_excused(peer_id))
+            .map(|peer_id| self.voter(peer_id))
+            .filter(|voter| *voter != Voter::Wallet(self.wallet_address()))
+            .filter(|voter| !self.inactivity.is_inactive(voter))
+            .collect()
+    }
+
+    // bidders while bidding, voters while voting
+    fn participants(&self) -> HashSet<Voter> {
+        match self.inactivity.phase() {
+            Some(RoundPhase::Voting) => self.votes.iter().map(|vote| self.voter(&vote.id())).collect(),
+            _ => self.peers_bids.keys().map(|peer_id| self.voter(peer_id)).collect(),
+        }
+    }
+
+    // counts the running phase against the expected validators, returns those it made inactive
+    pub fn record_participation(&mut self, connected: &[PeerId]) -> Vec<Voter> {
+        let mut expected = self.expected_voters(connected);
+        // the forger does not vote on its own block
+        if let (Some(RoundPhase::Voting), Some(creator)) = (self.inactivity.phase(), self.block_creator) {
+            expected.remove(&self.voter(&creator));
+        }
+        let participated = self.participants();
+        self.inactivity.record(&expected, &participated)
+    }
+
+    // a forger that never sent its block missed the round on its own
+    pub fn record_missing_block(&mut self, forger: PeerId) -> Vec<Voter> {
+        let expected = HashSet::from([self.voter(&forger)]);
+        self.inactivity.record(&expected, &HashSet::new())
+    }
+
+    // the timed out phase goes on without the peers that did not answer
+    pub fn excuse_absent(&mut self, connected: &[PeerId]) {
+        let participated = self.participants();
+        let absent: Vec<PeerId> = connected.iter()
+            .filter(|peer_id| !participated.contains(&self.voter(peer_id)))
+            .copied()
+            .collect();
+        for peer_id in absent {
+            self.presence.excuse(peer_id);
+        }
+    }
+
+    // wallets of peers that bid for forging, this node's own included
+    pub fn validator_wallets(&self) -> HashMap<Address, PeerId> {
+        let mut validators: HashMap<Address, PeerId> = self.peer_wallets.iter()
+            .map(|(peer_id, address)| (*address, *peer_id))
+            .collect();
+        validators.insert(self.wallet_address(), self.node_id);
+        validators
+    }
+
+    pub fn ban_peer(&mut self, peer_id: PeerId, reason: &str) {
+        let wallet = match peer_id == self.node_id {
+            true => Some(self.wallet_address()),
+            false => self.peer_wallets.get(&peer_id).copied(),
+        };
+        report!("Banning {}: {}", peer_id, reason);
+        self.bans.ban(peer_id, wallet, reason, Utc::now());
+        if let Err(error) = self.bans.save() {
+            report!("Could not save bans: {}", error.message());
+        }
+    }
+
+    pub fn block_creator(&self) -> Option<PeerId> {
+        self.block_creator
+    }
+
+    pub fn pending_block(&self) -> &Option<BlockCandidate<Transaction>> {
+        &self.pending_block
+    }
+
+    pub fn pending_wallet_block(&self) -> &Option<BlockCandidate<Wallet>> {
+        &self.pending_wallet_block
+    }
+
+    pub fn vote_count(&self) -> usize {
+        self.votes.len()
+    }
+
+    pub fn synced_at(&self) -> Option<DateTime<Utc>> {
+        self.synced_at
+    }
+
+    pub fn mark_synced(&mut self, synced_at: DateTime<Utc>) {
+        self.synced_at = Some(synced_at);
+    }
+
+    pub fn sync(&self) -> &SyncManager {
+        &self.sync
+    }
+
+    pub fn sync_mut(&mut self) -> &mut SyncManager {
+        &mut self.sync
+    }
+
+    pub fn anti_entropy_mut(&mut self) -> &mut AntiEntropy {
+        &mut self.anti_entropy
+    }
+
+    pub fn request_diff(&mut self, peer_id: PeerId) {
+        self.pending_diffs.insert(peer_id);
+    }
+
+    // whether headers sent by the peer answer a diff request, unrequested ones are ignored
+    pub fn take_diff_request(&mut self, peer_id: &PeerId) -> bool {
+        self.pending_diffs.remove(peer_id)
     }
 
     pub fn set_block_creator(&mut self, peer_id: PeerId) {
         self.block_creator = Some(peer_id);
+        self.inactivity.start_voting(Utc::now());
     }
 
     pub fn set_pending_block(&mut self, pending_block: BlockCandidate<Transaction>) {
         self.pending_block = Some(pending_block);
     }
 
-    pub fn update_peers_bids(&mut self, peer_id: PeerId, bid: StakeBid) {
+    pub fn accept_block_proposal(
+        &mut self, proposer: PeerId, block: BlockCandidate<Transaction>,
+    ) -> Result<bool, ProposalRejection> {
+        let accepted = self.check_proposal(proposer, block.key())?;
+        if accepted {
+            self.pending_block = Some(block);
+        }
+        Ok(accepted)
+    }
+
+    pub fn accept_wallet_block_proposal(
+        &mut self, proposer: PeerId, block: BlockCandidate<Wallet>,
+    ) -> Result<bool, ProposalRejection> {
+        let accepted = self.check_proposal(proposer, block.key())?;
+        if accepted {
+            self.pending_wallet_block = Some(block);
+        }
+        Ok(accepted)
+    }
+
+    fn check_proposal(&mut self, proposer: PeerId, key: BlockKey) -> Result<bool, ProposalRejection> {
+        if self.block_creator != Some(proposer) {
+            return Err(ProposalRejection::NotForger);
+        }
+        let pending_key = match (&self.pending_block, &self.pending_wallet_block) {
+            (Some(pending), _) => pending.key(),
+            (None, Some(pending)) => pending.key(),
+            (None, None) => return Ok(true)
+        };
+        // gossip may deliver the same block twice, it is only voted on once
+        if pending_key == key {
+            return Ok(false);
+        }
+        self.ban_peer(proposer, "proposed two different blocks");
+        Err(ProposalRejection::Equivocation)
+    }
+
+    // A peer is bound to the wallet its bid names only once the wallet's key signed the bid for
+    // that peer, otherwise any peer could take over another's wallet in quorums and bans. The
+    // bid counts if the wallet can cover it besides its bonded stake.
+    pub fn accept_peer_bid(
+        &mut self, peer_id: PeerId, bid: StakeBid, wallets: &Blockchain<Wallet>, spendable: i64,
+    ) -> Result<(), TransactionValidationError> {
+        bid.verify(&peer_id.to_base58(), wallets)?;
+        if bid.stake() > spendable {
+            return Err(TransactionValidationError::InsufficientBalance {
+                have: spendable,
+                need: bid.stake(),
+            });
+        }
+        self.update_peers_bids(peer_id, bid);
+        Ok(())
+    }
+
+    fn update_peers_bids(&mut self, peer_id: PeerId, bid: StakeBid) {
+        let wallet = bid.transaction().source_address();
+        self.peer_wallets.insert(peer_id, wallet);
         self.peers_bids.insert(peer_id, bid);
+        if self.inactivity.reactivate(&Voter::Wallet(wallet)) {
+            report!("{} is active again", access::encode_address(wallet));
+        }
+        self.inactivity.open_bidding(Utc::now());
+    }
+
+    pub fn update_peer_capabilities(&mut self, peer_id: PeerId, capabilities: PeerCapabilities) {
+        self.peer_capabilities.insert(peer_id, capabilities);
+        compact_key::use_compact(self.negotiated_version() >= COMPACT_KEYS_VERSION);
+    }
+
+    pub fn remove_peer_capabilities(&mut self, peer_id: &PeerId) {
+        self.peer_capabilities.remove(peer_id);
+        self.clock.remove(peer_id);
+        self.latency.remove(peer_id);
+        self.anti_entropy.forget(peer_id);
+        compact_key::use_compact(self.negotiated_version() >= COMPACT_KEYS_VERSION);
+    }
+
+    pub fn negotiated_version(&self) -> u32 {
+        capability::negotiated_version(self.peer_capabilities.values())
+    }
+
+    // what was learned from the peer is dropped, it is told again after a rejoin, and its bid is
+    // withdrawn so it is not drawn to forge while gone
+    pub fn mark_peer_offline(&mut self, peer_id: PeerId, now: DateTime<Utc>) -> bool {
+        self.remove_peer_capabilities(&peer_id);
+        self.peers_bids.remove(&peer_id);
+        self.presence.mark_offline(peer_id, now)
+    }
+
+    // when the peer went offline, if it is rejoining
+    pub fn mark_peer_online(&mut self, peer_id: &PeerId) -> Option<DateTime<Utc>> {
+        self.presence.mark_online(peer_id)
+    }
+
+    pub fn presence(&self) -> &PeerPresence {
+        &self.presence
+    }
+
+    pub fn latency(&self) -> &PeerLatency {
+        &self.latency
+    }
+
+    pub fn latency_mut(&mut self) -> &mut PeerLatency {
+        &mut self.latency
+    }
+
+    pub fn clock(&self) -> &ClockSamples {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut ClockSamples {
+        &mut self.clock
+    }
+
+    pub fn peer_capabilities(&self, peer_id: &PeerId) -> Option<&PeerCapabilities> {
+        self.peer_capabilities.get(peer_id)
+    }
+
+    pub fn peer_supports(&self, peer_id: &PeerId, feature: Feature) -> bool {
+        match self.peer_capabilities.get(peer_id) {
+            None => false,
+            Some(capabilities) => capabilities.supports(feature)
+        }
+    }
+
+    pub fn any_peer_supports(&self, feature: Feature) -> bool {
+        self.peer_capabilities.values().any(|capabilities| capabilities.supports(feature))
+    }
+
+    // every node that appends a block announces it, only the first announcement per height is
+    // processed; heights past the local tip belong to no block and are refused
+    pub fn mark_receipt(&mut self, height: u64, chain_length: u64) -> bool {
+        if height > chain_length {
+            return false;
+        }
+        let oldest = chain_length.saturating_sub(self.max_reorg_depth);
+        self.receipts.retain(|marked| *marked >= oldest);
+        height >= oldest && self.receipts.insert(height)
+    }
+
+    pub fn rewind_receipts(&mut self, fork_height: u64) {
+        self.receipts.split_off(&fork_height);
+    }
+
+    pub fn governance(&self) -> &Governance {
+        &self.governance
+    }
+
+    pub fn governance_mut(&mut self) -> &mut Governance {
+        &mut self.governance
+    }
+
+    pub fn chains(&self) -> &ChainRegistry {
+        &self.chains
+    }
+
+    pub fn chains_mut(&mut self) -> &mut ChainRegistry {
+        &mut self.chains
+    }
+
+    pub fn validator_stats(&self) -> &ValidatorStats {
+        &self.validator_stats
+    }
+
+    pub fn validator_stats_mut(&mut self) -> &mut ValidatorStats {
+        &mut self.validator_stats
+    }
+
+    pub fn inactivity(&self) -> &InactivityTracker {
+        &self.inactivity
+    }
+
+    pub fn inactivity_mut(&mut self) -> &mut InactivityTracker {
+        &mut self.inactivity
+    }
+
+    pub fn orphans(&self) -> &OrphanPool {
+        &self.orphans
     }
 
+    pub fn orphans_mut(&mut self) -> &mut OrphanPool {
+        &mut self.orphans
+    }
+
+    pub fn approvals(&self) -> &ApprovalPool {
+        &self.approvals
+    }
+
+    pub fn approvals_mut(&mut self) -> &mut ApprovalPool {
+        &mut self.approvals
+    }
+
+    // a bid the node sent its peers
     pub fn update_bid(&mut self, bid: StakeBid) {
         self.node_bid = bid;
+        self.bid_published = true;
     }
 
-    pub fn all_bade(&self, peer_count: usize) -> bool {
-        self.peers_bids.len() == peer_count
+    pub fn bidding_quorum(&self, connected: &[PeerId], stakes: &StakeRegistry) -> bool {
+        let bade: HashSet<Voter> = self.peers_bids.keys().map(|peer_id| self.voter(peer_id)).collect();
+        self.quorum.bidding_complete(&self.expected_voters(connected), &bade, |voter| bonded_stake(stakes, voter))
     }
 
     pub fn mark_creator_bad(&mut self) -> Result<(), ()> {
         match self.block_creator {
             None => Err(()),
             Some(creator) => {
-                self.bad_peers.insert(creator);
+                self.ban_peer(creator, "block voted down");
                 Ok(())
             }
         }
     }
 
-    pub fn add_vote(&mut self, vote: Vote) {
-        self.votes.insert(vote);
+    // only the first vote of a wallet counts, whichever of its nodes cast it, and only while a
+    // block is up for voting
+    pub fn add_vote(&mut self, vote: Vote) -> bool {
+        if !self.awaits_votes() {
+            return false;
+        }
+        let voter = self.voter(&vote.id());
+        let repeated = self.votes.iter().any(|cast| self.voter(&cast.id()) == voter);
+        if repeated || voter == Voter::Wallet(self.wallet_address()) {
+            return false;
+        }
+        self.votes.insert(vote)
     }
 
-    pub fn all_voted(&self, peer_count: usize) -> bool {
-        self.votes.len() == peer_count
+    // a proposal is pending here, or this node forged the block its peers hold
+    fn awaits_votes(&self) -> bool {
+        self.pending_block.is_some() || self.pending_wallet_block.is_some() || self.block_creator == Some(self.node_id)
+    }
+
+    pub fn voting_quorum(&self, connected: &[PeerId], stakes: &StakeRegistry) -> bool {
+        let voted: HashSet<Voter> = self.votes.iter().map(|vote| self.voter(&vote.id())).collect();
+        self.quorum.voting_complete(&self.expected_voters(connected), &voted, |voter| bonded_stake(stakes, voter))
+    }
+
+    // the approving votes of the round reach the checkpoint share of the expected validators
+    pub fn checkpoint_reached(&self, connected: &[PeerId], stakes: &StakeRegistry) -> bool {
+        let approving: HashSet<Voter> = self.votes.iter()
+            .filter(|vote| vote.block_valid())
+            .map(|vote| self.voter(&vote.id()))
+            .collect();
+        self.quorum.checkpoints(&self.expected_voters(connected), &approving, |voter| bonded_stake(stakes, voter))
+    }
+
+    pub fn clear_votes(&mut self) {
+        self.votes.clear();
+        self.presence.end_round();
+        self.inactivity.close_phase();
+        communication::end_outbox_round();
+    }
+
+    // every node draws from the same bids with the same seed and the same misses read from the
+    // chains, so all of them agree on the forger and on the fallback order behind it
+    pub fn elect_forger(&mut self, seed: [u8; 32], misses: &ChainMisses) -> Option<(PeerId, Transaction)> {
+        let mut bids: Vec<(PeerId, Transaction)> = self.peers_bids.iter()
+            .map(|(peer_id, bid)| (*peer_id, bid.transaction().clone()))
+            .collect();
+        // the own bid only counts once the peers were sent it, otherwise they draw without it
+        if self.bid_published {
+            bids.push((self.node_id, self.node_bid.transaction().clone()));
+        }
+        // a wallet's stake is drawn once, the node with the lowest peer id forges for it
+        let mut candidates: HashMap<Address, (PeerId, Transaction)> = HashMap::new();
+        for (peer_id, bid) in bids {
+            match candidates.get(&bid.source_address()) {
+                Some((chosen, _)) if chosen.to_bytes() < peer_id.to_bytes() => {}
+                _ => {
+                    candidates.insert(bid.source_address(), (peer_id, bid));
+                }
+            }
+        }
+        // validators that keep missing their rounds are drawn less often
+        let mut order = election::weighted_draw_order(
+            candidates.into_values().collect(), seed, |bid| misses.election_weight(bid),
+        );
+        if order.is_empty() {
+            return None;
+        }
+        let winner = order.remove(0);
+        self.fallback_forgers = order;
+        self.reproposals = 0;
+        Some(winner)
+    }
+
+    // next runner-up that has not misbehaved, none once the retry limit is reached
+    pub fn next_forger(&mut self) -> Option<(PeerId, Transaction)> {
+        if self.reproposals >= MAX_REPROPOSALS {
+            return None;
+        }
+        while !self.fallback_forgers.is_empty() {
+            let (peer_id, bid) = self.fallback_forgers.remove(0);
+            if !self.bans.is_banned(&peer_id, Utc::now()) {
+                self.reproposals += 1;
+                return Some((peer_id, bid));
+            }
+        }
+        None
     }
 
     pub fn take_pending_block(&mut self) -> Option<BlockCandidate<Transaction>> {
         mem::take(&mut self.pending_block)
     }
 
+    pub fn take_pending_wallet_block(&mut self) -> Option<BlockCandidate<Wallet>> {
+        mem::take(&mut self.pending_wallet_block)
+    }
+
     pub fn take_block_creator(&mut self) -> Option<PeerId> {
         mem::take(&mut self.block_creator)
     }
 
-    pub fn summarize_votes(&self) -> VotingResult {
-        let mut block_valid = 0;
-        let mut block_invalid = 0;
+    pub fn summarize_votes(&self, stakes: &StakeRegistry) -> VotingResult {
+        let mut valid = HashSet::new();
+        let mut invalid = HashSet::new();
+        // a wallet learned after its nodes voted may have more than one vote in
+        let mut counted = HashSet::new();
         for vote in &self.votes {
+            let voter = self.voter(&vote.id());
+            if !counted.insert(voter) {
+                continue;
+            }
             if vote.block_valid() {
-                block_valid += 1;
+                valid.insert(voter);
             } else {
-                block_invalid += 1;
+                invalid.insert(voter);
             }
         }
-        VotingResult::evaluate(block_valid, block_invalid)
+        let approved = self.quorum.approves(&valid, &invalid, |voter| bonded_stake(stakes, voter));
+        VotingResult::evaluate(valid.len() as i64, invalid.len() as i64)
+            .with_approval(approved)
+            .with_reasons(self.votes.iter().filter_map(|vote| vote.reason().as_ref()))
     }
 
-    pub fn select_highest_bid(&self) -> (&PeerId, &StakeBid) {
-        let max_peer_bid = self.peers_bids
-            .iter()
-            .max_by(|first, second| {
-                first.1.stake().cmp(&second.1.stake())
-            }).unwrap();
-        if max_peer_bid.1.stake() > self.node_bid.stake() {
-            max_peer_bid
-        } else {
-            (&self.node_id, &self.node_bid)
-        }
-    }
     pub fn reset_peer_bids(&mut self) {
         self.peers_bids.clear();
+        self.bid_published = false;
+    }
+}
+
+// what a validator weighs in stake weighted quorums, peers without a known wallet have none
+fn bonded_stake(stakes: &StakeRegistry, voter: &Voter) -> i64 {
+    match voter {
+        Voter::Wallet(address) => stakes.bonded(*address),
+        Voter::Peer(_) => 0,
     }
 }
 
-pub fn configure_swarm() -> Swarm<BlockchainBehaviour> {
+// gossip settings libp2p refuses come back as a config error rather than a panic
+pub fn configure_swarm(config: &NodeConfig) -> Result<Swarm<BlockchainBehaviour>, Box<dyn BlockchainError>> {
+    let gossip = config.gossip();
     let key = Keypair::generate_ed25519();
     let local_id = PeerId::from(key.public());
 
+    let validation_mode = match gossip.validation_mode() {
+        GossipValidation::Strict => ValidationMode::Strict,
+        GossipValidation::Permissive => ValidationMode::Permissive,
+        GossipValidation::Anonymous => ValidationMode::Anonymous,
+        GossipValidation::None => ValidationMode::None,
+    };
     let gossipsub_config = gossipsub::GossipsubConfigBuilder::default()
-        .heartbeat_interval(Duration::from_secs(10))
-        .validation_mode(ValidationMode::Strict)
+        .heartbeat_interval(Duration::from_secs(gossip.heartbeat_interval_seconds()))
+        .mesh_n_low(gossip.mesh_n_low())
+        .mesh_n(gossip.mesh_n())
+        .mesh_n_high(gossip.mesh_n_high())
+        .mesh_outbound_min(cmp::min(2, cmp::min(gossip.mesh_n_low(), gossip.mesh_n() / 2)))
+        .validation_mode(validation_mode)
+        .max_transmit_size(gossip.max_transmit_size())
+        .flood_publish(gossip.flood_publish())
         //    .message_id_fn(message_id_fn)
         .build()
-        .expect("Valid config");
+        .map_err(|error| Box::new(ConfigError::new(&format!("Gossip: {}", error))) as Box<dyn BlockchainError>)?;
+    communication::limit_message_size(gossip.max_transmit_size());
+    let authenticity = if gossip.validation_mode().signed() {
+        MessageAuthenticity::Signed(key.clone())
+    } else {
+        MessageAuthenticity::Anonymous
+    };
 
-    let transport = TokioTransport::new(Config::default().nodelay(true))
+    let tcp_transport = TokioTransport::new(Config::default().nodelay(true));
+    #[cfg(feature = "nat")]
+    let (relay_transport, relay_client) = relay::v2::client::Client::new_transport_and_behaviour(local_id);
+    #[cfg(feature = "nat")]
+    let tcp_transport = OrTransport::new(relay_transport, tcp_transport);
+    let transport = tcp_transport
         .upgrade(upgrade::Version::V1)
         .authenticate(
             noise::NoiseAuthenticated::xx(&key)
                 .expect("Signing libp2p-noise static DH keypair failed."),
         ).multiplex(mplex::MplexConfig::new())
         .boxed();
-    let gossipsub = Gossipsub::new(MessageAuthenticity::Signed(key), gossipsub_config)
-        .expect("Correct configuration");
+    let gossipsub = Gossipsub::new(authenticity, gossipsub_config)
+        .map_err(|error| Box::new(ConfigError::new(&format!("Gossip: {}", error))) as Box<dyn BlockchainError>)?;
 
     let mut behaviour = BlockchainBehaviour {
         gossipsub,
         mdns: TokioBehaviour::new(mdns::Config {
             ttl: Duration::MAX,
             query_interval: Duration::from_secs(1),
-            enable_ipv6: false,
+            enable_ipv6: config.mdns_ipv6(),
         }).unwrap(),
+        // a single lost ping must not close the connection
+        ping: ping::Behaviour::new(
+            ping::Config::new()
+                .with_interval(Duration::from_secs(PING_INTERVAL_SECONDS))
+                .with_max_failures(NonZeroU32::new(3).unwrap()),
+        ),
+        #[cfg(feature = "nat")]
+        nat: NatBehaviour::new(&key, relay_client),
     };
     behaviour.gossipsub.subscribe(&NETWORK_TOPIC).expect("subscribe");
 
-    Swarm::with_tokio_executor(transport, behaviour, local_id)
+    Ok(Swarm::with_tokio_executor(transport, behaviour, local_id))
 }
 
 #[derive(NetworkBehaviour)]
@@ -171,6 +950,9 @@ pub fn configure_swarm() -> Swarm<BlockchainBehaviour> {
 pub struct BlockchainBehaviour {
     gossipsub: Gossipsub,
     mdns: TokioBehaviour,
+    ping: ping::Behaviour,
+    #[cfg(feature = "nat")]
+    nat: NatBehaviour,
 }
 
 impl BlockchainBehaviour {
@@ -181,11 +963,19 @@ impl BlockchainBehaviour {
     pub fn mdns(&mut self) -> &mut TokioBehaviour {
         &mut self.mdns
     }
+
+    #[cfg(feature = "nat")]
+    pub fn nat(&mut self) -> &mut NatBehaviour {
+        &mut self.nat
+    }
 }
 
 pub enum BlockchainBehaviourEvent {
     Gossipsub(GossipsubEvent),
     Mdns(Event),
+    Ping(ping::Event),
+    #[cfg(feature = "nat")]
+    Nat(NatEvent),
 }
 
 
@@ -200,3 +990,16 @@ impl From<Event> for BlockchainBehaviourEvent {
         BlockchainBehaviourEvent::Mdns(event)
     }
 }
+
+impl From<ping::Event> for BlockchainBehaviourEvent {
+    fn from(event: ping::Event) -> Self {
+        BlockchainBehaviourEvent::Ping(event)
+    }
+}
+
+#[cfg(feature = "nat")]
+impl From<NatEvent> for BlockchainBehaviourEvent {
+    fn from(event: NatEvent) -> Self {
+        BlockchainBehaviourEvent::Nat(event)
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::PeerId;
+
+use crate::blockchain::BlockchainData;
+use crate::blockchain::core::Blockchain;
+use crate::network::divergence::{self, BlockHeader, Divergence, MAX_DIFF_HEADERS};
+
+// how often a node announces its chain tip to the peers it is connected to
+pub static ANTI_ENTROPY_SECONDS: i64 = 60;
+// a peer that does not answer with its headers in time is asked again at its next announcement
+pub static PROBE_TIMEOUT_SECONDS: i64 = 30;
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum TipCheck {
+    Agrees,
+    // the local chain is longer, the peer finds out from this node's own announcement
+    Ahead,
+    // a fork of equal length the local branch wins with its lower tip hash, the peer syncs from
+    // this node once it compares the tips the other way round
+    Diverged,
+    // headers to ask the peer for, enough to reach back to the finalized height
+    Probe(u64),
+    // the peer was asked for its headers already
+    Probing,
+}
+
+// Both halves of a partitioned network keep forging, and peers that stayed connected through
+// the split never start a new sync session. Nodes therefore announce their tip now and then;
+// a peer announcing a longer chain is asked for its recent headers, and if fork choice prefers
+// its branch the node syncs from it, rolling back the blocks of the losing branch.
+#[derive(Default)]
+pub struct AntiEntropy {
+    last_announced: Option<DateTime<Utc>>,
+    probes: HashMap<PeerId, DateTime<Utc>>,
+}
+
+impl AntiEntropy {
+    // true once per interval, the caller announces its tip then
+    pub fn announcement_due(&mut self, now: DateTime<Utc>) -> bool {
+        if self.last_announced.is_some_and(|last| now - last < Duration::seconds(ANTI_ENTROPY_SECONDS)) {
+            return false;
+        }
+        self.last_announced = Some(now);
+        true
+    }
+
+    pub fn check_tip<T>(
+        &mut self, peer: PeerId, height: u64, hash: &str, local: &Blockchain<T>, max_reorg_depth: u64,
+        now: DateTime<Utc>,
+    ) -> TipCheck where T: BlockchainData {
+        // unanswered probes are forgotten, the peer is asked again at its next announcement
+        self.probes.retain(|_, asked| now - *asked < Duration::seconds(PROBE_TIMEOUT_SECONDS));
+        let local_tip = tip_hash(local);
+        if height < local.chain_length() {
+            return TipCheck::Ahead;
+        }
+        if height == local.chain_length() && local_tip.as_deref() == Some(hash) {
+            return TipCheck::Agrees;
+        }
+        if !divergence::prefers_remote(local.chain_length(), local_tip.as_deref(), height, Some(hash)) {
+            return TipCheck::Diverged;
+        }
+        if self.probes.contains_key(&peer) {
+            return TipCheck::Probing;
+        }
+        self.probes.insert(peer, now);
+        TipCheck::Probe((height - local.finalized_height(max_reorg_depth) + 1).min(MAX_DIFF_HEADERS))
+    }
+
+    // none for headers this node did not probe for, e.g. those answering a diff command
+    pub fn resolve<T>(
+        &mut self, peer: PeerId, height: u64, headers: &[BlockHeader], local: &Blockchain<T>, max_reorg_depth: u64,
+    ) -> Option<Divergence> where T: BlockchainData {
+        self.probes.remove(&peer)?;
+        Some(Divergence::compare(local, height, headers, max_reorg_depth))
+    }
+
+    pub fn forget(&mut self, peer: &PeerId) {
+        self.probes.remove(peer);
+    }
+
+    pub fn probes(&self) -> usize {
+        self.probes.len()
+    }
+}
+
+pub fn tip_hash<T>(chain: &Blockchain<T>) -> Option<String> where T: BlockchainData {
+    chain.last_block().as_ref().map(|block| block.key().hash())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::network::anti_entropy::{self, AntiEntropy, TipCheck};
+    use crate::network::communication::BlockchainDto;
+    use crate::network::divergence::{self, ForkChoice};
+
+    fn extend(chain: &mut Blockchain<Transaction>, title: &str, blocks: usize) {
+        for _ in 0..blocks {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], title.to_string(), 1, Utc::now())
+            ], chain.last_block()).ok().unwrap();
+            chain.submit_new_block(block);
+        }
+    }
+
+    #[test]
+    fn longer_branches_announced_after_a_partition_are_probed_and_chosen() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        extend(&mut local, "shared", 3);
+        let mut remote = Blockchain::try_from(BlockchainDto::from(&local)).ok().unwrap();
+        extend(&mut local, "local", 2);
+        extend(&mut remote, "remote", 2);
+
+        let (peer, now) = (PeerId::random(), Utc::now());
+        let mut anti_entropy = AntiEntropy::default();
+        assert!(anti_entropy.announcement_due(now));
+        assert!(!anti_entropy.announcement_due(now + Duration::seconds(10)));
+        assert!(anti_entropy.announcement_due(now + Duration::seconds(60)));
+
+        let remote_tip = anti_entropy::tip_hash(&remote).unwrap();
+        let local_tip = anti_entropy::tip_hash(&local).unwrap();
+        let check = |anti_entropy: &mut AntiEntropy, remote: &Blockchain<Transaction>, tip: &str| {
+            anti_entropy.check_tip(peer, remote.chain_length(), tip, &local, 3, now)
+        };
+        // of two equally long branches only the lower tip is worth syncing to
+        match (check(&mut anti_entropy, &remote, &remote_tip), remote_tip < local_tip) {
+            (TipCheck::Probe(_), true) | (TipCheck::Diverged, false) => {}
+            (other, _) => panic!("unexpected {:?} for a tie", other),
+        }
+        anti_entropy.forget(&peer);
+        assert_eq!(check(&mut anti_entropy, &local, &local_tip), TipCheck::Agrees);
+        assert!(anti_entropy.resolve(peer, remote.chain_length(), &[], &local, 3).is_none());
+
+        // the other half forged one more block meanwhile
+        extend(&mut remote, "remote", 1);
+        let remote_tip = anti_entropy::tip_hash(&remote).unwrap();
+        let count = match check(&mut anti_entropy, &remote, &remote_tip) {
+            TipCheck::Probe(count) => count,
+            other => panic!("expected a probe, got {:?}", other),
+        };
+        assert_eq!(count, 5);
+        assert_eq!(check(&mut anti_entropy, &remote, &remote_tip), TipCheck::Probing);
+
+        let headers = divergence::recent_headers(&remote, count);
+        let divergence = anti_entropy.resolve(peer, remote.chain_length(), &headers, &local, 3).unwrap();
+        assert_eq!(divergence.fork_point(), Some(3));
+        assert_eq!(divergence.fork_choice(), ForkChoice::Remote);
+        assert!(anti_entropy.resolve(peer, remote.chain_length(), &headers, &local, 3).is_none());
+    }
+
+    #[test]
+    fn unanswered_probes_are_pruned() {
+        let local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        let (silent, leaving, now) = (PeerId::random(), PeerId::random(), Utc::now());
+        let mut anti_entropy = AntiEntropy::default();
+        for peer in [silent, leaving] {
+            assert!(matches!(anti_entropy.check_tip(peer, 9, "tip", &local, 3, now), TipCheck::Probe(_)));
+        }
+        anti_entropy.forget(&leaving);
+        assert_eq!(anti_entropy.probes(), 1);
+
+        // any announcement after the timeout drops the probe the silent peer never answered
+        let later = now + Duration::seconds(anti_entropy::PROBE_TIMEOUT_SECONDS);
+        assert_eq!(anti_entropy.check_tip(leaving, 0, "behind", &local, 3, later), TipCheck::Ahead);
+        assert_eq!(anti_entropy.probes(), 0);
+    }
+}
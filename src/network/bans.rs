@@ -0,0 +1,169 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Address;
+use crate::blockchain::access;
+use crate::blockchain::core::{BlockchainError, StorageError};
+
+pub static BANS_FILE: &str = "bans.json";
+// misbehaving forgers sit out a day of rounds, operators can lift a ban earlier
+pub static BAN_DURATION_HOURS: i64 = 24;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BanRecord {
+    // base58, as printed in logs
+    peer_id: String,
+    wallet: Option<Address>,
+    reason: String,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    cleared_at: Option<DateTime<Utc>>,
+}
+
+impl BanRecord {
+    pub fn peer_id(&self) -> Option<PeerId> {
+        PeerId::from_str(&self.peer_id).ok()
+    }
+    pub fn wallet(&self) -> Option<Address> {
+        self.wallet
+    }
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        self.issued_at
+    }
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> bool {
+        self.cleared_at.is_none() && self.expires_at > now
+    }
+
+    pub fn describe(&self, now: DateTime<Utc>) -> String {
+        let wallet = match self.wallet {
+            None => String::from("unknown wallet"),
+            Some(wallet) => access::encode_address(wallet)
+        };
+        let state = match self.cleared_at {
+            Some(cleared_at) => format!("cleared at {}", cleared_at.to_rfc3339()),
+            None if self.active(now) => format!("until {}", self.expires_at.to_rfc3339()),
+            None => format!("expired at {}", self.expires_at.to_rfc3339()),
+        };
+        format!(
+            "{} ({}): {}, banned at {}, {}",
+            self.peer_id, wallet, self.reason, self.issued_at.to_rfc3339(), state
+        )
+    }
+}
+
+// Every ban ever issued, kept after expiry as an audit trail. Lists loaded from a file write
+// every change back to it, lists created in memory are never persisted.
+#[derive(Serialize, Deserialize, Default)]
+pub struct BanList {
+    records: Vec<BanRecord>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl BanList {
+    pub fn load(path: &Path) -> BanList {
+        let bans: BanList = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => BanList::default()
+        };
+        BanList {
+            path: Some(path.to_path_buf()),
+            ..bans
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let content = serde_json::to_string_pretty(self).unwrap();
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn records(&self) -> &[BanRecord] {
+        &self.records
+    }
+
+    pub fn is_banned(&self, peer_id: &PeerId, now: DateTime<Utc>) -> bool {
+        let peer_id = peer_id.to_base58();
+        self.records.iter().any(|record| record.peer_id == peer_id && record.active(now))
+    }
+
+    pub fn active(&self, now: DateTime<Utc>) -> Vec<&BanRecord> {
+        self.records.iter().filter(|record| record.active(now)).collect()
+    }
+
+    pub fn ban(&mut self, peer_id: PeerId, wallet: Option<Address>, reason: &str, now: DateTime<Utc>) {
+        self.records.push(BanRecord {
+            peer_id: peer_id.to_base58(),
+            wallet,
+            reason: reason.to_string(),
+            issued_at: now,
+            expires_at: now + Duration::hours(BAN_DURATION_HOURS),
+            cleared_at: None,
+        });
+    }
+
+    // lifts active bans of one peer or of everyone, returns how many were lifted
+    pub fn clear(&mut self, peer_id: Option<PeerId>, now: DateTime<Utc>) -> usize {
+        let peer_id = peer_id.map(|peer_id| peer_id.to_base58());
+        let mut cleared = 0;
+        for record in &mut self.records {
+            let matches = peer_id.as_ref().is_none_or(|peer_id| *peer_id == record.peer_id);
+            if matches && record.active(now) {
+                record.cleared_at = Some(now);
+                cleared += 1;
+            }
+        }
+        cleared
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::network::bans::BanList;
+
+    #[test]
+    fn bans_survive_a_restart_and_stay_listed_after_clearing() {
+        let path = env::temp_dir().join(format!("kingcoin-bans-{}.json", std::process::id()));
+        let (forger, other) = (PeerId::random(), PeerId::random());
+        let now = Utc::now();
+        let mut bans = BanList::load(&path);
+        bans.ban(forger, Some([3; 32]), "equivocation", now);
+        bans.ban(other, None, "block voted down", now);
+        assert!(bans.save().is_ok());
+
+        let mut reloaded = BanList::load(&path);
+        assert!(reloaded.is_banned(&forger, now));
+        assert!(!reloaded.is_banned(&forger, now + Duration::hours(25)));
+        assert_eq!(reloaded.records()[0].wallet(), Some([3; 32]));
+
+        assert_eq!(reloaded.clear(Some(forger), now), 1);
+        assert!(!reloaded.is_banned(&forger, now));
+        assert!(reloaded.is_banned(&other, now));
+        assert_eq!(reloaded.records().len(), 2);
+        fs::remove_file(&path).ok();
+    }
+}
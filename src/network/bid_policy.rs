@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::BlockchainError;
+use crate::command::CommandError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BidPolicy {
+    Fixed(i64),
+    Percent(u8),
+    Off,
+}
+
+impl Default for BidPolicy {
+    fn default() -> Self {
+        BidPolicy::Percent(75)
+    }
+}
+
+impl BidPolicy {
+    pub fn parse(value: &str) -> Result<BidPolicy, Box<dyn BlockchainError>> {
+        let invalid = || -> Box<dyn BlockchainError> {
+            Box::new(CommandError::new("Bid policy must be fixed:<amount>, percent:<0-100> or off"))
+        };
+        match value.split_once(':') {
+            None if value == "off" => Ok(BidPolicy::Off),
+            Some(("fixed", amount)) => match amount.parse::<i64>() {
+                Ok(amount) if amount > 0 => Ok(BidPolicy::Fixed(amount)),
+                _ => Err(invalid())
+            },
+            Some(("percent", percent)) => match percent.parse::<u8>() {
+                Ok(percent) if percent <= 100 => Ok(BidPolicy::Percent(percent)),
+                _ => Err(invalid())
+            },
+            _ => Err(invalid())
+        }
+    }
+
+    pub fn participates(&self) -> bool {
+        *self != BidPolicy::Off
+    }
+
+    // None when the node sits the round out
+    pub fn bid_amount(&self, balance: i64) -> Option<i64> {
+        let balance = balance.max(0);
+        match self {
+            BidPolicy::Fixed(amount) => Some(*amount.min(&balance)),
+            BidPolicy::Percent(percent) => Some(balance * *percent as i64 / 100),
+            BidPolicy::Off => None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            BidPolicy::Fixed(amount) => format!("fixed:{}", amount),
+            BidPolicy::Percent(percent) => format!("percent:{}", percent),
+            BidPolicy::Off => String::from("off"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::network::bid_policy::BidPolicy;
+
+    #[test]
+    fn parses_policies_and_caps_bids_at_balance() {
+        assert_eq!(BidPolicy::parse("percent:75").ok(), Some(BidPolicy::Percent(75)));
+        assert_eq!(BidPolicy::parse("off").ok(), Some(BidPolicy::Off));
+        assert!(BidPolicy::parse("percent:120").is_err());
+        assert!(BidPolicy::parse("fixed:-3").is_err());
+
+        assert_eq!(BidPolicy::Percent(75).bid_amount(200), Some(150));
+        assert_eq!(BidPolicy::Fixed(500).bid_amount(200), Some(200));
+        assert_eq!(BidPolicy::Off.bid_amount(200), None);
+    }
+}
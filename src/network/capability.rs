@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// version 2 wraps every message in an envelope, see communication::envelope. Version 1 nodes
+// cannot read enveloped messages, they are disconnected once their hello arrives.
+pub static PROTOCOL_VERSION: u32 = 3;
+pub static MIN_PROTOCOL_VERSION: u32 = 2;
+// wallet keys gossiped as base64 DER, see blockchain::compact_key
+pub static COMPACT_KEYS_VERSION: u32 = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    ChainSync,
+    MempoolSync,
+    Receipts,
+    WalletSync,
+    // features advertised by newer nodes that this node does not know about
+    #[serde(other)]
+    Unknown,
+}
+
+pub static LOCAL_FEATURES: &[Feature] = &[
+    Feature::ChainSync, Feature::MempoolSync, Feature::Receipts, Feature::WalletSync,
+];
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Hello {
+    protocol_version: u32,
+    features: Vec<Feature>,
+    // sender's clock, sampled to detect local clock drift, absent from older nodes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sent_at: Option<DateTime<Utc>>,
+}
+
+impl Hello {
+    pub fn local() -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            features: LOCAL_FEATURES.to_vec(),
+            sent_at: Some(Utc::now()),
+        }
+    }
+
+    pub fn sent_at(&self) -> Option<DateTime<Utc>> {
+        self.sent_at
+    }
+
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    pub fn features(&self) -> &Vec<Feature> {
+        &self.features
+    }
+
+    pub fn compatible(&self) -> bool {
+        self.protocol_version >= MIN_PROTOCOL_VERSION
+    }
+}
+
+pub struct PeerCapabilities {
+    protocol_version: u32,
+    features: HashSet<Feature>,
+}
+
+impl PeerCapabilities {
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+}
+
+// gossip reaches every peer alike, so messages are encoded for the oldest version among them
+pub fn negotiated_version<'a>(peers: impl Iterator<Item = &'a PeerCapabilities>) -> u32 {
+    peers.map(PeerCapabilities::protocol_version).min().unwrap_or(PROTOCOL_VERSION)
+}
+
+impl From<Hello> for PeerCapabilities {
+    fn from(hello: Hello) -> Self {
+        Self {
+            protocol_version: hello.protocol_version,
+            // only features both sides understand are worth keeping
+            features: hello.features.into_iter()
+                .filter(|feature| LOCAL_FEATURES.contains(feature))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::network::capability::{self, COMPACT_KEYS_VERSION, Hello, LOCAL_FEATURES, PeerCapabilities, PROTOCOL_VERSION};
+
+    fn peer(protocol_version: u32) -> PeerCapabilities {
+        PeerCapabilities::from(Hello {
+            protocol_version,
+            features: LOCAL_FEATURES.to_vec(),
+            sent_at: None,
+        })
+    }
+
+    #[test]
+    fn the_oldest_peer_decides_the_negotiated_version() {
+        assert_eq!(capability::negotiated_version([].iter()), PROTOCOL_VERSION);
+        let current = [peer(PROTOCOL_VERSION), peer(COMPACT_KEYS_VERSION)];
+        assert!(capability::negotiated_version(current.iter()) >= COMPACT_KEYS_VERSION);
+        let mixed = [peer(PROTOCOL_VERSION), peer(2)];
+        assert_eq!(capability::negotiated_version(mixed.iter()), 2);
+    }
+}
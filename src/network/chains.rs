@@ -0,0 +1,333 @@
+use std::any::Any;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::blockchain::BlockchainData;
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, StorageError};
+use crate::network::communication::{BlockchainDto, BlockDto};
+
+pub struct ChainError {
+    message: String,
+}
+
+impl ChainError {
+    pub fn new(message: &str) -> ChainError {
+        ChainError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for ChainError {
+    fn message(&self) -> String {
+        format!("Chain: {}", self.message)
+    }
+}
+
+// Data stored on a chain of its own besides transactions, wallets and stakes. Such chains are
+// submitted to, forged by the round's forger, announced and synced through the generic
+// Chain* messages, so adding one needs no new message variants or dispatch paths.
+pub trait ChainPayload: BlockchainData + DeserializeOwned + Send + Sync + 'static {
+    // names the chain in messages, unique within a registry
+    fn chain_name() -> &'static str;
+
+    // checked for announced blocks and replayed for every block of a synced chain
+    fn block_valid(
+        chain: &Blockchain<Self>, block: &BlockCandidate<Self>,
+    ) -> Result<(), Box<dyn BlockchainError>>;
+
+    // rejects data early so it never reaches the pool
+    fn data_valid(_chain: &Blockchain<Self>, _data: &Self) -> Result<(), Box<dyn BlockchainError>> {
+        Ok(())
+    }
+}
+
+// type-erased view of a registered chain, payloads cross it as json values
+pub trait RegisteredChain: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn chain_length(&self) -> u64;
+    fn pending_data(&self) -> usize;
+    fn submit_data(&mut self, data: Value) -> Result<(), Box<dyn BlockchainError>>;
+    fn forge_block(&self) -> Option<Value>;
+    fn append_block(&mut self, block: Value) -> Result<u64, Box<dyn BlockchainError>>;
+    fn export(&self) -> Value;
+    // adopts a valid remote chain if it is longer, returns whether it was adopted
+    fn import(&mut self, chain: Value) -> Result<bool, Box<dyn BlockchainError>>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+fn malformed(error: serde_json::Error) -> Box<dyn BlockchainError> {
+    Box::new(ChainError::new(&format!("Malformed payload: {}", error)))
+}
+
+impl<T> RegisteredChain for Blockchain<T> where T: ChainPayload {
+    fn name(&self) -> &'static str {
+        T::chain_name()
+    }
+
+    fn chain_length(&self) -> u64 {
+        Blockchain::chain_length(self)
+    }
+
+    fn pending_data(&self) -> usize {
+        self.uncommitted_data().len()
+    }
+
+    fn submit_data(&mut self, data: Value) -> Result<(), Box<dyn BlockchainError>> {
+        let data: T = serde_json::from_value(data).map_err(malformed)?;
+        T::data_valid(self, &data)?;
+        // gossip may deliver the same submission twice
+        if self.uncommitted_data().iter().any(|pending| pending.summary() == data.summary()) {
+            return Ok(());
+        }
+        self.add_uncommitted(data);
+        Ok(())
+    }
+
+    fn forge_block(&self) -> Option<Value> {
+        let pending = self.uncommitted_data();
+        if pending.is_empty() {
+            return None;
+        }
+        let units = std::cmp::min(self.data_units_per_block() as usize, pending.len());
+        let block = BlockCandidate::create_new(pending[..units].to_vec(), self.last_block()).ok()?;
+        Some(serde_json::to_value(BlockDto::from(block)).unwrap())
+    }
+
+    fn append_block(&mut self, block: Value) -> Result<u64, Box<dyn BlockchainError>> {
+        let block_dto: BlockDto<T> = serde_json::from_value(block).map_err(malformed)?;
+        let block = BlockCandidate::try_from(block_dto)?;
+        let tip = match self.last_block() {
+            None => return Err(Box::new(ChainError::new("Chain has no genesis block"))),
+            Some(tip) => tip.key(),
+        };
+        let expected = BlockCandidate::<T>::hash(
+            tip.raw_hash(), BlockCandidate::summarize(block.data()), block.key().raw_state_root(),
+        );
+        if block.key().raw_previous_hash() != Some(tip.raw_hash()) || block.key().hash() != expected.hash() {
+            return Err(Box::new(ChainError::new(&format!(
+                "Block {} does not extend {} at height {}",
+                block.key().hash(), T::chain_name(), Blockchain::chain_length(self)
+            ))));
+        }
+        T::block_valid(self, &block)?;
+        Ok(self.submit_new_block(block).block_number())
+    }
+
+    fn export(&self) -> Value {
+        serde_json::to_value(BlockchainDto::from(self)).unwrap()
+    }
+
+    fn import(&mut self, chain: Value) -> Result<bool, Box<dyn BlockchainError>> {
+        let chain_dto: BlockchainDto<T> = serde_json::from_value(chain).map_err(malformed)?;
+        let mut remote = Blockchain::try_from(chain_dto)?;
+        let genesis_hash = |chain: &Blockchain<T>| -> Result<Option<String>, Box<dyn BlockchainError>> {
+            Ok(chain.block_at(0)?.map(|genesis| genesis.key().hash()))
+        };
+        if genesis_hash(&remote)? != genesis_hash(self)? {
+            return Err(Box::new(ChainError::new(&format!("{} starts from another genesis block", T::chain_name()))));
+        }
+        remote.verify_full(T::block_valid)?;
+        if Blockchain::chain_length(&remote) <= Blockchain::chain_length(self) {
+            return Ok(false);
+        }
+        // pending data is no part of the chain, it has to pass as if it were submitted here
+        let pending = remote.uncommitted_data().to_vec();
+        remote.discard_uncommitted(&pending);
+        for data in pending {
+            if T::data_valid(&remote, &data).is_ok() {
+                remote.add_uncommitted(data);
+            }
+        }
+        self.replace(remote);
+        Ok(true)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[derive(Default)]
+pub struct ChainRegistry {
+    chains: Vec<Box<dyn RegisteredChain>>,
+    // once set, every chain is kept in a json file named after it in here
+    dir: Option<PathBuf>,
+}
+
+impl ChainRegistry {
+    pub fn new() -> ChainRegistry {
+        ChainRegistry::default()
+    }
+
+    pub fn register<T>(&mut self, chain: Blockchain<T>) -> Result<(), Box<dyn BlockchainError>> where T: ChainPayload {
+        if self.get(T::chain_name()).is_some() {
+            return Err(Box::new(ChainError::new(&format!("{} is already registered", T::chain_name()))));
+        }
+        self.chains.push(Box::new(chain));
+        Ok(())
+    }
+
+    // restores the chains saved in the directory, replaying them like synced ones, and saves
+    // them there from now on
+    pub fn persist_in(&mut self, dir: PathBuf) -> Result<(), Box<dyn BlockchainError>> {
+        for chain in &mut self.chains {
+            let content = match fs::read_to_string(dir.join(format!("{}.json", chain.name()))) {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            chain.import(serde_json::from_str(&content).map_err(malformed)?)?;
+        }
+        self.dir = Some(dir);
+        Ok(())
+    }
+
+    pub fn save(&self, name: &str) -> Result<(), Box<dyn BlockchainError>> {
+        let (dir, chain) = match (&self.dir, self.get(name)) {
+            (Some(dir), Some(chain)) => (dir, chain),
+            _ => return Ok(()),
+        };
+        let content = serde_json::to_string(&chain.export()).unwrap();
+        match fs::write(dir.join(format!("{}.json", name)), content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.chains.iter().map(|chain| chain.name()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn RegisteredChain> {
+        self.chains.iter()
+            .find(|chain| chain.name() == name)
+            .map(|chain| chain.as_ref())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Box<dyn RegisteredChain>> {
+        self.chains.iter_mut().find(|chain| chain.name() == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&dyn RegisteredChain> {
+        self.chains.iter().map(|chain| chain.as_ref())
+    }
+
+    // typed access for code that knows the payload, e.g. to query a chain
+    pub fn chain<T>(&self) -> Option<&Blockchain<T>> where T: ChainPayload {
+        self.get(T::chain_name())?.as_any().downcast_ref()
+    }
+
+    pub fn chain_mut<T>(&mut self) -> Option<&mut Blockchain<T>> where T: ChainPayload {
+        self.get_mut(T::chain_name())?.as_any_mut().downcast_mut()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use crate::blockchain::BlockchainData;
+    use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, Summary};
+    use crate::network::chains::{ChainError, ChainPayload, ChainRegistry};
+    use crate::network::communication::BlockchainDto;
+
+    #[derive(Serialize, Deserialize, Clone)]
+    struct Note(String);
+
+    impl Summary for Note {
+        fn summary(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    impl BlockchainData for Note {}
+
+    impl ChainPayload for Note {
+        fn chain_name() -> &'static str {
+            "notes"
+        }
+
+        fn block_valid(_chain: &Blockchain<Note>, block: &BlockCandidate<Note>) -> Result<(), Box<dyn BlockchainError>> {
+            match block.data().iter().any(|note| note.0.is_empty()) {
+                true => Err(Box::new(ChainError::new("Empty note"))),
+                false => Ok(())
+            }
+        }
+    }
+
+    #[test]
+    fn registered_chains_are_forged_announced_and_synced_by_name() {
+        let mut forger = ChainRegistry::new();
+        let mut peer = ChainRegistry::new();
+        let mut late = ChainRegistry::new();
+        for registry in [&mut forger, &mut peer, &mut late] {
+            assert!(registry.register(Blockchain::<Note>::empty_chain()).is_ok());
+        }
+        assert!(forger.register(Blockchain::<Note>::empty_chain()).is_err());
+
+        let notes = forger.get_mut("notes").unwrap();
+        assert!(notes.submit_data(json!("hello")).is_ok());
+        assert!(notes.submit_data(json!("hello")).is_ok());
+        assert!(notes.submit_data(json!(7)).is_err());
+        assert_eq!(notes.pending_data(), 1);
+        let block = notes.forge_block().unwrap();
+        assert_eq!(notes.append_block(block.clone()).ok(), Some(1));
+        assert_eq!(peer.get_mut("notes").unwrap().append_block(block.clone()).ok(), Some(1));
+        // the same block cannot be appended twice
+        assert!(peer.get_mut("notes").unwrap().append_block(block).is_err());
+
+        let exported = forger.get("notes").unwrap().export();
+        assert_eq!(late.get_mut("notes").unwrap().import(exported.clone()).ok(), Some(true));
+        assert_eq!(late.get_mut("notes").unwrap().import(exported).ok(), Some(false));
+        let synced = late.chain::<Note>().unwrap();
+        assert_eq!(synced.chain_length(), 2);
+        assert_eq!(synced.last_block().as_ref().unwrap().data()[0].0, "hello");
+    }
+
+    #[test]
+    fn imported_chains_are_replayed_block_by_block() {
+        let mut registry = ChainRegistry::new();
+        assert!(registry.register(Blockchain::<Note>::empty_chain()).is_ok());
+        let mut remote = Blockchain::<Note>::empty_chain();
+        for note in ["first", "", "third"] {
+            let block = BlockCandidate::create_new(vec![Note(note.to_string())], remote.last_block()).ok().unwrap();
+            remote.submit_new_block(block);
+        }
+
+        let exported = serde_json::to_value(BlockchainDto::from(&remote)).unwrap();
+        assert!(registry.get_mut("notes").unwrap().import(exported).is_err());
+        assert_eq!(registry.get("notes").unwrap().chain_length(), 1);
+    }
+
+    #[test]
+    fn persisted_chains_are_restored_on_start() {
+        let dir = env::temp_dir().join(format!("kingcoin-chains-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut registry = ChainRegistry::new();
+        assert!(registry.register(Blockchain::<Note>::empty_chain()).is_ok());
+        assert!(registry.persist_in(dir.clone()).is_ok());
+        let notes = registry.get_mut("notes").unwrap();
+        assert!(notes.submit_data(json!("kept")).is_ok());
+        let block = notes.forge_block().unwrap();
+        assert!(notes.append_block(block).is_ok());
+        assert!(registry.save("notes").is_ok());
+
+        let mut restarted = ChainRegistry::new();
+        assert!(restarted.register(Blockchain::<Note>::empty_chain()).is_ok());
+        assert!(restarted.persist_in(dir.clone()).is_ok());
+        assert_eq!(restarted.chain::<Note>().unwrap().chain_length(), 2);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
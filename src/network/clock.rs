@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::PeerId;
+
+// the offset is only trusted once enough peers reported their time
+pub static MIN_CLOCK_SAMPLES: usize = 3;
+pub static CLOCK_DRIFT_WARNING_SECONDS: i64 = 30;
+// a node this far off the network is more likely fed bad samples than drifting, it keeps its
+// own clock and relies on the operator
+pub static MAX_CLOCK_OFFSET_SECONDS: i64 = 600;
+
+// Differences between peer clocks and the local one, taken from the time peers put in their
+// Hello. The median of them is how far the local clock lags the network.
+#[derive(Default)]
+pub struct ClockSamples {
+    offsets: HashMap<PeerId, i64>,
+    warned: bool,
+}
+
+impl ClockSamples {
+    pub fn new() -> ClockSamples {
+        ClockSamples::default()
+    }
+
+    pub fn size(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // returns whether the local clock just drifted past the warning threshold
+    pub fn record(&mut self, peer_id: PeerId, remote_time: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.offsets.insert(peer_id, (remote_time - now).num_seconds());
+        let drifting = self.median_offset().is_some_and(|offset| offset.abs() > CLOCK_DRIFT_WARNING_SECONDS);
+        let newly_drifting = drifting && !self.warned;
+        self.warned = drifting;
+        newly_drifting
+    }
+
+    pub fn remove(&mut self, peer_id: &PeerId) {
+        self.offsets.remove(peer_id);
+    }
+
+    // seconds the network median is ahead of the local clock, none with too few samples
+    pub fn median_offset(&self) -> Option<i64> {
+        if self.offsets.len() < MIN_CLOCK_SAMPLES {
+            return None;
+        }
+        let mut offsets: Vec<i64> = self.offsets.values().copied().collect();
+        offsets.sort();
+        Some(offsets[offsets.len() / 2])
+    }
+
+    // added to the local clock when validating block times
+    pub fn offset(&self) -> Duration {
+        match self.median_offset() {
+            Some(offset) if offset.abs() <= MAX_CLOCK_OFFSET_SECONDS => Duration::seconds(offset),
+            _ => Duration::zero()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::network::clock::ClockSamples;
+
+    #[test]
+    fn offset_follows_the_median_peer_clock() {
+        let now = Utc::now();
+        let mut clock = ClockSamples::new();
+        assert!(!clock.record(PeerId::random(), now + Duration::seconds(40), now));
+        assert!(!clock.record(PeerId::random(), now + Duration::seconds(45), now));
+        assert_eq!(clock.offset(), Duration::zero());
+        // a single peer far off does not move the median
+        let liar = PeerId::random();
+        assert!(clock.record(liar, now - Duration::hours(5), now));
+        assert_eq!(clock.offset(), Duration::seconds(40));
+        assert!(!clock.record(PeerId::random(), now + Duration::seconds(50), now));
+        assert_eq!(clock.offset(), Duration::seconds(45));
+
+        clock.remove(&liar);
+        assert_eq!(clock.size(), 3);
+        let mut skewed = ClockSamples::new();
+        for _ in 0..3 {
+            skewed.record(PeerId::random(), now + Duration::hours(1), now);
+        }
+        assert_eq!(skewed.median_offset(), Some(3600));
+        assert_eq!(skewed.offset(), Duration::zero());
+    }
+}
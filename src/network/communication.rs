@@ -1,19 +1,42 @@
 use std::mem;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::Instant;
 
 use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
 use libp2p::{PeerId, Swarm};
+use libp2p::gossipsub::error::PublishError;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::blockchain::{BlockchainData, StakeBid, Transaction, Wallet};
-use crate::blockchain::core::{BlockCandidate, Blockchain, Summary};
+use crate::blockchain::{BlockchainData, RejectionReason, StakeBid, Transaction, Wallet};
+use crate::blockchain::core::{Block, BlockCandidate, Blockchain, BlockchainError, Summary};
+use crate::blockchain::governance::{GovernanceRecord, GovernanceVote, Proposal};
 use crate::network::{BlockchainBehaviour, NETWORK_TOPIC};
+use crate::network::capability::Hello;
+use crate::network::communication::outbox::{Outbox, PublishOutcome};
+use crate::network::divergence::BlockHeader;
+use crate::report;
 
+pub mod approval;
 pub mod dispatch;
+pub mod envelope;
+pub mod mempool;
+pub mod orphan;
+pub mod outbox;
+pub mod registrations;
+
+lazy_static! {
+    // shared by every publish site, they are spread over dispatch and the command handlers
+    static ref OUTBOX: Mutex<Outbox> = Mutex::new(Outbox::default());
+}
 
 #[derive(Eq, PartialEq, Hash)]
 pub struct Vote {
     id: PeerId,
     block_valid: bool,
+    reason: Option<RejectionReason>,
 }
 
 impl Vote {
@@ -21,17 +44,35 @@ impl Vote {
         Vote {
             id,
             block_valid,
+            reason: None,
         }
     }
 
+    pub fn with_reason(mut self, reason: Option<RejectionReason>) -> Self {
+        self.reason = reason;
+        self
+    }
+
+    pub fn id(&self) -> PeerId {
+        self.id
+    }
+
     pub fn block_valid(&self) -> bool {
         self.block_valid
     }
+
+    pub fn reason(&self) -> &Option<RejectionReason> {
+        &self.reason
+    }
 }
 
 pub struct VotingResult {
     block_valid: i64,
     block_invalid: i64,
+    // whether the votes met the approval threshold, a simple majority unless configured otherwise
+    approved: bool,
+    // distinct reasons given by the rejecting voters with how many gave each, most common first
+    reasons: Vec<(RejectionReason, i64)>,
 }
 
 impl VotingResult {
@@ -39,28 +80,79 @@ impl VotingResult {
         VotingResult {
             block_valid,
             block_invalid,
+            approved: block_valid > block_invalid,
+            reasons: vec![],
         }
     }
 
+    pub fn with_approval(mut self, approved: bool) -> Self {
+        self.approved = approved;
+        self
+    }
+
+    pub fn with_reasons<'a>(mut self, reasons: impl Iterator<Item=&'a RejectionReason>) -> Self {
+        for reason in reasons {
+            match self.reasons.iter_mut().find(|(given, _)| given == reason) {
+                Some((_, count)) => *count += 1,
+                None => self.reasons.push((reason.clone(), 1))
+            }
+        }
+        self.reasons.sort_by_key(|(reason, count)| (-count, reason.message()));
+        self
+    }
+
+    pub fn votes_for(&self) -> i64 {
+        self.block_valid
+    }
+
+    pub fn votes_against(&self) -> i64 {
+        self.block_invalid
+    }
+
     pub fn should_append_block(&self) -> bool {
-        self.block_valid > self.block_invalid
+        self.approved
+    }
+
+    pub fn reasons(&self) -> &[(RejectionReason, i64)] {
+        &self.reasons
+    }
+
+    pub fn diagnosis(&self) -> String {
+        let explained: i64 = self.reasons.iter().map(|(_, count)| count).sum();
+        let mut lines = vec![format!(
+            "rejected by {} of {} votes", self.block_invalid, self.block_valid + self.block_invalid
+        )];
+        for (reason, count) in &self.reasons {
+            lines.push(format!("  {} x {}", count, reason.message()));
+        }
+        if explained < self.block_invalid {
+            lines.push(format!("  {} x no reason given", self.block_invalid - explained));
+        }
+        lines.join("\n")
     }
 }
 
+// block data is always deserializable, see BlockchainData
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(bound(deserialize = ""))]
 pub struct BlockDto<T> where T: BlockchainData {
     block_hash: String,
     previous_block_hash: Option<String>,
+    // absent from blocks of nodes before state roots
+    #[serde(default)]
+    state_root: Option<String>,
     data: Vec<T>,
     time: DateTime<Utc>,
     block_number: u64,
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(bound(deserialize = ""))]
 pub struct BlockchainDto<T> where T: BlockchainData {
     blocks: Vec<BlockDto<T>>,
     chain_length: u64,
     uncommitted_data: Vec<T>,
+    // informational, receivers go by the block size of their own genesis
     max_data_units_per_block: u64,
     remaining_pool: i64,
 }
@@ -85,28 +177,22 @@ impl<T> BlockchainDto<T> where T: BlockchainData {
 
 impl<T> From<Blockchain<T>> for BlockchainDto<T> where T: BlockchainData {
     fn from(blockchain: Blockchain<T>) -> Self {
-        let blocks = {
-            let mut current_block = blockchain.last_block();
-            let mut result: Vec<BlockDto<T>> = vec![];
-            loop {
-                match current_block {
-                    None => break,
-                    Some(block) => {
-                        let block_key = block.key();
-                        let block_dto = BlockDto {
-                            block_hash: block_key.hash(),
-                            previous_block_hash: block_key.previous_hash(),
-                            data: block.data().clone(),
-                            time: block.time().clone().unwrap(),
-                            block_number: block.block_number(),
-                        };
-                        result.push(block_dto);
-                        current_block = block.previous_block();
-                    }
-                }
-            };
-            result
-        };
+        BlockchainDto::from(&blockchain)
+    }
+}
+
+// answering a sync request must not give up the local chain
+impl<T> From<&Blockchain<T>> for BlockchainDto<T> where T: BlockchainData {
+    fn from(blockchain: &Blockchain<T>) -> Self {
+        // newest first, a bounded chain reads its older blocks back from disk
+        let mut blocks = vec![];
+        for block in blockchain.blocks() {
+            match block {
+                Ok(block) => blocks.push(BlockDto::from(block.deref())),
+                Err(error) => report!("Chain not fully shared: {}", error.message())
+            }
+        }
+        blocks.reverse();
         Self {
             blocks,
             chain_length: blockchain.chain_length(),
@@ -126,6 +212,10 @@ impl<T> BlockDto<T> where T: BlockchainData {
         mem::take(&mut self.previous_block_hash)
     }
 
+    pub fn take_state_root(&mut self) -> Option<String> {
+        mem::take(&mut self.state_root)
+    }
+
     pub fn take_data(&mut self) -> Vec<T> {
         mem::take(&mut self.data)
     }
@@ -139,12 +229,27 @@ impl<T> BlockDto<T> where T: BlockchainData {
     }
 }
 
+impl<T> From<&Block<T>> for BlockDto<T> where T: BlockchainData {
+    fn from(block: &Block<T>) -> Self {
+        let block_key = block.key();
+        Self {
+            block_hash: block_key.hash(),
+            previous_block_hash: block_key.previous_hash(),
+            state_root: block_key.state_root(),
+            data: block.data().clone(),
+            time: block.time().unwrap_or_default(),
+            block_number: block.block_number(),
+        }
+    }
+}
+
 impl<T> From<BlockCandidate<T>> for BlockDto<T> where T: BlockchainData + Summary {
     fn from(mut candidate: BlockCandidate<T>) -> Self {
         let block_key = candidate.key();
         Self {
             block_hash: block_key.hash(),
             previous_block_hash: block_key.previous_hash(),
+            state_root: block_key.state_root(),
             data: candidate.take_data(),
             time: candidate.take_time(),
             block_number: candidate.block_number(),
@@ -159,24 +264,131 @@ pub enum BlockchainMessage {
         wallets: BlockchainDto<Wallet>,
         staked: BlockchainDto<Transaction>
     },
+    // sync session: peers answer a request with their height, the tallest is then asked for
+    // its chains by peer id since gossip reaches everyone
+    SyncRequest,
+    ChainHeight(u64),
+    SyncFrom(String),
+    // diagnostics: the addressed peer answers with the headers of its newest blocks
+    HeadersRequest {
+        peer: String,
+        count: u64,
+    },
+    Headers {
+        height: u64,
+        headers: Vec<BlockHeader>,
+    },
+    // anti-entropy: the sender's transaction chain height and the hash of its last block
+    ChainTip {
+        height: u64,
+        hash: String,
+    },
+    // chains registered in network::chains, payloads are the chain's data, BlockDto and
+    // BlockchainDto as json
+    ChainData {
+        chain: String,
+        data: Value,
+    },
+    ChainBlock {
+        chain: String,
+        block: Value,
+    },
+    ChainSync {
+        chain: String,
+        blocks: Value,
+    },
     SubmitTransaction(Transaction),
+    // a contract settlement carrying the approvals of some of its parties
+    SettlementApproval(Transaction),
     SubmitBlock {
         block_dto: BlockDto<Transaction>
     },
+    RegisterWallet(Wallet),
+    // carries the rotated wallet entry, signed by the key it replaces
+    UpdateWalletKey(Wallet),
+    SubmitWalletBlock {
+        block_dto: BlockDto<Wallet>
+    },
     Vote {
-        block_valid: bool
+        block_valid: bool,
+        // peers predating vote justifications send none
+        #[serde(default)]
+        reason: Option<RejectionReason>,
     },
     Bid(StakeBid),
+    Hello(Hello),
+    MempoolDigest(Vec<String>),
+    MempoolRequest(Vec<String>),
+    MempoolTransactions(Vec<Transaction>),
+    // pending wallet entries, reconciled by Wallet::id like the mempool
+    WalletDigest(Vec<String>),
+    WalletRequest(Vec<String>),
+    WalletEntries(Vec<Wallet>),
+    Proposal(Proposal),
+    GovernanceVote(GovernanceVote),
+    // every proposal and vote the sender knows, sent along with its chains on sync
+    GovernanceRecords(Vec<GovernanceRecord>),
+    // receipt announcement, transactions are identified by Transaction::id
+    BlockAppended {
+        height: u64,
+        tx_hashes: Vec<String>,
+    },
 }
 
+impl BlockchainMessage {
+    // only meant for the consensus round they were sent in
+    pub fn round_scoped(&self) -> bool {
+        matches!(
+            self,
+            BlockchainMessage::Bid(_) | BlockchainMessage::Vote { .. }
+                | BlockchainMessage::SubmitBlock { .. } | BlockchainMessage::SubmitWalletBlock { .. }
+        )
+    }
+}
 
+
+// Messages are held in the outbox while no mesh peer exists or when publishing fails, and go
+// out from flush_outbox. A held message waiting out its retry delay does not hold back newer
+// ones, so peers may see them out of order.
 pub fn publish_message(swarm: &mut Swarm<BlockchainBehaviour>, message: BlockchainMessage) {
-    let message = serde_json::to_string(&message).unwrap();
-    let sending_result = swarm.behaviour_mut()
-        .gossipsub()
-        .publish(NETWORK_TOPIC.clone(), message);
-    match sending_result {
-        Ok(_) => {}
-        Err(_) => println!("Could not publish")
+    let round_scoped = message.round_scoped();
+    let message = envelope::encode(&message);
+    let mut outbox = OUTBOX.lock().expect("Outbox lock poisoned");
+    outbox.hold(message, round_scoped, Instant::now());
+    flush(swarm, &mut outbox);
+}
+
+// bids, votes and blocks of the round still held are not worth sending into the next one
+pub fn end_outbox_round() {
+    OUTBOX.lock().expect("Outbox lock poisoned").end_round();
+}
+
+// set from the gossip config before anything is published
+pub fn limit_message_size(max_size: usize) {
+    OUTBOX.lock().expect("Outbox lock poisoned").limit_size(max_size);
+}
+
+pub fn flush_outbox(swarm: &mut Swarm<BlockchainBehaviour>) {
+    flush(swarm, &mut OUTBOX.lock().expect("Outbox lock poisoned"));
+}
+
+pub fn held_messages() -> usize {
+    OUTBOX.lock().expect("Outbox lock poisoned").len()
+}
+
+fn flush(swarm: &mut Swarm<BlockchainBehaviour>, outbox: &mut Outbox) {
+    let gossipsub = swarm.behaviour_mut().gossipsub();
+    if gossipsub.mesh_peers(&NETWORK_TOPIC.hash()).next().is_none() {
+        return;
     }
+    outbox.flush(Instant::now(), |message| {
+        match gossipsub.publish(NETWORK_TOPIC.clone(), message) {
+            Ok(_) | Err(PublishError::Duplicate) => PublishOutcome::Published,
+            Err(PublishError::InsufficientPeers) => PublishOutcome::Retry,
+            Err(error) => {
+                report!("Could not publish: {:?}", error);
+                PublishOutcome::Rejected
+            }
+        }
+    });
 }
\ No newline at end of file
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::blockchain::Transaction;
+use crate::blockchain::contract::Contract;
+
+pub static MAX_PENDING_SETTLEMENTS: usize = 256;
+// a release and a refund, plus room for parties that signed a variant the others did not
+pub static MAX_SETTLEMENTS_PER_ESCROW: usize = 4;
+// parties get a day to add the approvals a settlement still lacks
+pub static APPROVAL_TTL_HOURS: i64 = 24;
+
+// Settlements that need more approvals before they may enter the mempool, keyed by the escrow
+// they settle. Only approvals signed by a party of the escrow are stored, see
+// TransactionValidator::party_approvals, and they are merged into the pending settlement with
+// the content they were signed over.
+pub struct ApprovalPool {
+    pending: HashMap<String, Vec<(Transaction, DateTime<Utc>)>>,
+}
+
+impl Default for ApprovalPool {
+    fn default() -> Self {
+        ApprovalPool::new()
+    }
+}
+
+impl ApprovalPool {
+    pub fn new() -> ApprovalPool {
+        ApprovalPool {
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.pending.len()
+    }
+
+    // the pending settlement with the approvals of the received one added
+    pub fn merged(&self, received: &Transaction) -> Transaction {
+        let pending = escrow_id(received)
+            .and_then(|escrow_id| self.pending.get(escrow_id))
+            .and_then(|settlements| settlements.iter()
+                .find(|(pending, _)| pending.signed_content() == received.signed_content()));
+        match pending {
+            None => received.clone(),
+            Some((pending, _)) => {
+                let mut merged = pending.clone();
+                for approval in received.approvals() {
+                    merged.add_approval(approval.clone());
+                }
+                merged
+            }
+        }
+    }
+
+    pub fn store(&mut self, settlement: Transaction, now: DateTime<Utc>) -> bool {
+        self.expire(now);
+        let escrow_id = match escrow_id(&settlement) {
+            None => return false,
+            Some(escrow_id) => escrow_id.to_string()
+        };
+        if !self.pending.contains_key(&escrow_id) && self.pending.len() >= MAX_PENDING_SETTLEMENTS {
+            return false;
+        }
+        let settlements = self.pending.entry(escrow_id).or_default();
+        match settlements.iter().position(|(pending, _)| pending.signed_content() == settlement.signed_content()) {
+            Some(position) => settlements[position] = (settlement, now),
+            None if settlements.len() >= MAX_SETTLEMENTS_PER_ESCROW => return false,
+            None => settlements.push((settlement, now)),
+        }
+        true
+    }
+
+    // an escrow settles once, whichever of its settlements made it
+    pub fn remove(&mut self, settlement: &Transaction) {
+        if let Some(escrow_id) = escrow_id(settlement) {
+            self.pending.remove(escrow_id);
+        }
+    }
+
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let deadline = now - Duration::hours(APPROVAL_TTL_HOURS);
+        self.pending.retain(|_, settlements| {
+            settlements.retain(|(_, stored_at)| *stored_at > deadline);
+            !settlements.is_empty()
+        });
+    }
+}
+
+fn escrow_id(settlement: &Transaction) -> Option<&str> {
+    settlement.contract().as_ref().and_then(Contract::lock_id)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{Transaction, TransactionValidationError, TransactionValidator, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::contract::Approval;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::signer::Signer;
+    use crate::network::communication::approval::ApprovalPool;
+    use crate::random;
+
+    #[test]
+    fn escrow_settles_once_two_of_three_parties_approved() {
+        let mut rng = random::seeded(11);
+        let [sender, recipient, arbiter, outsider] = [(); 4].map(|_| HotWallet::generate(&mut rng));
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            [&sender, &recipient, &arbiter, &outsider].iter().map(|party| party.wallet().clone()).collect(),
+            wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+        let mut escrow = Transaction::escrow_open(sender.address(), recipient.address(), arbiter.address(), 60);
+        sender.sign(&mut escrow, &mut rng);
+        let block = BlockCandidate::create_new(vec![escrow.clone()], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+
+        let mut pool = ApprovalPool::new();
+        let validator = TransactionValidator::new(&wallets, &transactions);
+        let mut approval = Transaction::escrow_release(&escrow).unwrap();
+        Signer::approve(&sender, &mut approval, &mut rng).ok().unwrap();
+        assert_eq!(validator.party_approvals(&approval).len(), 1);
+        let merged = pool.merged(&approval);
+        assert_eq!(
+            validator.transaction_valid(&merged),
+            Err(TransactionValidationError::MissingApprovals { have: 1, need: 2 })
+        );
+        pool.store(merged, Utc::now());
+        // outsiders and approvals over other content never make it into the pool
+        let mut stranger = Transaction::escrow_release(&escrow).unwrap();
+        Signer::approve(&outsider, &mut stranger, &mut rng).ok().unwrap();
+        assert!(validator.party_approvals(&stranger).is_empty());
+        let mut forged = Transaction::escrow_release(&escrow).unwrap();
+        forged.add_approval(Approval::new(arbiter.address(), approval.approvals()[0].signature().to_string()));
+        assert!(validator.party_approvals(&forged).is_empty());
+        let mut refund = Transaction::escrow_refund(&escrow).unwrap();
+        Signer::approve(&recipient, &mut refund, &mut rng).ok().unwrap();
+        assert!(pool.store(refund.clone(), Utc::now()));
+        assert_eq!(pool.size(), 1);
+        assert_eq!(pool.merged(&refund).approvals().len(), 1);
+
+        let mut approval = Transaction::escrow_release(&escrow).unwrap();
+        Signer::approve(&arbiter, &mut approval, &mut rng).ok().unwrap();
+        let settlement = pool.merged(&approval);
+        assert!(validator.transaction_valid(&settlement).is_ok());
+        assert_eq!(settlement.approvals().len(), 2);
+        // a refund only approved by the recipient stays pending
+        assert!(validator.transaction_valid(&refund).is_err());
+
+        let block = BlockCandidate::create_new(vec![settlement], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        assert_eq!(transactions.balance_of(recipient.address()), 60);
+    }
+}
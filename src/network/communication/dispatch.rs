@@ -1,11 +1,31 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use libp2p::{PeerId, Swarm};
 use libp2p::gossipsub::GossipsubEvent;
 use libp2p::mdns::Event;
+use libp2p::ping;
+use libp2p::core::ConnectedPoint;
 use libp2p::swarm::SwarmEvent;
 
-use crate::blockchain::{BlockchainData, StakeBid, Transaction, TransactionValidator, Wallet};
-use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, TransactionCountError, Validate};
-use crate::network::{BlockchainBehaviour, BlockchainBehaviourEvent, communication::{self, BlockDto, Vote}, NodeState};
+use crate::blockchain::{access, Address, BlockchainData, invariants, RejectionReason, StakeBid, Transaction, TransactionValidationError, TransactionValidator, Wallet, WalletValidator};
+use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+use crate::blockchain::contract::{self, Contract};
+use crate::blockchain::core::{BlockCandidate, Blockchain, BlockchainError, Rollback, TransactionCountError, Validate};
+use crate::blockchain::key_history::KeyHistory;
+use crate::blockchain::rules::{ChainRules, Rules};
+use crate::blockchain::snapshot;
+use crate::blockchain::stake::{self, StakeRegistry, UNBONDING_PERIOD};
+use crate::blockchain::upgrade::{UPGRADE_SCHEDULE, UpgradeSchedule};
+use crate::network::{BlockchainBehaviour, BlockchainBehaviourEvent, communication::{self, BlockchainDto, BlockDto, envelope, mempool, registrations, Vote, VotingResult}, election, NodeState, ProposalRejection, Voter};
+use crate::network::capability::{Feature, Hello, PeerCapabilities};
+use crate::network::anti_entropy::{self, TipCheck};
+use crate::network::divergence::{self, DIFF_HEADERS, Divergence, ForkChoice, MAX_DIFF_HEADERS};
+use crate::network::inactivity::RoundPhase;
+use crate::network::rounds::RoundOutcome;
+use crate::network::sync::SyncAction;
+use crate::network::validators::ChainMisses;
+use crate::report;
 
 use super::BlockchainMessage;
 
@@ -22,33 +42,76 @@ pub fn dispatch_network_event<H>(
                                       message,
                                   })
         ) => {
-            if let Ok(message) = serde_json::from_slice::<BlockchainMessage>(&message.data) {
-                dispatch_blockchain_event(
+            match envelope::decode(&message.data) {
+                Ok(message) => dispatch_blockchain_event(
                     swarm, transactions, wallets,
                     peer_id, message, node_state, stakes,
-                );
+                ),
+                Err(error) => report!("Ignoring message from {}: {}", peer_id, error.message()),
+            }
+        }
+        SwarmEvent::Behaviour(BlockchainBehaviourEvent::Gossipsub(
+                                  GossipsubEvent::Subscribed { .. })
+        ) => {
+            // a peer joined the topic, introduce ourselves so it learns our capabilities
+            communication::publish_message(swarm, BlockchainMessage::Hello(Hello::local()));
+            if let SyncAction::RequestHeights = node_state.sync_mut().begin(Utc::now()) {
+                report!("Sync: asking peers for their chain heights");
+                communication::publish_message(swarm, BlockchainMessage::SyncRequest);
             }
         }
         SwarmEvent::Behaviour(BlockchainBehaviourEvent::Mdns(event)) => {
-            dispatch_mdns(swarm, event)
+            dispatch_mdns(swarm, event, transactions, wallets, node_state, stakes)
+        }
+        SwarmEvent::Behaviour(BlockchainBehaviourEvent::Ping(ping::Event { peer, result })) => match result {
+            Ok(ping::Success::Ping { rtt }) => node_state.latency_mut().record_rtt(peer, rtt),
+            Ok(ping::Success::Pong) => {}
+            Err(_) => node_state.latency_mut().record_failure(peer),
+        }
+        #[cfg(feature = "nat")]
+        SwarmEvent::Behaviour(BlockchainBehaviourEvent::Nat(event)) => {
+            crate::network::nat::dispatch_nat(swarm, event)
+        }
+        SwarmEvent::NewListenAddr { address, .. } => {
+            report!("Listening on {}/p2p/{}", address, swarm.local_peer_id());
+        }
+        SwarmEvent::ExpiredListenAddr { address, .. } => {
+            report!("No longer listening on {}", address);
+        }
+        SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
+            // only dialed addresses are worth keeping, inbound connections come from ephemeral ports
+            if let ConnectedPoint::Dialer { address, .. } = endpoint {
+                node_state.known_peers_mut().connected(peer_id, &address, Utc::now());
+            }
+            on_peer_online(swarm, node_state, peer_id)
+        }
+        SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), .. } => {
+            node_state.known_peers_mut().dial_failed(peer_id);
+        }
+        SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+            on_peer_offline(swarm, transactions, wallets, node_state, stakes, peer_id)
         }
         _ => {}
     }
 }
 
-fn dispatch_mdns(swarm: &mut Swarm<BlockchainBehaviour>, event: Event) {
+fn dispatch_mdns(
+    swarm: &mut Swarm<BlockchainBehaviour>, event: Event, transactions: &mut Blockchain<Transaction>,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+) {
     match event {
         Event::Discovered(list) => {
             for (peer, addr) in list {
-                println!("found {peer} {addr}");
+                report!("found {peer} {addr}");
                 swarm.behaviour_mut().gossipsub().add_explicit_peer(&peer);
             }
         }
         Event::Expired(list) => {
             for (peer, addr) in list {
-                println!("expired {peer} {addr}");
+                report!("expired {peer} {addr}");
                 if !swarm.behaviour_mut().mdns().has_node(&peer) {
                     swarm.behaviour_mut().gossipsub().remove_explicit_peer(&peer);
+                    on_peer_offline(swarm, transactions, wallets, node_state, stakes, peer);
                 }
             }
         }
@@ -64,55 +127,775 @@ fn dispatch_blockchain_event(
 ) {
     match message {
         BlockchainMessage::SubmitTransaction(transaction) => {
-            transactions.add_uncommitted(transaction)
+            mempool::merge(transactions, wallets, stakes, node_state.orphans_mut(), vec![transaction]);
+        }
+        BlockchainMessage::SettlementApproval(settlement) => {
+            if let Err(error) = collect_approval(transactions, wallets, node_state, settlement) {
+                report!("Ignoring approval from {}: {}", sending_peer, error.message());
+            }
         }
         BlockchainMessage::SubmitBlock { block_dto } => {
-            let block_candidate = BlockCandidate::from(block_dto);
-            let transaction_validator = TransactionValidator::new(&wallets, &transactions);
-            let block_valid = match transaction_validator.block_valid(&block_candidate) {
-                Ok(_) => true,
+            let block_candidate = match BlockCandidate::try_from(block_dto) {
+                Ok(block_candidate) => block_candidate,
                 Err(error) => {
-                    println!("{}", error.message());
-                    false
+                    report!("{}", error.message());
+                    return;
                 }
             };
-            node_state.set_pending_block(block_candidate);
-            let vote = BlockchainMessage::Vote {
-                block_valid
+            match node_state.accept_block_proposal(sending_peer, block_candidate) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(rejection) => {
+                    report!("Ignoring block from {}: {}", sending_peer, rejection.message());
+                    if let ProposalRejection::Equivocation = rejection {
+                        slash_forger(swarm, transactions, wallets, node_state, stakes);
+                    }
+                    return;
+                }
+            }
+            let schedule = node_state.governance().schedule(&UPGRADE_SCHEDULE, transactions);
+            let transaction_validator = TransactionValidator::with_upgrades(
+                wallets, transactions, &schedule,
+            ).with_stakes(stakes)
+                .with_clock_offset(node_state.clock().offset());
+            let pending_block = node_state.pending_block()
+                .as_ref()
+                .expect("Accepted proposal is pending");
+            let reason = transaction_validator.diagnose(pending_block).err()
+                .or_else(|| rule_broken(node_state.rules().transactions(), pending_block));
+            if let Some(reason) = &reason {
+                report!("Voting against block from {}: {}", sending_peer, reason.message());
+            }
+            publish_vote(swarm, node_state, reason);
+        }
+        BlockchainMessage::RegisterWallet(wallet) => {
+            if let Err(error) = register_wallet(wallets, wallet) {
+                report!("{}", error.message());
+            }
+        }
+        BlockchainMessage::UpdateWalletKey(wallet) => {
+            if let Err(error) = update_wallet_key(wallets, wallet) {
+                report!("{}", error.message());
+            }
+        }
+        BlockchainMessage::SubmitWalletBlock { block_dto } => {
+            let block_candidate = match BlockCandidate::try_from(block_dto) {
+                Ok(block_candidate) => block_candidate,
+                Err(error) => {
+                    report!("{}", error.message());
+                    return;
+                }
             };
-            communication::publish_message(swarm, vote);
+            match node_state.accept_wallet_block_proposal(sending_peer, block_candidate) {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(rejection) => {
+                    report!("Ignoring wallet block from {}: {}", sending_peer, rejection.message());
+                    if let ProposalRejection::Equivocation = rejection {
+                        slash_forger(swarm, transactions, wallets, node_state, stakes);
+                    }
+                    return;
+                }
+            }
+            let pending_block = node_state.pending_wallet_block()
+                .as_ref()
+                .expect("Accepted proposal is pending");
+            let reason = WalletValidator::new(wallets)
+                .with_clock_offset(node_state.clock().offset())
+                .diagnose(pending_block)
+                .err()
+                .or_else(|| rule_broken(node_state.rules().wallets(), pending_block));
+            if let Some(reason) = &reason {
+                report!("Voting against wallet block from {}: {}", sending_peer, reason.message());
+            }
+            publish_vote(swarm, node_state, reason);
         }
-        BlockchainMessage::Vote { block_valid } => on_vote_received(
-            swarm, transactions, sending_peer, node_state, block_valid,
+        BlockchainMessage::Vote { block_valid, reason } => on_vote_received(
+            swarm, transactions, wallets, node_state, stakes,
+            Vote::new(sending_peer, block_valid).with_reason(reason),
         ),
         BlockchainMessage::Bid(stake_bid) => on_stake_raised(
-            swarm, transactions, sending_peer, node_state, stakes, stake_bid,
+            swarm, transactions, wallets, sending_peer, node_state, stakes, stake_bid,
         ),
-        BlockchainMessage::Sync { .. } => {todo!()}
+        BlockchainMessage::Sync {
+            transactions: remote_transactions,
+            wallets: remote_wallets,
+            staked,
+        } => {
+            if !node_state.peer_supports(&sending_peer, Feature::ChainSync) {
+                report!("Ignoring chain from {}: sync not negotiated", sending_peer);
+                return;
+            }
+            let requested = node_state.sync_mut().receive_chain(sending_peer, Utc::now());
+            let schedule = node_state.governance().schedule(&UPGRADE_SCHEDULE, transactions);
+            let max_reorg_depth = node_state.max_reorg_depth();
+            let reorg_depth = node_state.reorg_depth(transactions);
+            let validated = validate_sync(remote_transactions, remote_wallets, staked, &schedule, node_state.rules())
+                .and_then(|(remote_transactions, remote_wallets, remote_stakes)| {
+                    if let Err(error) = transactions.reorg_allowed(&remote_transactions, reorg_depth) {
+                        report_divergence(sending_peer, transactions, &remote_transactions, reorg_depth);
+                        return Err(error);
+                    }
+                    wallets.reorg_allowed(&remote_wallets, max_reorg_depth)?;
+                    stakes.reorg_allowed(&remote_stakes, max_reorg_depth)?;
+                    Ok((remote_transactions, remote_wallets, remote_stakes))
+                });
+            match validated {
+                Ok((remote_transactions, remote_wallets, remote_stakes)) => {
+                    if let Some(rollback) = adopt_if_preferred(transactions, remote_transactions) {
+                        on_transactions_rolled_back(node_state, rollback);
+                        settle_governance(node_state, transactions);
+                    }
+                    adopt_if_preferred(wallets, remote_wallets);
+                    adopt_if_preferred(stakes, remote_stakes);
+                    node_state.mark_synced(Utc::now());
+                    if requested {
+                        let next = node_state.sync_mut().complete(Utc::now(), transactions.chain_length());
+                        report!("Sync: {}", node_state.sync().describe(transactions.chain_length()));
+                        perform_sync_action(swarm, node_state, transactions, next);
+                    }
+                }
+                Err(error) => {
+                    report!("Rejected chain from {}: {}", sending_peer, error.message());
+                    if requested {
+                        let next = node_state.sync_mut().fail(Utc::now(), transactions.chain_length());
+                        perform_sync_action(swarm, node_state, transactions, next);
+                    }
+                }
+            }
+        }
+        BlockchainMessage::SyncRequest => {
+            if node_state.peer_supports(&sending_peer, Feature::ChainSync) {
+                communication::publish_message(swarm, BlockchainMessage::ChainHeight(transactions.chain_length()));
+            }
+        }
+        BlockchainMessage::ChainHeight(height) => {
+            node_state.sync_mut().record_height(sending_peer, height);
+        }
+        BlockchainMessage::SyncFrom(peer) => {
+            if peer != node_state.node_id().to_base58() {
+                return;
+            }
+            communication::publish_message(swarm, BlockchainMessage::Sync {
+                transactions: BlockchainDto::from(&*transactions),
+                wallets: BlockchainDto::from(&*wallets),
+                staked: BlockchainDto::from(&*stakes),
+            });
+            for chain in node_state.chains().iter() {
+                communication::publish_message(swarm, BlockchainMessage::ChainSync {
+                    chain: chain.name().to_string(),
+                    blocks: chain.export(),
+                });
+            }
+            communication::publish_message(swarm, BlockchainMessage::GovernanceRecords(
+                node_state.governance().records().iter_transactions().rev().cloned().collect(),
+            ));
+        }
+        BlockchainMessage::HeadersRequest { peer, count } => {
+            if peer != node_state.node_id().to_base58() {
+                return;
+            }
+            communication::publish_message(swarm, BlockchainMessage::Headers {
+                height: transactions.chain_length(),
+                headers: divergence::recent_headers(transactions, count.min(MAX_DIFF_HEADERS)),
+            });
+        }
+        BlockchainMessage::Headers { height, headers } => {
+            let max_reorg_depth = node_state.reorg_depth(transactions);
+            if node_state.take_diff_request(&sending_peer) {
+                let divergence = Divergence::compare(transactions, height, &headers, max_reorg_depth);
+                report!("Chain of {}:\n{}", sending_peer, divergence.describe());
+                return;
+            }
+            let probed = node_state.anti_entropy_mut()
+                .resolve(sending_peer, height, &headers, transactions, max_reorg_depth);
+            if let Some(divergence) = probed {
+                on_branch_probed(swarm, sending_peer, divergence);
+            }
+        }
+        BlockchainMessage::ChainTip { height, hash } => {
+            let max_reorg_depth = node_state.reorg_depth(transactions);
+            let check = node_state.anti_entropy_mut()
+                .check_tip(sending_peer, height, &hash, transactions, max_reorg_depth, Utc::now());
+            match check {
+                TipCheck::Probe(count) => communication::publish_message(swarm, BlockchainMessage::HeadersRequest {
+                    peer: sending_peer.to_base58(),
+                    count,
+                }),
+                TipCheck::Diverged => report!(
+                    "Chain of {} forked at height {}, the local branch has the lower tip and stays", sending_peer, height
+                ),
+                TipCheck::Agrees | TipCheck::Ahead | TipCheck::Probing => {}
+            }
+        }
+        BlockchainMessage::ChainData { chain, data } => {
+            let submitted = match node_state.chains_mut().get_mut(&chain) {
+                None => return,
+                Some(registered) => registered.submit_data(data),
+            };
+            if let Err(error) = submitted {
+                report!("Ignoring {} data from {}: {}", chain, sending_peer, error.message());
+            }
+        }
+        BlockchainMessage::ChainBlock { chain, block } => {
+            if node_state.block_creator() != Some(sending_peer) {
+                report!("Ignoring {} block from {}: not this round's forger", chain, sending_peer);
+                return;
+            }
+            let appended = match node_state.chains_mut().get_mut(&chain) {
+                None => return,
+                Some(registered) => registered.append_block(block),
+            };
+            match appended {
+                Ok(block_number) => {
+                    report!("Appended {} block {}", chain, block_number);
+                    save_registered_chain(node_state, &chain);
+                }
+                Err(error) => report!("Rejected {} block from {}: {}", chain, sending_peer, error.message())
+            }
+        }
+        BlockchainMessage::ChainSync { chain, blocks } => {
+            if !node_state.peer_supports(&sending_peer, Feature::ChainSync) {
+                return;
+            }
+            let imported = match node_state.chains_mut().get_mut(&chain) {
+                None => return,
+                Some(registered) => registered.import(blocks),
+            };
+            match imported {
+                Ok(true) => save_registered_chain(node_state, &chain),
+                Ok(false) => {}
+                Err(error) => report!("Rejected {} chain from {}: {}", chain, sending_peer, error.message())
+            }
+        }
+        BlockchainMessage::Hello(hello) => {
+            if let Some(sent_at) = hello.sent_at() {
+                if node_state.clock_mut().record(sending_peer, sent_at, Utc::now()) {
+                    report!(
+                        "Warning: local clock is {}s off the network median, check the system time",
+                        node_state.clock().median_offset().unwrap_or_default()
+                    );
+                }
+            }
+            if hello.compatible() {
+                node_state.update_peer_capabilities(sending_peer, PeerCapabilities::from(hello));
+                if node_state.peer_supports(&sending_peer, Feature::MempoolSync) {
+                    communication::publish_message(
+                        swarm, BlockchainMessage::MempoolDigest(mempool::digest(transactions)),
+                    );
+                }
+                if node_state.peer_supports(&sending_peer, Feature::WalletSync) {
+                    communication::publish_message(
+                        swarm, BlockchainMessage::WalletDigest(registrations::digest(wallets)),
+                    );
+                }
+            } else {
+                // it could not read anything this node publishes
+                report!(
+                    "Disconnecting {}: unsupported protocol version {}",
+                    sending_peer, hello.protocol_version()
+                );
+                let _ = swarm.disconnect_peer_id(sending_peer);
+            }
+        }
+        BlockchainMessage::MempoolDigest(remote_digest) => {
+            let missing = mempool::missing(transactions, &remote_digest);
+            if !missing.is_empty() {
+                communication::publish_message(swarm, BlockchainMessage::MempoolRequest(missing));
+            }
+        }
+        BlockchainMessage::MempoolRequest(requested) => {
+            let found = mempool::collect(transactions, &requested);
+            if !found.is_empty() {
+                communication::publish_message(swarm, BlockchainMessage::MempoolTransactions(found));
+            }
+        }
+        BlockchainMessage::MempoolTransactions(received) => {
+            mempool::merge(transactions, wallets, stakes, node_state.orphans_mut(), received);
+        }
+        BlockchainMessage::WalletDigest(remote_digest) => {
+            let missing = registrations::missing(wallets, &remote_digest);
+            if !missing.is_empty() {
+                communication::publish_message(swarm, BlockchainMessage::WalletRequest(missing));
+            }
+        }
+        BlockchainMessage::WalletRequest(requested) => {
+            let found = registrations::collect(wallets, &requested);
+            if !found.is_empty() {
+                communication::publish_message(swarm, BlockchainMessage::WalletEntries(found));
+            }
+        }
+        BlockchainMessage::WalletEntries(received) => {
+            registrations::merge(wallets, received);
+        }
+        BlockchainMessage::BlockAppended { height, tx_hashes } => {
+            if node_state.mark_receipt(height, transactions.chain_length()) {
+                notify_incoming_payments(transactions, node_state.wallet_address(), height, &tx_hashes);
+            }
+        }
+        BlockchainMessage::Proposal(proposal) => {
+            let chain_height = transactions.chain_length();
+            match node_state.governance_mut().submit_proposal(proposal, wallets, chain_height) {
+                Ok(_) => save_governance(node_state),
+                Err(error) => report!("{}", error.message())
+            }
+        }
+        BlockchainMessage::GovernanceVote(vote) => {
+            match node_state.governance_mut().submit_vote(vote, wallets) {
+                Ok(_) => save_governance(node_state),
+                Err(error) => report!("{}", error.message())
+            }
+        }
+        BlockchainMessage::GovernanceRecords(records) => {
+            if !node_state.peer_supports(&sending_peer, Feature::ChainSync) {
+                return;
+            }
+            let merged = node_state.governance_mut().merge(records, wallets);
+            if merged > 0 {
+                report!("Merged {} governance records from {}", merged, sending_peer);
+                save_governance(node_state);
+            }
+        }
     }
 }
 
+// advances the sync session on a timer, so silent peers are skipped even without traffic
+pub fn drive_sync(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &Blockchain<Transaction>, node_state: &mut NodeState,
+) {
+    let costs = node_state.latency().costs();
+    node_state.sync_mut().set_peer_costs(costs);
+    let action = node_state.sync_mut().tick(Utc::now(), transactions.chain_length());
+    perform_sync_action(swarm, node_state, transactions, action);
+    let connected = swarm.connected_peers().next().is_some();
+    if connected && node_state.anti_entropy_mut().announcement_due(Utc::now()) {
+        if let Some(hash) = anti_entropy::tip_hash(transactions) {
+            communication::publish_message(swarm, BlockchainMessage::ChainTip {
+                height: transactions.chain_length(),
+                hash,
+            });
+        }
+    }
+}
+
+// the losing branch is rolled back when the synced chain is adopted, see adopt_if_preferred
+fn on_branch_probed(swarm: &mut Swarm<BlockchainBehaviour>, peer: PeerId, divergence: Divergence) {
+    match divergence.fork_choice() {
+        ForkChoice::Remote => {
+            match divergence.fork_point() {
+                Some(fork_point) if divergence.forked() => {
+                    report!("Chain of {} forked after block {} and is longer, syncing from it", peer, fork_point);
+                }
+                _ => report!("Chain of {} is longer, syncing from it", peer),
+            }
+            communication::publish_message(swarm, BlockchainMessage::SyncFrom(peer.to_base58()));
+        }
+        ForkChoice::Finalized => report!("Chain of {}:\n{}", peer, divergence.describe()),
+        ForkChoice::Local => {}
+    }
+}
+
+fn perform_sync_action(
+    swarm: &mut Swarm<BlockchainBehaviour>, node_state: &NodeState,
+    transactions: &Blockchain<Transaction>, action: Option<SyncAction>,
+) {
+    match action {
+        None => {}
+        Some(SyncAction::RequestHeights) => {
+            communication::publish_message(swarm, BlockchainMessage::SyncRequest);
+        }
+        Some(SyncAction::Download(peer)) => {
+            report!("Sync: {}", node_state.sync().describe(transactions.chain_length()));
+            communication::publish_message(swarm, BlockchainMessage::SyncFrom(peer.to_base58()));
+        }
+    }
+}
+
+// transactions, wallets and stakes, in the order they are synced
+type SyncedChains = (Blockchain<Transaction>, Blockchain<Wallet>, Blockchain<Transaction>);
+
+fn validate_sync(
+    transactions: BlockchainDto<Transaction>, wallets: BlockchainDto<Wallet>,
+    stakes: BlockchainDto<Transaction>, upgrades: &UpgradeSchedule, rules: &Rules,
+) -> Result<SyncedChains, Box<dyn BlockchainError>> {
+    let transactions = Blockchain::try_from(transactions)?;
+    let wallets = Blockchain::try_from(wallets)?;
+    wallets.verify_full(|replayed, block| {
+        WalletValidator::new(replayed).block_valid(block)?;
+        rules.wallets().block_valid(block)
+    })?;
+    let key_history = KeyHistory::derive(&wallets, &transactions)?;
+    invariants::verify_transactions(&transactions, &wallets, &key_history, upgrades)?;
+    // a chain its peers accepted under other rules is another network's
+    transactions.verify_full(|_, block| rules.transactions().block_valid(block))?;
+    // elections, bonds and penalties all follow the stakes chain, it is replayed like the others
+    let stakes = Blockchain::try_from(stakes)?;
+    stake::verify(&stakes, &transactions, &wallets)?;
+    Ok((transactions, wallets, stakes))
+}
+
+// a synced chain forking below the finalized height is a consensus split worth explaining
+fn report_divergence(
+    peer: PeerId, local: &Blockchain<Transaction>, remote: &Blockchain<Transaction>, max_reorg_depth: u64,
+) {
+    let headers = divergence::recent_headers(remote, DIFF_HEADERS);
+    let divergence = Divergence::compare(local, remote.chain_length(), &headers, max_reorg_depth);
+    report!("Chain of {} conflicts with ours:\n{}", peer, divergence.describe());
+}
+
+// the same fork choice as divergence::prefers_remote, equally long chains go to the lower tip hash
+pub(crate) fn adopt_if_preferred<T>(
+    local: &mut Blockchain<T>, remote: Blockchain<T>,
+) -> Option<Rollback<T>> where T: BlockchainData {
+    let preferred = divergence::prefers_remote(
+        local.chain_length(), anti_entropy::tip_hash(local).as_deref(),
+        remote.chain_length(), anti_entropy::tip_hash(&remote).as_deref(),
+    );
+    match preferred {
+        true => Some(local.replace(remote)),
+        false => None
+    }
+}
+
+// proposals the chain reached keep the outcome they have now, later votes change nothing
+fn settle_governance(node_state: &mut NodeState, transactions: &Blockchain<Transaction>) {
+    if node_state.governance_mut().settle(transactions) {
+        save_governance(node_state);
+    }
+}
+
+pub fn save_governance(node_state: &NodeState) {
+    if let Err(error) = node_state.governance().save() {
+        report!("Could not save governance: {}", error.message());
+    }
+}
+
+// receipts of the rolled back heights are stale, announcements for the new blocks count again
+fn on_transactions_rolled_back(node_state: &mut NodeState, rollback: Rollback<Transaction>) {
+    if rollback.depth() == 0 {
+        return;
+    }
+    node_state.rewind_receipts(rollback.fork_height());
+    let wallet_address = node_state.wallet_address();
+    let unconfirmed = rollback.affected().iter()
+        .filter(|transaction| {
+            transaction.source_address() == wallet_address || transaction.target_address() == wallet_address
+        })
+        .count();
+    report!(
+        "Reorg: {} block(s) from height {} rolled back, {} of our transactions are unconfirmed again",
+        rollback.depth(), rollback.fork_height(), unconfirmed
+    );
+}
+
+// registrations wait in the wallet mempool until a forger includes them in a wallet block
+pub fn register_wallet(wallets: &mut Blockchain<Wallet>, wallet: Wallet) -> Result<(), Box<dyn BlockchainError>> {
+    WalletValidator::new(wallets).registration_valid(&wallet)?;
+    if !wallets.uncommitted_data().iter().any(|pending| pending.address() == wallet.address()) {
+        wallets.add_uncommitted(wallet);
+    }
+    Ok(())
+}
+
+pub fn update_wallet_key(wallets: &mut Blockchain<Wallet>, wallet: Wallet) -> Result<(), Box<dyn BlockchainError>> {
+    WalletValidator::new(wallets).key_update_valid(&wallet)?;
+    if !wallets.uncommitted_data().iter().any(|pending| pending.address() == wallet.address()) {
+        wallets.add_uncommitted(wallet);
+    }
+    Ok(())
+}
+
 fn on_stake_raised(
     swarm: &mut Swarm<BlockchainBehaviour>,
-    transactions: &mut Blockchain<Transaction>,
+    transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>,
     sending_peer: PeerId, node_state: &mut NodeState,
     stakes: &mut Blockchain<Transaction>, stake_bid: StakeBid,
 ) {
-    node_state.update_peers_bids(sending_peer, stake_bid);
-    if node_state.all_bade(swarm.connected_peers().count()) {
-        let (winner, bid) = node_state.select_highest_bid();
+    // a bid not covered by what the bidder can spend besides its bonded stake would bond nothing
+    let bidder_balance = transactions.staked_breakdown(stakes, stake_bid.transaction().source_address()).spendable();
+    // only bids a registered wallet signed for the sending peer are counted, anyone can make up
+    // an address
+    if let Err(error) = node_state.accept_peer_bid(sending_peer, stake_bid, wallets, bidder_balance) {
+        report!("Ignoring bid from {}: {}", sending_peer, error.message());
+        return;
+    }
+    // money already on its way out must not be bid again
+    let balance = transactions.staked_breakdown(stakes, node_state.wallet_address()).spendable();
+    if node_state.enough_peers(swarm.connected_peers().count()) {
+        if let Some(own_bid) = node_state.prepare_bid(balance) {
+            communication::publish_message(swarm, BlockchainMessage::Bid(own_bid));
+        }
+    }
+    elect_if_quorum(swarm, transactions, wallets, node_state, stakes);
+}
+
+// rounds start with a bid, this one calls for a round while transactions or registrations wait
+pub fn on_block_interval(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &mut NodeState, stakes: &Blockchain<Transaction>,
+) {
+    let pending = !transactions.uncommitted_data().is_empty() || !wallets.uncommitted_data().is_empty();
+    if !pending || node_state.block_creator().is_some() || !node_state.enough_peers(swarm.connected_peers().count()) {
+        return;
+    }
+    let balance = transactions.staked_breakdown(stakes, node_state.wallet_address()).spendable();
+    if let Some(own_bid) = node_state.prepare_bid(balance) {
+        communication::publish_message(swarm, BlockchainMessage::Bid(own_bid));
+    }
+}
+
+// draws the forger once enough of the expected wallets bid, also after one went offline
+fn elect_if_quorum(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+) {
+    let connected: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    if !node_state.bidding_quorum(&connected, &StakeRegistry::derive(stakes, transactions)) {
+        return;
+    }
+    node_state.record_participation(&connected);
+    let previous_hash = transactions.last_block()
+        .as_ref()
+        .map(|block| block.key().hash())
+        .unwrap_or_default();
+    let seed = election::election_seed(&previous_hash, stakes.chain_length());
+    let bids = node_state.bid_count();
+    let misses = ChainMisses::derive(stakes, transactions, wallets);
+    if let Some((winner, bid)) = node_state.elect_forger(seed, &misses) {
+        node_state.rounds_mut().begin(winner, bids, Utc::now());
+        node_state.validator_stats_mut().elected(bid.source_address());
+        start_forging_round(swarm, transactions, wallets, node_state, stakes, winner, bid);
+    } else {
+        node_state.inactivity_mut().close_phase();
+    }
+    node_state.reset_peer_bids();
+}
+
+// Bids and votes are waited for at most the round timeout, the round then goes on without the
+// validators that stayed silent and counts the round as missed for them.
+pub fn drive_rounds(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+) {
+    let phase = match node_state.inactivity().timed_out(Utc::now()) {
+        None => return,
+        Some(phase) => phase,
+    };
+    let connected: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    let no_block = node_state.pending_block().is_none() && node_state.pending_wallet_block().is_none();
+    match (phase, node_state.block_creator()) {
+        (RoundPhase::Voting, Some(forger)) if no_block => {
+            report!("Round abandoned, forger {} sent no block", forger);
+            let deactivated = node_state.record_missing_block(forger);
+            node_state.rounds_mut().abandon(Utc::now());
+            node_state.validator_stats_mut().missed();
+            node_state.take_block_creator();
+            node_state.clear_votes();
+            on_validators_inactive(swarm, transactions, wallets, node_state, stakes, deactivated);
+        }
+        _ => {
+            let deactivated = node_state.record_participation(&connected);
+            node_state.excuse_absent(&connected);
+            on_validators_inactive(swarm, transactions, wallets, node_state, stakes, deactivated);
+            let registry = StakeRegistry::derive(stakes, transactions);
+            let quorum = match phase {
+                RoundPhase::Bidding => node_state.bidding_quorum(&connected, &registry),
+                RoundPhase::Voting => node_state.voting_quorum(&connected, &registry),
+            };
+            match phase {
+                _ if !quorum => abandon_short_round(transactions, wallets, node_state, phase),
+                RoundPhase::Bidding => elect_if_quorum(swarm, transactions, wallets, node_state, stakes),
+                RoundPhase::Voting => settle_if_quorum(swarm, transactions, wallets, node_state, stakes),
+            }
+        }
+    }
+}
 
-        let stakes_block = match BlockCandidate::create_new(
-            vec![bid.transaction().clone()], stakes.last_block(),
-        ) {
-            Ok(block) => block,
-            Err(_) => panic!("No genesis block")
+// even without the silent validators the round lacks the configured participants
+fn abandon_short_round(
+    transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState,
+    phase: RoundPhase,
+) {
+    report!("Round abandoned, fewer than {} validators took part", node_state.quorum().min_participants());
+    match phase {
+        RoundPhase::Bidding => {
+            node_state.inactivity_mut().close_phase();
+            node_state.reset_peer_bids();
+        }
+        RoundPhase::Voting => {
+            node_state.rounds_mut().abandon(Utc::now());
+            node_state.take_block_creator();
+            node_state.clear_votes();
+            node_state.take_pending_wallet_block();
+            if let Some(block) = node_state.take_pending_block() {
+                mempool::requeue(transactions, wallets, node_state.orphans_mut(), block.data().clone());
+            }
+        }
+    }
+}
+
+// the leak burns a share of every bond the validator holds, the same penalties on every node
+fn on_validators_inactive(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>,
+    node_state: &mut NodeState, stakes: &Blockchain<Transaction>, deactivated: Vec<Voter>,
+) {
+    let leak_percent = node_state.inactivity().config().leak_percent();
+    let registry = StakeRegistry::derive(stakes, transactions);
+    for voter in deactivated {
+        let address = match voter {
+            Voter::Peer(peer) => {
+                report!("{} is inactive", peer);
+                continue;
+            }
+            Voter::Wallet(address) => address,
         };
+        report!("{} is inactive", access::encode_address(address));
+        let mut leaked = 0;
+        for epoch in stakes.chain_length().saturating_sub(UNBONDING_PERIOD)..stakes.chain_length() {
+            let amount = registry.bond(address, epoch).unwrap_or(0) * leak_percent / 100;
+            let amount = amount.min(registry.unpenalized(transactions.uncommitted_data(), address, epoch));
+            if amount > 0 {
+                submit_penalty(swarm, transactions, wallets, node_state, stakes, Transaction::penalty(
+                    address, amount, epoch, "Leak", penalty_time(stakes),
+                ));
+                leaked += amount;
+            }
+        }
+        if leaked > 0 {
+            node_state.validator_stats_mut().slashed(address, leaked);
+            report!("Leaked {} of {}", leaked, access::encode_address(address));
+            stakes.notify_slashed(address, leaked);
+        }
+    }
+}
 
-        stakes.submit_new_block(stakes_block);
+// the round no longer waits for the peer, it may be complete without it
+fn on_peer_offline(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+    peer: PeerId,
+) {
+    if !node_state.mark_peer_offline(peer, Utc::now()) {
+        return;
+    }
+    report!("{} went offline", peer);
+    if !node_state.peers_bids().is_empty() {
+        elect_if_quorum(swarm, transactions, wallets, node_state, stakes);
+    }
+    if node_state.vote_count() > 0 {
+        settle_if_quorum(swarm, transactions, wallets, node_state, stakes);
+    }
+}
+
+// redials peers this node was connected to before a restart, alongside mdns discovery
+pub fn dial_known_peers(swarm: &mut Swarm<BlockchainBehaviour>, node_state: &NodeState) {
+    let now = Utc::now();
+    for (peer, address) in node_state.known_peers().dial_addresses() {
+        if node_state.bans().is_banned(&peer, now) {
+            continue;
+        }
+        swarm.behaviour_mut().gossipsub().add_explicit_peer(&peer);
+        if let Err(error) = swarm.dial(address.clone()) {
+            report!("Could not dial {}: {}", address, error);
+        }
+    }
+}
+
+// called every SAVE_INTERVAL_SECONDS and on shutdown, connections made in between are written once
+pub fn save_known_peers(node_state: &mut NodeState) {
+    if let Err(error) = node_state.known_peers_mut().save_changes() {
+        report!("Could not save known peers: {}", error.message());
+    }
+}
+
+// capabilities were forgotten when it left, the hello tells it ours and asks for its own
+fn on_peer_online(swarm: &mut Swarm<BlockchainBehaviour>, node_state: &mut NodeState, peer: PeerId) {
+    if let Some(offline_since) = node_state.mark_peer_online(&peer) {
+        report!("{} rejoined after {}s offline", peer, (Utc::now() - offline_since).num_seconds());
+        communication::publish_message(swarm, BlockchainMessage::Hello(Hello::local()));
+    }
+}
+
+// Merges a settlement's approvals with those collected so far. Once enough parties approved it,
+// the settlement moves to the mempool and true is returned.
+pub fn collect_approval(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>,
+    node_state: &mut NodeState, settlement: Transaction,
+) -> Result<bool, TransactionValidationError> {
+    let lock_id = match settlement.contract() {
+        Some(contract) if contract.needs_approvals() => contract.lock_id().unwrap_or_default(),
+        _ => return Err(TransactionValidationError::BadContract)
+    };
+    // every node completes the settlement on its own, only the first one reaching the mempool counts
+    let completed = transactions.uncommitted_data().iter()
+        .any(|pending| pending.contract().as_ref().and_then(Contract::lock_id) == Some(lock_id));
+    if completed {
+        return Ok(true);
+    }
+    let validator = TransactionValidator::new(wallets, transactions);
+    let mut received = settlement;
+    let approvals = validator.party_approvals(&received);
+    received.take_approvals();
+    if approvals.is_empty() {
+        return Err(validator.transaction_valid(&received).err().unwrap_or(TransactionValidationError::BadSignature));
+    }
+    for approval in approvals {
+        received.add_approval(approval);
+    }
+    let merged = node_state.approvals().merged(&received);
+    match validator.transaction_valid(&merged) {
+        Ok(_) => {
+            node_state.approvals_mut().remove(&merged);
+            transactions.add_uncommitted(merged);
+            Ok(true)
+        }
+        Err(TransactionValidationError::MissingApprovals { .. }) => {
+            node_state.approvals_mut().store(merged, Utc::now());
+            Ok(false)
+        }
+        Err(error) => Err(error)
+    }
+}
+
+fn start_forging_round(
+    swarm: &mut Swarm<BlockchainBehaviour>,
+    transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>,
+    node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+    forger: PeerId, bid: Transaction,
+) {
+    let stakes_block = match BlockCandidate::create_new(
+        vec![bid.clone()], stakes.last_block(),
+    ) {
+        Ok(block) => block,
+        Err(_) => panic!("No genesis block")
+    };
 
-        if winner.eq(&node_state.node_id) {
-            match try_forge_block(transactions) {
+    // the winning bid is bonded from here on, the others never locked anything
+    stakes.submit_new_block(stakes_block);
+
+    if forger.eq(&node_state.node_id) {
+        forge_registered_chains(swarm, node_state);
+        // pending registrations go first, transfers from new wallets depend on them
+        if !wallets.uncommitted_data().is_empty() {
+            let block_size = wallets.data_units_per_block();
+            let forged = try_forge_block(wallets, block_size, true, vec![])
+                .and_then(|block| node_state.rules().wallets().block_valid(&block).map(|_| block));
+            match forged {
+                Ok(block_candidate) => communication::publish_message(
+                    swarm,
+                    BlockchainMessage::SubmitWalletBlock {
+                        block_dto: BlockDto::from(block_candidate)
+                    },
+                ),
+                Err(error) => report!("{}", error.message())
+            }
+        } else {
+            let reward_address = node_state.node_bid().transaction().source_address();
+            let schedule = node_state.governance().schedule(&UPGRADE_SCHEDULE, transactions);
+            let forged = try_forge_transaction_block(
+                transactions, stakes, reward_address, &schedule, node_state.rules().transactions(), node_state.block_interval(),
+            );
+            match forged {
                 Ok(block_candidate) => {
                     communication::publish_message(
                         swarm,
@@ -121,48 +904,315 @@ fn on_stake_raised(
                         },
                     )
                 }
-                Err(error) => println!("{}", error.message())
+                Err(error) => report!("{}", error.message())
             }
         }
-        node_state.set_block_creator(winner.clone());
-        node_state.reset_peer_bids();
     }
+    node_state.set_block_creator(forger);
+}
+
+// rules the network's genesis adds, checked once the built-in validators accept the block
+fn rule_broken<T>(rules: &ChainRules<T>, block: &BlockCandidate<T>) -> Option<RejectionReason> where T: BlockchainData {
+    rules.block_valid(block).err().map(|error| RejectionReason::RuleBroken(error.message()))
+}
+
+// no reason means the block is valid, the node's own vote counts in its validator stats as well;
+// the peer threshold only keeps the node out of new rounds, a round it is already part of waits
+// for its vote, so it votes whatever the number of peers
+fn publish_vote(swarm: &mut Swarm<BlockchainBehaviour>, node_state: &mut NodeState, reason: Option<RejectionReason>) {
+    let wallet = node_state.wallet_address();
+    node_state.validator_stats_mut().voted(wallet);
+    communication::publish_message(swarm, BlockchainMessage::Vote {
+        block_valid: reason.is_none(),
+        reason,
+    });
 }
 
 fn on_vote_received(
     swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
-    sending_peer: PeerId, node_state: &mut NodeState, block_valid: bool,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState,
+    stakes: &mut Blockchain<Transaction>, vote: Vote,
 ) {
-    let vote = Vote::new(sending_peer, block_valid);
-    node_state.add_vote(vote);
+    let sending_peer = vote.id();
+    if !node_state.add_vote(vote) {
+        report!("Ignoring repeated or unsolicited vote from {}", sending_peer);
+        return;
+    }
+    if let Voter::Wallet(wallet) = node_state.voter(&sending_peer) {
+        node_state.validator_stats_mut().voted(wallet);
+    }
+    settle_if_quorum(swarm, transactions, wallets, node_state, stakes);
+}
 
-    if node_state.all_voted(swarm.connected_peers().count()) {
-        let result = node_state.summarize_votes();
-        if result.should_append_block() {
-            let block_candidate = node_state.take_pending_block().unwrap();
-            transactions.submit_new_block(block_candidate);
+// closes the voting once enough of the expected wallets voted, also after one went offline
+fn settle_if_quorum(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState, stakes: &mut Blockchain<Transaction>,
+) {
+    let connected: Vec<PeerId> = swarm.connected_peers().copied().collect();
+    let registry = StakeRegistry::derive(stakes, transactions);
+    if !node_state.voting_quorum(&connected, &registry) {
+        return;
+    }
+    node_state.record_participation(&connected);
+    let result = node_state.summarize_votes(&registry);
+    if !result.should_append_block() {
+        slash_forger(swarm, transactions, wallets, node_state, stakes);
+    }
+    let checkpoint = node_state.checkpoint_reached(&connected, &registry);
+    match settle_round(transactions, wallets, node_state, &result, checkpoint) {
+        Settlement::Appended { block_number, tx_hashes: Some(tx_hashes) } => {
+            if node_state.any_peer_supports(Feature::Receipts) && node_state.mark_receipt(block_number, transactions.chain_length()) {
+                communication::publish_message(swarm, BlockchainMessage::BlockAppended {
+                    height: block_number,
+                    tx_hashes,
+                });
+            }
+        }
+        Settlement::Appended { .. } => {}
+        Settlement::Rejected { next_forger: Some((forger, bid)) } => {
+            report!("Re-proposing round with forger {}", forger);
+            node_state.rounds_mut().repropose(forger, Utc::now());
+            node_state.validator_stats_mut().elected(bid.source_address());
+            start_forging_round(swarm, transactions, wallets, node_state, stakes, forger, *bid);
+        }
+        Settlement::Rejected { next_forger: None } => report!("Round abandoned, no fallback forger left"),
+        Settlement::Unsettled => {}
+    }
+}
+
+pub(crate) enum Settlement {
+    // transaction hashes of an appended transaction block, none for a wallet block
+    Appended { block_number: u64, tx_hashes: Option<Vec<String>> },
+    Rejected { next_forger: Option<(PeerId, Box<Transaction>)> },
+    // the votes had no block to append or forger to hold a rejection against
+    Unsettled,
+}
+
+// What the votes decide for the round's block, the same on every node: an approved block joins
+// its chain, a rejected one goes back to the mempool and the next fallback forger is drawn.
+// Whatever has to be published about it is left to the caller.
+pub(crate) fn settle_round(
+    transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>, node_state: &mut NodeState,
+    result: &VotingResult, checkpoint: bool,
+) -> Settlement {
+    let block_hash = match (node_state.pending_wallet_block(), node_state.pending_block()) {
+        (Some(wallet_block), _) => Some(wallet_block.key().hash()),
+        (None, Some(block)) => Some(block.key().hash()),
+        (None, None) => None,
+    };
+    if result.should_append_block() {
+        let (block_number, tx_hashes) = if let Some(wallet_block) = node_state.take_pending_wallet_block() {
+            let added = wallets.submit_new_block(wallet_block);
+            report!("Registered wallets in block {}", added.block_number());
+            node_state.validator_stats_mut().forged(&[]);
+            (added.block_number(), None)
+        } else if let Some(block_candidate) = node_state.take_pending_block() {
+            let tx_hashes = block_candidate.data().iter().map(Transaction::id).collect();
+            node_state.validator_stats_mut().forged(block_candidate.data());
+            let added = transactions.submit_new_block(block_candidate);
+            if checkpoint {
+                node_state.set_checkpoint(added.block_number());
+            }
+            settle_governance(node_state, transactions);
+            (added.block_number(), Some(tx_hashes))
         } else {
-            node_state.mark_creator_bad().unwrap();
+            // the forger publishes its block without keeping it, it catches up on the next sync
+            node_state.clear_votes();
+            return Settlement::Unsettled;
+        };
+        node_state.rounds_mut().settle(block_hash, result, RoundOutcome::Appended { block_number }, Utc::now());
+        node_state.clear_votes();
+        return Settlement::Appended { block_number, tx_hashes };
+    }
+    if node_state.mark_creator_bad().is_err() {
+        node_state.clear_votes();
+        return Settlement::Unsettled;
+    }
+    if node_state.block_creator() == Some(node_state.node_id()) {
+        report!("Our block was {}", result.diagnosis());
+    } else {
+        report!("Block {}", result.diagnosis());
+    }
+    let reason = result.reasons().first().map(|(reason, _)| reason.message());
+    node_state.rounds_mut().settle(block_hash, result, RoundOutcome::Rejected { reason }, Utc::now());
+    node_state.validator_stats_mut().missed();
+    node_state.clear_votes();
+    node_state.take_pending_wallet_block();
+    if let Some(rejected) = node_state.take_pending_block() {
+        let dropped = mempool::requeue(transactions, wallets, node_state.orphans_mut(), rejected.data().clone());
+        if dropped > 0 {
+            report!("Dropped {} invalid transactions from the rejected block", dropped);
+        }
+    }
+    Settlement::Rejected { next_forger: node_state.next_forger().map(|(forger, bid)| (forger, Box::new(bid))) }
+}
+
+// matches announced transactions against the local chain, or the mempool if the block is not here yet
+fn notify_incoming_payments(
+    transactions: &Blockchain<Transaction>, wallet_address: Address, height: u64, tx_hashes: &[String],
+) {
+    let (candidates, committed) = match transactions.block_at(height) {
+        Ok(Some(block)) => (block.data().clone(), true),
+        Ok(None) => (transactions.uncommitted_data().to_vec(), false),
+        Err(error) => {
+            report!("{}", error.message());
+            return;
         }
+    };
+    candidates.into_iter()
+        .filter(|transaction| transaction.target_address() == wallet_address)
+        .filter(|transaction| tx_hashes.contains(&transaction.id()))
+        .for_each(|transaction| transactions.notify_incoming(height, transaction, committed));
+}
+
+// registered chains ride along every round, each gets a block if it has pending data
+fn forge_registered_chains(swarm: &mut Swarm<BlockchainBehaviour>, node_state: &mut NodeState) {
+    let names = node_state.chains().names();
+    for name in names {
+        let chain = node_state.chains_mut().get_mut(name).expect("Registered chain");
+        let block = match chain.forge_block() {
+            None => continue,
+            Some(block) => block,
+        };
+        if let Err(error) = chain.append_block(block.clone()) {
+            report!("{}", error.message());
+            continue;
+        }
+        save_registered_chain(node_state, name);
+        communication::publish_message(swarm, BlockchainMessage::ChainBlock {
+            chain: name.to_string(),
+            block,
+        });
     }
 }
 
+fn save_registered_chain(node_state: &NodeState, name: &str) {
+    if let Err(error) = node_state.chains().save(name) {
+        report!("Could not save the {} chain: {}", name, error.message());
+    }
+}
+
+// the last stakes block holds the bid of this round's forger
+fn round_forger(stakes: &Blockchain<Transaction>) -> Option<Address> {
+    stakes.last_block()
+        .as_ref()
+        .and_then(|block| block.data().first())
+        .map(Transaction::source_address)
+}
+
+// the forger loses the bond of this round's bid, burned from its balance by a penalty the next
+// block carries
+fn slash_forger(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &mut NodeState, stakes: &Blockchain<Transaction>,
+) {
+    let forger = match round_forger(stakes) {
+        None => return,
+        Some(forger) => forger,
+    };
+    let epoch = stakes.chain_length() - 1;
+    let slashed = StakeRegistry::derive(stakes, transactions)
+        .unpenalized(transactions.uncommitted_data(), forger, epoch);
+    if slashed <= 0 {
+        return;
+    }
+    submit_penalty(swarm, transactions, wallets, node_state, stakes, Transaction::penalty(
+        forger, slashed, epoch, "Slash", penalty_time(stakes),
+    ));
+    node_state.validator_stats_mut().slashed(forger, slashed);
+    report!("Slashed {} of {}", slashed, access::encode_address(forger));
+    stakes.notify_slashed(forger, slashed);
+}
+
+// every node sees the same misbehaviour and dates the penalty by the stakes chain, so they all
+// build the same transaction and the mempool keeps one of them
+fn penalty_time(stakes: &Blockchain<Transaction>) -> DateTime<Utc> {
+    stakes.last_block()
+        .as_ref()
+        .and_then(|block| block.time())
+        .unwrap_or_default()
+}
+
+fn submit_penalty(
+    swarm: &mut Swarm<BlockchainBehaviour>, transactions: &mut Blockchain<Transaction>,
+    wallets: &Blockchain<Wallet>, node_state: &mut NodeState, stakes: &Blockchain<Transaction>, penalty: Transaction,
+) {
+    if mempool::merge(transactions, wallets, stakes, node_state.orphans_mut(), vec![penalty.clone()]) > 0 {
+        communication::publish_message(swarm, BlockchainMessage::SubmitTransaction(penalty));
+    }
+}
+
+pub fn try_forge_transaction_block(
+    transactions: &mut Blockchain<Transaction>, stakes: &Blockchain<Transaction>, reward_address: Address,
+    schedule: &UpgradeSchedule, chain_rules: &ChainRules<Transaction>, block_interval: Duration,
+) -> Result<BlockCandidate<Transaction>, Box<dyn BlockchainError>> {
+    let rules = schedule.rules_at(transactions.chain_length());
+    let reward = transactions.mintable(rules.block_reward());
+    let partial_allowed = interval_elapsed(transactions, block_interval);
+    let units = forgeable_units(transactions, rules.block_size(), partial_allowed)?;
+    let mut block_data = contract::keep_grants_within(
+        contract::keep_linked_sponsorships(transactions, &transactions.uncommitted_data()[..units]),
+        rules.grants_per_block(),
+    );
+    if block_data.is_empty() {
+        return Err(Box::new(TransactionCountError::new(rules.block_size(), 0)));
+    }
+    let payout = transactions.accumulated_fees(&block_data);
+    if reward > 0 {
+        block_data.push(Transaction::new(
+            MINTING_WALLET_ADDRESS, reward_address, "Reward".to_string(), reward, Utc::now(),
+        ));
+    }
+    if payout > 0 {
+        block_data.push(Transaction::fee_payout(reward_address, payout));
+    }
+    if rules.canonical_order() {
+        block_data.sort_by(Transaction::canonical_cmp);
+    }
+    let state_root = rules.state_roots().then(|| snapshot::account_state_root(transactions, &block_data, stakes));
+    let block_candidate = BlockCandidate::create_new(block_data, transactions.last_block())?;
+    let block_candidate = match state_root {
+        None => block_candidate,
+        Some(state_root) => block_candidate.with_state_root(state_root),
+    };
+    // peers would vote a block breaking the genesis rules down, it is not proposed at all
+    chain_rules.block_valid(&block_candidate)?;
+    Ok(block_candidate)
+}
+
+// a quiet network still gets its transactions in, just not sooner than a busy one would
+fn interval_elapsed<T>(blockchain: &Blockchain<T>, block_interval: Duration) -> bool where T: BlockchainData {
+    match blockchain.last_block().as_ref().and_then(|block| block.time()) {
+        None => true,
+        Some(time) => (Utc::now() - time).to_std().is_ok_and(|elapsed| elapsed >= block_interval)
+    }
+}
+
+// takes up to max_units pending entries, fewer only if partially filled blocks are allowed
 fn try_forge_block<T>(
-    blockchain: &mut Blockchain<T>
+    blockchain: &mut Blockchain<T>, max_units: u64, partial_allowed: bool, mut additional_data: Vec<T>,
 ) -> Result<BlockCandidate<T>, Box<dyn BlockchainError>> where T: BlockchainData {
+    let units = forgeable_units(blockchain, max_units, partial_allowed)?;
+    let mut to_commit = blockchain.uncommitted_data()[..units].to_vec();
+    to_commit.append(&mut additional_data);
+    BlockCandidate::create_new(
+        to_commit, blockchain.last_block(),
+    )
+}
+
+// pending units the next block takes, a partial block only once partial blocks are allowed
+fn forgeable_units<T>(
+    blockchain: &Blockchain<T>, max_units: u64, partial_allowed: bool,
+) -> Result<usize, Box<dyn BlockchainError>> where T: BlockchainData {
     let data = blockchain.uncommitted_data();
-    let required_units = blockchain.data_units_per_block();
-    if data.len() < required_units as usize {
+    if data.is_empty() || (data.len() < max_units as usize && !partial_allowed) {
         return Err(Box::new(
             TransactionCountError::new(
-                required_units, data.len() as u64,
+                max_units, data.len() as u64,
             )));
-    } else {
-        let to_commit = &data[..blockchain.data_units_per_block() as usize].to_vec();
-        BlockCandidate::create_new(
-            to_commit.clone(), blockchain.last_block(),
-        )
     }
+    Ok(std::cmp::min(max_units as usize, data.len()))
 }
 
 pub fn submit_transaction(
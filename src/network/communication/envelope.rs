@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::blockchain::core::BlockchainError;
+use crate::network::capability::PROTOCOL_VERSION;
+use crate::network::communication::BlockchainMessage;
+
+pub enum DecodeError {
+    // a message kind added after this node's version, ignored like unknown features
+    UnknownKind { kind: String, version: u32 },
+    Malformed(String),
+}
+
+impl BlockchainError for DecodeError {
+    fn message(&self) -> String {
+        match self {
+            DecodeError::UnknownKind { kind, version } => {
+                format!("Unknown message kind {} of protocol version {}", kind, version)
+            }
+            DecodeError::Malformed(error) => format!("Malformed message: {}", error),
+        }
+    }
+}
+
+// Messages travel wrapped with the sender's protocol version and their kind, so a node can tell
+// a message it does not know from a broken one. Nodes of protocol version 1 sent the message
+// bare, those are still read so their hello can be refused.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    kind: String,
+    // none for messages without fields, e.g. SyncRequest
+    #[serde(default)]
+    payload: Value,
+}
+
+pub fn encode(message: &BlockchainMessage) -> String {
+    let (kind, payload) = match serde_json::to_value(message).unwrap() {
+        Value::String(kind) => (kind, Value::Null),
+        Value::Object(tagged) => tagged.into_iter().next().expect("Tagged message"),
+        _ => unreachable!("Messages serialize as their kind tagging the payload")
+    };
+    serde_json::to_string(&Envelope {
+        version: PROTOCOL_VERSION,
+        kind,
+        payload,
+    }).unwrap()
+}
+
+pub fn decode(data: &[u8]) -> Result<BlockchainMessage, DecodeError> {
+    let value: Value = serde_json::from_slice(data).map_err(malformed)?;
+    // no message kind is called version, a bare message never has the field
+    if value.get("version").is_none() {
+        return serde_json::from_value(value).map_err(malformed);
+    }
+    let envelope: Envelope = serde_json::from_value(value).map_err(malformed)?;
+    let tagged = match envelope.payload {
+        Value::Null => Value::String(envelope.kind.clone()),
+        payload => Value::Object(Map::from_iter([(envelope.kind.clone(), payload)])),
+    };
+    serde_json::from_value(tagged).map_err(|error| {
+        // serde names the variant it could not match, fields newer nodes added are ignored anyway
+        match error.to_string().starts_with("unknown variant") {
+            true => DecodeError::UnknownKind {
+                kind: envelope.kind,
+                version: envelope.version,
+            },
+            false => malformed(error),
+        }
+    })
+}
+
+fn malformed(error: serde_json::Error) -> DecodeError {
+    DecodeError::Malformed(error.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::core::BlockchainError;
+    use crate::network::capability::{Hello, PROTOCOL_VERSION};
+    use crate::network::communication::BlockchainMessage;
+    use crate::network::communication::envelope::{self, DecodeError};
+
+    // published by nodes of protocol version 1, before envelopes
+    static VERSION_1: &[&str] = &[
+        r#""SyncRequest""#,
+        r#"{"ChainHeight":7}"#,
+        r#"{"SyncFrom":"12D3KooWQYhTNQdmr3ArTeUHRYzFg94BKyTkoWBDWez9kSCVe2Xo"}"#,
+        r#"{"Vote":{"block_valid":false}}"#,
+        r#"{"MempoolDigest":["ab","cd"]}"#,
+        r#"{"BlockAppended":{"height":3,"tx_hashes":["ab"]}}"#,
+    ];
+
+    static VERSION_2: &[&str] = &[
+        r#"{"version":2,"kind":"SyncRequest","payload":null}"#,
+        r#"{"version":2,"kind":"SyncRequest"}"#,
+        r#"{"version":2,"kind":"ChainHeight","payload":7}"#,
+        r#"{"version":2,"kind":"Vote","payload":{"block_valid":true,"reason":null}}"#,
+        r#"{"version":2,"kind":"HeadersRequest","payload":{"peer":"12D3KooW","count":32}}"#,
+    ];
+
+    #[test]
+    fn decodes_messages_of_every_previous_version() {
+        for fixture in VERSION_1.iter().chain(VERSION_2) {
+            if let Err(error) = envelope::decode(fixture.as_bytes()) {
+                panic!("{} not decoded: {}", fixture, error.message());
+            }
+        }
+        assert!(matches!(envelope::decode(VERSION_1[1].as_bytes()), Ok(BlockchainMessage::ChainHeight(7))));
+        assert!(matches!(
+            envelope::decode(VERSION_1[3].as_bytes()),
+            Ok(BlockchainMessage::Vote { block_valid: false, reason: None })
+        ));
+    }
+
+    #[test]
+    fn version_1_hellos_are_read_and_refused() {
+        let hello = r#"{"Hello":{"protocol_version":1,"features":["ChainSync","MempoolSync"]}}"#;
+        match envelope::decode(hello.as_bytes()) {
+            Ok(BlockchainMessage::Hello(hello)) => assert!(!hello.compatible()),
+            _ => panic!("version 1 hello not decoded"),
+        }
+        assert!(Hello::local().compatible());
+    }
+
+    #[test]
+    fn encoded_messages_decode_to_the_same_message() {
+        let messages = [
+            BlockchainMessage::SyncRequest,
+            BlockchainMessage::ChainHeight(9),
+            BlockchainMessage::MempoolRequest(vec!["ab".to_string()]),
+        ];
+        for message in messages {
+            let encoded = envelope::encode(&message);
+            assert!(encoded.starts_with(&format!(r#"{{"version":{},"#, PROTOCOL_VERSION)));
+            let decoded = envelope::decode(encoded.as_bytes()).ok().unwrap();
+            assert_eq!(envelope::encode(&decoded), encoded);
+        }
+    }
+
+    #[test]
+    fn newer_messages_are_told_from_broken_ones() {
+        let newer = r#"{"version":3,"kind":"Attestation","payload":{"slot":4}}"#;
+        assert!(matches!(
+            envelope::decode(newer.as_bytes()),
+            Err(DecodeError::UnknownKind { version: 3, .. })
+        ));
+        // fields added by newer nodes to known kinds are ignored
+        let extended = r#"{"version":3,"kind":"Vote","payload":{"block_valid":true,"weight":2}}"#;
+        assert!(envelope::decode(extended.as_bytes()).is_ok());
+
+        for broken in [r#"{"version":2,"kind":"ChainHeight","payload":"tall"}"#, r#"{"Vote""#, r#"{"Bid":4}"#] {
+            assert!(matches!(envelope::decode(broken.as_bytes()), Err(DecodeError::Malformed(_))));
+        }
+    }
+}
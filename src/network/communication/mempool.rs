@@ -0,0 +1,322 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{access, Transaction, TransactionValidator, Wallet};
+use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, REWARD_WALLET_ADDRESS};
+use crate::blockchain::contract::Contract;
+use crate::blockchain::core::Blockchain;
+use crate::display::table::{Color, Table};
+use crate::network::communication::orphan::OrphanPool;
+
+// A pending transaction as the mempool command lists it, with the fee paid along with it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PendingTransaction {
+    transaction: Transaction,
+    fee: i64,
+    age_seconds: i64,
+    // forgers take pending transactions in order, up to the block size
+    fits_next_block: bool,
+}
+
+impl PendingTransaction {
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn fee(&self) -> i64 {
+        self.fee
+    }
+
+    pub fn age_seconds(&self) -> i64 {
+        self.age_seconds
+    }
+
+    pub fn fits_next_block(&self) -> bool {
+        self.fits_next_block
+    }
+}
+
+// every row still waits to be committed, colored as pending
+pub fn table(pending: &[PendingTransaction]) -> Table {
+    let mut table = Table::new(&["Id", "From", "To", "Amount", "Fee", "Age", "Next block"]).with_numeric(&[3, 4, 5]);
+    for entry in pending {
+        let transaction = &entry.transaction;
+        table.push(vec![
+            transaction.id()[..16].to_string(),
+            access::encode_address(transaction.source_address()),
+            access::encode_address(transaction.target_address()),
+            transaction.amount().to_string(),
+            entry.fee.to_string(),
+            format!("{}s", entry.age_seconds),
+            String::from(if entry.fits_next_block { "yes" } else { "no" }),
+        ], Some(Color::Yellow));
+    }
+    table
+}
+
+// fee transactions are not listed on their own but folded into the transfer they pay for, the
+// one from the same sender a nonce before or the one a sponsor pays for
+pub fn pending(transactions: &Blockchain<Transaction>, block_size: u64, now: DateTime<Utc>) -> Vec<PendingTransaction> {
+    let data = transactions.uncommitted_data();
+    let is_fee = |transaction: &Transaction| {
+        transaction.target_address() == *REWARD_WALLET_ADDRESS && !transaction.nonce_exempt()
+    };
+    let fits = |position: usize| position < block_size as usize;
+    data.iter()
+        .enumerate()
+        .filter(|(_, transaction)| !is_fee(transaction))
+        .map(|(position, transaction)| {
+            let fee = data.iter().position(|fee| {
+                let sponsored = fee.contract().as_ref().and_then(Contract::sponsored_transfer);
+                is_fee(fee) && match sponsored {
+                    Some(transfer_id) => transfer_id == transaction.id(),
+                    None => fee.source_address() == transaction.source_address() && fee.nonce() == transaction.nonce() + 1,
+                }
+            });
+            PendingTransaction {
+                transaction: transaction.clone(),
+                fee: fee.map_or(0, |fee| data[fee].amount()),
+                age_seconds: (now - transaction.time()).num_seconds().max(0),
+                fits_next_block: fits(position) && fee.is_none_or(fits),
+            }
+        })
+        .collect()
+}
+
+pub fn digest(transactions: &Blockchain<Transaction>) -> Vec<String> {
+    transactions.uncommitted_data()
+        .iter()
+        .map(Transaction::id)
+        .collect()
+}
+
+pub fn missing(transactions: &Blockchain<Transaction>, remote_digest: &[String]) -> Vec<String> {
+    let known = known_ids(transactions);
+    remote_digest.iter()
+        .filter(|id| !known.contains(*id))
+        .cloned()
+        .collect()
+}
+
+pub fn collect(transactions: &Blockchain<Transaction>, requested: &[String]) -> Vec<Transaction> {
+    let requested: HashSet<&String> = requested.iter().collect();
+    transactions.uncommitted_data()
+        .iter()
+        .filter(|transaction| requested.contains(&transaction.id()))
+        .cloned()
+        .collect()
+}
+
+// only transactions a validator would accept are kept, parked orphans included, so a peer
+// cannot fill the mempool with transfers no block may carry
+pub fn merge(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, stakes: &Blockchain<Transaction>,
+    orphans: &mut OrphanPool, received: Vec<Transaction>,
+) -> usize {
+    let mut known = known_ids(transactions);
+    let mut merged = 0;
+    for transaction in received {
+        if known.contains(&transaction.id()) || !admissible(transactions, wallets, stakes, &transaction) {
+            continue;
+        }
+        merged += admit(transactions, orphans, &mut known, transaction);
+    }
+    merged
+}
+
+// puts the transfers and grants of a voted down block back up for the next forger, dropping
+// those that can never become valid, rewards and fee payouts are recreated by whoever forges next
+pub fn requeue(
+    transactions: &mut Blockchain<Transaction>, wallets: &Blockchain<Wallet>, orphans: &mut OrphanPool,
+    rejected: Vec<Transaction>,
+) -> usize {
+    let mut known = known_ids(transactions);
+    let mut invalid = vec![];
+    for transaction in rejected {
+        let source_address = transaction.source_address();
+        if (source_address == MINTING_WALLET_ADDRESS && !transaction.is_grant()) || source_address == *REWARD_WALLET_ADDRESS {
+            continue;
+        }
+        if TransactionValidator::new(wallets, transactions).transaction_valid(&transaction).is_err() {
+            invalid.push(transaction);
+        } else if known.contains(&transaction.id()) {
+            continue;
+        } else if stale(transactions, &transaction) {
+            invalid.push(transaction);
+        } else {
+            admit(transactions, orphans, &mut known, transaction);
+        }
+    }
+    transactions.discard_uncommitted(&invalid);
+    invalid.len()
+}
+
+// penalties are checked against the bonds they burn, deriving those only pays off for them
+fn admissible(
+    transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>, stakes: &Blockchain<Transaction>,
+    transaction: &Transaction,
+) -> bool {
+    let validator = TransactionValidator::new(wallets, transactions);
+    let validator = match transaction.is_penalty() {
+        true => validator.with_stakes(stakes),
+        false => validator,
+    };
+    validator.transaction_valid(transaction).is_ok()
+}
+
+// Exempt transactions go straight in, the others in nonce order, those ahead of their sender wait
+// among the orphans until the gap fills. Returns how many entered the mempool.
+fn admit(
+    transactions: &mut Blockchain<Transaction>, orphans: &mut OrphanPool, known: &mut HashSet<String>,
+    transaction: Transaction,
+) -> usize {
+    if transaction.nonce_exempt() {
+        known.insert(transaction.id());
+        transactions.add_uncommitted(transaction);
+        return 1;
+    }
+    let source_address = transaction.source_address();
+    let next_nonce = transactions.next_nonce(source_address);
+    if transaction.nonce() > next_nonce {
+        orphans.park(transaction, Utc::now());
+        return 0;
+    }
+    if transaction.nonce() < next_nonce {
+        return 0;
+    }
+    let mut admitted = 0;
+    let promoted = orphans.promote(source_address, next_nonce + 1);
+    for transaction in std::iter::once(transaction).chain(promoted) {
+        known.insert(transaction.id());
+        transactions.add_uncommitted(transaction);
+        admitted += 1;
+    }
+    admitted
+}
+
+// its sender already committed or queued a transaction with the nonce, it can never be valid again
+fn stale(transactions: &Blockchain<Transaction>, transaction: &Transaction) -> bool {
+    !transaction.nonce_exempt() && transaction.nonce() < transactions.next_nonce(transaction.source_address())
+}
+
+fn known_ids(transactions: &Blockchain<Transaction>) -> HashSet<String> {
+    transactions.uncommitted_data()
+        .iter()
+        .map(Transaction::id)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::blockchain::{Transaction, Wallet};
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, REWARD_WALLET_ADDRESS};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::network::communication::mempool;
+    use crate::network::communication::mempool::PendingTransaction;
+    use crate::network::communication::orphan::OrphanPool;
+    use crate::random;
+
+    #[test]
+    fn converges_on_missing_transactions() {
+        let mut rng = random::seeded(32);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut first = Transaction::new(sender.address(), recipient.address(), "first".to_string(), 5, Utc::now());
+        sender.sign(&mut first, &mut rng);
+        let mut second = Transaction::new(sender.address(), recipient.address(), "second".to_string(), 3, Utc::now());
+        second.set_nonce(1);
+        sender.sign(&mut second, &mut rng);
+        let genesis = vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 10, Utc::now())
+        ];
+        let stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut local = Blockchain::<Transaction>::transaction_chain(genesis.clone());
+        let mut remote = Blockchain::<Transaction>::transaction_chain(genesis);
+        local.add_uncommitted(first.clone());
+        remote.add_uncommitted(first);
+        remote.add_uncommitted(second.clone());
+
+        let requested = mempool::missing(&local, &mempool::digest(&remote));
+        assert_eq!(requested, vec![second.id()]);
+
+        let received = mempool::collect(&remote, &requested);
+        let mut orphans = OrphanPool::new();
+        assert_eq!(mempool::merge(&mut local, &wallets, &stakes, &mut orphans, received.clone()), 1);
+        assert_eq!(mempool::merge(&mut local, &wallets, &stakes, &mut orphans, received), 0);
+        assert_eq!(mempool::digest(&local), mempool::digest(&remote));
+
+        // no block could carry an unsigned transfer, so it is not even parked
+        let mut unsigned = Transaction::new(sender.address(), recipient.address(), "".to_string(), 1, Utc::now());
+        unsigned.set_nonce(5);
+        assert_eq!(mempool::merge(&mut local, &wallets, &stakes, &mut orphans, vec![unsigned]), 0);
+        assert_eq!(orphans.size(), 0);
+    }
+
+    #[test]
+    fn lists_transfers_with_their_fees_and_what_fits_the_next_block() {
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        for (nonce, amount) in [(0, 5), (2, 7)] {
+            let mut transfer = Transaction::new([1; 32], [2; 32], "".to_string(), amount, Utc::now());
+            transfer.set_nonce(nonce);
+            let mut fee = Transaction::fee([1; 32], 2);
+            fee.set_nonce(nonce + 1);
+            transactions.add_uncommitted(transfer);
+            transactions.add_uncommitted(fee);
+        }
+
+        let pending = mempool::pending(&transactions, 3, Utc::now() + Duration::seconds(30));
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending.iter().map(PendingTransaction::fee).collect::<Vec<i64>>(), vec![2, 2]);
+        assert!(pending[0].age_seconds() >= 30);
+        // the second transfer would make it in, its fee would not
+        assert!(pending[0].fits_next_block());
+        assert!(!pending[1].fits_next_block());
+    }
+
+    #[test]
+    fn requeue_returns_valid_transfers_and_counts_the_dropped_ones() {
+        let mut rng = random::seeded(31);
+        let sender = HotWallet::generate(&mut rng);
+        let recipient = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(
+            vec![sender.wallet().clone(), recipient.wallet().clone()], wallets.last_block(),
+        ).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, sender.address(), "".to_string(), 100, Utc::now())
+        ]);
+        let mut transfer = Transaction::new(sender.address(), recipient.address(), "".to_string(), 5, Utc::now());
+        sender.sign(&mut transfer, &mut rng);
+        let unsigned = Transaction::new(sender.address(), recipient.address(), "".to_string(), 7, Utc::now());
+        let reward = Transaction::new(MINTING_WALLET_ADDRESS, *REWARD_WALLET_ADDRESS, "Reward".to_string(), 3, Utc::now());
+        let mut orphans = OrphanPool::new();
+
+        let dropped = mempool::requeue(&mut transactions, &wallets, &mut orphans, vec![reward, transfer.clone(), unsigned]);
+        assert_eq!(dropped, 1);
+        assert_eq!(mempool::digest(&transactions), vec![transfer.id()]);
+        // requeueing the same block again does not duplicate what is pending
+        assert_eq!(mempool::requeue(&mut transactions, &wallets, &mut orphans, vec![transfer]), 0);
+        assert_eq!(transactions.uncommitted_data().len(), 1);
+
+        // another transfer at a nonce already taken is dropped, one ahead of its sender waits
+        let mut stale = Transaction::new(sender.address(), recipient.address(), "stale".to_string(), 5, Utc::now());
+        sender.sign(&mut stale, &mut rng);
+        let mut ahead = Transaction::new(sender.address(), recipient.address(), "ahead".to_string(), 5, Utc::now());
+        ahead.set_nonce(2);
+        sender.sign(&mut ahead, &mut rng);
+        assert_eq!(mempool::requeue(&mut transactions, &wallets, &mut orphans, vec![stale, ahead]), 1);
+        assert_eq!(transactions.uncommitted_data().len(), 1);
+        assert_eq!(orphans.size(), 1);
+    }
+}
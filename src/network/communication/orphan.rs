@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::blockchain::{Address, Transaction};
+
+pub static MAX_ORPHANS: usize = 1024;
+pub static MAX_ORPHANS_PER_ADDRESS: usize = 16;
+pub static ORPHAN_TTL_SECONDS: i64 = 600;
+
+pub struct OrphanPool {
+    parked: HashMap<Address, BTreeMap<u64, (Transaction, DateTime<Utc>)>>,
+    size: usize,
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        OrphanPool::new()
+    }
+}
+
+impl OrphanPool {
+    pub fn new() -> OrphanPool {
+        OrphanPool {
+            parked: HashMap::new(),
+            size: 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn park(&mut self, transaction: Transaction, now: DateTime<Utc>) -> bool {
+        self.expire(now);
+        if self.size >= MAX_ORPHANS {
+            return false;
+        }
+        let parked = self.parked.entry(transaction.source_address()).or_default();
+        if parked.len() >= MAX_ORPHANS_PER_ADDRESS || parked.contains_key(&transaction.nonce()) {
+            return false;
+        }
+        parked.insert(transaction.nonce(), (transaction, now));
+        self.size += 1;
+        true
+    }
+
+    pub fn promote(&mut self, address: Address, mut next_nonce: u64) -> Vec<Transaction> {
+        let mut promoted = vec![];
+        let parked = match self.parked.get_mut(&address) {
+            None => return promoted,
+            Some(parked) => parked
+        };
+        while let Some((transaction, _)) = parked.remove(&next_nonce) {
+            promoted.push(transaction);
+            next_nonce += 1;
+        }
+        // anything below the filled gap can never be promoted anymore
+        let stale = parked.range(..next_nonce).count();
+        *parked = parked.split_off(&next_nonce);
+        self.size -= promoted.len() + stale;
+        if parked.is_empty() {
+            self.parked.remove(&address);
+        }
+        promoted
+    }
+
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        let deadline = now - Duration::seconds(ORPHAN_TTL_SECONDS);
+        let mut expired = 0;
+        self.parked.retain(|_, parked| {
+            let before = parked.len();
+            parked.retain(|_, (_, parked_at)| *parked_at > deadline);
+            expired += before - parked.len();
+            !parked.is_empty()
+        });
+        self.size -= expired;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::blockchain::Transaction;
+    use crate::network::communication::orphan::{ORPHAN_TTL_SECONDS, OrphanPool};
+
+    fn transaction(nonce: u64) -> Transaction {
+        let mut transaction = Transaction::new([1; 32], [2; 32], "".to_string(), 1, Utc::now());
+        transaction.set_nonce(nonce);
+        transaction
+    }
+
+    #[test]
+    fn promotes_when_gap_fills_and_expires_stale() {
+        let now = Utc::now();
+        let mut orphans = OrphanPool::new();
+        assert!(orphans.park(transaction(2), now));
+        assert!(orphans.park(transaction(3), now));
+        assert!(!orphans.park(transaction(3), now));
+
+        assert!(orphans.promote([1; 32], 1).is_empty());
+        let promoted = orphans.promote([1; 32], 2);
+        assert_eq!(promoted.iter().map(Transaction::nonce).collect::<Vec<u64>>(), vec![2, 3]);
+        assert_eq!(orphans.size(), 0);
+
+        assert!(orphans.park(transaction(7), now));
+        orphans.expire(now + Duration::seconds(ORPHAN_TTL_SECONDS + 1));
+        assert_eq!(orphans.size(), 0);
+    }
+}
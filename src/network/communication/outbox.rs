@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::report;
+
+pub static OUTBOX_CAPACITY: usize = 256;
+pub static MAX_PUBLISH_ATTEMPTS: u32 = 8;
+static FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+static MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// what gossip adds around the payload: sender, sequence number, topic, signature and framing
+pub static GOSSIP_FRAMING: usize = 256;
+
+pub enum PublishOutcome {
+    Published,
+    // worth another try later, e.g. no peer subscribed yet
+    Retry,
+    // would fail the same way every time, e.g. too large or already published
+    Rejected,
+}
+
+struct HeldMessage {
+    payload: String,
+    attempts: u32,
+    next_attempt: Instant,
+    // bids, votes and proposed blocks only mean something in the round they were sent in
+    round: Option<u64>,
+}
+
+// Serialized messages that could not be published yet, oldest first. Each failed attempt
+// doubles the wait before the next one, messages are given up after MAX_PUBLISH_ATTEMPTS,
+// round-scoped ones as soon as their round ends.
+#[derive(Default)]
+pub struct Outbox {
+    held: VecDeque<HeldMessage>,
+    // gossip's max transmit size, peers drop framed messages larger than it
+    max_size: Option<usize>,
+    round: u64,
+}
+
+impl Outbox {
+    pub fn limit_size(&mut self, max_size: usize) {
+        self.max_size = Some(max_size);
+    }
+
+    // a full outbox makes room by dropping its oldest message, one too large once framed is refused
+    pub fn hold(&mut self, payload: String, round_scoped: bool, now: Instant) -> bool {
+        if let Some(max_size) = self.max_size {
+            let max_payload = max_size.saturating_sub(GOSSIP_FRAMING);
+            if payload.len() > max_payload {
+                report!(
+                    "Not publishing a {} byte message, peers accept at most {} bytes", payload.len(), max_payload
+                );
+                return false;
+            }
+        }
+        if self.held.len() >= OUTBOX_CAPACITY {
+            self.held.pop_front();
+            report!("Outbox full, dropped the oldest unpublished message");
+        }
+        self.held.push_back(HeldMessage {
+            payload,
+            attempts: 0,
+            next_attempt: now,
+            round: round_scoped.then_some(self.round),
+        });
+        true
+    }
+
+    pub fn end_round(&mut self) {
+        let ended = self.round;
+        self.round += 1;
+        let held = self.held.len();
+        self.held.retain(|message| message.round != Some(ended));
+        if self.held.len() < held {
+            report!("Dropped {} unpublished messages of the ended round", held - self.held.len());
+        }
+    }
+
+    // tries every message that is due, returns how many were published
+    pub fn flush<F>(&mut self, now: Instant, mut publish: F) -> usize where F: FnMut(&str) -> PublishOutcome {
+        let mut published = 0;
+        let mut kept = VecDeque::with_capacity(self.held.len());
+        while let Some(mut message) = self.held.pop_front() {
+            if message.next_attempt > now {
+                kept.push_back(message);
+                continue;
+            }
+            match publish(&message.payload) {
+                PublishOutcome::Published => published += 1,
+                PublishOutcome::Rejected => {}
+                PublishOutcome::Retry => {
+                    message.attempts += 1;
+                    if message.attempts >= MAX_PUBLISH_ATTEMPTS {
+                        report!("Gave up publishing a message after {} attempts", message.attempts);
+                        continue;
+                    }
+                    message.next_attempt = now + retry_delay(message.attempts);
+                    kept.push_back(message);
+                }
+            }
+        }
+        self.held = kept;
+        published
+    }
+
+    pub fn len(&self) -> usize {
+        self.held.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.held.is_empty()
+    }
+}
+
+fn retry_delay(attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    FIRST_RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::network::communication::outbox::{GOSSIP_FRAMING, MAX_PUBLISH_ATTEMPTS, Outbox, PublishOutcome};
+
+    #[test]
+    fn failed_publishes_back_off_until_given_up() {
+        let start = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.hold(String::from("transfer"), false, start);
+        outbox.hold(String::from("oversized"), false, start);
+
+        let rejected = |payload: &str| match payload {
+            "oversized" => PublishOutcome::Rejected,
+            _ => PublishOutcome::Retry,
+        };
+        assert_eq!(outbox.flush(start, rejected), 0);
+        assert_eq!(outbox.len(), 1);
+        // the first retry waits a second, nothing is due before that
+        assert_eq!(outbox.flush(start + Duration::from_millis(500), |_| PublishOutcome::Published), 0);
+        assert_eq!(outbox.flush(start + Duration::from_secs(1), |_| PublishOutcome::Published), 1);
+        assert!(outbox.is_empty());
+
+        outbox.hold(String::from("transfer"), false, start);
+        let mut attempts = 0;
+        let mut now = start;
+        while !outbox.is_empty() {
+            attempts += outbox.flush(now, |_| PublishOutcome::Retry) + 1;
+            now += Duration::from_secs(3600);
+        }
+        assert_eq!(attempts as u32, MAX_PUBLISH_ATTEMPTS);
+    }
+
+    #[test]
+    fn payloads_leave_room_for_the_gossip_framing() {
+        let mut outbox = Outbox::default();
+        outbox.limit_size(GOSSIP_FRAMING + 8);
+        assert!(outbox.hold(String::from("transfer"), false, Instant::now()));
+        // fits the transmit size alone, not once gossip frames it
+        assert!(!outbox.hold(String::from("oversized"), false, Instant::now()));
+        assert!(!outbox.hold("x".repeat(GOSSIP_FRAMING), false, Instant::now()));
+        assert_eq!(outbox.len(), 1);
+    }
+
+    #[test]
+    fn round_scoped_messages_are_dropped_when_their_round_ends() {
+        let start = Instant::now();
+        let mut outbox = Outbox::default();
+        outbox.hold(String::from("transfer"), false, start);
+        outbox.hold(String::from("vote"), true, start);
+        outbox.end_round();
+        outbox.hold(String::from("bid"), true, start);
+
+        let mut published = Vec::new();
+        outbox.flush(start, |payload| {
+            published.push(payload.to_string());
+            PublishOutcome::Published
+        });
+        assert_eq!(published, vec!["transfer", "bid"]);
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashSet;
+
+use crate::blockchain::{find_wallet_by_address, Wallet, WalletValidator};
+use crate::blockchain::core::{Blockchain, BlockchainError};
+use crate::report;
+
+// Pending wallet registrations and key rotations are reconciled like the mempool: peers trade
+// the hashes of the entries they hold and only fetch the ones they miss. Every entry fetched is
+// checked on its own, a peer cannot add wallets to the set by sending a list of them.
+
+pub fn digest(wallets: &Blockchain<Wallet>) -> Vec<String> {
+    wallets.uncommitted_data()
+        .iter()
+        .map(Wallet::id)
+        .collect()
+}
+
+pub fn missing(wallets: &Blockchain<Wallet>, remote_digest: &[String]) -> Vec<String> {
+    let known: HashSet<String> = digest(wallets).into_iter().collect();
+    remote_digest.iter()
+        .filter(|id| !known.contains(*id))
+        .cloned()
+        .collect()
+}
+
+pub fn collect(wallets: &Blockchain<Wallet>, requested: &[String]) -> Vec<Wallet> {
+    let requested: HashSet<&String> = requested.iter().collect();
+    wallets.uncommitted_data()
+        .iter()
+        .filter(|wallet| requested.contains(&wallet.id()))
+        .cloned()
+        .collect()
+}
+
+// a new address has to match its key, a known one has to be rotated by its current key
+pub fn entry_valid(wallets: &Blockchain<Wallet>, wallet: &Wallet) -> Result<(), Box<dyn BlockchainError>> {
+    let validator = WalletValidator::new(wallets);
+    match find_wallet_by_address(wallet.address(), wallets) {
+        None => validator.registration_valid(wallet),
+        Some(_) => validator.key_update_valid(wallet),
+    }
+}
+
+// adds the valid entries, one per address as a wallet block takes them, returns how many
+pub fn merge(wallets: &mut Blockchain<Wallet>, received: Vec<Wallet>) -> usize {
+    let mut merged = 0;
+    for wallet in received {
+        let pending = wallets.uncommitted_data().iter().any(|pending| pending.address() == wallet.address());
+        if pending {
+            continue;
+        }
+        match entry_valid(wallets, &wallet) {
+            Ok(_) => {
+                wallets.add_uncommitted(wallet);
+                merged += 1;
+            }
+            Err(error) => report!("Ignoring wallet entry: {}", error.message())
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::Wallet;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::HotWallet;
+    use crate::blockchain::core::Blockchain;
+    use crate::network::communication::registrations;
+    use crate::random;
+
+    #[test]
+    fn fetches_only_missing_entries_and_checks_each_one() {
+        let mut rng = random::seeded(11);
+        let (first, second) = (HotWallet::generate(&mut rng), HotWallet::generate(&mut rng));
+        let mut local = Blockchain::<Wallet>::wallet_chain();
+        let mut remote = Blockchain::<Wallet>::wallet_chain();
+        local.add_uncommitted(first.wallet().clone());
+        remote.add_uncommitted(first.wallet().clone());
+        remote.add_uncommitted(second.wallet().clone());
+        // a wallet claiming an address its key does not derive
+        remote.add_uncommitted(Wallet::new([7; 32], first.wallet().key().clone()));
+        remote.add_uncommitted(Wallet::new(MINTING_WALLET_ADDRESS, None));
+
+        let requested = registrations::missing(&local, &registrations::digest(&remote));
+        assert_eq!(requested.len(), 3);
+        assert!(!requested.contains(&first.wallet().id()));
+
+        let received = registrations::collect(&remote, &requested);
+        assert_eq!(registrations::merge(&mut local, received.clone()), 1);
+        assert_eq!(registrations::merge(&mut local, received), 0);
+        assert_eq!(registrations::digest(&local), vec![first.wallet().id(), second.wallet().id()]);
+    }
+}
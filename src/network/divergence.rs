@@ -0,0 +1,258 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::BlockchainData;
+use crate::blockchain::core::Blockchain;
+
+// headers asked of a peer by the diff command, a peer never sends more than the maximum
+pub static DIFF_HEADERS: u64 = 32;
+pub static MAX_DIFF_HEADERS: u64 = 256;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct BlockHeader {
+    block_number: u64,
+    block_hash: String,
+    previous_block_hash: Option<String>,
+    time: Option<DateTime<Utc>>,
+    data_units: usize,
+}
+
+impl BlockHeader {
+    pub fn block_number(&self) -> u64 {
+        self.block_number
+    }
+
+    pub fn block_hash(&self) -> &str {
+        &self.block_hash
+    }
+
+    fn describe(&self) -> String {
+        let time = self.time.map_or(String::from("no time"), |time| time.to_rfc3339());
+        format!("  {} {} {} items, {}", self.block_number, self.block_hash, self.data_units, time)
+    }
+}
+
+// the newest count blocks of a chain, oldest first, blocks that cannot be read are left out
+pub fn recent_headers<T>(chain: &Blockchain<T>, count: u64) -> Vec<BlockHeader> where T: BlockchainData {
+    chain.blocks_from(chain.chain_length().saturating_sub(count))
+        .filter_map(Result::ok)
+        .map(|block| BlockHeader {
+            block_number: block.block_number(),
+            block_hash: block.key().hash(),
+            previous_block_hash: block.key().previous_hash(),
+            time: block.time(),
+            data_units: block.data().len(),
+        })
+        .collect()
+}
+
+// The longer chain wins. Of two equally long ones the one with the lower tip hash does, so all
+// nodes settle a tie alike instead of each keeping its own branch.
+pub fn prefers_remote(local_height: u64, local_tip: Option<&str>, remote_height: u64, remote_tip: Option<&str>) -> bool {
+    match remote_height.cmp(&local_height) {
+        Ordering::Greater => true,
+        Ordering::Less => false,
+        Ordering::Equal => remote_tip.is_some_and(|remote_tip| local_tip.is_some_and(|local_tip| remote_tip < local_tip)),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ForkChoice {
+    // the peer's chain is shorter, the same or loses the tie on equal length
+    Local,
+    Remote,
+    // the peer's chain is longer but rolls back blocks this node considers final
+    Finalized,
+}
+
+// How the local chain and a peer's recent headers relate: the last block both share, the
+// blocks only one of them has above it and which chain the node would keep when syncing.
+pub struct Divergence {
+    local_height: u64,
+    remote_height: u64,
+    // none when the chains differ on every header the peer sent
+    fork_point: Option<u64>,
+    oldest_compared: u64,
+    local_only: Vec<BlockHeader>,
+    remote_only: Vec<BlockHeader>,
+    fork_choice: ForkChoice,
+}
+
+impl Divergence {
+    pub fn compare<T>(
+        local: &Blockchain<T>, remote_height: u64, remote_headers: &[BlockHeader], max_reorg_depth: u64,
+    ) -> Divergence where T: BlockchainData {
+        let oldest_compared = remote_headers.iter()
+            .map(BlockHeader::block_number)
+            .min()
+            .unwrap_or(remote_height);
+        let local_headers = recent_headers(local, local.chain_length().saturating_sub(oldest_compared));
+        let local_hashes: HashMap<u64, &str> = local_headers.iter()
+            .map(|header| (header.block_number, header.block_hash()))
+            .collect();
+        let fork_point = remote_headers.iter()
+            .filter(|header| local_hashes.get(&header.block_number) == Some(&header.block_hash()))
+            .map(BlockHeader::block_number)
+            .max();
+        let above_fork = |header: &&BlockHeader| fork_point.is_none_or(|fork_point| header.block_number > fork_point);
+        let local_only: Vec<BlockHeader> = local_headers.iter().filter(above_fork).cloned().collect();
+        let remote_only: Vec<BlockHeader> = remote_headers.iter().filter(above_fork).cloned().collect();
+
+        // without a shared header the chains share at most the blocks below the compared ones
+        let common_height = fork_point.map_or(oldest_compared, |fork_point| fork_point + 1);
+        let local_tip = local_headers.last().filter(|header| header.block_number + 1 == local.chain_length());
+        let remote_tip = remote_headers.iter().find(|header| header.block_number + 1 == remote_height);
+        let preferred = prefers_remote(
+            local.chain_length(), local_tip.map(BlockHeader::block_hash),
+            remote_height, remote_tip.map(BlockHeader::block_hash),
+        );
+        let fork_choice = if !preferred {
+            ForkChoice::Local
+        } else if common_height < local.finalized_height(max_reorg_depth) {
+            ForkChoice::Finalized
+        } else {
+            ForkChoice::Remote
+        };
+        Divergence {
+            local_height: local.chain_length(),
+            remote_height,
+            fork_point,
+            oldest_compared,
+            local_only,
+            remote_only,
+            fork_choice,
+        }
+    }
+
+    pub fn fork_point(&self) -> Option<u64> {
+        self.fork_point
+    }
+
+    pub fn local_only(&self) -> &[BlockHeader] {
+        &self.local_only
+    }
+
+    pub fn remote_only(&self) -> &[BlockHeader] {
+        &self.remote_only
+    }
+
+    pub fn fork_choice(&self) -> ForkChoice {
+        self.fork_choice
+    }
+
+    // the chains only conflict when both have blocks the other lacks
+    pub fn forked(&self) -> bool {
+        !self.local_only.is_empty() && !self.remote_only.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = vec![format!("Local height {}, peer height {}", self.local_height, self.remote_height)];
+        lines.push(match self.fork_point {
+            Some(fork_point) => format!("Last common block {}", fork_point),
+            None => format!("No common block since block {}, the fork is older", self.oldest_compared),
+        });
+        if !self.local_only.is_empty() {
+            lines.push(format!("{} blocks only on this node:", self.local_only.len()));
+            lines.extend(self.local_only.iter().map(BlockHeader::describe));
+        }
+        if !self.remote_only.is_empty() {
+            lines.push(format!("{} blocks only on the peer:", self.remote_only.len()));
+            lines.extend(self.remote_only.iter().map(BlockHeader::describe));
+        }
+        lines.push(String::from(match self.fork_choice {
+            ForkChoice::Local if self.local_only.is_empty() && self.remote_only.is_empty() => "Chains agree",
+            ForkChoice::Local => "Fork choice keeps the local chain, the peer's is not preferred",
+            ForkChoice::Remote => "Fork choice adopts the peer's chain, it is preferred",
+            ForkChoice::Finalized => "Fork choice keeps the local chain, the peer's rolls back finalized blocks",
+        }));
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::network::communication::BlockchainDto;
+    use crate::network::divergence::{self, Divergence, ForkChoice};
+
+    fn extend(chain: &mut Blockchain<Transaction>, title: &str, blocks: usize) {
+        for _ in 0..blocks {
+            let block = BlockCandidate::create_new(vec![
+                Transaction::new([1; 32], [2; 32], title.to_string(), 1, Utc::now())
+            ], chain.last_block()).ok().unwrap();
+            chain.submit_new_block(block);
+        }
+    }
+
+    #[test]
+    fn finds_fork_point_and_the_chain_fork_choice_keeps() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        extend(&mut local, "shared", 3);
+        let mut remote = Blockchain::try_from(BlockchainDto::from(&local)).ok().unwrap();
+        extend(&mut local, "local", 2);
+        extend(&mut remote, "remote", 3);
+
+        let headers = divergence::recent_headers(&remote, 5);
+        assert_eq!(headers.len(), 5);
+        let divergence = Divergence::compare(&local, remote.chain_length(), &headers, 6);
+        assert_eq!(divergence.fork_point(), Some(3));
+        assert_eq!((divergence.local_only().len(), divergence.remote_only().len()), (2, 3));
+        assert_eq!(divergence.remote_only()[0].block_number(), 4);
+        assert!(divergence.forked());
+        assert_eq!(divergence.fork_choice(), ForkChoice::Remote);
+        // a fork older than the reorg depth allows is not followed, however long the peer's chain
+        assert_eq!(Divergence::compare(&local, remote.chain_length(), &headers, 1).fork_choice(), ForkChoice::Finalized);
+
+        let behind = divergence::recent_headers(&local, 3);
+        let same = Divergence::compare(&local, local.chain_length(), &behind, 6);
+        assert_eq!(same.fork_point(), Some(5));
+        assert!(!same.forked());
+        assert_eq!(same.fork_choice(), ForkChoice::Local);
+        assert!(same.describe().ends_with("Chains agree"));
+
+        let unrelated = divergence::recent_headers(&remote, 2);
+        let older = Divergence::compare(&local, 9, &unrelated, 6);
+        assert_eq!(older.fork_point(), None);
+        assert_eq!(older.remote_only().len(), 2);
+    }
+
+    #[test]
+    fn equally_long_forks_go_to_the_lower_tip_hash() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 100, Utc::now())
+        ]);
+        extend(&mut local, "shared", 2);
+        let mut remote = Blockchain::try_from(BlockchainDto::from(&local)).ok().unwrap();
+        extend(&mut local, "local", 2);
+        extend(&mut remote, "remote", 2);
+
+        let remote_lower = remote.last_block().as_ref().unwrap().key().hash() < local.last_block().as_ref().unwrap().key().hash();
+        let expected = match remote_lower {
+            true => ForkChoice::Remote,
+            false => ForkChoice::Local,
+        };
+        let headers = divergence::recent_headers(&remote, 4);
+        assert_eq!(Divergence::compare(&local, remote.chain_length(), &headers, 6).fork_choice(), expected);
+        // the other side reaches the opposite verdict on the same two branches
+        let opposite = match remote_lower {
+            true => ForkChoice::Local,
+            false => ForkChoice::Remote,
+        };
+        let local_headers = divergence::recent_headers(&local, 4);
+        assert_eq!(Divergence::compare(&remote, local.chain_length(), &local_headers, 6).fork_choice(), opposite);
+
+        assert!(divergence::prefers_remote(3, Some("b"), 3, Some("a")));
+        assert!(!divergence::prefers_remote(3, Some("a"), 3, Some("b")));
+        assert!(!divergence::prefers_remote(3, Some("a"), 3, Some("a")));
+        assert!(divergence::prefers_remote(3, Some("a"), 4, Some("b")));
+    }
+}
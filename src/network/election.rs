@@ -0,0 +1,93 @@
+use libp2p::PeerId;
+use sha2::{Digest, Sha256};
+
+use crate::blockchain::Transaction;
+
+static ELECTION_DOMAIN: &str = "kingcoin-election";
+
+// Forgers are drawn with a chance proportional to their bid instead of the highest bid always
+// winning. The seed only depends on the last transaction block and the staking epoch, so every
+// peer holding the same chain and bids computes the same draw and can check who may forge.
+// The previous forger picked that block's contents and could grind its hash, a commit-reveal
+// round would be needed to close that gap.
+pub fn election_seed(previous_hash: &str, epoch: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(ELECTION_DOMAIN.as_bytes());
+    hasher.update(previous_hash.as_bytes());
+    hasher.update(epoch.to_be_bytes());
+    hasher.finalize().into()
+}
+
+// weighted draw without replacement, the winner first and runners-up in the order they would
+// have been drawn; bids of zero can only follow, in peer id order
+pub fn draw_order(
+    candidates: Vec<(PeerId, Transaction)>, seed: [u8; 32],
+) -> Vec<(PeerId, Transaction)> {
+    weighted_draw_order(candidates, seed, |bid| bid.amount().max(0) as u64)
+}
+
+// the same draw with each bid counting as much as weight makes of it, e.g. lowered by penalties
+pub fn weighted_draw_order<F>(
+    mut candidates: Vec<(PeerId, Transaction)>, seed: [u8; 32], weight: F,
+) -> Vec<(PeerId, Transaction)> where F: Fn(&Transaction) -> u64 {
+    candidates.sort_by_key(|(peer_id, _)| peer_id.to_bytes());
+    let mut order = vec![];
+    let mut draw_seed = seed;
+    loop {
+        let total: u64 = candidates.iter().map(|(_, bid)| weight(bid)).sum();
+        if total == 0 {
+            break;
+        }
+        let draw = u64::from_be_bytes(draw_seed[..8].try_into().unwrap()) % total;
+        let mut cumulative = 0;
+        let drawn = candidates.iter()
+            .position(|(_, bid)| {
+                cumulative += weight(bid);
+                draw < cumulative
+            })
+            .unwrap();
+        order.push(candidates.remove(drawn));
+        draw_seed = Sha256::digest(draw_seed).into();
+    }
+    order.extend(candidates);
+    order
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use libp2p::PeerId;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::STAKE_WALLET_ADDRESS;
+    use crate::network::election::{draw_order, election_seed};
+
+    fn bid(amount: i64) -> Transaction {
+        Transaction::new([amount as u8; 32], *STAKE_WALLET_ADDRESS, "Bid".to_string(), amount, Utc::now())
+    }
+
+    #[test]
+    fn forgers_win_in_proportion_to_their_bids() {
+        let whale = PeerId::random();
+        let minnow = PeerId::random();
+        let idle = PeerId::random();
+        let candidates = vec![(whale, bid(30)), (minnow, bid(10)), (idle, bid(0))];
+
+        let mut whale_wins = 0;
+        for epoch in 0..2000 {
+            let order = draw_order(candidates.clone(), election_seed("tip", epoch));
+            assert_eq!(order.len(), 3);
+            assert_eq!(order[2].0, idle);
+            if order[0].0 == whale {
+                whale_wins += 1;
+            }
+        }
+        assert!((1400..1600).contains(&whale_wins), "whale won {} of 2000", whale_wins);
+
+        // every peer derives the same order whatever order it received the bids in
+        let seed = election_seed("tip", 7);
+        let reversed: Vec<_> = candidates.iter().rev().cloned().collect();
+        let peers = |order: Vec<(PeerId, Transaction)>| order.into_iter().map(|(peer, _)| peer).collect::<Vec<_>>();
+        assert_eq!(peers(draw_order(candidates, seed)), peers(draw_order(reversed, seed)));
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::BlockchainError;
+use crate::config::ConfigError;
+use crate::network::Voter;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct InactivityConfig {
+    // bidding or voting rounds a validator may miss in a row before it is marked inactive
+    inactive_after_rounds: u32,
+    // how long a round waits for bids or votes before going on without the missing ones
+    round_timeout_seconds: i64,
+    // share of its bonded stake a validator loses when marked inactive, none by default
+    leak_percent: i64,
+}
+
+impl Default for InactivityConfig {
+    fn default() -> Self {
+        InactivityConfig {
+            inactive_after_rounds: 3,
+            round_timeout_seconds: 30,
+            leak_percent: 0,
+        }
+    }
+}
+
+impl InactivityConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        if self.inactive_after_rounds == 0 {
+            return Err(Box::new(ConfigError::new("Inactivity needs at least one missed round")));
+        }
+        if self.round_timeout_seconds <= 0 {
+            return Err(Box::new(ConfigError::new("Round timeout must be positive")));
+        }
+        if !(0..=100).contains(&self.leak_percent) {
+            return Err(Box::new(ConfigError::new("Inactivity leak must be between 0 and 100 percent")));
+        }
+        Ok(())
+    }
+
+    pub fn leak_percent(&self) -> i64 {
+        self.leak_percent
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoundPhase {
+    Bidding,
+    Voting,
+}
+
+// Validators that stopped bidding and voting while still connected. Each bidding and voting
+// phase waits at most the round timeout; whoever it went on without missed the round. After
+// enough misses in a row a validator is inactive and no longer counted in quorums, until its
+// next bid registers it again.
+pub struct InactivityTracker {
+    config: InactivityConfig,
+    missed: HashMap<Voter, u32>,
+    inactive: HashSet<Voter>,
+    phase: Option<(RoundPhase, DateTime<Utc>)>,
+}
+
+impl Default for InactivityTracker {
+    fn default() -> Self {
+        InactivityTracker::new(InactivityConfig::default())
+    }
+}
+
+impl InactivityTracker {
+    pub fn new(config: InactivityConfig) -> InactivityTracker {
+        InactivityTracker {
+            config,
+            missed: HashMap::new(),
+            inactive: HashSet::new(),
+            phase: None,
+        }
+    }
+
+    pub fn config(&self) -> &InactivityConfig {
+        &self.config
+    }
+
+    pub fn phase(&self) -> Option<RoundPhase> {
+        self.phase.map(|(phase, _)| phase)
+    }
+
+    // the first bid of a round starts the timeout, later ones do not extend it
+    pub fn open_bidding(&mut self, now: DateTime<Utc>) {
+        self.phase.get_or_insert((RoundPhase::Bidding, now));
+    }
+
+    pub fn start_voting(&mut self, now: DateTime<Utc>) {
+        self.phase = Some((RoundPhase::Voting, now));
+    }
+
+    pub fn close_phase(&mut self) {
+        self.phase = None;
+    }
+
+    pub fn timed_out(&self, now: DateTime<Utc>) -> Option<RoundPhase> {
+        match self.phase {
+            Some((phase, started)) if now - started >= Duration::seconds(self.config.round_timeout_seconds) => {
+                Some(phase)
+            }
+            _ => None
+        }
+    }
+
+    // counts a phase, returns the validators it made inactive
+    pub fn record(&mut self, expected: &HashSet<Voter>, participated: &HashSet<Voter>) -> Vec<Voter> {
+        let mut deactivated = vec![];
+        for voter in expected {
+            if participated.contains(voter) {
+                self.missed.remove(voter);
+                continue;
+            }
+            let missed = self.missed.entry(*voter).or_insert(0);
+            *missed += 1;
+            if *missed >= self.config.inactive_after_rounds && self.inactive.insert(*voter) {
+                deactivated.push(*voter);
+            }
+        }
+        deactivated
+    }
+
+    // true if the voter was inactive
+    pub fn reactivate(&mut self, voter: &Voter) -> bool {
+        self.missed.remove(voter);
+        self.inactive.remove(voter)
+    }
+
+    pub fn is_inactive(&self, voter: &Voter) -> bool {
+        self.inactive.contains(voter)
+    }
+
+    pub fn inactive_count(&self) -> usize {
+        self.inactive.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::network::Voter;
+    use crate::network::inactivity::{InactivityConfig, InactivityTracker, RoundPhase};
+
+    #[test]
+    fn validators_missing_rounds_in_a_row_turn_inactive_until_they_bid() {
+        let (steady, silent, flaky) = (Voter::Wallet([1; 32]), Voter::Wallet([2; 32]), Voter::Peer(PeerId::random()));
+        let mut tracker = InactivityTracker::new(InactivityConfig::default());
+        let expected = HashSet::from([steady, silent, flaky]);
+        let start = Utc::now();
+        tracker.open_bidding(start);
+        tracker.open_bidding(start + Duration::seconds(20));
+        assert_eq!(tracker.timed_out(start + Duration::seconds(29)), None);
+        assert_eq!(tracker.timed_out(start + Duration::seconds(30)), Some(RoundPhase::Bidding));
+        tracker.start_voting(start + Duration::seconds(30));
+        assert_eq!(tracker.timed_out(start + Duration::seconds(40)), None);
+        tracker.close_phase();
+        assert_eq!(tracker.timed_out(start + Duration::seconds(90)), None);
+
+        assert!(tracker.record(&expected, &HashSet::from([steady])).is_empty());
+        assert!(tracker.record(&expected, &HashSet::from([steady, flaky])).is_empty());
+        assert_eq!(tracker.record(&expected, &HashSet::from([steady])), vec![silent]);
+        assert!(tracker.is_inactive(&silent));
+        assert!(!tracker.is_inactive(&flaky));
+        assert!(tracker.record(&expected, &HashSet::from([steady])).is_empty());
+        assert_eq!(tracker.inactive_count(), 1);
+
+        assert!(tracker.reactivate(&silent));
+        assert!(!tracker.reactivate(&steady));
+        assert!(!tracker.is_inactive(&silent));
+
+        let config: InactivityConfig = serde_json::from_str(r#"{"leak_percent": 150}"#).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::{Multiaddr, PeerId};
+use libp2p::multiaddr::Protocol;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::{BlockchainError, StorageError};
+
+pub static KNOWN_PEERS_FILE: &str = "known_peers.json";
+// addresses not connected to for this long are forgotten when the file is loaded
+pub static KNOWN_PEER_DAYS: i64 = 14;
+pub static MAX_KNOWN_PEERS: usize = 64;
+// dials failing in a row before an address is dropped
+pub static MAX_DIAL_FAILURES: u32 = 5;
+// connections come and go in bursts, changes are written out at most this often
+pub static SAVE_INTERVAL_SECONDS: u64 = 30;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct KnownPeer {
+    // base58, as printed in logs
+    peer_id: String,
+    address: String,
+    last_connected: DateTime<Utc>,
+    failures: u32,
+}
+
+impl KnownPeer {
+    fn dial_address(&self) -> Option<(PeerId, Multiaddr)> {
+        let peer_id = PeerId::from_str(&self.peer_id).ok()?;
+        let address = Multiaddr::from_str(&self.address).ok()?;
+        Some((peer_id, address.with(Protocol::P2p(peer_id.into()))))
+    }
+}
+
+// Addresses this node dialed successfully, so a small network re-forms after a restart without
+// waiting for mdns. A list loaded from a file writes its changes back to it with save_changes,
+// which the node calls every SAVE_INTERVAL_SECONDS rather than on every connection.
+#[derive(Serialize, Deserialize, Default)]
+pub struct KnownPeers {
+    peers: Vec<KnownPeer>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl KnownPeers {
+    pub fn load(path: &Path, now: DateTime<Utc>) -> KnownPeers {
+        let mut known: KnownPeers = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => KnownPeers::default()
+        };
+        known.peers.retain(|peer| now - peer.last_connected < Duration::days(KNOWN_PEER_DAYS));
+        KnownPeers {
+            path: Some(path.to_path_buf()),
+            ..known
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let content = serde_json::to_string_pretty(self).unwrap();
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    // writes the list only if it changed since it was last written
+    pub fn save_changes(&mut self) -> Result<(), Box<dyn BlockchainError>> {
+        if !self.changed {
+            return Ok(());
+        }
+        self.save()?;
+        self.changed = false;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    // the most recently connected first, the address ends in the peer's /p2p id
+    pub fn dial_addresses(&self) -> Vec<(PeerId, Multiaddr)> {
+        let mut peers: Vec<&KnownPeer> = self.peers.iter().collect();
+        peers.sort_by_key(|peer| Reverse(peer.last_connected));
+        peers.into_iter().filter_map(KnownPeer::dial_address).collect()
+    }
+
+    // an address the node dialed and got a connection on, the peer's /p2p suffix is stripped
+    pub fn connected(&mut self, peer_id: PeerId, address: &Multiaddr, now: DateTime<Utc>) {
+        let mut address = address.clone();
+        if matches!(address.iter().last(), Some(Protocol::P2p(_))) {
+            address.pop();
+        }
+        let (peer_id, address) = (peer_id.to_base58(), address.to_string());
+        self.peers.retain(|peer| peer.peer_id != peer_id || peer.address != address);
+        self.peers.push(KnownPeer {
+            peer_id,
+            address,
+            last_connected: now,
+            failures: 0,
+        });
+        self.changed = true;
+        if self.peers.len() > MAX_KNOWN_PEERS {
+            let oldest = self.peers.iter()
+                .enumerate()
+                .min_by_key(|(_, peer)| peer.last_connected)
+                .map(|(index, _)| index);
+            if let Some(oldest) = oldest {
+                self.peers.remove(oldest);
+            }
+        }
+    }
+
+    // a failed dial counts against every address of the peer, true when one was dropped
+    pub fn dial_failed(&mut self, peer_id: PeerId) -> bool {
+        let peer_id = peer_id.to_base58();
+        let known = self.peers.len();
+        for peer in self.peers.iter_mut().filter(|peer| peer.peer_id == peer_id) {
+            peer.failures += 1;
+            self.changed = true;
+        }
+        self.peers.retain(|peer| peer.failures < MAX_DIAL_FAILURES);
+        self.peers.len() < known
+    }
+
+    pub fn contains(&self, peer_id: &PeerId) -> bool {
+        let peer_id = peer_id.to_base58();
+        self.peers.iter().any(|peer| peer.peer_id == peer_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::str::FromStr;
+
+    use chrono::{Duration, Utc};
+    use libp2p::{Multiaddr, PeerId};
+    use libp2p::multiaddr::Protocol;
+
+    use crate::network::known_peers::{KnownPeers, MAX_DIAL_FAILURES};
+
+    #[test]
+    fn connected_addresses_are_dialed_after_a_restart_until_they_keep_failing() {
+        let path = env::temp_dir().join(format!("kingcoin-known-peers-{}.json", std::process::id()));
+        let (steady, flaky, stale) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let address = Multiaddr::from_str("/ip4/192.168.1.7/tcp/4001").unwrap();
+        let now = Utc::now();
+        let mut known = KnownPeers::load(&path, now);
+        known.connected(stale, &address, now - Duration::days(30));
+        known.connected(flaky, &address, now - Duration::minutes(5));
+        known.connected(steady, &address.clone().with(Protocol::P2p(steady.into())), now);
+        known.connected(steady, &address, now);
+        assert_eq!(known.len(), 3);
+        assert!(known.save().is_ok());
+
+        let mut reloaded = KnownPeers::load(&path, now);
+        assert!(!reloaded.contains(&stale));
+        let dialed = reloaded.dial_addresses();
+        assert_eq!(dialed.len(), 2);
+        assert_eq!(dialed[0].0, steady);
+        assert_eq!(dialed[0].1.to_string(), format!("/ip4/192.168.1.7/tcp/4001/p2p/{}", steady));
+
+        for _ in 1..MAX_DIAL_FAILURES {
+            assert!(!reloaded.dial_failed(flaky));
+        }
+        assert!(reloaded.dial_failed(flaky));
+        assert!(!reloaded.contains(&flaky));
+        assert!(reloaded.contains(&steady));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn changes_are_written_once_until_the_list_changes_again() {
+        let path = env::temp_dir().join(format!("kingcoin-known-peers-batch-{}.json", std::process::id()));
+        let address = Multiaddr::from_str("/ip4/192.168.1.8/tcp/4001").unwrap();
+        let mut known = KnownPeers::load(&path, Utc::now());
+        assert!(known.save_changes().is_ok());
+        assert!(!path.exists());
+
+        known.connected(PeerId::random(), &address, Utc::now());
+        known.connected(PeerId::random(), &address, Utc::now());
+        assert!(known.save_changes().is_ok());
+        assert_eq!(KnownPeers::load(&path, Utc::now()).len(), 2);
+        fs::remove_file(&path).ok();
+        assert!(known.save_changes().is_ok());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::display::table::Table;
+
+pub static PING_INTERVAL_SECONDS: u64 = 15;
+// round trip times kept per peer, older ones are forgotten
+pub static RTT_SAMPLES: usize = 8;
+// peers never measured are ranked as if they were this slow
+static UNMEASURED_RTT: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Probes {
+    rtts: VecDeque<Duration>,
+    answered: u32,
+    failed: u32,
+}
+
+// what the peers command lists for one peer
+#[derive(Serialize, Deserialize)]
+pub struct PeerProbe {
+    // base58, as printed in logs
+    peer: String,
+    rtt_millis: Option<u128>,
+    samples: usize,
+    // share of answered pings, none before the first round trip was measured
+    reliability: Option<f64>,
+}
+
+// Round trip times and failed pings per connected peer, from the ping protocol.
+#[derive(Default)]
+pub struct PeerLatency {
+    peers: HashMap<PeerId, Probes>,
+}
+
+impl PeerLatency {
+    pub fn record_rtt(&mut self, peer: PeerId, rtt: Duration) {
+        let probes = self.peers.entry(peer).or_default();
+        if probes.rtts.len() >= RTT_SAMPLES {
+            probes.rtts.pop_front();
+        }
+        probes.rtts.push_back(rtt);
+        probes.answered += 1;
+    }
+
+    pub fn record_failure(&mut self, peer: PeerId) {
+        self.peers.entry(peer).or_default().failed += 1;
+    }
+
+    pub fn remove(&mut self, peer: &PeerId) {
+        self.peers.remove(peer);
+    }
+
+    pub fn average_rtt(&self, peer: &PeerId) -> Option<Duration> {
+        let rtts = &self.peers.get(peer)?.rtts;
+        match rtts.len() {
+            0 => None,
+            samples => Some(rtts.iter().sum::<Duration>() / samples as u32)
+        }
+    }
+
+    // share of answered pings, a peer without any history starts at one half
+    pub fn reliability(&self, peer: &PeerId) -> f64 {
+        let (answered, failed) = self.peers.get(peer).map_or((0, 0), |probes| (probes.answered, probes.failed));
+        (answered as f64 + 1.0) / ((answered + failed) as f64 + 2.0)
+    }
+
+    // lower is better, a slow but dependable peer beats a fast one that drops pings
+    pub fn cost(&self, peer: &PeerId) -> f64 {
+        let rtt = self.average_rtt(peer).unwrap_or(UNMEASURED_RTT);
+        rtt.as_secs_f64() / self.reliability(peer)
+    }
+
+    pub fn costs(&self) -> HashMap<PeerId, f64> {
+        self.peers.keys().map(|peer| (*peer, self.cost(peer))).collect()
+    }
+
+    pub fn probes(&self, peers: &[PeerId]) -> Vec<PeerProbe> {
+        peers.iter()
+            .map(|peer| {
+                let rtt = self.average_rtt(peer);
+                PeerProbe {
+                    peer: peer.to_base58(),
+                    rtt_millis: rtt.map(|rtt| rtt.as_millis()),
+                    samples: self.peers.get(peer).map_or(0, |probes| probes.rtts.len()),
+                    reliability: rtt.map(|_| self.reliability(peer)),
+                }
+            })
+            .collect()
+    }
+
+    pub fn table(&self, peers: &[PeerId]) -> Table {
+        let mut table = Table::new(&["Peer", "RTT", "Pings", "Answered"]).with_numeric(&[1, 2, 3]);
+        for probe in self.probes(peers) {
+            table.push(vec![
+                probe.peer,
+                probe.rtt_millis.map_or(String::from("n/a"), |rtt| format!("{}ms", rtt)),
+                probe.samples.to_string(),
+                probe.reliability.map_or(String::from("n/a"), |reliability| format!("{:.0}%", reliability * 100.0)),
+            ], None);
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use libp2p::PeerId;
+
+    use crate::network::latency::{PeerLatency, RTT_SAMPLES};
+
+    #[test]
+    fn flaky_peers_cost_more_than_slow_dependable_ones() {
+        let (steady, flaky, unknown) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut latency = PeerLatency::default();
+        for _ in 0..RTT_SAMPLES {
+            latency.record_rtt(steady, Duration::from_millis(80));
+        }
+        latency.record_rtt(steady, Duration::from_millis(160));
+        latency.record_rtt(flaky, Duration::from_millis(40));
+        for _ in 0..5 {
+            latency.record_failure(flaky);
+        }
+
+        assert_eq!(latency.average_rtt(&steady), Some(Duration::from_millis(90)));
+        assert_eq!(latency.average_rtt(&unknown), None);
+        assert!(latency.cost(&steady) < latency.cost(&flaky));
+        assert!(latency.cost(&flaky) < latency.cost(&unknown));
+        latency.remove(&flaky);
+        assert_eq!(latency.costs().len(), 1);
+    }
+}
@@ -0,0 +1,95 @@
+use libp2p::{autonat, dcutr, identify, identity::Keypair, PeerId, Swarm};
+use libp2p::relay::v2::client::{self, Client};
+use libp2p::swarm::NetworkBehaviour;
+
+use crate::network::BlockchainBehaviour;
+use crate::report;
+
+pub static IDENTIFY_PROTOCOL: &str = "/kingcoin/id/1";
+
+// identify tells peers our observed address, AutoNAT uses them to probe reachability,
+// dcutr hole-punches through relayed connections and the relay client is the fallback
+#[derive(NetworkBehaviour)]
+#[behaviour(out_event = "NatEvent")]
+pub struct NatBehaviour {
+    identify: identify::Behaviour,
+    autonat: autonat::Behaviour,
+    relay_client: Client,
+    dcutr: dcutr::behaviour::Behaviour,
+}
+
+impl NatBehaviour {
+    pub fn new(key: &Keypair, relay_client: Client) -> NatBehaviour {
+        let local_id = PeerId::from(key.public());
+        NatBehaviour {
+            identify: identify::Behaviour::new(
+                identify::Config::new(IDENTIFY_PROTOCOL.to_string(), key.public())
+            ),
+            autonat: autonat::Behaviour::new(local_id, autonat::Config::default()),
+            relay_client,
+            dcutr: dcutr::behaviour::Behaviour::new(),
+        }
+    }
+
+    pub fn autonat(&mut self) -> &mut autonat::Behaviour {
+        &mut self.autonat
+    }
+}
+
+pub enum NatEvent {
+    Identify(identify::Event),
+    Autonat(autonat::Event),
+    Relay(client::Event),
+    Dcutr(dcutr::behaviour::Event),
+}
+
+impl From<identify::Event> for NatEvent {
+    fn from(event: identify::Event) -> Self {
+        NatEvent::Identify(event)
+    }
+}
+
+impl From<autonat::Event> for NatEvent {
+    fn from(event: autonat::Event) -> Self {
+        NatEvent::Autonat(event)
+    }
+}
+
+impl From<client::Event> for NatEvent {
+    fn from(event: client::Event) -> Self {
+        NatEvent::Relay(event)
+    }
+}
+
+impl From<dcutr::behaviour::Event> for NatEvent {
+    fn from(event: dcutr::behaviour::Event) -> Self {
+        NatEvent::Dcutr(event)
+    }
+}
+
+pub fn dispatch_nat(swarm: &mut Swarm<BlockchainBehaviour>, event: NatEvent) {
+    match event {
+        NatEvent::Identify(identify::Event::Received { peer_id, info }) => {
+            report!("{} observes us at {}", peer_id, info.observed_addr);
+            // every identified peer can probe whether we are publicly reachable
+            for address in info.listen_addrs {
+                swarm.behaviour_mut().nat().autonat().add_server(peer_id, Some(address));
+            }
+        }
+        NatEvent::Autonat(autonat::Event::StatusChanged { new, .. }) => match new {
+            autonat::NatStatus::Public(address) => report!("Publicly reachable at {}", address),
+            autonat::NatStatus::Private => report!("Behind NAT, reachable through relays only"),
+            autonat::NatStatus::Unknown => {}
+        },
+        NatEvent::Relay(client::Event::ReservationReqAccepted { relay_peer_id, .. }) => {
+            report!("Reachable via relay {}", relay_peer_id);
+        }
+        NatEvent::Dcutr(dcutr::behaviour::Event::DirectConnectionUpgradeSucceeded { remote_peer_id }) => {
+            report!("Hole-punched direct connection to {}", remote_peer_id);
+        }
+        NatEvent::Dcutr(dcutr::behaviour::Event::DirectConnectionUpgradeFailed { remote_peer_id, .. }) => {
+            report!("Hole punching to {} failed, staying relayed", remote_peer_id);
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+
+// Peers that expired from mdns or lost their last connection. They are not waited for in
+// bids and votes; one that drops out during a round stays excused until the round ends, even
+// if it comes back, since it may have missed the proposal it would have to vote on.
+#[derive(Default)]
+pub struct PeerPresence {
+    offline: HashMap<PeerId, DateTime<Utc>>,
+    excused: HashSet<PeerId>,
+}
+
+impl PeerPresence {
+    // true if the peer was online until now
+    pub fn mark_offline(&mut self, peer: PeerId, now: DateTime<Utc>) -> bool {
+        self.excused.insert(peer);
+        match self.offline.contains_key(&peer) {
+            true => false,
+            false => {
+                self.offline.insert(peer, now);
+                true
+            }
+        }
+    }
+
+    // when the peer went offline, if it is rejoining
+    pub fn mark_online(&mut self, peer: &PeerId) -> Option<DateTime<Utc>> {
+        self.offline.remove(peer)
+    }
+
+    pub fn is_offline(&self, peer: &PeerId) -> bool {
+        self.offline.contains_key(peer)
+    }
+
+    // offline peers and those that dropped out of the running round
+    pub fn is_excused(&self, peer: &PeerId) -> bool {
+        self.excused.contains(peer) || self.is_offline(peer)
+    }
+
+    pub fn offline_count(&self) -> usize {
+        self.offline.len()
+    }
+
+    // a peer still connected but not answering is not waited for either
+    pub fn excuse(&mut self, peer: PeerId) {
+        self.excused.insert(peer);
+    }
+
+    pub fn end_round(&mut self) {
+        self.excused.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use libp2p::PeerId;
+
+    use crate::network::presence::PeerPresence;
+
+    #[test]
+    fn rejoined_peer_stays_excused_until_the_round_ends() {
+        let (peer, other) = (PeerId::random(), PeerId::random());
+        let mut presence = PeerPresence::default();
+        let gone_at = Utc::now();
+        assert!(presence.mark_offline(peer, gone_at));
+        assert!(!presence.mark_offline(peer, Utc::now()));
+        assert!(presence.is_offline(&peer));
+        assert_eq!(presence.offline_count(), 1);
+
+        assert_eq!(presence.mark_online(&peer), Some(gone_at));
+        assert_eq!(presence.mark_online(&other), None);
+        assert!(!presence.is_offline(&peer));
+        assert!(presence.is_excused(&peer));
+        presence.end_round();
+        assert!(!presence.is_excused(&peer));
+        assert!(!presence.is_excused(&other));
+    }
+}
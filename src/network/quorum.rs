@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::BlockchainError;
+use crate::blockchain::protocol::ProtocolError;
+use crate::network::Voter;
+
+// Set at genesis like the other consensus parameters, nodes counting quorums differently would
+// settle the same round differently. The defaults keep the original rules: every expected
+// validator bids and votes, and a block is appended when more votes approve it than reject it.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default)]
+pub struct QuorumConfig {
+    // share of the expected validators that has to bid before the forger is drawn
+    bid_percent: i64,
+    // share of the expected validators that has to vote before the round settles
+    vote_percent: i64,
+    // a block is appended once more than this share of the counted votes approve it
+    approve_percent: i64,
+    // fewest validators besides this node that have to bid or vote, a round short of them is abandoned
+    min_participants: usize,
+    // a block approved by this share of the expected validators is a checkpoint, no
+    // reorganization rolls it back
+    checkpoint_percent: i64,
+    // validators weigh their bonded stake instead of one wallet one voice
+    stake_weighted: bool,
+}
+
+impl Default for QuorumConfig {
+    fn default() -> Self {
+        QuorumConfig {
+            bid_percent: 100,
+            vote_percent: 100,
+            approve_percent: 50,
+            min_participants: 0,
+            checkpoint_percent: 66,
+            stake_weighted: false,
+        }
+    }
+}
+
+impl QuorumConfig {
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let quorums = [self.bid_percent, self.vote_percent, self.checkpoint_percent];
+        if quorums.iter().any(|percent| !(1..=100).contains(percent)) {
+            return Err(Box::new(ProtocolError::new("Bid, vote and checkpoint quorums must be between 1 and 100 percent")));
+        }
+        if !(0..100).contains(&self.approve_percent) {
+            return Err(Box::new(ProtocolError::new("Approval threshold must be between 0 and 99 percent")));
+        }
+        Ok(())
+    }
+
+    pub fn min_participants(&self) -> usize {
+        self.min_participants
+    }
+
+    pub fn bidding_complete(
+        &self, expected: &HashSet<Voter>, bade: &HashSet<Voter>, stake: impl Fn(&Voter) -> i64,
+    ) -> bool {
+        self.complete(self.bid_percent, expected, bade, stake)
+    }
+
+    pub fn voting_complete(
+        &self, expected: &HashSet<Voter>, voted: &HashSet<Voter>, stake: impl Fn(&Voter) -> i64,
+    ) -> bool {
+        self.complete(self.vote_percent, expected, voted, stake)
+    }
+
+    pub fn checkpoints(
+        &self, expected: &HashSet<Voter>, approving: &HashSet<Voter>, stake: impl Fn(&Voter) -> i64,
+    ) -> bool {
+        self.complete(self.checkpoint_percent, expected, approving, stake)
+    }
+
+    pub fn approves(&self, valid: &HashSet<Voter>, invalid: &HashSet<Voter>, stake: impl Fn(&Voter) -> i64) -> bool {
+        let counted: HashSet<Voter> = valid.union(invalid).copied().collect();
+        let staked = self.staked(&counted, &stake);
+        let (approving, rejecting) = (weight(valid, staked, &stake), weight(invalid, staked, &stake));
+        approving * 100 > (approving + rejecting) * self.approve_percent
+    }
+
+    fn complete(
+        &self, percent: i64, expected: &HashSet<Voter>, participated: &HashSet<Voter>, stake: impl Fn(&Voter) -> i64,
+    ) -> bool {
+        let participated: HashSet<Voter> = participated.intersection(expected).copied().collect();
+        if participated.len() < self.min_participants {
+            return false;
+        }
+        let staked = self.staked(expected, &stake);
+        weight(&participated, staked, &stake) * 100 >= weight(expected, staked, &stake) * percent
+    }
+
+    // stake weighted quorums count voters one each while none of them has stake bonded
+    fn staked(&self, voters: &HashSet<Voter>, stake: &impl Fn(&Voter) -> i64) -> bool {
+        self.stake_weighted && weight(voters, true, stake) > 0
+    }
+}
+
+fn weight(voters: &HashSet<Voter>, staked: bool, stake: &impl Fn(&Voter) -> i64) -> i64 {
+    match staked {
+        true => voters.iter().map(stake).sum(),
+        false => voters.len() as i64,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use crate::network::Voter;
+    use crate::network::quorum::QuorumConfig;
+
+    fn voters(addresses: &[u8]) -> HashSet<Voter> {
+        addresses.iter().map(|address| Voter::Wallet([*address; 32])).collect()
+    }
+
+    fn stake(voter: &Voter) -> i64 {
+        match voter {
+            Voter::Wallet(address) => address[0] as i64 * 10,
+            Voter::Peer(_) => 0,
+        }
+    }
+
+    #[test]
+    fn quorums_follow_the_configured_shares_and_stake() {
+        let expected = voters(&[1, 2, 3]);
+        let defaults = QuorumConfig::default();
+        assert!(!defaults.bidding_complete(&expected, &voters(&[1, 2, 9]), stake));
+        assert!(defaults.bidding_complete(&expected, &voters(&[1, 2, 3]), stake));
+        assert!(defaults.approves(&voters(&[1, 2]), &voters(&[3]), stake));
+        assert!(!defaults.approves(&voters(&[1]), &voters(&[2]), stake));
+
+        let config: QuorumConfig = serde_json::from_str(
+            r#"{"vote_percent": 66, "approve_percent": 66, "min_participants": 2, "stake_weighted": true}"#
+        ).unwrap();
+        assert!(config.validate().is_ok());
+        // wallet 3 alone holds half of the expected stake but is a single participant
+        assert!(!config.voting_complete(&expected, &voters(&[3]), stake));
+        assert!(!config.voting_complete(&expected, &voters(&[1, 2]), stake));
+        assert!(config.voting_complete(&expected, &voters(&[1, 3]), stake));
+        assert!(config.approves(&voters(&[3]), &voters(&[1]), stake));
+        assert!(!config.approves(&voters(&[1, 2]), &voters(&[3]), stake));
+        // without any stake bonded every wallet counts once
+        assert!(config.voting_complete(&expected, &voters(&[1, 2]), |_| 0));
+
+        let invalid: QuorumConfig = serde_json::from_str(r#"{"approve_percent": 100}"#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn checkpoints_need_two_thirds_of_the_expected_validators() {
+        let expected = voters(&[1, 2, 3]);
+        let defaults = QuorumConfig::default();
+        // a simple majority appends the block but does not make it final
+        assert!(defaults.approves(&voters(&[1, 2]), &voters(&[3]), stake));
+        assert!(!defaults.checkpoints(&expected, &voters(&[1]), stake));
+        assert!(defaults.checkpoints(&expected, &voters(&[1, 2]), stake));
+
+        let weighted: QuorumConfig = serde_json::from_str(r#"{"stake_weighted": true}"#).unwrap();
+        assert!(!weighted.checkpoints(&expected, &voters(&[1, 2]), stake));
+        assert!(weighted.checkpoints(&expected, &voters(&[2, 3]), stake));
+    }
+}
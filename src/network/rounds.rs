@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::core::{BlockchainError, StorageError};
+use crate::network::communication::VotingResult;
+use crate::report;
+
+pub static ROUNDS_FILE: &str = "rounds.json";
+// older rounds are dropped from memory and the file alike
+pub static MAX_LOGGED_ROUNDS: usize = 500;
+pub static DEFAULT_SHOWN_ROUNDS: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum RoundOutcome {
+    Appended { block_number: u64 },
+    // the most common reason the rejecting voters gave, if any
+    Rejected { reason: Option<String> },
+    // a new round began before this one was settled or the forger went silent
+    Abandoned,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RoundRecord {
+    // base58, as printed in logs
+    forger: String,
+    // bids the forger was elected from, a re-proposal inherits the round's bids
+    bids: usize,
+    started_at: DateTime<Utc>,
+    finished_at: DateTime<Utc>,
+    block_hash: Option<String>,
+    votes_for: i64,
+    votes_against: i64,
+    outcome: RoundOutcome,
+}
+
+impl RoundRecord {
+    pub fn forger(&self) -> &str {
+        &self.forger
+    }
+    pub fn bids(&self) -> usize {
+        self.bids
+    }
+    pub fn block_hash(&self) -> &Option<String> {
+        &self.block_hash
+    }
+    pub fn votes(&self) -> (i64, i64) {
+        (self.votes_for, self.votes_against)
+    }
+    pub fn outcome(&self) -> &RoundOutcome {
+        &self.outcome
+    }
+
+    pub fn duration_millis(&self) -> i64 {
+        (self.finished_at - self.started_at).num_milliseconds()
+    }
+
+    pub fn describe(&self) -> String {
+        let outcome = match &self.outcome {
+            RoundOutcome::Appended { block_number } => format!("appended block {}", block_number),
+            RoundOutcome::Rejected { reason: None } => String::from("rejected"),
+            RoundOutcome::Rejected { reason: Some(reason) } => format!("rejected: {}", reason),
+            RoundOutcome::Abandoned => String::from("abandoned"),
+        };
+        format!(
+            "{} forger {}, {} bids, block {}, {} for/{} against, {:.1}s, {}",
+            self.started_at.to_rfc3339(), self.forger, self.bids,
+            self.block_hash.as_deref().unwrap_or("none"), self.votes_for, self.votes_against,
+            self.duration_millis() as f64 / 1000.0, outcome
+        )
+    }
+}
+
+struct OpenRound {
+    forger: PeerId,
+    bids: usize,
+    started_at: DateTime<Utc>,
+}
+
+// Outcomes of the last consensus rounds this node took part in, oldest first. Logs loaded from
+// a file write every settled round back to it, logs created in memory are never persisted.
+#[derive(Serialize, Deserialize, Default)]
+pub struct RoundLog {
+    records: VecDeque<RoundRecord>,
+    #[serde(skip)]
+    open: Option<OpenRound>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl RoundLog {
+    pub fn load(path: &Path) -> RoundLog {
+        let rounds: RoundLog = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => RoundLog::default()
+        };
+        RoundLog {
+            path: Some(path.to_path_buf()),
+            ..rounds
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let path = match &self.path {
+            None => return Ok(()),
+            Some(path) => path,
+        };
+        let content = serde_json::to_string(self).unwrap();
+        match fs::write(path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn begin(&mut self, forger: PeerId, bids: usize, now: DateTime<Utc>) {
+        if self.open.is_some() {
+            self.close(None, 0, 0, RoundOutcome::Abandoned, now);
+        }
+        self.open = Some(OpenRound {
+            forger,
+            bids,
+            started_at: now,
+        });
+    }
+
+    // the fallback forger of a rejected round, elected from the same bids
+    pub fn repropose(&mut self, forger: PeerId, now: DateTime<Utc>) {
+        let bids = self.records.back().map_or(0, RoundRecord::bids);
+        self.begin(forger, bids, now);
+    }
+
+    pub fn settle(
+        &mut self, block_hash: Option<String>, votes: &VotingResult, outcome: RoundOutcome, now: DateTime<Utc>,
+    ) {
+        self.close(block_hash, votes.votes_for(), votes.votes_against(), outcome, now);
+    }
+
+    fn close(
+        &mut self, block_hash: Option<String>, votes_for: i64, votes_against: i64,
+        outcome: RoundOutcome, now: DateTime<Utc>,
+    ) {
+        let open = match self.open.take() {
+            None => return,
+            Some(open) => open,
+        };
+        self.records.push_back(RoundRecord {
+            forger: open.forger.to_base58(),
+            bids: open.bids,
+            started_at: open.started_at,
+            finished_at: now,
+            block_hash,
+            votes_for,
+            votes_against,
+            outcome,
+        });
+        while self.records.len() > MAX_LOGGED_ROUNDS {
+            self.records.pop_front();
+        }
+        if let Err(error) = self.save() {
+            report!("Round log not saved: {}", error.message());
+        }
+    }
+
+    // the forger never sent a block before the round timed out
+    pub fn abandon(&mut self, now: DateTime<Utc>) {
+        self.close(None, 0, 0, RoundOutcome::Abandoned, now);
+    }
+
+    // the newest count rounds, newest first
+    pub fn recent(&self, count: usize) -> Vec<RoundRecord> {
+        self.records.iter().rev().take(count).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::network::communication::VotingResult;
+    use crate::network::rounds::{RoundLog, RoundOutcome};
+
+    #[test]
+    fn settled_rounds_are_kept_newest_first_across_restarts() {
+        let path = env::temp_dir().join(format!("kingcoin-rounds-{}.json", std::process::id()));
+        let (forger, fallback, silent) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let start = Utc::now();
+        let mut rounds = RoundLog::load(&path);
+        rounds.begin(forger, 3, start);
+        rounds.settle(
+            Some("ab".to_string()), &VotingResult::evaluate(1, 2),
+            RoundOutcome::Rejected { reason: Some("bad nonce".to_string()) }, start + Duration::seconds(2),
+        );
+        rounds.repropose(fallback, start + Duration::seconds(2));
+        rounds.settle(
+            Some("cd".to_string()), &VotingResult::evaluate(3, 0),
+            RoundOutcome::Appended { block_number: 4 }, start + Duration::seconds(3),
+        );
+        rounds.begin(silent, 2, start + Duration::seconds(10));
+        rounds.begin(forger, 2, start + Duration::seconds(40));
+
+        let reloaded = RoundLog::load(&path);
+        let recent = reloaded.recent(10);
+        assert_eq!(recent.len(), 3);
+        assert_eq!(*recent[0].outcome(), RoundOutcome::Abandoned);
+        assert_eq!(recent[0].forger(), silent.to_base58());
+        assert_eq!(recent[1].bids(), 3);
+        assert_eq!(recent[1].votes(), (3, 0));
+        assert_eq!(recent[1].duration_millis(), 1000);
+        assert_eq!(*recent[2].block_hash(), Some("ab".to_string()));
+        assert_eq!(reloaded.recent(1).len(), 1);
+        std::fs::remove_file(path).ok();
+    }
+}
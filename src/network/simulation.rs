@@ -0,0 +1,712 @@
+// In-process consensus harness: every node runs the same NodeState round logic the dispatcher
+// drives, messages are delivered directly instead of over gossipsub.
+//
+// Guarantees exercised below:
+// - only the elected forger can get a block voted on, a second different block from it is
+//   rejected and gets it marked bad and slashed
+// - a round settles a single block, a forger proposing both a wallet and a transaction block
+//   equivocates
+// - a peer's vote counts once, equivocating voters cannot close a round early
+// - a wallet served by several nodes votes and is drawn as forger once
+// - a peer speaks for a wallet only once the wallet signed a bid naming that peer
+// - a round only settles once every connected wallet voted, a single vote withholder stalls
+//   the round (liveness needs all peers) but never lets a block in without a majority
+// - a peer that goes offline is no longer waited for, rejoining during the round does not
+//   make the round wait for it again
+// - every node draws the same forger from the same bids, a voted down round moves on to the
+//   next drawn bidder, at most MAX_REPROPOSALS times
+// - a forger fills blocks up to the chain's block size, a partially filled one only goes out
+//   once the block interval passed, voters reject blocks carrying more than the block size
+// - syncing never adopts a chain that is not longer than the local one
+// - after a partition heals, the half on the shorter branch learns of the longer one from its
+//   tip announcement and reorgs onto it
+// - a forger does not propose a block breaking the rules of the network's genesis
+// - blocks timed before the median of recent blocks or too far ahead of the voter are rejected
+// - voters recompute the state root in the block header, a voter whose stakes drifted apart
+//   rejects the block instead of silently appending it
+// - a node short of its minimum of connected peers stays out of rounds until it has them again,
+//   a bid it never sent is not drawn
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::identity::{ed25519, Keypair};
+use libp2p::PeerId;
+
+use crate::blockchain::{RejectionReason, StakeBid, Transaction, TransactionValidationError, TransactionValidator, Wallet};
+use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, TRANSACTION_FEE, TRANSFER_FEE};
+use crate::blockchain::access::HotWallet;
+use crate::blockchain::core::{BlockCandidate, Blockchain};
+use crate::blockchain::rules::{ChainRules, RuleRegistry, RulesConfig, TRANSACTIONS_CHAIN};
+use crate::blockchain::snapshot;
+use crate::blockchain::stake::StakeRegistry;
+use crate::blockchain::upgrade::{ConsensusRules, SignatureScheme, UPGRADE_SCHEDULE, UpgradeSchedule};
+use crate::network::{election, MAX_REPROPOSALS, NodeState, ProposalRejection, Voter};
+use crate::network::anti_entropy::TipCheck;
+use crate::network::communication::{BlockchainDto, BlockDto, Vote};
+use crate::network::divergence::{self, ForkChoice};
+use crate::network::validators::ChainMisses;
+use crate::network::communication::dispatch::{self, Settlement};
+use crate::random;
+
+// peer ids derived from the node index keep every run of a scenario identical
+fn simulated_peer_id(index: usize) -> PeerId {
+    let secret = ed25519::SecretKey::from_bytes([index as u8 + 1; 32]).unwrap();
+    PeerId::from(Keypair::Ed25519(ed25519::Keypair::from(secret)).public())
+}
+
+struct SimulatedNode {
+    node_state: NodeState,
+    transactions: Blockchain<Transaction>,
+    wallets: Blockchain<Wallet>,
+}
+
+struct Simulation {
+    nodes: Vec<SimulatedNode>,
+}
+
+impl Simulation {
+    fn new(node_count: usize) -> Simulation {
+        let nodes = (0..node_count)
+            .map(|index| SimulatedNode {
+                node_state: NodeState::init(
+                    simulated_peer_id(index), StakeBid::bid(0, [index as u8 + 10; 32]),
+                ),
+                transactions: Blockchain::<Transaction>::transaction_chain(vec![]),
+                wallets: Blockchain::<Wallet>::wallet_chain(),
+            })
+            .collect();
+        Simulation {
+            nodes,
+        }
+    }
+
+    fn peer_id(&self, node: usize) -> PeerId {
+        self.nodes[node].node_state.node_id()
+    }
+
+    fn elect(&mut self, forger: usize) {
+        let forger = self.peer_id(forger);
+        for node in &mut self.nodes {
+            node.node_state.set_block_creator(forger);
+        }
+    }
+
+    fn forge(&self, forger: usize, reward: i64) -> BlockCandidate<Transaction> {
+        let transactions = &self.nodes[forger].transactions;
+        let reward = Transaction::new(
+            MINTING_WALLET_ADDRESS, [forger as u8 + 10; 32], "Reward".to_string(), reward, Utc::now(),
+        );
+        BlockCandidate::create_new(vec![reward], transactions.last_block()).ok().unwrap()
+    }
+
+    // delivers a proposal to every other node, honest receivers vote on what they accepted
+    fn propose(
+        &mut self, proposer: usize, block: &BlockCandidate<Transaction>,
+    ) -> Vec<(usize, Result<bool, ProposalRejection>)> {
+        let proposer_id = self.peer_id(proposer);
+        let mut outcomes = vec![];
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if index == proposer {
+                continue;
+            }
+            let copy = BlockCandidate::create_new(
+                block.data().clone(), node.transactions.last_block(),
+            ).ok().unwrap();
+            outcomes.push((index, node.node_state.accept_block_proposal(proposer_id, copy)));
+        }
+        outcomes
+    }
+
+    fn validate(&self, node: usize) -> bool {
+        self.diagnose(node).is_ok()
+    }
+
+    fn diagnose(&self, node: usize) -> Result<(), RejectionReason> {
+        let node = &self.nodes[node];
+        let pending_block = node.node_state.pending_block().as_ref().unwrap();
+        TransactionValidator::new(&node.wallets, &node.transactions).diagnose(pending_block)
+    }
+
+    fn vote(&mut self, voter: usize, block_valid: bool) {
+        let voter_id = self.peer_id(voter);
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if index != voter {
+                node.node_state.add_vote(Vote::new(voter_id, block_valid));
+            }
+        }
+    }
+
+    fn object(&mut self, voter: usize, reason: RejectionReason) {
+        let voter_id = self.peer_id(voter);
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            if index != voter {
+                node.node_state.add_vote(Vote::new(voter_id, false).with_reason(Some(reason.clone())));
+            }
+        }
+    }
+
+    // every node saw the same bids, node i bid 10 * (node count - i), returns the elected node
+    fn auction(&mut self, seed: [u8; 32]) -> usize {
+        let node_count = self.nodes.len();
+        let bids: Vec<(PeerId, i64)> = (0..node_count)
+            .map(|index| (self.peer_id(index), 10 * (node_count - index) as i64))
+            .collect();
+        let mut elected = vec![];
+        for (index, node) in self.nodes.iter_mut().enumerate() {
+            for (peer_id, amount) in &bids {
+                let bid = StakeBid::bid(*amount, [*amount as u8; 32]);
+                if *peer_id == node.node_state.node_id() {
+                    node.node_state.update_bid(bid);
+                } else {
+                    node.node_state.update_peers_bids(*peer_id, bid);
+                }
+            }
+            let (winner, _) = node.node_state.elect_forger(seed, &ChainMisses::default()).unwrap();
+            node.node_state.set_block_creator(winner);
+            assert_eq!(node.node_state.node_id(), bids[index].0);
+            elected.push(winner);
+        }
+        assert!(elected.iter().all(|winner| *winner == elected[0]));
+        bids.iter().position(|(peer_id, _)| *peer_id == elected[0]).unwrap()
+    }
+
+    // the settle step of settle_if_quorum, returns whether the block was appended
+    fn settle(&mut self, node: usize) -> Option<bool> {
+        let peers: Vec<PeerId> = (0..self.nodes.len())
+            .filter(|peer| *peer != node)
+            .map(|peer| self.peer_id(peer))
+            .collect();
+        let node = &mut self.nodes[node];
+        // no bids were ever recorded, every voter weighs the same
+        let registry = StakeRegistry::derive(&Blockchain::<Transaction>::transaction_chain(vec![]), &node.transactions);
+        if !node.node_state.voting_quorum(&peers, &registry) {
+            return None;
+        }
+        let result = node.node_state.summarize_votes(&registry);
+        match dispatch::settle_round(&mut node.transactions, &mut node.wallets, &mut node.node_state, &result, false) {
+            Settlement::Appended { .. } => Some(true),
+            Settlement::Rejected { next_forger } => {
+                if let Some((forger, _)) = next_forger {
+                    node.node_state.set_block_creator(forger);
+                }
+                Some(false)
+            }
+            Settlement::Unsettled => None,
+        }
+    }
+
+    // a forger whose clock is off, the block hash does not cover the time
+    fn retime(block: BlockCandidate<Transaction>, time: DateTime<Utc>) -> BlockCandidate<Transaction> {
+        let mut dto = serde_json::to_value(BlockDto::from(block)).unwrap();
+        dto["time"] = serde_json::to_value(time).unwrap();
+        BlockCandidate::try_from(serde_json::from_value::<BlockDto<Transaction>>(dto).unwrap()).ok().unwrap()
+    }
+
+    fn tip(&self, node: usize) -> String {
+        self.nodes[node].transactions.last_block().as_ref().unwrap().key().hash()
+    }
+}
+
+#[test]
+fn honest_nodes_converge_on_valid_block() {
+    let mut simulation = Simulation::new(4);
+    simulation.elect(0);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    assert!(simulation.propose(0, &block).iter().all(|(_, outcome)| matches!(outcome, Ok(true))));
+    for voter in 1..4 {
+        let block_valid = simulation.validate(voter);
+        simulation.vote(voter, block_valid);
+    }
+    simulation.vote(0, true);
+    for node in 1..4 {
+        assert_eq!(simulation.settle(node), Some(true));
+    }
+    assert_eq!(simulation.tip(1), simulation.tip(2));
+    assert_eq!(simulation.tip(2), simulation.tip(3));
+}
+
+#[test]
+fn forger_cannot_propose_wallet_and_transaction_block_in_one_round() {
+    let mut simulation = Simulation::new(2);
+    simulation.elect(0);
+    let forger = simulation.peer_id(0);
+    let node = &mut simulation.nodes[1];
+    let registration = HotWallet::generate(&mut random::seeded(1));
+    let wallet_block = BlockCandidate::create_new(
+        vec![registration.wallet().clone()], node.wallets.last_block(),
+    ).ok().unwrap();
+    assert!(matches!(node.node_state.accept_wallet_block_proposal(forger, wallet_block), Ok(true)));
+
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    assert!(matches!(simulation.propose(0, &block)[0].1, Err(ProposalRejection::Equivocation)));
+}
+
+#[test]
+fn tied_bids_elect_the_same_forger_on_every_node() {
+    let mut simulation = Simulation::new(4);
+    let peers: Vec<PeerId> = (0..4).map(|index| simulation.peer_id(index)).collect();
+    for node in &mut simulation.nodes {
+        node.node_state.update_bid(StakeBid::bid(10, node.node_state.wallet_address()));
+        for (index, peer_id) in peers.iter().enumerate() {
+            if *peer_id != node.node_state.node_id() {
+                node.node_state.update_peers_bids(*peer_id, StakeBid::bid(10, [index as u8 + 10; 32]));
+            }
+        }
+    }
+    let elected: Vec<PeerId> = simulation.nodes.iter_mut()
+        .map(|node| node.node_state.elect_forger(election::election_seed("tip", 1), &ChainMisses::default()).unwrap().0)
+        .collect();
+    assert!(elected.iter().all(|forger| *forger == elected[0]));
+}
+
+#[test]
+fn nodes_holding_the_same_chains_draw_the_same_order() {
+    let mut simulation = Simulation::new(4);
+    let (steady, flaky) = ([12; 32], [13; 32]);
+    let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+    for (forger, delivered) in [(steady, true), (flaky, false), (flaky, false)] {
+        let round = BlockCandidate::create_new(vec![Transaction::stake_bid(10, forger)], stakes.last_block()).ok().unwrap();
+        stakes.submit_new_block(round);
+        if delivered {
+            let reward = Transaction::new(MINTING_WALLET_ADDRESS, forger, "Reward".to_string(), 10, Utc::now());
+            let transactions = &mut simulation.nodes[0].transactions;
+            let block = BlockCandidate::create_new(vec![reward], transactions.last_block()).ok().unwrap();
+            transactions.submit_new_block(block);
+        }
+    }
+    // the second node synced the same chains from the first
+    let synced_stakes: Blockchain<Transaction> = Blockchain::try_from(BlockchainDto::from(&stakes)).ok().unwrap();
+    simulation.nodes[1].transactions = Blockchain::try_from(BlockchainDto::from(&simulation.nodes[0].transactions)).ok().unwrap();
+    simulation.nodes[1].wallets = Blockchain::try_from(BlockchainDto::from(&simulation.nodes[0].wallets)).ok().unwrap();
+    let misses = [
+        ChainMisses::derive(&stakes, &simulation.nodes[0].transactions, &simulation.nodes[0].wallets),
+        ChainMisses::derive(&synced_stakes, &simulation.nodes[1].transactions, &simulation.nodes[1].wallets),
+    ];
+    assert!(misses.iter().all(|misses| misses.penalty_percent(flaky) == 40 && misses.penalty_percent(steady) == 0));
+
+    let bidders = [simulation.peer_id(2), simulation.peer_id(3)];
+    for node in &mut simulation.nodes[..2] {
+        node.node_state.update_peers_bids(bidders[0], StakeBid::bid(10, steady));
+        node.node_state.update_peers_bids(bidders[1], StakeBid::bid(10, flaky));
+    }
+    for round in 0..16 {
+        let seed = election::election_seed("tip", round);
+        let orders: Vec<Vec<PeerId>> = simulation.nodes[..2].iter_mut().zip(&misses)
+            .map(|(node, misses)| {
+                let winner = node.node_state.elect_forger(seed, misses).unwrap().0;
+                let runner_up = node.node_state.next_forger().unwrap().0;
+                vec![winner, runner_up]
+            })
+            .collect();
+        assert_eq!(orders[0], orders[1]);
+    }
+}
+
+#[test]
+fn blocks_from_peers_other_than_the_forger_are_ignored() {
+    let mut simulation = Simulation::new(3);
+    simulation.elect(0);
+    let spam = simulation.forge(2, TRANSACTION_FEE * 10);
+    for (_, outcome) in simulation.propose(2, &spam) {
+        assert!(matches!(outcome, Err(ProposalRejection::NotForger)));
+    }
+    assert!(simulation.nodes[1].node_state.pending_block().is_none());
+}
+
+#[test]
+fn equivocating_forger_is_marked_bad() {
+    let mut simulation = Simulation::new(3);
+    simulation.elect(0);
+    let first = simulation.forge(0, TRANSACTION_FEE);
+    let second = simulation.forge(0, TRANSACTION_FEE + 1);
+    simulation.propose(0, &first);
+    assert!(matches!(simulation.propose(0, &first)[0].1, Ok(false)));
+    for (index, outcome) in simulation.propose(0, &second) {
+        assert!(matches!(outcome, Err(ProposalRejection::Equivocation)));
+        let forger = simulation.peer_id(0);
+        assert!(simulation.nodes[index].node_state.bad_peers().contains(&forger));
+    }
+}
+
+#[test]
+fn overpaying_forger_is_voted_down() {
+    let mut simulation = Simulation::new(3);
+    simulation.elect(0);
+    let block = simulation.forge(0, TRANSACTION_FEE * 2);
+    simulation.propose(0, &block);
+    for voter in 1..3 {
+        let block_valid = simulation.validate(voter);
+        assert!(!block_valid);
+        simulation.vote(voter, block_valid);
+    }
+    simulation.vote(0, true);
+    assert_eq!(simulation.settle(1), Some(false));
+    assert_eq!(simulation.nodes[1].transactions.chain_length(), 1);
+    assert!(simulation.nodes[1].node_state.pending_block().is_none());
+}
+
+#[test]
+fn rejected_round_falls_back_to_runner_up() {
+    let mut simulation = Simulation::new(5);
+    let forger = simulation.auction(election::election_seed("tip", 1));
+    let others: Vec<usize> = (0..5).filter(|node| *node != forger).collect();
+    let block = simulation.forge(forger, TRANSACTION_FEE * 2);
+    simulation.propose(forger, &block);
+    for voter in &others {
+        simulation.vote(*voter, false);
+    }
+    simulation.vote(forger, true);
+    for node in &others {
+        assert_eq!(simulation.settle(*node), Some(false));
+    }
+    let runner_up = simulation.nodes[others[0]].node_state.block_creator().unwrap();
+    assert_ne!(runner_up, simulation.peer_id(forger));
+    for node in &others {
+        assert_eq!(simulation.nodes[*node].node_state.block_creator(), Some(runner_up));
+    }
+    assert_eq!(simulation.nodes[others[0]].node_state.vote_count(), 0);
+
+    let runner_up = (0..5).find(|node| simulation.peer_id(*node) == runner_up).unwrap();
+    let block = simulation.forge(runner_up, TRANSACTION_FEE);
+    let outcomes = simulation.propose(runner_up, &block);
+    assert!(outcomes.iter()
+        .filter(|(node, _)| *node != forger)
+        .all(|(_, outcome)| matches!(outcome, Ok(true))));
+
+    // a runner-up is still queued but the retry limit ends the round
+    let observer = others[others.len() - 1];
+    for _ in 1..MAX_REPROPOSALS {
+        assert!(simulation.nodes[observer].node_state.next_forger().is_some());
+    }
+    assert!(simulation.nodes[observer].node_state.next_forger().is_none());
+}
+
+#[test]
+fn forger_learns_why_its_block_was_rejected() {
+    let mut simulation = Simulation::new(4);
+    simulation.elect(0);
+    let block = simulation.forge(0, TRANSACTION_FEE * 2);
+    simulation.propose(0, &block);
+    for voter in 1..4 {
+        let reason = simulation.diagnose(voter).err().unwrap();
+        simulation.object(voter, reason);
+    }
+
+    let registry = StakeRegistry::derive(&Blockchain::<Transaction>::transaction_chain(vec![]), &simulation.nodes[0].transactions);
+    let result = simulation.nodes[0].node_state.summarize_votes(&registry);
+    assert!(!result.should_append_block());
+    let bad_reward = RejectionReason::InvalidPayout(TransactionValidationError::BadReward {
+        expected: TRANSACTION_FEE,
+        actual: TRANSACTION_FEE * 2,
+    });
+    assert_eq!(result.reasons(), &[(bad_reward, 3)]);
+    assert!(result.diagnosis().starts_with("rejected by 3 of 3 votes"));
+}
+
+#[test]
+fn equivocating_voter_counts_once_and_withholder_stalls_round() {
+    let mut simulation = Simulation::new(4);
+    simulation.elect(0);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    simulation.propose(0, &block);
+    simulation.vote(0, true);
+    simulation.vote(2, true);
+    simulation.vote(2, false);
+    // node 3 withholds its vote
+    assert_eq!(simulation.nodes[1].node_state.vote_count(), 2);
+    assert_eq!(simulation.settle(1), None);
+    assert_eq!(simulation.nodes[1].transactions.chain_length(), 1);
+}
+
+#[test]
+fn backup_node_of_a_wallet_votes_and_forges_once() {
+    let mut simulation = Simulation::new(4);
+    // node 3 is a backup of node 2, both bid from the same wallet
+    let wallets = [[10; 32], [11; 32], [12; 32], [12; 32]];
+    let peers: Vec<PeerId> = (0..4).map(|index| simulation.peer_id(index)).collect();
+    for (index, node) in simulation.nodes.iter_mut().enumerate() {
+        node.node_state.update_bid(StakeBid::bid(10, wallets[index]));
+        for (peer, peer_id) in peers.iter().enumerate().filter(|(peer, _)| *peer != index) {
+            node.node_state.update_peers_bids(*peer_id, StakeBid::bid(10, wallets[peer]));
+        }
+    }
+    let mut forgers = vec![];
+    for seed in 0..64u8 {
+        let (forger, _) = simulation.nodes[0].node_state.elect_forger([seed; 32], &ChainMisses::default()).unwrap();
+        forgers.push(peers.iter().position(|peer_id| *peer_id == forger).unwrap());
+    }
+    assert!(forgers.contains(&2) != forgers.contains(&3));
+
+    simulation.elect(1);
+    let block = simulation.forge(1, TRANSACTION_FEE);
+    simulation.propose(1, &block);
+    simulation.vote(2, true);
+    simulation.vote(3, true);
+    assert_eq!(simulation.nodes[0].node_state.vote_count(), 1);
+    // node 3 speaks for the wallet node 2 already voted with
+    assert_eq!(simulation.nodes[3].node_state.vote_count(), 0);
+    simulation.vote(1, false);
+    assert_eq!(simulation.settle(0), Some(false));
+}
+
+#[test]
+fn votes_without_a_pending_block_are_dropped() {
+    let mut simulation = Simulation::new(3);
+    simulation.elect(0);
+    simulation.vote(2, false);
+    assert_eq!(simulation.nodes[1].node_state.vote_count(), 0);
+    // the forger's block is with its peers
+    assert_eq!(simulation.nodes[0].node_state.vote_count(), 1);
+    simulation.vote(0, true);
+    assert_eq!(simulation.settle(1), None);
+    assert_eq!(simulation.nodes[1].transactions.chain_length(), 1);
+}
+
+#[test]
+fn round_settles_without_a_peer_that_went_offline() {
+    let mut simulation = Simulation::new(4);
+    simulation.elect(0);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    simulation.propose(0, &block);
+    simulation.vote(0, true);
+    simulation.vote(1, true);
+    assert_eq!(simulation.settle(2), None);
+
+    let gone = simulation.peer_id(3);
+    let observer = &mut simulation.nodes[2].node_state;
+    assert!(observer.mark_peer_offline(gone, Utc::now()));
+    assert!(observer.mark_peer_online(&gone).is_some());
+    assert_eq!(simulation.settle(2), Some(true));
+    assert_eq!(simulation.nodes[2].transactions.chain_length(), 2);
+    // the next round expects it again
+    assert!(!simulation.nodes[2].node_state.presence().is_excused(&gone));
+}
+
+#[test]
+fn bids_of_peers_that_went_offline_are_not_drawn() {
+    let mut simulation = Simulation::new(2);
+    let gone = simulation.peer_id(1);
+    let observer = &mut simulation.nodes[0].node_state;
+    observer.update_peers_bids(gone, StakeBid::bid(10, [11; 32]));
+    assert!(observer.mark_peer_offline(gone, Utc::now()));
+    assert!(observer.peers_bids().is_empty());
+    assert!(observer.elect_forger([1; 32], &ChainMisses::default()).is_none());
+}
+
+#[test]
+fn peers_are_bound_to_wallets_by_bids_the_wallet_signed() {
+    let mut rng = random::seeded(46);
+    let bidder = HotWallet::generate(&mut rng);
+    let mut simulation = Simulation::new(2);
+    let peer = simulation.peer_id(1);
+    let node = &mut simulation.nodes[0];
+    let registration = BlockCandidate::create_new(vec![bidder.wallet().clone()], node.wallets.last_block()).ok().unwrap();
+    node.wallets.submit_new_block(registration);
+
+    let unsigned = StakeBid::bid(10, bidder.address());
+    assert_eq!(
+        node.node_state.accept_peer_bid(peer, unsigned, &node.wallets, 100),
+        Err(TransactionValidationError::MissingSignature)
+    );
+    let elsewhere = StakeBid::signed(10, &simulated_peer_id(0).to_base58(), &bidder, &mut rng).ok().unwrap();
+    assert_eq!(
+        node.node_state.accept_peer_bid(peer, elsewhere, &node.wallets, 100),
+        Err(TransactionValidationError::BadSignature)
+    );
+    assert_eq!(node.node_state.voter(&peer), Voter::Peer(peer));
+    assert!(node.node_state.peers_bids().is_empty());
+
+    let signed = StakeBid::signed(10, &peer.to_base58(), &bidder, &mut rng).ok().unwrap();
+    assert_eq!(
+        node.node_state.accept_peer_bid(peer, signed.clone(), &node.wallets, 5),
+        Err(TransactionValidationError::InsufficientBalance { have: 5, need: 10 })
+    );
+    assert!(node.node_state.accept_peer_bid(peer, signed, &node.wallets, 100).is_ok());
+    assert_eq!(node.node_state.voter(&peer), Voter::Wallet(bidder.address()));
+}
+
+#[test]
+fn partial_blocks_wait_for_the_interval_and_overfilled_ones_are_rejected() {
+    let mut simulation = Simulation::new(1);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    let node = &mut simulation.nodes[0];
+    node.transactions.submit_new_block(block);
+    // a genesis with blocks of two transactions
+    let small_blocks = UpgradeSchedule::new(
+        ConsensusRules::new(TRANSACTION_FEE, TRANSFER_FEE, 2, SignatureScheme::RsaPssSha512).with_state_roots(),
+    );
+    let schedule = node.node_state.governance().schedule(&small_blocks, &node.transactions);
+    assert_eq!(schedule.rules_at(node.transactions.chain_length()).block_size(), 2);
+
+    let transfer = |amount| Transaction::new([1; 32], [2; 32], "".to_string(), amount, Utc::now());
+    let hour = std::time::Duration::from_secs(3600);
+    let stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+    let no_rules = ChainRules::default();
+    node.transactions.add_uncommitted(transfer(1));
+    assert!(dispatch::try_forge_transaction_block(&mut node.transactions, &stakes, [10; 32], &schedule, &no_rules, hour).is_err());
+    let partial = dispatch::try_forge_transaction_block(
+        &mut node.transactions, &stakes, [10; 32], &schedule, &no_rules, std::time::Duration::ZERO,
+    ).ok().unwrap();
+    assert!(partial.key().raw_state_root().is_some());
+    assert_eq!(partial.data().iter().filter(|transaction| transaction.source_address() == [1; 32]).count(), 1);
+
+    node.transactions.add_uncommitted(transfer(2));
+    node.transactions.add_uncommitted(transfer(3));
+    let full = dispatch::try_forge_transaction_block(&mut node.transactions, &stakes, [10; 32], &schedule, &no_rules, hour).ok().unwrap();
+    assert_eq!(full.data().iter().filter(|transaction| transaction.source_address() == [1; 32]).count(), 2);
+
+    let overfilled = BlockCandidate::create_new(
+        vec![transfer(1), transfer(2), transfer(3)], node.transactions.last_block(),
+    ).ok().unwrap();
+    let validator = TransactionValidator::with_upgrades(&node.wallets, &node.transactions, &schedule);
+    assert!(matches!(validator.diagnose(&overfilled), Err(RejectionReason::Malformed(_))));
+}
+
+#[test]
+fn forgers_do_not_propose_blocks_breaking_the_genesis_rules() {
+    let mut simulation = Simulation::new(1);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    let node = &mut simulation.nodes[0];
+    node.transactions.submit_new_block(block);
+    let schedule = node.node_state.governance().schedule(&UPGRADE_SCHEDULE, &node.transactions);
+    let stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+    let genesis = RulesConfig::default().with_validator(TRANSACTIONS_CHAIN, "no-self-transfers");
+    let rules = RuleRegistry::new().rules(&genesis).ok().unwrap();
+    let now = std::time::Duration::ZERO;
+
+    node.transactions.add_uncommitted(Transaction::new([1; 32], [1; 32], "".to_string(), 1, Utc::now()));
+    assert!(dispatch::try_forge_transaction_block(
+        &mut node.transactions, &stakes, [10; 32], &schedule, &ChainRules::default(), now,
+    ).is_ok());
+    assert!(dispatch::try_forge_transaction_block(
+        &mut node.transactions, &stakes, [10; 32], &schedule, rules.transactions(), now,
+    ).is_err());
+}
+
+#[test]
+fn voters_recompute_the_state_root_forgers_put_in_the_header() {
+    let simulation = Simulation::new(1);
+    let node = &simulation.nodes[0];
+    let stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+    let unrooted = simulation.forge(0, TRANSACTION_FEE);
+    let state_root = snapshot::account_state_root(&node.transactions, unrooted.data(), &stakes);
+    let rooted = BlockCandidate::create_new(unrooted.data().clone(), node.transactions.last_block())
+        .ok().unwrap()
+        .with_state_root(state_root);
+    assert_ne!(rooted.key().hash(), unrooted.key().hash());
+
+    let voter = TransactionValidator::new(&node.wallets, &node.transactions).with_stakes(&stakes);
+    assert!(voter.diagnose(&rooted).is_ok());
+    assert!(matches!(voter.diagnose(&unrooted), Err(RejectionReason::Malformed(_))));
+    // replays of history have no stake state to recompute roots from
+    assert!(TransactionValidator::new(&node.wallets, &node.transactions).diagnose(&unrooted).is_ok());
+
+    let mut drifted = Blockchain::<Transaction>::transaction_chain(vec![]);
+    let bid = BlockCandidate::create_new(vec![Transaction::stake_bid(5, [10; 32])], drifted.last_block()).ok().unwrap();
+    drifted.submit_new_block(bid);
+    let drifted_voter = TransactionValidator::new(&node.wallets, &node.transactions).with_stakes(&drifted);
+    assert!(matches!(drifted_voter.diagnose(&rooted), Err(RejectionReason::StateRootMismatch { .. })));
+
+    let received = BlockCandidate::try_from(BlockDto::from(rooted)).ok().unwrap();
+    assert_eq!(received.key().raw_state_root(), Some(state_root));
+}
+
+#[test]
+fn stale_sync_is_not_adopted() {
+    let mut simulation = Simulation::new(2);
+    let block = simulation.forge(0, TRANSACTION_FEE);
+    simulation.nodes[0].transactions.submit_new_block(block);
+    let tip = simulation.tip(0);
+
+    let stale = Blockchain::<Transaction>::transaction_chain(vec![]);
+    dispatch::adopt_if_preferred(&mut simulation.nodes[0].transactions, stale);
+    assert_eq!(simulation.tip(0), tip);
+    assert_eq!(simulation.nodes[0].transactions.chain_length(), 2);
+}
+
+#[test]
+fn healed_partition_reorgs_onto_the_longer_branch() {
+    let mut simulation = Simulation::new(2);
+    for (node, blocks) in [(0, 2), (1, 1)] {
+        for _ in 0..blocks {
+            let block = simulation.forge(node, TRANSACTION_FEE);
+            simulation.nodes[node].transactions.submit_new_block(block);
+        }
+    }
+    let (winner, height, tip) = (simulation.peer_id(0), simulation.nodes[0].transactions.chain_length(), simulation.tip(0));
+
+    let loser = &mut simulation.nodes[1];
+    let depth = loser.node_state.max_reorg_depth();
+    let count = match loser.node_state.anti_entropy_mut().check_tip(winner, height, &tip, &loser.transactions, depth, Utc::now()) {
+        TipCheck::Probe(count) => count,
+        other => panic!("expected a probe, got {:?}", other),
+    };
+    let headers = divergence::recent_headers(&simulation.nodes[0].transactions, count);
+    let synced = Blockchain::try_from(BlockchainDto::from(&simulation.nodes[0].transactions)).ok().unwrap();
+
+    let loser = &mut simulation.nodes[1];
+    let divergence = loser.node_state.anti_entropy_mut()
+        .resolve(winner, height, &headers, &loser.transactions, depth)
+        .unwrap();
+    assert!(divergence.forked());
+    assert_eq!(divergence.fork_choice(), ForkChoice::Remote);
+    let rollback = dispatch::adopt_if_preferred(&mut loser.transactions, synced).unwrap();
+    assert_eq!(rollback.depth(), 1);
+    assert_eq!(simulation.tip(1), tip);
+}
+
+#[test]
+fn skewed_block_times_are_rejected() {
+    let mut simulation = Simulation::new(1);
+    for _ in 0..3 {
+        let block = simulation.forge(0, TRANSACTION_FEE);
+        simulation.nodes[0].transactions.submit_new_block(block);
+    }
+    let node = &simulation.nodes[0];
+    let median_time_past = node.transactions.median_time_past().unwrap();
+    let validator = TransactionValidator::new(&node.wallets, &node.transactions);
+    assert!(validator.diagnose(&simulation.forge(0, TRANSACTION_FEE)).is_ok());
+
+    let backdated = Simulation::retime(simulation.forge(0, TRANSACTION_FEE), median_time_past - Duration::seconds(1));
+    assert_eq!(validator.diagnose(&backdated), Err(RejectionReason::TimeTooEarly { median_time_past }));
+    let ahead = Simulation::retime(simulation.forge(0, TRANSACTION_FEE), Utc::now() + Duration::minutes(10));
+    assert!(matches!(validator.diagnose(&ahead), Err(RejectionReason::TimeTooLate { .. })));
+    // a voter whose clock lags the network accepts it once the offset is applied
+    let lagging = TransactionValidator::new(&node.wallets, &node.transactions).with_clock_offset(Duration::minutes(10));
+    assert!(lagging.diagnose(&ahead).is_ok());
+}
+
+#[test]
+fn node_short_of_peers_stays_out_of_rounds() {
+    let mut node_state = NodeState::init(simulated_peer_id(0), StakeBid::bid(0, [10; 32])).with_min_peers(3);
+    assert!(!node_state.enough_peers(0));
+    assert!(!node_state.enough_peers(2));
+    assert!(node_state.enough_peers(3));
+    assert!(!node_state.enough_peers(1));
+}
+
+#[test]
+fn unpublished_own_bid_is_not_drawn() {
+    let mut node_state = NodeState::init(simulated_peer_id(0), StakeBid::bid(50, [10; 32]));
+    node_state.update_peers_bids(simulated_peer_id(1), StakeBid::bid(10, [11; 32]));
+    for seed in 0..16u8 {
+        assert_eq!(node_state.elect_forger([seed; 32], &ChainMisses::default()).unwrap().0, simulated_peer_id(1));
+    }
+
+    node_state.update_bid(StakeBid::bid(50, [10; 32]));
+    let own_wins = (0..16u8).any(|seed| node_state.elect_forger([seed; 32], &ChainMisses::default()).unwrap().0 == simulated_peer_id(0));
+    assert!(own_wins);
+}
+
+#[test]
+fn receipts_are_processed_once_per_block_height() {
+    let mut node_state = NodeState::init(simulated_peer_id(0), StakeBid::bid(0, [10; 32])).with_max_reorg_depth(4);
+    assert!(!node_state.mark_receipt(u64::MAX, 10));
+    assert!(node_state.mark_receipt(9, 10));
+    // a lower height announced later is still a different block
+    assert!(node_state.mark_receipt(8, 10));
+    assert!(!node_state.mark_receipt(9, 10));
+    assert!(!node_state.mark_receipt(5, 10));
+    node_state.rewind_receipts(9);
+    assert!(node_state.mark_receipt(9, 10));
+    assert!(!node_state.mark_receipt(8, 10));
+}
@@ -0,0 +1,181 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Transaction;
+use crate::blockchain::core::Blockchain;
+use crate::config::GossipConfig;
+use crate::network::NodeState;
+use crate::network::communication;
+
+#[derive(Serialize, Deserialize)]
+pub enum SyncState {
+    Standalone,
+    NotSynced,
+    Synced(DateTime<Utc>),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeStatus {
+    // base58, as printed in logs
+    node_id: String,
+    chain_height: u64,
+    finalized_height: u64,
+    tip_hash: Option<String>,
+    sync_state: SyncState,
+    sync_progress: String,
+    mempool_size: usize,
+    orphan_count: usize,
+    peer_count: usize,
+    // fewer connected peers keep the node out of consensus
+    min_peers: usize,
+    // peers gone since they last connected, not waited for in rounds
+    offline_peers: usize,
+    // messages held in the outbox until they can be published
+    unpublished: usize,
+    // seconds the network median clock is ahead of ours, none before enough peers reported
+    clock_offset: Option<i64>,
+    epoch: u64,
+    validator: Option<String>,
+    // validators that stopped bidding and voting, not waited for until they bid again
+    inactive_validators: usize,
+    own_stake: i64,
+    pending_votes: usize,
+    awaiting_block: bool,
+    gossip: GossipConfig,
+}
+
+impl NodeStatus {
+    pub fn collect(
+        node_state: &NodeState, transactions: &Blockchain<Transaction>,
+        stakes: &Blockchain<Transaction>, peer_count: usize, gossip: &GossipConfig,
+    ) -> NodeStatus {
+        let sync_state = match (peer_count, node_state.synced_at()) {
+            (0, _) => SyncState::Standalone,
+            (_, None) => SyncState::NotSynced,
+            (_, Some(synced_at)) => SyncState::Synced(synced_at)
+        };
+        NodeStatus {
+            node_id: node_state.node_id().to_base58(),
+            chain_height: transactions.chain_length(),
+            finalized_height: transactions.finalized_height(node_state.reorg_depth(transactions)),
+            tip_hash: transactions.last_block()
+                .as_ref()
+                .map(|block| block.key().hash()),
+            sync_state,
+            sync_progress: node_state.sync().describe(transactions.chain_length()),
+            mempool_size: transactions.uncommitted_data().len(),
+            orphan_count: node_state.orphans().size(),
+            peer_count,
+            min_peers: node_state.min_peers(),
+            offline_peers: node_state.presence().offline_count(),
+            unpublished: communication::held_messages(),
+            clock_offset: node_state.clock().median_offset(),
+            // every staking round appends one block to the stakes chain
+            epoch: stakes.chain_length(),
+            validator: node_state.block_creator().map(|validator| validator.to_base58()),
+            inactive_validators: node_state.inactivity().inactive_count(),
+            own_stake: node_state.node_bid().stake(),
+            pending_votes: node_state.vote_count(),
+            awaiting_block: node_state.pending_block().is_some(),
+            gossip: gossip.clone(),
+        }
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+    pub fn chain_height(&self) -> u64 {
+        self.chain_height
+    }
+    pub fn finalized_height(&self) -> u64 {
+        self.finalized_height
+    }
+    pub fn tip_hash(&self) -> Option<&str> {
+        self.tip_hash.as_deref()
+    }
+    pub fn sync_state(&self) -> &SyncState {
+        &self.sync_state
+    }
+    pub fn sync_progress(&self) -> &str {
+        &self.sync_progress
+    }
+    pub fn mempool_size(&self) -> usize {
+        self.mempool_size
+    }
+    pub fn orphan_count(&self) -> usize {
+        self.orphan_count
+    }
+    pub fn peer_count(&self) -> usize {
+        self.peer_count
+    }
+    pub fn min_peers(&self) -> usize {
+        self.min_peers
+    }
+    pub fn offline_peers(&self) -> usize {
+        self.offline_peers
+    }
+    pub fn unpublished(&self) -> usize {
+        self.unpublished
+    }
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock_offset
+    }
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+    pub fn validator(&self) -> Option<&str> {
+        self.validator.as_deref()
+    }
+    pub fn own_stake(&self) -> i64 {
+        self.own_stake
+    }
+    pub fn pending_votes(&self) -> usize {
+        self.pending_votes
+    }
+    pub fn awaiting_block(&self) -> bool {
+        self.awaiting_block
+    }
+    pub fn gossip(&self) -> &GossipConfig {
+        &self.gossip
+    }
+
+    pub fn describe(&self) -> String {
+        let sync_state = match &self.sync_state {
+            SyncState::Standalone => String::from("standalone (no peers)"),
+            SyncState::NotSynced => String::from("not synced"),
+            SyncState::Synced(synced_at) => format!("synced at {}", synced_at.to_rfc3339())
+        };
+        let validator = match &self.validator {
+            None => String::from("none"),
+            Some(validator) if *validator == self.node_id => format!("{} (this node)", validator),
+            Some(validator) => validator.clone()
+        };
+        let short_of_peers = match self.peer_count < self.min_peers {
+            true => format!(", not enough for consensus (needs {})", self.min_peers),
+            false => String::new(),
+        };
+        let clock = match self.clock_offset {
+            None => String::from("not enough peer samples"),
+            Some(offset) => format!("{:+}s from network median", offset)
+        };
+        format!(
+            "Node: {}\n\
+             Chain height: {} (finalized below {})\n\
+             Tip: {}\n\
+             Sync: {} ({})\n\
+             Mempool: {} pending, {} orphaned\n\
+             Peers: {} ({} offline){}, {} messages awaiting publish\n\
+             Clock: {}\n\
+             Gossip: {}\n\
+             Epoch: {}, validator: {}, {} inactive validators\n\
+             Own stake: {}\n\
+             Votes: {}{}",
+            self.node_id, self.chain_height, self.finalized_height,
+            self.tip_hash.as_deref().unwrap_or("none"), sync_state, self.sync_progress,
+            self.mempool_size, self.orphan_count, self.peer_count, self.offline_peers, short_of_peers, self.unpublished,
+            clock, self.gossip.describe(),
+            self.epoch, validator, self.inactive_validators, self.own_stake, self.pending_votes,
+            if self.awaiting_block { " (block awaiting votes)" } else { "" }
+        )
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Duration, Utc};
+use libp2p::PeerId;
+
+use crate::report;
+
+// how long peers get to announce their heights before a download source is picked
+pub static DISCOVERY_SECONDS: i64 = 3;
+// a chosen peer that does not deliver its chain in time is skipped for the next best one
+pub static SYNC_TIMEOUT_SECONDS: i64 = 30;
+pub static MAX_SYNC_ATTEMPTS: u32 = 3;
+// peers this close to the tallest are picked by latency and reliability instead of height
+pub static SYNC_HEIGHT_TOLERANCE: u64 = 2;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SyncPhase {
+    Discovering,
+    Downloading {
+        peer: PeerId,
+        from: u64,
+        to: u64,
+    },
+    Verifying {
+        peer: PeerId,
+        from: u64,
+        to: u64,
+    },
+    Done,
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum SyncAction {
+    RequestHeights,
+    Download(PeerId),
+}
+
+// One sync session at a time: peers announce their chain heights, the tallest one is asked for
+// its chains, which are verified before being adopted. Timeouts and rejected chains fall back to
+// the next tallest peer. Among peers about as tall, the cheapest one by network::latency is asked.
+pub struct SyncManager {
+    phase: SyncPhase,
+    phase_started: DateTime<Utc>,
+    heights: HashMap<PeerId, u64>,
+    failed: HashSet<PeerId>,
+    attempts: u32,
+    blocks_per_second: Option<f64>,
+    costs: HashMap<PeerId, f64>,
+}
+
+impl Default for SyncManager {
+    fn default() -> Self {
+        SyncManager::new()
+    }
+}
+
+impl SyncManager {
+    pub fn new() -> SyncManager {
+        SyncManager {
+            phase: SyncPhase::Done,
+            phase_started: DateTime::<Utc>::MIN_UTC,
+            heights: HashMap::new(),
+            failed: HashSet::new(),
+            attempts: 0,
+            blocks_per_second: None,
+            costs: HashMap::new(),
+        }
+    }
+
+    pub fn phase(&self) -> SyncPhase {
+        self.phase
+    }
+    pub fn blocks_per_second(&self) -> Option<f64> {
+        self.blocks_per_second
+    }
+
+    // a new session, e.g. when a peer joins, forgets heights announced in the last one
+    pub fn begin(&mut self, now: DateTime<Utc>) -> SyncAction {
+        self.heights.clear();
+        self.failed.clear();
+        self.attempts = 0;
+        self.enter(SyncPhase::Discovering, now);
+        SyncAction::RequestHeights
+    }
+
+    pub fn set_peer_costs(&mut self, costs: HashMap<PeerId, f64>) {
+        self.costs = costs;
+    }
+
+    pub fn record_height(&mut self, peer: PeerId, height: u64) {
+        self.heights.insert(peer, height);
+    }
+
+    pub fn tick(&mut self, now: DateTime<Utc>, local_height: u64) -> Option<SyncAction> {
+        let elapsed = now - self.phase_started;
+        match self.phase {
+            SyncPhase::Discovering if elapsed < Duration::seconds(DISCOVERY_SECONDS) => None,
+            SyncPhase::Discovering => self.download_next(now, local_height),
+            // heights of a finished session are stale, only begin starts another one
+            SyncPhase::Done => None,
+            SyncPhase::Downloading { peer, .. } | SyncPhase::Verifying { peer, .. } => {
+                if elapsed < Duration::seconds(SYNC_TIMEOUT_SECONDS) {
+                    return None;
+                }
+                report!("Sync from {} timed out", peer);
+                self.fail(now, local_height)
+            }
+        }
+    }
+
+    // whether the chain received from a peer is the one this session asked for
+    pub fn receive_chain(&mut self, peer: PeerId, now: DateTime<Utc>) -> bool {
+        match self.phase {
+            SyncPhase::Downloading { peer: source, from, to } if source == peer => {
+                self.enter(SyncPhase::Verifying { peer, from, to }, now);
+                true
+            }
+            _ => false
+        }
+    }
+
+    // the received chain was adopted, a source that fell short of its announced height failed
+    pub fn complete(&mut self, now: DateTime<Utc>, local_height: u64) -> Option<SyncAction> {
+        if let SyncPhase::Verifying { from, to, .. } = self.phase {
+            if local_height < to {
+                report!("Sync reached block {} of the announced {}", local_height, to);
+                return self.fail(now, local_height);
+            }
+            let seconds = (now - self.phase_started).num_milliseconds().max(1) as f64 / 1000.0;
+            self.blocks_per_second = Some(to.saturating_sub(from) as f64 / seconds);
+        }
+        self.attempts = 0;
+        self.enter(SyncPhase::Done, now);
+        None
+    }
+
+    // the current source misbehaved or went silent, another peer gets a chance
+    pub fn fail(&mut self, now: DateTime<Utc>, local_height: u64) -> Option<SyncAction> {
+        if let SyncPhase::Downloading { peer, .. } | SyncPhase::Verifying { peer, .. } = self.phase {
+            self.failed.insert(peer);
+        }
+        if self.attempts >= MAX_SYNC_ATTEMPTS {
+            report!("Sync gave up after {} attempts", self.attempts);
+            self.enter(SyncPhase::Done, now);
+            return None;
+        }
+        self.download_next(now, local_height)
+    }
+
+    pub fn progress(&self, local_height: u64) -> Option<f64> {
+        match self.phase {
+            SyncPhase::Downloading { from, to, .. } | SyncPhase::Verifying { from, to, .. } if to > from => {
+                Some(local_height.saturating_sub(from) as f64 / (to - from) as f64 * 100.0)
+            }
+            _ => None
+        }
+    }
+
+    pub fn describe(&self, local_height: u64) -> String {
+        let progress = self.progress(local_height).unwrap_or(0.0);
+        let phase = match self.phase {
+            SyncPhase::Discovering => String::from("discovering peer heights"),
+            SyncPhase::Downloading { peer, to, .. } => {
+                format!("downloading {}/{} blocks from {} ({:.0}%)", local_height, to, peer, progress)
+            }
+            SyncPhase::Verifying { peer, to, .. } => {
+                format!("verifying {} blocks from {} ({:.0}%)", to, peer, progress)
+            }
+            SyncPhase::Done => String::from("idle"),
+        };
+        match self.blocks_per_second {
+            None => phase,
+            Some(rate) => format!("{}, last sync {:.1} blocks/s", phase, rate)
+        }
+    }
+
+    fn download_next(&mut self, now: DateTime<Utc>, local_height: u64) -> Option<SyncAction> {
+        let candidates: Vec<(PeerId, u64)> = self.heights.iter()
+            .filter(|(peer, height)| **height > local_height && !self.failed.contains(peer))
+            .map(|(peer, height)| (*peer, *height))
+            .collect();
+        let tallest = candidates.iter().map(|(_, height)| *height).max().unwrap_or(0);
+        // unmeasured peers all cost the same, the taller one wins then
+        let cost = |peer: &PeerId| self.costs.get(peer).copied().unwrap_or(f64::MAX);
+        let source = candidates.into_iter()
+            .filter(|(_, height)| height.saturating_add(SYNC_HEIGHT_TOLERANCE) >= tallest)
+            .min_by(|(peer, height), (other, other_height)| {
+                cost(peer).total_cmp(&cost(other))
+                    .then(other_height.cmp(height))
+                    .then(other.to_bytes().cmp(&peer.to_bytes()))
+            });
+        match source {
+            None => {
+                if self.phase != SyncPhase::Done {
+                    self.enter(SyncPhase::Done, now);
+                }
+                None
+            }
+            Some((peer, to)) => {
+                self.attempts += 1;
+                self.enter(SyncPhase::Downloading { peer, from: local_height, to }, now);
+                Some(SyncAction::Download(peer))
+            }
+        }
+    }
+
+    fn enter(&mut self, phase: SyncPhase, now: DateTime<Utc>) {
+        self.phase = phase;
+        self.phase_started = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::{Duration, Utc};
+    use libp2p::PeerId;
+
+    use crate::network::sync::{SyncAction, SyncManager, SyncPhase};
+
+    #[test]
+    fn downloads_from_tallest_peer_and_falls_back_on_timeout() {
+        let (short, tall, tallest) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut sync = SyncManager::new();
+        let start = Utc::now();
+        assert_eq!(sync.begin(start), SyncAction::RequestHeights);
+        sync.record_height(short, 4);
+        sync.record_height(tall, 20);
+        sync.record_height(tallest, 30);
+        assert_eq!(sync.tick(start, 5), None);
+
+        let discovered = start + Duration::seconds(3);
+        assert_eq!(sync.tick(discovered, 5), Some(SyncAction::Download(tallest)));
+        let timed_out = discovered + Duration::seconds(30);
+        assert_eq!(sync.tick(timed_out, 5), Some(SyncAction::Download(tall)));
+        assert!(!sync.receive_chain(tallest, timed_out));
+        assert!(sync.receive_chain(tall, timed_out));
+        assert_eq!(sync.progress(5), Some(0.0));
+
+        assert_eq!(sync.complete(timed_out + Duration::seconds(5), 20), None);
+        assert_eq!(sync.phase(), SyncPhase::Done);
+        assert_eq!(sync.blocks_per_second(), Some(3.0));
+        assert_eq!(sync.tick(timed_out + Duration::seconds(6), 20), None);
+    }
+
+    #[test]
+    fn sessions_end_once_the_announced_height_is_reached() {
+        let (tall, short) = (PeerId::random(), PeerId::random());
+        let mut sync = SyncManager::new();
+        let start = Utc::now();
+        sync.begin(start);
+        sync.record_height(tall, 30);
+        sync.record_height(short, 10);
+
+        let discovered = start + Duration::seconds(3);
+        assert_eq!(sync.tick(discovered, 5), Some(SyncAction::Download(tall)));
+        assert!(sync.receive_chain(tall, discovered));
+        // the adopted chain stopped short of what the peer announced
+        assert_eq!(sync.complete(discovered, 8), Some(SyncAction::Download(short)));
+        assert!(sync.receive_chain(short, discovered));
+        assert_eq!(sync.complete(discovered, 10), None);
+        assert_eq!(sync.phase(), SyncPhase::Done);
+        // the height announced by the failed peer does not start downloads again
+        assert_eq!(sync.tick(discovered + Duration::seconds(60), 10), None);
+        assert_eq!(sync.begin(discovered + Duration::seconds(60)), SyncAction::RequestHeights);
+    }
+
+    #[test]
+    fn prefers_cheap_peers_among_those_about_as_tall() {
+        let (slow, fast, behind) = (PeerId::random(), PeerId::random(), PeerId::random());
+        let mut sync = SyncManager::new();
+        let start = Utc::now();
+        sync.begin(start);
+        sync.record_height(slow, 30);
+        sync.record_height(fast, 29);
+        sync.record_height(behind, 20);
+        sync.set_peer_costs(HashMap::from([(slow, 0.8), (fast, 0.05), (behind, 0.01)]));
+
+        let discovered = start + Duration::seconds(3);
+        assert_eq!(sync.tick(discovered, 5), Some(SyncAction::Download(fast)));
+        assert_eq!(sync.fail(discovered, 5), Some(SyncAction::Download(slow)));
+    }
+}
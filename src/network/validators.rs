@@ -0,0 +1,298 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{access, Address, Transaction, Wallet};
+use crate::blockchain::core::Blockchain;
+use crate::blockchain::protocol::{MINTING_WALLET_ADDRESS, REWARD_WALLET_ADDRESS};
+use crate::display::table::Table;
+
+// each round missed in a row costs the validator this share of its election weight
+pub static MISS_PENALTY_PERCENT: u64 = 20;
+pub static MAX_MISS_PENALTY_PERCENT: u64 = 80;
+// rounds looked back on for misses, far more than it takes to reach the largest penalty
+static MISS_WINDOW: usize = 16;
+
+#[derive(Default, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct ValidatorRecord {
+    blocks_forged: u64,
+    // rounds the validator was elected for that ended without its block
+    blocks_missed: u64,
+    missed_in_a_row: u64,
+    votes_cast: u64,
+    rewards_earned: i64,
+    times_slashed: u64,
+    amount_slashed: i64,
+}
+
+impl ValidatorRecord {
+    pub fn blocks_forged(&self) -> u64 {
+        self.blocks_forged
+    }
+    pub fn blocks_missed(&self) -> u64 {
+        self.blocks_missed
+    }
+    pub fn missed_in_a_row(&self) -> u64 {
+        self.missed_in_a_row
+    }
+    pub fn votes_cast(&self) -> u64 {
+        self.votes_cast
+    }
+    pub fn rewards_earned(&self) -> i64 {
+        self.rewards_earned
+    }
+    pub fn times_slashed(&self) -> u64 {
+        self.times_slashed
+    }
+
+    // blocks forged out of the rounds the validator was elected for
+    pub fn uptime(&self) -> Option<f64> {
+        let elected = self.blocks_forged + self.blocks_missed;
+        match elected {
+            0 => None,
+            _ => Some(self.blocks_forged as f64 / elected as f64 * 100.0)
+        }
+    }
+
+    fn missed(&mut self) {
+        self.blocks_missed += 1;
+        self.missed_in_a_row += 1;
+    }
+}
+
+// What validators did in the rounds this node saw since it started, by wallet. The stats are
+// local, like bans, so they are only reported, the election weighs the misses the chains
+// record, see ChainMisses.
+#[derive(Default)]
+pub struct ValidatorStats {
+    records: HashMap<Address, ValidatorRecord>,
+    // forger of the round in progress, settled by forged or missed
+    elected: Option<Address>,
+}
+
+impl ValidatorStats {
+    pub fn new() -> ValidatorStats {
+        ValidatorStats::default()
+    }
+
+    pub fn record(&self, validator: Address) -> Option<&ValidatorRecord> {
+        self.records.get(&validator)
+    }
+
+    // a forger elected while the previous one is still unsettled never delivered its block
+    pub fn elected(&mut self, forger: Address) {
+        if let Some(previous) = self.elected.replace(forger) {
+            self.entry(previous).missed();
+        }
+    }
+
+    pub fn forged(&mut self, block: &[Transaction]) {
+        if let Some(forger) = self.elected.take() {
+            let reward: i64 = block.iter()
+                .filter(|transaction| transaction.target_address() == forger && rewarded(transaction))
+                .map(Transaction::amount)
+                .sum();
+            let record = self.entry(forger);
+            record.blocks_forged += 1;
+            record.missed_in_a_row = 0;
+            record.rewards_earned += reward;
+        }
+    }
+
+    pub fn missed(&mut self) {
+        if let Some(forger) = self.elected.take() {
+            self.entry(forger).missed();
+        }
+    }
+
+    pub fn voted(&mut self, voter: Address) {
+        self.entry(voter).votes_cast += 1;
+    }
+
+    pub fn slashed(&mut self, validator: Address, amount: i64) {
+        let record = self.entry(validator);
+        record.times_slashed += 1;
+        record.amount_slashed += amount;
+    }
+
+    // most blocks forged first
+    pub fn records(&self) -> Vec<(Address, &ValidatorRecord)> {
+        let mut records: Vec<(Address, &ValidatorRecord)> = self.records.iter()
+            .map(|(address, record)| (*address, record))
+            .collect();
+        records.sort_by(|(address, record), (other_address, other)| {
+            other.blocks_forged.cmp(&record.blocks_forged).then(address.cmp(other_address))
+        });
+        records
+    }
+
+    pub fn table(&self) -> Table {
+        let records = self.records();
+        let mut table = Table::new(&[
+            "Validator", "Forged", "Missed", "In a row", "Uptime", "Votes", "Earned", "Slashed"
+        ]).with_numeric(&[1, 2, 3, 4, 5, 6, 7]);
+        for (address, record) in records {
+            table.push(vec![
+                access::encode_address(address),
+                record.blocks_forged.to_string(),
+                record.blocks_missed.to_string(),
+                record.missed_in_a_row.to_string(),
+                record.uptime().map_or(String::from("n/a"), |uptime| format!("{:.0}%", uptime)),
+                record.votes_cast.to_string(),
+                record.rewards_earned.to_string(),
+                format!("{} ({}x)", record.amount_slashed, record.times_slashed),
+            ], None);
+        }
+        table
+    }
+
+    fn entry(&mut self, validator: Address) -> &mut ValidatorRecord {
+        self.records.entry(validator).or_default()
+    }
+}
+
+// Rounds forgers missed as the chains record them, so every node holding the same chains
+// weighs the next election the same. Each stakes block holds the winning bid of a round, its
+// forger delivered if a transaction block paying it the reward, or a wallet block, was forged
+// after the round was drawn and before the next one.
+#[derive(Default)]
+pub struct ChainMisses {
+    in_a_row: HashMap<Address, u64>,
+}
+
+impl ChainMisses {
+    pub fn derive(
+        stakes: &Blockchain<Transaction>, transactions: &Blockchain<Transaction>, wallets: &Blockchain<Wallet>,
+    ) -> ChainMisses {
+        // newest first, with the time each round was drawn
+        let rounds: Vec<(Address, DateTime<Utc>)> = stakes.iter()
+            .take(MISS_WINDOW)
+            .filter_map(|block| Some((block.data().first()?.source_address(), block.time()?)))
+            .collect();
+        let since = match rounds.last() {
+            None => return ChainMisses::default(),
+            Some((_, drawn_at)) => *drawn_at,
+        };
+        let paid: Vec<(DateTime<Utc>, Vec<Address>)> = transactions.iter()
+            .map_while(|block| Some((block.time().filter(|time| *time >= since)?, block.data())))
+            .map(|(time, data)| {
+                let forgers = data.iter().filter(|transaction| rewarded(transaction)).map(Transaction::target_address);
+                (time, forgers.collect())
+            })
+            .collect();
+        let registered: Vec<DateTime<Utc>> = wallets.iter()
+            .map_while(|block| block.time().filter(|time| *time >= since))
+            .collect();
+
+        let mut in_a_row = HashMap::new();
+        let mut delivered = HashSet::new();
+        let mut next_round: Option<DateTime<Utc>> = None;
+        for (forger, drawn_at) in rounds {
+            let in_round = |time: &DateTime<Utc>| *time >= drawn_at && !next_round.is_some_and(|next| *time >= next);
+            let forged = paid.iter().any(|(time, forgers)| in_round(time) && forgers.contains(&forger))
+                || registered.iter().any(|time| in_round(time));
+            // only misses since the last round it delivered count
+            if forged {
+                delivered.insert(forger);
+            } else if !delivered.contains(&forger) {
+                *in_a_row.entry(forger).or_insert(0) += 1;
+            }
+            next_round = Some(drawn_at);
+        }
+        ChainMisses {
+            in_a_row,
+        }
+    }
+
+    pub fn missed_in_a_row(&self, validator: Address) -> u64 {
+        self.in_a_row.get(&validator).copied().unwrap_or(0)
+    }
+
+    pub fn penalty_percent(&self, validator: Address) -> u64 {
+        (self.missed_in_a_row(validator) * MISS_PENALTY_PERCENT).min(MAX_MISS_PENALTY_PERCENT)
+    }
+
+    // the bid a validator is drawn with, lowered while it keeps missing its rounds
+    pub fn election_weight(&self, bid: &Transaction) -> u64 {
+        bid.amount().max(0) as u64 * (100 - self.penalty_percent(bid.source_address())) / 100
+    }
+}
+
+// the block reward and the fees paid out to the forger, grants are minted too but earned by work
+fn rewarded(transaction: &Transaction) -> bool {
+    let source = transaction.source_address();
+    (source == MINTING_WALLET_ADDRESS && !transaction.is_grant()) || source == *REWARD_WALLET_ADDRESS
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::{Address, Transaction, Wallet};
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::network::validators::{ChainMisses, ValidatorStats};
+
+    fn record_round(stakes: &mut Blockchain<Transaction>, forger: Address) {
+        let block = BlockCandidate::create_new(vec![Transaction::stake_bid(100, forger)], stakes.last_block()).ok().unwrap();
+        stakes.submit_new_block(block);
+    }
+
+    #[test]
+    fn missed_blocks_count_in_a_row_until_the_next_forged_one() {
+        let (steady, flaky) = ([1; 32], [2; 32]);
+        let mut stats = ValidatorStats::new();
+        stats.elected(steady);
+        stats.forged(&[
+            Transaction::new([5; 32], [6; 32], "".to_string(), 40, Utc::now()),
+            Transaction::new(MINTING_WALLET_ADDRESS, steady, "Reward".to_string(), 10, Utc::now()),
+            Transaction::fee_payout(steady, 3),
+            Transaction::grant(steady, 50, 0),
+        ]);
+        stats.elected(flaky);
+        stats.missed();
+        stats.elected(flaky);
+        // elected again before its block arrived
+        stats.elected(flaky);
+        stats.voted(steady);
+        stats.slashed(flaky, 7);
+
+        let steady_record = stats.record(steady).unwrap();
+        assert_eq!((steady_record.blocks_forged(), steady_record.rewards_earned()), (1, 13));
+        assert_eq!(steady_record.votes_cast(), 1);
+        let flaky_record = stats.record(flaky).unwrap();
+        assert_eq!((flaky_record.blocks_missed(), flaky_record.missed_in_a_row()), (2, 2));
+        assert_eq!(flaky_record.times_slashed(), 1);
+        assert_eq!(flaky_record.uptime(), Some(0.0));
+
+        stats.forged(&[]);
+        assert_eq!(stats.record(flaky).unwrap().missed_in_a_row(), 0);
+        assert_eq!(stats.table().len(), 2);
+    }
+
+    #[test]
+    fn misses_are_read_from_the_rounds_the_chains_record() {
+        let (steady, flaky, registrar) = ([1; 32], [2; 32], [3; 32]);
+        let mut stakes = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+
+        record_round(&mut stakes, steady);
+        let reward = Transaction::new(MINTING_WALLET_ADDRESS, steady, "Reward".to_string(), 10, Utc::now());
+        let block = BlockCandidate::create_new(vec![reward], transactions.last_block()).ok().unwrap();
+        transactions.submit_new_block(block);
+        record_round(&mut stakes, registrar);
+        let block = BlockCandidate::create_new(vec![Wallet::new([4; 32], None)], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(block);
+        // two rounds in a row without a block
+        record_round(&mut stakes, flaky);
+        record_round(&mut stakes, flaky);
+
+        let misses = ChainMisses::derive(&stakes, &transactions, &wallets);
+        assert_eq!((misses.missed_in_a_row(steady), misses.missed_in_a_row(registrar)), (0, 0));
+        assert_eq!((misses.missed_in_a_row(flaky), misses.penalty_percent(flaky)), (2, 40));
+        assert_eq!(misses.election_weight(&Transaction::stake_bid(100, flaky)), 60);
+        assert_eq!(misses.election_weight(&Transaction::stake_bid(100, steady)), 100);
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+// Text for people, or for scripts driving a node through stdin one JSON document per line:
+// a command answers with exactly one, anything the node reports on its own is an event.
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // what the command being run has reported so far, commands run on the main loop's thread
+    static CAPTURE: RefCell<Option<Capture>> = const { RefCell::new(None) };
+}
+
+#[derive(Default)]
+struct Capture {
+    // the command printed its document, whatever it reports after is dropped
+    answered: bool,
+}
+
+// the same externally tagged form the rpc answers use
+#[derive(Serialize)]
+enum Report<'a> {
+    Event(&'a str),
+}
+
+// the println of everything the node tells its user
+#[macro_export]
+macro_rules! report {
+    ($($arg:tt)*) => {
+        $crate::output::line(format!($($arg)*))
+    };
+}
+
+pub fn enable_json() {
+    JSON.store(true, Ordering::Relaxed);
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+pub fn line(text: String) {
+    if !json() {
+        println!("{}", text);
+        return;
+    }
+    // a command answers with its document, the lines it reports along the way are dropped
+    let captured = CAPTURE.with(|capture| capture.borrow().is_some());
+    if !captured {
+        print(&Report::Event(&text));
+    }
+}
+
+// the structured answer of a command, only its first one is printed
+pub fn document(document: &impl Serialize) {
+    let answered = CAPTURE.with(|capture| match capture.borrow_mut().as_mut() {
+        Some(capture) => std::mem::replace(&mut capture.answered, true),
+        None => false,
+    });
+    if !answered {
+        print(document);
+    }
+}
+
+// runs a command, in json mode it answers with the fallback unless it printed a document of
+// its own, e.g. an action that went through without anything else to tell
+pub fn command<R>(fallback: &impl Serialize, run: impl FnOnce() -> R) -> R {
+    if !json() {
+        return run();
+    }
+    CAPTURE.with(|capture| *capture.borrow_mut() = Some(Capture::default()));
+    let result = run();
+    let capture = CAPTURE.with(|capture| capture.borrow_mut().take()).unwrap_or_default();
+    if !capture.answered {
+        print(fallback);
+    }
+    result
+}
+
+fn print(document: &impl Serialize) {
+    println!("{}", serde_json::to_string(document).unwrap());
+}
+
+#[cfg(test)]
+mod test {
+    use serde::Serialize;
+
+    use crate::output::{Capture, CAPTURE, Report};
+
+    #[derive(Serialize)]
+    enum Answer {
+        Accepted,
+        Failed,
+    }
+
+    #[test]
+    fn commands_answer_with_one_document() {
+        assert_eq!(serde_json::to_string(&Report::Event("Peer joined")).unwrap(), r#"{"Event":"Peer joined"}"#);
+
+        // only the first document of a command is printed
+        CAPTURE.with(|capture| *capture.borrow_mut() = Some(Capture::default()));
+        super::document(&Answer::Failed);
+        assert!(CAPTURE.with(|capture| capture.borrow().as_ref().unwrap().answered));
+        super::document(&Answer::Accepted);
+        assert!(CAPTURE.with(|capture| capture.borrow_mut().take()).unwrap().answered);
+        assert!(super::command(&Answer::Accepted, || !super::json()));
+    }
+}
@@ -0,0 +1,146 @@
+#[cfg(any(unix, windows))]
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// What differs between Linux, macOS and Windows, the rest of the crate only uses portable std
+// APIs. Nodes, signers and thin clients talk over TCP on loopback, there are no socket files.
+
+#[cfg(any(unix, windows))]
+static DATA_DIR: &str = ".kingcoin";
+#[cfg(windows)]
+static WINDOWS_DATA_DIR: &str = "Kingcoin";
+
+// device names Windows reserves in every directory, whatever the extension
+static RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+// ~/.kingcoin on Linux and macOS
+#[cfg(unix)]
+pub fn default_data_dir() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(DATA_DIR))
+}
+
+// %APPDATA%\Kingcoin, unless an older node already keeps its files in the user profile
+#[cfg(windows)]
+pub fn default_data_dir() -> Option<PathBuf> {
+    let profile_dir = env::var_os("USERPROFILE").map(|profile| PathBuf::from(profile).join(DATA_DIR));
+    match (profile_dir, env::var_os("APPDATA")) {
+        (Some(profile_dir), _) if profile_dir.is_dir() => Some(profile_dir),
+        (_, Some(app_data)) => Some(PathBuf::from(app_data).join(WINDOWS_DATA_DIR)),
+        (profile_dir, None) => profile_dir,
+    }
+}
+
+// no convention to follow, the data directory has to be chosen with KINGCOIN_HOME
+#[cfg(not(any(unix, windows)))]
+pub fn default_data_dir() -> Option<PathBuf> {
+    None
+}
+
+// keys are only readable by the user running the node, on Windows files in the user's
+// directories are private to it already
+#[cfg(unix)]
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    // the mode only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(content)
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    fs::write(path, content)
+}
+
+#[cfg(unix)]
+pub fn make_dir_private(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+pub fn make_dir_private(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// passwords are typed without being shown, input that is no terminal is left as it is
+#[cfg(unix)]
+pub fn set_echo(enabled: bool) -> io::Result<()> {
+    // SAFETY: termios is plain data filled in by tcgetattr before it is read
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return Ok(());
+        }
+        let mut termios: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        match enabled {
+            true => termios.c_lflag |= libc::ECHO,
+            false => termios.c_lflag &= !libc::ECHO,
+        }
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// the Windows console has no portable switch, passwords are shown there
+#[cfg(not(unix))]
+pub fn set_echo(_enabled: bool) -> io::Result<()> {
+    Ok(())
+}
+
+// one line of standard input with echo off, for prompts outside the node's command loop
+pub fn read_password() -> io::Result<String> {
+    set_echo(false)?;
+    let mut password = String::new();
+    let read = io::stdin().read_line(&mut password);
+    set_echo(true)?;
+    println!();
+    read.map(|_| password.trim_end().to_string())
+}
+
+// checked everywhere, so files named on one platform can be copied to the others
+pub fn portable_file_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default();
+    !RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+
+    use crate::platform;
+
+    #[test]
+    fn private_files_and_names_work_on_every_platform() {
+        let path = env::temp_dir().join(format!("kingcoin-private-{}.json", std::process::id()));
+        assert!(platform::write_private(&path, b"first").is_ok());
+        assert!(platform::write_private(&path, b"second").is_ok());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        fs::remove_file(&path).ok();
+
+        assert!(platform::portable_file_name("savings"));
+        assert!(platform::portable_file_name("console"));
+        assert!(!platform::portable_file_name("con"));
+        assert!(!platform::portable_file_name("Lpt1.json"));
+        assert!(platform::default_data_dir().is_none_or(|dir| dir.ends_with(".kingcoin") || dir.ends_with("Kingcoin")));
+    }
+}
@@ -0,0 +1,32 @@
+use rand::rngs::OsRng;
+#[cfg(test)]
+use rand::rngs::StdRng;
+#[cfg(test)]
+use rand::SeedableRng;
+
+// Every source of randomness the node uses (key generation, signing salts) takes the rng as an
+// argument. A running node always draws from the operating system, only tests fix a seed so
+// their keys and signatures come out the same on every run.
+pub fn node_rng() -> OsRng {
+    OsRng
+}
+
+#[cfg(test)]
+pub fn seeded(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blockchain::access::HotWallet;
+    use crate::random;
+
+    #[test]
+    fn same_seed_generates_same_wallet() {
+        let first = HotWallet::generate(&mut random::seeded(7));
+        let second = HotWallet::generate(&mut random::seeded(7));
+        let other = HotWallet::generate(&mut random::seeded(8));
+        assert_eq!(first.address(), second.address());
+        assert_ne!(first.address(), other.address());
+    }
+}
@@ -0,0 +1,482 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::io::{self, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+
+use crate::blockchain::{find_wallet_by_address, Transaction, TransactionValidator, Wallet};
+use crate::blockchain::{access, contract};
+use crate::blockchain::core::{Blockchain, BlockchainError};
+use crate::blockchain::governance::Proposal;
+use crate::blockchain::history::{HistoryFilter, Page, Statement, TransactionHistory};
+use crate::blockchain::proof::BalanceProof;
+use crate::blockchain::upgrade::ConsensusRules;
+use crate::network::bans::BanRecord;
+use crate::network::bid_policy::BidPolicy;
+use crate::network::communication::{BlockchainMessage, dispatch, mempool};
+use crate::network::communication::mempool::PendingTransaction;
+use crate::network::latency::PeerProbe;
+use crate::network::NodeState;
+use crate::network::rounds::RoundRecord;
+use crate::network::status::NodeStatus;
+use crate::network::validators::ValidatorRecord;
+use crate::schedule::ScheduledPayment;
+
+static RPC_TIMEOUT: Duration = Duration::from_secs(30);
+pub static RPC_QUEUE_CAPACITY: usize = 16;
+// requests only carry addresses and a few signed transactions, responses may list a long history
+static MAX_REQUEST_LENGTH: u64 = 1 << 20;
+static MAX_RESPONSE_LENGTH: u64 = 64 << 20;
+
+pub type RpcCall = (RpcRequest, oneshot::Sender<RpcResponse>);
+
+pub struct RpcError {
+    message: String,
+}
+
+impl RpcError {
+    pub fn new(message: &str) -> RpcError {
+        RpcError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for RpcError {
+    fn message(&self) -> String {
+        format!("Rpc: {}", self.message)
+    }
+}
+
+// One json request per line, answered by one json response per line, like the signer protocol.
+// Thin clients hold the keys, the node only ever receives signed transactions.
+#[derive(Serialize, Deserialize)]
+pub enum RpcRequest {
+    // what a client needs to build and sign a transfer
+    Account { address: String },
+    // nodes answer older clients, which send neither filter nor page, with the whole history
+    History {
+        address: String,
+        #[serde(default)]
+        filter: HistoryFilter,
+        #[serde(default)]
+        page: Option<Page>,
+    },
+    Register(Wallet),
+    Submit(Vec<Transaction>),
+    Mempool,
+    Block { block_number: u64 },
+    // the newest consensus rounds this node took part in
+    Rounds { count: usize },
+    // committed balance as of a block
+    BalanceAt { address: String, height: u64 },
+    // the balance snapshot at or below the height, the latest one without
+    Snapshot { height: Option<u64> },
+    Stats,
+    Audit { address: String },
+    Validators,
+    Proposals,
+    // one token, or every token the address holds
+    Tokens { address: String, token_id: Option<String> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum RpcResponse {
+    Account {
+        confirmed: i64,
+        spendable: i64,
+        next_nonce: u64,
+        transfer_fee: i64,
+        registered: bool,
+    },
+    History(Vec<(u64, Transaction)>),
+    Mempool(Vec<PendingTransaction>),
+    Block {
+        block_number: u64,
+        block_hash: String,
+        time: Option<DateTime<Utc>>,
+        transactions: Vec<Transaction>,
+    },
+    Rounds(Vec<RoundRecord>),
+    BalanceAt {
+        height: u64,
+        confirmed: i64,
+    },
+    Snapshot {
+        height: u64,
+        account_count: usize,
+        state_root: String,
+    },
+    Stats {
+        block_count: u64,
+        total_transactions: usize,
+        total_volume: i64,
+        average_block_fill: f64,
+        average_block_interval_seconds: Option<i64>,
+        active_addresses: usize,
+        circulating_supply: i64,
+        burned: i64,
+    },
+    Audit(BalanceProof),
+    // by validator address, most blocks forged first
+    Validators(Vec<(String, ValidatorRecord)>),
+    // each with the stake approving and rejecting it
+    Proposals(Vec<(Proposal, i64, i64)>),
+    // token id, symbol and balance, none when the balance overflows
+    Tokens(Vec<(String, String, Option<i64>)>),
+    // what only the node's own console asks for, in the same form
+    Status(NodeStatus),
+    Peers(Vec<PeerProbe>),
+    Bans(Vec<BanRecord>),
+    // name and address
+    Contacts(Vec<(String, String)>),
+    // name, address and whether it is the active wallet
+    Wallets(Vec<(String, String, bool)>),
+    Schedule(Vec<ScheduledPayment>),
+    BidPolicy(BidPolicy),
+    Statement(Statement),
+    PaymentRequest {
+        uri: String,
+        qr_code: Option<String>,
+    },
+    // the id of what a command created, e.g. a token, an escrow or a proposal
+    Created(String),
+    Locked {
+        lock_id: String,
+        // only when the node generated it
+        secret: Option<String>,
+    },
+    Batch {
+        sent: usize,
+        // why each failed row was left out
+        failed: Vec<String>,
+    },
+    // the next line answers it, a confirmation or a password
+    Prompt(String),
+    Accepted,
+    Failed(String),
+}
+
+// node side, returns the answer and what has to be gossiped because of it
+pub fn answer(
+    request: RpcRequest, transactions: &mut Blockchain<Transaction>, wallets: &mut Blockchain<Wallet>,
+    node_state: &NodeState, rules: &ConsensusRules,
+) -> (RpcResponse, Vec<BlockchainMessage>) {
+    let failed = |error: Box<dyn BlockchainError>| (RpcResponse::Failed(error.message()), vec![]);
+    match request {
+        RpcRequest::Account { address } => match access::decode_address(&address) {
+            Ok(address) => {
+                let balance = transactions.balance_breakdown(address);
+                let response = RpcResponse::Account {
+                    confirmed: balance.confirmed(),
+                    spendable: balance.spendable(),
+                    next_nonce: transactions.next_nonce(address),
+                    transfer_fee: rules.transfer_fee(),
+                    registered: find_wallet_by_address(address, wallets).is_some(),
+                };
+                (response, vec![])
+            }
+            Err(error) => failed(error)
+        },
+        RpcRequest::History { address, filter, page } => match access::decode_address(&address) {
+            Ok(address) => match TransactionHistory::of(transactions, address) {
+                Ok(history) => {
+                    let history = history.filter(&filter);
+                    let entries = match page {
+                        None => history.entries(),
+                        Some(page) => history.page(page),
+                    };
+                    let listed = entries.iter()
+                        .map(|entry| (entry.block_number(), entry.transaction().clone()))
+                        .collect();
+                    (RpcResponse::History(listed), vec![])
+                }
+                Err(error) => failed(error)
+            },
+            Err(error) => failed(error)
+        },
+        RpcRequest::Mempool => {
+            let pending = mempool::pending(transactions, rules.block_size(), Utc::now());
+            (RpcResponse::Mempool(pending), vec![])
+        }
+        RpcRequest::Block { block_number } => match transactions.block_at(block_number) {
+            Ok(Some(block)) => {
+                let response = RpcResponse::Block {
+                    block_number,
+                    block_hash: block.key().hash(),
+                    time: block.time(),
+                    transactions: block.data().to_vec(),
+                };
+                (response, vec![])
+            }
+            Ok(None) => failed(Box::new(RpcError::new(&format!("No block {}", block_number)))),
+            Err(error) => failed(error)
+        },
+        RpcRequest::Rounds { count } => (RpcResponse::Rounds(node_state.rounds().recent(count)), vec![]),
+        RpcRequest::BalanceAt { address, height } => {
+            match access::decode_address(&address).and_then(|address| transactions.committed_balance_at(address, height)) {
+                Ok(confirmed) => (RpcResponse::BalanceAt { height, confirmed }, vec![]),
+                Err(error) => failed(error)
+            }
+        }
+        RpcRequest::Snapshot { height } => {
+            let snapshot = match height {
+                None => transactions.latest_snapshot(),
+                Some(height) => transactions.snapshot_at(height),
+            };
+            match snapshot {
+                None => failed(Box::new(RpcError::new("No balance snapshot yet"))),
+                Some(snapshot) => {
+                    let response = RpcResponse::Snapshot {
+                        height: snapshot.height(),
+                        account_count: snapshot.account_count(),
+                        state_root: snapshot.state_root().to_string(),
+                    };
+                    (response, vec![])
+                }
+            }
+        }
+        RpcRequest::Stats => match transactions.stats() {
+            Ok(stats) => {
+                let response = RpcResponse::Stats {
+                    block_count: stats.block_count(),
+                    total_transactions: stats.total_transactions(),
+                    total_volume: stats.total_volume(),
+                    average_block_fill: stats.average_block_fill(),
+                    average_block_interval_seconds: stats.average_block_interval().map(|interval| interval.num_seconds()),
+                    active_addresses: stats.active_addresses(),
+                    circulating_supply: stats.circulating_supply(),
+                    burned: stats.burned(),
+                };
+                (response, vec![])
+            }
+            Err(error) => failed(error)
+        },
+        RpcRequest::Audit { address } => {
+            match access::decode_address(&address).and_then(|address| BalanceProof::derive(transactions, address)) {
+                Ok(proof) => (RpcResponse::Audit(proof), vec![]),
+                Err(error) => failed(error)
+            }
+        }
+        RpcRequest::Validators => {
+            let validators = node_state.validator_stats().records().into_iter()
+                .map(|(address, record)| (access::encode_address(address), record.clone()))
+                .collect();
+            (RpcResponse::Validators(validators), vec![])
+        }
+        RpcRequest::Proposals => {
+            let governance = node_state.governance();
+            let proposals = governance.proposals().into_iter()
+                .map(|proposal| {
+                    let tally = governance.tally(&proposal.id(), transactions);
+                    (proposal.clone(), tally.approving_stake(), tally.rejecting_stake())
+                })
+                .collect();
+            (RpcResponse::Proposals(proposals), vec![])
+        }
+        RpcRequest::Tokens { address, token_id } => match access::decode_address(&address) {
+            Ok(address) => (RpcResponse::Tokens(contract::token_balances(transactions, address, token_id)), vec![]),
+            Err(error) => failed(error)
+        },
+        RpcRequest::Register(wallet) => match dispatch::register_wallet(wallets, wallet.clone()) {
+            Ok(_) => (RpcResponse::Accepted, vec![BlockchainMessage::RegisterWallet(wallet)]),
+            Err(error) => failed(error)
+        },
+        // a transfer and its fee arrive together, either both enter the mempool or neither, checked
+        // like a send from this node would be
+        RpcRequest::Submit(submitted) => {
+            if let Err(error) = TransactionValidator::new(wallets, transactions).check_transactions(&submitted) {
+                return failed(Box::new(error));
+            }
+            for transaction in &submitted {
+                transactions.add_uncommitted(transaction.clone());
+            }
+            let messages = submitted.into_iter().map(BlockchainMessage::SubmitTransaction).collect();
+            (RpcResponse::Accepted, messages)
+        }
+    }
+}
+
+// Accepts clients on its own task and hands each request to the main loop, which owns the
+// chains, so serving a slow client never stalls consensus. Every connection is answered on a
+// task of its own, a client that never finishes its line only holds up itself.
+pub async fn serve(listener: TcpListener, calls: mpsc::Sender<RpcCall>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(answer_client(stream, calls.clone()));
+            }
+            Err(_) => continue
+        }
+    }
+}
+
+async fn answer_client(stream: TcpStream, calls: mpsc::Sender<RpcCall>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    let mut reader = io::BufReader::new(reader).take(MAX_REQUEST_LENGTH);
+    if !matches!(time::timeout(RPC_TIMEOUT, reader.read_line(&mut line)).await, Ok(Ok(_))) {
+        return;
+    }
+    let response = if line.len() as u64 == MAX_REQUEST_LENGTH && !line.ends_with('\n') {
+        RpcResponse::Failed(String::from("Request too long"))
+    } else {
+        match serde_json::from_str::<RpcRequest>(&line) {
+            Err(_) => RpcResponse::Failed(String::from("Malformed request")),
+            Ok(request) => {
+                let (sender, receiver) = oneshot::channel();
+                if calls.send((request, sender)).await.is_err() {
+                    return;
+                }
+                receiver.await.unwrap_or(RpcResponse::Failed(String::from("Node is shutting down")))
+            }
+        }
+    };
+    let mut answer = serde_json::to_string(&response).unwrap();
+    answer.push('\n');
+    let _ = writer.write_all(answer.as_bytes()).await;
+}
+
+pub fn request(endpoint: SocketAddr, request: &RpcRequest) -> Result<RpcResponse, Box<dyn BlockchainError>> {
+    let failed = |error: std::io::Error| -> Box<dyn BlockchainError> {
+        Box::new(RpcError::new(&format!("{} unreachable: {}", endpoint, error)))
+    };
+    let mut stream = std::net::TcpStream::connect_timeout(&endpoint, RPC_TIMEOUT).map_err(failed)?;
+    stream.set_read_timeout(Some(RPC_TIMEOUT)).map_err(failed)?;
+    let mut line = serde_json::to_string(request).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes()).map_err(failed)?;
+
+    let mut response = String::new();
+    BufReader::new(stream).take(MAX_RESPONSE_LENGTH).read_line(&mut response).map_err(failed)?;
+    match serde_json::from_str(&response) {
+        Ok(RpcResponse::Failed(reason)) => Err(Box::new(RpcError::new(&reason))),
+        Ok(response) => Ok(response),
+        Err(_) => Err(Box::new(RpcError::new("Malformed response")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    use chrono::Utc;
+    use libp2p::PeerId;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    use crate::blockchain::{StakeBid, Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::access::{self, HotWallet};
+    use crate::blockchain::builder::TransactionBuilder;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::blockchain::upgrade::UPGRADE_SCHEDULE;
+    use crate::network::NodeState;
+    use crate::random;
+    use crate::rpc::{self, MAX_REQUEST_LENGTH, RpcRequest, RpcResponse};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn thin_client_reads_its_account_and_submits_signed_transfers() {
+        let mut rng = random::seeded(14);
+        let client = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let registration = BlockCandidate::create_new(vec![client.wallet().clone()], wallets.last_block()).ok().unwrap();
+        wallets.submit_new_block(registration);
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, client.address(), "".to_string(), 100, Utc::now())
+        ]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = listener.local_addr().unwrap();
+        let (calls, mut received) = mpsc::channel(rpc::RPC_QUEUE_CAPACITY);
+        tokio::spawn(rpc::serve(listener, calls));
+        let node_state = NodeState::init(PeerId::random(), StakeBid::bid(0, client.address()));
+        let node = thread::spawn(move || {
+            let mut gossiped = 0;
+            for _ in 0..4 {
+                let (request, responder) = received.blocking_recv().unwrap();
+                let (response, messages) = rpc::answer(
+                    request, &mut transactions, &mut wallets, &node_state, UPGRADE_SCHEDULE.rules_at(0),
+                );
+                gossiped += messages.len();
+                responder.send(response).ok();
+            }
+            (transactions.uncommitted_data().len(), gossiped)
+        });
+
+        // a client that never finishes its request holds up nobody else
+        let _stalled = TcpStream::connect(endpoint).unwrap();
+        tokio::task::spawn_blocking(move || {
+            let address = access::encode_address(client.address());
+            let next_nonce = match rpc::request(endpoint, &RpcRequest::Account { address: address.clone() }) {
+                Ok(RpcResponse::Account { spendable: 100, next_nonce, transfer_fee: 1, registered: true, .. }) => next_nonce,
+                _ => panic!("unexpected account answer"),
+            };
+            let signed = TransactionBuilder::from_transaction(Transaction::burn(client.address(), 30))
+                .with_fee(1)
+                .with_nonce(next_nonce)
+                .sign(&client, &mut rng)
+                .ok().unwrap();
+            let unsigned = Transaction::burn(client.address(), 30);
+            assert!(rpc::request(endpoint, &RpcRequest::Submit(vec![unsigned])).is_err());
+            assert!(matches!(rpc::request(endpoint, &RpcRequest::Submit(signed)), Ok(RpcResponse::Accepted)));
+            match rpc::request(endpoint, &RpcRequest::Mempool) {
+                Ok(RpcResponse::Mempool(pending)) => {
+                    assert_eq!(pending.len(), 1);
+                    assert_eq!(pending[0].fee(), 1);
+                    assert!(pending[0].fits_next_block());
+                }
+                _ => panic!("unexpected mempool answer"),
+            }
+        }).await.unwrap();
+        assert_eq!(node.join().unwrap(), (2, 2));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn overlong_requests_are_refused_without_reaching_the_node() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = listener.local_addr().unwrap();
+        let (calls, mut received) = mpsc::channel(rpc::RPC_QUEUE_CAPACITY);
+        tokio::spawn(rpc::serve(listener, calls));
+
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = TcpStream::connect(endpoint).unwrap();
+            stream.write_all(&vec![b'['; MAX_REQUEST_LENGTH as usize]).unwrap();
+            let mut response = String::new();
+            BufReader::new(stream).read_line(&mut response).unwrap();
+            response
+        }).await.unwrap();
+        assert!(matches!(serde_json::from_str(&response), Ok(RpcResponse::Failed(reason)) if reason == "Request too long"));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[test]
+    fn queries_answer_with_their_own_documents() {
+        let mut rng = random::seeded(15);
+        let client = HotWallet::generate(&mut rng);
+        let mut wallets = Blockchain::<Wallet>::wallet_chain();
+        let mut transactions = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, client.address(), "".to_string(), 100, Utc::now())
+        ]);
+        let node_state = NodeState::init(PeerId::random(), StakeBid::bid(0, client.address()));
+        let address = access::encode_address(client.address());
+
+        let mut ask = |request| rpc::answer(
+            request, &mut transactions, &mut wallets, &node_state, UPGRADE_SCHEDULE.rules_at(0),
+        ).0;
+        assert!(matches!(ask(RpcRequest::Stats), RpcResponse::Stats { block_count: 1, circulating_supply: 100, .. }));
+        assert!(matches!(
+            ask(RpcRequest::BalanceAt { address: address.clone(), height: 0 }),
+            RpcResponse::BalanceAt { height: 0, confirmed: 100 }
+        ));
+        assert!(matches!(ask(RpcRequest::Tokens { address, token_id: None }), RpcResponse::Tokens(tokens) if tokens.is_empty()));
+        assert!(matches!(ask(RpcRequest::BalanceAt { address: "nobody".to_string(), height: 0 }), RpcResponse::Failed(_)));
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Address;
+use crate::blockchain::core::{BlockchainError, StorageError};
+
+pub static SCHEDULE_FILE: &str = "schedule.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ScheduledPayment {
+    id: u64,
+    target_address: Address,
+    amount: i64,
+    next_run: DateTime<Utc>,
+    // in seconds, none for one-off payments
+    interval: Option<i64>,
+}
+
+impl ScheduledPayment {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+    pub fn target_address(&self) -> Address {
+        self.target_address
+    }
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+    pub fn next_run(&self) -> DateTime<Utc> {
+        self.next_run
+    }
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval.map(Duration::seconds)
+    }
+
+    pub fn title(&self) -> String {
+        format!("Scheduled payment #{}", self.id)
+    }
+
+    fn due(&self, now: DateTime<Utc>) -> bool {
+        self.next_run <= now
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PaymentSchedule {
+    next_id: u64,
+    payments: Vec<ScheduledPayment>,
+    // where load found the schedule, save writes back to the same file
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl PaymentSchedule {
+    pub fn load(path: &Path) -> PaymentSchedule {
+        let schedule: PaymentSchedule = match fs::read_to_string(path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+            Err(_) => PaymentSchedule::default()
+        };
+        PaymentSchedule {
+            path: path.to_path_buf(),
+            ..schedule
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn BlockchainError>> {
+        let content = serde_json::to_string_pretty(self).unwrap();
+        match fs::write(&self.path, content) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Box::new(StorageError::new(&error.to_string())))
+        }
+    }
+
+    pub fn payments(&self) -> &[ScheduledPayment] {
+        &self.payments
+    }
+
+    pub fn register(
+        &mut self, target_address: Address, amount: i64,
+        first_run: DateTime<Utc>, interval: Option<Duration>,
+    ) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.payments.push(ScheduledPayment {
+            id,
+            target_address,
+            amount,
+            next_run: first_run,
+            interval: interval.map(|interval| interval.num_seconds()),
+        });
+        id
+    }
+
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let count = self.payments.len();
+        self.payments.retain(|payment| payment.id != id);
+        count != self.payments.len()
+    }
+
+    // Payments that cannot be covered by the available balance stay due
+    // and are retried on the next call.
+    pub fn take_due(&mut self, now: DateTime<Utc>, mut available_balance: i64) -> Vec<ScheduledPayment> {
+        let mut executed = vec![];
+        for payment in &mut self.payments {
+            if !payment.due(now) || payment.amount > available_balance {
+                continue;
+            }
+            available_balance -= payment.amount;
+            executed.push(payment.clone());
+            if let Some(interval) = payment.interval {
+                while payment.next_run <= now {
+                    payment.next_run += Duration::seconds(interval);
+                }
+            }
+        }
+        self.payments.retain(|payment| {
+            payment.interval.is_some() || !executed.iter().any(|done| done.id == payment.id)
+        });
+        executed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::schedule::PaymentSchedule;
+
+    #[test]
+    fn keeps_unfunded_payments_due() {
+        let now = Utc::now();
+        let mut schedule = PaymentSchedule::default();
+        let recurring = schedule.register([2; 32], 40, now, Some(Duration::days(7)));
+        let one_off = schedule.register([3; 32], 100, now, None);
+
+        let executed = schedule.take_due(now, 50);
+
+        assert_eq!(executed.len(), 1);
+        assert_eq!(executed[0].id(), recurring);
+        assert_eq!(schedule.payments().len(), 2);
+        assert!(schedule.payments()[0].next_run() > now);
+
+        let executed = schedule.take_due(now, 100);
+        assert_eq!(executed[0].id(), one_off);
+        assert_eq!(schedule.payments().len(), 1);
+    }
+}
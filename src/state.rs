@@ -0,0 +1,104 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::blockchain::{Transaction, Wallet};
+use crate::blockchain::core::Blockchain;
+use crate::network::NodeState;
+
+// Handle to the node state shared between the main loop and background subsystems.
+// Cloning is cheap, every clone points at the same chains.
+// Locks that are held together are always taken in field order to avoid deadlocks.
+#[derive(Clone)]
+pub struct SharedState {
+    transactions: Arc<RwLock<Blockchain<Transaction>>>,
+    wallets: Arc<RwLock<Blockchain<Wallet>>>,
+    stakes: Arc<RwLock<Blockchain<Transaction>>>,
+    node_state: Arc<RwLock<NodeState>>,
+}
+
+impl SharedState {
+    pub fn new(
+        transactions: Blockchain<Transaction>, wallets: Blockchain<Wallet>,
+        stakes: Blockchain<Transaction>, node_state: NodeState,
+    ) -> SharedState {
+        SharedState {
+            transactions: Arc::new(RwLock::new(transactions)),
+            wallets: Arc::new(RwLock::new(wallets)),
+            stakes: Arc::new(RwLock::new(stakes)),
+            node_state: Arc::new(RwLock::new(node_state)),
+        }
+    }
+
+    pub fn transactions(&self) -> RwLockReadGuard<'_, Blockchain<Transaction>> {
+        self.transactions.read().expect("Transaction chain lock poisoned")
+    }
+
+    pub fn transactions_mut(&self) -> RwLockWriteGuard<'_, Blockchain<Transaction>> {
+        self.transactions.write().expect("Transaction chain lock poisoned")
+    }
+
+    pub fn wallets(&self) -> RwLockReadGuard<'_, Blockchain<Wallet>> {
+        self.wallets.read().expect("Wallet chain lock poisoned")
+    }
+
+    pub fn wallets_mut(&self) -> RwLockWriteGuard<'_, Blockchain<Wallet>> {
+        self.wallets.write().expect("Wallet chain lock poisoned")
+    }
+
+    pub fn stakes(&self) -> RwLockReadGuard<'_, Blockchain<Transaction>> {
+        self.stakes.read().expect("Stake chain lock poisoned")
+    }
+
+    pub fn stakes_mut(&self) -> RwLockWriteGuard<'_, Blockchain<Transaction>> {
+        self.stakes.write().expect("Stake chain lock poisoned")
+    }
+
+    pub fn node_state(&self) -> RwLockReadGuard<'_, NodeState> {
+        self.node_state.read().expect("Node state lock poisoned")
+    }
+
+    pub fn node_state_mut(&self) -> RwLockWriteGuard<'_, NodeState> {
+        self.node_state.write().expect("Node state lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use libp2p::PeerId;
+
+    use crate::blockchain::{StakeBid, Transaction, Wallet};
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain};
+    use crate::network::NodeState;
+    use crate::state::SharedState;
+
+    #[test]
+    fn readers_see_blocks_appended_by_writer() {
+        let state = SharedState::new(
+            Blockchain::<Transaction>::transaction_chain(vec![]),
+            Blockchain::<Wallet>::wallet_chain(),
+            Blockchain::<Transaction>::transaction_chain(vec![]),
+            NodeState::init(PeerId::random(), StakeBid::bid(0, MINTING_WALLET_ADDRESS)),
+        );
+        let reader = state.clone();
+        let observer = thread::spawn(move || {
+            let mut last_seen = 0;
+            while last_seen < 5 {
+                let chain_length = reader.transactions().chain_length();
+                assert!(chain_length >= last_seen);
+                last_seen = chain_length;
+            }
+        });
+
+        for _ in 0..4 {
+            let mut transactions = state.transactions_mut();
+            let block_candidate = BlockCandidate::create_new(vec![], transactions.last_block())
+                .ok()
+                .unwrap();
+            transactions.submit_new_block(block_candidate);
+        }
+        observer.join().unwrap();
+        assert_eq!(state.transactions().chain_length(), 5);
+    }
+}
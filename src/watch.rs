@@ -0,0 +1,272 @@
+use std::future;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::blockchain::{Address, Transaction};
+use crate::blockchain::access;
+use crate::blockchain::core::ChainEvent;
+use crate::blockchain::memo;
+use crate::report;
+
+pub static CONFIRMATION_TARGET: u64 = 3;
+
+pub enum WalletActivity {
+    Pending {
+        transaction: Transaction,
+        incoming: bool,
+    },
+    Confirmed {
+        transaction: Transaction,
+        incoming: bool,
+        block_number: u64,
+        confirmations: u64,
+    },
+    Reorg {
+        depth: u64,
+    },
+    // was confirmed in a block the reorg rolled back
+    Unconfirmed {
+        transaction: Transaction,
+        incoming: bool,
+    },
+}
+
+impl WalletActivity {
+    pub fn describe(&self) -> String {
+        match self {
+            WalletActivity::Pending { transaction, incoming } => format!(
+                "[pending] {}",
+                describe_transfer(transaction, *incoming)
+            ),
+            WalletActivity::Confirmed { transaction, incoming, block_number, confirmations } => format!(
+                "[{}/{} confirmations, block {}] {}",
+                confirmations, CONFIRMATION_TARGET, block_number,
+                describe_transfer(transaction, *incoming)
+            ),
+            WalletActivity::Reorg { depth } => format!(
+                "[reorg] {} block(s) rolled back, confirmations may change", depth
+            ),
+            WalletActivity::Unconfirmed { transaction, incoming } => format!(
+                "[unconfirmed by reorg] {}",
+                describe_transfer(transaction, *incoming)
+            ),
+        }
+    }
+}
+
+pub struct WalletWatcher {
+    address: Address,
+    events: broadcast::Receiver<ChainEvent<Transaction>>,
+    confirming: Vec<(Transaction, u64)>,
+    last_block_number: u64,
+}
+
+impl WalletWatcher {
+    pub fn new(
+        address: Address, events: broadcast::Receiver<ChainEvent<Transaction>>,
+    ) -> WalletWatcher {
+        WalletWatcher {
+            address,
+            events,
+            confirming: vec![],
+            last_block_number: 0,
+        }
+    }
+
+    pub async fn next_activity(&mut self) -> Vec<WalletActivity> {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => {
+                    let activity = self.process(event);
+                    if !activity.is_empty() {
+                        return activity;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    report!("watch: skipped {} chain events", skipped);
+                }
+                Err(RecvError::Closed) => future::pending::<()>().await
+            }
+        }
+    }
+
+    fn process(&mut self, event: ChainEvent<Transaction>) -> Vec<WalletActivity> {
+        let mut activity = vec![];
+        match &event {
+            ChainEvent::DataSubmitted(_) => {
+                for transaction in event.wallet_transactions(self.address) {
+                    activity.push(WalletActivity::Pending {
+                        transaction: transaction.clone(),
+                        incoming: self.incoming(transaction),
+                    });
+                }
+            }
+            ChainEvent::BlockAppended { block_number, .. } => {
+                self.last_block_number = *block_number;
+                // announced payments were already reported for the block they arrived in
+                let earlier = self.confirming.iter()
+                    .filter(|(_, included_in)| included_in < block_number);
+                for (transaction, included_in) in earlier {
+                    activity.push(WalletActivity::Confirmed {
+                        transaction: transaction.clone(),
+                        incoming: self.incoming(transaction),
+                        block_number: *included_in,
+                        confirmations: (block_number + 1).saturating_sub(*included_in),
+                    });
+                }
+                for transaction in event.wallet_transactions(self.address) {
+                    if self.tracked(transaction) {
+                        continue;
+                    }
+                    activity.push(WalletActivity::Confirmed {
+                        transaction: transaction.clone(),
+                        incoming: self.incoming(transaction),
+                        block_number: *block_number,
+                        confirmations: 1,
+                    });
+                    self.confirming.push((transaction.clone(), *block_number));
+                }
+                let last_block_number = self.last_block_number;
+                self.confirming.retain(|(_, included_in)| {
+                    (last_block_number + 1).saturating_sub(*included_in) < CONFIRMATION_TARGET
+                });
+            }
+            // announced by a peer, possibly before the block reached the local chain
+            ChainEvent::IncomingPayment { data, committed: false, .. } => {
+                if self.incoming(data) && !self.tracked(data) {
+                    activity.push(WalletActivity::Pending {
+                        transaction: data.clone(),
+                        incoming: true,
+                    });
+                }
+            }
+            ChainEvent::IncomingPayment { block_number, data, committed: true } => {
+                if self.incoming(data) && !self.tracked(data) {
+                    activity.push(WalletActivity::Confirmed {
+                        transaction: data.clone(),
+                        incoming: true,
+                        block_number: *block_number,
+                        confirmations: 1,
+                    });
+                    self.confirming.push((data.clone(), *block_number));
+                }
+            }
+            ChainEvent::Reorg { depth, .. } => {
+                let rolled_back_from = self.last_block_number.saturating_sub(*depth);
+                self.confirming.retain(|(_, included_in)| *included_in <= rolled_back_from);
+                activity.push(WalletActivity::Reorg { depth: *depth });
+                for transaction in event.wallet_transactions(self.address) {
+                    activity.push(WalletActivity::Unconfirmed {
+                        transaction: transaction.clone(),
+                        incoming: self.incoming(transaction),
+                    });
+                }
+            }
+            ChainEvent::Slashed { .. } => {}
+        }
+        activity
+    }
+
+    fn tracked(&self, transaction: &Transaction) -> bool {
+        self.confirming.iter().any(|(confirming, _)| confirming == transaction)
+    }
+
+    fn incoming(&self, transaction: &Transaction) -> bool {
+        transaction.target_address() == self.address
+    }
+}
+
+fn describe_transfer(transaction: &Transaction, incoming: bool) -> String {
+    if incoming {
+        format!(
+            "+{} from {} \"{}\"",
+            transaction.amount(), access::encode_address(transaction.source_address()),
+            memo::readable(transaction.title(), None)
+        )
+    } else {
+        format!(
+            "-{} to {} \"{}\"",
+            transaction.amount(), access::encode_address(transaction.target_address()),
+            memo::readable(transaction.title(), None)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::blockchain::Transaction;
+    use crate::blockchain::protocol::MINTING_WALLET_ADDRESS;
+    use crate::blockchain::core::{BlockCandidate, Blockchain, ChainEvent};
+    use crate::network::communication::BlockchainDto;
+    use crate::watch::{WalletActivity, WalletWatcher};
+
+    #[test]
+    fn announced_payment_is_not_reported_again_on_append() {
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut watcher = WalletWatcher::new([2; 32], transactions.subscribe());
+        let payment = Transaction::new([1; 32], [2; 32], "Rent".to_string(), 10, Utc::now());
+
+        let announced = watcher.process(ChainEvent::IncomingPayment {
+            block_number: 1,
+            data: payment.clone(),
+            committed: true,
+        });
+        assert_eq!(announced.len(), 1);
+
+        let appended = watcher.process(ChainEvent::BlockAppended {
+            block_number: 1,
+            block_hash: String::new(),
+            data: vec![payment],
+        });
+        assert!(appended.is_empty());
+    }
+
+    #[test]
+    fn payments_announced_ahead_of_their_block_are_pending() {
+        let transactions = Blockchain::<Transaction>::transaction_chain(vec![]);
+        let mut watcher = WalletWatcher::new([2; 32], transactions.subscribe());
+        let payment = Transaction::new([1; 32], [2; 32], "Rent".to_string(), 10, Utc::now());
+
+        let announced = watcher.process(ChainEvent::IncomingPayment {
+            block_number: 1,
+            data: payment.clone(),
+            committed: false,
+        });
+        assert!(matches!(announced.as_slice(), [WalletActivity::Pending { incoming: true, .. }]));
+
+        let appended = watcher.process(ChainEvent::BlockAppended {
+            block_number: 1,
+            block_hash: String::new(),
+            data: vec![payment],
+        });
+        assert!(matches!(appended.as_slice(), [WalletActivity::Confirmed { confirmations: 1, .. }]));
+    }
+
+    #[test]
+    fn transactions_of_rolled_back_blocks_are_reported_unconfirmed() {
+        let mut local = Blockchain::<Transaction>::transaction_chain(vec![
+            Transaction::new(MINTING_WALLET_ADDRESS, [1; 32], "".to_string(), 50, Utc::now())
+        ]);
+        let mut remote = Blockchain::try_from(BlockchainDto::from(&local)).ok().unwrap();
+        let payment = Transaction::new([1; 32], [2; 32], "Rent".to_string(), 10, Utc::now());
+        let kept = Transaction::new([1; 32], [3; 32], "".to_string(), 5, Utc::now());
+        local.submit_new_block(BlockCandidate::create_new(vec![payment.clone(), kept.clone()], local.last_block()).ok().unwrap());
+        remote.submit_new_block(BlockCandidate::create_new(vec![kept], remote.last_block()).ok().unwrap());
+        remote.submit_new_block(BlockCandidate::create_new(vec![], remote.last_block()).ok().unwrap());
+
+        let mut watcher = WalletWatcher::new([2; 32], local.subscribe());
+        let rollback = local.replace(remote);
+        assert_eq!((rollback.depth(), rollback.fork_height()), (1, 1));
+        assert_eq!(rollback.affected(), std::slice::from_ref(&payment));
+
+        let reorg = watcher.events.try_recv().ok().unwrap();
+        assert!(matches!(&reorg, ChainEvent::Reorg { affected_txs, .. } if affected_txs.len() == 1));
+        let activity = watcher.process(reorg);
+        assert!(matches!(
+            activity.as_slice(),
+            [WalletActivity::Reorg { depth: 1 }, WalletActivity::Unconfirmed { incoming: true, .. }]
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::blockchain::{access, Address, Transaction};
+use crate::blockchain::core::{BlockchainError, ChainEvent};
+use crate::report;
+
+pub static WEBHOOK_ATTEMPTS: u32 = 5;
+// doubles after every failed attempt
+pub static FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+pub static SIGNATURE_HEADER: &str = "X-Kingcoin-Signature";
+static WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+static HMAC_BLOCK_SIZE: usize = 64;
+
+pub struct WebhookError {
+    message: String,
+}
+
+impl WebhookError {
+    pub fn new(message: &str) -> WebhookError {
+        WebhookError {
+            message: message.to_string(),
+        }
+    }
+}
+
+impl BlockchainError for WebhookError {
+    fn message(&self) -> String {
+        format!("Webhook: {}", self.message)
+    }
+}
+
+// Payloads are posted as plain http, so a receiver outside the operator's network should sit
+// behind a tls terminating proxy. With a secret set every post carries an hmac-sha256 of its
+// body, receivers recompute it to drop posts that did not come from this node.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    url: String,
+    secret: Option<String>,
+    // payment events are only posted for these addresses
+    #[serde(default)]
+    watched_addresses: Vec<String>,
+}
+
+impl WebhookConfig {
+    pub fn new(url: &str, secret: Option<&str>, watched_addresses: Vec<String>) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            secret: secret.map(str::to_string),
+            watched_addresses,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn BlockchainError>> {
+        Endpoint::parse(&self.url)?;
+        self.watched()?;
+        Ok(())
+    }
+
+    fn watched(&self) -> Result<Vec<Address>, Box<dyn BlockchainError>> {
+        self.watched_addresses.iter()
+            .map(|address| access::decode_address(address))
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Block {
+        block_number: u64,
+        block_hash: String,
+        transactions: usize,
+    },
+    Payment {
+        address: String,
+        block_number: u64,
+        transaction_id: String,
+        source: String,
+        amount: i64,
+    },
+    Slashed {
+        address: String,
+        amount: i64,
+    },
+}
+
+// payments are reported once their block is appended, not when a peer announces them
+pub fn events_of(event: &ChainEvent<Transaction>, watched: &[Address]) -> Vec<WebhookEvent> {
+    match event {
+        ChainEvent::BlockAppended { block_number, block_hash, data } => {
+            let mut events = vec![WebhookEvent::Block {
+                block_number: *block_number,
+                block_hash: block_hash.clone(),
+                transactions: data.len(),
+            }];
+            events.extend(data.iter()
+                .filter(|transaction| watched.contains(&transaction.target_address()))
+                .map(|transaction| WebhookEvent::Payment {
+                    address: access::encode_address(transaction.target_address()),
+                    block_number: *block_number,
+                    transaction_id: transaction.id(),
+                    source: access::encode_address(transaction.source_address()),
+                    amount: transaction.amount(),
+                }));
+            events
+        }
+        ChainEvent::Slashed { address, amount } => vec![WebhookEvent::Slashed {
+            address: access::encode_address(*address),
+            amount: *amount,
+        }],
+        _ => vec![]
+    }
+}
+
+pub fn sign(secret: &str, body: &str) -> String {
+    format!("sha256={}", array_bytes::bytes2hex("", hmac_sha256(secret.as_bytes(), body.as_bytes())))
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block_key = vec![0; HMAC_BLOCK_SIZE];
+    match key.len() > HMAC_BLOCK_SIZE {
+        true => block_key[..32].copy_from_slice(&Sha256::digest(key)),
+        false => block_key[..key.len()].copy_from_slice(key),
+    }
+    let padded = |pad: u8| -> Vec<u8> { block_key.iter().map(|byte| byte ^ pad).collect() };
+    let inner = Sha256::new()
+        .chain_update(padded(0x36))
+        .chain_update(data)
+        .finalize();
+    Sha256::new()
+        .chain_update(padded(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+struct Endpoint {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Endpoint, Box<dyn BlockchainError>> {
+        let invalid = |reason: &str| -> Box<dyn BlockchainError> {
+            Box::new(WebhookError::new(&format!("Invalid url {}: {}", url, reason)))
+        };
+        let rest = match url.strip_prefix("http://") {
+            None => return Err(invalid("only http:// urls are supported")),
+            Some(rest) => rest
+        };
+        let (authority, path) = match rest.find('/') {
+            None => (rest, "/"),
+            Some(index) => (&rest[..index], &rest[index..])
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            None => (authority, 80),
+            Some((host, port)) => match port.parse() {
+                Ok(port) => (host, port),
+                Err(_) => return Err(invalid("bad port"))
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        Ok(Endpoint {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+// one post, any 2xx status counts as delivered
+pub fn deliver(config: &WebhookConfig, body: &str) -> Result<(), Box<dyn BlockchainError>> {
+    let failed = |reason: String| -> Box<dyn BlockchainError> {
+        Box::new(WebhookError::new(&format!("{}: {}", config.url, reason)))
+    };
+    let endpoint = Endpoint::parse(&config.url)?;
+    let socket_address = (endpoint.host.as_str(), endpoint.port).to_socket_addrs()
+        .map_err(|error| failed(error.to_string()))?
+        .next()
+        .ok_or_else(|| failed("host did not resolve".to_string()))?;
+    let mut stream = TcpStream::connect_timeout(&socket_address, WEBHOOK_TIMEOUT)
+        .map_err(|error| failed(error.to_string()))?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT)).map_err(|error| failed(error.to_string()))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT)).map_err(|error| failed(error.to_string()))?;
+    let signature = match &config.secret {
+        None => String::new(),
+        Some(secret) => format!("{}: {}\r\n", SIGNATURE_HEADER, sign(secret, body))
+    };
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        endpoint.path, endpoint.host, body.len(), signature, body
+    );
+    stream.write_all(request.as_bytes()).map_err(|error| failed(error.to_string()))?;
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).map_err(|error| failed(error.to_string()))?;
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(failed(format!("receiver answered {}", status))),
+        None => Err(failed("receiver sent no status".to_string()))
+    }
+}
+
+pub fn deliver_with_retry(
+    config: &WebhookConfig, body: &str, attempts: u32, first_delay: Duration,
+) -> Result<(), Box<dyn BlockchainError>> {
+    let mut delay = first_delay;
+    let mut attempt = 1;
+    loop {
+        match deliver(config, body) {
+            Ok(()) => return Ok(()),
+            Err(error) if attempt >= attempts => return Err(error),
+            Err(_) => {
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Hook {
+    watched: Vec<Address>,
+    payloads: mpsc::Sender<String>,
+}
+
+// Each configured url gets its own delivery thread, so a receiver that is down only delays
+// its own events, which are still posted in the order they happened.
+#[derive(Clone)]
+pub struct Webhooks {
+    hooks: Vec<Hook>,
+}
+
+impl Webhooks {
+    pub fn start(configs: &[WebhookConfig]) -> Result<Webhooks, Box<dyn BlockchainError>> {
+        let mut hooks = vec![];
+        for config in configs {
+            config.validate()?;
+            let (payloads, queued) = mpsc::channel::<String>();
+            let watched = config.watched()?;
+            let config = config.clone();
+            thread::spawn(move || {
+                for body in queued {
+                    if let Err(error) = deliver_with_retry(&config, &body, WEBHOOK_ATTEMPTS, FIRST_RETRY_DELAY) {
+                        report!("Dropping event after {} attempts: {}", WEBHOOK_ATTEMPTS, error.message());
+                    }
+                }
+            });
+            hooks.push(Hook {
+                watched,
+                payloads,
+            });
+        }
+        Ok(Webhooks { hooks })
+    }
+
+    pub fn post(&self, event: &ChainEvent<Transaction>) {
+        for hook in &self.hooks {
+            for webhook_event in events_of(event, &hook.watched) {
+                // the delivery thread only stops with the process
+                let _ = hook.payloads.send(serde_json::to_string(&webhook_event).unwrap());
+            }
+        }
+    }
+
+    // posts every event of the transaction chain until it is dropped
+    pub fn follow(&self, events: broadcast::Receiver<ChainEvent<Transaction>>) {
+        self.follow_filtered(events, |_| true);
+    }
+
+    // the stake chain has blocks of its own, only its slashing is of interest to receivers
+    pub fn follow_slashing(&self, events: broadcast::Receiver<ChainEvent<Transaction>>) {
+        self.follow_filtered(events, |event| matches!(event, ChainEvent::Slashed { .. }));
+    }
+
+    fn follow_filtered(
+        &self, mut events: broadcast::Receiver<ChainEvent<Transaction>>,
+        posted: fn(&ChainEvent<Transaction>) -> bool,
+    ) {
+        let webhooks = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) if posted(&event) => webhooks.post(&event),
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        report!("Webhooks fell behind, {} chain events were not posted", skipped)
+                    }
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use chrono::Utc;
+
+    use crate::blockchain::{access, Transaction};
+    use crate::blockchain::core::ChainEvent;
+    use crate::webhook::{self, SIGNATURE_HEADER, WebhookConfig, WebhookEvent};
+
+    #[test]
+    fn signs_like_rfc_4231() {
+        assert_eq!(
+            webhook::sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn reports_blocks_watched_payments_and_slashing() {
+        let payment = Transaction::new([1; 32], [2; 32], "".to_string(), 5, Utc::now());
+        let other = Transaction::new([1; 32], [3; 32], "".to_string(), 7, Utc::now());
+        let appended = ChainEvent::BlockAppended {
+            block_number: 4,
+            block_hash: "ab".to_string(),
+            data: vec![payment.clone(), other],
+        };
+        assert_eq!(webhook::events_of(&appended, &[[2; 32]]), vec![
+            WebhookEvent::Block { block_number: 4, block_hash: "ab".to_string(), transactions: 2 },
+            WebhookEvent::Payment {
+                address: access::encode_address([2; 32]),
+                block_number: 4,
+                transaction_id: payment.id(),
+                source: access::encode_address([1; 32]),
+                amount: 5,
+            },
+        ]);
+        let slashed = ChainEvent::<Transaction>::Slashed { address: [1; 32], amount: 10 };
+        assert_eq!(webhook::events_of(&slashed, &[]), vec![
+            WebhookEvent::Slashed { address: access::encode_address([1; 32]), amount: 10 }
+        ]);
+    }
+
+    #[test]
+    fn retries_until_the_receiver_accepts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks", listener.local_addr().unwrap());
+        let (received, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for status in ["500 Internal Server Error", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(length) = line.strip_prefix("Content-Length: ") {
+                        content_length = length.trim().parse().unwrap();
+                    }
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                stream.write_all(format!("HTTP/1.1 {}\r\n\r\n", status).as_bytes()).unwrap();
+                received.send((head, String::from_utf8(body).unwrap())).unwrap();
+            }
+        });
+
+        let config = WebhookConfig::new(&url, Some("secret"), vec![]);
+        assert!(config.validate().is_ok());
+        assert!(webhook::deliver_with_retry(&config, "{}", 3, Duration::from_millis(10)).is_ok());
+        let (_, (head, body)) = (requests.recv().unwrap(), requests.recv().unwrap());
+        assert!(head.starts_with("POST /hooks HTTP/1.1"));
+        assert!(head.contains(&format!("{}: {}", SIGNATURE_HEADER, webhook::sign("secret", "{}"))));
+        assert_eq!(body, "{}");
+
+        assert!(WebhookConfig::new("https://example.com", None, vec![]).validate().is_err());
+    }
+}